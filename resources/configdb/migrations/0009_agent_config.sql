@@ -0,0 +1,7 @@
+CREATE TABLE agent_config (
+       key_id INTEGER PRIMARY KEY REFERENCES agent_keys(id) ON DELETE CASCADE,
+       version INTEGER NOT NULL,
+       config TEXT NOT NULL,
+       updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+       applied_version INTEGER,
+       applied_at TIMESTAMP);
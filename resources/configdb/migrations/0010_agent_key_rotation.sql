@@ -0,0 +1,5 @@
+ALTER TABLE agent_keys ADD COLUMN expires_at TIMESTAMP;
+ALTER TABLE agent_keys ADD COLUMN rotated_at TIMESTAMP;
+ALTER TABLE agent_keys ADD COLUMN previous_key TEXT;
+ALTER TABLE agent_keys ADD COLUMN previous_key_expires_at TIMESTAMP;
+CREATE UNIQUE INDEX agent_keys_previous_key ON agent_keys(previous_key);
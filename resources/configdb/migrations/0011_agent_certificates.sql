@@ -0,0 +1,4 @@
+ALTER TABLE agent_keys ADD COLUMN cert_fingerprint TEXT;
+ALTER TABLE agent_keys ADD COLUMN cert_subject TEXT;
+CREATE UNIQUE INDEX agent_keys_cert_fingerprint ON agent_keys(cert_fingerprint);
+CREATE UNIQUE INDEX agent_keys_cert_subject ON agent_keys(cert_subject);
@@ -0,0 +1,19 @@
+CREATE TABLE pcap_audit (
+       id INTEGER PRIMARY KEY AUTOINCREMENT,
+       timestamp TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+       username TEXT NOT NULL,
+       remote TEXT NOT NULL,
+       event_id TEXT,
+       mode TEXT,
+       filter TEXT,
+       window TEXT,
+       source TEXT,
+       format TEXT NOT NULL,
+       outcome TEXT NOT NULL,
+       message TEXT,
+       packets INTEGER,
+       bytes INTEGER NOT NULL,
+       truncated BOOLEAN NOT NULL,
+       sha256 TEXT);
+CREATE INDEX pcap_audit_timestamp ON pcap_audit(timestamp);
+CREATE INDEX pcap_audit_event_id ON pcap_audit(event_id);
@@ -0,0 +1,2 @@
+-- Existing users keep the full access they had before roles.
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'admin';
@@ -0,0 +1,10 @@
+CREATE TABLE api_tokens (
+       id INTEGER PRIMARY KEY AUTOINCREMENT,
+       user_uuid TEXT NOT NULL REFERENCES users(uuid),
+       name TEXT NOT NULL,
+       token_hash TEXT UNIQUE NOT NULL,
+       scope TEXT NOT NULL,
+       created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+       expires_at TIMESTAMP,
+       last_used TIMESTAMP,
+       UNIQUE (user_uuid, name));
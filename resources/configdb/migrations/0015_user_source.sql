@@ -0,0 +1,3 @@
+-- Where a user authenticates: "local" for a password in this database,
+-- or the external provider that created the user.
+ALTER TABLE users ADD COLUMN source TEXT NOT NULL DEFAULT 'local';
@@ -0,0 +1,16 @@
+-- A user's TOTP second factor. The secret is pending until a code from
+-- it has been confirmed.
+CREATE TABLE user_totp (
+       user_uuid TEXT PRIMARY KEY REFERENCES users(uuid),
+       secret TEXT NOT NULL,
+       enabled INTEGER NOT NULL DEFAULT 0,
+       last_step INTEGER,
+       created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP);
+
+-- Single use codes for logging in without the TOTP device.
+CREATE TABLE user_recovery_codes (
+       id INTEGER PRIMARY KEY AUTOINCREMENT,
+       user_uuid TEXT NOT NULL REFERENCES users(uuid),
+       code_hash TEXT NOT NULL,
+       used_at TIMESTAMP,
+       UNIQUE (user_uuid, code_hash));
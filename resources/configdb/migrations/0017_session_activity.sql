@@ -0,0 +1,6 @@
+-- When a login session was created and last used, in Unix seconds, and
+-- the client it was created from, for listing a user's sessions.
+ALTER TABLE sessions ADD COLUMN created_at INTEGER;
+ALTER TABLE sessions ADD COLUMN last_seen INTEGER;
+ALTER TABLE sessions ADD COLUMN remote TEXT;
+ALTER TABLE sessions ADD COLUMN user_agent TEXT;
@@ -0,0 +1,13 @@
+CREATE TABLE audit_log (
+       id INTEGER PRIMARY KEY AUTOINCREMENT,
+       timestamp TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+       username TEXT NOT NULL,
+       api_token TEXT,
+       remote TEXT NOT NULL,
+       action TEXT NOT NULL,
+       target TEXT,
+       parameters TEXT NOT NULL,
+       outcome TEXT NOT NULL,
+       message TEXT);
+CREATE INDEX audit_log_timestamp ON audit_log(timestamp);
+CREATE INDEX audit_log_action ON audit_log(action);
@@ -0,0 +1,3 @@
+-- The sensors, by host name, whose events a user may see, as a JSON
+-- array. NULL for every sensor.
+ALTER TABLE users ADD COLUMN sensors TEXT;
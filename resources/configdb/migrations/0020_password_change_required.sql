@@ -0,0 +1,3 @@
+-- Users who must change their password before doing anything else, such
+-- as new users given a temporary password.
+ALTER TABLE users ADD COLUMN password_change_required INTEGER NOT NULL DEFAULT 0;
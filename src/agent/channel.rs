@@ -1,7 +1,8 @@
 // SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
 // SPDX-License-Identifier: MIT
 
-//! Persistent agent control channel and remote PCAP worker.
+//! Persistent agent control channel, remote PCAP worker, rule update runner
+//! and receiver of server-pushed configuration.
 //!
 //! The WebSocket is a small JSON control plane. Packet bytes are uploaded on
 //! a separate HTTP request so future command families can share this channel
@@ -22,12 +23,17 @@ use tokio_tungstenite::tungstenite::http::Uri;
 use tokio_tungstenite::tungstenite::http::header::SEC_WEBSOCKET_PROTOCOL;
 use tokio_util::sync::CancellationToken;
 
+use crate::agent::config::AgentConfigHandle;
 use crate::agent::protocol::{
-    AGENT_HEADER, AgentHandshake, AgentMessage, CAPABILITY_PCAP, CONTROL_MESSAGE_MAX_BYTES,
-    PCAP_CONTENT_TYPE, PcapResult, PcapResultCode, PcapUploadStatus, SUBPROTOCOL, ServerMessage,
-    WireLimits, WirePcapFilter, WireStats, agent_pcap_upload_path,
+    AGENT_HEADER, AgentHandshake, AgentMessage, CAPABILITY_CONFIG, CAPABILITY_PCAP,
+    CAPABILITY_PCAP_FIELDS, CAPABILITY_PCAP_RETENTION, CAPABILITY_PCAPNG, CAPABILITY_RULES_UPDATE,
+    CAPABILITY_SURICATA_COMMAND, CONTROL_MESSAGE_MAX_BYTES, PCAP_CONTENT_TYPE, PcapResult,
+    PcapResultCode, PcapUploadStatus, RulesUpdateResult, SUBPROTOCOL, ServerMessage, WireLimits,
+    WirePcapFilter, WirePcapng, WireStats, agent_pcap_upload_path,
 };
-use crate::pcap::{self, FetchError, PcapRequest, PcapSource, SpoolConfig};
+use crate::agent::retention::RetentionState;
+use crate::agent::suricata::SuricataConfig;
+use crate::pcap::{self, FetchError, PcapRequest, PcapSource};
 use crate::prelude::*;
 
 const MIN_BACKOFF: Duration = Duration::from_secs(1);
@@ -58,7 +64,16 @@ pub(crate) struct ChannelConfig {
     /// Agent key (`server.key` / `EVEBOX_SERVER_KEY`) presented as a bearer
     /// token on the WebSocket upgrade.
     pub(crate) server_key: Option<String>,
-    pub(crate) spool: SpoolConfig,
+    /// TLS client certificate presented on the WebSocket upgrade.
+    pub(crate) client_certificate: Option<crate::agent::tls::ClientCertificate>,
+    /// Effective agent settings. The packet-capture spool is read from here
+    /// per job, and server-pushed configuration is applied to it.
+    pub(crate) settings: AgentConfigHandle,
+    /// Suricata rule update command and command socket.
+    pub(crate) suricata: SuricataConfig,
+    /// Spool retention: receives the server's protected windows and
+    /// reports spool usage.
+    pub(crate) retention: Option<Arc<RetentionState>>,
     pub(crate) disable_certificate_check: bool,
 }
 
@@ -77,6 +92,12 @@ struct ConnectWarnings {
     unauthorized: bool,
 }
 
+/// The message of a server's `{"error": {"code", "message"}}` refusal.
+fn rejection_message(body: &Option<Vec<u8>>) -> Option<String> {
+    let body: serde_json::Value = serde_json::from_slice(body.as_deref()?).ok()?;
+    body["error"]["message"].as_str().map(str::to_string)
+}
+
 fn next_backoff(backoff: Duration, connected_for: Duration) -> (Duration, Duration) {
     if connected_for >= HEALTHY_CONNECTION_AGE {
         (MIN_BACKOFF, MIN_BACKOFF)
@@ -95,13 +116,22 @@ struct ActiveJob {
 /// waiting browser request, and the user simply retries.
 type Jobs = Arc<Mutex<HashMap<String, ActiveJob>>>;
 
+/// Workers shared across reconnects.
+struct Workers {
+    /// PCAP upload client.
+    client: reqwest::Client,
+    /// Serializes disk extraction across connections: a blocking producer
+    /// can outlive the connection which started it.
+    extraction: Arc<Semaphore>,
+    /// One rule update at a time. An update is not tied to the connection
+    /// that started it and keeps running if that connection drops.
+    rules_update: Arc<Semaphore>,
+}
+
 /// Run the control channel forever. Callers should spawn this independently
 /// of the fail-fast EVE importer task set.
 pub(crate) async fn run(config: ChannelConfig) {
     let config = Arc::new(config);
-    // Serializes disk extraction across connections: a blocking producer can
-    // outlive the connection which started it.
-    let extraction = Arc::new(Semaphore::new(1));
     // Building the upload client is deterministic; a failure would repeat on
     // every retry, so give up on the channel rather than spin.
     let client = match crate::agent::client::build_reqwest_client(config.disable_certificate_check)
@@ -112,11 +142,16 @@ pub(crate) async fn run(config: ChannelConfig) {
             return;
         }
     };
+    let workers = Workers {
+        client,
+        extraction: Arc::new(Semaphore::new(1)),
+        rules_update: Arc::new(Semaphore::new(1)),
+    };
     let mut backoff = MIN_BACKOFF;
     let mut warned = ConnectWarnings::default();
 
     loop {
-        let outcome = connect_and_run(&config, &client, &extraction, &mut warned).await;
+        let outcome = connect_and_run(&config, &workers, &mut warned).await;
 
         let delay = match outcome {
             ConnectionOutcome::Disconnected { connected_for } => {
@@ -125,11 +160,15 @@ pub(crate) async fn run(config: ChannelConfig) {
                 delay
             }
             ConnectionOutcome::ConnectFailed => {
+                config.settings.revert_to_local();
                 let (delay, next) = next_backoff(backoff, Duration::ZERO);
                 backoff = next;
                 delay
             }
-            ConnectionOutcome::ServerTooOld | ConnectionOutcome::Unauthorized => MAX_BACKOFF,
+            ConnectionOutcome::ServerTooOld | ConnectionOutcome::Unauthorized => {
+                config.settings.revert_to_local();
+                MAX_BACKOFF
+            }
         };
         tokio::time::sleep(delay + jitter(delay)).await;
     }
@@ -137,15 +176,14 @@ pub(crate) async fn run(config: ChannelConfig) {
 
 async fn connect_and_run(
     config: &Arc<ChannelConfig>,
-    client: &reqwest::Client,
-    extraction: &Arc<Semaphore>,
+    workers: &Workers,
     warned: &mut ConnectWarnings,
 ) -> ConnectionOutcome {
     let handshake = AgentHandshake {
         name: config.agent_id.clone(),
         hostname: config.hostname.clone(),
         version: crate::version::version().to_string(),
-        capabilities: vec![CAPABILITY_PCAP.to_string()],
+        capabilities: agent_capabilities(config),
     };
     let handshake = match serde_json::to_string(&handshake) {
         Ok(handshake) => ascii_json(&handshake),
@@ -180,7 +218,16 @@ async fn connect_and_run(
     let ws_config = tokio_tungstenite::tungstenite::protocol::WebSocketConfig::default()
         .max_message_size(Some(CONTROL_MESSAGE_MAX_BYTES))
         .max_frame_size(Some(CONTROL_MESSAGE_MAX_BYTES));
-    let connector = crate::agent::tls::connector(config.disable_certificate_check);
+    let connector = match crate::agent::tls::connector(
+        config.disable_certificate_check,
+        config.client_certificate.as_ref(),
+    ) {
+        Ok(connector) => connector,
+        Err(err) => {
+            error!("agent channel: could not load the client certificate: {err:#}");
+            return ConnectionOutcome::ConnectFailed;
+        }
+    };
     let connect = connect_async_tls_with_config(request, Some(ws_config), false, connector);
 
     match tokio::time::timeout(CONNECT_TIMEOUT, connect).await {
@@ -202,7 +249,7 @@ async fn connect_and_run(
                 config.server_url, config.agent_id
             );
             let connected_at = Instant::now();
-            run_connection(ws, config, client, extraction).await;
+            run_connection(ws, config, workers).await;
             ConnectionOutcome::Disconnected {
                 connected_for: connected_at.elapsed(),
             }
@@ -220,7 +267,12 @@ async fn connect_and_run(
         }
         Ok(Err(WsError::Http(response))) if response.status() == 401 => {
             if !warned.unauthorized {
-                if config.server_key.is_some() {
+                if let Some(message) = rejection_message(response.body()) {
+                    error!(
+                        "agent channel: the server at {} refused agent {:?}: {message}",
+                        config.server_url, config.agent_id
+                    );
+                } else if config.server_key.is_some() {
                     error!(
                         "agent channel: the server at {} rejected the configured agent key \
                          (unknown or removed); issue a new one with `evebox config agents add {:?}` \
@@ -268,12 +320,8 @@ async fn connect_and_run(
     }
 }
 
-async fn run_connection<S>(
-    ws: S,
-    config: &Arc<ChannelConfig>,
-    client: &reqwest::Client,
-    extraction: &Arc<Semaphore>,
-) where
+async fn run_connection<S>(ws: S, config: &Arc<ChannelConfig>, workers: &Workers)
+where
     S: Stream<Item = Result<Message, WsError>> + Sink<Message, Error = WsError> + Unpin,
 {
     let (mut sink, mut stream) = ws.split();
@@ -285,6 +333,12 @@ async fn run_connection<S>(
     let mut control = ControlState::default();
     let idle = tokio::time::sleep(RECEIVE_IDLE_TIMEOUT);
     tokio::pin!(idle);
+    // Report the spool usage once per connection, then on every change.
+    let mut usage_rx = config.retention.as_ref().map(|retention| {
+        let mut rx = retention.subscribe_usage();
+        rx.mark_changed();
+        rx
+    });
 
     loop {
         tokio::select! {
@@ -300,14 +354,23 @@ async fn run_connection<S>(
                         match handle_message(
                             text.as_str(),
                             config,
-                            client,
+                            workers,
                             &jobs,
                             &result_tx,
-                            extraction,
                             &mut control,
                         ) {
                             MessageOutcome::Continue => {}
                             MessageOutcome::Fatal => break,
+                            MessageOutcome::Reply { message, reconnect } => {
+                                let Ok(text) = serde_json::to_string(&message) else {
+                                    break;
+                                };
+                                if !send_control(&mut sink, Message::Text(text.into())).await
+                                    || reconnect
+                                {
+                                    break;
+                                }
+                            }
                         }
                     }
                     Some(Ok(Message::Ping(payload))) => {
@@ -337,6 +400,21 @@ async fn run_connection<S>(
                     break;
                 }
             }
+            changed = usage_changed(&mut usage_rx) => {
+                let Some(rx) = usage_rx.as_mut() else { continue };
+                if changed.is_err() {
+                    usage_rx = None;
+                    continue;
+                }
+                let Some(usage) = *rx.borrow_and_update() else { continue };
+                let message = AgentMessage::SpoolUsage {
+                    usage: usage.into(),
+                };
+                let Ok(text) = serde_json::to_string(&message) else { break };
+                if !send_control(&mut sink, Message::Text(text.into())).await {
+                    break;
+                }
+            }
         }
     }
 
@@ -347,6 +425,16 @@ async fn run_connection<S>(
     }
 }
 
+/// Wait for a spool usage change; pending forever without retention.
+async fn usage_changed(
+    rx: &mut Option<watch::Receiver<Option<pcap::SpoolUsage>>>,
+) -> Result<(), watch::error::RecvError> {
+    match rx {
+        Some(rx) => rx.changed().await,
+        None => std::future::pending().await,
+    }
+}
+
 /// One bounded WebSocket write; a false return ends the connection.
 async fn send_control<S>(sink: &mut S, message: Message) -> bool
 where
@@ -365,12 +453,39 @@ where
     }
 }
 
+/// Capabilities claimed on the next connection. Packet capture is only
+/// offered while a spool is configured, so a pushed change to it is
+/// renegotiated by reconnecting. Rule updates and the command socket proxy
+/// are offered when configured.
+fn agent_capabilities(config: &ChannelConfig) -> Vec<String> {
+    let mut capabilities = vec![CAPABILITY_CONFIG.to_string()];
+    if config.settings.current().settings.spool.is_some() {
+        capabilities.push(CAPABILITY_PCAP.to_string());
+        capabilities.push(CAPABILITY_PCAPNG.to_string());
+        capabilities.push(CAPABILITY_PCAP_FIELDS.to_string());
+        if config.retention.is_some() {
+            capabilities.push(CAPABILITY_PCAP_RETENTION.to_string());
+        }
+    }
+    if config.suricata.update.is_some() {
+        capabilities.push(CAPABILITY_RULES_UPDATE.to_string());
+    }
+    if !config.suricata.commands.is_empty() {
+        capabilities.push(CAPABILITY_SURICATA_COMMAND.to_string());
+    }
+    capabilities
+}
+
 #[derive(Default)]
 enum ControlState {
     #[default]
     AwaitingHello,
     Ready {
         pcap: bool,
+        config: bool,
+        rules_update: bool,
+        suricata_command: bool,
+        retention: bool,
     },
 }
 
@@ -380,6 +495,16 @@ impl ControlState {
             (Self::AwaitingHello, ServerMessage::Hello { capabilities, .. }) => {
                 *self = Self::Ready {
                     pcap: capabilities.iter().any(|value| value == CAPABILITY_PCAP),
+                    config: capabilities.iter().any(|value| value == CAPABILITY_CONFIG),
+                    rules_update: capabilities
+                        .iter()
+                        .any(|value| value == CAPABILITY_RULES_UPDATE),
+                    suricata_command: capabilities
+                        .iter()
+                        .any(|value| value == CAPABILITY_SURICATA_COMMAND),
+                    retention: capabilities
+                        .iter()
+                        .any(|value| value == CAPABILITY_PCAP_RETENTION),
                 };
                 true
             }
@@ -392,21 +517,67 @@ impl ControlState {
                 warn!("agent channel: received control message before server hello; reconnecting");
                 false
             }
-            (Self::Ready { pcap: false }, ServerMessage::PcapRequest { .. }) => {
+            (Self::Ready { pcap: false, .. }, ServerMessage::PcapRequest { .. }) => {
                 warn!(
                     "agent channel: server sent a pcap request without advertising the pcap capability; reconnecting"
                 );
                 false
             }
+            (Self::Ready { config: false, .. }, ServerMessage::Config { .. }) => {
+                warn!(
+                    "agent channel: server pushed configuration without advertising the config capability; reconnecting"
+                );
+                false
+            }
+            (
+                Self::Ready {
+                    rules_update: false,
+                    ..
+                },
+                ServerMessage::RulesUpdate { .. },
+            ) => {
+                warn!(
+                    "agent channel: server requested a rule update without advertising the rules-update capability; reconnecting"
+                );
+                false
+            }
+            (
+                Self::Ready {
+                    suricata_command: false,
+                    ..
+                },
+                ServerMessage::SuricataCommand { .. },
+            ) => {
+                warn!(
+                    "agent channel: server sent a Suricata command without advertising the suricata-command capability; reconnecting"
+                );
+                false
+            }
+            (
+                Self::Ready {
+                    retention: false, ..
+                },
+                ServerMessage::PcapProtect { .. },
+            ) => {
+                warn!(
+                    "agent channel: server sent protected windows without advertising the pcap-retention capability; reconnecting"
+                );
+                false
+            }
             (Self::Ready { .. }, _) => true,
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 enum MessageOutcome {
     Continue,
     Fatal,
+    /// Send a reply, then reconnect if asked to renegotiate capabilities.
+    Reply {
+        message: AgentMessage,
+        reconnect: bool,
+    },
 }
 
 fn decode_server_message(text: &str) -> Option<ServerMessage> {
@@ -422,10 +593,9 @@ fn decode_server_message(text: &str) -> Option<ServerMessage> {
 fn handle_message(
     text: &str,
     config: &Arc<ChannelConfig>,
-    client: &reqwest::Client,
+    workers: &Workers,
     jobs: &Jobs,
     result_tx: &mpsc::Sender<AgentMessage>,
-    extraction: &Arc<Semaphore>,
     control: &mut ControlState,
 ) -> MessageOutcome {
     let Some(message) = decode_server_message(text) else {
@@ -451,10 +621,21 @@ fn handle_message(
             start_us,
             end_us,
             limits,
+            pcapng,
         } => {
             match start_job(
-                config, client, jobs, result_tx, extraction, id, token, filter, start_us, end_us,
+                config,
+                &workers.client,
+                jobs,
+                result_tx,
+                &workers.extraction,
+                id,
+                token,
+                filter,
+                start_us,
+                end_us,
                 limits,
+                pcapng,
             ) {
                 StartJob::Started | StartJob::Duplicate => {}
                 StartJob::Conflict => return MessageOutcome::Fatal,
@@ -479,6 +660,84 @@ fn handle_message(
                 }
             }
         }
+        ServerMessage::Config {
+            version,
+            config: pushed,
+        } => {
+            let had_pcap = config.settings.current().settings.spool.is_some();
+            let restart_required = config.settings.apply(version, &pushed);
+            let has_pcap = config.settings.current().settings.spool.is_some();
+            if had_pcap != has_pcap {
+                info!(
+                    "agent channel: packet capture {} by server configuration; reconnecting to renegotiate",
+                    if has_pcap { "enabled" } else { "disabled" }
+                );
+            }
+            return MessageOutcome::Reply {
+                message: AgentMessage::ConfigApplied {
+                    version,
+                    restart_required,
+                },
+                reconnect: had_pcap != has_pcap,
+            };
+        }
+        ServerMessage::RulesUpdate { id } => {
+            let Ok(permit) = workers.rules_update.clone().try_acquire_owned() else {
+                return MessageOutcome::Reply {
+                    message: AgentMessage::RulesUpdateResult {
+                        id,
+                        result: RulesUpdateResult {
+                            message: Some("a rule update is already running".to_string()),
+                            ..Default::default()
+                        },
+                    },
+                    reconnect: false,
+                };
+            };
+            let config = config.clone();
+            let result_tx = result_tx.clone();
+            tokio::spawn(async move {
+                let result =
+                    crate::agent::suricata::run_rules_update(&config.suricata, &id, &result_tx)
+                        .await;
+                drop(permit);
+                info!(
+                    "agent channel: rule update {id} finished: exit_code={:?} reloaded={}",
+                    result.exit_code, result.reloaded
+                );
+                let _ = result_tx
+                    .send(AgentMessage::RulesUpdateResult { id, result })
+                    .await;
+            });
+        }
+        ServerMessage::SuricataCommand {
+            id,
+            command,
+            arguments,
+        } => {
+            info!("agent channel: running Suricata command {command} for the server");
+            let config = config.clone();
+            let result_tx = result_tx.clone();
+            tokio::spawn(async move {
+                let result = crate::agent::suricata::proxy_command(
+                    &config.suricata,
+                    &command,
+                    arguments.as_ref(),
+                )
+                .await;
+                if let Some(err) = &result.error {
+                    warn!("agent channel: Suricata command {command} failed: {err}");
+                }
+                let _ = result_tx
+                    .send(AgentMessage::SuricataCommandResult { id, result })
+                    .await;
+            });
+        }
+        ServerMessage::PcapProtect { windows } => {
+            if let Some(retention) = &config.retention {
+                retention.protect(windows);
+            }
+        }
         ServerMessage::Unknown => {
             debug!("agent channel: ignored unknown server message type");
         }
@@ -506,6 +765,7 @@ fn start_job(
     start_us: u64,
     end_us: u64,
     limits: WireLimits,
+    pcapng: Option<WirePcapng>,
 ) -> StartJob {
     let cancel = CancellationToken::new();
     {
@@ -551,6 +811,7 @@ fn start_job(
             start_us,
             end_us,
             limits,
+            pcapng,
             &worker_cancel,
             producer_done_tx,
         )
@@ -691,6 +952,7 @@ async fn run_job(
     start_us: u64,
     end_us: u64,
     limits: WireLimits,
+    pcapng: Option<WirePcapng>,
     cancel: &CancellationToken,
     producer_done: oneshot::Sender<()>,
 ) -> PcapResult {
@@ -719,8 +981,13 @@ async fn run_job(
         start: Some(start_us),
         end: Some(end_us),
         limits: limits.into(),
+        format: pcapng.into(),
+        progress: None,
     };
-    let source = PcapSource::Spool(config.spool.clone());
+    let Some(spool) = config.settings.current().settings.spool.clone() else {
+        return PcapResult::error("packet capture is not configured on this agent".to_string());
+    };
+    let source = PcapSource::Spool(spool);
     let (tx, mut rx) = mpsc::channel::<Bytes>(UPLOAD_CHANNEL_CAPACITY);
     // Server cancellation propagates from the parent token. Local transport
     // failure cancels only this child, so the terminal code remains `error`
@@ -1152,9 +1419,17 @@ mod tests {
                 max_bytes: 1024,
                 scan_timeout_ms: 1000,
             },
+            pcapng: None,
         }
     }
 
+    fn spool_settings(directory: &std::path::Path) -> AgentConfigHandle {
+        AgentConfigHandle::new(crate::agent::config::AgentSettings {
+            spool: Some(crate::pcap::SpoolConfig::new(directory, None)),
+            ..Default::default()
+        })
+    }
+
     #[test]
     fn request_before_hello_is_connection_fatal() {
         assert!(!ControlState::default().accept(&pcap_request()));
@@ -1181,6 +1456,236 @@ mod tests {
         assert!(state.accept(&pcap_request()));
     }
 
+    #[test]
+    fn config_push_requires_the_negotiated_capability() {
+        let push = ServerMessage::Config {
+            version: 1,
+            config: Default::default(),
+        };
+        assert!(!ControlState::default().accept(&push));
+        let mut state = ControlState::default();
+        assert!(state.accept(&hello(&[CAPABILITY_PCAP])));
+        assert!(!state.accept(&push));
+        let mut state = ControlState::default();
+        assert!(state.accept(&hello(&[CAPABILITY_CONFIG])));
+        assert!(state.accept(&push));
+
+        let update = ServerMessage::RulesUpdate {
+            id: "job".to_string(),
+        };
+        assert!(!state.accept(&update));
+        let mut state = ControlState::default();
+        assert!(state.accept(&hello(&[CAPABILITY_RULES_UPDATE])));
+        assert!(state.accept(&update));
+
+        let protect = ServerMessage::PcapProtect {
+            windows: Vec::new(),
+        };
+        assert!(!state.accept(&protect));
+        let mut state = ControlState::default();
+        assert!(state.accept(&hello(&[CAPABILITY_PCAP_RETENTION])));
+        assert!(state.accept(&protect));
+    }
+
+    /// Retention is offered with packet capture, and the server's
+    /// protected windows reach it.
+    #[tokio::test]
+    async fn retention_is_offered_with_a_spool_and_receives_windows() {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let directory = tempfile::tempdir().unwrap();
+        let retention = Arc::new(RetentionState::new(Default::default()));
+        let config = Arc::new(ChannelConfig {
+            server_url: "http://127.0.0.1:1".to_string(),
+            agent_id: "sensor".to_string(),
+            hostname: "host".to_string(),
+            server_key: None,
+            client_certificate: None,
+            settings: spool_settings(directory.path()),
+            suricata: SuricataConfig::default(),
+            retention: Some(retention.clone()),
+            disable_certificate_check: false,
+        });
+        assert_eq!(
+            agent_capabilities(&config),
+            vec![
+                CAPABILITY_CONFIG,
+                CAPABILITY_PCAP,
+                CAPABILITY_PCAPNG,
+                CAPABILITY_PCAP_FIELDS,
+                CAPABILITY_PCAP_RETENTION
+            ]
+        );
+
+        let workers = test_workers();
+        let jobs: Jobs = Arc::new(Mutex::new(HashMap::new()));
+        let (result_tx, _result_rx) = mpsc::channel(RESULT_CHANNEL_CAPACITY);
+        let mut control = ControlState::default();
+        let mut handle = |message: &ServerMessage| {
+            handle_message(
+                &serde_json::to_string(message).unwrap(),
+                &config,
+                &workers,
+                &jobs,
+                &result_tx,
+                &mut control,
+            )
+        };
+        assert_eq!(
+            handle(&hello(&[CAPABILITY_PCAP_RETENTION])),
+            MessageOutcome::Continue
+        );
+        let protect = ServerMessage::PcapProtect {
+            windows: vec![crate::agent::protocol::WireWindow {
+                start_us: 5,
+                end_us: 9,
+            }],
+        };
+        assert_eq!(handle(&protect), MessageOutcome::Continue);
+        assert_eq!(retention.protected_windows().unwrap().windows(), [(5, 9)]);
+    }
+
+    /// A push is acknowledged with its version, and one that toggles
+    /// packet capture also reconnects so the capability is renegotiated.
+    #[tokio::test]
+    async fn config_push_is_acknowledged_and_renegotiates_pcap() {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let directory = tempfile::tempdir().unwrap();
+        let config = Arc::new(ChannelConfig {
+            server_url: "http://127.0.0.1:1".to_string(),
+            agent_id: "sensor".to_string(),
+            hostname: "host".to_string(),
+            server_key: None,
+            client_certificate: None,
+            settings: spool_settings(directory.path()),
+            suricata: SuricataConfig::default(),
+            retention: None,
+            disable_certificate_check: false,
+        });
+        assert_eq!(
+            agent_capabilities(&config),
+            vec![
+                CAPABILITY_CONFIG,
+                CAPABILITY_PCAP,
+                CAPABILITY_PCAPNG,
+                CAPABILITY_PCAP_FIELDS
+            ]
+        );
+        let workers = test_workers();
+        let jobs: Jobs = Arc::new(Mutex::new(HashMap::new()));
+        let (result_tx, _result_rx) = mpsc::channel(RESULT_CHANNEL_CAPACITY);
+        let mut control = ControlState::default();
+        let mut handle =
+            |text: &str| handle_message(text, &config, &workers, &jobs, &result_tx, &mut control);
+
+        assert_eq!(
+            handle(r#"{"type":"hello","server_version":"test","capabilities":["config"]}"#),
+            MessageOutcome::Continue
+        );
+        assert_eq!(
+            handle(r#"{"type":"config","version":4,"config":{"geoip":true}}"#),
+            MessageOutcome::Reply {
+                message: AgentMessage::ConfigApplied {
+                    version: 4,
+                    restart_required: Vec::new(),
+                },
+                reconnect: false,
+            }
+        );
+        assert_eq!(
+            handle(r#"{"type":"config","version":5,"config":{"pcap_directory":""}}"#),
+            MessageOutcome::Reply {
+                message: AgentMessage::ConfigApplied {
+                    version: 5,
+                    restart_required: Vec::new(),
+                },
+                reconnect: true,
+            }
+        );
+        assert_eq!(agent_capabilities(&config), vec![CAPABILITY_CONFIG]);
+    }
+
+    fn test_workers() -> Workers {
+        Workers {
+            client: crate::agent::client::build_reqwest_client(false).unwrap(),
+            extraction: Arc::new(Semaphore::new(1)),
+            rules_update: Arc::new(Semaphore::new(1)),
+        }
+    }
+
+    /// A rule update runs in the background and reports its output and
+    /// one result; a second request while it runs is refused at once.
+    #[tokio::test]
+    async fn rules_update_streams_status_and_refuses_overlap() {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let config = Arc::new(ChannelConfig {
+            server_url: "http://127.0.0.1:1".to_string(),
+            agent_id: "sensor".to_string(),
+            hostname: "host".to_string(),
+            server_key: None,
+            client_certificate: None,
+            settings: AgentConfigHandle::new(Default::default()),
+            suricata: SuricataConfig {
+                command_socket: None,
+                update: Some(crate::agent::suricata::RulesUpdateConfig {
+                    command: vec![
+                        "sh".to_string(),
+                        "-c".to_string(),
+                        "echo updated; sleep 0.2; exit 2".to_string(),
+                    ],
+                    reload: false,
+                }),
+                commands: Vec::new(),
+            },
+            retention: None,
+            disable_certificate_check: false,
+        });
+        assert_eq!(
+            agent_capabilities(&config),
+            vec![CAPABILITY_CONFIG, CAPABILITY_RULES_UPDATE]
+        );
+        let workers = test_workers();
+        let jobs: Jobs = Arc::new(Mutex::new(HashMap::new()));
+        let (result_tx, mut result_rx) = mpsc::channel(RESULT_CHANNEL_CAPACITY);
+        let mut control = ControlState::default();
+        let mut handle =
+            |text: &str| handle_message(text, &config, &workers, &jobs, &result_tx, &mut control);
+
+        assert_eq!(
+            handle(r#"{"type":"hello","server_version":"test","capabilities":["rules-update"]}"#),
+            MessageOutcome::Continue
+        );
+        assert_eq!(
+            handle(r#"{"type":"rules-update","id":"first"}"#),
+            MessageOutcome::Continue
+        );
+        assert_eq!(
+            handle(r#"{"type":"rules-update","id":"second"}"#),
+            MessageOutcome::Reply {
+                message: AgentMessage::RulesUpdateResult {
+                    id: "second".to_string(),
+                    result: RulesUpdateResult {
+                        message: Some("a rule update is already running".to_string()),
+                        ..Default::default()
+                    },
+                },
+                reconnect: false,
+            }
+        );
+        assert_eq!(
+            result_rx.recv().await.unwrap(),
+            AgentMessage::RulesUpdateStatus {
+                id: "first".to_string(),
+                line: "updated".to_string(),
+            }
+        );
+        let AgentMessage::RulesUpdateResult { id, result } = result_rx.recv().await.unwrap() else {
+            panic!("expected a rule update result");
+        };
+        assert_eq!(id, "first");
+        assert_eq!(result.exit_code, Some(2));
+        assert!(!result.reloaded);
+    }
+
     #[test]
     fn malformed_control_json_is_fatal_but_unknown_types_are_tolerated() {
         assert_eq!(decode_server_message("not-json"), None);
@@ -1492,7 +1997,10 @@ mod tests {
             agent_id: "sensor".to_string(),
             hostname: "host".to_string(),
             server_key: None,
-            spool: SpoolConfig::new(directory.path(), None),
+            client_certificate: None,
+            settings: spool_settings(directory.path()),
+            suricata: SuricataConfig::default(),
+            retention: None,
             disable_certificate_check: false,
         };
         let client = crate::agent::client::build_reqwest_client(false).unwrap();
@@ -1510,6 +2018,7 @@ mod tests {
                 max_bytes: 8_000_000,
                 scan_timeout_ms: 60_000,
             },
+            None,
             &cancel,
             oneshot::channel().0,
         )
@@ -1557,7 +2066,10 @@ mod tests {
             agent_id: "sensor".to_string(),
             hostname: "host".to_string(),
             server_key: None,
-            spool: SpoolConfig::new(directory.path(), None),
+            client_certificate: None,
+            settings: spool_settings(directory.path()),
+            suricata: SuricataConfig::default(),
+            retention: None,
             disable_certificate_check: false,
         };
         let client = crate::agent::client::build_reqwest_client(false).unwrap();
@@ -1577,6 +2089,7 @@ mod tests {
                     max_bytes: 8_000_000,
                     scan_timeout_ms: 60_000,
                 },
+                None,
                 &worker_cancel,
                 oneshot::channel().0,
             )
@@ -1617,7 +2130,10 @@ mod tests {
             agent_id: "sensor".to_string(),
             hostname: "host".to_string(),
             server_key: None,
-            spool: SpoolConfig::new(directory.path(), None),
+            client_certificate: None,
+            settings: spool_settings(directory.path()),
+            suricata: SuricataConfig::default(),
+            retention: None,
             disable_certificate_check: false,
         };
         let client = crate::agent::client::build_reqwest_client(false).unwrap();
@@ -1636,6 +2152,7 @@ mod tests {
                     max_bytes: 8_000_000,
                     scan_timeout_ms: 60_000,
                 },
+                None,
                 &CancellationToken::new(),
                 oneshot::channel().0,
             ),
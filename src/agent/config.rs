@@ -0,0 +1,279 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+#![cfg_attr(windows, allow(dead_code))]
+
+//! Server-pushed agent configuration.
+//!
+//! The agent always starts from its local agent.yaml. A configuration pushed
+//! over the control channel is layered on top of it and applied live where
+//! the setting allows. When the agent cannot reach the server it falls back
+//! to the local file until the server pushes again.
+
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use tokio::sync::watch;
+
+use crate::agent::protocol::WireAgentConfig;
+use crate::eve::filters::EveFilterTrait;
+use crate::pcap::SpoolConfig;
+use crate::prelude::*;
+
+/// Names reported in `restart_required` for settings that cannot change
+/// while the agent runs.
+const INPUT_PATHS: &str = "input_paths";
+
+/// The agent settings a server may manage.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct AgentSettings {
+    pub(crate) input_paths: Vec<String>,
+    pub(crate) geoip: bool,
+    /// `None` when packet capture is disabled.
+    pub(crate) spool: Option<SpoolConfig>,
+    pub(crate) add_fields: BTreeMap<String, serde_json::Value>,
+}
+
+impl AgentSettings {
+    /// Layer a pushed configuration over these settings. Fields absent from
+    /// the push keep their value.
+    pub(crate) fn overlay(&self, config: &WireAgentConfig) -> Self {
+        let mut settings = self.clone();
+        if let Some(paths) = &config.input_paths {
+            settings.input_paths = paths.clone();
+        }
+        if let Some(geoip) = config.geoip {
+            settings.geoip = geoip;
+        }
+        if config.pcap_directory.is_some() || config.pcap_prefix.is_some() {
+            let directory = config
+                .pcap_directory
+                .as_deref()
+                .map(|value| value.trim().to_string())
+                .or_else(|| {
+                    self.spool
+                        .as_ref()
+                        .map(|spool| spool.directory.display().to_string())
+                })
+                .filter(|value| !value.is_empty());
+            let prefix = match &config.pcap_prefix {
+                Some(prefix) => Some(prefix.trim().to_string()).filter(|value| !value.is_empty()),
+                None => self.spool.as_ref().and_then(|spool| spool.prefix.clone()),
+            };
+            let index = self.spool.as_ref().is_some_and(|spool| spool.index);
+            settings.spool = directory.map(|directory| {
+                let mut spool = SpoolConfig::new(directory, prefix);
+                spool.index = index;
+                spool
+            });
+        }
+        if let Some(filters) = &config.filters {
+            settings.add_fields = filters.add_fields.clone();
+        }
+        settings
+    }
+
+    /// Settings changed between `self` and `next` that only take effect on
+    /// restart. Added input paths start live; removed ones keep running.
+    fn restart_required(&self, next: &Self) -> Vec<String> {
+        let mut names = Vec::new();
+        if self
+            .input_paths
+            .iter()
+            .any(|path| !next.input_paths.contains(path))
+        {
+            names.push(INPUT_PATHS.to_string());
+        }
+        names
+    }
+}
+
+/// The settings in effect and the server configuration version they came
+/// from; `None` means the local file alone.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct EffectiveSettings {
+    pub(crate) version: Option<u64>,
+    pub(crate) settings: Arc<AgentSettings>,
+}
+
+/// Shared handle to the agent's effective settings. The control channel
+/// updates it; the importer and packet-capture worker observe it.
+#[derive(Debug, Clone)]
+pub(crate) struct AgentConfigHandle {
+    local: Arc<AgentSettings>,
+    effective: Arc<watch::Sender<EffectiveSettings>>,
+}
+
+impl AgentConfigHandle {
+    pub(crate) fn new(local: AgentSettings) -> Self {
+        let local = Arc::new(local);
+        let effective = watch::Sender::new(EffectiveSettings {
+            version: None,
+            settings: local.clone(),
+        });
+        Self {
+            local,
+            effective: Arc::new(effective),
+        }
+    }
+
+    pub(crate) fn current(&self) -> EffectiveSettings {
+        self.effective.borrow().clone()
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<EffectiveSettings> {
+        self.effective.subscribe()
+    }
+
+    /// Apply a pushed configuration over the local file, returning the
+    /// changed settings that need an agent restart. Re-applying the version
+    /// already in effect is a no-op.
+    pub(crate) fn apply(&self, version: u64, config: &WireAgentConfig) -> Vec<String> {
+        let current = self.current();
+        if current.version == Some(version) {
+            return Vec::new();
+        }
+        let next = self.local.overlay(config);
+        let restart_required = current.settings.restart_required(&next);
+        info!("Applying server configuration version {version}");
+        if !restart_required.is_empty() {
+            warn!(
+                "Server configuration version {version} changes {restart_required:?}; restart the agent to apply them fully"
+            );
+        }
+        self.effective.send_replace(EffectiveSettings {
+            version: Some(version),
+            settings: Arc::new(next),
+        });
+        restart_required
+    }
+
+    /// Fall back to the local file. Returns false if it was already in
+    /// effect.
+    pub(crate) fn revert_to_local(&self) -> bool {
+        let reverted = self.effective.send_if_modified(|effective| {
+            if effective.version.is_none() {
+                return false;
+            }
+            *effective = EffectiveSettings {
+                version: None,
+                settings: self.local.clone(),
+            };
+            true
+        });
+        if reverted {
+            info!("Server unreachable; falling back to the local agent configuration");
+        }
+        reverted
+    }
+}
+
+/// Event filters taken from the effective settings: GeoIP and added
+/// fields. The GeoIP database is opened the first time it is enabled; a
+/// failure to open it is logged once and GeoIP stays off until restart.
+#[derive(Debug)]
+pub(crate) struct AgentSettingsFilter {
+    settings: watch::Receiver<EffectiveSettings>,
+    geoip: OnceLock<Option<crate::geoip::GeoIP>>,
+}
+
+impl AgentSettingsFilter {
+    pub(crate) fn new(handle: &AgentConfigHandle) -> Self {
+        Self {
+            settings: handle.subscribe(),
+            geoip: OnceLock::new(),
+        }
+    }
+
+    fn geoip(&self) -> Option<&crate::geoip::GeoIP> {
+        self.geoip
+            .get_or_init(|| match crate::geoip::GeoIP::open(None) {
+                Ok(geoip) => Some(geoip),
+                Err(err) => {
+                    warn!("Failed to open GeoIP database: {}", err);
+                    None
+                }
+            })
+            .as_ref()
+    }
+}
+
+impl EveFilterTrait for AgentSettingsFilter {
+    fn run(&self, event: &mut serde_json::Value) {
+        let settings = self.settings.borrow().settings.clone();
+        if settings.geoip
+            && let Some(geoip) = self.geoip()
+        {
+            geoip.add_geoip_to_eve(event);
+        }
+        for (field, value) in &settings.add_fields {
+            event[field] = value.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::agent::protocol::WireAgentFilters;
+
+    fn local() -> AgentSettings {
+        AgentSettings {
+            input_paths: vec!["/var/log/suricata/eve.json".to_string()],
+            geoip: false,
+            spool: Some(SpoolConfig::new("/captures", Some("log.".to_string()))),
+            add_fields: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn absent_fields_keep_the_local_value() {
+        let settings = local().overlay(&WireAgentConfig {
+            geoip: Some(true),
+            pcap_prefix: Some("  ".to_string()),
+            ..WireAgentConfig::default()
+        });
+        assert!(settings.geoip);
+        assert_eq!(settings.input_paths, local().input_paths);
+        let spool = settings.spool.unwrap();
+        assert_eq!(spool.directory, PathBuf::from("/captures"));
+        assert_eq!(spool.prefix, None);
+
+        let disabled = local().overlay(&WireAgentConfig {
+            pcap_directory: Some(String::new()),
+            ..WireAgentConfig::default()
+        });
+        assert!(disabled.spool.is_none());
+    }
+
+    #[test]
+    fn apply_reports_removed_inputs_and_reverts_to_local() {
+        let handle = AgentConfigHandle::new(local());
+        let config = WireAgentConfig {
+            input_paths: Some(vec!["/data/eve.json".to_string()]),
+            filters: Some(WireAgentFilters {
+                add_fields: BTreeMap::from([("sensor-name".to_string(), json!("edge"))]),
+            }),
+            ..WireAgentConfig::default()
+        };
+        assert_eq!(handle.apply(1, &config), vec![INPUT_PATHS.to_string()]);
+        assert_eq!(handle.current().version, Some(1));
+        // The same version again changes nothing.
+        assert!(handle.apply(1, &config).is_empty());
+
+        let filter = AgentSettingsFilter::new(&handle);
+        let mut event = json!({});
+        filter.run(&mut event);
+        assert_eq!(event["sensor-name"], "edge");
+
+        assert!(handle.revert_to_local());
+        assert!(!handle.revert_to_local());
+        assert_eq!(handle.current().version, None);
+        assert_eq!(*handle.current().settings, local());
+        let mut event = json!({});
+        filter.run(&mut event);
+        assert!(event.get("sensor-name").is_none());
+    }
+}
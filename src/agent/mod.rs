@@ -4,6 +4,11 @@
 #[cfg(not(windows))]
 pub(crate) mod channel;
 pub(crate) mod client;
+pub(crate) mod config;
 pub(crate) mod importer;
 pub(crate) mod protocol;
+#[cfg(not(windows))]
+pub(crate) mod retention;
+#[cfg(not(windows))]
+pub(crate) mod suricata;
 pub(crate) mod tls;
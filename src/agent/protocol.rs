@@ -8,6 +8,7 @@
 //! tolerate unknown message types and unknown fields so additive protocol
 //! changes do not disconnect older peers.
 
+use std::collections::BTreeMap;
 use std::net::IpAddr;
 
 use serde::{Deserialize, Serialize};
@@ -25,11 +26,47 @@ pub(crate) const AGENT_WS_PATH: &str = "/api/agent/ws";
 /// Packet-capture control-channel capability.
 pub(crate) const CAPABILITY_PCAP: &str = "pcap";
 
+/// pcapng output capability: the agent honours [`WirePcapng`] on a PCAP
+/// request. Older agents ignore the field and always upload classic pcap.
+pub(crate) const CAPABILITY_PCAPNG: &str = "pcapng";
+
+/// Structured filter capability: the agent renders
+/// [`WirePcapFilter::Fields`]. Older agents cannot parse the variant.
+pub(crate) const CAPABILITY_PCAP_FIELDS: &str = "pcap-fields";
+
+/// Server-pushed agent configuration capability.
+pub(crate) const CAPABILITY_CONFIG: &str = "config";
+
+/// Suricata rule update capability: the agent has a rule update command
+/// configured.
+pub(crate) const CAPABILITY_RULES_UPDATE: &str = "rules-update";
+
+/// Suricata command socket proxy capability.
+pub(crate) const CAPABILITY_SURICATA_COMMAND: &str = "suricata-command";
+
+/// In-process spool retention capability: the agent enforces its
+/// retention policy and keeps the files of the windows sent in
+/// [`ServerMessage::PcapProtect`].
+pub(crate) const CAPABILITY_PCAP_RETENTION: &str = "pcap-retention";
+
+/// Suricata unix command socket commands that may be proxied through an
+/// agent. Both peers enforce this list.
+pub(crate) const SURICATA_COMMANDS: &[&str] = &[
+    "dump-counters",
+    "iface-list",
+    "iface-stat",
+    "reload-rules",
+    "ruleset-stats",
+    "uptime",
+    "version",
+];
+
 /// Maximum inbound control message or frame size on either peer.
 pub(crate) const CONTROL_MESSAGE_MAX_BYTES: usize = 256 * 1024;
 
 /// Content type the agent sends on the PCAP upload data plane and the
-/// server's upload endpoint requires.
+/// server's upload endpoint requires. Used for pcapng uploads too: the
+/// server already knows the format it asked for.
 #[cfg_attr(windows, allow(dead_code))]
 pub(crate) const PCAP_CONTENT_TYPE: &str = "application/vnd.tcpdump.pcap";
 
@@ -80,9 +117,34 @@ pub(crate) enum WirePcapFilter {
     Expression {
         expression: String,
     },
+    /// Structured fields, rendered to BPF by the agent.
+    Fields {
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        hosts: Vec<IpAddr>,
+        /// (network address, prefix length) pairs.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        nets: Vec<(IpAddr, u8)>,
+        /// Inclusive (first, last) port ranges.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        ports: Vec<(u16, u16)>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        proto: Option<u8>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        direction: Option<WireDirection>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        vlan: Option<u16>,
+    },
     All,
 }
 
+/// The end of a packet the fields of [`WirePcapFilter::Fields`] match.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum WireDirection {
+    Src,
+    Dst,
+}
+
 /// Effective extraction limits selected by the server.
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub(crate) struct WireLimits {
@@ -92,6 +154,15 @@ pub(crate) struct WireLimits {
     pub(crate) scan_timeout_ms: u64,
 }
 
+/// pcapng output options for a PCAP request. Absent for classic pcap.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct WirePcapng {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) section_comment: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) packet_comment: Option<String>,
+}
+
 /// Terminal extraction statistics reported by an agent.
 #[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
 pub(crate) struct WireStats {
@@ -101,9 +172,28 @@ pub(crate) struct WireStats {
     pub(crate) files_scanned: u32,
     pub(crate) files_vanished: u32,
     #[serde(default)]
+    pub(crate) files_skipped: u32,
+    #[serde(default)]
     pub(crate) truncated: bool,
 }
 
+/// A packet time range, as inclusive Unix microseconds.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct WireWindow {
+    pub(crate) start_us: u64,
+    pub(crate) end_us: u64,
+}
+
+/// Spool size and age reported by an agent.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct WireSpoolUsage {
+    pub(crate) files: u64,
+    pub(crate) bytes: u64,
+    /// Oldest packet, as Unix microseconds; absent for an empty spool.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) oldest_us: Option<u64>,
+}
+
 /// Terminal outcome of an accepted packet-capture job.
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "kebab-case")]
@@ -157,6 +247,36 @@ impl PcapResult {
     }
 }
 
+/// Agent settings pushed by the server, stored per agent key.
+///
+/// Every field is optional: an absent field leaves the agent's local
+/// agent.yaml value in effect, so a server only manages what it sets.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct WireAgentConfig {
+    /// EVE input paths and patterns, replacing `input.paths`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) input_paths: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) geoip: Option<bool>,
+    /// Suricata pcap-log spool directory; an empty string disables packet
+    /// capture.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) pcap_directory: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) pcap_prefix: Option<String>,
+    /// Event filters: fields added to every event, replacing
+    /// `additional-fields`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) filters: Option<WireAgentFilters>,
+}
+
+/// Event filters carried by [`WireAgentConfig`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct WireAgentFilters {
+    #[serde(default)]
+    pub(crate) add_fields: BTreeMap<String, serde_json::Value>,
+}
+
 /// Messages sent from the server to an agent.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(tag = "type", rename_all = "kebab-case")]
@@ -177,10 +297,34 @@ pub(crate) enum ServerMessage {
         /// Inclusive upper packet timestamp bound, as Unix microseconds.
         end_us: u64,
         limits: WireLimits,
+        /// Write pcapng rather than classic pcap. Only sent to agents
+        /// advertising [`CAPABILITY_PCAPNG`].
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pcapng: Option<WirePcapng>,
     },
     /// Cancel a job. Best effort: a job whose control channel is gone is
     /// cancelled by the agent itself.
     Cancel { id: String, token: String },
+    /// Replace the server-managed configuration. Sent after hello when one
+    /// is stored for the agent's key, and again whenever it changes.
+    Config {
+        version: u64,
+        config: WireAgentConfig,
+    },
+    /// Run the agent's configured rule update command, then reload
+    /// Suricata's rules if the agent is configured to.
+    RulesUpdate { id: String },
+    /// Run one allow-listed command on Suricata's unix command socket.
+    SuricataCommand {
+        id: String,
+        command: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        arguments: Option<serde_json::Value>,
+    },
+    /// Replace the packet time ranges retention must keep. Sent after
+    /// hello and again each time the server refreshes them, only to
+    /// agents advertising [`CAPABILITY_PCAP_RETENTION`].
+    PcapProtect { windows: Vec<WireWindow> },
     /// A message type this build does not understand.
     #[serde(other)]
     Unknown,
@@ -197,16 +341,69 @@ pub(crate) enum AgentMessage {
         #[serde(flatten)]
         result: PcapResult,
     },
+    /// Acknowledges a [`ServerMessage::Config`]. Settings that could not be
+    /// applied without restarting the agent are named in `restart_required`.
+    ConfigApplied {
+        version: u64,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        restart_required: Vec<String>,
+    },
+    /// One line of rule update command output.
+    RulesUpdateStatus { id: String, line: String },
+    /// Exactly one terminal result per rule update request.
+    RulesUpdateResult {
+        id: String,
+        #[serde(flatten)]
+        result: RulesUpdateResult,
+    },
+    /// The reply to one [`ServerMessage::SuricataCommand`].
+    SuricataCommandResult {
+        id: String,
+        #[serde(flatten)]
+        result: SuricataCommandResult,
+    },
+    /// The spool usage after a retention pass, sent on connect and
+    /// whenever it changes.
+    SpoolUsage { usage: WireSpoolUsage },
     /// A message type this build does not understand.
     #[serde(other)]
     Unknown,
 }
 
+/// Terminal outcome of a rule update, carried by
+/// [`AgentMessage::RulesUpdateResult`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct RulesUpdateResult {
+    /// The command's exit code; absent when it could not be started, timed
+    /// out or was killed by a signal.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) exit_code: Option<i32>,
+    /// Whether Suricata acknowledged a rule reload.
+    #[serde(default)]
+    pub(crate) reloaded: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) message: Option<String>,
+}
+
+/// Outcome of a proxied Suricata command: Suricata's `message` on success,
+/// otherwise an error message from Suricata or the agent.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct SuricataCommandResult {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) response: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+}
+
 mod pcap_conversions {
     use std::time::Duration;
 
     use super::*;
-    use crate::pcap::{FetchStats, FlowSelector, Limits, PcapFilter};
+    use crate::pcap::retention::ProtectedWindows;
+    use crate::pcap::{
+        FetchStats, FieldDirection, FilterFields, FlowSelector, Limits, OutputFormat, PcapFilter,
+        SpoolUsage,
+    };
 
     impl From<&FlowSelector> for WirePcapFilter {
         fn from(selector: &FlowSelector) -> Self {
@@ -231,6 +428,23 @@ mod pcap_conversions {
                 PcapFilter::Expression(expression) => Self::Expression {
                     expression: expression.clone(),
                 },
+                PcapFilter::Fields(fields) => fields.into(),
+            }
+        }
+    }
+
+    impl From<&FilterFields> for WirePcapFilter {
+        fn from(fields: &FilterFields) -> Self {
+            Self::Fields {
+                hosts: fields.hosts.clone(),
+                nets: fields.nets.clone(),
+                ports: fields.ports.clone(),
+                proto: fields.proto,
+                direction: fields.direction.map(|direction| match direction {
+                    FieldDirection::Src => WireDirection::Src,
+                    FieldDirection::Dst => WireDirection::Dst,
+                }),
+                vlan: fields.vlan,
             }
         }
     }
@@ -257,11 +471,59 @@ mod pcap_conversions {
                     b: (b.ip, b.port),
                 })),
                 Self::Expression { expression } => Some(PcapFilter::Expression(expression)),
+                Self::Fields {
+                    hosts,
+                    nets,
+                    ports,
+                    proto,
+                    direction,
+                    vlan,
+                } => Some(PcapFilter::Fields(FilterFields {
+                    hosts,
+                    nets,
+                    ports,
+                    proto,
+                    direction: direction.map(|direction| match direction {
+                        WireDirection::Src => FieldDirection::Src,
+                        WireDirection::Dst => FieldDirection::Dst,
+                    }),
+                    vlan,
+                })),
                 Self::All => None,
             }
         }
     }
 
+    impl From<Option<WirePcapng>> for OutputFormat {
+        fn from(pcapng: Option<WirePcapng>) -> Self {
+            match pcapng {
+                Some(WirePcapng {
+                    section_comment,
+                    packet_comment,
+                }) => Self::Pcapng {
+                    section_comment,
+                    packet_comment,
+                },
+                None => Self::Pcap,
+            }
+        }
+    }
+
+    impl From<&OutputFormat> for Option<WirePcapng> {
+        fn from(format: &OutputFormat) -> Self {
+            match format {
+                OutputFormat::Pcap => None,
+                OutputFormat::Pcapng {
+                    section_comment,
+                    packet_comment,
+                } => Some(WirePcapng {
+                    section_comment: section_comment.clone(),
+                    packet_comment: packet_comment.clone(),
+                }),
+            }
+        }
+    }
+
     impl From<WireLimits> for Limits {
         fn from(limits: WireLimits) -> Self {
             Self {
@@ -295,6 +557,7 @@ mod pcap_conversions {
                 bytes: stats.bytes,
                 files_scanned: stats.files_scanned,
                 files_vanished: stats.files_vanished,
+                files_skipped: stats.files_skipped,
                 truncated: stats.truncated,
             }
         }
@@ -307,11 +570,45 @@ mod pcap_conversions {
                 bytes: stats.bytes,
                 files_scanned: stats.files_scanned,
                 files_vanished: stats.files_vanished,
+                files_skipped: stats.files_skipped,
                 truncated: stats.truncated,
                 linktype: None,
             }
         }
     }
+
+    impl ServerMessage {
+        /// The [`ServerMessage::PcapProtect`] carrying `windows`.
+        pub(crate) fn pcap_protect(windows: &ProtectedWindows) -> Self {
+            Self::PcapProtect {
+                windows: windows
+                    .windows()
+                    .iter()
+                    .map(|&(start_us, end_us)| WireWindow { start_us, end_us })
+                    .collect(),
+            }
+        }
+    }
+
+    impl From<SpoolUsage> for WireSpoolUsage {
+        fn from(usage: SpoolUsage) -> Self {
+            Self {
+                files: usage.files,
+                bytes: usage.bytes,
+                oldest_us: usage.oldest_us,
+            }
+        }
+    }
+
+    impl From<WireSpoolUsage> for SpoolUsage {
+        fn from(usage: WireSpoolUsage) -> Self {
+            Self {
+                files: usage.files,
+                bytes: usage.bytes,
+                oldest_us: usage.oldest_us,
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -367,6 +664,7 @@ mod tests {
                 max_bytes: 8_000_000,
                 scan_timeout_ms: 60_000,
             },
+            pcapng: None,
         };
         let text = serde_json::to_string(&message).unwrap();
         assert_eq!(
@@ -379,6 +677,33 @@ mod tests {
         );
     }
 
+    #[test]
+    fn pcapng_request_round_trips() {
+        let message = ServerMessage::PcapRequest {
+            id: "job-1".to_string(),
+            token: "token-1".to_string(),
+            filter: WirePcapFilter::All,
+            start_us: 1,
+            end_us: 2,
+            limits: WireLimits {
+                max_bytes: 1024,
+                scan_timeout_ms: 1000,
+            },
+            pcapng: Some(WirePcapng {
+                section_comment: Some("EveBox capture from agent sensor".to_string()),
+                packet_comment: None,
+            }),
+        };
+        let text = serde_json::to_string(&message).unwrap();
+        assert!(
+            text.contains(r#""pcapng":{"section_comment":"EveBox capture from agent sensor"}"#)
+        );
+        assert_eq!(
+            serde_json::from_str::<ServerMessage>(&text).unwrap(),
+            message
+        );
+    }
+
     #[test]
     fn flow_filter_omits_absent_ports() {
         let filter = WirePcapFilter::Flow {
@@ -411,6 +736,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn fields_filter_round_trips() {
+        let filter = WirePcapFilter::Fields {
+            hosts: vec!["192.0.2.1".parse().unwrap()],
+            nets: vec![("10.0.0.0".parse().unwrap(), 8)],
+            ports: vec![(8000, 8100)],
+            proto: Some(6),
+            direction: Some(WireDirection::Dst),
+            vlan: None,
+        };
+        let text = serde_json::to_string(&filter).unwrap();
+        assert_eq!(
+            text,
+            r#"{"type":"fields","hosts":["192.0.2.1"],"nets":[["10.0.0.0",8]],"ports":[[8000,8100]],"proto":6,"direction":"dst"}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<WirePcapFilter>(&text).unwrap(),
+            filter
+        );
+    }
+
     #[test]
     fn cancel_repeats_the_token() {
         let message = ServerMessage::Cancel {
@@ -439,6 +785,7 @@ mod tests {
                     bytes: 8_192,
                     files_scanned: 4,
                     files_vanished: 1,
+                    files_skipped: 2,
                     truncated: false,
                 }),
             },
@@ -452,6 +799,119 @@ mod tests {
         );
     }
 
+    #[test]
+    fn config_push_and_ack_have_stable_wire_shapes() {
+        let message = ServerMessage::Config {
+            version: 3,
+            config: WireAgentConfig {
+                geoip: Some(true),
+                pcap_directory: Some(String::new()),
+                ..WireAgentConfig::default()
+            },
+        };
+        let text = serde_json::to_string(&message).unwrap();
+        assert_eq!(
+            text,
+            r#"{"type":"config","version":3,"config":{"geoip":true,"pcap_directory":""}}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<ServerMessage>(&text).unwrap(),
+            message
+        );
+
+        let ack = AgentMessage::ConfigApplied {
+            version: 3,
+            restart_required: Vec::new(),
+        };
+        let text = serde_json::to_string(&ack).unwrap();
+        assert_eq!(text, r#"{"type":"config-applied","version":3}"#);
+        assert_eq!(serde_json::from_str::<AgentMessage>(&text).unwrap(), ack);
+    }
+
+    #[test]
+    fn rules_update_messages_have_stable_wire_shapes() {
+        let request = ServerMessage::RulesUpdate {
+            id: "job-4".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&request).unwrap(),
+            r#"{"type":"rules-update","id":"job-4"}"#
+        );
+        let result = AgentMessage::RulesUpdateResult {
+            id: "job-4".to_string(),
+            result: RulesUpdateResult {
+                exit_code: Some(0),
+                reloaded: true,
+                message: None,
+            },
+        };
+        let text = serde_json::to_string(&result).unwrap();
+        assert_eq!(
+            text,
+            r#"{"type":"rules-update-result","id":"job-4","exit_code":0,"reloaded":true}"#
+        );
+        assert_eq!(serde_json::from_str::<AgentMessage>(&text).unwrap(), result);
+    }
+
+    #[test]
+    fn suricata_command_messages_have_stable_wire_shapes() {
+        let request = ServerMessage::SuricataCommand {
+            id: "cmd-1".to_string(),
+            command: "iface-stat".to_string(),
+            arguments: Some(json!({"iface": "eth0"})),
+        };
+        assert_eq!(
+            serde_json::to_string(&request).unwrap(),
+            r#"{"type":"suricata-command","id":"cmd-1","command":"iface-stat","arguments":{"iface":"eth0"}}"#
+        );
+        let result = AgentMessage::SuricataCommandResult {
+            id: "cmd-1".to_string(),
+            result: SuricataCommandResult {
+                response: Some(json!({"pkts": 10})),
+                error: None,
+            },
+        };
+        let text = serde_json::to_string(&result).unwrap();
+        assert_eq!(
+            text,
+            r#"{"type":"suricata-command-result","id":"cmd-1","response":{"pkts":10}}"#
+        );
+        assert_eq!(serde_json::from_str::<AgentMessage>(&text).unwrap(), result);
+    }
+
+    #[test]
+    fn retention_messages_have_stable_wire_shapes() {
+        let protect = ServerMessage::PcapProtect {
+            windows: vec![WireWindow {
+                start_us: 1,
+                end_us: 2,
+            }],
+        };
+        let text = serde_json::to_string(&protect).unwrap();
+        assert_eq!(
+            text,
+            r#"{"type":"pcap-protect","windows":[{"start_us":1,"end_us":2}]}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<ServerMessage>(&text).unwrap(),
+            protect
+        );
+
+        let usage = AgentMessage::SpoolUsage {
+            usage: WireSpoolUsage {
+                files: 3,
+                bytes: 30,
+                oldest_us: None,
+            },
+        };
+        let text = serde_json::to_string(&usage).unwrap();
+        assert_eq!(
+            text,
+            r#"{"type":"spool-usage","usage":{"files":3,"bytes":30}}"#
+        );
+        assert_eq!(serde_json::from_str::<AgentMessage>(&text).unwrap(), usage);
+    }
+
     #[test]
     fn unknown_message_types_are_tolerated_in_both_directions() {
         assert_eq!(
@@ -0,0 +1,156 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! In-process retention of the agent's packet-capture spool.
+//!
+//! The policy comes from the local `pcap.retention` settings. The
+//! protected windows (escalated events) come from the server over the
+//! control channel, and the spool usage after each pass goes back to
+//! it.
+
+use tokio::sync::watch;
+
+use crate::agent::config::AgentConfigHandle;
+use crate::agent::protocol::WireWindow;
+use crate::pcap::SpoolUsage;
+use crate::pcap::retention::{
+    self, MAX_PROTECTED_WINDOWS, ProtectedWindows, RetentionPolicy, RetentionSettings,
+};
+use crate::prelude::*;
+
+/// Retention settings and the state shared with the control channel.
+#[derive(Debug)]
+pub(crate) struct RetentionState {
+    settings: RetentionSettings,
+    /// The server's last protected windows; `None` until the first list
+    /// arrives. Kept across reconnects.
+    protected: watch::Sender<Option<ProtectedWindows>>,
+    /// Spool usage after the last pass; `None` without a spool.
+    usage: watch::Sender<Option<SpoolUsage>>,
+}
+
+impl RetentionState {
+    pub(crate) fn new(settings: RetentionSettings) -> Self {
+        Self {
+            settings,
+            protected: watch::Sender::new(None),
+            usage: watch::Sender::new(None),
+        }
+    }
+
+    /// Replace the protected windows with the server's list.
+    pub(crate) fn protect(&self, windows: Vec<WireWindow>) {
+        let windows = ProtectedWindows::new(
+            windows
+                .into_iter()
+                .map(|window| (window.start_us, window.end_us)),
+            MAX_PROTECTED_WINDOWS,
+        );
+        debug!(
+            "PCAP retention: {} protected windows from the server",
+            windows.windows().len()
+        );
+        self.protected.send_replace(Some(windows));
+    }
+
+    #[cfg(test)]
+    pub(crate) fn protected_windows(&self) -> Option<ProtectedWindows> {
+        self.protected.borrow().clone()
+    }
+
+    pub(crate) fn subscribe_usage(&self) -> watch::Receiver<Option<SpoolUsage>> {
+        self.usage.subscribe()
+    }
+
+    /// The policy and protected windows of the next pass. Until the
+    /// server's first list arrives only the minimum free space is
+    /// enforced, so a restart without a server connection cannot delete
+    /// the packets of escalated events for age or size.
+    fn plan(&self) -> (RetentionPolicy, ProtectedWindows) {
+        if !self.settings.protect_escalated {
+            return (self.settings.policy.clone(), ProtectedWindows::default());
+        }
+        match &*self.protected.borrow() {
+            Some(protected) => (self.settings.policy.clone(), protected.clone()),
+            None => (
+                RetentionPolicy {
+                    min_free: self.settings.policy.min_free,
+                    ..Default::default()
+                },
+                ProtectedWindows::default(),
+            ),
+        }
+    }
+}
+
+/// Enforce retention on the current spool every interval. Runs forever.
+pub(crate) async fn run(state: Arc<RetentionState>, settings: AgentConfigHandle) {
+    let mut interval = tokio::time::interval(state.settings.interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let Some(spool) = settings.current().settings.spool.clone() else {
+            state.usage.send_replace(None);
+            continue;
+        };
+        let (policy, protected) = state.plan();
+        let result =
+            tokio::task::spawn_blocking(move || retention::enforce(&spool, &policy, &protected))
+                .await;
+        match result {
+            Ok(Ok(usage)) => {
+                state.usage.send_if_modified(|current| {
+                    let changed = *current != Some(usage);
+                    *current = Some(usage);
+                    changed
+                });
+            }
+            Ok(Err(err)) => warn!("PCAP retention failed: {err}"),
+            Err(err) => error!("PCAP retention panicked: {err}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn only_min_free_applies_until_the_server_protects() {
+        let policy = RetentionPolicy {
+            max_age: Some(Duration::from_secs(60)),
+            min_free: Some(1024),
+            ..Default::default()
+        };
+        let state = RetentionState::new(RetentionSettings {
+            policy: policy.clone(),
+            ..Default::default()
+        });
+        let (pending, protected) = state.plan();
+        assert_eq!(pending.max_age, None);
+        assert_eq!(pending.min_free, Some(1024));
+        assert!(protected.is_empty());
+
+        state.protect(vec![
+            WireWindow {
+                start_us: 10,
+                end_us: 20,
+            },
+            WireWindow {
+                start_us: 15,
+                end_us: 30,
+            },
+        ]);
+        let (active, protected) = state.plan();
+        assert_eq!(active, policy);
+        assert_eq!(protected.windows(), [(10, 30)]);
+
+        let state = RetentionState::new(RetentionSettings {
+            policy: policy.clone(),
+            protect_escalated: false,
+            ..Default::default()
+        });
+        assert_eq!(state.plan().0, policy);
+    }
+}
@@ -0,0 +1,490 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! Suricata on the agent host: the rule update command, and a client for
+//! Suricata's unix command socket used for rule reloads and proxied
+//! commands.
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+use tokio::sync::mpsc;
+
+use crate::agent::protocol::{
+    AgentMessage, CONTROL_MESSAGE_MAX_BYTES, RulesUpdateResult, SURICATA_COMMANDS,
+    SuricataCommandResult,
+};
+use crate::prelude::*;
+
+/// Suricata's default command socket when `suricata.command-socket` is
+/// not set.
+pub(crate) const DEFAULT_COMMAND_SOCKET: &str = "/var/run/suricata/suricata-command.socket";
+
+/// Protocol version sent in the command socket handshake.
+const SOCKET_PROTOCOL_VERSION: &str = "0.2";
+
+/// Bound on one command socket response. `dump-counters` on a busy
+/// many-threaded sensor is the largest reply and stays well below this.
+const MAX_SOCKET_RESPONSE_BYTES: usize = 8 * 1024 * 1024;
+
+/// Bound on the whole command socket exchange. A rule reload on a large
+/// ruleset is the slowest command.
+const SOCKET_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Bound on a rule update command's run time.
+const RULES_UPDATE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// Bound on the output lines streamed back per rule update; the rest is
+/// dropped with a note.
+const MAX_STATUS_LINES: usize = 1000;
+
+/// Output lines longer than this are cut.
+const MAX_STATUS_LINE_BYTES: usize = 1024;
+
+/// Suricata settings from the `suricata` and `suricata-update` sections of
+/// agent.yaml.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct SuricataConfig {
+    pub(crate) command_socket: Option<PathBuf>,
+    pub(crate) update: Option<RulesUpdateConfig>,
+    /// Command socket commands the server may run through this agent; a
+    /// subset of [`SURICATA_COMMANDS`]. Empty disables the proxy.
+    pub(crate) commands: Vec<String>,
+}
+
+impl SuricataConfig {
+    pub(crate) fn command_socket(&self) -> &Path {
+        self.command_socket
+            .as_deref()
+            .unwrap_or_else(|| Path::new(DEFAULT_COMMAND_SOCKET))
+    }
+}
+
+/// The rule update command and whether to reload Suricata after it
+/// succeeds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RulesUpdateConfig {
+    /// Program and arguments; never run through a shell.
+    pub(crate) command: Vec<String>,
+    pub(crate) reload: bool,
+}
+
+/// Run one command over Suricata's unix command socket and return its
+/// `message`. A `NOK` return is an error carrying Suricata's message.
+pub(crate) async fn socket_command(
+    socket: &Path,
+    command: &str,
+    arguments: Option<&serde_json::Value>,
+) -> Result<serde_json::Value> {
+    tokio::time::timeout(SOCKET_TIMEOUT, socket_exchange(socket, command, arguments))
+        .await
+        .map_err(|_| {
+            anyhow!("no response from the Suricata command socket within {SOCKET_TIMEOUT:?}")
+        })?
+}
+
+async fn socket_exchange(
+    socket: &Path,
+    command: &str,
+    arguments: Option<&serde_json::Value>,
+) -> Result<serde_json::Value> {
+    let mut stream = UnixStream::connect(socket).await.with_context(|| {
+        format!(
+            "failed to connect to the Suricata command socket {}",
+            socket.display()
+        )
+    })?;
+    let mut buf = Vec::new();
+
+    let hello = json!({"version": SOCKET_PROTOCOL_VERSION});
+    stream.write_all(format!("{hello}\n").as_bytes()).await?;
+    let response = read_response(&mut stream, &mut buf).await?;
+    if response["return"] != "OK" {
+        bail!("Suricata refused the command socket handshake: {response}");
+    }
+
+    let mut request = json!({"command": command});
+    if let Some(arguments) = arguments {
+        request["arguments"] = arguments.clone();
+    }
+    stream.write_all(format!("{request}\n").as_bytes()).await?;
+    let response = read_response(&mut stream, &mut buf).await?;
+    match response["return"].as_str() {
+        Some("OK") => Ok(response["message"].clone()),
+        _ => bail!(
+            "Suricata command {command} failed: {}",
+            response["message"]
+                .as_str()
+                .map(str::to_string)
+                .unwrap_or_else(|| response.to_string())
+        ),
+    }
+}
+
+/// Run a command on behalf of the server. Only commands this agent allows
+/// are run, and a response too large for the control channel is refused.
+pub(crate) async fn proxy_command(
+    config: &SuricataConfig,
+    command: &str,
+    arguments: Option<&serde_json::Value>,
+) -> SuricataCommandResult {
+    if !SURICATA_COMMANDS.contains(&command) || !config.commands.iter().any(|c| c == command) {
+        return SuricataCommandResult {
+            response: None,
+            error: Some(format!("command {command} is not allowed on this agent")),
+        };
+    }
+    match socket_command(config.command_socket(), command, arguments).await {
+        Ok(response) => {
+            // Leave room for the envelope around the response.
+            let size = serde_json::to_string(&response).map_or(usize::MAX, |text| text.len());
+            if size > CONTROL_MESSAGE_MAX_BYTES - 1024 {
+                return SuricataCommandResult {
+                    response: None,
+                    error: Some(format!(
+                        "the response to {command} ({size} bytes) is too large to return"
+                    )),
+                };
+            }
+            SuricataCommandResult {
+                response: Some(response),
+                error: None,
+            }
+        }
+        Err(err) => SuricataCommandResult {
+            response: None,
+            error: Some(format!("{err:#}")),
+        },
+    }
+}
+
+/// Read one JSON value. Suricata ends each message with a newline, so the
+/// buffer is only parsed once a read ends a line, or the socket closes.
+async fn read_response(stream: &mut UnixStream, buf: &mut Vec<u8>) -> Result<serde_json::Value> {
+    buf.clear();
+    let mut chunk = [0u8; 64 * 1024];
+    loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            if buf.iter().all(u8::is_ascii_whitespace) {
+                bail!("the Suricata command socket closed before replying");
+            }
+            return serde_json::from_slice(buf)
+                .map_err(|err| anyhow!("malformed reply from the Suricata command socket: {err}"));
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if chunk[..n].contains(&b'\n') {
+            match serde_json::from_slice::<serde_json::Value>(buf) {
+                Ok(value) => return Ok(value),
+                // A newline within a value, not the end of the message.
+                Err(err) if err.is_eof() => {}
+                Err(err) => bail!("malformed reply from the Suricata command socket: {err}"),
+            }
+        }
+        if buf.len() > MAX_SOCKET_RESPONSE_BYTES {
+            bail!(
+                "reply from the Suricata command socket exceeds {MAX_SOCKET_RESPONSE_BYTES} bytes"
+            );
+        }
+    }
+}
+
+/// Run the rule update command, streaming its output as status messages,
+/// and reload Suricata on success when configured to. A closed `status`
+/// channel (the connection went away) does not stop the update: a rule
+/// update interrupted halfway is worse than one nobody is watching.
+pub(crate) async fn run_rules_update(
+    config: &SuricataConfig,
+    id: &str,
+    status: &mpsc::Sender<AgentMessage>,
+) -> RulesUpdateResult {
+    let Some(update) = &config.update else {
+        return RulesUpdateResult {
+            message: Some("no rule update command is configured on this agent".to_string()),
+            ..Default::default()
+        };
+    };
+    let Some((program, args)) = update.command.split_first() else {
+        return RulesUpdateResult {
+            message: Some("the rule update command is empty".to_string()),
+            ..Default::default()
+        };
+    };
+    info!("Running rule update command {:?}", update.command);
+    let mut child = match tokio::process::Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) => {
+            return RulesUpdateResult {
+                message: Some(format!("failed to start {program:?}: {err}")),
+                ..Default::default()
+            };
+        }
+    };
+
+    let mut stdout = BufReader::new(child.stdout.take().unwrap()).lines();
+    let mut stderr = BufReader::new(child.stderr.take().unwrap()).lines();
+    let mut sent = 0;
+    let mut stdout_open = true;
+    let mut stderr_open = true;
+    let deadline = tokio::time::sleep(RULES_UPDATE_TIMEOUT);
+    tokio::pin!(deadline);
+
+    while stdout_open || stderr_open {
+        let line = tokio::select! {
+            line = stdout.next_line(), if stdout_open => {
+                stdout_open = still_open(&line);
+                line
+            }
+            line = stderr.next_line(), if stderr_open => {
+                stderr_open = still_open(&line);
+                line
+            }
+            _ = &mut deadline => {
+                let _ = child.kill().await;
+                return RulesUpdateResult {
+                    message: Some(format!("rule update timed out after {RULES_UPDATE_TIMEOUT:?}")),
+                    ..Default::default()
+                };
+            }
+        };
+        if let Ok(Some(line)) = line {
+            sent += 1;
+            if sent <= MAX_STATUS_LINES {
+                send_status(status, id, truncate_line(line)).await;
+            } else if sent == MAX_STATUS_LINES + 1 {
+                send_status(status, id, "[further output dropped]".to_string()).await;
+            }
+        }
+    }
+
+    let exit = tokio::select! {
+        exit = child.wait() => exit,
+        _ = &mut deadline => {
+            let _ = child.kill().await;
+            return RulesUpdateResult {
+                message: Some(format!("rule update timed out after {RULES_UPDATE_TIMEOUT:?}")),
+                ..Default::default()
+            };
+        }
+    };
+    let exit_code = match exit {
+        Ok(exit) => exit.code(),
+        Err(err) => {
+            return RulesUpdateResult {
+                message: Some(format!("failed to wait for the rule update command: {err}")),
+                ..Default::default()
+            };
+        }
+    };
+    if exit_code != Some(0) {
+        return RulesUpdateResult {
+            exit_code,
+            message: Some("the rule update command failed; Suricata was not reloaded".to_string()),
+            ..Default::default()
+        };
+    }
+    if !update.reload {
+        return RulesUpdateResult {
+            exit_code,
+            ..Default::default()
+        };
+    }
+
+    send_status(status, id, "Reloading Suricata rules".to_string()).await;
+    match socket_command(config.command_socket(), "reload-rules", None).await {
+        Ok(_) => RulesUpdateResult {
+            exit_code,
+            reloaded: true,
+            message: None,
+        },
+        Err(err) => RulesUpdateResult {
+            exit_code,
+            reloaded: false,
+            message: Some(format!("{err:#}")),
+        },
+    }
+}
+
+/// A stream stays open past a line that is not UTF-8; that line is
+/// skipped.
+fn still_open(line: &std::io::Result<Option<String>>) -> bool {
+    match line {
+        Ok(line) => line.is_some(),
+        Err(err) => err.kind() == std::io::ErrorKind::InvalidData,
+    }
+}
+
+async fn send_status(status: &mpsc::Sender<AgentMessage>, id: &str, line: String) {
+    let _ = status
+        .send(AgentMessage::RulesUpdateStatus {
+            id: id.to_string(),
+            line,
+        })
+        .await;
+}
+
+fn truncate_line(mut line: String) -> String {
+    if line.len() > MAX_STATUS_LINE_BYTES {
+        let mut end = MAX_STATUS_LINE_BYTES;
+        while !line.is_char_boundary(end) {
+            end -= 1;
+        }
+        line.truncate(end);
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::UnixListener;
+
+    /// A fake Suricata command socket answering the handshake and one
+    /// command with `reply`.
+    fn fake_socket(dir: &Path, reply: serde_json::Value) -> PathBuf {
+        let path = dir.join("suricata-command.socket");
+        let listener = UnixListener::bind(&path).unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = Vec::new();
+            let hello = read_response(&mut stream, &mut buf).await.unwrap();
+            assert_eq!(hello["version"], SOCKET_PROTOCOL_VERSION);
+            stream.write_all(b"{\"return\": \"OK\"}\n").await.unwrap();
+            let command = read_response(&mut stream, &mut buf).await.unwrap();
+            assert!(command["command"].is_string());
+            // Split the reply to exercise reassembly.
+            let reply = format!("{reply}\n").into_bytes();
+            let (head, tail) = reply.split_at(reply.len() / 2);
+            stream.write_all(head).await.unwrap();
+            stream.flush().await.unwrap();
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            stream.write_all(tail).await.unwrap();
+        });
+        path
+    }
+
+    #[tokio::test]
+    async fn socket_command_returns_the_message_or_the_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = fake_socket(
+            dir.path(),
+            json!({"return": "OK", "message": {"uptime": 42}}),
+        );
+        let message = socket_command(&socket, "uptime", None).await.unwrap();
+        assert_eq!(message["uptime"], 42);
+
+        let dir = tempfile::tempdir().unwrap();
+        let socket = fake_socket(
+            dir.path(),
+            json!({"return": "NOK", "message": "Unknown command"}),
+        );
+        let err = socket_command(&socket, "bogus", None).await.unwrap_err();
+        assert!(err.to_string().contains("Unknown command"));
+    }
+
+    #[tokio::test]
+    async fn replies_are_read_to_their_newline_or_the_close() {
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+        let mut buf = Vec::new();
+        server.write_all(b"{\"return\":").await.unwrap();
+        server.write_all(b" \"OK\"}\n").await.unwrap();
+        let reply = read_response(&mut client, &mut buf).await.unwrap();
+        assert_eq!(reply["return"], "OK");
+
+        server.write_all(b"{\"message\": 1}").await.unwrap();
+        drop(server);
+        let reply = read_response(&mut client, &mut buf).await.unwrap();
+        assert_eq!(reply["message"], 1);
+        assert!(read_response(&mut client, &mut buf).await.is_err());
+
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+        server.write_all(b"{\"return\" \"OK\"}\n").await.unwrap();
+        let err = read_response(&mut client, &mut buf).await.unwrap_err();
+        assert!(err.to_string().contains("malformed"));
+    }
+
+    #[tokio::test]
+    async fn proxy_runs_only_commands_the_agent_allows() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = fake_socket(
+            dir.path(),
+            json!({"return": "OK", "message": {"pkts": 7, "drop": 0}}),
+        );
+        let config = SuricataConfig {
+            command_socket: Some(socket),
+            update: None,
+            commands: vec!["iface-stat".to_string()],
+        };
+        let result = proxy_command(&config, "iface-stat", Some(&json!({"iface": "eth0"}))).await;
+        assert_eq!(result.error, None);
+        assert_eq!(result.response.unwrap()["pkts"], 7);
+
+        let refused = proxy_command(&config, "dump-counters", None).await;
+        assert!(refused.error.unwrap().contains("not allowed"));
+        let refused = proxy_command(&config, "shutdown", None).await;
+        assert!(refused.error.unwrap().contains("not allowed"));
+    }
+
+    #[tokio::test]
+    async fn rules_update_streams_output_and_reloads() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = fake_socket(dir.path(), json!({"return": "OK", "message": "done"}));
+        let config = SuricataConfig {
+            command_socket: Some(socket),
+            update: Some(RulesUpdateConfig {
+                command: vec![
+                    "sh".to_string(),
+                    "-c".to_string(),
+                    "echo fetching; echo warning >&2".to_string(),
+                ],
+                reload: true,
+            }),
+            commands: Vec::new(),
+        };
+        let (tx, mut rx) = mpsc::channel(16);
+        let result = run_rules_update(&config, "job", &tx).await;
+        assert_eq!(
+            result,
+            RulesUpdateResult {
+                exit_code: Some(0),
+                reloaded: true,
+                message: None,
+            }
+        );
+        drop(tx);
+        let mut lines = Vec::new();
+        while let Some(AgentMessage::RulesUpdateStatus { line, .. }) = rx.recv().await {
+            lines.push(line);
+        }
+        assert!(lines.contains(&"fetching".to_string()));
+        assert!(lines.contains(&"warning".to_string()));
+        assert_eq!(lines.last().unwrap(), "Reloading Suricata rules");
+    }
+
+    #[tokio::test]
+    async fn failed_rules_update_does_not_reload() {
+        let config = SuricataConfig {
+            // Never connected: a failed command must not reach the reload.
+            command_socket: Some(PathBuf::from("/nonexistent/suricata.socket")),
+            update: Some(RulesUpdateConfig {
+                command: vec!["sh".to_string(), "-c".to_string(), "exit 3".to_string()],
+                reload: true,
+            }),
+            commands: Vec::new(),
+        };
+        let (tx, _rx) = mpsc::channel(16);
+        let result = run_rules_update(&config, "job", &tx).await;
+        assert_eq!(result.exit_code, Some(3));
+        assert!(!result.reloaded);
+    }
+}
@@ -8,8 +8,11 @@
 //! Normal connections use tokio-tungstenite's native-root rustls connector.
 //! When the existing agent `disable-certificate-check` option is enabled, the
 //! custom connector skips certificate chain, name, and expiry validation but
-//! still verifies the handshake signature against the presented key.
+//! still verifies the handshake signature against the presented key. An
+//! agent configured with a client certificate presents it on the control
+//! channel.
 
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use anyhow::Context;
@@ -17,9 +20,11 @@ use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, Server
 use rustls::crypto::{
     WebPkiSupportedAlgorithms, ring, verify_tls12_signature, verify_tls13_signature,
 };
-use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
 use rustls::{DigitallySignedStruct, SignatureScheme};
 use tokio_tungstenite::Connector;
+use tracing::{debug, warn};
 
 use super::protocol::AGENT_WS_PATH;
 
@@ -64,31 +69,99 @@ pub(crate) fn websocket_url(server_url: &str) -> anyhow::Result<String> {
     Ok(url.into())
 }
 
+/// The agent's TLS client certificate (`server.certificate`) and its
+/// private key (`server.certificate-key`), both PEM files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ClientCertificate {
+    pub(crate) certificate: PathBuf,
+    pub(crate) key: PathBuf,
+}
+
+impl ClientCertificate {
+    /// Read the certificate chain and key. They are read on every connect
+    /// so a renewed certificate is picked up without a restart.
+    fn load(&self) -> anyhow::Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+        let pem = std::fs::read(&self.certificate)
+            .with_context(|| format!("failed to read {}", self.certificate.display()))?;
+        let certs = CertificateDer::pem_slice_iter(&pem)
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("invalid certificate file {}", self.certificate.display()))?;
+        if certs.is_empty() {
+            bail!("no certificates found in {}", self.certificate.display());
+        }
+        let pem = std::fs::read(&self.key)
+            .with_context(|| format!("failed to read {}", self.key.display()))?;
+        let key = PrivateKeyDer::from_pem_slice(&pem)
+            .with_context(|| format!("invalid private key file {}", self.key.display()))?;
+        Ok((certs, key))
+    }
+
+    /// Check that the files load, for reporting configuration errors at
+    /// startup.
+    pub(crate) fn check(&self) -> anyhow::Result<()> {
+        self.load().map(|_| ())
+    }
+}
+
 /// Select the TLS connector for the agent control channel.
 ///
 /// `None` tells tokio-tungstenite to use its normal native-root verifier.
 /// Certificate checking is disabled only when explicitly requested, matching
 /// the existing agent HTTP client's `danger_accept_invalid_certs` behavior.
-pub(crate) fn connector(disable_certificate_check: bool) -> Option<Connector> {
-    disable_certificate_check.then(|| Connector::Rustls(Arc::new(insecure_client_config())))
+/// A client certificate needs its own connector, verifying the server
+/// against the same native roots unless checking is disabled.
+pub(crate) fn connector(
+    disable_certificate_check: bool,
+    client_certificate: Option<&ClientCertificate>,
+) -> anyhow::Result<Option<Connector>> {
+    let builder = client_config_builder(disable_certificate_check);
+    let config = match client_certificate {
+        Some(client_certificate) => {
+            let (certs, key) = client_certificate.load()?;
+            builder
+                .with_client_auth_cert(certs, key)
+                .context("invalid client certificate or key")?
+        }
+        None if disable_certificate_check => builder.with_no_client_auth(),
+        None => return Ok(None),
+    };
+    Ok(Some(Connector::Rustls(Arc::new(config))))
 }
 
-fn insecure_client_config() -> rustls::ClientConfig {
-    rustls::ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+fn client_config_builder(
+    disable_certificate_check: bool,
+) -> rustls::ConfigBuilder<rustls::ClientConfig, rustls::client::WantsClientCert> {
+    let builder = rustls::ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
         .with_safe_default_protocol_versions()
-        .expect("ring provides safe default protocol versions")
-        .dangerous()
-        .with_custom_certificate_verifier(Arc::new(NoVerifier::new()))
-        .with_no_client_auth()
+        .expect("ring provides safe default protocol versions");
+    if disable_certificate_check {
+        return builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoVerifier::new()));
+    }
+    let mut roots = rustls::RootCertStore::empty();
+    let native = rustls_native_certs::load_native_certs();
+    for err in &native.errors {
+        warn!("agent channel: failed to load a native root certificate: {err}");
+    }
+    let (added, ignored) = roots.add_parsable_certificates(native.certs);
+    debug!("agent channel: loaded {added} native root certificates ({ignored} ignored)");
+    builder.with_root_certificates(roots)
+}
+
+#[cfg(test)]
+fn insecure_client_config() -> rustls::ClientConfig {
+    client_config_builder(true).with_no_client_auth()
 }
 
+/// Accepts any server certificate, for when checking is disabled.
 #[derive(Debug)]
-struct NoVerifier {
+pub(crate) struct NoVerifier {
     supported: WebPkiSupportedAlgorithms,
 }
 
 impl NoVerifier {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self {
             supported: ring::default_provider().signature_verification_algorithms,
         }
@@ -176,12 +249,42 @@ mod tests {
 
     #[test]
     fn verifying_path_uses_the_default_connector() {
-        assert!(connector(false).is_none());
+        assert!(connector(false, None).unwrap().is_none());
     }
 
     #[test]
     fn disabled_certificate_check_supplies_a_rustls_connector() {
-        assert!(matches!(connector(true), Some(Connector::Rustls(_))));
+        assert!(matches!(
+            connector(true, None).unwrap(),
+            Some(Connector::Rustls(_))
+        ));
+    }
+
+    #[test]
+    fn client_certificates_supply_a_rustls_connector() {
+        let dir = tempfile::tempdir().unwrap();
+        let issued = crate::cert::get_or_create_agent_ca(dir.path())
+            .unwrap()
+            .issue("sensor-a", 30)
+            .unwrap();
+        let client_certificate = ClientCertificate {
+            certificate: dir.path().join("sensor-a.pem"),
+            key: dir.path().join("sensor-a-key.pem"),
+        };
+        assert!(client_certificate.check().is_err());
+        std::fs::write(&client_certificate.certificate, &issued.cert_pem).unwrap();
+        std::fs::write(&client_certificate.key, &issued.key_pem).unwrap();
+        client_certificate.check().unwrap();
+        for disable_certificate_check in [false, true] {
+            assert!(matches!(
+                connector(disable_certificate_check, Some(&client_certificate)).unwrap(),
+                Some(Connector::Rustls(_))
+            ));
+        }
+
+        // A key that is not a key is refused.
+        std::fs::write(&client_certificate.key, &issued.cert_pem).unwrap();
+        assert!(connector(false, Some(&client_certificate)).is_err());
     }
 
     #[test]
@@ -2,7 +2,11 @@
 // SPDX-License-Identifier: MIT
 
 use anyhow::Result;
-use rcgen::{CertificateParams, DistinguishedName, DnType, Ia5String, KeyPair};
+use rcgen::{
+    BasicConstraints, CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose,
+    Ia5String, IsCa, KeyPair, KeyUsagePurpose,
+};
+use sha2::{Digest, Sha256};
 use std::fs;
 use std::path::{Path, PathBuf};
 use tracing::info;
@@ -12,6 +16,10 @@ static CN_NAME: &str = "EveBox Server";
 static CERT_FILENAME: &str = "cert.pem";
 static KEY_FILENAME: &str = "key.pem";
 
+static AGENT_CA_NAME: &str = "EveBox Agent CA";
+pub(crate) static AGENT_CA_CERT_FILENAME: &str = "agent-ca.pem";
+static AGENT_CA_KEY_FILENAME: &str = "agent-ca-key.pem";
+
 pub(crate) fn create_and_write_cert<P: AsRef<Path>>(dir: P) -> Result<(PathBuf, PathBuf)> {
     let mut params: CertificateParams = Default::default();
     params.not_before = rcgen::date_time_ymd(2023, 1, 1);
@@ -56,3 +64,244 @@ pub(crate) fn get_or_create_cert<P: AsRef<Path>>(dir: P) -> Result<(PathBuf, Pat
         Ok((cert_path, key_path))
     }
 }
+
+/// The local CA that issues agent client certificates.
+pub(crate) struct AgentCa {
+    pub(crate) cert_path: PathBuf,
+    certificate: rcgen::Certificate,
+    key_pair: KeyPair,
+}
+
+/// An agent client certificate and its private key, both PEM encoded.
+pub(crate) struct IssuedCertificate {
+    pub(crate) cert_pem: String,
+    pub(crate) key_pem: String,
+    pub(crate) fingerprint: String,
+}
+
+/// The CA's parameters are fixed so the CA can be rebuilt from its key
+/// alone: issuing only needs its name and key identifier, both of which
+/// are derived from these parameters and the key.
+fn agent_ca_params() -> CertificateParams {
+    let mut params: CertificateParams = Default::default();
+    params.not_before = rcgen::date_time_ymd(2026, 1, 1);
+    params.not_after = rcgen::date_time_ymd(3026, 1, 1);
+    params.distinguished_name = DistinguishedName::new();
+    params
+        .distinguished_name
+        .push(DnType::OrganizationName, ORG_NAME);
+    params
+        .distinguished_name
+        .push(DnType::CommonName, AGENT_CA_NAME);
+    params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    params
+}
+
+/// Load the agent CA from `dir`, creating it on first use.
+pub(crate) fn get_or_create_agent_ca<P: AsRef<Path>>(dir: P) -> Result<AgentCa> {
+    let dir = dir.as_ref();
+    let cert_path = dir.join(AGENT_CA_CERT_FILENAME);
+    let key_path = dir.join(AGENT_CA_KEY_FILENAME);
+    if key_path.exists() {
+        let key_pair = KeyPair::from_pem(&fs::read_to_string(&key_path)?)?;
+        let certificate = agent_ca_params().self_signed(&key_pair)?;
+        if !cert_path.exists() {
+            fs::write(&cert_path, certificate.pem().as_bytes())?;
+        }
+        return Ok(AgentCa {
+            cert_path,
+            certificate,
+            key_pair,
+        });
+    }
+    let key_pair = KeyPair::generate()?;
+    let certificate = agent_ca_params().self_signed(&key_pair)?;
+    write_private(&key_path, key_pair.serialize_pem().as_bytes())?;
+    fs::write(&cert_path, certificate.pem().as_bytes())?;
+    info!(
+        "Created agent CA certificate and key: {}, {}",
+        cert_path.display(),
+        key_path.display()
+    );
+    Ok(AgentCa {
+        cert_path,
+        certificate,
+        key_pair,
+    })
+}
+
+impl AgentCa {
+    /// Issue a client certificate for the agent `name`, valid for `days`.
+    /// The agent name is the certificate's common name.
+    pub(crate) fn issue(&self, name: &str, days: u32) -> Result<IssuedCertificate> {
+        let mut params: CertificateParams = Default::default();
+        let now = time::OffsetDateTime::now_utc();
+        params.not_before = now - time::Duration::hours(1);
+        params.not_after = now + time::Duration::days(days.into());
+        params.distinguished_name = DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(DnType::OrganizationName, ORG_NAME);
+        params.distinguished_name.push(DnType::CommonName, name);
+        params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        params.use_authority_key_identifier_extension = true;
+        let mut serial = [0u8; 16];
+        rand::RngCore::fill_bytes(&mut rand::rng(), &mut serial);
+        serial[0] &= 0x7f;
+        params.serial_number = Some(serial.to_vec().into());
+        let key_pair = KeyPair::generate()?;
+        let cert = params.signed_by(&key_pair, &self.certificate, &self.key_pair)?;
+        Ok(IssuedCertificate {
+            cert_pem: cert.pem(),
+            key_pem: key_pair.serialize_pem(),
+            fingerprint: fingerprint(cert.der()),
+        })
+    }
+}
+
+/// Write a file readable only by its owner.
+pub(crate) fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(contents)
+}
+
+/// The SHA-256 fingerprint of a DER certificate as lowercase hex.
+pub(crate) fn fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Normalize a SHA-256 fingerprint as printed by common tools, such as
+/// `openssl x509 -fingerprint -sha256`: colons and case are ignored.
+pub(crate) fn normalize_fingerprint(input: &str) -> Option<String> {
+    let hex: String = input
+        .trim()
+        .chars()
+        .filter(|c| *c != ':')
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    (hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit())).then_some(hex)
+}
+
+/// Split one DER element into its tag, contents and the remaining input.
+fn der_element(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&first, mut input) = input.split_first()?;
+    let length = if first < 0x80 {
+        first as usize
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 || input.len() < count {
+            return None;
+        }
+        let length = input[..count]
+            .iter()
+            .fold(0usize, |length, byte| (length << 8) | *byte as usize);
+        input = &input[count..];
+        length
+    };
+    if input.len() < length {
+        return None;
+    }
+    Some((tag, &input[..length], &input[length..]))
+}
+
+/// The subject common name of a DER certificate.
+pub(crate) fn common_name(der: &[u8]) -> Option<String> {
+    const SEQUENCE: u8 = 0x30;
+    const SET: u8 = 0x31;
+    const COMMON_NAME_OID: &[u8] = &[0x55, 0x04, 0x03];
+
+    let (SEQUENCE, certificate, _) = der_element(der)? else {
+        return None;
+    };
+    let (SEQUENCE, mut tbs, _) = der_element(certificate)? else {
+        return None;
+    };
+    // Skip the optional explicit version, then the serial number,
+    // signature algorithm, issuer and validity.
+    if tbs.first() == Some(&0xa0) {
+        tbs = der_element(tbs)?.2;
+    }
+    for _ in 0..4 {
+        tbs = der_element(tbs)?.2;
+    }
+    let (SEQUENCE, mut subject, _) = der_element(tbs)? else {
+        return None;
+    };
+    while !subject.is_empty() {
+        let (tag, mut set, rest) = der_element(subject)?;
+        subject = rest;
+        if tag != SET {
+            return None;
+        }
+        while !set.is_empty() {
+            let (_, attribute, rest) = der_element(set)?;
+            set = rest;
+            let (_, oid, value) = der_element(attribute)?;
+            if oid != COMMON_NAME_OID {
+                continue;
+            }
+            let (tag, value, _) = der_element(value)?;
+            // UTF8String, PrintableString or IA5String.
+            if [0x0c, 0x13, 0x16].contains(&tag) {
+                return String::from_utf8(value.to_vec()).ok();
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use rustls_pki_types::pem::PemObject;
+
+    use super::*;
+
+    #[test]
+    fn issued_agent_certificates_carry_the_agent_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca = get_or_create_agent_ca(dir.path()).unwrap();
+        let issued = ca.issue("sensor-a", 30).unwrap();
+
+        let der =
+            rustls_pki_types::CertificateDer::from_pem_slice(issued.cert_pem.as_bytes()).unwrap();
+        assert_eq!(common_name(&der).as_deref(), Some("sensor-a"));
+        assert_eq!(issued.fingerprint, fingerprint(&der));
+
+        // Reloading the CA from its key keeps issuing under the same name.
+        let reloaded = get_or_create_agent_ca(dir.path()).unwrap();
+        assert_eq!(reloaded.cert_path, ca.cert_path);
+        let ca_pem = fs::read(&ca.cert_path).unwrap();
+        let ca_der = rustls_pki_types::CertificateDer::from_pem_slice(&ca_pem).unwrap();
+        assert_eq!(common_name(&ca_der).as_deref(), Some(AGENT_CA_NAME));
+    }
+
+    #[test]
+    fn fingerprints_are_normalized() {
+        let hex = "ab".repeat(32);
+        let colons = vec!["AB"; 32].join(":");
+        assert_eq!(
+            normalize_fingerprint(&colons).as_deref(),
+            Some(hex.as_str())
+        );
+        assert_eq!(normalize_fingerprint(&hex).as_deref(), Some(hex.as_str()));
+        assert!(normalize_fingerprint("abcd").is_none());
+        assert!(normalize_fingerprint(&"zz".repeat(32)).is_none());
+    }
+
+    #[test]
+    fn malformed_certificates_have_no_common_name() {
+        assert!(common_name(&[]).is_none());
+        assert!(common_name(&[0x30, 0x82, 0xff]).is_none());
+        assert!(common_name(&[0x30, 0x00]).is_none());
+    }
+}
@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: MIT
 
 use crate::agent::client::Client;
+use crate::agent::config::{AgentConfigHandle, AgentSettings, AgentSettingsFilter};
 use crate::agent::importer::EveBoxEventSink;
 use crate::config::Config;
 use crate::eve::filters::EveFilterChain;
@@ -11,7 +12,7 @@ use crate::{bookmark, eve};
 use clap::{CommandFactory, Parser};
 use futures::StreamExt;
 use futures::stream::FuturesUnordered;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::task::JoinHandle;
@@ -138,62 +139,51 @@ pub async fn main(args_matches: &clap::ArgMatches) -> anyhow::Result<()> {
     #[cfg(windows)]
     let (agent_id, _) = agent_identity(&config);
 
-    // The packet-capture channel is optional and deliberately independent of
-    // the EVE importer tasks below. Direct-to-Elasticsearch mode has no
-    // EveBox server connection to carry control messages, so it cannot serve
-    // remote capture requests.
+    // The settings a server may manage, as read from the local file. They
+    // remain in effect until the server pushes a configuration, and again
+    // whenever the server cannot be reached.
+    let local_settings = local_settings(&config, args_matches)?;
+    for (field, value) in &local_settings.add_fields {
+        info!("Adding custom field: {} -> {:?}", field, value);
+    }
+    let settings = AgentConfigHandle::new(local_settings);
+
+    // The control channel is optional and deliberately independent of the
+    // EVE importer tasks below. Direct-to-Elasticsearch mode has no EveBox
+    // server connection to carry control messages, so it cannot serve
+    // remote capture requests or receive configuration.
     #[cfg(not(windows))]
-    let pcap_channel = build_pcap_channel(
+    let channel = build_channel(
         &config,
         &server_url,
         disable_certificate_check,
         &agent_id,
         &agent_hostname,
+        &settings,
     )?;
     #[cfg(windows)]
-    let pcap_channel = {
-        if config
-            .get_string("pcap.directory")
-            .map(|value| !value.trim().is_empty())
-            .unwrap_or(false)
-        {
-            warn!("Full packet capture is not supported on Windows; ignoring pcap configuration");
-        }
-        None::<()>
-    };
+    let channel = None::<()>;
 
     // Collect EVE file and socket inputs.
-    let eve_filenames = get_eve_filenames(&config)?;
     let delete_processed_spool_files = config.get_bool("input.delete-spool-files")?;
     let eve_sockets = eve::socket::get_inputs(&config)?;
-    if eve_filenames.is_empty() && eve_sockets.is_empty() {
-        if pcap_channel.is_some() {
+    if settings.current().settings.input_paths.is_empty() && eve_sockets.is_empty() {
+        if settings.current().settings.spool.is_some() {
             info!("No EVE inputs configured; running in pcap-only mode (events are not shipped)");
+        } else if channel.is_some() {
+            info!("No EVE inputs configured; waiting for input paths from the server");
         } else {
             bail!("No EVE inputs configured. Exiting as there is nothing to do.");
         }
     }
 
-    let enable_geoip = args_matches
-        .get_one::<bool>("geoip.enabled")
-        .is_some_and(|v| *v);
-
     let rule_filenames = get_rule_filenames(&config)?;
 
     let mut filters = EveFilterChain::with_defaults();
     filters.add_filter(eve::filters::AddAgentHostnameFilter::default());
     filters.add_filter(eve::filters::AddAgentIdFilter::new(agent_id));
-
-    if enable_geoip {
-        match crate::geoip::GeoIP::open(None) {
-            Err(err) => {
-                warn!("Failed to open GeoIP database: {}", err);
-            }
-            Ok(geoipdb) => {
-                filters.add_filter(eve::filters::GeoIpFilter::new(geoipdb));
-            }
-        }
-    }
+    // GeoIP and the additional fields follow the effective settings.
+    filters.add_filter(AgentSettingsFilter::new(&settings));
 
     if !rule_filenames.is_empty() {
         let rule_collection = Arc::new(crate::rules::load_rules(&rule_filenames));
@@ -203,16 +193,6 @@ pub async fn main(args_matches: &clap::ArgMatches) -> anyhow::Result<()> {
         crate::rules::watch_rules(rule_collection);
     }
 
-    // Get additional fields to add to events.
-    let additional_fields = get_additional_fields(&config)?;
-    if let Some(custom_fields) = additional_fields {
-        for (field, value) in custom_fields {
-            info!("Adding custom field: {} -> {:?}", field, value);
-            let filter = crate::eve::filters::AddFieldFilter::new(field, value);
-            filters.add_filter(filter);
-        }
-    }
-
     let mut log_runners = HashSet::new();
 
     let importer = if config.get_bool("elasticsearch.enabled")? {
@@ -274,11 +254,33 @@ pub async fn main(args_matches: &clap::ArgMatches) -> anyhow::Result<()> {
     // fail-fast EVE processor set so a control-channel reconnect can never
     // terminate event shipping, and pcap-only mode can have an empty set.
     #[cfg(not(windows))]
-    if let Some(channel) = pcap_channel {
-        info!("Starting full packet capture control channel");
+    if let Some(channel) = channel {
+        if settings
+            .current()
+            .settings
+            .spool
+            .as_ref()
+            .is_some_and(|spool| spool.index)
+        {
+            let settings = settings.clone();
+            tokio::spawn(crate::pcap::index::run_indexer(move || {
+                settings.current().settings.spool.clone()
+            }));
+        }
+        if let Some(retention) = &channel.retention {
+            tokio::spawn(crate::agent::retention::run(
+                retention.clone(),
+                settings.clone(),
+            ));
+        }
+        info!("Starting agent control channel");
         tokio::spawn(crate::agent::channel::run(channel));
     }
 
+    // Input paths may change with a server-pushed configuration; new paths
+    // are picked up on the next scan, which a change triggers immediately.
+    let mut settings_rx = settings.subscribe();
+
     let mut tasks = FuturesUnordered::new();
 
     for input in eve_sockets {
@@ -290,6 +292,7 @@ pub async fn main(args_matches: &clap::ArgMatches) -> anyhow::Result<()> {
     }
 
     loop {
+        let eve_filenames = settings_rx.borrow_and_update().settings.input_paths.clone();
         let mut paths = Vec::new();
         for path in &eve_filenames {
             paths.extend(crate::path::expand(path)?);
@@ -318,6 +321,7 @@ pub async fn main(args_matches: &clap::ArgMatches) -> anyhow::Result<()> {
         }
         tokio::select! {
             _ = tokio::time::sleep(std::time::Duration::from_secs(60)) => {}
+            _ = settings_rx.changed() => {}
             _ = tasks.select_next_some(), if !tasks.is_empty() => {
                 bail!("An EVE input task unexpectedly aborted");
             }
@@ -337,17 +341,31 @@ fn agent_identity(config: &Config) -> (String, String) {
     (agent_id, hostname)
 }
 
-/// Build the persistent packet-capture channel configuration, or return
-/// `None` when no spool directory is configured or packet capture is
-/// incompatible with the selected output.
-#[cfg(not(windows))]
-fn build_pcap_channel(
+/// The locally configured settings that a server-pushed configuration may
+/// override.
+fn local_settings(
     config: &Config,
-    server_url: &str,
-    disable_certificate_check: bool,
-    agent_id: &str,
-    hostname: &str,
-) -> anyhow::Result<Option<crate::agent::channel::ChannelConfig>> {
+    args_matches: &clap::ArgMatches,
+) -> anyhow::Result<AgentSettings> {
+    let geoip = args_matches
+        .get_one::<bool>("geoip.enabled")
+        .is_some_and(|v| *v);
+    let add_fields: BTreeMap<String, serde_json::Value> = get_additional_fields(config)?
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+    Ok(AgentSettings {
+        input_paths: get_eve_filenames(config)?,
+        geoip,
+        spool: local_spool(config)?,
+        add_fields,
+    })
+}
+
+/// The packet-capture spool from the local configuration, or `None` when no
+/// spool directory is configured or packet capture is incompatible with the
+/// selected output.
+fn local_spool(config: &Config) -> anyhow::Result<Option<crate::pcap::SpoolConfig>> {
     // Packet capture is enabled by setting a spool directory, either
     // `pcap.directory` in the configuration file or --pcap-directory on the
     // command line; there is no separate enable flag, matching the server.
@@ -359,6 +377,10 @@ fn build_pcap_channel(
         return Ok(None);
     };
 
+    if cfg!(windows) {
+        warn!("Full packet capture is not supported on Windows; ignoring pcap configuration");
+        return Ok(None);
+    }
     if config.get_bool("elasticsearch.enabled")? {
         warn!(
             "Full packet capture is not supported with direct Elasticsearch output; ignoring pcap configuration"
@@ -369,23 +391,131 @@ fn build_pcap_channel(
         .get_string("pcap.prefix")
         .map(|value| value.trim().to_string())
         .filter(|value| !value.is_empty());
+    info!("Full packet capture enabled: spool {directory}");
+    let mut spool = crate::pcap::SpoolConfig::new(directory, prefix);
+    spool.index = config.get_bool("pcap.index")?;
+    Ok(Some(spool))
+}
+
+/// Suricata rule update and command proxy settings from the
+/// `suricata-update` and `suricata` sections. The update command may be a
+/// list or a whitespace-separated string; it is never run through a shell.
+#[cfg(not(windows))]
+fn local_suricata(config: &Config) -> anyhow::Result<crate::agent::suricata::SuricataConfig> {
+    let command: Vec<String> =
+        match config.get_value::<serde_yaml::Value>("suricata-update.command")? {
+            None | Some(serde_yaml::Value::Null) => Vec::new(),
+            Some(serde_yaml::Value::String(command)) => {
+                command.split_whitespace().map(String::from).collect()
+            }
+            Some(value) => serde_yaml::from_value(value).map_err(|_| {
+                anyhow!("suricata-update.command must be a string or a list of strings")
+            })?,
+        };
+    let update = if command.is_empty() {
+        None
+    } else {
+        let reload = config.get_bool_with_default("suricata-update.reload", false);
+        info!("Remote rule updates enabled: command {command:?}, reload={reload}");
+        Some(crate::agent::suricata::RulesUpdateConfig { command, reload })
+    };
+    let command_socket = config
+        .get_string("suricata.command-socket")
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from);
+    let commands = config
+        .get_value::<Vec<String>>("suricata.commands")
+        .map_err(|_| anyhow!("suricata.commands must be a list of command names"))?
+        .unwrap_or_default();
+    for command in &commands {
+        if !crate::agent::protocol::SURICATA_COMMANDS.contains(&command.as_str()) {
+            bail!(
+                "suricata.commands: {command:?} cannot be proxied; supported commands are {}",
+                crate::agent::protocol::SURICATA_COMMANDS.join(", ")
+            );
+        }
+    }
+    if !commands.is_empty() {
+        info!("Suricata command proxy enabled for {commands:?}");
+    }
+    Ok(crate::agent::suricata::SuricataConfig {
+        command_socket,
+        update,
+        commands,
+    })
+}
+
+/// Build the persistent control channel configuration, or return `None`
+/// when there is nothing for it to do: no spool directory, rule update
+/// command, proxied Suricata command or agent key (to receive
+/// server-managed configuration) is configured, or the output is direct
+/// Elasticsearch.
+#[cfg(not(windows))]
+fn build_channel(
+    config: &Config,
+    server_url: &str,
+    disable_certificate_check: bool,
+    agent_id: &str,
+    hostname: &str,
+    settings: &AgentConfigHandle,
+) -> anyhow::Result<Option<crate::agent::channel::ChannelConfig>> {
+    if config.get_bool("elasticsearch.enabled")? {
+        return Ok(None);
+    }
     let server_key = config
         .get_string("server.key")
         .map(|value| value.trim().to_string())
         .filter(|value| !value.is_empty());
+    let suricata = local_suricata(config)?;
+    let client_certificate = client_certificate(config)?;
+    if server_key.is_none()
+        && client_certificate.is_none()
+        && settings.current().settings.spool.is_none()
+        && suricata.update.is_none()
+        && suricata.commands.is_empty()
+    {
+        return Ok(None);
+    }
     let server_url = crate::agent::tls::normalize_server_url(server_url)?;
-    info!("Full packet capture enabled: spool {directory} as agent {agent_id:?}");
+    let retention = crate::pcap::retention::configure(config)?;
+    info!("Agent control channel enabled as agent {agent_id:?}");
 
     Ok(Some(crate::agent::channel::ChannelConfig {
         server_url,
         agent_id: agent_id.to_string(),
         hostname: hostname.to_string(),
         server_key,
-        spool: crate::pcap::SpoolConfig::new(directory, prefix),
+        client_certificate,
+        settings: settings.clone(),
+        suricata,
+        retention: Some(Arc::new(crate::agent::retention::RetentionState::new(
+            retention,
+        ))),
         disable_certificate_check,
     }))
 }
 
+/// The control channel's TLS client certificate, from
+/// `server.certificate` and `server.certificate-key`.
+#[cfg(not(windows))]
+fn client_certificate(
+    config: &Config,
+) -> anyhow::Result<Option<crate::agent::tls::ClientCertificate>> {
+    let certificate = config.get_string("server.certificate");
+    let key = config.get_string("server.certificate-key");
+    let client_certificate = match (certificate, key) {
+        (Some(certificate), Some(key)) => crate::agent::tls::ClientCertificate {
+            certificate: certificate.into(),
+            key: key.into(),
+        },
+        (None, None) => return Ok(None),
+        _ => bail!("server.certificate and server.certificate-key must be set together"),
+    };
+    client_certificate.check()?;
+    Ok(Some(client_certificate))
+}
+
 fn start_runner(
     input: &EveInput,
     importer: EventSink,
@@ -565,14 +695,19 @@ mod tests {
         yaml_config_with_args(yaml, &[])
     }
 
-    /// Build the channel the way `main()` does: the agent identity is
-    /// resolved from the same configuration.
+    /// Build the channel the way `main()` does: the agent identity and the
+    /// local settings are resolved from the same configuration.
     fn channel_from(
         config: &Config,
         server_url: &str,
     ) -> anyhow::Result<Option<crate::agent::channel::ChannelConfig>> {
         let (agent_id, hostname) = agent_identity(config);
-        build_pcap_channel(config, server_url, false, &agent_id, &hostname)
+        let settings = AgentConfigHandle::new(local_settings(config, &config.args)?);
+        build_channel(config, server_url, false, &agent_id, &hostname, &settings)
+    }
+
+    fn spool(channel: &crate::agent::channel::ChannelConfig) -> crate::pcap::SpoolConfig {
+        channel.settings.current().settings.spool.clone().unwrap()
     }
 
     fn yaml_config_with_args(yaml: &str, args: &[&str]) -> (tempfile::TempDir, Config) {
@@ -597,8 +732,8 @@ mod tests {
             .unwrap();
         assert_eq!(channel.agent_id, "edge-a");
         assert_eq!(channel.server_url, "https://evebox.test");
-        assert_eq!(channel.spool.directory, PathBuf::from("/captures"));
-        assert_eq!(channel.spool.prefix.as_deref(), Some("log.pcap"));
+        assert_eq!(spool(&channel).directory, PathBuf::from("/captures"));
+        assert_eq!(spool(&channel).prefix.as_deref(), Some("log.pcap"));
         drop(dir);
     }
 
@@ -633,8 +768,8 @@ mod tests {
         )
         .unwrap()
         .unwrap();
-        assert_eq!(channel.spool.directory, PathBuf::from("/captures"));
-        assert_eq!(channel.spool.prefix, None);
+        assert_eq!(spool(&channel).directory, PathBuf::from("/captures"));
+        assert_eq!(spool(&channel).prefix, None);
         assert_eq!(
             channel.agent_id,
             gethostname::gethostname().to_string_lossy()
@@ -660,8 +795,8 @@ mod tests {
         let channel = channel_from(&config, "https://evebox.test")
             .unwrap()
             .unwrap();
-        assert_eq!(channel.spool.directory, PathBuf::from("/captures"));
-        assert_eq!(channel.spool.prefix.as_deref(), Some("log.pcap"));
+        assert_eq!(spool(&channel).directory, PathBuf::from("/captures"));
+        assert_eq!(spool(&channel).prefix.as_deref(), Some("log.pcap"));
     }
 
     #[test]
@@ -675,8 +810,105 @@ mod tests {
         let channel = channel_from(&config, "https://evebox.test")
             .unwrap()
             .unwrap();
-        assert_eq!(channel.spool.directory, PathBuf::from("/from-cli"));
-        assert_eq!(channel.spool.prefix.as_deref(), Some("yaml."));
+        assert_eq!(spool(&channel).directory, PathBuf::from("/from-cli"));
+        assert_eq!(spool(&channel).prefix.as_deref(), Some("yaml."));
+    }
+
+    #[test]
+    fn agent_key_starts_channel_without_a_spool() {
+        let (_dir, keyed) =
+            yaml_config("elasticsearch:\n  enabled: false\nserver:\n  key: eba_test\n");
+        let channel = channel_from(&keyed, "http://evebox.test").unwrap().unwrap();
+        assert_eq!(channel.server_key.as_deref(), Some("eba_test"));
+        assert!(channel.settings.current().settings.spool.is_none());
+
+        let (_dir, neither) = yaml_config("elasticsearch:\n  enabled: false\n");
+        assert!(
+            channel_from(&neither, "http://evebox.test")
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn client_certificate_starts_channel() {
+        let dir = tempfile::tempdir().unwrap();
+        let issued = crate::cert::get_or_create_agent_ca(dir.path())
+            .unwrap()
+            .issue("sensor-a", 30)
+            .unwrap();
+        let certificate = dir.path().join("sensor-a.pem");
+        let key = dir.path().join("sensor-a-key.pem");
+        std::fs::write(&certificate, &issued.cert_pem).unwrap();
+        std::fs::write(&key, &issued.key_pem).unwrap();
+
+        let (_dir, config) = yaml_config(&format!(
+            "elasticsearch:\n  enabled: false\nserver:\n  certificate: {}\n  certificate-key: {}\n",
+            certificate.display(),
+            key.display()
+        ));
+        let channel = channel_from(&config, "https://evebox.test")
+            .unwrap()
+            .unwrap();
+        assert!(channel.server_key.is_none());
+        assert_eq!(
+            channel.client_certificate,
+            Some(crate::agent::tls::ClientCertificate { certificate, key })
+        );
+
+        let (_dir, half) = yaml_config(
+            "elasticsearch:\n  enabled: false\nserver:\n  certificate: /nonexistent.pem\n",
+        );
+        assert!(channel_from(&half, "https://evebox.test").is_err());
+    }
+
+    #[test]
+    fn rules_update_command_starts_channel() {
+        let (_dir, string) = yaml_config(
+            "elasticsearch:\n  enabled: false\nsuricata-update:\n  command: suricata-update --no-reload\n  reload: true\nsuricata:\n  command-socket: /run/suricata.socket\n",
+        );
+        let channel = channel_from(&string, "http://evebox.test")
+            .unwrap()
+            .unwrap();
+        let update = channel.suricata.update.as_ref().unwrap();
+        assert_eq!(update.command, vec!["suricata-update", "--no-reload"]);
+        assert!(update.reload);
+        assert_eq!(
+            channel.suricata.command_socket(),
+            std::path::Path::new("/run/suricata.socket")
+        );
+
+        let (_dir, list) = yaml_config(
+            "elasticsearch:\n  enabled: false\nsuricata-update:\n  command: [/usr/bin/suricata-update, -o, /rules]\n",
+        );
+        let channel = channel_from(&list, "http://evebox.test").unwrap().unwrap();
+        let update = channel.suricata.update.as_ref().unwrap();
+        assert_eq!(
+            update.command,
+            vec!["/usr/bin/suricata-update", "-o", "/rules"]
+        );
+        assert!(!update.reload);
+        assert_eq!(
+            channel.suricata.command_socket(),
+            std::path::Path::new(crate::agent::suricata::DEFAULT_COMMAND_SOCKET)
+        );
+    }
+
+    #[test]
+    fn suricata_command_proxy_is_limited_to_supported_commands() {
+        let (_dir, proxy) = yaml_config(
+            "elasticsearch:\n  enabled: false\nsuricata:\n  commands: [iface-stat, dump-counters]\n",
+        );
+        let channel = channel_from(&proxy, "http://evebox.test").unwrap().unwrap();
+        assert_eq!(
+            channel.suricata.commands,
+            vec!["iface-stat", "dump-counters"]
+        );
+        assert!(channel.suricata.update.is_none());
+
+        let (_dir, unsupported) =
+            yaml_config("elasticsearch:\n  enabled: false\nsuricata:\n  commands: [shutdown]\n");
+        assert!(channel_from(&unsupported, "http://evebox.test").is_err());
     }
 
     #[test]
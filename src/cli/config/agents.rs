@@ -1,12 +1,14 @@
 // SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
 // SPDX-License-Identifier: MIT
 
+use std::time::Duration;
+
 use anyhow::Result;
 use clap::FromArgMatches;
 use clap::Parser;
 use clap::Subcommand;
 
-use super::users::open_config_repo;
+use super::users::{config_repo_directory, open_config_repo};
 
 #[derive(Parser, Debug)]
 #[command(name = "agents", about = "Configure agent keys")]
@@ -21,6 +23,9 @@ enum AgentsCommands {
     Add {
         /// Agent name, normally the agent's `agent-id`
         name: String,
+        /// Expire the key after this long, such as 90d
+        #[arg(long, value_parser = humantime::parse_duration)]
+        expires: Option<Duration>,
         #[arg(from_global, id = "config-directory")]
         config_directory: Option<String>,
         #[arg(from_global, id = "data-directory")]
@@ -37,6 +42,63 @@ enum AgentsCommands {
         #[arg(from_global, id = "data-directory")]
         data_directory: Option<String>,
     },
+    /// Replace an agent's key, keeping its identity and configuration
+    Rotate {
+        name: String,
+        /// Keep accepting the replaced key for this long, such as 24h;
+        /// by default it stops working immediately
+        #[arg(long, value_parser = humantime::parse_duration)]
+        grace: Option<Duration>,
+        /// Expire the new key after this long, such as 90d
+        #[arg(long, value_parser = humantime::parse_duration)]
+        expires: Option<Duration>,
+        #[arg(from_global, id = "config-directory")]
+        config_directory: Option<String>,
+        #[arg(from_global, id = "data-directory")]
+        data_directory: Option<String>,
+    },
+    /// Set when an agent's key expires, from now, or "never"
+    Expire {
+        name: String,
+        /// A duration such as 30d, or "never" to remove the expiry
+        expires: String,
+        #[arg(from_global, id = "config-directory")]
+        config_directory: Option<String>,
+        #[arg(from_global, id = "data-directory")]
+        data_directory: Option<String>,
+    },
+    /// Map client certificates to an agent, by fingerprint and/or subject
+    /// common name; omitted values clear the mapping
+    Certificate {
+        name: String,
+        /// SHA-256 fingerprint, as printed by
+        /// `openssl x509 -noout -fingerprint -sha256`
+        #[arg(long)]
+        fingerprint: Option<String>,
+        /// Subject common name accepted from certificates issued by the
+        /// configured client CA
+        #[arg(long)]
+        subject: Option<String>,
+        #[arg(from_global, id = "config-directory")]
+        config_directory: Option<String>,
+        #[arg(from_global, id = "data-directory")]
+        data_directory: Option<String>,
+    },
+    /// Issue a client certificate for an agent from the local agent CA,
+    /// creating the CA on first use, and map it to the agent's key
+    IssueCertificate {
+        name: String,
+        /// Directory to write <name>.pem and <name>-key.pem to
+        #[arg(long, default_value = ".")]
+        output: String,
+        /// Days the certificate is valid
+        #[arg(long, default_value_t = 365)]
+        days: u32,
+        #[arg(from_global, id = "config-directory")]
+        config_directory: Option<String>,
+        #[arg(from_global, id = "data-directory")]
+        data_directory: Option<String>,
+    },
     /// Remove an agent key
     Rm {
         name: String,
@@ -52,14 +114,42 @@ pub(crate) async fn main(args: &clap::ArgMatches) -> Result<()> {
     match args {
         AgentsCommands::Add {
             name,
+            expires,
             config_directory,
             data_directory,
-        } => add(name, config_directory, data_directory).await,
+        } => add(name, expires, config_directory, data_directory).await,
         AgentsCommands::List {
             keys,
             config_directory,
             data_directory,
         } => list(keys, config_directory, data_directory).await,
+        AgentsCommands::Rotate {
+            name,
+            grace,
+            expires,
+            config_directory,
+            data_directory,
+        } => rotate(name, grace, expires, config_directory, data_directory).await,
+        AgentsCommands::Expire {
+            name,
+            expires,
+            config_directory,
+            data_directory,
+        } => expire(name, expires, config_directory, data_directory).await,
+        AgentsCommands::Certificate {
+            name,
+            fingerprint,
+            subject,
+            config_directory,
+            data_directory,
+        } => certificate(name, fingerprint, subject, config_directory, data_directory).await,
+        AgentsCommands::IssueCertificate {
+            name,
+            output,
+            days,
+            config_directory,
+            data_directory,
+        } => issue_certificate(name, output, days, config_directory, data_directory).await,
         AgentsCommands::Rm {
             name,
             config_directory,
@@ -70,13 +160,17 @@ pub(crate) async fn main(args: &clap::ArgMatches) -> Result<()> {
 
 async fn add(
     name: String,
+    expires: Option<Duration>,
     config_directory: Option<String>,
     data_directory: Option<String>,
 ) -> Result<()> {
     let repo = open_config_repo(config_directory.as_deref(), data_directory.as_deref()).await?;
-    let added = repo.add_agent_key(&name).await?;
+    let added = repo.add_agent_key(&name, expires).await?;
     println!("Agent key added: name={:?}", added.name);
     println!("Key: {}", added.key);
+    if let Some(expires_at) = added.expires_at {
+        println!("Expires: {}", expires_at.to_rfc3339());
+    }
     println!("Set this as server.key in the agent's agent.yaml (or EVEBOX_SERVER_KEY).");
     Ok(())
 }
@@ -92,13 +186,136 @@ async fn list(
         if !keys {
             // Keys are re-showable on request (--keys) but kept out of the
             // default listing.
-            value.as_object_mut().unwrap().remove("key");
+            let value = value.as_object_mut().unwrap();
+            value.remove("key");
+            value.remove("previous_key");
         }
         println!("{}", serde_json::to_string(&value)?);
     }
     Ok(())
 }
 
+async fn rotate(
+    name: String,
+    grace: Option<Duration>,
+    expires: Option<Duration>,
+    config_directory: Option<String>,
+    data_directory: Option<String>,
+) -> Result<()> {
+    let repo = open_config_repo(config_directory.as_deref(), data_directory.as_deref()).await?;
+    let Some(row) = repo.get_agent_key_by_name(&name).await? else {
+        return Err(anyhow!("no agent key named {name:?}"));
+    };
+    let rotated = repo
+        .rotate_agent_key(row.id, grace.unwrap_or_default(), expires)
+        .await?
+        .ok_or_else(|| anyhow!("no agent key named {name:?}"))?;
+    println!("Agent key rotated: name={:?}", rotated.name);
+    println!("Key: {}", rotated.key);
+    if let Some(expires_at) = rotated.expires_at {
+        println!("Expires: {}", expires_at.to_rfc3339());
+    }
+    match rotated.previous_key_expires_at {
+        Some(until) => println!(
+            "The previous key is accepted until {}; update server.key in the agent's \
+             agent.yaml (or EVEBOX_SERVER_KEY) before then.",
+            until.to_rfc3339()
+        ),
+        None => println!(
+            "The previous key no longer works; update server.key in the agent's agent.yaml \
+             (or EVEBOX_SERVER_KEY). A connected agent is disconnected within a minute."
+        ),
+    }
+    Ok(())
+}
+
+async fn expire(
+    name: String,
+    expires: String,
+    config_directory: Option<String>,
+    data_directory: Option<String>,
+) -> Result<()> {
+    let expires = match expires.as_str() {
+        "never" => None,
+        duration => Some(humantime::parse_duration(duration)?),
+    };
+    let repo = open_config_repo(config_directory.as_deref(), data_directory.as_deref()).await?;
+    let Some(row) = repo.get_agent_key_by_name(&name).await? else {
+        return Err(anyhow!("no agent key named {name:?}"));
+    };
+    repo.set_agent_key_expiry(row.id, expires).await?;
+    match repo
+        .get_agent_key_by_id(row.id)
+        .await?
+        .and_then(|row| row.expires_at)
+    {
+        Some(expires_at) => println!("Agent key {name:?} expires {}", expires_at.to_rfc3339()),
+        None => println!("Agent key {name:?} no longer expires"),
+    }
+    Ok(())
+}
+
+async fn certificate(
+    name: String,
+    fingerprint: Option<String>,
+    subject: Option<String>,
+    config_directory: Option<String>,
+    data_directory: Option<String>,
+) -> Result<()> {
+    let repo = open_config_repo(config_directory.as_deref(), data_directory.as_deref()).await?;
+    let Some(row) = repo.get_agent_key_by_name(&name).await? else {
+        return Err(anyhow!("no agent key named {name:?}"));
+    };
+    repo.set_agent_key_certificate(row.id, fingerprint.as_deref(), subject.as_deref())
+        .await?;
+    if fingerprint.is_none() && subject.is_none() {
+        println!("Client certificate mapping removed for agent {name:?}");
+    } else {
+        println!("Client certificate mapped to agent {name:?}");
+    }
+    Ok(())
+}
+
+async fn issue_certificate(
+    name: String,
+    output: String,
+    days: u32,
+    config_directory: Option<String>,
+    data_directory: Option<String>,
+) -> Result<()> {
+    let directory = config_repo_directory(config_directory.as_deref(), data_directory.as_deref())?;
+    let repo = open_config_repo(config_directory.as_deref(), data_directory.as_deref()).await?;
+    let Some(row) = repo.get_agent_key_by_name(&name).await? else {
+        return Err(anyhow!(
+            "no agent key named {name:?}: add one with `evebox config agents add {name:?}`"
+        ));
+    };
+    let ca = crate::cert::get_or_create_agent_ca(&directory)?;
+    let issued = ca.issue(&name, days)?;
+    let output = std::path::PathBuf::from(output);
+    let cert_path = output.join(format!("{name}.pem"));
+    let key_path = output.join(format!("{name}-key.pem"));
+    std::fs::write(&cert_path, issued.cert_pem.as_bytes())?;
+    crate::cert::write_private(&key_path, issued.key_pem.as_bytes())?;
+    repo.set_agent_key_certificate(
+        row.id,
+        Some(&issued.fingerprint),
+        row.cert_subject.as_deref(),
+    )
+    .await?;
+    println!("Client certificate issued for agent {name:?}, valid for {days} days");
+    println!("Certificate: {}", cert_path.display());
+    println!("Key: {}", key_path.display());
+    println!("Fingerprint: {}", issued.fingerprint);
+    println!(
+        "Copy both to the agent and set server.certificate and server.certificate-key in its \
+         agent.yaml. The server verifies agent certificates with agents.client-certificates.ca \
+         set to {}.",
+        ca.cert_path.display()
+    );
+    Ok(())
+}
+
 async fn remove(
     name: String,
     config_directory: Option<String>,
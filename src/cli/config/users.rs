@@ -3,12 +3,14 @@
 
 use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::FromArgMatches;
 use clap::Parser;
 use clap::Subcommand;
 use tracing::info;
 
+use crate::server::password::PasswordPolicy;
+use crate::server::session::Role;
 use crate::sqlite::configdb;
 use crate::sqlite::configdb::ConfigDb;
 
@@ -21,7 +23,7 @@ pub(crate) struct UsersCommand {
 
 #[derive(Debug, Subcommand)]
 enum UsersCommands {
-    /// Add a new user
+    /// Add a new user, with a password checked against the password policy
     Add(AddArgs),
     /// Remove an existing user
     Rm {
@@ -39,8 +41,35 @@ enum UsersCommands {
         #[arg(from_global, id = "data-directory")]
         data_directory: Option<String>,
     },
-    /// Change password
+    /// Change password, checked against the password policy
     Passwd {
+        username: String,
+        /// Make the user change the password at their next login
+        #[arg(long)]
+        require_change: bool,
+        /// Server configuration file with the password policy; the
+        /// default policy without one
+        #[arg(long, short)]
+        config: Option<String>,
+        #[arg(from_global, id = "config-directory")]
+        config_directory: Option<String>,
+        #[arg(from_global, id = "data-directory")]
+        data_directory: Option<String>,
+    },
+    /// Manage a user's API tokens
+    #[command(subcommand)]
+    Token(TokenCommands),
+    /// Set a user's role: viewer, analyst, pcap or admin
+    Role {
+        username: String,
+        role: Role,
+        #[arg(from_global, id = "config-directory")]
+        config_directory: Option<String>,
+        #[arg(from_global, id = "data-directory")]
+        data_directory: Option<String>,
+    },
+    /// Remove a user's TOTP second factor and recovery codes
+    TotpReset {
         username: String,
         #[arg(from_global, id = "config-directory")]
         config_directory: Option<String>,
@@ -49,12 +78,66 @@ enum UsersCommands {
     },
 }
 
+#[derive(Debug, Subcommand)]
+enum TokenCommands {
+    /// Create an API token; it is only shown once
+    Add(AddTokenArgs),
+    /// List a user's API tokens
+    #[command(alias = "ls")]
+    List {
+        username: String,
+        #[arg(from_global, id = "config-directory")]
+        config_directory: Option<String>,
+        #[arg(from_global, id = "data-directory")]
+        data_directory: Option<String>,
+    },
+    /// Revoke an API token
+    Rm {
+        username: String,
+        /// Token ID, from the list command
+        id: i64,
+        #[arg(from_global, id = "config-directory")]
+        config_directory: Option<String>,
+        #[arg(from_global, id = "data-directory")]
+        data_directory: Option<String>,
+    },
+}
+
+#[derive(Parser, Debug)]
+struct AddTokenArgs {
+    username: String,
+    /// A name for the token, such as the script using it
+    #[arg(long, short)]
+    name: String,
+    /// The most the token may do; the user's role by default
+    #[arg(long, short)]
+    scope: Option<Role>,
+    /// Expire the token after this long, such as 90d
+    #[arg(long, value_parser = humantime::parse_duration)]
+    expires: Option<std::time::Duration>,
+
+    #[arg(from_global, id = "config-directory")]
+    config_directory: Option<String>,
+    #[arg(from_global, id = "data-directory")]
+    data_directory: Option<String>,
+}
+
 #[derive(Parser, Debug)]
 struct AddArgs {
     #[arg(long, short)]
     username: Option<String>,
     #[arg(long, short)]
     password: Option<String>,
+    /// Role: viewer, analyst, pcap or admin
+    #[arg(long, short, default_value = "admin")]
+    role: Role,
+    /// Make the user change the password at their first login
+    #[arg(long)]
+    require_change: bool,
+    /// Server configuration file with the password policy; the default
+    /// policy without one
+    #[arg(long, short)]
+    config: Option<String>,
 
     #[arg(from_global, id = "config-directory")]
     config_directory: Option<String>,
@@ -77,16 +160,40 @@ pub(crate) async fn main(args: &clap::ArgMatches) -> Result<()> {
         } => remove(username, config_directory, data_directory).await,
         UsersCommands::Passwd {
             username,
+            require_change,
+            config,
             config_directory,
             data_directory,
-        } => password(username, config_directory, data_directory).await,
+        } => {
+            password(
+                username,
+                require_change,
+                config,
+                config_directory,
+                data_directory,
+            )
+            .await
+        }
+        UsersCommands::Token(command) => token(command).await,
+        UsersCommands::Role {
+            username,
+            role,
+            config_directory,
+            data_directory,
+        } => set_role(username, role, config_directory, data_directory).await,
+        UsersCommands::TotpReset {
+            username,
+            config_directory,
+            data_directory,
+        } => totp_reset(username, config_directory, data_directory).await,
     }
 }
 
-pub(super) async fn open_config_repo<P: AsRef<Path>>(
+/// The directory holding config.sqlite.
+pub(super) fn config_repo_directory<P: AsRef<Path>>(
     config_directory: Option<P>,
     data_directory: Option<P>,
-) -> Result<ConfigDb> {
+) -> Result<PathBuf> {
     // Prefer config_directory over data_directory
     let directory = config_directory
         .map(|p| PathBuf::from(p.as_ref()))
@@ -98,12 +205,29 @@ pub(super) async fn open_config_repo<P: AsRef<Path>>(
             return Err(anyhow!("--config-directory or --data-directory required"));
         }
     };
+    Ok(directory)
+}
+
+pub(super) async fn open_config_repo<P: AsRef<Path>>(
+    config_directory: Option<P>,
+    data_directory: Option<P>,
+) -> Result<ConfigDb> {
+    let directory = config_repo_directory(config_directory, data_directory)?;
     info!("Using directory {}", directory.display());
     let filename = directory.join("config.sqlite");
     let config_repo = configdb::open(Some(&filename)).await?;
     Ok(config_repo)
 }
 
+/// The password policy of a server configuration file, as the server
+/// applies it to passwords set through the API.
+fn password_policy(config: Option<&str>) -> Result<PasswordPolicy> {
+    let args = clap::Command::new("users").get_matches_from(["users"]);
+    let config = crate::config::Config::new(args, config)
+        .with_context(|| format!("failed to load configuration {config:?}"))?;
+    PasswordPolicy::configure(&config)
+}
+
 async fn list(config_directory: Option<String>, data_directory: Option<String>) -> Result<()> {
     let repo = open_config_repo(config_directory.as_deref(), data_directory.as_deref()).await?;
     let users = repo.get_users().await?;
@@ -140,8 +264,14 @@ async fn add(args: AddArgs) -> Result<()> {
             .prompt()?
     };
 
-    repo.add_user(&username, &password).await?;
-    println!("User added: username=\"{username}\"");
+    if let Some(error) = password_policy(args.config.as_deref())?.check(&username, &password) {
+        return Err(anyhow!(error));
+    }
+    repo.add_user(&username, &password, args.role).await?;
+    if args.require_change {
+        repo.set_password_change_required(&username, true).await?;
+    }
+    println!("User added: username=\"{username}\" role={}", args.role);
 
     Ok(())
 }
@@ -161,9 +291,12 @@ async fn remove(
 
 async fn password(
     username: String,
+    require_change: bool,
+    config: Option<String>,
     config_directory: Option<String>,
     data_directory: Option<String>,
 ) -> Result<()> {
+    let policy = password_policy(config.as_deref())?;
     let repo = open_config_repo(config_directory.as_deref(), data_directory.as_deref()).await?;
     let user = repo.get_user_by_name(&username).await?;
     let password = inquire::Password::new("Password:")
@@ -171,10 +304,105 @@ async fn password(
         .with_display_mode(inquire::PasswordDisplayMode::Masked)
         .with_validator(inquire::required!())
         .prompt()?;
+    if let Some(error) = policy.check(&username, &password) {
+        return Err(anyhow!(error));
+    }
     if repo.update_password_by_id(&user.uuid, &password).await? {
+        if require_change {
+            repo.set_password_change_required(&username, true).await?;
+        }
         println!("Password has been updated.");
         Ok(())
     } else {
         Err(anyhow!("Failed to update password, user does not exist"))
     }
 }
+
+async fn set_role(
+    username: String,
+    role: Role,
+    config_directory: Option<String>,
+    data_directory: Option<String>,
+) -> Result<()> {
+    let repo = open_config_repo(config_directory.as_deref(), data_directory.as_deref()).await?;
+    if !repo.set_user_role(&username, role).await? {
+        return Err(anyhow!("user does not exist"));
+    }
+    println!("Role updated: username=\"{username}\" role={role}");
+    Ok(())
+}
+
+async fn totp_reset(
+    username: String,
+    config_directory: Option<String>,
+    data_directory: Option<String>,
+) -> Result<()> {
+    let repo = open_config_repo(config_directory.as_deref(), data_directory.as_deref()).await?;
+    repo.get_user_by_name(&username)
+        .await
+        .map_err(|_| anyhow!("user does not exist"))?;
+    if repo.remove_totp(&username).await? {
+        println!("Second factor removed: username=\"{username}\"");
+    } else {
+        println!("User has no second factor: username=\"{username}\"");
+    }
+    Ok(())
+}
+
+async fn token(command: TokenCommands) -> Result<()> {
+    match command {
+        TokenCommands::Add(args) => {
+            let repo = open_config_repo(
+                args.config_directory.as_deref(),
+                args.data_directory.as_deref(),
+            )
+            .await?;
+            let user = repo.get_user_by_name(&args.username).await?;
+            let scope = args.scope.unwrap_or(user.role);
+            if scope > user.role {
+                return Err(anyhow!(
+                    "scope {scope} exceeds the user's role {}",
+                    user.role
+                ));
+            }
+            let (row, token) = repo
+                .add_api_token(&user.username, &args.name, scope, args.expires)
+                .await?;
+            info!(
+                "api-token: user={:?} token={:?} scope={} expires={:?} outcome=created",
+                row.username, row.name, row.scope, row.expires_at
+            );
+            println!(
+                "Token created: id={} name=\"{}\" scope={}",
+                row.id, row.name, row.scope
+            );
+            println!("{token}");
+        }
+        TokenCommands::List {
+            username,
+            config_directory,
+            data_directory,
+        } => {
+            let repo =
+                open_config_repo(config_directory.as_deref(), data_directory.as_deref()).await?;
+            for row in repo.list_api_tokens(&username).await? {
+                println!("{}", serde_json::to_string(&row)?);
+            }
+        }
+        TokenCommands::Rm {
+            username,
+            id,
+            config_directory,
+            data_directory,
+        } => {
+            let repo =
+                open_config_repo(config_directory.as_deref(), data_directory.as_deref()).await?;
+            if !repo.remove_api_token(&username, id).await? {
+                return Err(anyhow!("no API token with id {id} for user {username:?}"));
+            }
+            info!("api-token: user={:?} id={} outcome=revoked", username, id);
+            println!("Token revoked: id={id}");
+        }
+    }
+    Ok(())
+}
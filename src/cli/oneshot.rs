@@ -116,8 +116,20 @@ struct Args {
     inputs: Vec<PathBuf>,
 }
 
+impl Args {
+    fn suricata_options(&self) -> SuricataOptions {
+        SuricataOptions {
+            backend: self.suricata_backend,
+            container_runtime: self.container_runtime,
+            image: self.suricata_image.clone(),
+            suricata: self.suricata.clone(),
+            suricata_update: self.suricata_update.clone(),
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
-enum SuricataBackend {
+pub(crate) enum SuricataBackend {
     #[default]
     Auto,
     Local,
@@ -148,6 +160,49 @@ struct PreparedInput {
     workspace: Option<TempDir>,
 }
 
+/// How to find Suricata for PCAP processing, shared by `oneshot --pcap`
+/// and the server's PCAP analysis.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SuricataOptions {
+    pub(crate) backend: Option<SuricataBackend>,
+    pub(crate) container_runtime: Option<container::ContainerRuntimeChoice>,
+    pub(crate) image: Option<String>,
+    pub(crate) suricata: Option<PathBuf>,
+    pub(crate) suricata_update: Option<PathBuf>,
+}
+
+impl SuricataOptions {
+    /// Read the backend options under `prefix` (`backend`,
+    /// `container-runtime`, `image`, `suricata`, `suricata-update`).
+    pub(crate) fn from_config(config: &crate::config::Config, prefix: &str) -> Result<Self> {
+        let get = |name: &str| -> Result<Option<String>> {
+            Ok(config
+                .get::<String>(&format!("{prefix}.{name}"))?
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty()))
+        };
+        let backend = get("backend")?
+            .map(|value| {
+                SuricataBackend::from_str(&value, true)
+                    .map_err(|_| anyhow!("bad {prefix}.backend: {value}"))
+            })
+            .transpose()?;
+        let container_runtime = get("container-runtime")?
+            .map(|value| {
+                container::ContainerRuntimeChoice::from_str(&value, true)
+                    .map_err(|_| anyhow!("bad {prefix}.container-runtime: {value}"))
+            })
+            .transpose()?;
+        Ok(Self {
+            backend,
+            container_runtime,
+            image: get("image")?,
+            suricata: get("suricata")?.map(PathBuf::from),
+            suricata_update: get("suricata-update")?.map(PathBuf::from),
+        })
+    }
+}
+
 enum PreparedPcapBackend {
     Container {
         runtime: container::ContainerRuntime,
@@ -162,18 +217,75 @@ impl PreparedPcapBackend {
     }
 }
 
-enum PcapEveGenerator {
+pub(crate) enum PcapEveGenerator {
     Container(container::EveGenerator),
     Local(Box<local::EveGenerator>),
 }
 
 impl PcapEveGenerator {
+    /// Resolve the backend and update its rules. `workspace` holds the
+    /// local backend's configuration and must outlive the generator.
+    pub(crate) async fn prepare(options: &SuricataOptions, workspace: &Path) -> Result<Self> {
+        let backend = prepare_pcap_backend(options, std::env::consts::OS).await?;
+        Self::prepare_backend(backend, workspace).await
+    }
+
+    async fn prepare_backend(backend: PreparedPcapBackend, workspace: &Path) -> Result<Self> {
+        if backend.is_container() && workspace.to_string_lossy().contains(',') {
+            anyhow::bail!(
+                "temporary directory {} contains a comma, which container mount \
+                 options cannot express; set TMPDIR to a path without commas",
+                workspace.display()
+            );
+        }
+        Ok(match backend {
+            PreparedPcapBackend::Container { runtime, image } => {
+                let data_dir = suricata_data_dir(runtime.program())?;
+                crate::path::ensure_exists(&data_dir).with_context(|| {
+                    format!("failed to create rules cache {}", data_dir.display())
+                })?;
+                PcapEveGenerator::Container(
+                    container::EveGenerator::prepare(runtime, &image, &data_dir).await?,
+                )
+            }
+            PreparedPcapBackend::Local(programs) => PcapEveGenerator::Local(Box::new(
+                local::EveGenerator::prepare(programs, workspace).await?,
+            )),
+        })
+    }
+
+    fn is_container(&self) -> bool {
+        matches!(self, Self::Container(_))
+    }
+
     async fn generate(&self, pcap: &Path, output: &Path) -> Result<()> {
         match self {
             Self::Container(generator) => generator.generate(pcap, output).await,
             Self::Local(generator) => generator.generate(pcap, output).await,
         }
     }
+
+    /// Run Suricata over `pcap`, writing `eve.json` into the existing
+    /// directory `workspace`. The container backend reads a staged copy
+    /// so the original capture stays out of the container mounts.
+    pub(crate) async fn generate_eve(&self, pcap: &Path, workspace: &Path) -> Result<PathBuf> {
+        let eve_path = workspace.join("eve.json");
+        if self.is_container() {
+            let staged_pcap = stage_pcap(pcap, workspace)?;
+            let result = self.generate(&staged_pcap, &eve_path).await;
+            if let Err(err) = std::fs::remove_file(&staged_pcap) {
+                warn!(
+                    "Failed to remove staged PCAP {}: {}",
+                    staged_pcap.display(),
+                    err
+                );
+            }
+            result?;
+        } else {
+            self.generate(pcap, &eve_path).await?;
+        }
+        Ok(eve_path)
+    }
 }
 
 impl PreparedInput {
@@ -385,11 +497,10 @@ async fn prepare_input(args: &Args) -> Result<PreparedInput> {
         .collect::<Result<Vec<_>>>()?;
     // Resolve and validate all user-managed prerequisites before staging a
     // potentially large PCAP.
-    let backend = prepare_pcap_backend(args, std::env::consts::OS).await?;
+    let backend = prepare_pcap_backend(&args.suricata_options(), std::env::consts::OS).await?;
     // Only the container backend stages a copy, so only it carries the
     // size limit.
-    let stage_inputs = backend.is_container();
-    if stage_inputs {
+    if backend.is_container() {
         for pcap in &pcaps {
             check_pcap_size(std::fs::metadata(pcap)?.len(), args.force)?;
         }
@@ -399,26 +510,7 @@ async fn prepare_input(args: &Args) -> Result<PreparedInput> {
         .prefix("evebox-oneshot-pcap-")
         .tempdir()
         .context("failed to create private PCAP processing workspace")?;
-    if backend.is_container() && workspace.path().to_string_lossy().contains(',') {
-        anyhow::bail!(
-            "temporary directory {} contains a comma, which container mount \
-             options cannot express; set TMPDIR to a path without commas",
-            workspace.path().display()
-        );
-    }
-    let generator = match backend {
-        PreparedPcapBackend::Container { runtime, image } => {
-            let data_dir = suricata_data_dir(runtime.program())?;
-            crate::path::ensure_exists(&data_dir)
-                .with_context(|| format!("failed to create rules cache {}", data_dir.display()))?;
-            PcapEveGenerator::Container(
-                container::EveGenerator::prepare(runtime, &image, &data_dir).await?,
-            )
-        }
-        PreparedPcapBackend::Local(programs) => PcapEveGenerator::Local(Box::new(
-            local::EveGenerator::prepare(programs, workspace.path()).await?,
-        )),
-    };
+    let generator = PcapEveGenerator::prepare_backend(backend, workspace.path()).await?;
     let mut eve_inputs = Vec::with_capacity(pcaps.len());
     for (index, pcap) in pcaps.iter().enumerate() {
         info!(
@@ -434,22 +526,7 @@ async fn prepare_input(args: &Args) -> Result<PreparedInput> {
                 input_workspace.display()
             )
         })?;
-        let eve_path = input_workspace.join("eve.json");
-        if stage_inputs {
-            // A staged copy keeps the user's original capture out of the
-            // container mounts.
-            let staged_pcap = stage_pcap(pcap, &input_workspace)?;
-            generator.generate(&staged_pcap, &eve_path).await?;
-            if let Err(err) = std::fs::remove_file(&staged_pcap) {
-                warn!(
-                    "Failed to remove staged PCAP {}: {}",
-                    staged_pcap.display(),
-                    err
-                );
-            }
-        } else {
-            generator.generate(pcap, &eve_path).await?;
-        }
+        let eve_path = generator.generate_eve(pcap, &input_workspace).await?;
         eve_inputs.push(EveInput::File(eve_path));
     }
     Ok(PreparedInput {
@@ -460,17 +537,20 @@ async fn prepare_input(args: &Args) -> Result<PreparedInput> {
     })
 }
 
-async fn prepare_pcap_backend(args: &Args, platform: &str) -> Result<PreparedPcapBackend> {
-    match selected_backend(args, platform)? {
-        SuricataBackend::Local => prepare_local_backend(args).await,
-        SuricataBackend::Container if allows_local_fallback(args) => {
-            match container::resolve_runtime(args.container_runtime.unwrap_or_default()).await {
-                Ok(runtime) => prepare_container_backend_with_runtime(args, runtime).await,
+async fn prepare_pcap_backend(
+    options: &SuricataOptions,
+    platform: &str,
+) -> Result<PreparedPcapBackend> {
+    match selected_backend(options, platform)? {
+        SuricataBackend::Local => prepare_local_backend(options).await,
+        SuricataBackend::Container if allows_local_fallback(options) => {
+            match container::resolve_runtime(options.container_runtime.unwrap_or_default()).await {
+                Ok(runtime) => prepare_container_backend_with_runtime(options, runtime).await,
                 Err(runtime_error) => {
                     warn!(
                         "No usable container runtime was found: {runtime_error:#}; trying local Suricata"
                     );
-                    prepare_local_backend(args).await.with_context(|| {
+                    prepare_local_backend(options).await.with_context(|| {
                         format!(
                             "no usable container runtime was found ({runtime_error:#}) and \
                              the local Suricata backend could not be prepared"
@@ -479,21 +559,21 @@ async fn prepare_pcap_backend(args: &Args, platform: &str) -> Result<PreparedPca
                 }
             }
         }
-        SuricataBackend::Container => prepare_container_backend(args, platform).await,
+        SuricataBackend::Container => prepare_container_backend(options, platform).await,
         SuricataBackend::Auto => unreachable!("auto backend must be resolved before preparation"),
     }
 }
 
-fn allows_local_fallback(args: &Args) -> bool {
-    args.suricata_backend.unwrap_or_default() == SuricataBackend::Auto
-        && args.container_runtime.is_none()
-        && args.suricata_image.is_none()
+fn allows_local_fallback(options: &SuricataOptions) -> bool {
+    options.backend.unwrap_or_default() == SuricataBackend::Auto
+        && options.container_runtime.is_none()
+        && options.image.is_none()
 }
 
-fn selected_backend(args: &Args, platform: &str) -> Result<SuricataBackend> {
-    let requested = args.suricata_backend.unwrap_or_default();
-    let has_local_options = args.suricata.is_some() || args.suricata_update.is_some();
-    let has_container_options = args.container_runtime.is_some() || args.suricata_image.is_some();
+fn selected_backend(options: &SuricataOptions, platform: &str) -> Result<SuricataBackend> {
+    let requested = options.backend.unwrap_or_default();
+    let has_local_options = options.suricata.is_some() || options.suricata_update.is_some();
+    let has_container_options = options.container_runtime.is_some() || options.image.is_some();
 
     if requested == SuricataBackend::Local && has_container_options {
         anyhow::bail!(
@@ -515,27 +595,32 @@ fn selected_backend(args: &Args, platform: &str) -> Result<SuricataBackend> {
     })
 }
 
-async fn prepare_local_backend(args: &Args) -> Result<PreparedPcapBackend> {
-    let programs =
-        local::Programs::discover(args.suricata.as_deref(), args.suricata_update.as_deref())
-            .await?;
+async fn prepare_local_backend(options: &SuricataOptions) -> Result<PreparedPcapBackend> {
+    let programs = local::Programs::discover(
+        options.suricata.as_deref(),
+        options.suricata_update.as_deref(),
+    )
+    .await?;
     Ok(PreparedPcapBackend::Local(programs))
 }
 
-async fn prepare_container_backend(args: &Args, platform: &str) -> Result<PreparedPcapBackend> {
+async fn prepare_container_backend(
+    options: &SuricataOptions,
+    platform: &str,
+) -> Result<PreparedPcapBackend> {
     if platform != "linux" {
         anyhow::bail!("the container Suricata backend is currently supported on Linux only");
     }
-    let runtime = container::resolve_runtime(args.container_runtime.unwrap_or_default()).await?;
-    prepare_container_backend_with_runtime(args, runtime).await
+    let runtime = container::resolve_runtime(options.container_runtime.unwrap_or_default()).await?;
+    prepare_container_backend_with_runtime(options, runtime).await
 }
 
 async fn prepare_container_backend_with_runtime(
-    args: &Args,
+    options: &SuricataOptions,
     runtime: container::ContainerRuntime,
 ) -> Result<PreparedPcapBackend> {
-    let image = args
-        .suricata_image
+    let image = options
+        .image
         .as_deref()
         .unwrap_or(container::DEFAULT_SURICATA_IMAGE);
     container::prepare_runtime(runtime, image).await?;
@@ -623,7 +708,7 @@ fn check_pcap_size(size: u64, force: bool) -> Result<()> {
     Ok(())
 }
 
-fn validate_pcap(path: &Path) -> Result<PathBuf> {
+pub(crate) fn validate_pcap(path: &Path) -> Result<PathBuf> {
     let metadata = std::fs::metadata(path)
         .with_context(|| format!("failed to access PCAP input {}", path.display()))?;
     if !metadata.is_file() {
@@ -20,7 +20,7 @@ pub(super) const DEFAULT_SURICATA_IMAGE: &str = "docker.io/jasonish/suricata:8.0
 const MINIMUM_SURICATA_VERSION: &str = "8.0.6";
 
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
-pub(super) enum ContainerRuntimeChoice {
+pub(crate) enum ContainerRuntimeChoice {
     #[default]
     Auto,
     Podman,
@@ -72,7 +72,7 @@ pub(super) async fn prepare_runtime(runtime: ContainerRuntime, image: &str) -> R
     Ok(())
 }
 
-pub(super) struct EveGenerator {
+pub(crate) struct EveGenerator {
     runtime: ContainerRuntime,
     image: String,
     data_dir: PathBuf,
@@ -440,7 +440,7 @@ fn utf8_path(path: &Path) -> Result<&str> {
     })
 }
 
-pub(super) struct EveGenerator {
+pub(crate) struct EveGenerator {
     programs: Programs,
     layout: WorkspaceLayout,
 }
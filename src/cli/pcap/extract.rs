@@ -2,11 +2,11 @@
 // SPDX-License-Identifier: MIT
 
 use crate::pcap::{
-    FetchError, FetchStats, Limits, PcapFilter, PcapRequest, PcapSource, SpoolConfig,
+    FetchError, FetchStats, Limits, OutputFormat, PcapFilter, PcapRequest, PcapSource, SpoolConfig,
 };
 use crate::prelude::*;
 use chrono::TimeZone;
-use clap::Parser as ClapParser;
+use clap::{Parser as ClapParser, ValueEnum};
 use same_file::Handle;
 use std::io::IsTerminal;
 use std::io::Write;
@@ -50,9 +50,39 @@ pub(super) struct ExtractArgs {
     /// Defaults to stdout. Output to a terminal is refused.
     #[clap(long)]
     output: Option<String>,
+
+    /// Output file format
+    ///
+    /// pcapng keeps packets of every link type in one file, where pcap
+    /// skips files whose link type differs from the first packet.
+    #[clap(long, value_enum, default_value_t)]
+    format: Format,
+
+    /// pcapng section comment
+    ///
+    /// Defaults to naming the directory the packets were extracted from.
+    #[clap(long)]
+    comment: Option<String>,
+
+    /// Use sidecar indexes
+    ///
+    /// Seek to the time window in indexed files, building indexes for
+    /// sealed files that have none (see `evebox pcap index`).
+    #[clap(long)]
+    index: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+enum Format {
+    #[default]
+    Pcap,
+    Pcapng,
 }
 
 pub(super) fn main(args: ExtractArgs) -> anyhow::Result<()> {
+    if args.comment.is_some() && args.format != Format::Pcapng {
+        bail!("--comment requires --format pcapng");
+    }
     validate_output(&args, std::io::stdout().is_terminal())?;
 
     let end = match args.duration {
@@ -63,6 +93,7 @@ pub(super) fn main(args: ExtractArgs) -> anyhow::Result<()> {
     let spool = SpoolConfig {
         // A zero margin preserves this command's historical file pruning.
         margin: Duration::ZERO,
+        index: args.index,
         ..SpoolConfig::new(&args.directory, args.prefix.clone())
     };
     let request = PcapRequest {
@@ -70,6 +101,8 @@ pub(super) fn main(args: ExtractArgs) -> anyhow::Result<()> {
         start: args.start_time,
         end,
         limits: Limits::default(),
+        format: output_format(&args),
+        progress: None,
     };
 
     let mut out = LazyOutput::new(output_filename(&args));
@@ -86,9 +119,36 @@ pub(super) fn main(args: ExtractArgs) -> anyhow::Result<()> {
     }
 }
 
+fn output_format(args: &ExtractArgs) -> OutputFormat {
+    match args.format {
+        Format::Pcap => OutputFormat::Pcap,
+        Format::Pcapng => OutputFormat::Pcapng {
+            section_comment: Some(
+                args.comment
+                    .clone()
+                    .unwrap_or_else(|| format!("EveBox extract from {}", args.directory)),
+            ),
+            packet_comment: None,
+        },
+    }
+}
+
 /// Write an empty, header-only capture to the output, using the link type
-/// of the first spool file that was opened, if any.
+/// of the first spool file that was opened, if any. An empty pcapng capture
+/// is just its section header.
 fn write_empty_output(args: &ExtractArgs, stats: FetchStats) -> Result<()> {
+    if let OutputFormat::Pcapng {
+        section_comment, ..
+    } = output_format(args)
+    {
+        let mut out = LazyOutput::new(output_filename(args));
+        out.write_all(&crate::util::pcap::create_pcapng_section_header(
+            section_comment.as_deref(),
+        ))?;
+        out.flush()?;
+        info!("No matching packets found, wrote an empty output capture");
+        return Ok(());
+    }
     let dead = pcap::Capture::dead(
         stats
             .linktype
@@ -240,6 +300,9 @@ mod test {
             start_time: None,
             duration: None,
             output: Some(output.to_string_lossy().into_owned()),
+            format: Format::Pcap,
+            comment: None,
+            index: false,
         }
     }
 
@@ -357,6 +420,66 @@ mod test {
         assert_eq!(count_packets(&output), 1);
     }
 
+    #[test]
+    fn test_extract_pcapng_keeps_mixed_linktypes() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let input_dir = tempdir.path().join("input");
+        std::fs::create_dir(&input_dir).unwrap();
+        let output = tempdir.path().join("extracted.pcapng");
+
+        write_pcap_file(&input_dir.join("a.pcap.1700000000"), &[(1_700_000_000, 53)]);
+        write_pcap_file_with_linktype(
+            &input_dir.join("b.pcap.1700000000"),
+            &[(1_700_000_001, 53)],
+            pcap::Linktype::NULL,
+        );
+
+        main(ExtractArgs {
+            format: Format::Pcapng,
+            comment: Some("incident 42".to_string()),
+            ..extract_args(&input_dir, &output)
+        })
+        .unwrap();
+
+        let bytes = std::fs::read(&output).unwrap();
+        assert_eq!(&bytes[0..4], &0x0A0D_0D0Au32.to_le_bytes());
+        assert!(String::from_utf8_lossy(&bytes).contains("incident 42"));
+        assert_eq!(count_packets(&output), 2);
+    }
+
+    #[test]
+    fn test_extract_pcapng_empty_output_is_a_section_header() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let input_dir = tempdir.path().join("input");
+        std::fs::create_dir(&input_dir).unwrap();
+        let output = tempdir.path().join("extracted.pcapng");
+
+        main(ExtractArgs {
+            format: Format::Pcapng,
+            ..extract_args(&input_dir, &output)
+        })
+        .unwrap();
+
+        let bytes = std::fs::read(&output).unwrap();
+        assert_eq!(&bytes[0..4], &0x0A0D_0D0Au32.to_le_bytes());
+        assert_eq!(
+            u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize,
+            bytes.len()
+        );
+    }
+
+    #[test]
+    fn test_comment_requires_pcapng() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let output = tempdir.path().join("extracted.pcap");
+        let err = main(ExtractArgs {
+            comment: Some("x".to_string()),
+            ..extract_args(tempdir.path(), &output)
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("--format pcapng"));
+    }
+
     #[test]
     fn test_extract_skips_unreadable_files() {
         let tempdir = tempfile::tempdir().unwrap();
@@ -454,6 +577,9 @@ mod test {
             start_time: Some(u64::MAX),
             duration: Some(1),
             output: Some("output.pcap".to_string()),
+            format: Format::Pcap,
+            comment: None,
+            index: false,
         };
         assert!(end_time(&args).is_err());
     }
@@ -0,0 +1,27 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+use crate::pcap::SpoolConfig;
+use crate::prelude::*;
+use clap::Parser as ClapParser;
+use std::path::PathBuf;
+
+#[derive(Debug, ClapParser)]
+pub(super) struct IndexArgs {
+    /// Directory containing PCAP spool files to index
+    directory: PathBuf,
+
+    /// Only index files with this prefix
+    #[arg(long)]
+    prefix: Option<String>,
+}
+
+/// Build missing or stale sidecar indexes for every sealed file of a
+/// spool, the same as the server and agent background indexer.
+pub(super) fn main(args: IndexArgs) -> Result<()> {
+    let mut spool = SpoolConfig::new(args.directory, args.prefix);
+    spool.index = true;
+    let built = crate::pcap::index::index_spool(&spool, &mut || true)?;
+    info!("Built {built} PCAP spool indexes");
+    Ok(())
+}
@@ -4,6 +4,7 @@
 use crate::cli::prelude::*;
 
 mod extract;
+mod index;
 mod purge;
 
 #[derive(Debug, Parser)]
@@ -24,6 +25,9 @@ enum Commands {
 
     /// Purge old PCAP files from a spool directory
     Purge(purge::PurgeArgs),
+
+    /// Build sidecar indexes for the sealed files of a spool directory
+    Index(index::IndexArgs),
 }
 
 pub fn command() -> Command {
@@ -36,5 +40,6 @@ pub async fn main(args: &ArgMatches) -> Result<()> {
     match args.command {
         Commands::Extract(args) => extract::main(args),
         Commands::Purge(args) => purge::main(args).await,
+        Commands::Index(args) => index::main(args),
     }
 }
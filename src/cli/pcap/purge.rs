@@ -3,10 +3,13 @@
 
 // Ported from "dumpy purge".
 
+use crate::pcap::retention::{
+    RetentionPolicy, collect_pcap_files, delete_files, files_to_delete, free_space, parse_size,
+};
 use crate::prelude::*;
 use clap::{ArgGroup, Parser as ClapParser};
 use std::num::NonZeroU64;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::time::{Duration, SystemTime};
 use tracing::{error, info, warn};
 
@@ -14,7 +17,7 @@ use tracing::{error, info, warn};
 #[command(group(
     ArgGroup::new("retention")
         .required(true)
-        .args(["keep_files", "max_size"])
+        .args(["keep_files", "max_size", "max_age", "min_free"])
 ))]
 pub(super) struct PurgeArgs {
     /// Directory containing PCAP files to purge
@@ -28,6 +31,15 @@ pub(super) struct PurgeArgs {
     #[arg(long, value_name = "SIZE", value_parser = parse_size)]
     max_size: Option<u64>,
 
+    /// Delete files older than this (for example, 7d or 12h)
+    #[arg(long, value_name = "AGE", value_parser = humantime::parse_duration)]
+    max_age: Option<Duration>,
+
+    /// Delete the oldest files until this much disk space is free (for
+    /// example, 50G)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    min_free: Option<u64>,
+
     /// Only process files with this prefix
     #[arg(long)]
     prefix: Option<String>,
@@ -45,13 +57,6 @@ pub(super) struct PurgeArgs {
     quiet: bool,
 }
 
-#[derive(Debug)]
-struct FileInfo {
-    path: PathBuf,
-    size: u64,
-    modified: SystemTime,
-}
-
 pub(super) async fn main(args: PurgeArgs) -> Result<()> {
     if let Some(interval_minutes) = args.interval {
         if !args.quiet {
@@ -88,13 +93,20 @@ fn run_purge_once(args: &PurgeArgs) -> Result<()> {
         return Ok(());
     }
 
-    let files_to_delete = if let Some(keep_count) = args.keep_files {
-        calculate_files_to_delete_by_count(&files, keep_count)
-    } else if let Some(max_size) = args.max_size {
-        calculate_files_to_delete_by_size(&files, max_size)
-    } else {
-        bail!("Either --keep-files or --max-size must be specified");
+    let policy = RetentionPolicy {
+        keep_files: args.keep_files,
+        max_size: args.max_size,
+        max_age: args.max_age,
+        min_free: args.min_free,
+    };
+    if policy.is_empty() {
+        bail!("One of --keep-files, --max-size, --max-age or --min-free must be specified");
+    }
+    let free = match policy.min_free {
+        Some(_) => Some(free_space(&args.directory)?),
+        None => None,
     };
+    let files_to_delete = files_to_delete(&files, &policy, free, SystemTime::now(), &|_| false);
 
     if files_to_delete.is_empty() {
         if !args.quiet {
@@ -132,154 +144,20 @@ fn run_purge_once(args: &PurgeArgs) -> Result<()> {
     delete_files(&files_to_delete, args.quiet)
 }
 
-fn delete_files(files: &[&FileInfo], quiet: bool) -> Result<()> {
-    let mut delete_count = 0;
-    let mut delete_errors = 0;
-    for file in files {
-        match std::fs::remove_file(&file.path) {
-            Ok(()) => {
-                delete_count += 1;
-                if !quiet {
-                    info!("Deleted: {}", file.path.display());
-                }
-            }
-            Err(err) => {
-                delete_errors += 1;
-                error!("Failed to delete {}: {err}", file.path.display());
-            }
-        }
-    }
-
-    if !quiet {
-        info!("Deleted {delete_count} files successfully, {delete_errors} errors");
-    }
-    if delete_errors > 0 {
-        bail!(
-            "failed to delete {delete_errors} of {} capture files",
-            files.len()
-        );
-    }
-    Ok(())
-}
-
-fn collect_pcap_files(directory: &Path, prefix: Option<&str>) -> Result<Vec<FileInfo>> {
-    if !directory.exists() {
-        bail!("Directory does not exist: {}", directory.display());
-    }
-    if !directory.is_dir() {
-        bail!("Path is not a directory: {}", directory.display());
-    }
-
-    let mut files = Vec::new();
-    for entry in std::fs::read_dir(directory)? {
-        let entry = entry?;
-        if !entry.file_type()?.is_file() {
-            continue;
-        }
-
-        let path = entry.path();
-        let Some(filename) = path.file_name().and_then(|filename| filename.to_str()) else {
-            continue;
-        };
-        if !is_pcap_filename(filename) {
-            continue;
-        }
-        if let Some(prefix) = prefix
-            && !filename.starts_with(prefix)
-        {
-            continue;
-        }
-
-        let metadata = entry.metadata()?;
-        files.push(FileInfo {
-            path,
-            size: metadata.len(),
-            modified: metadata.modified()?,
-        });
-    }
-
-    files.sort_by(|a, b| {
-        b.modified
-            .cmp(&a.modified)
-            .then_with(|| a.path.cmp(&b.path))
-    });
-    Ok(files)
-}
-
-fn is_pcap_filename(filename: &str) -> bool {
-    let lowercase = filename.to_ascii_lowercase();
-
-    [".pcap", ".cap"].iter().any(|extension| {
-        lowercase.ends_with(extension)
-            || lowercase
-                .split_once(&format!("{extension}."))
-                .is_some_and(|(_, suffix)| {
-                    !suffix.is_empty()
-                        && suffix
-                            .split('.')
-                            .all(|component| component.chars().all(|c| c.is_ascii_digit()))
-                })
-    })
-}
-
-fn calculate_files_to_delete_by_count(files: &[FileInfo], keep_count: usize) -> Vec<&FileInfo> {
-    files.get(keep_count..).unwrap_or_default().iter().collect()
-}
-
-fn calculate_files_to_delete_by_size(files: &[FileInfo], max_size_bytes: u64) -> Vec<&FileInfo> {
-    let mut total_size = 0u64;
-    let mut keep_count = 0;
-
-    for file in files {
-        let next_size = total_size.saturating_add(file.size);
-        if next_size > max_size_bytes {
-            break;
-        }
-        total_size = next_size;
-        keep_count += 1;
-    }
-
-    files[keep_count..].iter().collect()
-}
-
-fn parse_size(input: &str) -> std::result::Result<u64, String> {
-    let size = input.trim().to_ascii_uppercase();
-    if size.is_empty() {
-        return Err("size cannot be empty".to_string());
-    }
-
-    let (number, multiplier) = match size.as_bytes().last().copied() {
-        Some(b'K') => (&size[..size.len() - 1], 1024f64),
-        Some(b'M') => (&size[..size.len() - 1], 1024f64.powi(2)),
-        Some(b'G') => (&size[..size.len() - 1], 1024f64.powi(3)),
-        Some(b'T') => (&size[..size.len() - 1], 1024f64.powi(4)),
-        _ => {
-            return size
-                .parse::<u64>()
-                .map_err(|_| format!("invalid size '{input}'"));
-        }
-    };
-
-    let number = number
-        .parse::<f64>()
-        .map_err(|_| format!("invalid size '{input}'"))?;
-    let bytes = number * multiplier;
-    if !bytes.is_finite() || bytes < 0.0 || bytes >= u64::MAX as f64 {
-        return Err(format!("size '{input}' is out of range"));
-    }
-    Ok(bytes as u64)
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::pcap::retention::{FileInfo, calculate_files_to_delete_by_size, is_pcap_filename};
     use std::fs::FileTimes;
+    use std::path::Path;
 
     fn purge_args(directory: &Path) -> PurgeArgs {
         PurgeArgs {
             directory: directory.to_path_buf(),
             keep_files: Some(0),
             max_size: None,
+            max_age: None,
+            min_free: None,
             prefix: None,
             force: false,
             interval: None,
@@ -396,6 +274,26 @@ mod tests {
         assert!(newest.exists());
     }
 
+    #[test]
+    fn purge_removes_index_sidecars() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let oldest = tempdir.path().join("log.1.1.pcap");
+        let newest = tempdir.path().join("log.1.2.pcap");
+        write_file(&oldest, 10, 1);
+        write_file(&newest, 10, 2);
+        let sidecar = crate::pcap::index::sidecar_path(&oldest);
+        std::fs::write(&sidecar, b"index").unwrap();
+        std::fs::write(crate::pcap::index::sidecar_path(&newest), b"index").unwrap();
+
+        let mut args = purge_args(tempdir.path());
+        args.keep_files = Some(1);
+        args.force = true;
+        run_purge_once(&args).unwrap();
+        assert!(!oldest.exists());
+        assert!(!sidecar.exists());
+        assert!(crate::pcap::index::sidecar_path(&newest).exists());
+    }
+
     #[test]
     fn deletion_failures_are_returned_after_other_files_are_attempted() {
         let tempdir = tempfile::tempdir().unwrap();
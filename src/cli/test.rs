@@ -819,6 +819,7 @@ async fn check_elastic_alert_group_exact_fields(
             tls_sni: None,
             min_timestamp: min_timestamp.clone(),
             max_timestamp: max_timestamp.clone(),
+            sensors: None,
         })
         .await?;
     if updated != 1 {
@@ -835,6 +836,7 @@ async fn check_elastic_alert_group_exact_fields(
             tls_sni: Some(SNI.to_string()),
             min_timestamp,
             max_timestamp,
+            sensors: None,
         })
         .await?;
     if updated != 1 {
@@ -1035,6 +1037,7 @@ async fn check_sqlite_alert_group_exact_fields(repo: &SqliteEventRepo) -> Result
             tls_sni: None,
             min_timestamp: min_timestamp.clone(),
             max_timestamp: max_timestamp.clone(),
+            sensors: None,
         })
         .await?;
     if updated != 1 {
@@ -1051,6 +1054,7 @@ async fn check_sqlite_alert_group_exact_fields(repo: &SqliteEventRepo) -> Result
             tls_sni: Some(SNI.to_string()),
             min_timestamp,
             max_timestamp,
+            sensors: None,
         })
         .await?;
     if updated != 1 {
@@ -1114,6 +1118,7 @@ async fn check_sqlite_state_filter(
         tls_sni: None,
         min_timestamp: group.metadata.min_timestamp.to_rfc3339_utc(),
         max_timestamp: group.metadata.max_timestamp.to_rfc3339_utc(),
+        sensors: None,
     };
 
     // Nothing is escalated yet.
@@ -1661,6 +1666,7 @@ const COMMON_QUERY_CHECK_NAMES: &[&str] = &[
     "deescalate_event_by_id",
     "archive_event_by_id",
     "comment_event_by_id",
+    "add_history_by_id",
     "archive_by_alert_group",
 ];
 
@@ -1802,6 +1808,7 @@ const MUTATION_CHECK_NAMES: &[&str] = &[
     "deescalate_event_by_id",
     "archive_event_by_id",
     "comment_event_by_id",
+    "add_history_by_id",
     "archive_by_alert_group",
 ];
 
@@ -1813,7 +1820,7 @@ async fn run_common_read_query_checks(repo: &EventRepo, checks: &mut Vec<Check>)
     // earliest_timestamp — also reused for the stats time range.
     let earliest = {
         let start = Instant::now();
-        match repo.earliest_timestamp().await {
+        match repo.earliest_timestamp(None).await {
             Ok(ts) => {
                 let detail = ts
                     .as_ref()
@@ -1830,7 +1837,7 @@ async fn run_common_read_query_checks(repo: &EventRepo, checks: &mut Vec<Check>)
 
     check!(checks, "get_event_types", {
         let types = match repo {
-            EventRepo::Elastic(repo) => repo.get_event_types().await?,
+            EventRepo::Elastic(repo) => repo.get_event_types(None).await?,
             EventRepo::SQLite(repo) => repo.get_event_types(Vec::new()).await?,
         };
         Ok(Some(format!("types={}", types.len())))
@@ -1967,6 +1974,7 @@ async fn run_common_read_query_checks(repo: &EventRepo, checks: &mut Vec<Check>)
                     tls_sni: None,
                     min_timestamp: alert.metadata.min_timestamp.to_rfc3339_utc(),
                     max_timestamp: alert.metadata.max_timestamp.to_rfc3339_utc(),
+                    sensors: None,
                 });
                 checks.push(Check::pass(
                     "alerts",
@@ -1983,16 +1991,16 @@ async fn run_common_read_query_checks(repo: &EventRepo, checks: &mut Vec<Check>)
 
     check!(checks, "dhcp_request", {
         let rows = match repo {
-            EventRepo::Elastic(repo) => repo.dhcp_request(None, None).await?,
-            EventRepo::SQLite(repo) => repo.dhcp_request(None, None).await?,
+            EventRepo::Elastic(repo) => repo.dhcp_request(None, None, None).await?,
+            EventRepo::SQLite(repo) => repo.dhcp_request(None, None, None).await?,
         };
         Ok(Some(format!("rows={}", rows.len())))
     });
 
     check!(checks, "dhcp_ack", {
         let rows = match repo {
-            EventRepo::Elastic(repo) => repo.dhcp_ack(None, None).await?,
-            EventRepo::SQLite(repo) => repo.dhcp_ack(None, None).await?,
+            EventRepo::Elastic(repo) => repo.dhcp_ack(None, None, None).await?,
+            EventRepo::SQLite(repo) => repo.dhcp_ack(None, None, None).await?,
         };
         Ok(Some(format!("rows={}", rows.len())))
     });
@@ -2000,12 +2008,24 @@ async fn run_common_read_query_checks(repo: &EventRepo, checks: &mut Vec<Check>)
     check!(checks, "dns_reverse_lookup", {
         let value = match repo {
             EventRepo::Elastic(repo) => {
-                repo.dns_reverse_lookup(None, None, "10.0.0.1".to_string(), "10.0.0.2".to_string())
-                    .await?
+                repo.dns_reverse_lookup(
+                    None,
+                    None,
+                    None,
+                    "10.0.0.1".to_string(),
+                    "10.0.0.2".to_string(),
+                )
+                .await?
             }
             EventRepo::SQLite(repo) => {
-                repo.dns_reverse_lookup(None, None, "10.0.0.1".to_string(), "10.0.0.2".to_string())
-                    .await?
+                repo.dns_reverse_lookup(
+                    None,
+                    None,
+                    None,
+                    "10.0.0.1".to_string(),
+                    "10.0.0.2".to_string(),
+                )
+                .await?
             }
         };
         let _ = value;
@@ -2021,6 +2041,7 @@ async fn run_common_read_query_checks(repo: &EventRepo, checks: &mut Vec<Check>)
     let stats_params = StatsAggQueryParams {
         field: "stats.uptime".to_string(),
         sensor_name: None,
+        sensors: None,
         start_time: stats_start,
         end_time: stats_end,
     };
@@ -2095,6 +2116,12 @@ async fn run_common_mutation_checks(
                     .await?;
                 Ok(None)
             });
+            check!(checks, "add_history_by_id", {
+                let entry =
+                    elastic::HistoryEntryBuilder::new_pcap_pin_failed("backend-test").build();
+                repo.add_history_by_id(id, &entry).await?;
+                Ok(None)
+            });
         }
         None => {
             for name in [
@@ -2102,6 +2129,7 @@ async fn run_common_mutation_checks(
                 "deescalate_event_by_id",
                 "archive_event_by_id",
                 "comment_event_by_id",
+                "add_history_by_id",
             ] {
                 checks.push(Check::skip(name, "no sample event id"));
             }
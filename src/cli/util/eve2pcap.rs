@@ -22,11 +22,24 @@ pub(super) struct Args {
     #[arg(short, long = "payload")]
     payload: bool,
 
+    /// Write pcapng, commenting each packet with its event's flow id and
+    /// signature. Packets of different link types share one file.
+    #[arg(long = "pcapng")]
+    pcapng: bool,
+
     /// Input EVE JSON file(s) to read from
     #[arg(required = true)]
     inputs: Vec<PathBuf>,
 }
 
+/// The output file and what has been written to it so far.
+struct Output {
+    file: File,
+    header_done: bool,
+    /// Set when writing pcapng.
+    interfaces: Option<pcap::PcapngInterfaces>,
+}
+
 /// Run the eve2pcap command
 pub(super) async fn main(args: Args) -> Result<()> {
     if args.inputs.len() > 1 {
@@ -39,22 +52,21 @@ pub(super) async fn main(args: Args) -> Result<()> {
     info!("Converting {} data to PCAP", event_type);
     info!("Output file: {}", args.output.display());
 
-    let mut output =
+    let file =
         File::create(&args.output).map_err(|e| anyhow!("Failed to create output file: {}", e))?;
-    let mut header_done = false;
+    let mut output = Output {
+        file,
+        header_done: false,
+        interfaces: args.pcapng.then(pcap::PcapngInterfaces::default),
+    };
     for input_path in &args.inputs {
-        process_file(input_path, &mut output, &mut header_done, args.payload)?;
+        process_file(input_path, &mut output, args.payload)?;
     }
 
     Ok(())
 }
 
-fn process_file(
-    input_path: &PathBuf,
-    output: &mut File,
-    header_done: &mut bool,
-    payload: bool,
-) -> Result<()> {
+fn process_file(input_path: &PathBuf, output: &mut Output, payload: bool) -> Result<()> {
     info!("Processing file: {}", input_path.display());
 
     let file = match File::open(input_path) {
@@ -73,7 +85,7 @@ fn process_file(
     let reader = BufReader::new(file);
     for (lineno, result) in reader.lines().enumerate() {
         match result {
-            Ok(line) => process_line(&line, lineno, output, header_done, payload),
+            Ok(line) => process_line(&line, lineno, output, payload),
             Err(err) => {
                 warn!("Error reading line: {}", err);
             }
@@ -84,18 +96,12 @@ fn process_file(
 }
 
 /// Process a single line of EVE JSON
-fn process_line(
-    line: &str,
-    lineno: usize,
-    output: &mut File,
-    header_one: &mut bool,
-    payload: bool,
-) {
+fn process_line(line: &str, lineno: usize, output: &mut Output, payload: bool) {
     // Parse the JSON
     match serde_json::from_str::<serde_json::Value>(line) {
         Ok(event) => {
             if (event["payload"].is_string() && payload) || event["packet"].is_string() {
-                process_event(&event, lineno, output, header_one, payload);
+                process_event(&event, lineno, output, payload);
             }
         }
         Err(err) => {
@@ -104,13 +110,32 @@ fn process_line(
     }
 }
 
-fn process_event(
-    event: &serde_json::Value,
-    lineno: usize,
-    output: &mut File,
-    header_done: &mut bool,
-    payload: bool,
-) {
+fn process_event(event: &serde_json::Value, lineno: usize, output: &mut Output, payload: bool) {
+    let event_type = if payload { "payload" } else { "packet" };
+    if let Some(interfaces) = &mut output.interfaces {
+        match pcap::eve_packet(event_type, event) {
+            Ok((linktype, ts, packet)) => {
+                let comment = pcap::event_packet_comment(event, None);
+                let (mut buf, interface) = interfaces.prepare(linktype, None);
+                buf.extend_from_slice(&pcap::create_pcapng_packet(
+                    interface,
+                    ts.to_seconds() as u32,
+                    ts.micros_part() as u32,
+                    packet.len() as u32,
+                    &packet,
+                    Some(comment.as_str()).filter(|comment| !comment.is_empty()),
+                ));
+                output.file.write_all(&buf).unwrap_or_else(|e| {
+                    error!("Failed to write PCAP data: {}", e);
+                });
+            }
+            Err(err) => {
+                warn!("Failed to convert event on line {}: {}", lineno + 1, err);
+            }
+        }
+        return;
+    }
+
     // Convert EVE to PCAP based on event type
     let result = if payload {
         pcap::payload_to_pcap(event)
@@ -120,7 +145,7 @@ fn process_event(
 
     match result {
         Ok(pcap_data) => {
-            write_pcap(&pcap_data, output, header_done).unwrap_or_else(|e| {
+            write_pcap(&pcap_data, &mut output.file, &mut output.header_done).unwrap_or_else(|e| {
                 error!("Failed to write PCAP data: {}", e);
             });
         }
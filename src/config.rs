@@ -128,6 +128,20 @@ impl Config {
         }
     }
 
+    /// Return the configuration value as a duration: a humantime string
+    /// such as `15m`, or a number of seconds.
+    pub fn get_duration(&self, name: &str) -> anyhow::Result<Option<std::time::Duration>> {
+        let value = match self.get_value::<Value>(name)? {
+            None => return Ok(None),
+            Some(Value::Number(n)) => n.to_string(),
+            Some(Value::String(s)) => s,
+            Some(other) => anyhow::bail!("{name}: invalid duration {other:?}"),
+        };
+        crate::server::pcap::parse_duration_seconds(&value)
+            .map(Some)
+            .map_err(|err| anyhow::anyhow!("{name}: invalid duration {value:?}: {err}"))
+    }
+
     /// Suppress clippy warning for another day...
     #[allow(clippy::only_used_in_recursion)]
     pub fn get_node<'a>(&self, root: &'a Value, name: &str) -> Option<&'a Value> {
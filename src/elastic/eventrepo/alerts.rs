@@ -100,6 +100,10 @@ impl ElasticEventRepo {
             }
         }
 
+        if let Some(sensors) = &options.sensors {
+            filters.push(self.sensors_filter(sensors));
+        }
+
         if !has_min_timestamp && let Some(ts) = options.timestamp_gte {
             filters.push(json!({"range": {"@timestamp": {"gte": ts.to_elastic()}}}));
         }
@@ -520,6 +524,7 @@ mod tests {
                 tls_sni: Some("gateway.discord.com".to_string()),
                 min_timestamp: "2026-08-11T00:00:00Z".to_string(),
                 max_timestamp: "2026-08-11T01:00:00Z".to_string(),
+                sensors: Some(vec!["sensor-a".to_string()]),
             },
             &mut must_not,
         );
@@ -527,6 +532,9 @@ mod tests {
         assert!(filters.contains(&json!({
             "term": {"dns.queries.rrname.keyword": "discord.com"}
         })));
+        assert!(filters.contains(&json!({
+            "terms": {"host.keyword": ["sensor-a"]}
+        })));
         assert!(filters.contains(&json!({
             "term": {"tls.sni.keyword": "gateway.discord.com"}
         })));
@@ -15,6 +15,7 @@ impl ElasticEventRepo {
         earliest: Option<DateTime>,
         dhcp_type: &str,
         sensor: Option<String>,
+        sensors: Option<&[String]>,
     ) -> Result<Vec<serde_json::Value>> {
         let mut filters = vec![];
 
@@ -31,6 +32,9 @@ impl ElasticEventRepo {
                 filters.push(term_filter(&self.map_field("host"), sensor));
             }
         }
+        if let Some(sensors) = sensors {
+            filters.push(self.sensors_filter(sensors));
+        }
 
         filters.push(term_filter(&self.map_field("dhcp.dhcp_type"), dhcp_type));
 
@@ -75,15 +79,17 @@ impl ElasticEventRepo {
         &self,
         earliest: Option<DateTime>,
         sensor: Option<String>,
+        sensors: Option<&[String]>,
     ) -> Result<Vec<serde_json::Value>> {
-        self.dhcp(earliest, "request", sensor).await
+        self.dhcp(earliest, "request", sensor, sensors).await
     }
 
     pub async fn dhcp_ack(
         &self,
         earliest: Option<DateTime>,
         sensor: Option<String>,
+        sensors: Option<&[String]>,
     ) -> Result<Vec<serde_json::Value>> {
-        self.dhcp(earliest, "ack", sensor).await
+        self.dhcp(earliest, "ack", sensor, sensors).await
     }
 }
@@ -13,6 +13,7 @@ impl ElasticEventRepo {
         &self,
         before: Option<DateTime>,
         sensor: Option<String>,
+        sensors: Option<&[String]>,
         src_ip: String,
         dest_ip: String,
     ) -> Result<serde_json::Value> {
@@ -34,6 +35,9 @@ impl ElasticEventRepo {
                 filters.push(term_filter(&self.map_field("host"), &host));
             }
         }
+        if let Some(sensors) = sensors {
+            filters.push(self.sensors_filter(sensors));
+        }
 
         filters.push(json!({
             "bool": {
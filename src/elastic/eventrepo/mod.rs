@@ -373,6 +373,17 @@ impl ElasticEventRepo {
         self.add_tags_by_query(query, &[], &action).await
     }
 
+    pub async fn add_history_by_id(&self, event_id: &str, entry: &HistoryEntry) -> Result<u64> {
+        let query = json!({
+            "bool": {
+                "filter": {
+                    "term": {"_id": event_id}
+                }
+            }
+        });
+        self.add_tags_by_query(query, &[], entry).await
+    }
+
     pub async fn get_event_by_id(&self, event_id: String) -> Result<Option<serde_json::Value>> {
         let query = json!({
             "query": {
@@ -408,6 +419,12 @@ impl ElasticEventRepo {
         Ok(None)
     }
 
+    /// A filter matching events from one of `sensors`; with none it
+    /// matches nothing.
+    pub(crate) fn sensors_filter(&self, sensors: &[String]) -> serde_json::Value {
+        json!({"terms": {self.map_field("host"): sensors}})
+    }
+
     pub(crate) fn apply_query_string(
         &self,
         q: &[queryparser::QueryElement],
@@ -574,6 +591,9 @@ impl ElasticEventRepo {
                         filter.push(expression);
                     }
                 }
+                queryparser::QueryValue::Sensors(sensors) => {
+                    filter.push(self.sensors_filter(sensors));
+                }
             }
         }
     }
@@ -714,23 +734,23 @@ impl ElasticEventRepo {
             .await
     }
 
-    pub(crate) async fn earliest_timestamp(&self) -> Result<Option<crate::datetime::DateTime>> {
-        #[rustfmt::skip]
-	let request = json!({
-	    "query": {
-		"bool": {
-		    "filter": [
-			{
-			    "exists": {
-				"field": self.map_field("event_type"),
-			    },
-			},
-		    ],
-		},
-	    },
-	    "sort": [{"@timestamp": {"order": "asc"}}],
-	    "size": 1,
-	});
+    pub(crate) async fn earliest_timestamp(
+        &self,
+        sensors: Option<&[String]>,
+    ) -> Result<Option<crate::datetime::DateTime>> {
+        let mut filter = vec![exists_filter(&self.map_field("event_type"))];
+        if let Some(sensors) = sensors {
+            filter.push(self.sensors_filter(sensors));
+        }
+        let request = json!({
+            "query": {
+                "bool": {
+                    "filter": filter,
+                },
+            },
+            "sort": [{"@timestamp": {"order": "asc"}}],
+            "size": 1,
+        });
         let response: serde_json::Value = self.search(&request).await?.json().await?;
         if let Some(hits) = response["hits"]["hits"].as_array() {
             for hit in hits {
@@ -757,7 +777,7 @@ impl ElasticEventRepo {
         let bound_max = datetime::DateTime::now();
         let bound_min = if let Some(timestamp) = qs.first_from() {
             timestamp
-        } else if let Some(timestamp) = self.earliest_timestamp().await? {
+        } else if let Some(timestamp) = self.earliest_timestamp(None).await? {
             debug!(
                 "No time-range provided by client, using earliest from database of {}",
                 &timestamp
@@ -965,6 +985,9 @@ impl ElasticEventRepo {
                 filter.push(json!({"term": {self.map_field("host"): sensor}}));
             }
         }
+        if let Some(sensors) = &request.sensors {
+            filter.push(self.sensors_filter(sensors));
+        }
         filter
     }
 
@@ -1067,19 +1090,17 @@ impl ElasticEventRepo {
         Ok(sensors)
     }
 
-    pub async fn get_event_types(&self) -> anyhow::Result<Vec<String>> {
+    pub async fn get_event_types(&self, sensors: Option<&[String]>) -> anyhow::Result<Vec<String>> {
+        let mut must = vec![json!({"exists": {"field": self.map_field("event_type")}})];
+        if let Some(sensors) = sensors {
+            must.push(self.sensors_filter(sensors));
+        }
         #[rustfmt::skip]
         let request = json!({
             "size": 0,
             "query": {
                 "bool": {
-                    "must": [
-                        {
-                            "exists": {
-                                "field": self.map_field("event_type")
-                            }
-                        }
-                    ]
+                    "must": must,
                 }
             },
             "aggs": {
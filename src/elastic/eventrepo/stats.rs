@@ -17,6 +17,9 @@ impl ElasticEventRepo {
         let mut filters = vec![];
         filters.push(json!({"term": {self.map_field("event_type"): "stats"}}));
         filters.push(json!({"range": {"@timestamp": {"gte": start_time, "lte": end_time}}}));
+        if let Some(sensors) = &params.sensors {
+            filters.push(self.sensors_filter(sensors));
+        }
         if let Some(sensor_name) = &params.sensor_name {
             if sensor_name == "(no-name)" {
                 // Filter for documents without a host field
@@ -89,6 +92,9 @@ impl ElasticEventRepo {
         let mut filters = vec![];
         filters.push(json!({"term": {self.map_field("event_type"): "stats"}}));
         filters.push(json!({"range": {"@timestamp": {"gte": start_time, "lte": end_time}}}));
+        if let Some(sensors) = &params.sensors {
+            filters.push(self.sensors_filter(sensors));
+        }
         if let Some(sensor_name) = &params.sensor_name {
             if sensor_name == "(no-name)" {
                 // Filter for documents without a host field
@@ -185,6 +191,9 @@ impl ElasticEventRepo {
         let mut filters = vec![];
         filters.push(json!({"term": {self.map_field("event_type"): "stats"}}));
         filters.push(json!({"range": {"@timestamp": {"gte": start_time, "lte": end_time}}}));
+        if let Some(sensors) = &params.sensors {
+            filters.push(self.sensors_filter(sensors));
+        }
 
         let field = self.map_field(&params.field);
         let host_field = self.map_field("host");
@@ -294,6 +303,9 @@ impl ElasticEventRepo {
         let mut filters = vec![];
         filters.push(json!({"term": {self.map_field("event_type"): "stats"}}));
         filters.push(json!({"range": {"@timestamp": {"gte": start_time, "lte": end_time}}}));
+        if let Some(sensors) = &params.sensors {
+            filters.push(self.sensors_filter(sensors));
+        }
 
         let field = self.map_field(&params.field);
         let host_field = self.map_field("host");
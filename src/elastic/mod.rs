@@ -34,6 +34,8 @@ pub(crate) enum HistoryType {
     Escalated,
     Deescalated,
     Comment,
+    PcapPinned,
+    PcapPinFailed,
 }
 
 impl std::fmt::Display for HistoryType {
@@ -44,6 +46,8 @@ impl std::fmt::Display for HistoryType {
             HistoryType::Escalated => write!(f, "escalated"),
             HistoryType::Deescalated => write!(f, "de-escalated"),
             HistoryType::Comment => write!(f, "comment"),
+            HistoryType::PcapPinned => write!(f, "pcap-pinned"),
+            HistoryType::PcapPinFailed => write!(f, "pcap-pin-failed"),
         }
     }
 }
@@ -54,6 +58,8 @@ pub(crate) struct AlertQueryOptions {
     pub query_string: Option<String>,
     pub tags: Vec<String>,
     pub sensor: Option<String>,
+    /// The sensors the user may see; None for all of them.
+    pub sensors: Option<Vec<String>>,
     pub timeout: Option<u64>,
 }
 
@@ -67,6 +73,17 @@ pub(crate) struct HistoryEntry {
     pub cause: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub comment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pcap: Option<HistoryPcap>,
+}
+
+/// The preserved capture a `pcap-pinned` history entry refers to.
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct HistoryPcap {
+    pub sha256: String,
+    pub bytes: u64,
+    pub source: String,
+    pub truncated: bool,
 }
 
 impl HistoryEntry {
@@ -81,6 +98,7 @@ pub(crate) struct HistoryEntryBuilder {
     username: Option<String>,
     cause: Option<String>,
     comment: Option<String>,
+    pcap: Option<HistoryPcap>,
 }
 
 impl HistoryEntryBuilder {
@@ -91,6 +109,7 @@ impl HistoryEntryBuilder {
             username: None,
             cause: None,
             comment: None,
+            pcap: None,
         }
     }
 
@@ -116,6 +135,18 @@ impl HistoryEntryBuilder {
         Self::new(HistoryType::Comment)
     }
 
+    pub(crate) fn new_pcap_pinned(pcap: HistoryPcap) -> Self {
+        let mut builder = Self::new(HistoryType::PcapPinned);
+        builder.pcap = Some(pcap);
+        builder
+    }
+
+    pub(crate) fn new_pcap_pin_failed(cause: impl Into<String>) -> Self {
+        let mut builder = Self::new(HistoryType::PcapPinFailed);
+        builder.cause = Some(cause.into());
+        builder
+    }
+
     pub(crate) fn username(mut self, username: Option<impl Into<String>>) -> Self {
         self.username = username.map(|u| u.into());
         self
@@ -133,6 +164,7 @@ impl HistoryEntryBuilder {
             action: self.action,
             cause: self.cause,
             comment: self.comment,
+            pcap: self.pcap,
         }
     }
 }
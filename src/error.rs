@@ -51,7 +51,11 @@ impl From<crate::sqlite::configdb::ConfigDbError> for AppError {
             ConfigDbError::AgentKeyNameInUse(_)
             | ConfigDbError::AgentNameReserved(_)
             | ConfigDbError::EmptyAgentName
-            | ConfigDbError::AgentNameTooLong(_) => Self::BadRequest(value.to_string()),
+            | ConfigDbError::AgentNameTooLong(_)
+            | ConfigDbError::InvalidCertificateFingerprint(_)
+            | ConfigDbError::AgentCertificateInUse
+            | ConfigDbError::ApiTokenNameInUse(_)
+            | ConfigDbError::EmptyApiTokenName => Self::BadRequest(value.to_string()),
             _ => Self::StringError(value.to_string()),
         }
     }
@@ -35,6 +35,8 @@ pub(crate) enum EventRepo {
 pub(crate) struct StatsAggQueryParams {
     pub field: String,
     pub sensor_name: Option<String>,
+    /// The sensors the user may see; None for all of them.
+    pub sensors: Option<Vec<String>>,
     pub start_time: DateTime,
     pub end_time: DateTime,
 }
@@ -176,6 +178,20 @@ impl EventRepo {
         }
     }
 
+    pub async fn add_history_by_id(
+        &self,
+        event_id: &str,
+        entry: &elastic::HistoryEntry,
+    ) -> Result<()> {
+        match self {
+            EventRepo::Elastic(ds) => {
+                ds.add_history_by_id(event_id, entry).await?;
+                Ok(())
+            }
+            EventRepo::SQLite(ds) => ds.add_history_by_id(event_id, entry).await,
+        }
+    }
+
     pub async fn agg(
         &self,
         field: &str,
@@ -189,10 +205,15 @@ impl EventRepo {
         }
     }
 
-    pub(crate) async fn earliest_timestamp(&self) -> Result<Option<DateTime>> {
+    /// The timestamp of the earliest event, from one of `sensors` when
+    /// given.
+    pub(crate) async fn earliest_timestamp(
+        &self,
+        sensors: Option<&[String]>,
+    ) -> Result<Option<DateTime>> {
         match self {
-            EventRepo::Elastic(repo) => repo.earliest_timestamp().await,
-            EventRepo::SQLite(repo) => repo.earliest_timestamp().await,
+            EventRepo::Elastic(repo) => repo.earliest_timestamp(sensors).await,
+            EventRepo::SQLite(repo) => repo.earliest_timestamp(sensors).await,
         }
     }
 }
@@ -11,7 +11,8 @@ use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 use tokio_util::sync::CancellationToken;
 
-use super::filter::vlan_wrapped;
+use super::filter::{FlowSelector, vlan_wrapped};
+use super::index;
 use super::request::{FetchStats, PcapFilter, PcapRequest, PcapSource};
 use super::spool;
 use super::writer::PcapWriter;
@@ -102,12 +103,19 @@ impl Flusher {
 /// Per-fetch matching state shared by every cursor: the filter
 /// renderings prepared per file and the packet time gate.
 struct Matcher<'a> {
-    /// A `PcapFilter::Expression`, compiled against each capture's
-    /// actual link type.
+    /// A `PcapFilter::Expression`, or the rendering of a
+    /// `PcapFilter::Fields` with a VLAN, compiled against each
+    /// capture's actual link type.
     expression: Option<&'a str>,
-    /// The (VLAN-wrapped, base) renderings of a `PcapFilter::Flow`,
-    /// compiled per file.
+    /// The (VLAN-wrapped, base) renderings of a `PcapFilter::Flow` or
+    /// of a `PcapFilter::Fields` without a VLAN, compiled per file.
     flow_expressions: &'a Option<(String, String)>,
+    /// The selector of a `PcapFilter::Flow`, checked against file
+    /// indexes.
+    selector: Option<&'a FlowSelector>,
+    /// Use sidecar indexes to skip, seek within and stop early in
+    /// spool files.
+    index: bool,
     /// Only gate on packet timestamps when the request has a time
     /// window, preserving the historical extract behavior of passing
     /// through packets with unusual timestamps when no window was
@@ -170,6 +178,11 @@ struct OpenCapture {
     /// applied manually per packet.
     filter_program: Option<pcap::BpfProgram>,
     path: PathBuf,
+    /// Byte offset of the next record.
+    offset: u64,
+    /// Offset from which the file's index shows every record is past
+    /// the time window.
+    stop_at: Option<u64>,
 }
 
 /// The public `pcap::BpfProgram::filter` helper synthesizes a packet
@@ -203,6 +216,26 @@ fn bpf_matches(program: &pcap::BpfProgram, packet: &pcap::Packet<'_>) -> bool {
     unsafe { offline_filter(&raw, packet.header, packet.data.as_ptr()) > 0 }
 }
 
+unsafe extern "C" {
+    #[link_name = "pcap_file"]
+    fn capture_file(capture: *mut libc::c_void) -> *mut libc::FILE;
+}
+
+/// Reposition an offline capture at a record boundary taken from the
+/// file's index. libpcap reads records from the current position of
+/// the underlying stream.
+fn seek_capture(capture: &pcap::Capture<pcap::Offline>, offset: u64) -> bool {
+    let Ok(offset) = libc::off_t::try_from(offset) else {
+        return false;
+    };
+    // SAFETY: the capture is a live offline capture, whose stream
+    // pcap_file returns; it is not read concurrently.
+    unsafe {
+        let file = capture_file(capture.as_ptr() as *mut libc::c_void);
+        !file.is_null() && libc::fseeko(file, offset, libc::SEEK_SET) == 0
+    }
+}
+
 /// Walks one rotation group's files in order, holding at most one
 /// open capture and one pending matched packet.
 struct GroupCursor {
@@ -257,11 +290,20 @@ impl GroupCursor {
                 let Some(path) = self.files.pop_front() else {
                     return Ok(());
                 };
-                self.open = open_group_file(&path, matcher, bpf_expr_tracker, stats)?;
+                self.open = open_group_file(&path, matcher, bpf_expr_tracker, ctl, stats)?;
                 continue;
             };
+            if open.stop_at.is_some_and(|stop_at| open.offset >= stop_at) {
+                debug!(
+                    "Done with {}, its index shows no later packets",
+                    open.path.display()
+                );
+                self.open = None;
+                continue;
+            }
             match open.capture.next_packet() {
                 Ok(pkt) => {
+                    open.offset += 16 + u64::from(pkt.header.caplen);
                     if matcher.gate {
                         let Ok(secs) = u64::try_from(pkt.header.ts.tv_sec) else {
                             continue;
@@ -318,14 +360,38 @@ impl GroupCursor {
 
 /// Open one spool file with the request's filter compiled against the
 /// file's link type. `Ok(None)` means the file was skipped (vanished,
-/// or a link-type specific filter incompatibility) with the stats
-/// updated; only file-descriptor exhaustion is an error.
+/// ruled out by its index, or a link-type specific filter
+/// incompatibility) with the stats updated; only file-descriptor
+/// exhaustion is an error.
 fn open_group_file(
     path: &Path,
     matcher: &Matcher,
     bpf_expr_tracker: &mut BpfExprTracker,
+    ctl: &mut Control,
     stats: &mut FetchStats,
 ) -> Result<Option<OpenCapture>, FetchError> {
+    let index = if matcher.index {
+        index::load_or_build(path, &mut || ctl.live())
+    } else {
+        None
+    };
+    if let Some(index) = &index {
+        if matcher.gate && !index.overlaps(matcher.start, matcher.end) {
+            debug!(
+                "Skipping {}, its index has no packets in the time window",
+                path.display()
+            );
+            stats.files_skipped += 1;
+            return Ok(None);
+        }
+        if let Some(selector) = matcher.selector
+            && !index.may_contain(selector)
+        {
+            debug!("Skipping {}, its index rules out the flow", path.display());
+            stats.files_skipped += 1;
+            return Ok(None);
+        }
+    }
     info!("Processing file {}", path.display());
     let capture = match pcap::Capture::from_file(path).map_err(anyhow::Error::from) {
         Ok(capture) => capture,
@@ -399,11 +465,27 @@ fn open_group_file(
             None => None,
         }
     };
+    // Classic pcap records start after the 24 byte file header; only
+    // classic files are indexed.
+    let mut offset = 24;
+    let mut stop_at = None;
+    if matcher.gate
+        && let Some(index) = &index
+    {
+        let seek = index.seek_offset(matcher.start);
+        if seek > offset && seek_capture(&capture, seek) {
+            debug!("Seeking {} to offset {seek}", path.display());
+            offset = seek;
+        }
+        stop_at = index.stop_offset(matcher.end);
+    }
     Ok(Some(OpenCapture {
         capture,
         linktype,
         filter_program,
         path: path.to_path_buf(),
+        offset,
+        stop_at,
     }))
 }
 
@@ -456,22 +538,39 @@ pub(crate) fn fetch(
 ) -> Result<FetchStats, FetchError> {
     let started = Instant::now();
 
-    // The wrapped and base renderings of a Flow filter, compiled per
-    // file below against the file's link type.
+    // The wrapped and base renderings of a Flow or Fields filter,
+    // compiled per file below against the file's link type.
     let mut flow_expressions: Option<(String, String)> = None;
 
     // Generated flow filters must always compile for Ethernet; a failure
     // here is a generator bug and should fail before any file is touched.
     // User expressions are instead compiled against each candidate's
     // actual link type below, so valid link-type-specific syntax works.
-    if let Some(PcapFilter::Flow(selector)) = &request.filter {
-        let base = selector.to_bpf();
-        let wrapped = vlan_wrapped(&base);
+    // Structured fields are generated too. With a VLAN they are then
+    // compiled per file like a user expression, as only link types with
+    // VLAN support can match.
+    let mut fields_expression: Option<String> = None;
+    match &request.filter {
+        Some(PcapFilter::Flow(selector)) => {
+            let base = selector.to_bpf();
+            flow_expressions = Some((vlan_wrapped(&base), base));
+        }
+        Some(PcapFilter::Fields(fields)) if fields.vlan.is_none() => {
+            let base = fields.to_bpf();
+            flow_expressions = Some((vlan_wrapped(&base), base));
+        }
+        Some(PcapFilter::Fields(fields)) => fields_expression = Some(fields.to_bpf()),
+        _ => {}
+    }
+    let generated = match &flow_expressions {
+        Some((wrapped, _)) => Some(wrapped),
+        None => fields_expression.as_ref(),
+    };
+    if let Some(expression) = generated {
         let dead = pcap::Capture::dead(pcap::Linktype::ETHERNET)
             .map_err(|err| FetchError::Format(err.to_string()))?;
-        dead.compile(&wrapped, true)
+        dead.compile(expression, true)
             .map_err(|err| FetchError::Format(err.to_string()))?;
-        flow_expressions = Some((wrapped, base));
     }
 
     let start = request.start.unwrap_or(0);
@@ -512,16 +611,22 @@ pub(crate) fn fetch(
     }
 
     let mut stats = FetchStats::default();
-    let mut writer = PcapWriter::new(out);
-    // The link type of the output, established by the first written
-    // packet. Files with a different link type are skipped after that.
+    let mut writer = PcapWriter::with_format(out, &request.format);
+    // The link type of a classic pcap output, established by the first
+    // written packet. Files with a different link type are skipped after
+    // that; pcapng output takes every link type.
     let mut output_linktype: Option<pcap::Linktype> = None;
     let matcher = Matcher {
         expression: match &request.filter {
             Some(PcapFilter::Expression(expression)) => Some(expression.as_str()),
-            _ => None,
+            _ => fields_expression.as_deref(),
         },
         flow_expressions: &flow_expressions,
+        selector: match &request.filter {
+            Some(PcapFilter::Flow(selector)) => Some(selector),
+            _ => None,
+        },
+        index: matches!(source, PcapSource::Spool(spool) if spool.index),
         gate: request.start.is_some() || request.end.is_some(),
         start,
         end,
@@ -554,6 +659,7 @@ pub(crate) fn fetch(
             &mut writer,
             &mut flusher,
         )?;
+        request.report(&stats);
         if let Some(pending) = &cursor.pending {
             heap.push(Reverse((pending.ts_micros, index)));
         }
@@ -570,6 +676,7 @@ pub(crate) fn fetch(
             .take()
             .expect("popped cursor has a pending packet");
         if let Some(output_linktype) = output_linktype
+            && !writer.mixed_linktypes()
             && packet.linktype != output_linktype
         {
             // The pending packet always comes from the cursor's
@@ -586,7 +693,7 @@ pub(crate) fn fetch(
         } else {
             if stats
                 .bytes
-                .saturating_add(writer.next_write_size(packet.data.len()))
+                .saturating_add(writer.next_write_size(packet.linktype, packet.data.len()))
                 > request.limits.max_bytes
             {
                 stats.truncated = true;
@@ -603,6 +710,7 @@ pub(crate) fn fetch(
             ctl.write_credit += write_started.elapsed();
             stats.bytes = writer.bytes_written();
             stats.packets += 1;
+            request.report(&stats);
             output_linktype.get_or_insert(packet.linktype);
         }
         cursor.refill(
@@ -613,6 +721,7 @@ pub(crate) fn fetch(
             &mut writer,
             &mut flusher,
         )?;
+        request.report(&stats);
         if let Some(pending) = &cursor.pending {
             heap.push(Reverse((pending.ts_micros, index)));
         }
@@ -634,7 +743,7 @@ pub(crate) fn fetch(
         {
             return Err(FetchError::Format(err));
         }
-        if stats.files_scanned == 0 {
+        if stats.files_scanned == 0 && stats.files_skipped == 0 {
             // Every candidate vanished before it could be opened.
             return Err(FetchError::NoCandidateFiles);
         }
@@ -650,7 +759,7 @@ mod test {
         LINKTYPE_RAW, count_packets, ipv4_datagram, ipv4_packet, ports, write_pcap_file,
         write_pcap_file_bytes, write_pcap_file_with_linktype, write_raw_pcap_file,
     };
-    use crate::pcap::{FlowSelector, Limits, SpoolConfig};
+    use crate::pcap::{FlowSelector, Limits, OutputFormat, SpoolConfig};
     use std::path::Path;
 
     // Sizes of the pcap stream elements for the test UDP packet, used by
@@ -816,15 +925,26 @@ mod test {
         let matcher = Matcher {
             expression: Some("udp and port 53"),
             flow_expressions: &flow_expressions,
+            selector: None,
+            index: false,
             gate: false,
             start: 0,
             end: u64::MAX,
         };
         let mut stats = FetchStats::default();
         let mut bpf_expr_tracker = BpfExprTracker::default();
-        let mut open = open_group_file(&path, &matcher, &mut bpf_expr_tracker, &mut stats)
-            .unwrap()
-            .unwrap();
+        let cancel = CancellationToken::new();
+        let mut ctl = Control {
+            cancel: &cancel,
+            started: Instant::now(),
+            deadline: None,
+            stopped: None,
+            write_credit: Duration::ZERO,
+        };
+        let mut open =
+            open_group_file(&path, &matcher, &mut bpf_expr_tracker, &mut ctl, &mut stats)
+                .unwrap()
+                .unwrap();
 
         let first = open.capture.next_packet().unwrap();
         assert_eq!(u16::from_be_bytes([first.data[36], first.data[37]]), 9999);
@@ -1006,6 +1126,45 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_fetch_uses_spool_index() {
+        let (_tempdir, input) = setup();
+        let matching = input.join("log.pcap.1.1700000000");
+        let other = input.join("log.pcap.2.1700000000");
+        let packets: Vec<(i64, u16)> = (0..10).map(|i| (1_700_000_000 + i, 53)).collect();
+        write_pcap_file(&matching, &packets);
+        write_pcap_file(&other, &[(1_700_000_000, 9999), (1_700_000_005, 9999)]);
+        // Only sealed files are indexed.
+        for path in [&matching, &other] {
+            std::fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(std::time::SystemTime::now() - index::SEALED_AGE * 2)
+                .unwrap();
+        }
+
+        let mut spool = SpoolConfig::new(&input, None);
+        spool.index = true;
+        let request = PcapRequest {
+            filter: Some(PcapFilter::Flow(FlowSelector {
+                proto: 17,
+                a: ("192.0.2.1".parse().unwrap(), Some(1234)),
+                b: ("198.51.100.2".parse().unwrap(), Some(53)),
+            })),
+            start: Some(micros(1_700_000_004)),
+            end: Some(micros(1_700_000_005)),
+            ..Default::default()
+        };
+        let (result, _out) = fetch_to_vec(&spool, &request);
+        let stats = result.unwrap();
+        assert_eq!(stats.packets, 2);
+        assert_eq!(stats.files_scanned, 1);
+        assert_eq!(stats.files_skipped, 1);
+        assert!(index::sidecar_path(&matching).exists());
+        assert!(index::sidecar_path(&other).exists());
+    }
+
     #[test]
     fn test_fetch_no_candidate_files() {
         // An empty spool directory.
@@ -1164,6 +1323,41 @@ mod test {
         assert_eq!(count_packets(&path), 1);
     }
 
+    #[test]
+    fn test_fetch_pcapng_keeps_mixed_linktypes() {
+        let (_tempdir, input) = setup();
+        write_pcap_file(&input.join("a.pcap.1700000000"), &[(1_700_000_000, 53)]);
+        write_pcap_file_with_linktype(
+            &input.join("b.pcap.1700000000"),
+            &[(1_700_000_001, 53)],
+            pcap::Linktype::NULL,
+        );
+
+        let spool = SpoolConfig::new(&input, None);
+        let request = PcapRequest {
+            format: OutputFormat::Pcapng {
+                section_comment: None,
+                packet_comment: Some("flow_id: 1".to_string()),
+            },
+            ..Default::default()
+        };
+        let (result, out) = fetch_to_vec(&spool, &request);
+        let stats = result.unwrap();
+        assert_eq!(stats.packets, 2);
+        assert_eq!(stats.bytes, out.len() as u64);
+
+        // Section header, then an interface and a packet per link type.
+        let mut types = vec![];
+        let mut offset = 0;
+        while offset < out.len() {
+            types.push(u32::from_le_bytes(
+                out[offset..offset + 4].try_into().unwrap(),
+            ));
+            offset += u32::from_le_bytes(out[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        }
+        assert_eq!(types, vec![0x0A0D_0D0A, 1, 6, 1, 6]);
+    }
+
     /// Seconds offset from the spool epoch as unix microseconds.
     fn micros(seconds: i64) -> u64 {
         u64::try_from(seconds).unwrap() * 1_000_000
@@ -17,20 +17,18 @@
 //! including flows on other port pairs (such as the mirrored-port
 //! flow the port clauses deliberately reject). Such stray
 //! continuations appear in the export as unreassemblable fragments.
+//!
+//! [`FilterFields`] is the structured alternative to a hand-written
+//! expression for standalone requests: hosts, networks, port ranges, a
+//! protocol, a direction and a VLAN, rendered by
+//! [`FilterFields::to_bpf`].
 
 use std::net::IpAddr;
 
-// The BPF renderings below feed the libpcap extraction path, which
-// Windows builds omit; the selector itself stays portable for the wire.
-#[cfg(not(windows))]
 const IPPROTO_ICMP: u8 = 1;
-#[cfg(not(windows))]
 const IPPROTO_TCP: u8 = 6;
-#[cfg(not(windows))]
 const IPPROTO_UDP: u8 = 17;
-#[cfg(not(windows))]
 const IPPROTO_ICMPV6: u8 = 58;
-#[cfg(not(windows))]
 const IPPROTO_SCTP: u8 = 132;
 
 /// Direction-symmetric flow filter: `a` and `b` match either way
@@ -44,6 +42,30 @@ pub(crate) struct FlowSelector {
     pub(crate) b: (IpAddr, Option<u16>),
 }
 
+/// Which end of a packet the host, net and port fields of a
+/// [`FilterFields`] match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FieldDirection {
+    Src,
+    Dst,
+}
+
+/// A structured packet filter. Every field given must match: any of the
+/// hosts or nets, any of the port ranges, the protocol and the VLAN.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct FilterFields {
+    pub(crate) hosts: Vec<IpAddr>,
+    /// Networks as (network address, prefix length).
+    pub(crate) nets: Vec<(IpAddr, u8)>,
+    /// Inclusive (first, last) port ranges.
+    pub(crate) ports: Vec<(u16, u16)>,
+    /// IP protocol number.
+    pub(crate) proto: Option<u8>,
+    /// Either end when None.
+    pub(crate) direction: Option<FieldDirection>,
+    pub(crate) vlan: Option<u16>,
+}
+
 /// Wrap a BPF expression so it also matches packets behind a single
 /// VLAN tag. Only valid on link types with `vlan` keyword support
 /// (Ethernet and friends); on others (raw IP, loopback, Linux
@@ -116,6 +138,137 @@ impl FlowSelector {
     }
 }
 
+impl FilterFields {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+            && self.nets.is_empty()
+            && self.ports.is_empty()
+            && self.proto.is_none()
+            && self.vlan.is_none()
+    }
+
+    /// Render the fields as a BPF filter expression. Without a VLAN the
+    /// rendering matches untagged packets only, like
+    /// [`FlowSelector::to_bpf`]; with one, only packets carrying that
+    /// tag.
+    pub(crate) fn to_bpf(&self) -> String {
+        let direction = match self.direction {
+            Some(FieldDirection::Src) => "src ",
+            Some(FieldDirection::Dst) => "dst ",
+            None => "",
+        };
+        let mut clauses = Vec::new();
+        if let Some(proto) = self.proto {
+            clauses.push(match proto {
+                IPPROTO_TCP => "tcp".to_string(),
+                IPPROTO_UDP => "udp".to_string(),
+                IPPROTO_SCTP => "sctp".to_string(),
+                IPPROTO_ICMP => "icmp".to_string(),
+                IPPROTO_ICMPV6 => "icmp6".to_string(),
+                n => format!("(ip proto {n} or ip6 proto {n})"),
+            });
+        }
+        let addresses: Vec<String> = self
+            .hosts
+            .iter()
+            .map(|host| format!("{direction}host {host}"))
+            .chain(
+                self.nets
+                    .iter()
+                    .map(|(net, prefix)| format!("{direction}net {net}/{prefix}")),
+            )
+            .collect();
+        if !addresses.is_empty() {
+            clauses.push(any_of(addresses));
+        }
+        let ports: Vec<String> = self
+            .ports
+            .iter()
+            .map(|(first, last)| match first == last {
+                true => format!("{direction}port {first}"),
+                false => format!("{direction}portrange {first}-{last}"),
+            })
+            .collect();
+        if !ports.is_empty() {
+            clauses.push(any_of(ports));
+        }
+        let base = clauses.join(" and ");
+        match self.vlan {
+            Some(vlan) if base.is_empty() => format!("vlan {vlan}"),
+            Some(vlan) => format!("vlan {vlan} and ({base})"),
+            None => base,
+        }
+    }
+}
+
+fn any_of(clauses: Vec<String>) -> String {
+    match clauses.len() {
+        1 => clauses.into_iter().next().unwrap_or_default(),
+        _ => format!("({})", clauses.join(" or ")),
+    }
+}
+
+/// True if `proto` has ports for a port field to match.
+pub(crate) fn has_ports(proto: u8) -> bool {
+    matches!(proto, IPPROTO_TCP | IPPROTO_UDP | IPPROTO_SCTP)
+}
+
+/// Parse a network in CIDR notation. A bare address is a host network,
+/// and host bits are cleared: `10.1.2.3/8` is `10.0.0.0/8`, as libpcap
+/// refuses networks with host bits set.
+pub(crate) fn parse_net(input: &str) -> Result<(IpAddr, u8), String> {
+    let (addr, prefix) = match input.split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (input, None),
+    };
+    let addr: IpAddr = addr
+        .trim()
+        .parse()
+        .map_err(|_| format!("bad network address: {input}"))?;
+    let bits = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix
+            .trim()
+            .parse::<u8>()
+            .ok()
+            .filter(|prefix| *prefix <= bits)
+            .ok_or_else(|| format!("bad network prefix length: {input}"))?,
+        None => bits,
+    };
+    let addr = match addr {
+        IpAddr::V4(addr) => {
+            let mask = u32::MAX.checked_shl(u32::from(32 - prefix)).unwrap_or(0);
+            IpAddr::from((u32::from(addr) & mask).to_be_bytes())
+        }
+        IpAddr::V6(addr) => {
+            let mask = u128::MAX.checked_shl(u32::from(128 - prefix)).unwrap_or(0);
+            IpAddr::from((u128::from(addr) & mask).to_be_bytes())
+        }
+    };
+    Ok((addr, prefix))
+}
+
+/// Parse a port (`443`) or an inclusive port range (`8000-8100`).
+pub(crate) fn parse_port_range(input: &str) -> Result<(u16, u16), String> {
+    let port = |value: &str| {
+        value
+            .trim()
+            .parse::<u16>()
+            .map_err(|_| format!("bad port: {input}"))
+    };
+    let (first, last) = match input.split_once('-') {
+        Some((first, last)) => (port(first)?, port(last)?),
+        None => {
+            let port = port(input)?;
+            (port, port)
+        }
+    };
+    if first > last {
+        return Err(format!("bad port range: {input}"));
+    }
+    Ok((first, last))
+}
+
 #[cfg(all(test, not(windows)))]
 mod test {
     use super::*;
@@ -139,11 +292,15 @@ mod test {
     /// Run the selector as a Flow filter through fetch() over a
     /// single-file tempdir spool and return the match count.
     fn match_count(selector: &FlowSelector, packets: &[Vec<u8>]) -> u64 {
+        filter_match_count(PcapFilter::Flow(selector.clone()), packets)
+    }
+
+    fn filter_match_count(filter: PcapFilter, packets: &[Vec<u8>]) -> u64 {
         let dir = tempfile::tempdir().unwrap();
         write_raw_pcap_file(&dir.path().join("log.pcap.1700000000"), packets);
         let spool = SpoolConfig::new(dir.path(), None);
         let request = PcapRequest {
-            filter: Some(PcapFilter::Flow(selector.clone())),
+            filter: Some(filter),
             ..Default::default()
         };
         let mut out = vec![];
@@ -426,4 +583,95 @@ mod test {
         let other = ipv4_fragment(17, "10.1.1.6", "192.0.2.10", 100, &[0xde; 8]);
         assert_eq!(match_count(&s, &[first, continuation, other]), 2);
     }
+
+    #[test]
+    fn test_fields_bpf() {
+        let fields = FilterFields {
+            hosts: vec!["10.0.0.1".parse().unwrap(), "2001:db8::1".parse().unwrap()],
+            nets: vec![("192.168.0.0".parse().unwrap(), 16)],
+            ports: vec![(53, 53), (8000, 8100)],
+            proto: Some(17),
+            direction: Some(FieldDirection::Dst),
+            vlan: None,
+        };
+        assert_eq!(
+            fields.to_bpf(),
+            "udp and (dst host 10.0.0.1 or dst host 2001:db8::1 or dst net 192.168.0.0/16) \
+             and (dst port 53 or dst portrange 8000-8100)"
+        );
+
+        let fields = FilterFields {
+            hosts: vec!["10.0.0.1".parse().unwrap()],
+            proto: Some(47),
+            vlan: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(
+            fields.to_bpf(),
+            "vlan 100 and ((ip proto 47 or ip6 proto 47) and host 10.0.0.1)"
+        );
+        let fields = FilterFields {
+            vlan: Some(7),
+            ..Default::default()
+        };
+        assert_eq!(fields.to_bpf(), "vlan 7");
+    }
+
+    #[test]
+    fn test_parse_fields() {
+        assert_eq!(
+            parse_net("10.1.2.3/8").unwrap(),
+            ("10.0.0.0".parse().unwrap(), 8)
+        );
+        assert_eq!(
+            parse_net("2001:db8::1/32").unwrap(),
+            ("2001:db8::".parse().unwrap(), 32)
+        );
+        assert_eq!(
+            parse_net("192.0.2.1").unwrap(),
+            ("192.0.2.1".parse().unwrap(), 32)
+        );
+        assert_eq!(parse_net("0.0.0.0/0").unwrap().1, 0);
+        assert!(parse_net("10.0.0.0/33").is_err());
+        assert!(parse_net("example.com/8").is_err());
+
+        assert_eq!(parse_port_range("443").unwrap(), (443, 443));
+        assert_eq!(parse_port_range("8000 - 8100").unwrap(), (8000, 8100));
+        assert!(parse_port_range("8100-8000").is_err());
+        assert!(parse_port_range("70000").is_err());
+    }
+
+    #[test]
+    fn test_match_fields() {
+        let fields = FilterFields {
+            ports: vec![(50, 60)],
+            proto: Some(17),
+            direction: Some(FieldDirection::Dst),
+            ..Default::default()
+        };
+        let to = ipv4_packet(17, "10.1.1.5", "192.0.2.10", &ports(4000, 53));
+        let from = ipv4_packet(17, "192.0.2.10", "10.1.1.5", &ports(53, 4000));
+        let outside = ipv4_packet(17, "10.1.1.5", "192.0.2.10", &ports(4000, 61));
+        let tagged = vlan_tag(&to, 100);
+        assert_eq!(
+            filter_match_count(PcapFilter::Fields(fields), &[to, from, outside, tagged]),
+            2
+        );
+    }
+
+    #[test]
+    fn test_match_fields_vlan() {
+        let fields = FilterFields {
+            vlan: Some(100),
+            proto: Some(17),
+            ..Default::default()
+        };
+        let packet = ipv4_packet(17, "10.1.1.5", "192.0.2.10", &ports(4000, 53));
+        let tagged = vlan_tag(&packet, 100);
+        let other = vlan_tag(&packet, 200);
+        assert_eq!(
+            filter_match_count(PcapFilter::Fields(fields), &[packet, tagged, other]),
+            1
+        );
+    }
 }
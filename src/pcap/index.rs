@@ -0,0 +1,1011 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! Sidecar indexes for PCAP spool files.
+//!
+//! A sealed spool file (one not modified for [`SEALED_AGE`]) can carry
+//! an index stored next to it as `<file>.evebox-idx`. The index
+//! records the file's first and last packet timestamps, byte offsets
+//! at a fixed timestamp granularity, and a Bloom filter over the
+//! file's flows. [`super::fetch`] uses it to seek straight to a time
+//! window, to stop reading once past it, and to skip files that
+//! cannot contain the requested flow without opening them.
+//!
+//! Indexes are advisory: a missing, stale or unreadable index only
+//! means the file is scanned in full. Indexes are built by a small
+//! classic pcap reader that reads just the first bytes of each
+//! record, either lazily by a fetch or by [`run_indexer`] in the
+//! background. pcapng files are never indexed.
+//!
+//! The Bloom filter is conservative with respect to the
+//! [`FlowSelector`] BPF rendering: every packet that rendering can
+//! match contributes a key, so a negative answer is always safe. It
+//! may also answer yes for packets the rendering rejects, e.g. behind
+//! a second VLAN tag or an IPv6 extension header.
+
+use crate::prelude::*;
+use bytes::{Buf, BufMut};
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs::Metadata;
+use std::io::{BufReader, Read};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use super::filter::FlowSelector;
+use super::reassembly::link_payload;
+use super::request::SpoolConfig;
+use super::spool;
+
+/// Filename suffix of the sidecar index next to each spool file.
+pub(crate) const INDEX_SUFFIX: &str = ".evebox-idx";
+
+/// Files not modified for this long are assumed rotated out by the
+/// writer and safe to index.
+pub(crate) const SEALED_AGE: Duration = Duration::from_secs(60);
+
+/// Timestamp granularity of the offset checkpoints, in microseconds.
+const GRANULARITY_US: u64 = 100_000;
+
+/// How often the background indexer looks for unindexed files.
+const INDEXER_INTERVAL: Duration = Duration::from_secs(60);
+
+const MAGIC: &[u8; 8] = b"EVBXIDX\0";
+const VERSION: u32 = 1;
+
+/// Magic, version, file size and file mtime: enough to tell whether an
+/// index is current without decoding the rest.
+const IDENTITY_SIZE: usize = 8 + 4 + 8 + 8;
+
+const FILE_HEADER_SIZE: u64 = 24;
+const RECORD_HEADER_SIZE: u64 = 16;
+
+/// Bytes read from each record for the Bloom filter; the rest is
+/// skipped. Covers the deepest headers the BPF rendering inspects.
+const SNAP: usize = 256;
+
+/// Larger record lengths mean a corrupt file; libpcap's own cap.
+const MAX_CAPLEN: u32 = 262_144;
+
+/// Packets between liveness checks while building.
+const LIVE_CHECK_PACKETS: u64 = 4096;
+
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+const IPPROTO_SCTP: u8 = 132;
+
+/// An offset checkpoint: every record before `offset` has a timestamp
+/// of at most `max_before`, and every record from `offset` on has a
+/// timestamp of at least `min_from` (unix microseconds).
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Checkpoint {
+    offset: u64,
+    max_before: u64,
+    min_from: u64,
+}
+
+/// The index of one classic pcap spool file.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SpoolIndex {
+    file_size: u64,
+    file_mtime_ns: u64,
+    /// The on-disk link type of the file.
+    pub(crate) linktype: u32,
+    pub(crate) packets: u64,
+    /// Earliest and latest packet timestamps, unix microseconds.
+    /// Records need not be in timestamp order, so these are the
+    /// minimum and maximum rather than the first and last records.
+    pub(crate) first_us: u64,
+    pub(crate) last_us: u64,
+    checkpoints: Vec<Checkpoint>,
+    /// `None` for link types the indexer cannot parse.
+    bloom: Option<Bloom>,
+}
+
+impl SpoolIndex {
+    /// True if the file may hold a packet in `[start, end]`.
+    pub(crate) fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.packets > 0 && self.first_us <= end && self.last_us >= start
+    }
+
+    /// The offset to start reading from for packets at or after
+    /// `start`: every record before it is earlier than `start`.
+    pub(crate) fn seek_offset(&self, start: u64) -> u64 {
+        match self
+            .checkpoints
+            .partition_point(|checkpoint| checkpoint.max_before < start)
+        {
+            0 => FILE_HEADER_SIZE,
+            n => self.checkpoints[n - 1].offset,
+        }
+    }
+
+    /// The offset from which every record is later than `end`, or
+    /// `None` when the file must be read to the end.
+    pub(crate) fn stop_offset(&self, end: u64) -> Option<u64> {
+        self.checkpoints
+            .get(
+                self.checkpoints
+                    .partition_point(|checkpoint| checkpoint.min_from <= end),
+            )
+            .map(|checkpoint| checkpoint.offset)
+    }
+
+    /// False only if no packet of the file can match the selector's
+    /// BPF rendering.
+    pub(crate) fn may_contain(&self, selector: &FlowSelector) -> bool {
+        let Some(bloom) = &self.bloom else {
+            return true;
+        };
+        let (a, x) = selector.a;
+        let (b, y) = selector.b;
+        match (x, y) {
+            (Some(x), Some(y)) if has_ports(selector.proto) => {
+                bloom.contains(flow_key(Key::Ports, selector.proto, (a, x), (b, y)))
+                    || (a.is_ipv4()
+                        && b.is_ipv4()
+                        && bloom.contains(flow_key(Key::Fragment, selector.proto, (a, 0), (b, 0))))
+            }
+            _ => bloom.contains(flow_key(Key::Hosts, selector.proto, (a, 0), (b, 0))),
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.put_slice(MAGIC);
+        buf.put_u32_le(VERSION);
+        buf.put_u64_le(self.file_size);
+        buf.put_u64_le(self.file_mtime_ns);
+        buf.put_u32_le(self.linktype);
+        buf.put_u64_le(self.packets);
+        buf.put_u64_le(self.first_us);
+        buf.put_u64_le(self.last_us);
+        buf.put_u32_le(self.checkpoints.len() as u32);
+        for checkpoint in &self.checkpoints {
+            buf.put_u64_le(checkpoint.offset);
+            buf.put_u64_le(checkpoint.max_before);
+            buf.put_u64_le(checkpoint.min_from);
+        }
+        match &self.bloom {
+            Some(bloom) => {
+                buf.put_u8(1);
+                buf.put_u32_le(bloom.hashes);
+                buf.put_u32_le(bloom.bits.len() as u32);
+                for word in &bloom.bits {
+                    buf.put_u64_le(*word);
+                }
+            }
+            None => buf.put_u8(0),
+        }
+        buf
+    }
+
+    fn decode(mut buf: &[u8]) -> Option<Self> {
+        if buf.len() < IDENTITY_SIZE || !buf.starts_with(MAGIC) {
+            return None;
+        }
+        buf.advance(MAGIC.len());
+        if buf.get_u32_le() != VERSION {
+            return None;
+        }
+        if buf.remaining() < 8 + 8 + 4 + 8 + 8 + 8 + 4 {
+            return None;
+        }
+        let file_size = buf.get_u64_le();
+        let file_mtime_ns = buf.get_u64_le();
+        let linktype = buf.get_u32_le();
+        let packets = buf.get_u64_le();
+        let first_us = buf.get_u64_le();
+        let last_us = buf.get_u64_le();
+        let count = buf.get_u32_le() as usize;
+        if buf.remaining() < count.checked_mul(24)? {
+            return None;
+        }
+        let checkpoints = (0..count)
+            .map(|_| Checkpoint {
+                offset: buf.get_u64_le(),
+                max_before: buf.get_u64_le(),
+                min_from: buf.get_u64_le(),
+            })
+            .collect();
+        if !buf.has_remaining() {
+            return None;
+        }
+        let bloom = match buf.get_u8() {
+            0 => None,
+            1 => {
+                if buf.remaining() < 8 {
+                    return None;
+                }
+                let hashes = buf.get_u32_le();
+                let words = buf.get_u32_le() as usize;
+                if words == 0 || buf.remaining() != words.checked_mul(8)? {
+                    return None;
+                }
+                let bits = (0..words).map(|_| buf.get_u64_le()).collect();
+                Some(Bloom { bits, hashes })
+            }
+            _ => return None,
+        };
+        Some(Self {
+            file_size,
+            file_mtime_ns,
+            linktype,
+            packets,
+            first_us,
+            last_us,
+            checkpoints,
+            bloom,
+        })
+    }
+}
+
+/// The sidecar index path of a spool file.
+pub(crate) fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(INDEX_SUFFIX);
+    PathBuf::from(name)
+}
+
+/// True if `path` names a sidecar index rather than a capture.
+pub(crate) fn is_sidecar(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.ends_with(INDEX_SUFFIX))
+}
+
+/// True if the file has not been modified for [`SEALED_AGE`].
+pub(crate) fn is_sealed(metadata: &Metadata) -> bool {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age >= SEALED_AGE)
+}
+
+/// The (size, mtime) pair an index is validated against.
+fn identity(metadata: &Metadata) -> (u64, u64) {
+    let mtime_ns = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|mtime| u64::try_from(mtime.as_nanos()).unwrap_or(u64::MAX))
+        .unwrap_or(0);
+    (metadata.len(), mtime_ns)
+}
+
+/// True if the sidecar's identity matches the file's current size and
+/// mtime. Reads only the sidecar header.
+fn is_current(path: &Path, metadata: &Metadata) -> bool {
+    let mut header = [0u8; IDENTITY_SIZE];
+    let Ok(mut file) = std::fs::File::open(sidecar_path(path)) else {
+        return false;
+    };
+    if file.read_exact(&mut header).is_err() || !header.starts_with(MAGIC) {
+        return false;
+    }
+    let mut buf = &header[MAGIC.len()..];
+    buf.get_u32_le() == VERSION && (buf.get_u64_le(), buf.get_u64_le()) == identity(metadata)
+}
+
+/// Load the index of `path` if one exists and is current.
+pub(crate) fn load(path: &Path) -> Option<SpoolIndex> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let sidecar = sidecar_path(path);
+    let bytes = match std::fs::read(&sidecar) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                debug!("Failed to read {}: {err}", sidecar.display());
+            }
+            return None;
+        }
+    };
+    let Some(index) = SpoolIndex::decode(&bytes) else {
+        debug!("Ignoring malformed index {}", sidecar.display());
+        return None;
+    };
+    if (index.file_size, index.file_mtime_ns) != identity(&metadata) {
+        debug!("Ignoring stale index {}", sidecar.display());
+        return None;
+    }
+    Some(index)
+}
+
+/// Atomically write the index of `path` to its sidecar.
+pub(crate) fn store(path: &Path, index: &SpoolIndex) -> std::io::Result<()> {
+    let sidecar = sidecar_path(path);
+    let directory = sidecar
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let mut tmp = tempfile::Builder::new()
+        .prefix(".")
+        .suffix(INDEX_SUFFIX)
+        .tempfile_in(directory)?;
+    std::io::Write::write_all(&mut tmp, &index.encode())?;
+    tmp.persist(&sidecar).map_err(|err| err.error)?;
+    Ok(())
+}
+
+/// The current index of a sealed file, building and storing it if
+/// missing or stale. `None` for unsealed or unindexable files, or
+/// when `live` stopped the build.
+pub(crate) fn load_or_build(path: &Path, live: &mut dyn FnMut() -> bool) -> Option<SpoolIndex> {
+    if let Some(index) = load(path) {
+        return Some(index);
+    }
+    let metadata = std::fs::metadata(path).ok()?;
+    if !is_sealed(&metadata) {
+        return None;
+    }
+    let index = match build(path, live) {
+        Ok(index) => index?,
+        Err(err) => {
+            debug!("Failed to index {}: {err}", path.display());
+            return None;
+        }
+    };
+    if let Err(err) = store(path, &index) {
+        warn!("Failed to write index for {}: {err}", path.display());
+    }
+    Some(index)
+}
+
+/// Build the index of a classic pcap file. `Ok(None)` if the file is
+/// not a classic pcap, is corrupt, or `live` returned false.
+pub(crate) fn build(
+    path: &Path,
+    live: &mut dyn FnMut() -> bool,
+) -> std::io::Result<Option<SpoolIndex>> {
+    let file = std::fs::File::open(path)?;
+    let (file_size, file_mtime_ns) = identity(&file.metadata()?);
+    let mut reader = BufReader::with_capacity(64 * 1024, file);
+
+    let mut header = [0u8; FILE_HEADER_SIZE as usize];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let (big_endian, nanos) = match header[0..4] {
+        [0xd4, 0xc3, 0xb2, 0xa1] => (false, false),
+        [0x4d, 0x3c, 0xb2, 0xa1] => (false, true),
+        [0xa1, 0xb2, 0xc3, 0xd4] => (true, false),
+        [0xa1, 0xb2, 0x3c, 0x4d] => (true, true),
+        _ => return Ok(None),
+    };
+    let read_u32 = |bytes: &[u8]| {
+        let bytes: [u8; 4] = bytes.try_into().unwrap();
+        if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    };
+    // The upper bits carry FCS information; see LT_LINKTYPE in libpcap.
+    let linktype = read_u32(&header[20..24]) & 0x03ff_ffff;
+
+    let mut builder = Builder::new(linktype);
+    let mut offset = FILE_HEADER_SIZE;
+    let mut record = [0u8; RECORD_HEADER_SIZE as usize];
+    let mut data = Vec::with_capacity(SNAP);
+    loop {
+        if builder.packets.is_multiple_of(LIVE_CHECK_PACKETS) && !live() {
+            return Ok(None);
+        }
+        match reader.read_exact(&mut record) {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        }
+        let seconds = u64::from(read_u32(&record[0..4]));
+        let fraction = u64::from(read_u32(&record[4..8]));
+        let caplen = read_u32(&record[8..12]);
+        if caplen > MAX_CAPLEN {
+            debug!(
+                "Not indexing {}: bad record length {caplen} at offset {offset}",
+                path.display()
+            );
+            return Ok(None);
+        }
+        let micros = if nanos { fraction / 1000 } else { fraction };
+        let timestamp = seconds.saturating_mul(1_000_000).saturating_add(micros);
+
+        let wanted = (caplen as usize).min(SNAP);
+        data.resize(wanted, 0);
+        match reader.read_exact(&mut data) {
+            Ok(()) => {}
+            // A truncated final record, as libpcap also stops there.
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        }
+        reader.seek_relative(i64::from(caplen) - wanted as i64)?;
+
+        builder.add(offset, timestamp, &data);
+        offset += RECORD_HEADER_SIZE + u64::from(caplen);
+    }
+    Ok(Some(builder.finish(file_size, file_mtime_ns)))
+}
+
+/// Index every sealed, unindexed file of a spool, and remove sidecars
+/// whose capture is gone (Suricata deletes its own rotated files).
+/// Returns the number of indexes built.
+pub(crate) fn index_spool(
+    spool: &SpoolConfig,
+    live: &mut dyn FnMut() -> bool,
+) -> std::io::Result<usize> {
+    let mut built = 0;
+    for path in spool::walk_files(&spool.directory, spool.prefix.as_deref())? {
+        if !live() {
+            break;
+        }
+        let Ok(metadata) = std::fs::metadata(&path) else {
+            continue;
+        };
+        if !is_sealed(&metadata) || is_current(&path, &metadata) {
+            continue;
+        }
+        match build(&path, live) {
+            Ok(Some(index)) => match store(&path, &index) {
+                Ok(()) => built += 1,
+                Err(err) => warn!("Failed to write index for {}: {err}", path.display()),
+            },
+            Ok(None) => {}
+            Err(err) => debug!("Failed to index {}: {err}", path.display()),
+        }
+    }
+    remove_orphans(&spool.directory)?;
+    Ok(built)
+}
+
+/// Remove sidecars whose capture file no longer exists.
+fn remove_orphans(directory: &Path) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            remove_orphans(&path)?;
+        } else if file_type.is_file()
+            && is_sidecar(&path)
+            && let Some(capture) = path
+                .to_str()
+                .and_then(|name| name.strip_suffix(INDEX_SUFFIX))
+            && !Path::new(capture).exists()
+        {
+            debug!("Removing orphaned index {}", path.display());
+            if let Err(err) = std::fs::remove_file(&path)
+                && err.kind() != std::io::ErrorKind::NotFound
+            {
+                warn!("Failed to remove {}: {err}", path.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Periodically index the spool returned by `current`, if it has
+/// indexing enabled. Runs forever.
+pub(crate) async fn run_indexer(current: impl Fn() -> Option<SpoolConfig>) {
+    let mut interval = tokio::time::interval(INDEXER_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let Some(spool) = current().filter(|spool| spool.index) else {
+            continue;
+        };
+        let started = std::time::Instant::now();
+        let result = tokio::task::spawn_blocking(move || index_spool(&spool, &mut || true)).await;
+        match result {
+            Ok(Ok(0)) => {}
+            Ok(Ok(built)) => info!(
+                "Indexed {built} PCAP spool files in {:?}",
+                started.elapsed()
+            ),
+            Ok(Err(err)) => warn!("Failed to index PCAP spool: {err}"),
+            Err(err) => error!("PCAP spool indexer panicked: {err}"),
+        }
+    }
+}
+
+/// Accumulates a [`SpoolIndex`] one record at a time.
+struct Builder {
+    linktype: u32,
+    packets: u64,
+    first_us: u64,
+    last_us: u64,
+    checkpoints: Vec<Checkpoint>,
+    /// Granularity bucket of the last checkpoint's `max_before`.
+    bucket: Option<u64>,
+    /// Minimum timestamp since the last checkpoint.
+    segment_min: u64,
+    /// `None` once a packet of an unsupported link type was seen.
+    keys: Option<HashSet<u128>>,
+}
+
+impl Builder {
+    fn new(linktype: u32) -> Self {
+        Self {
+            linktype,
+            packets: 0,
+            first_us: u64::MAX,
+            last_us: 0,
+            checkpoints: Vec::new(),
+            bucket: None,
+            segment_min: u64::MAX,
+            keys: link_payload(linktype, &[]).is_some().then(HashSet::new),
+        }
+    }
+
+    fn add(&mut self, offset: u64, timestamp: u64, data: &[u8]) {
+        if self.packets > 0 {
+            let bucket = self.last_us / GRANULARITY_US;
+            if self.bucket.is_none_or(|last| bucket > last) {
+                if let Some(previous) = self.checkpoints.last_mut() {
+                    previous.min_from = self.segment_min;
+                }
+                self.checkpoints.push(Checkpoint {
+                    offset,
+                    max_before: self.last_us,
+                    min_from: 0,
+                });
+                self.bucket = Some(bucket);
+                self.segment_min = u64::MAX;
+            }
+        }
+        self.packets += 1;
+        self.first_us = self.first_us.min(timestamp);
+        self.last_us = self.last_us.max(timestamp);
+        self.segment_min = self.segment_min.min(timestamp);
+        if let Some(keys) = &mut self.keys {
+            packet_keys(self.linktype, data, keys);
+        }
+    }
+
+    fn finish(mut self, file_size: u64, file_mtime_ns: u64) -> SpoolIndex {
+        if let Some(last) = self.checkpoints.last_mut() {
+            last.min_from = self.segment_min;
+        }
+        // Segment minimums to suffix minimums.
+        for i in (0..self.checkpoints.len().saturating_sub(1)).rev() {
+            self.checkpoints[i].min_from = self.checkpoints[i]
+                .min_from
+                .min(self.checkpoints[i + 1].min_from);
+        }
+        if self.packets == 0 {
+            self.first_us = 0;
+        }
+        SpoolIndex {
+            file_size,
+            file_mtime_ns,
+            linktype: self.linktype,
+            packets: self.packets,
+            first_us: self.first_us,
+            last_us: self.last_us,
+            checkpoints: self.checkpoints,
+            bloom: self.keys.as_ref().map(Bloom::with_keys),
+        }
+    }
+}
+
+/// A Bloom filter over 128-bit key hashes, using double hashing.
+#[derive(Debug, Clone, PartialEq)]
+struct Bloom {
+    bits: Vec<u64>,
+    hashes: u32,
+}
+
+impl Bloom {
+    /// Seven hashes at about 9.6 bits per key: a 1% false positive rate.
+    const HASHES: u32 = 7;
+    const BITS_PER_KEY: f64 = 9.6;
+
+    fn with_keys(keys: &HashSet<u128>) -> Self {
+        let bits = (keys.len() as f64 * Self::BITS_PER_KEY).ceil() as usize;
+        let mut bloom = Self {
+            bits: vec![0; bits.div_ceil(64).max(1)],
+            hashes: Self::HASHES,
+        };
+        for key in keys {
+            for bit in bloom.positions(*key) {
+                bloom.bits[bit / 64] |= 1 << (bit % 64);
+            }
+        }
+        bloom
+    }
+
+    fn contains(&self, key: u128) -> bool {
+        self.positions(key)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    fn positions(&self, key: u128) -> impl Iterator<Item = usize> + use<> {
+        let size = (self.bits.len() * 64) as u64;
+        let h1 = key as u64;
+        let h2 = (key >> 64) as u64 | 1;
+        (0..u64::from(self.hashes))
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % size) as usize)
+    }
+}
+
+/// The kinds of flow key a packet contributes.
+#[derive(Clone, Copy)]
+enum Key {
+    /// Protocol and unordered host pair, for every IP packet.
+    Hosts = 1,
+    /// Protocol and unordered (host, port) pair, for packets with
+    /// ports.
+    Ports = 2,
+    /// Protocol and host pair of non-first IPv4 fragments.
+    Fragment = 3,
+}
+
+fn has_ports(proto: u8) -> bool {
+    matches!(proto, IPPROTO_TCP | IPPROTO_UDP | IPPROTO_SCTP)
+}
+
+fn ip_octets(addr: IpAddr) -> [u8; 16] {
+    match addr {
+        IpAddr::V4(addr) => addr.to_ipv6_mapped().octets(),
+        IpAddr::V6(addr) => addr.octets(),
+    }
+}
+
+/// Direction-symmetric hash of a flow key.
+fn flow_key(kind: Key, proto: u8, a: (IpAddr, u16), b: (IpAddr, u16)) -> u128 {
+    let a = (ip_octets(a.0), a.1);
+    let b = (ip_octets(b.0), b.1);
+    let (lo, hi) = if a <= b { (a, b) } else { (b, a) };
+    let mut key = Vec::with_capacity(2 + 2 * 18);
+    key.push(kind as u8);
+    key.push(proto);
+    for (addr, port) in [lo, hi] {
+        key.extend_from_slice(&addr);
+        key.extend_from_slice(&port.to_be_bytes());
+    }
+    (u128::from(fnv1a(0xcbf2_9ce4_8422_2325, &key)) << 64)
+        | u128::from(fnv1a(0x8422_2325_cbf2_9ce4, &key))
+}
+
+fn fnv1a(basis: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(basis, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Insert the flow keys of one packet.
+fn packet_keys(linktype: u32, data: &[u8], keys: &mut HashSet<u128>) {
+    match link_payload(linktype, data).flatten() {
+        Some((4, ip)) => ipv4_keys(ip, keys),
+        Some((6, ip)) => ipv6_keys(ip, keys),
+        _ => {}
+    }
+}
+
+fn ports_at(data: &[u8], offset: usize) -> Option<(u16, u16)> {
+    let ports = data.get(offset..offset + 4)?;
+    Some((
+        u16::from_be_bytes([ports[0], ports[1]]),
+        u16::from_be_bytes([ports[2], ports[3]]),
+    ))
+}
+
+fn ipv4_keys(ip: &[u8], keys: &mut HashSet<u128>) {
+    if ip.len() < 20 {
+        return;
+    }
+    let header_len = usize::from(ip[0] & 0x0f) * 4;
+    let proto = ip[9];
+    let src = IpAddr::from(<[u8; 4]>::try_from(&ip[12..16]).unwrap());
+    let dst = IpAddr::from(<[u8; 4]>::try_from(&ip[16..20]).unwrap());
+    keys.insert(flow_key(Key::Hosts, proto, (src, 0), (dst, 0)));
+    if u16::from_be_bytes([ip[6], ip[7]]) & 0x1fff != 0 {
+        keys.insert(flow_key(Key::Fragment, proto, (src, 0), (dst, 0)));
+    } else if has_ports(proto)
+        && header_len >= 20
+        && let Some((sport, dport)) = ports_at(ip, header_len)
+    {
+        keys.insert(flow_key(Key::Ports, proto, (src, sport), (dst, dport)));
+    }
+}
+
+fn ipv6_keys(ip: &[u8], keys: &mut HashSet<u128>) {
+    if ip.len() < 40 {
+        return;
+    }
+    let src = IpAddr::from(<[u8; 16]>::try_from(&ip[8..24]).unwrap());
+    let dst = IpAddr::from(<[u8; 16]>::try_from(&ip[24..40]).unwrap());
+    // libpcap matches protocols directly after the fixed header or
+    // behind a fragment header; walk the extension headers and key
+    // every next header seen, which covers both.
+    let mut next = ip[6];
+    let mut offset = 40;
+    keys.insert(flow_key(Key::Hosts, next, (src, 0), (dst, 0)));
+    for _ in 0..8 {
+        let Some(header) = ip.get(offset..offset + 2) else {
+            return;
+        };
+        let length = match next {
+            // Hop-by-hop, routing and destination options.
+            0 | 43 | 60 => (usize::from(header[1]) + 1) * 8,
+            // Fragment: continuations carry no ports.
+            44 => {
+                let Some(fragment) = ip.get(offset + 2..offset + 4) else {
+                    return;
+                };
+                if u16::from_be_bytes([fragment[0], fragment[1]]) >> 3 != 0 {
+                    keys.insert(flow_key(Key::Hosts, header[0], (src, 0), (dst, 0)));
+                    return;
+                }
+                8
+            }
+            // Authentication header.
+            51 => (usize::from(header[1]) + 2) * 4,
+            _ => break,
+        };
+        next = header[0];
+        offset += length;
+        keys.insert(flow_key(Key::Hosts, next, (src, 0), (dst, 0)));
+    }
+    if has_ports(next)
+        && let Some((sport, dport)) = ports_at(ip, offset)
+    {
+        keys.insert(flow_key(Key::Ports, next, (src, sport), (dst, dport)));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pcap::testutil::{
+        LINKTYPE_RAW, ipv4_datagram, ipv4_fragment, ipv4_packet, ipv6_packet, ports, vlan_tag,
+        write_pcap_file_bytes,
+    };
+    use crate::util::pcap::{create_header_with_snaplen, create_record_raw};
+    use std::time::SystemTime;
+
+    fn selector(proto: u8, a: (&str, Option<u16>), b: (&str, Option<u16>)) -> FlowSelector {
+        FlowSelector {
+            proto,
+            a: (a.0.parse().unwrap(), a.1),
+            b: (b.0.parse().unwrap(), b.1),
+        }
+    }
+
+    /// Write an Ethernet pcap of UDP packets given as (unix
+    /// microseconds, destination port).
+    fn write_timed(path: &Path, packets: &[(u64, u16)]) {
+        let mut out = create_header_with_snaplen(1, 65_535);
+        for (timestamp, port) in packets {
+            let data = ipv4_packet(17, "10.0.0.1", "10.0.0.2", &ports(1234, *port));
+            out.extend_from_slice(&create_record_raw(
+                (timestamp / 1_000_000) as u32,
+                (timestamp % 1_000_000) as u32,
+                data.len() as u32,
+                &data,
+            ));
+        }
+        std::fs::write(path, out).unwrap();
+    }
+
+    fn build_index(path: &Path) -> SpoolIndex {
+        build(path, &mut || true).unwrap().unwrap()
+    }
+
+    /// Record offsets of the packets written by [`write_timed`].
+    fn record_offset(n: u64) -> u64 {
+        FILE_HEADER_SIZE + n * (RECORD_HEADER_SIZE + 42)
+    }
+
+    #[test]
+    fn time_range_and_offsets() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("log.pcap.1700000000");
+        let base = 1_700_000_000_000_000;
+        // Ten packets a quarter second apart, except one late record
+        // out of order.
+        let mut packets: Vec<(u64, u16)> = (0..10).map(|i| (base + i * 250_000, 53)).collect();
+        packets[4].0 = base + 3_000_000;
+        write_timed(&path, &packets);
+
+        let index = build_index(&path);
+        assert_eq!(index.packets, 10);
+        assert_eq!(index.linktype, 1);
+        assert_eq!(index.first_us, base);
+        assert_eq!(index.last_us, base + 3_000_000);
+        assert!(index.overlaps(base - 1_000_000, base));
+        assert!(!index.overlaps(base + 3_000_001, u64::MAX));
+        assert!(!index.overlaps(0, base - 1));
+
+        // Nothing before the first packet can be skipped.
+        assert_eq!(index.seek_offset(base), FILE_HEADER_SIZE);
+        // Every record before the seek offset is earlier than start,
+        // and nothing at or after start is skipped.
+        for start in [base + 500_000, base + 1_000_000, base + 2_100_000] {
+            let offset = index.seek_offset(start);
+            for (n, (timestamp, _)) in packets.iter().enumerate() {
+                if record_offset(n as u64) < offset {
+                    assert!(*timestamp < start, "start {start} skips record {n}");
+                }
+            }
+            assert!(offset > FILE_HEADER_SIZE);
+        }
+        // The out-of-order record holds the running maximum at 3s, so
+        // no later start can seek past it.
+        assert!(index.seek_offset(base + 2_250_000) <= record_offset(5));
+
+        // Every record from the stop offset on is later than end.
+        for end in [base, base + 600_000, base + 1_000_000] {
+            let stop = index.stop_offset(end).unwrap();
+            for (n, (timestamp, _)) in packets.iter().enumerate() {
+                if record_offset(n as u64) >= stop {
+                    assert!(*timestamp > end, "end {end} stops before record {n}");
+                }
+            }
+        }
+        // The out-of-order record keeps everything after it in play.
+        assert_eq!(index.stop_offset(base + 1_250_000), None);
+    }
+
+    #[test]
+    fn bloom_matches_flow_selectors() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("log.pcap.1700000000");
+        write_pcap_file_bytes(
+            &path,
+            1,
+            &[
+                ipv4_packet(6, "10.0.0.1", "10.0.0.2", &ports(40000, 443)),
+                vlan_tag(
+                    &ipv4_packet(17, "10.0.0.3", "10.0.0.4", &ports(5353, 53)),
+                    100,
+                ),
+                ipv4_fragment(17, "10.0.0.5", "10.0.0.6", 0x00b9, &[0; 8]),
+                ipv6_packet(17, "2001:db8::1", "2001:db8::2", &ports(1000, 2000)),
+                ipv4_packet(1, "10.0.0.7", "10.0.0.8", &[8, 0, 0, 0]),
+            ],
+        );
+        let index = build_index(&path);
+
+        // Either direction of a flow.
+        assert!(index.may_contain(&selector(
+            6,
+            ("10.0.0.2", Some(443)),
+            ("10.0.0.1", Some(40000))
+        )));
+        // Behind a VLAN tag.
+        assert!(index.may_contain(&selector(
+            17,
+            ("10.0.0.3", Some(5353)),
+            ("10.0.0.4", Some(53))
+        )));
+        // A fragment continuation matches any port pair of its hosts.
+        assert!(index.may_contain(&selector(17, ("10.0.0.5", Some(1)), ("10.0.0.6", Some(2)))));
+        assert!(index.may_contain(&selector(
+            17,
+            ("2001:db8::2", Some(2000)),
+            ("2001:db8::1", Some(1000))
+        )));
+        assert!(index.may_contain(&selector(1, ("10.0.0.7", None), ("10.0.0.8", None))));
+        // A single port renders as a hosts-only match.
+        assert!(index.may_contain(&selector(6, ("10.0.0.1", Some(9)), ("10.0.0.2", None))));
+
+        // The mirrored port pairing of a flow never matches.
+        assert!(!index.may_contain(&selector(
+            6,
+            ("10.0.0.1", Some(443)),
+            ("10.0.0.2", Some(40000))
+        )));
+        assert!(!index.may_contain(&selector(
+            17,
+            ("10.0.0.1", Some(40000)),
+            ("10.0.0.2", Some(443))
+        )));
+        assert!(!index.may_contain(&selector(
+            6,
+            ("192.0.2.1", Some(40000)),
+            ("10.0.0.2", Some(443))
+        )));
+        assert!(!index.may_contain(&selector(
+            17,
+            ("2001:db8::1", Some(1000)),
+            ("2001:db8::3", Some(2000))
+        )));
+    }
+
+    #[test]
+    fn unsupported_linktype_has_no_bloom() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("wifi.pcap");
+        write_pcap_file_bytes(&path, 105, &[vec![0; 32]]);
+        let index = build_index(&path);
+        assert_eq!(index.packets, 1);
+        assert!(index.may_contain(&selector(6, ("10.0.0.1", Some(1)), ("10.0.0.2", Some(2)))));
+
+        let raw = tempdir.path().join("raw.pcap");
+        write_pcap_file_bytes(
+            &raw,
+            LINKTYPE_RAW,
+            &[ipv4_datagram(6, "10.0.0.1", "10.0.0.2", &ports(1, 2))],
+        );
+        let index = build_index(&raw);
+        assert!(index.may_contain(&selector(6, ("10.0.0.1", Some(1)), ("10.0.0.2", Some(2)))));
+        assert!(!index.may_contain(&selector(6, ("10.0.0.1", Some(2)), ("10.0.0.2", Some(1)))));
+    }
+
+    #[test]
+    fn not_a_classic_pcap() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("notes.txt");
+        std::fs::write(&path, b"this is not a packet capture file").unwrap();
+        assert!(build(&path, &mut || true).unwrap().is_none());
+
+        // A corrupt record length.
+        let path = tempdir.path().join("corrupt.pcap");
+        let mut out = create_header_with_snaplen(1, 65_535);
+        out.extend_from_slice(&create_record_raw(1_700_000_000, 0, 4, &[0; 4]));
+        out[24 + 8..24 + 12].copy_from_slice(&u32::MAX.to_le_bytes());
+        std::fs::write(&path, out).unwrap();
+        assert!(build(&path, &mut || true).unwrap().is_none());
+
+        // A build stopped by the liveness callback.
+        let path = tempdir.path().join("log.pcap.1700000000");
+        write_timed(&path, &[(1_700_000_000_000_000, 53)]);
+        assert!(build(&path, &mut || false).unwrap().is_none());
+    }
+
+    #[test]
+    fn store_load_and_staleness() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("log.pcap.1700000000");
+        write_timed(&path, &[(1_700_000_000_000_000, 53)]);
+        assert!(load(&path).is_none());
+
+        let index = build_index(&path);
+        assert_eq!(SpoolIndex::decode(&index.encode()), Some(index.clone()));
+        assert_eq!(SpoolIndex::decode(&index.encode()[..40]), None);
+
+        store(&path, &index).unwrap();
+        assert!(sidecar_path(&path).exists());
+        assert_eq!(load(&path), Some(index));
+        assert!(is_current(&path, &std::fs::metadata(&path).unwrap()));
+
+        // Appending to the capture invalidates its index.
+        write_timed(
+            &path,
+            &[(1_700_000_000_000_000, 53), (1_700_000_001_000_000, 53)],
+        );
+        assert!(load(&path).is_none());
+        assert!(!is_current(&path, &std::fs::metadata(&path).unwrap()));
+    }
+
+    #[test]
+    fn only_sealed_files_are_indexed() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let sealed = tempdir.path().join("log.pcap.1700000000");
+        let active = tempdir.path().join("log.pcap.1700000060");
+        write_timed(&sealed, &[(1_700_000_000_000_000, 53)]);
+        write_timed(&active, &[(1_700_000_060_000_000, 53)]);
+        std::fs::File::options()
+            .write(true)
+            .open(&sealed)
+            .unwrap()
+            .set_modified(SystemTime::now() - SEALED_AGE * 2)
+            .unwrap();
+
+        assert!(load_or_build(&active, &mut || true).is_none());
+        assert!(load_or_build(&sealed, &mut || true).is_some());
+        assert!(sidecar_path(&sealed).exists());
+        assert!(!sidecar_path(&active).exists());
+
+        // The indexer skips current indexes and removes orphans.
+        let mut spool = SpoolConfig::new(tempdir.path(), Some("log.pcap".to_string()));
+        spool.index = true;
+        assert_eq!(index_spool(&spool, &mut || true).unwrap(), 0);
+        std::fs::remove_file(&sealed).unwrap();
+        index_spool(&spool, &mut || true).unwrap();
+        assert!(!sidecar_path(&sealed).exists());
+    }
+}
@@ -15,7 +15,11 @@
 #[cfg(not(windows))]
 mod fetch;
 mod filter;
+#[cfg(not(windows))]
+pub(crate) mod index;
+pub(crate) mod reassembly;
 mod request;
+pub(crate) mod retention;
 #[cfg(not(windows))]
 mod spool;
 mod timeframe;
@@ -27,10 +31,16 @@ pub(crate) mod testutil;
 
 #[cfg(not(windows))]
 pub(crate) use fetch::{FetchError, fetch};
-pub(crate) use filter::FlowSelector;
-pub(crate) use request::{FetchStats, Limits, PcapFilter, PcapRequest, PcapSource, SpoolConfig};
+pub(crate) use filter::{
+    FieldDirection, FilterFields, FlowSelector, has_ports, parse_net, parse_port_range,
+};
+pub(crate) use request::{
+    FetchProgress, FetchStats, Limits, OutputFormat, PcapFilter, PcapRequest, PcapSource,
+    SpoolConfig, SpoolUsage,
+};
 #[cfg(not(windows))]
 pub(crate) use spool::walk_files;
 pub(crate) use timeframe::{
-    Window, derive_window, selector_from_event, window_around_event, window_from_start,
+    Window, derive_window, normalize_proto, selector_from_event, window_around_event,
+    window_from_start,
 };
@@ -0,0 +1,813 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! TCP stream reassembly of an extracted capture.
+//!
+//! Reads a classic pcap as written by the extraction engine and rebuilds
+//! both directions of each TCP connection in it in sequence order. Bytes
+//! carried more than once are taken from the segment starting earliest
+//! in the sequence, the first captured of equals; bytes never seen are
+//! recorded as gaps rather than filled. An HTTP/1 conversation can be
+//! further split into its requests and responses.
+
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+
+use crate::util::pcap::{FILE_HEADER_LEN, PCAP_RECORD_HEADER_SIZE};
+
+const IPPROTO_TCP: u8 = 6;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_ACK: u8 = 0x10;
+
+/// The most connections reassembled from one capture; later ones are
+/// only counted.
+pub(crate) const MAX_CONNECTIONS: usize = 16;
+
+/// The largest HTTP header block recognized.
+const MAX_HTTP_HEADER: usize = 64 * 1024;
+
+pub(crate) type Endpoint = (IpAddr, u16);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Direction {
+    ToServer,
+    ToClient,
+}
+
+/// One direction of a connection.
+#[derive(Debug, Default)]
+pub(crate) struct Stream {
+    /// The reassembled bytes. Missing bytes are left out, see `gaps`.
+    pub(crate) data: Vec<u8>,
+    pub(crate) packets: u64,
+    /// Where bytes are missing: the offset in `data` and how many bytes
+    /// were never seen before it.
+    pub(crate) gaps: Vec<(usize, u64)>,
+    /// Bytes carried again by retransmitted or overlapping segments.
+    pub(crate) retransmitted: u64,
+    pub(crate) fin: bool,
+    pub(crate) rst: bool,
+}
+
+/// A run of stream bytes as first carried on the wire, for reading the
+/// two directions interleaved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Chunk {
+    pub(crate) direction: Direction,
+    /// Unix microseconds.
+    pub(crate) timestamp: u64,
+    /// The offset in the direction's stream data.
+    pub(crate) offset: usize,
+    pub(crate) len: usize,
+}
+
+#[derive(Debug)]
+pub(crate) struct Connection {
+    pub(crate) client: Endpoint,
+    pub(crate) server: Endpoint,
+    /// False when the handshake was not captured and the client is only
+    /// the first endpoint seen sending.
+    pub(crate) handshake: bool,
+    /// Unix microseconds.
+    pub(crate) first_seen: u64,
+    pub(crate) last_seen: u64,
+    pub(crate) to_server: Stream,
+    pub(crate) to_client: Stream,
+    /// The data of both directions in capture order.
+    pub(crate) chunks: Vec<Chunk>,
+}
+
+impl Connection {
+    pub(crate) fn stream(&self, direction: Direction) -> &Stream {
+        match direction {
+            Direction::ToServer => &self.to_server,
+            Direction::ToClient => &self.to_client,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Reassembly {
+    /// TCP packets read.
+    pub(crate) packets: u64,
+    pub(crate) connections: Vec<Connection>,
+    /// Connections beyond [`MAX_CONNECTIONS`], not reassembled.
+    pub(crate) omitted: usize,
+}
+
+/// A TCP segment as decoded from a packet.
+struct Segment<'a> {
+    src: Endpoint,
+    dst: Endpoint,
+    seq: u32,
+    flags: u8,
+    payload: &'a [u8],
+}
+
+/// A segment's data, held until the connection is assembled.
+struct Pending<'a> {
+    timestamp: u64,
+    seq: u32,
+    payload: &'a [u8],
+}
+
+#[derive(Default)]
+struct Side<'a> {
+    /// The initial sequence number, when the SYN was seen.
+    isn: Option<u32>,
+    segments: Vec<Pending<'a>>,
+    packets: u64,
+    fin: bool,
+    rst: bool,
+}
+
+/// A connection being collected; sides are indexed by the endpoint
+/// that sent them, the first endpoint seen being 0.
+struct Builder<'a> {
+    endpoints: [Endpoint; 2],
+    client: Option<usize>,
+    sides: [Side<'a>; 2],
+    first_seen: u64,
+    last_seen: u64,
+}
+
+impl<'a> Builder<'a> {
+    fn new(segment: &Segment, timestamp: u64) -> Self {
+        Self {
+            endpoints: [segment.src, segment.dst],
+            client: None,
+            sides: Default::default(),
+            first_seen: timestamp,
+            last_seen: timestamp,
+        }
+    }
+
+    fn side(&self, src: Endpoint) -> usize {
+        usize::from(self.endpoints[0] != src)
+    }
+
+    /// True if a SYN from `src` opens a new connection on this one's
+    /// addresses rather than repeating its own.
+    fn reused_by(&self, segment: &Segment) -> bool {
+        let side = &self.sides[self.side(segment.src)];
+        match side.isn {
+            Some(isn) => isn != segment.seq,
+            None => !side.segments.is_empty() || side.fin || side.rst,
+        }
+    }
+
+    fn add(&mut self, segment: Segment<'a>, timestamp: u64) {
+        let index = self.side(segment.src);
+        self.last_seen = self.last_seen.max(timestamp);
+        let mut seq = segment.seq;
+        if segment.flags & TCP_SYN != 0 {
+            if self.client.is_none() {
+                self.client = Some(if segment.flags & TCP_ACK == 0 {
+                    index
+                } else {
+                    1 - index
+                });
+            }
+            self.sides[index].isn.get_or_insert(segment.seq);
+            seq = seq.wrapping_add(1);
+        }
+        let side = &mut self.sides[index];
+        side.packets += 1;
+        side.fin |= segment.flags & TCP_FIN != 0;
+        side.rst |= segment.flags & TCP_RST != 0;
+        if !segment.payload.is_empty() {
+            side.segments.push(Pending {
+                timestamp,
+                seq,
+                payload: segment.payload,
+            });
+        }
+    }
+
+    fn finish(self) -> Connection {
+        let client = self.client.unwrap_or(0);
+        let [first, second] = self.sides;
+        let (client_side, server_side) = if client == 0 {
+            (first, second)
+        } else {
+            (second, first)
+        };
+        let mut chunks = Vec::new();
+        let to_server = assemble(client_side, Direction::ToServer, &mut chunks);
+        let to_client = assemble(server_side, Direction::ToClient, &mut chunks);
+        chunks.sort_by_key(|chunk| chunk.timestamp);
+        Connection {
+            client: self.endpoints[client],
+            server: self.endpoints[1 - client],
+            handshake: self.client.is_some(),
+            first_seen: self.first_seen,
+            last_seen: self.last_seen,
+            to_server,
+            to_client,
+            chunks,
+        }
+    }
+}
+
+/// Put one direction's segments in sequence order. The stream starts
+/// after the SYN when it was seen, otherwise at the lowest sequence
+/// number carried.
+fn assemble(side: Side, direction: Direction, chunks: &mut Vec<Chunk>) -> Stream {
+    let mut stream = Stream {
+        packets: side.packets,
+        fin: side.fin,
+        rst: side.rst,
+        ..Default::default()
+    };
+    let Some(first) = side.segments.first().map(|segment| segment.seq) else {
+        return stream;
+    };
+    let base = match side.isn {
+        Some(isn) => isn.wrapping_add(1),
+        None => side
+            .segments
+            .iter()
+            .map(|segment| segment.seq)
+            .min_by_key(|seq| seq.wrapping_sub(first) as i32)
+            .unwrap_or(first),
+    };
+    let offset = |segment: &Pending| i64::from(segment.seq.wrapping_sub(base) as i32);
+
+    let mut segments = side.segments;
+    // Stable, so of two segments at the same offset the first captured
+    // is used.
+    segments.sort_by_key(|segment| offset(segment));
+    let mut cursor: i64 = 0;
+    for segment in &segments {
+        let start = offset(segment);
+        let end = start + segment.payload.len() as i64;
+        if end <= cursor {
+            stream.retransmitted += segment.payload.len() as u64;
+            continue;
+        }
+        if start > cursor {
+            stream
+                .gaps
+                .push((stream.data.len(), (start - cursor) as u64));
+            cursor = start;
+        }
+        let skip = (cursor - start) as usize;
+        stream.retransmitted += skip as u64;
+        chunks.push(Chunk {
+            direction,
+            timestamp: segment.timestamp,
+            offset: stream.data.len(),
+            len: segment.payload.len() - skip,
+        });
+        stream.data.extend_from_slice(&segment.payload[skip..]);
+        cursor = end;
+    }
+    stream
+}
+
+/// Reassemble the TCP connections of a classic little-endian pcap.
+pub(crate) fn reassemble(capture: &[u8]) -> Result<Reassembly, String> {
+    let mut reassembly = Reassembly::default();
+    if capture.is_empty() {
+        return Ok(reassembly);
+    }
+    if capture.len() < FILE_HEADER_LEN || capture[0..4] != 0xa1b2_c3d4u32.to_le_bytes() {
+        return Err("unexpected capture format".to_string());
+    }
+    let linktype = u32::from_le_bytes(capture[20..24].try_into().unwrap());
+
+    let mut builders: Vec<Builder> = Vec::new();
+    // The latest connection on each address pair.
+    let mut current: HashMap<(Endpoint, Endpoint), usize> = HashMap::new();
+    let mut omitted: HashSet<(Endpoint, Endpoint)> = HashSet::new();
+    let mut offset = FILE_HEADER_LEN;
+    while offset < capture.len() {
+        let record = &capture[offset..];
+        if record.len() < PCAP_RECORD_HEADER_SIZE {
+            return Err("truncated capture record".to_string());
+        }
+        let field = |at: usize| u32::from_le_bytes(record[at..at + 4].try_into().unwrap());
+        let len = PCAP_RECORD_HEADER_SIZE + field(8) as usize;
+        if record.len() < len {
+            return Err("truncated capture record".to_string());
+        }
+        let timestamp = u64::from(field(0)) * 1_000_000 + u64::from(field(4));
+        offset += len;
+
+        let Some(segment) = tcp_segment(linktype, &record[PCAP_RECORD_HEADER_SIZE..len]) else {
+            continue;
+        };
+        reassembly.packets += 1;
+        let key = if segment.src <= segment.dst {
+            (segment.src, segment.dst)
+        } else {
+            (segment.dst, segment.src)
+        };
+        let opens = segment.flags & (TCP_SYN | TCP_ACK) == TCP_SYN;
+        let index = match current.get(&key) {
+            Some(&index) if !(opens && builders[index].reused_by(&segment)) => index,
+            _ => {
+                if builders.len() == MAX_CONNECTIONS {
+                    omitted.insert(key);
+                    continue;
+                }
+                builders.push(Builder::new(&segment, timestamp));
+                current.insert(key, builders.len() - 1);
+                omitted.remove(&key);
+                builders.len() - 1
+            }
+        };
+        builders[index].add(segment, timestamp);
+    }
+    reassembly.omitted = omitted.len();
+    reassembly.connections = builders.into_iter().map(Builder::finish).collect();
+    Ok(reassembly)
+}
+
+/// Decode the TCP segment of a packet. Fragments and packets without
+/// a complete TCP header are skipped.
+fn tcp_segment(linktype: u32, data: &[u8]) -> Option<Segment<'_>> {
+    let (src, dst, tcp) = match link_payload(linktype, data).flatten()? {
+        (4, ip) => {
+            if ip.len() < 20 || ip[9] != IPPROTO_TCP {
+                return None;
+            }
+            // More fragments or a fragment offset.
+            if u16::from_be_bytes([ip[6], ip[7]]) & 0x3fff != 0 {
+                return None;
+            }
+            let header_len = usize::from(ip[0] & 0x0f) * 4;
+            // Drop link layer padding.
+            let total = usize::from(u16::from_be_bytes([ip[2], ip[3]])).min(ip.len());
+            let src = IpAddr::from(<[u8; 4]>::try_from(&ip[12..16]).unwrap());
+            let dst = IpAddr::from(<[u8; 4]>::try_from(&ip[16..20]).unwrap());
+            (src, dst, ip.get(header_len.max(20)..total)?)
+        }
+        (6, ip) => {
+            if ip.len() < 40 {
+                return None;
+            }
+            let total = (40 + usize::from(u16::from_be_bytes([ip[4], ip[5]]))).min(ip.len());
+            let src = IpAddr::from(<[u8; 16]>::try_from(&ip[8..24]).unwrap());
+            let dst = IpAddr::from(<[u8; 16]>::try_from(&ip[24..40]).unwrap());
+            let mut next = ip[6];
+            let mut offset = 40;
+            // Hop-by-hop, routing and destination options.
+            while matches!(next, 0 | 43 | 60) {
+                let header = ip.get(offset..offset + 2)?;
+                next = header[0];
+                offset += (usize::from(header[1]) + 1) * 8;
+            }
+            if next != IPPROTO_TCP {
+                return None;
+            }
+            (src, dst, ip.get(offset..total)?)
+        }
+        _ => return None,
+    };
+    if tcp.len() < 20 {
+        return None;
+    }
+    let header_len = usize::from(tcp[12] >> 4) * 4;
+    Some(Segment {
+        src: (src, u16::from_be_bytes([tcp[0], tcp[1]])),
+        dst: (dst, u16::from_be_bytes([tcp[2], tcp[3]])),
+        seq: u32::from_be_bytes(tcp[4..8].try_into().unwrap()),
+        flags: tcp[13],
+        payload: tcp.get(header_len.max(20)..)?,
+    })
+}
+
+/// The IP version and network layer of a packet, for the link types
+/// the indexer and reassembly understand. `Some(None)` for supported link types
+/// carrying something other than IP; `None` for unsupported link
+/// types.
+pub(super) fn link_payload(linktype: u32, data: &[u8]) -> Option<Option<(u8, &[u8])>> {
+    fn by_version(payload: &[u8]) -> Option<(u8, &[u8])> {
+        match payload.first().map(|byte| byte >> 4) {
+            Some(version @ (4 | 6)) => Some((version, payload)),
+            _ => None,
+        }
+    }
+    fn by_ethertype(ethertype: u16, payload: &[u8]) -> Option<(u8, &[u8])> {
+        match ethertype {
+            0x0800 => Some((4, payload)),
+            0x86dd => Some((6, payload)),
+            _ => None,
+        }
+    }
+    let be16 = |offset: usize| {
+        data.get(offset..offset + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    };
+    Some(match linktype {
+        // Null and loopback: a 4 byte address family of either byte
+        // order, so go by the IP version instead.
+        0 | 108 => data.get(4..).and_then(by_version),
+        // Ethernet, behind up to two VLAN tags.
+        1 => {
+            let mut offset = 12;
+            let mut ethertype = be16(offset);
+            for _ in 0..2 {
+                if !matches!(ethertype, Some(0x8100 | 0x88a8 | 0x9100)) {
+                    break;
+                }
+                offset += 4;
+                ethertype = be16(offset);
+            }
+            ethertype.and_then(|ethertype| by_ethertype(ethertype, data.get(offset + 2..)?))
+        }
+        // Raw IP.
+        12 | 14 | 101 => by_version(data),
+        // Linux cooked capture v1 and v2.
+        113 => be16(14).and_then(|ethertype| by_ethertype(ethertype, data.get(16..)?)),
+        276 => be16(0).and_then(|ethertype| by_ethertype(ethertype, data.get(20..)?)),
+        _ => return None,
+    })
+}
+
+/// An HTTP/1 request or response found in a stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct HttpMessage {
+    /// The offset of the message in its stream data.
+    pub(crate) offset: usize,
+    pub(crate) start_line: String,
+    pub(crate) headers: Vec<(String, String)>,
+    /// The offset and length of the body in the stream data, as sent:
+    /// a chunked body keeps its framing.
+    pub(crate) body: (usize, usize),
+    /// False when the stream ends before the body does.
+    pub(crate) complete: bool,
+}
+
+impl HttpMessage {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn chunked(&self) -> bool {
+        self.header("transfer-encoding")
+            .is_some_and(|value| value.to_ascii_lowercase().contains("chunked"))
+    }
+
+    fn content_length(&self) -> Option<usize> {
+        self.header("content-length")
+            .and_then(|value| value.trim().parse().ok())
+    }
+
+    /// The request method, or the response status code.
+    fn first_token(&self) -> &str {
+        let mut tokens = self.start_line.split(' ');
+        match self.start_line.starts_with("HTTP/") {
+            true => tokens.nth(1).unwrap_or_default(),
+            false => tokens.next().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct HttpExchange {
+    pub(crate) request: HttpMessage,
+    /// The final response, if captured; interim 1xx responses are
+    /// skipped.
+    pub(crate) response: Option<HttpMessage>,
+}
+
+/// Split a connection into HTTP/1 requests and responses, or `None` if
+/// the client's stream does not start with a request.
+pub(crate) fn http_exchanges(connection: &Connection) -> Option<Vec<HttpExchange>> {
+    let requests = &connection.to_server.data;
+    let responses = &connection.to_client.data;
+
+    let mut exchanges: Vec<HttpExchange> = Vec::new();
+    let mut offset = 0;
+    while offset < requests.len() {
+        let Some(request) = http_message(requests, offset, None) else {
+            break;
+        };
+        offset = request.body.0 + request.body.1;
+        exchanges.push(HttpExchange {
+            request,
+            response: None,
+        });
+    }
+    if exchanges.is_empty() {
+        return None;
+    }
+
+    let mut offset = 0;
+    let mut next = 0;
+    while next < exchanges.len() && offset < responses.len() {
+        let method = exchanges[next].request.first_token().to_string();
+        let Some(response) = http_message(responses, offset, Some(&method)) else {
+            break;
+        };
+        offset = response.body.0 + response.body.1;
+        let status = response.first_token().to_string();
+        if status.starts_with('1') && status != "101" {
+            continue;
+        }
+        exchanges[next].response = Some(response);
+        next += 1;
+        // The connection is no longer HTTP after a protocol switch.
+        if status == "101" {
+            break;
+        }
+    }
+    Some(exchanges)
+}
+
+/// Parse the message at `offset`: a request when `method` is `None`,
+/// otherwise a response to a request with that method.
+fn http_message(data: &[u8], offset: usize, method: Option<&str>) -> Option<HttpMessage> {
+    let rest = &data[offset..];
+    let window = &rest[..rest.len().min(MAX_HTTP_HEADER)];
+    let head_len = window.windows(4).position(|bytes| bytes == b"\r\n\r\n")? + 4;
+    let head = String::from_utf8_lossy(&rest[..head_len - 4]);
+    let mut lines = head.split("\r\n");
+    let start_line = lines.next()?.to_string();
+    let valid = match method {
+        None => {
+            let mut tokens = start_line.split(' ');
+            let verb = tokens.next()?;
+            !verb.is_empty()
+                && verb.bytes().all(|byte| byte.is_ascii_uppercase())
+                && tokens.next().is_some_and(|target| !target.is_empty())
+                && tokens
+                    .next()
+                    .is_some_and(|version| version.starts_with("HTTP/1."))
+                && tokens.next().is_none()
+        }
+        Some(_) => {
+            start_line.starts_with("HTTP/1.")
+                && start_line.split(' ').nth(1).is_some_and(|status| {
+                    status.len() == 3 && status.bytes().all(|byte| byte.is_ascii_digit())
+                })
+        }
+    };
+    if !valid {
+        return None;
+    }
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+
+    let mut message = HttpMessage {
+        offset,
+        start_line,
+        headers,
+        body: (offset + head_len, 0),
+        complete: true,
+    };
+    let body_start = offset + head_len;
+    let available = data.len() - body_start;
+    let bodyless = match method {
+        None => false,
+        Some(method) => {
+            let status = message.first_token();
+            method == "HEAD" || status.starts_with('1') || status == "204" || status == "304"
+        }
+    };
+    let declared = if bodyless {
+        Some(0)
+    } else if message.chunked() {
+        chunked_len(&data[body_start..])
+    } else if let Some(length) = message.content_length() {
+        Some(length)
+    } else if method.is_none() {
+        Some(0)
+    } else {
+        // A response without a length runs to the end of the
+        // connection.
+        Some(available)
+    };
+    match declared {
+        Some(length) if length <= available => message.body.1 = length,
+        _ => {
+            message.body.1 = available;
+            message.complete = false;
+        }
+    }
+    Some(message)
+}
+
+/// The length of a chunked body including its framing and trailers, or
+/// `None` if it is cut short.
+fn chunked_len(data: &[u8]) -> Option<usize> {
+    let line_end = |from: usize| {
+        data.get(from..)?
+            .windows(2)
+            .position(|bytes| bytes == b"\r\n")
+            .map(|at| from + at)
+    };
+    let mut offset = 0;
+    loop {
+        let end = line_end(offset)?;
+        let line = std::str::from_utf8(&data[offset..end]).ok()?;
+        let size = line.split(';').next()?.trim();
+        let size = usize::from_str_radix(size, 16).ok()?;
+        offset = end + 2;
+        if size == 0 {
+            // Trailers, up to an empty line.
+            loop {
+                let end = line_end(offset)?;
+                let empty = end == offset;
+                offset = end + 2;
+                if empty {
+                    return Some(offset);
+                }
+            }
+        }
+        offset = offset.checked_add(size)?.checked_add(2)?;
+        if offset > data.len() {
+            return None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::pcap::{create_header_with_snaplen, create_record_raw};
+
+    const CLIENT: &str = "10.0.0.1";
+    const SERVER: &str = "10.0.0.2";
+
+    /// An Ethernet/IPv4/TCP packet.
+    fn tcp(src: &str, dst: &str, ports: (u16, u16), seq: u32, flags: u8, data: &[u8]) -> Vec<u8> {
+        let mut segment = vec![0; 20];
+        segment[0..2].copy_from_slice(&ports.0.to_be_bytes());
+        segment[2..4].copy_from_slice(&ports.1.to_be_bytes());
+        segment[4..8].copy_from_slice(&seq.to_be_bytes());
+        segment[12] = 5 << 4;
+        segment[13] = flags;
+        segment.extend_from_slice(data);
+
+        let mut packet = vec![0; 14];
+        packet[12..14].copy_from_slice(&0x0800_u16.to_be_bytes());
+        let mut ip = vec![0; 20];
+        ip[0] = 0x45;
+        ip[2..4].copy_from_slice(&((20 + segment.len()) as u16).to_be_bytes());
+        ip[8] = 64;
+        ip[9] = IPPROTO_TCP;
+        ip[12..16].copy_from_slice(&src.parse::<std::net::Ipv4Addr>().unwrap().octets());
+        ip[16..20].copy_from_slice(&dst.parse::<std::net::Ipv4Addr>().unwrap().octets());
+        packet.extend_from_slice(&ip);
+        packet.extend_from_slice(&segment);
+        packet
+    }
+
+    fn to_server(seq: u32, flags: u8, data: &[u8]) -> Vec<u8> {
+        tcp(CLIENT, SERVER, (40000, 80), seq, flags, data)
+    }
+
+    fn to_client(seq: u32, flags: u8, data: &[u8]) -> Vec<u8> {
+        tcp(SERVER, CLIENT, (80, 40000), seq, flags, data)
+    }
+
+    fn capture(packets: &[Vec<u8>]) -> Vec<u8> {
+        let mut out = create_header_with_snaplen(1, 65_535);
+        for (i, packet) in packets.iter().enumerate() {
+            out.extend_from_slice(&create_record_raw(
+                1_700_000_000 + i as u32,
+                0,
+                packet.len() as u32,
+                packet,
+            ));
+        }
+        out
+    }
+
+    #[test]
+    fn streams_are_put_in_sequence_order() {
+        let reassembly = reassemble(&capture(&[
+            to_server(100, TCP_SYN, b""),
+            to_client(500, TCP_SYN | TCP_ACK, b""),
+            to_server(101, TCP_ACK, b"hello "),
+            // Out of order, then a retransmission overlapping both.
+            to_server(112, TCP_ACK, b"!"),
+            to_server(107, TCP_ACK, b"world"),
+            to_server(105, TCP_ACK, b"o world!"),
+            to_client(501, TCP_ACK, b"hi"),
+            to_server(113, TCP_ACK | TCP_FIN, b""),
+        ]))
+        .unwrap();
+        assert_eq!(reassembly.packets, 8);
+        assert_eq!(reassembly.connections.len(), 1);
+        let connection = &reassembly.connections[0];
+        assert!(connection.handshake);
+        assert_eq!(connection.client, (CLIENT.parse().unwrap(), 40000));
+        assert_eq!(connection.to_server.data, b"hello world!");
+        assert_eq!(connection.to_server.retransmitted, 8);
+        assert!(connection.to_server.gaps.is_empty());
+        assert!(connection.to_server.fin);
+        assert_eq!(connection.to_client.data, b"hi");
+        assert_eq!(
+            connection
+                .chunks
+                .iter()
+                .map(|chunk| (chunk.direction, chunk.offset, chunk.len))
+                .collect::<Vec<_>>(),
+            vec![
+                (Direction::ToServer, 0, 6),
+                (Direction::ToServer, 6, 6),
+                (Direction::ToClient, 0, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_bytes_are_gaps() {
+        // No handshake: the server's packet comes first, and the
+        // stream starts at the lowest sequence number seen.
+        let reassembly = reassemble(&capture(&[
+            to_client(9000, TCP_ACK, b"pong"),
+            to_server(1010, TCP_ACK, b"def"),
+            to_server(1000, TCP_ACK, b"abc"),
+        ]))
+        .unwrap();
+        let connection = &reassembly.connections[0];
+        assert!(!connection.handshake);
+        assert_eq!(connection.client, (SERVER.parse().unwrap(), 80));
+        assert_eq!(connection.to_client.data, b"abcdef");
+        assert_eq!(connection.to_client.gaps, vec![(3, 7)]);
+    }
+
+    #[test]
+    fn a_new_syn_starts_a_new_connection() {
+        let reassembly = reassemble(&capture(&[
+            to_server(100, TCP_SYN, b""),
+            to_server(101, TCP_ACK, b"first"),
+            to_server(7000, TCP_SYN, b""),
+            to_server(7001, TCP_ACK, b"second"),
+        ]))
+        .unwrap();
+        assert_eq!(reassembly.connections.len(), 2);
+        assert_eq!(reassembly.connections[0].to_server.data, b"first");
+        assert_eq!(reassembly.connections[1].to_server.data, b"second");
+    }
+
+    #[test]
+    fn http_is_split_into_exchanges() {
+        let requests = concat!(
+            "GET /index.html HTTP/1.1\r\nHost: example.com\r\n\r\n",
+            "POST /form HTTP/1.1\r\nContent-Length: 3\r\n\r\na=b",
+            "HEAD /index.html HTTP/1.1\r\n\r\n",
+        );
+        let responses = concat!(
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello",
+            "HTTP/1.1 100 Continue\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nok\r\n0\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n",
+        );
+        let reassembly = reassemble(&capture(&[
+            to_server(100, TCP_SYN, b""),
+            to_client(500, TCP_SYN | TCP_ACK, b""),
+            to_server(101, TCP_ACK, requests.as_bytes()),
+            to_client(501, TCP_ACK, responses.as_bytes()),
+        ]))
+        .unwrap();
+        let connection = &reassembly.connections[0];
+        let exchanges = http_exchanges(connection).unwrap();
+        assert_eq!(exchanges.len(), 3);
+
+        let get = &exchanges[0];
+        assert_eq!(get.request.start_line, "GET /index.html HTTP/1.1");
+        assert_eq!(
+            get.request.headers,
+            vec![("Host".to_string(), "example.com".to_string())]
+        );
+        let response = get.response.as_ref().unwrap();
+        let (offset, len) = response.body;
+        assert_eq!(&connection.to_client.data[offset..offset + len], b"hello");
+
+        let post = &exchanges[1];
+        let (offset, len) = post.request.body;
+        assert_eq!(&connection.to_server.data[offset..offset + len], b"a=b");
+        let response = post.response.as_ref().unwrap();
+        assert_eq!(response.body.1, "2\r\nok\r\n0\r\n\r\n".len());
+
+        let head = exchanges[2].response.as_ref().unwrap();
+        assert_eq!(head.body.1, 0);
+        assert!(head.complete);
+    }
+
+    #[test]
+    fn other_protocols_are_not_http() {
+        let reassembly = reassemble(&capture(&[to_server(
+            100,
+            TCP_ACK,
+            b"\x16\x03\x01\x00\x05hello",
+        )]))
+        .unwrap();
+        assert!(http_exchanges(&reassembly.connections[0]).is_none());
+        assert!(reassemble(b"not a capture").is_err());
+    }
+}
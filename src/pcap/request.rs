@@ -8,13 +8,15 @@
 //! libpcap-backed [`super::fetch`] entry point is compiled out.
 
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::time::Duration;
 
-use super::filter::FlowSelector;
+use super::filter::{FilterFields, FlowSelector};
 
 /// A directory of PCAP spool files to extract packets from.
 #[cfg_attr(windows, allow(dead_code))]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub(crate) struct SpoolConfig {
     pub(crate) directory: PathBuf,
     /// If set, only files beginning with the prefix will be considered.
@@ -23,6 +25,9 @@ pub(crate) struct SpoolConfig {
     /// candidate files (not the per-packet gate). The CLI uses ZERO to
     /// preserve its historical behavior; the server will use 60s.
     pub(crate) margin: Duration,
+    /// Use, and lazily build, sidecar indexes of sealed spool files to
+    /// seek to the time window and skip files without the flow.
+    pub(crate) index: bool,
 }
 
 impl SpoolConfig {
@@ -31,6 +36,7 @@ impl SpoolConfig {
             directory: directory.into(),
             prefix,
             margin: Duration::from_secs(60),
+            index: false,
         }
     }
 }
@@ -90,6 +96,11 @@ pub(crate) enum PcapFilter {
     /// cancellation and the deadline are observed with per-packet
     /// granularity.
     Flow(FlowSelector),
+    /// Structured fields, rendered to BPF once. Without a VLAN the
+    /// rendering is applied like a `Flow`'s, VLAN-wrapped where the
+    /// link type allows; with one it is compiled like an `Expression`,
+    /// skipping files whose link type has no VLAN support.
+    Fields(FilterFields),
 }
 
 /// Statistics from a completed or partially-completed fetch.
@@ -98,12 +109,16 @@ pub(crate) enum PcapFilter {
 pub(crate) struct FetchStats {
     /// Number of packets written to the output.
     pub(crate) packets: u64,
-    /// Bytes written to the output, including the pcap file header.
+    /// Bytes written to the output, including the pcap file header (or
+    /// pcapng section and interface blocks).
     pub(crate) bytes: u64,
     /// Files successfully opened.
     pub(crate) files_scanned: u32,
     /// Files that failed to open or read (rotation race).
     pub(crate) files_vanished: u32,
+    /// Files skipped without being read because their index showed
+    /// they hold no packets of the time window or flow.
+    pub(crate) files_skipped: u32,
     /// The fetch was stopped by max_bytes or the deadline.
     pub(crate) truncated: bool,
     /// Raw link type of the first opened file, if any. Kept as the
@@ -111,6 +126,37 @@ pub(crate) struct FetchStats {
     pub(crate) linktype: Option<i32>,
 }
 
+/// The size and age of a spool, as last seen by retention.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct SpoolUsage {
+    /// Capture files in the spool.
+    pub(crate) files: u64,
+    /// Total size of the capture files.
+    pub(crate) bytes: u64,
+    /// Unix microseconds of the oldest packet, approximated from the
+    /// oldest file's index or rotation timestamp. `None` for an empty
+    /// spool.
+    pub(crate) oldest_us: Option<u64>,
+}
+
+/// The file format a fetch writes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) enum OutputFormat {
+    /// Classic pcap. Files whose link type differs from the first
+    /// written packet are skipped.
+    #[default]
+    Pcap,
+    /// pcapng, with an interface description block per link type so
+    /// captures of mixed link types share one output file.
+    Pcapng {
+        /// Section header comment, typically naming the source.
+        section_comment: Option<String>,
+        /// Comment attached to every packet, typically linking it back
+        /// to the EVE event it was extracted for.
+        packet_comment: Option<String>,
+    },
+}
+
 /// A request for packets from a PCAP spool.
 #[derive(Debug, Default)]
 pub(crate) struct PcapRequest {
@@ -121,4 +167,40 @@ pub(crate) struct PcapRequest {
     /// Inclusive end of the time window, unix microseconds.
     pub(crate) end: Option<u64>,
     pub(crate) limits: Limits,
+    pub(crate) format: OutputFormat,
+    /// Live counters for a caller polling a long fetch.
+    pub(crate) progress: Option<Arc<FetchProgress>>,
+}
+
+impl PcapRequest {
+    /// Publish the statistics so far to the progress counters, if any.
+    #[cfg_attr(windows, allow(dead_code))]
+    pub(crate) fn report(&self, stats: &FetchStats) {
+        if let Some(progress) = &self.progress {
+            progress.update(stats);
+        }
+    }
+}
+
+/// The running statistics of a fetch, readable while it runs.
+#[derive(Debug, Default)]
+pub(crate) struct FetchProgress {
+    files_scanned: AtomicU32,
+    packets: AtomicU64,
+}
+
+impl FetchProgress {
+    pub(crate) fn update(&self, stats: &FetchStats) {
+        self.files_scanned
+            .store(stats.files_scanned, Ordering::Relaxed);
+        self.packets.store(stats.packets, Ordering::Relaxed);
+    }
+
+    pub(crate) fn files_scanned(&self) -> u32 {
+        self.files_scanned.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn packets(&self) -> u64 {
+        self.packets.load(Ordering::Relaxed)
+    }
 }
@@ -0,0 +1,759 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! PCAP spool retention.
+//!
+//! The deletion planning of `evebox pcap purge`, also enforced
+//! in-process by the server and agent on their configured spool. In
+//! addition to the purge command's count and size limits, a
+//! [`RetentionPolicy`] can expire files by age and keep a minimum of
+//! free disk space, and files holding packets of a protected window
+//! (the flows of escalated events) are never deleted.
+//!
+//! The policy and protected window types are portable so a Windows
+//! server can protect the spools of its agents; enforcement needs the
+//! spool walker and is compiled out with it.
+
+use crate::prelude::*;
+use std::time::Duration;
+#[cfg(not(windows))]
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+#[cfg(not(windows))]
+use super::request::{SpoolConfig, SpoolUsage};
+#[cfg(not(windows))]
+use super::{index, spool};
+
+/// Default interval between two enforcement passes.
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Upper bound on the protected windows held or sent to an agent. The
+/// closest windows are merged beyond it: protecting a little more than
+/// needed is always safe, and it keeps the list well below the control
+/// channel's message size limit.
+pub(crate) const MAX_PROTECTED_WINDOWS: usize = 2048;
+
+/// Which files of a spool to delete. Every limit that is set applies;
+/// a policy without limits deletes nothing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct RetentionPolicy {
+    /// Keep only the newest N files.
+    pub(crate) keep_files: Option<usize>,
+    /// Keep the newest files up to this total size in bytes.
+    pub(crate) max_size: Option<u64>,
+    /// Delete files last modified longer ago than this.
+    pub(crate) max_age: Option<Duration>,
+    /// Delete the oldest sealed files until the file system has at least
+    /// this many bytes available.
+    pub(crate) min_free: Option<u64>,
+}
+
+impl RetentionPolicy {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.keep_files.is_none()
+            && self.max_size.is_none()
+            && self.max_age.is_none()
+            && self.min_free.is_none()
+    }
+}
+
+/// In-process retention settings, from the `pcap.retention` section of
+/// the server or agent configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RetentionSettings {
+    pub(crate) policy: RetentionPolicy,
+    /// How often the policy is enforced and the spool usage refreshed.
+    pub(crate) interval: Duration,
+    /// Never delete files holding packets of escalated events.
+    pub(crate) protect_escalated: bool,
+}
+
+impl Default for RetentionSettings {
+    fn default() -> Self {
+        Self {
+            policy: RetentionPolicy::default(),
+            interval: DEFAULT_INTERVAL,
+            protect_escalated: true,
+        }
+    }
+}
+
+/// Parse the `pcap.retention` section. An absent section is the default
+/// settings: nothing is deleted, but spool usage is still reported.
+pub(crate) fn configure(config: &crate::config::Config) -> Result<RetentionSettings> {
+    let duration = |name: &str| -> Result<Option<Duration>> {
+        config
+            .get_value::<String>(name)?
+            .map(|value| {
+                humantime::parse_duration(value.trim()).map_err(|err| anyhow!("bad {name}: {err}"))
+            })
+            .transpose()
+    };
+    let size = |name: &str| -> Result<Option<u64>> {
+        match config.get_value::<serde_yaml::Value>(name)? {
+            None => Ok(None),
+            Some(serde_yaml::Value::Number(number)) => number
+                .as_u64()
+                .map(Some)
+                .ok_or_else(|| anyhow!("bad {name}: {number} is not a size")),
+            Some(serde_yaml::Value::String(value)) => parse_size(&value)
+                .map(Some)
+                .map_err(|err| anyhow!("bad {name}: {err}")),
+            Some(_) => bail!("bad {name}: expected a size such as 500G"),
+        }
+    };
+
+    let mut settings = RetentionSettings {
+        policy: RetentionPolicy {
+            keep_files: config.get_value::<usize>("pcap.retention.keep-files")?,
+            max_size: size("pcap.retention.max-size")?,
+            max_age: duration("pcap.retention.max-age")?,
+            min_free: size("pcap.retention.min-free")?,
+        },
+        ..RetentionSettings::default()
+    };
+    if let Some(interval) = duration("pcap.retention.interval")? {
+        if interval.is_zero() {
+            bail!("bad pcap.retention.interval: must be greater than zero");
+        }
+        settings.interval = interval;
+    }
+    if let Some(protect) = config.get_value::<bool>("pcap.retention.protect-escalated")? {
+        settings.protect_escalated = protect;
+    }
+    Ok(settings)
+}
+
+/// Parse a size such as `1024`, `500M` or `1.5G` (binary multiples).
+pub(crate) fn parse_size(input: &str) -> std::result::Result<u64, String> {
+    let size = input.trim().to_ascii_uppercase();
+    if size.is_empty() {
+        return Err("size cannot be empty".to_string());
+    }
+
+    let (number, multiplier) = match size.as_bytes().last().copied() {
+        Some(b'K') => (&size[..size.len() - 1], 1024f64),
+        Some(b'M') => (&size[..size.len() - 1], 1024f64.powi(2)),
+        Some(b'G') => (&size[..size.len() - 1], 1024f64.powi(3)),
+        Some(b'T') => (&size[..size.len() - 1], 1024f64.powi(4)),
+        _ => {
+            return size
+                .parse::<u64>()
+                .map_err(|_| format!("invalid size '{input}'"));
+        }
+    };
+
+    let number = number
+        .parse::<f64>()
+        .map_err(|_| format!("invalid size '{input}'"))?;
+    let bytes = number * multiplier;
+    if !bytes.is_finite() || bytes < 0.0 || bytes >= u64::MAX as f64 {
+        return Err(format!("size '{input}' is out of range"));
+    }
+
+    Ok(bytes as u64)
+}
+
+/// Sorted, non-overlapping packet time ranges (inclusive unix
+/// microseconds) whose spool files must be kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct ProtectedWindows(Vec<(u64, u64)>);
+
+impl ProtectedWindows {
+    /// Coalesce `windows`, merging across the smallest gaps until at most
+    /// `max` remain.
+    pub(crate) fn new(windows: impl IntoIterator<Item = (u64, u64)>, max: usize) -> Self {
+        let mut windows: Vec<(u64, u64)> = windows
+            .into_iter()
+            .map(|(start, end)| (start.min(end), start.max(end)))
+            .collect();
+        windows.sort_unstable();
+
+        let mut merged: Vec<(u64, u64)> = Vec::with_capacity(windows.len());
+        for (start, end) in windows {
+            match merged.last_mut() {
+                Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+
+        let max = max.max(1);
+        if merged.len() > max {
+            // Bridge the (len - max) smallest gaps.
+            let mut gaps: Vec<(u64, usize)> = merged
+                .windows(2)
+                .enumerate()
+                .map(|(i, pair)| (pair[1].0 - pair[0].1, i))
+                .collect();
+            gaps.sort_unstable();
+            let mut bridge = vec![false; merged.len()];
+            for &(_, i) in &gaps[..merged.len() - max] {
+                bridge[i] = true;
+            }
+            let mut bridged: Vec<(u64, u64)> = Vec::with_capacity(max);
+            let mut join = false;
+            for (i, window) in merged.into_iter().enumerate() {
+                match bridged.last_mut() {
+                    Some(last) if join => last.1 = window.1,
+                    _ => bridged.push(window),
+                }
+                join = bridge[i];
+            }
+            merged = bridged;
+        }
+        Self(merged)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn windows(&self) -> &[(u64, u64)] {
+        &self.0
+    }
+
+    /// True if any window overlaps `[start, end]`.
+    pub(crate) fn overlaps(&self, start: u64, end: u64) -> bool {
+        // The first window ending at or after `start` is the only
+        // candidate: the windows are sorted and disjoint.
+        let i = self.0.partition_point(|window| window.1 < start);
+        self.0.get(i).is_some_and(|window| window.0 <= end)
+    }
+}
+
+/// A capture file considered for deletion.
+#[cfg(not(windows))]
+#[derive(Debug)]
+pub(crate) struct FileInfo {
+    pub(crate) path: PathBuf,
+    pub(crate) size: u64,
+    pub(crate) modified: SystemTime,
+}
+
+/// The capture files directly inside `directory`, newest first.
+#[cfg(not(windows))]
+pub(crate) fn collect_pcap_files(directory: &Path, prefix: Option<&str>) -> Result<Vec<FileInfo>> {
+    if !directory.exists() {
+        bail!("Directory does not exist: {}", directory.display());
+    }
+    if !directory.is_dir() {
+        bail!("Path is not a directory: {}", directory.display());
+    }
+
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+
+        let path = entry.path();
+        let Some(filename) = path.file_name().and_then(|filename| filename.to_str()) else {
+            continue;
+        };
+        if !is_pcap_filename(filename) {
+            continue;
+        }
+        if let Some(prefix) = prefix
+            && !filename.starts_with(prefix)
+        {
+            continue;
+        }
+
+        let metadata = entry.metadata()?;
+        files.push(FileInfo {
+            path,
+            size: metadata.len(),
+            modified: metadata.modified()?,
+        });
+    }
+
+    sort_newest_first(&mut files);
+    Ok(files)
+}
+
+/// Every capture file of a spool, including its subdirectories as the
+/// extraction engine sees them, newest first.
+#[cfg(not(windows))]
+fn collect_spool_files(spool: &SpoolConfig) -> Result<Vec<FileInfo>> {
+    let mut files = Vec::new();
+    for path in spool::walk_files(&spool.directory, spool.prefix.as_deref())? {
+        if !path
+            .file_name()
+            .and_then(|filename| filename.to_str())
+            .is_some_and(is_pcap_filename)
+        {
+            continue;
+        }
+        // Files rotated away during the walk are simply gone.
+        let Ok(metadata) = std::fs::metadata(&path) else {
+            continue;
+        };
+        files.push(FileInfo {
+            path,
+            size: metadata.len(),
+            modified: metadata.modified()?,
+        });
+    }
+    sort_newest_first(&mut files);
+    Ok(files)
+}
+
+#[cfg(not(windows))]
+fn sort_newest_first(files: &mut [FileInfo]) {
+    files.sort_by(|a, b| {
+        b.modified
+            .cmp(&a.modified)
+            .then_with(|| a.path.cmp(&b.path))
+    });
+}
+
+#[cfg(not(windows))]
+pub(crate) fn is_pcap_filename(filename: &str) -> bool {
+    let lowercase = filename.to_ascii_lowercase();
+
+    [".pcap", ".cap"].iter().any(|extension| {
+        lowercase.ends_with(extension)
+            || lowercase
+                .split_once(&format!("{extension}."))
+                .is_some_and(|(_, suffix)| {
+                    !suffix.is_empty()
+                        && suffix
+                            .split('.')
+                            .all(|component| component.chars().all(|c| c.is_ascii_digit()))
+                })
+    })
+}
+
+#[cfg(not(windows))]
+pub(crate) fn calculate_files_to_delete_by_size(
+    files: &[FileInfo],
+    max_size_bytes: u64,
+) -> Vec<&FileInfo> {
+    let mut total_size = 0u64;
+    let mut keep_count = 0;
+
+    for file in files {
+        let next_size = total_size.saturating_add(file.size);
+        if next_size > max_size_bytes {
+            break;
+        }
+        total_size = next_size;
+        keep_count += 1;
+    }
+
+    files[keep_count..].iter().collect()
+}
+
+/// Plan the deletions of `policy` over `files` (newest first): the union
+/// of the count, size and age limits, less the files `protected` keeps.
+/// Then, while the file system would still have less than the minimum
+/// free space, the oldest remaining sealed and unprotected files.
+#[cfg(not(windows))]
+pub(crate) fn files_to_delete<'a>(
+    files: &'a [FileInfo],
+    policy: &RetentionPolicy,
+    free: Option<u64>,
+    now: SystemTime,
+    protected: &dyn Fn(&FileInfo) -> bool,
+) -> Vec<&'a FileInfo> {
+    let mut delete = vec![false; files.len()];
+    let mut mark_from = |keep: usize| {
+        for marked in delete.iter_mut().skip(keep) {
+            *marked = true;
+        }
+    };
+    if let Some(keep_count) = policy.keep_files {
+        mark_from(keep_count);
+    }
+    if let Some(max_size) = policy.max_size {
+        mark_from(files.len() - calculate_files_to_delete_by_size(files, max_size).len());
+    }
+    if let Some(cutoff) = policy.max_age.and_then(|max_age| now.checked_sub(max_age)) {
+        for (marked, file) in delete.iter_mut().zip(files) {
+            if file.modified < cutoff {
+                *marked = true;
+            }
+        }
+    }
+    for (marked, file) in delete.iter_mut().zip(files) {
+        if *marked && protected(file) {
+            *marked = false;
+        }
+    }
+
+    if let (Some(min_free), Some(free)) = (policy.min_free, free) {
+        let mut available = files
+            .iter()
+            .zip(&delete)
+            .filter(|(_, marked)| **marked)
+            .fold(free, |total, (file, _)| total.saturating_add(file.size));
+        for (i, file) in files.iter().enumerate().rev() {
+            if available >= min_free {
+                break;
+            }
+            // The file being written is never sealed.
+            let sealed = now
+                .duration_since(file.modified)
+                .is_ok_and(|age| age >= index::SEALED_AGE);
+            if delete[i] || !sealed || protected(file) {
+                continue;
+            }
+            delete[i] = true;
+            available = available.saturating_add(file.size);
+        }
+    }
+
+    files
+        .iter()
+        .zip(delete)
+        .filter_map(|(file, marked)| marked.then_some(file))
+        .collect()
+}
+
+/// Delete capture files and their sidecar indexes. Every file is
+/// attempted; an error reports how many failed.
+#[cfg(not(windows))]
+pub(crate) fn delete_files(files: &[&FileInfo], quiet: bool) -> Result<()> {
+    let mut delete_count = 0;
+    let mut delete_errors = 0;
+    for file in files {
+        match std::fs::remove_file(&file.path) {
+            Ok(()) => {
+                delete_count += 1;
+                if !quiet {
+                    info!("Deleted: {}", file.path.display());
+                }
+                let sidecar = index::sidecar_path(&file.path);
+                if let Err(err) = std::fs::remove_file(&sidecar)
+                    && err.kind() != std::io::ErrorKind::NotFound
+                {
+                    warn!("Failed to delete {}: {err}", sidecar.display());
+                }
+            }
+            Err(err) => {
+                delete_errors += 1;
+                error!("Failed to delete {}: {err}", file.path.display());
+            }
+        }
+    }
+
+    if !quiet {
+        info!("Deleted {delete_count} files successfully, {delete_errors} errors");
+    }
+    if delete_errors > 0 {
+        bail!(
+            "failed to delete {delete_errors} of {} capture files",
+            files.len()
+        );
+    }
+    Ok(())
+}
+
+/// Bytes available to unprivileged users on the file system holding
+/// `directory`.
+#[cfg(not(windows))]
+pub(crate) fn free_space(directory: &Path) -> std::io::Result<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(directory.as_os_str().as_bytes())
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is NUL-terminated and `stat` is a valid out pointer.
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    #[allow(clippy::unnecessary_cast)]
+    Ok((stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64))
+}
+
+/// The packet time span of a capture file in unix microseconds: exact
+/// from a current sidecar index, otherwise from its rotation timestamp
+/// to its last modification. The start is unknown for files without a
+/// rotation timestamp in their name.
+#[cfg(not(windows))]
+fn file_span(file: &FileInfo) -> (Option<u64>, u64) {
+    if let Some(index) = index::load(&file.path) {
+        return (Some(index.first_us), index.last_us);
+    }
+    let end = file
+        .modified
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|mtime| u64::try_from(mtime.as_micros()).unwrap_or(u64::MAX))
+        .unwrap_or(0);
+    let start = file
+        .path
+        .file_name()
+        .and_then(spool::parse_filename)
+        .map(|(_, _, ts)| ts.saturating_mul(1_000_000))
+        .filter(|start| *start <= end);
+    (start, end)
+}
+
+/// The size and age of a spool's remaining files, newest first.
+#[cfg(not(windows))]
+fn usage(files: &[&FileInfo]) -> SpoolUsage {
+    SpoolUsage {
+        files: files.len() as u64,
+        bytes: files
+            .iter()
+            .fold(0u64, |total, file| total.saturating_add(file.size)),
+        oldest_us: files.last().map(|oldest| {
+            let (start, end) = file_span(oldest);
+            start.unwrap_or(end)
+        }),
+    }
+}
+
+/// Enforce `policy` on a spool, keeping every file that overlaps a
+/// protected window, and return the usage of what remains. Blocking.
+#[cfg(not(windows))]
+pub(crate) fn enforce(
+    spool: &SpoolConfig,
+    policy: &RetentionPolicy,
+    protected: &ProtectedWindows,
+) -> Result<SpoolUsage> {
+    let files = collect_spool_files(spool)?;
+    let free = match policy.min_free {
+        Some(_) => Some(free_space(&spool.directory)?),
+        None => None,
+    };
+    let is_protected = |file: &FileInfo| {
+        if protected.is_empty() {
+            return false;
+        }
+        let (start, end) = file_span(file);
+        protected.overlaps(start.unwrap_or(0), end)
+    };
+    let plan = files_to_delete(&files, policy, free, SystemTime::now(), &is_protected);
+
+    if !plan.is_empty() {
+        let bytes = plan
+            .iter()
+            .fold(0u64, |total, file| total.saturating_add(file.size));
+        info!(
+            "Retention deleting {} PCAP files ({:.2} MB) from {}",
+            plan.len(),
+            bytes as f64 / 1024.0 / 1024.0,
+            spool.directory.display()
+        );
+        if let Err(err) = delete_files(&plan, true) {
+            warn!("PCAP retention: {err}");
+        }
+    }
+
+    let remaining: Vec<&FileInfo> = files.iter().filter(|file| file.path.exists()).collect();
+    Ok(usage(&remaining))
+}
+
+#[cfg(all(test, not(windows)))]
+mod test {
+    use super::*;
+    use std::fs::FileTimes;
+
+    const HOUR: u64 = 3600;
+
+    fn file(name: &str, size: u64, modified: u64) -> FileInfo {
+        FileInfo {
+            path: name.into(),
+            size,
+            modified: SystemTime::UNIX_EPOCH + Duration::from_secs(modified),
+        }
+    }
+
+    fn names<'a>(files: &[&'a FileInfo]) -> Vec<&'a str> {
+        files
+            .iter()
+            .map(|file| file.path.to_str().unwrap())
+            .collect()
+    }
+
+    /// Three hourly files, newest first, an hour after the newest.
+    fn hourly() -> (Vec<FileInfo>, SystemTime) {
+        let base = 1_700_000_000;
+        let files = vec![
+            file("c.pcap", 30, base + 2 * HOUR),
+            file("b.pcap", 20, base + HOUR),
+            file("a.pcap", 10, base),
+        ];
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(base + 3 * HOUR);
+        (files, now)
+    }
+
+    #[test]
+    fn limits_are_combined() {
+        let (files, now) = hourly();
+        let never = |_: &FileInfo| false;
+
+        let policy = RetentionPolicy::default();
+        assert!(policy.is_empty());
+        assert!(files_to_delete(&files, &policy, None, now, &never).is_empty());
+
+        let policy = RetentionPolicy {
+            max_age: Some(Duration::from_secs(2 * HOUR + 60)),
+            ..Default::default()
+        };
+        assert_eq!(
+            names(&files_to_delete(&files, &policy, None, now, &never)),
+            ["a.pcap"]
+        );
+
+        let policy = RetentionPolicy {
+            keep_files: Some(2),
+            max_size: Some(30),
+            ..Default::default()
+        };
+        assert_eq!(
+            names(&files_to_delete(&files, &policy, None, now, &never)),
+            ["b.pcap", "a.pcap"]
+        );
+    }
+
+    #[test]
+    fn min_free_deletes_oldest_sealed_files() {
+        let (files, now) = hourly();
+        let never = |_: &FileInfo| false;
+        let policy = RetentionPolicy {
+            min_free: Some(100),
+            ..Default::default()
+        };
+
+        assert!(files_to_delete(&files, &policy, Some(100), now, &never).is_empty());
+        assert_eq!(
+            names(&files_to_delete(&files, &policy, Some(75), now, &never)),
+            ["b.pcap", "a.pcap"]
+        );
+
+        // The file still being written is never deleted for space.
+        let written = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000 + 2 * HOUR);
+        assert_eq!(
+            names(&files_to_delete(&files, &policy, Some(0), written, &never)),
+            ["b.pcap", "a.pcap"]
+        );
+
+        // Files deleted by another limit count towards the free space.
+        let policy = RetentionPolicy {
+            keep_files: Some(2),
+            min_free: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(
+            names(&files_to_delete(&files, &policy, Some(90), now, &never)),
+            ["a.pcap"]
+        );
+    }
+
+    #[test]
+    fn protected_files_are_kept() {
+        let (files, now) = hourly();
+        let protect_a = |file: &FileInfo| file.path == Path::new("a.pcap");
+
+        let policy = RetentionPolicy {
+            keep_files: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            names(&files_to_delete(&files, &policy, None, now, &protect_a)),
+            ["b.pcap"]
+        );
+
+        // Space comes from the next oldest unprotected file instead.
+        let policy = RetentionPolicy {
+            min_free: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(
+            names(&files_to_delete(&files, &policy, Some(90), now, &protect_a)),
+            ["b.pcap"]
+        );
+    }
+
+    #[test]
+    fn protected_windows_coalesce_and_cap() {
+        let windows = ProtectedWindows::new([(50, 60), (10, 20), (15, 30), (31, 40), (90, 80)], 10);
+        assert_eq!(windows.windows(), [(10, 40), (50, 60), (80, 90)]);
+        assert!(windows.overlaps(0, 10));
+        assert!(windows.overlaps(41, 55));
+        assert!(windows.overlaps(85, 85));
+        assert!(!windows.overlaps(41, 49));
+        assert!(!windows.overlaps(61, 79));
+        assert!(!windows.overlaps(91, u64::MAX));
+
+        // The smallest gap (40..50) is bridged first.
+        let capped = ProtectedWindows::new([(10, 40), (50, 60), (80, 90)], 2);
+        assert_eq!(capped.windows(), [(10, 60), (80, 90)]);
+        let capped = ProtectedWindows::new([(10, 40), (50, 60), (80, 90)], 1);
+        assert_eq!(capped.windows(), [(10, 90)]);
+
+        assert!(!ProtectedWindows::default().overlaps(0, u64::MAX));
+    }
+
+    #[test]
+    fn enforce_keeps_protected_files_and_reports_usage() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let base = 1_700_000_000;
+        let old = tempdir.path().join(format!("log.pcap.{base}"));
+        let protected = tempdir.path().join(format!("log.pcap.{}", base + HOUR));
+        let nested = tempdir.path().join("1");
+        std::fs::create_dir(&nested).unwrap();
+        let new = nested.join(format!("log.pcap.{}", base + 2 * HOUR));
+        let notes = tempdir.path().join("notes.txt");
+        for (path, modified) in [
+            (&old, base + HOUR),
+            (&protected, base + 2 * HOUR),
+            (&new, base + 3 * HOUR),
+            (&notes, base),
+        ] {
+            std::fs::write(path, [0u8; 10]).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_times(
+                    FileTimes::new()
+                        .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(modified)),
+                )
+                .unwrap();
+        }
+        std::fs::write(index::sidecar_path(&old), b"index").unwrap();
+
+        let spool = SpoolConfig::new(tempdir.path(), None);
+        let policy = RetentionPolicy {
+            keep_files: Some(1),
+            ..Default::default()
+        };
+        // Inside the second file's span only: [rotation, mtime].
+        let windows = ProtectedWindows::new(
+            [(
+                (base + HOUR + 10) * 1_000_000,
+                (base + HOUR + 11) * 1_000_000,
+            )],
+            8,
+        );
+        let usage = enforce(&spool, &policy, &windows).unwrap();
+
+        assert!(!old.exists());
+        assert!(!index::sidecar_path(&old).exists());
+        assert!(protected.exists());
+        assert!(new.exists());
+        assert!(notes.exists());
+        assert_eq!(
+            usage,
+            SpoolUsage {
+                files: 2,
+                bytes: 20,
+                oldest_us: Some((base + HOUR) * 1_000_000),
+            }
+        );
+    }
+
+    #[test]
+    fn free_space_of_a_directory() {
+        let tempdir = tempfile::tempdir().unwrap();
+        free_space(tempdir.path()).unwrap();
+        assert!(free_space(&tempdir.path().join("missing")).is_err());
+    }
+}
@@ -18,6 +18,7 @@ pub(crate) type SortedFiles = HashMap<(PathBuf, String, u64), Vec<(u64, PathBuf)
 const MIN_ROTATION_TIMESTAMP: u64 = 946_684_800; // 2000-01-01 00:00:00 UTC
 const MAX_ROTATION_TIMESTAMP: u64 = 4_102_444_800; // 2100-01-01 00:00:00 UTC
 
+use super::index;
 use super::request::SpoolConfig;
 
 /// Discover spool files grouped by rotation sequence, pruned to the files
@@ -110,6 +111,9 @@ fn collect_files(
             {
                 continue;
             }
+            if index::is_sidecar(&path) {
+                continue;
+            }
             let filename = path.file_name().unwrap();
             if let Some((basename, id, ts)) = parse_filename(filename) {
                 sorted
@@ -151,6 +155,9 @@ fn walk(directory: &Path, prefix: Option<&str>, files: &mut Vec<PathBuf>) -> std
             {
                 continue;
             }
+            if index::is_sidecar(&path) {
+                continue;
+            }
             files.push(path);
         } else {
             debug!("Ignoring {:?}", &path);
@@ -181,7 +188,7 @@ fn strip_capture_ext(filename: &str) -> &str {
     filename
 }
 
-fn parse_filename(filename: &OsStr) -> Option<(String, u64, u64)> {
+pub(crate) fn parse_filename(filename: &OsStr) -> Option<(String, u64, u64)> {
     lazy_static! {
         // Matches the Suricata pcap-log format with thread-id.
         static ref RE_SURICATA_WITH_THREAD_ID: Regex =
@@ -1,41 +1,78 @@
 // SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
 // SPDX-License-Identifier: MIT
 
-//! Streaming classic-pcap writer with a lazily written file header.
+//! Streaming pcap and pcapng writer with a lazily written file header.
 
 use std::io::Write;
 
+use super::request::OutputFormat;
 use crate::util::pcap::{
-    FILE_HEADER_LEN, PCAP_RECORD_HEADER_SIZE, create_header, create_record_raw,
+    FILE_HEADER_LEN, PCAP_RECORD_HEADER_SIZE, PcapngInterfaces, create_header,
+    create_pcapng_packet, create_record_raw, pcapng_packet_size,
 };
 
-/// Writes packets as a classic little-endian pcap stream. Nothing is
-/// written until the first packet, so an output with zero bytes written
-/// means no packet matched.
+/// Writes packets as a classic little-endian pcap stream, or as pcapng.
+/// Nothing is written until the first packet, so an output with zero
+/// bytes written means no packet matched.
 pub(crate) struct PcapWriter<'a> {
     out: &'a mut dyn Write,
     bytes: u64,
     header_written: bool,
+    pcapng: Option<Pcapng>,
+}
+
+/// pcapng state: the interfaces written so far and the comments.
+struct Pcapng {
+    interfaces: PcapngInterfaces,
+    section_comment: Option<String>,
+    packet_comment: Option<String>,
 }
 
 impl<'a> PcapWriter<'a> {
-    pub(crate) fn new(out: &'a mut dyn Write) -> Self {
+    pub(crate) fn with_format(out: &'a mut dyn Write, format: &OutputFormat) -> Self {
+        let pcapng = match format {
+            OutputFormat::Pcap => None,
+            OutputFormat::Pcapng {
+                section_comment,
+                packet_comment,
+            } => Some(Pcapng {
+                interfaces: PcapngInterfaces::default(),
+                section_comment: section_comment.clone(),
+                packet_comment: packet_comment.clone(),
+            }),
+        };
         Self {
             out,
             bytes: 0,
             header_written: false,
+            pcapng,
         }
     }
 
+    /// True if packets of any link type can be written to this output.
+    /// A classic pcap output is limited to the link type of its first
+    /// packet.
+    pub(crate) fn mixed_linktypes(&self) -> bool {
+        self.pcapng.is_some()
+    }
+
     /// Total bytes written so far, including the pcap file header.
     pub(crate) fn bytes_written(&self) -> u64 {
         self.bytes
     }
 
-    /// The number of bytes that writing a packet with `data_len` bytes of
-    /// packet data would add to the output, including the lazily written
-    /// file header if it has not been written yet.
-    pub(crate) fn next_write_size(&self, data_len: usize) -> u64 {
+    /// The number of bytes that writing a packet of `linktype` with
+    /// `data_len` bytes of packet data would add to the output, including
+    /// the lazily written file header (or pcapng section header and
+    /// interface description) if it has not been written yet.
+    pub(crate) fn next_write_size(&self, linktype: pcap::Linktype, data_len: usize) -> u64 {
+        if let Some(pcapng) = &self.pcapng {
+            let blocks = pcapng
+                .interfaces
+                .prepare_size(linktype.0 as u32, pcapng.section_comment.as_deref());
+            let packet = pcapng_packet_size(data_len, pcapng.packet_comment.as_deref());
+            return (blocks + packet) as u64;
+        }
         let mut size = (PCAP_RECORD_HEADER_SIZE + data_len) as u64;
         if !self.header_written {
             size += FILE_HEADER_LEN as u64;
@@ -51,6 +88,24 @@ impl<'a> PcapWriter<'a> {
         linktype: pcap::Linktype,
         pkt: &pcap::Packet,
     ) -> std::io::Result<()> {
+        if let Some(pcapng) = &mut self.pcapng {
+            let (blocks, interface) = pcapng
+                .interfaces
+                .prepare(linktype.0 as u32, pcapng.section_comment.as_deref());
+            let packet = create_pcapng_packet(
+                interface,
+                pkt.header.ts.tv_sec as u32,
+                pkt.header.ts.tv_usec as u32,
+                pkt.header.len,
+                pkt.data,
+                pcapng.packet_comment.as_deref(),
+            );
+            self.out.write_all(&blocks)?;
+            self.out.write_all(&packet)?;
+            self.bytes += (blocks.len() + packet.len()) as u64;
+            self.header_written = true;
+            return Ok(());
+        }
         if !self.header_written {
             // The first packet cannot predict the largest later caplen, so
             // advertise the classic-PCAP maximum rather than a snaplen that
@@ -85,10 +140,10 @@ mod test {
     #[test]
     fn test_lazy_header_and_byte_accounting() {
         let mut buf = vec![];
-        let mut writer = PcapWriter::new(&mut buf);
+        let mut writer = PcapWriter::with_format(&mut buf, &OutputFormat::Pcap);
         assert_eq!(writer.bytes_written(), 0);
         assert_eq!(
-            writer.next_write_size(42),
+            writer.next_write_size(pcap::Linktype::ETHERNET, 42),
             (FILE_HEADER_LEN + PCAP_RECORD_HEADER_SIZE + 42) as u64
         );
 
@@ -111,7 +166,7 @@ mod test {
 
         // A second record does not repeat the file header.
         assert_eq!(
-            writer.next_write_size(42),
+            writer.next_write_size(pcap::Linktype::ETHERNET, 42),
             (PCAP_RECORD_HEADER_SIZE + 42) as u64
         );
         writer
@@ -137,7 +192,7 @@ mod test {
     #[test]
     fn test_global_snaplen_covers_oversized_record() {
         let mut buf = vec![];
-        let mut writer = PcapWriter::new(&mut buf);
+        let mut writer = PcapWriter::with_format(&mut buf, &OutputFormat::Pcap);
         let data = vec![0u8; 70_000];
         let header = pcap::PacketHeader {
             ts: libc::timeval {
@@ -157,4 +212,59 @@ mod test {
         assert!(snaplen >= incl_len);
         assert_eq!(incl_len, 70_000);
     }
+
+    #[test]
+    fn test_pcapng_mixed_linktypes() {
+        let mut buf = vec![];
+        let format = OutputFormat::Pcapng {
+            section_comment: Some("EveBox capture from source local".to_string()),
+            packet_comment: Some("flow_id: 1".to_string()),
+        };
+        let mut writer = PcapWriter::with_format(&mut buf, &format);
+        assert!(writer.mixed_linktypes());
+
+        let data = [0u8; 42];
+        let header = pcap::PacketHeader {
+            ts: libc::timeval {
+                tv_sec: 1_700_000_000,
+                tv_usec: 123,
+            },
+            caplen: 42,
+            len: 60,
+        };
+        let mut expected = 0;
+        for linktype in [
+            pcap::Linktype::ETHERNET,
+            pcap::Linktype(101),
+            pcap::Linktype::ETHERNET,
+        ] {
+            let size = writer.next_write_size(linktype, data.len());
+            writer
+                .write_packet(linktype, &pcap::Packet::new(&header, &data))
+                .unwrap();
+            expected += size;
+            assert_eq!(writer.bytes_written(), expected);
+        }
+        assert_eq!(buf.len() as u64, expected);
+
+        // Walk the blocks: a section header, an interface, a packet, a
+        // second interface for the raw packet, then two more packets.
+        let mut types = vec![];
+        let mut offset = 0;
+        while offset < buf.len() {
+            let block_type = u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap());
+            let len = u32::from_le_bytes(buf[offset + 4..offset + 8].try_into().unwrap());
+            types.push(block_type);
+            if block_type == 6 {
+                let interface =
+                    u32::from_le_bytes(buf[offset + 8..offset + 12].try_into().unwrap());
+                types.push(interface);
+            }
+            offset += len as usize;
+        }
+        assert_eq!(types, vec![0x0A0D_0D0A, 1, 6, 0, 1, 6, 1, 6, 0]);
+        let text = String::from_utf8_lossy(&buf);
+        assert!(text.contains("EveBox capture from source local"));
+        assert!(text.contains("flow_id: 1"));
+    }
 }
@@ -86,6 +86,11 @@ pub(crate) enum QueryValue {
     /// `is:escalated` - match events in the escalated state. Like
     /// [`QueryValue::Archived`], handled per datastore.
     Escalated,
+
+    /// Match events from one of these sensors, by `host`. Never parsed
+    /// from a query string; added to the queries of a user limited to
+    /// some sensors, and not negatable.
+    Sensors(Vec<String>),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
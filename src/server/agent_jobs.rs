@@ -0,0 +1,448 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+#![cfg_attr(windows, allow(dead_code))]
+
+//! Server-side state of control-channel jobs other than packet capture.
+//!
+//! A rule update runs for minutes and reports progress, so unlike a PCAP
+//! request nobody waits on it: the job is recorded here, its output and
+//! result are filled in as the agent reports them, and the API polls it.
+//! Only the latest job per agent is kept.
+//!
+//! A proxied Suricata command is a plain request and reply: the API request
+//! waits for the agent's answer.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+
+use crate::agent::protocol::{
+    AgentMessage, CAPABILITY_RULES_UPDATE, CAPABILITY_SURICATA_COMMAND, RulesUpdateResult,
+    ServerMessage, SuricataCommandResult,
+};
+use crate::datetime::DateTime;
+use crate::prelude::*;
+use crate::server::agents::{AgentConnectionId, AgentMessageHandler, AgentRegistry};
+
+/// Bound on the output lines kept per job. The agent caps what it sends;
+/// this keeps a misbehaving one from growing server memory.
+const MAX_OUTPUT_LINES: usize = 2000;
+
+/// How long to wait for a proxied command's reply. The agent bounds its
+/// socket exchange at five minutes; this adds a margin for the round trip.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(310);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum JobState {
+    Running,
+    Complete,
+    Failed,
+}
+
+/// One rule update, as returned by `/api/agents/{name}/rules/update`.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct RulesUpdateJob {
+    pub(crate) id: String,
+    pub(crate) agent: String,
+    pub(crate) user: String,
+    pub(crate) started: DateTime,
+    pub(crate) finished: Option<DateTime>,
+    pub(crate) state: JobState,
+    pub(crate) output: Vec<String>,
+    pub(crate) exit_code: Option<i32>,
+    pub(crate) reloaded: bool,
+    pub(crate) message: Option<String>,
+    /// The connection the request went out on; reports from any other are
+    /// stale.
+    #[serde(skip)]
+    generation: u64,
+    #[serde(skip)]
+    remote: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum StartError {
+    NotConnected,
+    Unsupported,
+    /// The agent's previous update is still running.
+    Busy,
+    /// The agent's control channel went away or its queue is full.
+    Unavailable,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum CommandError {
+    NotConnected,
+    Unsupported,
+    Unavailable,
+    Disconnected,
+    TimedOut,
+}
+
+struct PendingCommand {
+    connection: AgentConnectionId,
+    reply: oneshot::Sender<SuricataCommandResult>,
+}
+
+/// Latest rule update per agent name, and proxied commands awaiting a
+/// reply by id.
+#[derive(Default)]
+pub(crate) struct AgentJobs {
+    rules_updates: Mutex<HashMap<String, RulesUpdateJob>>,
+    commands: Mutex<HashMap<String, PendingCommand>>,
+}
+
+impl AgentJobs {
+    /// Ask an agent to run its rule update command.
+    pub(crate) fn start_rules_update(
+        &self,
+        agents: &AgentRegistry,
+        agent: &str,
+        user: &str,
+        remote: &str,
+    ) -> Result<RulesUpdateJob, StartError> {
+        let entry = agents.get(agent).ok_or(StartError::NotConnected)?;
+        if !entry.supports(CAPABILITY_RULES_UPDATE) {
+            return Err(StartError::Unsupported);
+        }
+        let mut jobs = self.rules_updates.lock().unwrap();
+        if jobs
+            .get(agent)
+            .is_some_and(|job| job.state == JobState::Running)
+        {
+            return Err(StartError::Busy);
+        }
+        let id = uuid::Uuid::new_v4().to_string();
+        agents
+            .try_send_current(&entry, ServerMessage::RulesUpdate { id: id.clone() })
+            .map_err(|_| StartError::Unavailable)?;
+        let job = RulesUpdateJob {
+            id,
+            agent: agent.to_string(),
+            user: user.to_string(),
+            started: DateTime::now(),
+            finished: None,
+            state: JobState::Running,
+            output: Vec::new(),
+            exit_code: None,
+            reloaded: false,
+            message: None,
+            generation: entry.generation,
+            remote: remote.to_string(),
+        };
+        jobs.insert(agent.to_string(), job.clone());
+        Ok(job)
+    }
+
+    /// Run a Suricata command socket command through an agent and wait for
+    /// its reply.
+    pub(crate) async fn suricata_command(
+        &self,
+        agents: &AgentRegistry,
+        agent: &str,
+        command: &str,
+        arguments: Option<serde_json::Value>,
+    ) -> Result<SuricataCommandResult, CommandError> {
+        let entry = agents.get(agent).ok_or(CommandError::NotConnected)?;
+        if !entry.supports(CAPABILITY_SURICATA_COMMAND) {
+            return Err(CommandError::Unsupported);
+        }
+        let id = uuid::Uuid::new_v4().to_string();
+        let (reply, reply_rx) = oneshot::channel();
+        self.commands.lock().unwrap().insert(
+            id.clone(),
+            PendingCommand {
+                connection: entry.connection_id(),
+                reply,
+            },
+        );
+        let message = ServerMessage::SuricataCommand {
+            id: id.clone(),
+            command: command.to_string(),
+            arguments,
+        };
+        if agents.try_send_current(&entry, message).is_err() {
+            self.commands.lock().unwrap().remove(&id);
+            return Err(CommandError::Unavailable);
+        }
+        let result = tokio::time::timeout(COMMAND_TIMEOUT, reply_rx).await;
+        self.commands.lock().unwrap().remove(&id);
+        match result {
+            Ok(Ok(result)) => Ok(result),
+            // The sender is dropped when the connection goes away.
+            Ok(Err(_)) => Err(CommandError::Disconnected),
+            Err(_) => Err(CommandError::TimedOut),
+        }
+    }
+
+    pub(crate) fn rules_update(&self, agent: &str) -> Option<RulesUpdateJob> {
+        self.rules_updates.lock().unwrap().get(agent).cloned()
+    }
+
+    fn finish(job: &mut RulesUpdateJob, state: JobState, result: RulesUpdateResult) {
+        job.state = state;
+        job.finished = Some(DateTime::now());
+        job.exit_code = result.exit_code;
+        job.reloaded = result.reloaded;
+        job.message = result.message;
+        let outcome = match state {
+            JobState::Complete => "complete",
+            _ => "failed",
+        };
+        info!(
+            "rules-update: user={:?} remote={:?} agent={:?} job={} outcome={} exit_code={:?} reloaded={} message={:?}",
+            job.user,
+            job.remote,
+            job.agent,
+            job.id,
+            outcome,
+            job.exit_code,
+            job.reloaded,
+            job.message
+        );
+    }
+}
+
+impl AgentMessageHandler for AgentJobs {
+    fn message(&self, connection: &AgentConnectionId, message: AgentMessage) {
+        if let AgentMessage::SuricataCommandResult { id, result } = message {
+            let mut commands = self.commands.lock().unwrap();
+            if commands
+                .get(&id)
+                .is_some_and(|pending| pending.connection == *connection)
+                && let Some(pending) = commands.remove(&id)
+            {
+                let _ = pending.reply.send(result);
+            }
+            return;
+        }
+        let (id, update) = match message {
+            AgentMessage::RulesUpdateStatus { id, line } => (id, Err(line)),
+            AgentMessage::RulesUpdateResult { id, result } => (id, Ok(result)),
+            _ => return,
+        };
+        let mut jobs = self.rules_updates.lock().unwrap();
+        let Some(job) = jobs.get_mut(&connection.name).filter(|job| {
+            job.id == id
+                && job.generation == connection.generation
+                && job.state == JobState::Running
+        }) else {
+            debug!(
+                "Ignoring stale rule update report {id} from agent {:?}",
+                connection.name
+            );
+            return;
+        };
+        match update {
+            Err(line) => {
+                if job.output.len() < MAX_OUTPUT_LINES {
+                    job.output.push(line);
+                }
+            }
+            Ok(result) => {
+                let state = if result.exit_code == Some(0) && result.message.is_none() {
+                    JobState::Complete
+                } else {
+                    JobState::Failed
+                };
+                Self::finish(job, state, result);
+            }
+        }
+    }
+
+    fn disconnected(&self, connection: &AgentConnectionId) {
+        self.commands
+            .lock()
+            .unwrap()
+            .retain(|_, pending| pending.connection != *connection);
+        let mut jobs = self.rules_updates.lock().unwrap();
+        if let Some(job) = jobs
+            .get_mut(&connection.name)
+            .filter(|job| job.generation == connection.generation && job.state == JobState::Running)
+        {
+            Self::finish(
+                job,
+                JobState::Failed,
+                RulesUpdateResult {
+                    message: Some("agent disconnected before the rule update finished".to_string()),
+                    ..Default::default()
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::agent::protocol::AgentHandshake;
+    use crate::server::agents::OUTBOUND_CAPACITY;
+
+    fn register(
+        agents: &AgentRegistry,
+        capabilities: &[&str],
+    ) -> (AgentConnectionId, mpsc::Receiver<ServerMessage>) {
+        let (tx, rx) = mpsc::channel(OUTBOUND_CAPACITY);
+        let handshake = AgentHandshake {
+            name: "sensor".to_string(),
+            hostname: "sensor.example.test".to_string(),
+            version: "0.28.0".to_string(),
+            capabilities: capabilities.iter().map(|value| value.to_string()).collect(),
+        };
+        let entry = agents
+            .register(handshake, None, "127.0.0.1:0".parse().unwrap(), tx)
+            .unwrap();
+        (entry.connection_id(), rx)
+    }
+
+    #[test]
+    fn rules_update_collects_output_and_ignores_stale_reports() {
+        let agents = AgentRegistry::default();
+        let jobs = AgentJobs::default();
+        assert_eq!(
+            jobs.start_rules_update(&agents, "sensor", "admin", "-")
+                .unwrap_err(),
+            StartError::NotConnected
+        );
+        let (_, _rx) = register(&agents, &[]);
+        assert_eq!(
+            jobs.start_rules_update(&agents, "sensor", "admin", "-")
+                .unwrap_err(),
+            StartError::Unsupported
+        );
+
+        let (connection, mut rx) = register(&agents, &[CAPABILITY_RULES_UPDATE]);
+        let job = jobs
+            .start_rules_update(&agents, "sensor", "admin", "-")
+            .unwrap();
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            ServerMessage::RulesUpdate { id: job.id.clone() }
+        );
+        assert_eq!(
+            jobs.start_rules_update(&agents, "sensor", "admin", "-")
+                .unwrap_err(),
+            StartError::Busy
+        );
+
+        let stale = AgentConnectionId {
+            name: "sensor".to_string(),
+            generation: connection.generation - 1,
+        };
+        jobs.message(
+            &stale,
+            AgentMessage::RulesUpdateStatus {
+                id: job.id.clone(),
+                line: "stale".to_string(),
+            },
+        );
+        jobs.message(
+            &connection,
+            AgentMessage::RulesUpdateStatus {
+                id: job.id.clone(),
+                line: "23000 rules loaded".to_string(),
+            },
+        );
+        jobs.message(
+            &connection,
+            AgentMessage::RulesUpdateResult {
+                id: job.id.clone(),
+                result: RulesUpdateResult {
+                    exit_code: Some(0),
+                    reloaded: true,
+                    message: None,
+                },
+            },
+        );
+        let done = jobs.rules_update("sensor").unwrap();
+        assert_eq!(done.state, JobState::Complete);
+        assert_eq!(done.output, vec!["23000 rules loaded"]);
+        assert!(done.reloaded);
+        assert!(done.finished.is_some());
+    }
+
+    #[tokio::test]
+    async fn suricata_command_waits_for_the_reply_from_its_connection() {
+        let agents = AgentRegistry::default();
+        let jobs = Arc::new(AgentJobs::default());
+        let (_, _rx) = register(&agents, &[]);
+        assert_eq!(
+            jobs.suricata_command(&agents, "sensor", "uptime", None)
+                .await
+                .unwrap_err(),
+            CommandError::Unsupported
+        );
+
+        let agents = Arc::new(agents);
+        let (connection, mut rx) = register(&agents, &[CAPABILITY_SURICATA_COMMAND]);
+        let request = {
+            let (agents, jobs) = (agents.clone(), jobs.clone());
+            tokio::spawn(async move {
+                jobs.suricata_command(&agents, "sensor", "uptime", None)
+                    .await
+            })
+        };
+        let ServerMessage::SuricataCommand { id, command, .. } = rx.recv().await.unwrap() else {
+            panic!("expected a Suricata command");
+        };
+        assert_eq!(command, "uptime");
+        let reply = SuricataCommandResult {
+            response: Some(json!(42)),
+            error: None,
+        };
+        let stale = AgentConnectionId {
+            name: "sensor".to_string(),
+            generation: connection.generation - 1,
+        };
+        jobs.message(
+            &stale,
+            AgentMessage::SuricataCommandResult {
+                id: id.clone(),
+                result: SuricataCommandResult::default(),
+            },
+        );
+        jobs.message(
+            &connection,
+            AgentMessage::SuricataCommandResult {
+                id,
+                result: reply.clone(),
+            },
+        );
+        assert_eq!(request.await.unwrap().unwrap(), reply);
+
+        let request = {
+            let (agents, jobs) = (agents.clone(), jobs.clone());
+            tokio::spawn(async move {
+                jobs.suricata_command(&agents, "sensor", "uptime", None)
+                    .await
+            })
+        };
+        rx.recv().await.unwrap();
+        jobs.disconnected(&connection);
+        assert_eq!(
+            request.await.unwrap().unwrap_err(),
+            CommandError::Disconnected
+        );
+    }
+
+    #[test]
+    fn disconnect_fails_the_running_update() {
+        let agents = AgentRegistry::default();
+        let jobs = AgentJobs::default();
+        let (connection, _rx) = register(&agents, &[CAPABILITY_RULES_UPDATE]);
+        jobs.start_rules_update(&agents, "sensor", "admin", "-")
+            .unwrap();
+        jobs.disconnected(&connection);
+        let job = jobs.rules_update("sensor").unwrap();
+        assert_eq!(job.state, JobState::Failed);
+        assert_eq!(job.exit_code, None);
+        // A failed job does not block the next one.
+        jobs.start_rules_update(&agents, "sensor", "admin", "-")
+            .unwrap();
+    }
+}
@@ -20,6 +20,8 @@ use tokio_util::sync::CancellationToken;
 
 use crate::agent::protocol::{AgentHandshake, AgentMessage, CAPABILITY_PCAP, ServerMessage};
 use crate::datetime::DateTime;
+use crate::pcap::SpoolUsage;
+use crate::pcap::retention::ProtectedWindows;
 use crate::prelude::*;
 
 /// Capacity of each connection's outbound control-message queue.
@@ -87,6 +89,22 @@ impl AgentMessageHandler for IgnoreAgentMessages {
     fn message(&self, _connection: &AgentConnectionId, _message: AgentMessage) {}
 }
 
+/// Fans each message and lifecycle event out to several consumers, each of
+/// which ignores the message types it does not own.
+impl AgentMessageHandler for Vec<Arc<dyn AgentMessageHandler>> {
+    fn message(&self, connection: &AgentConnectionId, message: AgentMessage) {
+        for handler in self {
+            handler.message(connection, message.clone());
+        }
+    }
+
+    fn disconnected(&self, connection: &AgentConnectionId) {
+        for handler in self {
+            handler.disconnected(connection);
+        }
+    }
+}
+
 /// A connected agent and its outbound control-channel handle.
 pub(crate) struct AgentEntry {
     pub(crate) name: String,
@@ -112,6 +130,8 @@ pub(crate) struct AgentEntry {
     ping_request: Notify,
     /// Pong counter; liveness probes wait for the next increment.
     pongs: watch::Sender<u64>,
+    /// The spool usage last reported by the agent's retention.
+    spool_usage: RwLock<Option<SpoolUsage>>,
     shutdown: CancellationToken,
 }
 
@@ -131,6 +151,14 @@ impl AgentEntry {
         self.rtt_ms.store(rtt_ms, Ordering::Relaxed);
     }
 
+    pub(crate) fn set_spool_usage(&self, usage: SpoolUsage) {
+        *self.spool_usage.write().unwrap() = Some(usage);
+    }
+
+    pub(crate) fn spool_usage(&self) -> Option<SpoolUsage> {
+        *self.spool_usage.read().unwrap()
+    }
+
     pub(crate) fn supports(&self, capability: &str) -> bool {
         self.capabilities.iter().any(|value| value == capability)
     }
@@ -202,6 +230,10 @@ struct RegistryState {
     /// closes the race where an upgrade authenticated just before deletion but
     /// reaches registration just after the live connection was bumped.
     revoked_key_ids: HashSet<i64>,
+    /// The packet time ranges each agent's retention must keep, by agent
+    /// name; sent again after every reconnect. `None` until the server's
+    /// retention has computed them once.
+    protected: Option<HashMap<String, ProtectedWindows>>,
 }
 
 /// Live connected agents, keyed by their claimed name.
@@ -291,6 +323,7 @@ impl AgentRegistry {
             rtt_ms: AtomicU64::new(u64::MAX),
             ping_request: Notify::new(),
             pongs: watch::Sender::new(0),
+            spool_usage: RwLock::new(None),
             shutdown: CancellationToken::new(),
         });
 
@@ -350,6 +383,17 @@ impl AgentRegistry {
         }
     }
 
+    /// The connected agent authenticated with `key_id`, if any.
+    pub(crate) fn agent_for_key(&self, key_id: i64) -> Option<Arc<AgentEntry>> {
+        self.state
+            .read()
+            .unwrap()
+            .agents
+            .values()
+            .find(|entry| entry.key.as_ref().is_some_and(|key| key.id == key_id))
+            .cloned()
+    }
+
     /// Dispatch only while this exact generation is still the registry's
     /// current connection. Holding the registry read lock closes the race in
     /// which teardown notifies pending jobs and a stale-but-open MPSC sender
@@ -361,10 +405,10 @@ impl AgentRegistry {
         message: ServerMessage,
     ) -> Result<(), mpsc::error::TrySendError<ServerMessage>> {
         let state = self.state.read().unwrap();
-        if !state
+        if state
             .agents
             .get(&entry.name)
-            .is_some_and(|current| current.generation == entry.generation)
+            .is_none_or(|current| current.generation != entry.generation)
         {
             return Err(mpsc::error::TrySendError::Closed(message));
         }
@@ -405,6 +449,22 @@ impl AgentRegistry {
             .any(|entry| entry.supports(CAPABILITY_PCAP) && entry.name != LOCAL_PCAP_SOURCE_NAME)
     }
 
+    /// Replace the protected windows of every agent.
+    pub(crate) fn set_protected(&self, protected: HashMap<String, ProtectedWindows>) {
+        self.state.write().unwrap().protected = Some(protected);
+    }
+
+    /// The [`ServerMessage::PcapProtect`] for an agent, once the server
+    /// has computed the protected windows: an agent without any gets an
+    /// empty list.
+    pub(crate) fn protect_message(&self, name: &str) -> Option<ServerMessage> {
+        let state = self.state.read().unwrap();
+        let protected = state.protected.as_ref()?;
+        Some(ServerMessage::pcap_protect(
+            protected.get(name).unwrap_or(&ProtectedWindows::default()),
+        ))
+    }
+
     pub(crate) fn connected(&self) -> usize {
         self.state.read().unwrap().agents.len()
     }
@@ -0,0 +1,399 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! PCAP analysis on the server.
+//!
+//! A capture uploaded to `/api/analyze/pcap` is run through Suricata
+//! with the same backends as `evebox oneshot --pcap`, and the events are
+//! imported into the live datastore. Each event is given the analysis'
+//! synthetic sensor name as its `host` and stamped with the analysis id,
+//! so the uploaded capture serves its PCAP downloads.
+//!
+//! Every analysis keeps a directory under the analysis directory holding
+//! the capture and a JSON record, so finished analyses keep serving
+//! their packets across restarts.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::cli::oneshot::{PcapEveGenerator, SuricataOptions};
+use crate::prelude::*;
+use crate::server::ServerContext;
+
+const CAPTURE_FILENAME: &str = "capture.pcap";
+const RECORD_FILENAME: &str = "analysis.json";
+
+/// Events submitted between commits.
+const BATCH_SIZE: usize = 300;
+
+#[derive(Debug, Clone)]
+pub(crate) struct AnalyzeSettings {
+    /// Where analyses are kept.
+    pub(crate) directory: PathBuf,
+    /// Largest capture accepted for upload.
+    pub(crate) max_bytes: u64,
+    pub(crate) suricata: SuricataOptions,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum AnalysisState {
+    /// Waiting for an earlier analysis to finish.
+    Queued,
+    Running,
+    Complete,
+    Failed,
+}
+
+/// An analysis' record, as stored next to its capture and returned by
+/// the API.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Analysis {
+    pub(crate) id: String,
+    /// The `host` given to the imported events.
+    pub(crate) sensor: String,
+    /// The uploaded file's name.
+    pub(crate) filename: String,
+    pub(crate) bytes: u64,
+    pub(crate) sha256: String,
+    pub(crate) username: String,
+    /// RFC 3339 upload time.
+    pub(crate) created: String,
+    pub(crate) finished: Option<String>,
+    pub(crate) state: AnalysisState,
+    /// Events imported.
+    pub(crate) events: u64,
+    pub(crate) error: Option<String>,
+}
+
+pub(crate) struct Analyzer {
+    pub(crate) settings: AnalyzeSettings,
+    analyses: Mutex<HashMap<String, Analysis>>,
+    /// Held while Suricata runs, so analyses run one at a time.
+    running: tokio::sync::Mutex<()>,
+}
+
+impl Analyzer {
+    /// Open the analysis directory and load its records. An analysis
+    /// interrupted by a restart is marked failed.
+    pub(crate) fn open(settings: AnalyzeSettings) -> Result<Self> {
+        std::fs::create_dir_all(&settings.directory)
+            .map_err(|err| anyhow!("failed to create {}: {err}", settings.directory.display()))?;
+        let analyzer = Self {
+            settings,
+            analyses: Mutex::new(HashMap::new()),
+            running: tokio::sync::Mutex::new(()),
+        };
+        for entry in std::fs::read_dir(&analyzer.settings.directory)?.flatten() {
+            let path = entry.path().join(RECORD_FILENAME);
+            let mut analysis = match std::fs::read(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|json| Ok(serde_json::from_slice::<Analysis>(&json)?))
+            {
+                Ok(analysis) => analysis,
+                Err(err) => {
+                    if path.exists() {
+                        warn!("Ignoring analysis record {}: {err}", path.display());
+                    }
+                    continue;
+                }
+            };
+            if matches!(
+                analysis.state,
+                AnalysisState::Queued | AnalysisState::Running
+            ) {
+                analysis.state = AnalysisState::Failed;
+                analysis.finished = Some(crate::datetime::DateTime::now().to_rfc3339_utc());
+                analysis.error = Some("interrupted by a server restart".to_string());
+                if let Err(err) = analyzer.save(&analysis) {
+                    warn!("Failed to update analysis record {}: {err}", path.display());
+                }
+            }
+            analyzer
+                .analyses
+                .lock()
+                .unwrap()
+                .insert(analysis.id.clone(), analysis);
+        }
+        Ok(analyzer)
+    }
+
+    fn directory(&self, id: &str) -> PathBuf {
+        self.settings.directory.join(id)
+    }
+
+    pub(crate) fn capture_path(&self, id: &str) -> PathBuf {
+        self.directory(id).join(CAPTURE_FILENAME)
+    }
+
+    /// Reserve a new analysis: its id, sensor name, and an empty
+    /// directory for the capture.
+    pub(crate) fn create(&self, username: &str, filename: &str) -> std::io::Result<Analysis> {
+        let id = uuid::Uuid::new_v4().to_string();
+        std::fs::create_dir_all(self.directory(&id))?;
+        Ok(Analysis {
+            sensor: format!("analysis-{}", &id[..8]),
+            id,
+            filename: filename.to_string(),
+            bytes: 0,
+            sha256: String::new(),
+            username: username.to_string(),
+            created: crate::datetime::DateTime::now().to_rfc3339_utc(),
+            finished: None,
+            state: AnalysisState::Queued,
+            events: 0,
+            error: None,
+        })
+    }
+
+    /// Remove a reserved analysis whose upload failed.
+    pub(crate) fn discard(&self, id: &str) {
+        if let Err(err) = std::fs::remove_dir_all(self.directory(id)) {
+            warn!("Failed to remove analysis directory for {id}: {err}");
+        }
+    }
+
+    /// Store an analysis' record, keeping the last one held when the
+    /// file cannot be written.
+    pub(crate) fn update(&self, analysis: &Analysis) {
+        if let Err(err) = self.save(analysis) {
+            error!(
+                "Failed to write the record of analysis {}: {err}",
+                analysis.id
+            );
+        }
+        self.analyses
+            .lock()
+            .unwrap()
+            .insert(analysis.id.clone(), analysis.clone());
+    }
+
+    /// Replace the record atomically, so a crash never leaves a partial
+    /// one.
+    fn save(&self, analysis: &Analysis) -> std::io::Result<()> {
+        let directory = self.directory(&analysis.id);
+        let mut file = tempfile::NamedTempFile::new_in(&directory)?;
+        file.write_all(&serde_json::to_vec_pretty(analysis)?)?;
+        file.persist(directory.join(RECORD_FILENAME))
+            .map_err(|err| err.error)?;
+        Ok(())
+    }
+
+    pub(crate) fn get(&self, id: &str) -> Option<Analysis> {
+        self.analyses.lock().unwrap().get(id).cloned()
+    }
+
+    /// Every analysis, newest first.
+    pub(crate) fn list(&self) -> Vec<Analysis> {
+        let mut analyses: Vec<Analysis> = self.analyses.lock().unwrap().values().cloned().collect();
+        analyses.sort_by(|a, b| b.created.cmp(&a.created));
+        analyses
+    }
+
+    /// The finished analyses whose capture is still present.
+    pub(crate) fn captures(&self) -> Vec<(Analysis, PathBuf)> {
+        self.list()
+            .into_iter()
+            .filter(|analysis| analysis.state == AnalysisState::Complete)
+            .map(|analysis| {
+                let path = self.capture_path(&analysis.id);
+                (analysis, path)
+            })
+            .filter(|(_, path)| path.exists())
+            .collect()
+    }
+}
+
+/// Run an uploaded capture through Suricata and import its events,
+/// then serve its packets under the analysis' sensor name.
+pub(crate) async fn run(
+    context: Arc<ServerContext>,
+    analyzer: Arc<Analyzer>,
+    mut analysis: Analysis,
+) {
+    let _running = analyzer.running.lock().await;
+    analysis.state = AnalysisState::Running;
+    analyzer.update(&analysis);
+
+    let result = analyze(&context, &analyzer, &mut analysis).await;
+    analysis.finished = Some(crate::datetime::DateTime::now().to_rfc3339_utc());
+    match result {
+        Ok(()) => {
+            info!(
+                "analyze: user={:?} analysis={} sensor={} events={} outcome=complete",
+                analysis.username, analysis.id, analysis.sensor, analysis.events
+            );
+            analysis.state = AnalysisState::Complete;
+            context.pcap.add_analysis(
+                &analysis.id,
+                &analysis.sensor,
+                vec![analyzer.capture_path(&analysis.id)],
+            );
+        }
+        Err(err) => {
+            warn!(
+                "analyze: user={:?} analysis={} events={} outcome=failed message={:?}",
+                analysis.username,
+                analysis.id,
+                analysis.events,
+                format!("{err:#}")
+            );
+            analysis.state = AnalysisState::Failed;
+            analysis.error = Some(format!("{err:#}"));
+        }
+    }
+    analyzer.update(&analysis);
+}
+
+async fn analyze(
+    context: &ServerContext,
+    analyzer: &Analyzer,
+    analysis: &mut Analysis,
+) -> Result<()> {
+    // The workspace holds the rules and Suricata output, and goes once
+    // the events are imported.
+    let workspace = tempfile::Builder::new()
+        .prefix("workspace-")
+        .tempdir_in(analyzer.directory(&analysis.id))
+        .context("failed to create the analysis workspace")?;
+    let generator =
+        PcapEveGenerator::prepare(&analyzer.settings.suricata, workspace.path()).await?;
+    let eve = generator
+        .generate_eve(&analyzer.capture_path(&analysis.id), workspace.path())
+        .await?;
+    import(context, analyzer, analysis, &eve).await
+}
+
+pub(crate) async fn import(
+    context: &ServerContext,
+    analyzer: &Analyzer,
+    analysis: &mut Analysis,
+    eve: &Path,
+) -> Result<()> {
+    let mut importer = context
+        .datastore
+        .get_importer()
+        .ok_or_else(|| anyhow!("the datastore does not support importing events"))?;
+    let mut reader = crate::eve::reader::EveReader::new(eve.to_path_buf());
+    while let Some(mut event) = reader.next_file_record()? {
+        stamp(&mut event, analysis);
+        if let Some(filters) = &context.filters {
+            filters.run(&mut event);
+        }
+        importer.submit(event).await?;
+        analysis.events += 1;
+        if importer.pending() >= BATCH_SIZE {
+            importer.commit().await?;
+            analyzer.update(analysis);
+        }
+    }
+    importer.commit().await?;
+    Ok(())
+}
+
+/// Give an event its analysis' sensor name and id.
+fn stamp(event: &mut serde_json::Value, analysis: &Analysis) {
+    event["host"] = analysis.sensor.clone().into();
+    crate::eve::eve::ensure_has_evebox(event);
+    event["evebox"]["analysis"] = json!({
+        "id": analysis.id,
+        "filename": analysis.filename,
+    });
+}
+
+/// Read `analyze`. Analysis is off unless `enabled` is set; analyses
+/// are kept in `analyze` in the data directory by default.
+pub(crate) fn configure(
+    config: &crate::config::Config,
+    data_directory: Option<&str>,
+) -> Result<Option<AnalyzeSettings>> {
+    if !config.get_bool("analyze.enabled")? {
+        return Ok(None);
+    }
+    let directory = match config
+        .get::<String>("analyze.directory")?
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+    {
+        Some(directory) => PathBuf::from(directory),
+        None => match data_directory {
+            Some(data_directory) => PathBuf::from(data_directory).join("analyze"),
+            None => bail!("analyze requires a data directory or analyze.directory"),
+        },
+    };
+    let max_bytes = match config.get::<String>("analyze.max-size")? {
+        Some(size) => crate::pcap::retention::parse_size(&size)
+            .map_err(|err| anyhow!("bad analyze.max-size: {err}"))?,
+        None => 1024 * 1024 * 1024,
+    };
+    let suricata = SuricataOptions::from_config(config, "analyze")?;
+    info!(
+        "PCAP analysis enabled, keeping analyses in {}",
+        directory.display()
+    );
+    Ok(Some(AnalyzeSettings {
+        directory,
+        max_bytes,
+        suricata,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn settings(directory: &Path) -> AnalyzeSettings {
+        AnalyzeSettings {
+            directory: directory.to_path_buf(),
+            max_bytes: 1024,
+            suricata: SuricataOptions::default(),
+        }
+    }
+
+    #[test]
+    fn records_survive_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let analyzer = Analyzer::open(settings(dir.path())).unwrap();
+
+        let mut complete = analyzer.create("admin", "first.pcap").unwrap();
+        assert!(complete.sensor.starts_with("analysis-"));
+        complete.state = AnalysisState::Complete;
+        complete.events = 12;
+        analyzer.update(&complete);
+        std::fs::write(analyzer.capture_path(&complete.id), b"pcap").unwrap();
+
+        let mut running = analyzer.create("admin", "second.pcap").unwrap();
+        running.state = AnalysisState::Running;
+        analyzer.update(&running);
+
+        let discarded = analyzer.create("admin", "third.pcap").unwrap();
+        analyzer.discard(&discarded.id);
+
+        let analyzer = Analyzer::open(settings(dir.path())).unwrap();
+        assert_eq!(analyzer.list().len(), 2);
+        assert_eq!(analyzer.get(&complete.id).unwrap(), complete);
+        let interrupted = analyzer.get(&running.id).unwrap();
+        assert_eq!(interrupted.state, AnalysisState::Failed);
+        assert!(interrupted.error.is_some());
+
+        let captures = analyzer.captures();
+        assert_eq!(captures.len(), 1);
+        assert_eq!(captures[0].0.id, complete.id);
+    }
+
+    #[test]
+    fn events_are_stamped_with_their_analysis() {
+        let dir = tempfile::tempdir().unwrap();
+        let analyzer = Analyzer::open(settings(dir.path())).unwrap();
+        let analysis = analyzer.create("admin", "capture.pcap").unwrap();
+        let mut event = json!({ "event_type": "alert", "host": "suricata" });
+        stamp(&mut event, &analysis);
+        assert_eq!(event["host"], analysis.sensor.as_str());
+        assert_eq!(
+            crate::server::pcap::stamped_analysis_id(&event),
+            Some(analysis.id.as_str())
+        );
+        assert_eq!(event["evebox"]["analysis"]["filename"], "capture.pcap");
+    }
+}
@@ -7,15 +7,24 @@ use axum::Form;
 use axum::response::IntoResponse;
 use axum::{Extension, Json, extract::Path};
 
+use crate::agent::protocol::{CAPABILITY_CONFIG, WireAgentConfig};
+use crate::server::audit::{Auditor, Outcome};
+use crate::server::session::{Role, Session};
+use crate::server::totp;
 use crate::server::{ServerContext, main::SessionExtractor};
 use crate::sqlite::configdb::{AgentKey, EventFilter, FilterEntry, FilterRow};
 
 pub(super) async fn update_ja4db(
     Extension(context): Extension<Arc<ServerContext>>,
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
+    audit: Auditor,
 ) -> Result<Json<serde_json::Value>, AppError> {
     info!("API request to update JA4 database");
-    match do_update(context).await {
+    let result = do_update(context).await;
+    audit
+        .result(&session, "ja4db.update", None, json!({}), &result)
+        .await;
+    match result {
         Ok(response) => {
             info!("JA4db updated");
             Ok(response)
@@ -42,29 +51,58 @@ async fn do_update(context: Arc<ServerContext>) -> Result<Json<serde_json::Value
 /// For now just use the FilterEntry from configdb as the form
 /// type. But that may need to change as we extend this.
 pub(super) async fn add_filter(
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
     Extension(context): Extension<Arc<ServerContext>>,
+    audit: Auditor,
     Form(mut entry): Form<FilterEntry>,
 ) -> Result<impl IntoResponse, AppError> {
     let comment = entry.comment.take();
     let filter = EventFilter::from(&entry);
-    let mut tx = context.configdb.pool.begin().await?;
+    let parameters = json!({"filter": &filter, "comment": &comment});
 
-    if let Ok(filters) = context.auto_archive.read()
-        && filters.contains(&filter)
-    {
+    let exists = context
+        .auto_archive
+        .read()
+        .is_ok_and(|filters| filters.contains(&filter));
+    if exists {
         info!("Archive filters already contain {:?}", &filter);
+        audit
+            .record(
+                &session,
+                "filter.add",
+                None,
+                parameters,
+                Outcome::Rejected("the filter already exists".to_string()),
+            )
+            .await;
         return Ok(Json(json!({})));
     }
 
-    let sql = "INSERT INTO filters (user_id, filter, comment) VALUES (?, ?, ?)";
-    sqlx::query(sql)
-        .bind(0)
-        .bind(sqlx::types::Json(&filter))
-        .bind(&comment)
-        .execute(&mut *tx)
-        .await?;
-    tx.commit().await?;
+    let result = async {
+        let mut tx = context.configdb.pool.begin().await?;
+        let sql = "INSERT INTO filters (user_id, filter, comment) VALUES (?, ?, ?)";
+        let id = sqlx::query(sql)
+            .bind(0)
+            .bind(sqlx::types::Json(&filter))
+            .bind(&comment)
+            .execute(&mut *tx)
+            .await?
+            .last_insert_rowid();
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(id)
+    }
+    .await;
+    let target = result.as_ref().ok().map(|id| id.to_string());
+    audit
+        .result(
+            &session,
+            "filter.add",
+            target.as_deref(),
+            parameters,
+            &result,
+        )
+        .await;
+    result?;
 
     let mut ingest = context.auto_archive.write().unwrap();
     ingest.add(&filter);
@@ -86,26 +124,51 @@ pub(super) async fn get_filters(
 }
 
 pub(super) async fn delete_filter(
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
     Extension(context): Extension<Arc<ServerContext>>,
+    audit: Auditor,
     Path(id): Path<u32>,
 ) -> Result<impl IntoResponse, AppError> {
     // Remove from database.
-    let mut tx = context.configdb.pool.begin().await?;
-    let row: Option<FilterRow> =
-        sqlx::query_as::<_, FilterRow>("SELECT * FROM filters WHERE id = ?")
-            .bind(id)
-            .fetch_optional(&mut *tx)
-            .await?;
-    if row.is_some()
-        && sqlx::query("DELETE FROM filters WHERE id = ?")
-            .bind(id)
-            .execute(&mut *tx)
-            .await
-            .is_ok()
-    {
-        tx.commit().await?;
+    let result = async {
+        let mut tx = context.configdb.pool.begin().await?;
+        let row: Option<FilterRow> =
+            sqlx::query_as::<_, FilterRow>("SELECT * FROM filters WHERE id = ?")
+                .bind(id)
+                .fetch_optional(&mut *tx)
+                .await?;
+        if row.is_some() {
+            sqlx::query("DELETE FROM filters WHERE id = ?")
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+        }
+        Ok::<_, sqlx::Error>(row)
     }
+    .await;
+    let target = id.to_string();
+    let (parameters, outcome) = match &result {
+        Ok(Some(row)) => (
+            json!({"filter": &row.filter.0, "comment": &row.comment}),
+            Outcome::Ok,
+        ),
+        Ok(None) => (
+            json!({}),
+            Outcome::Rejected(format!("no filter with id {id}")),
+        ),
+        Err(err) => (json!({}), Outcome::Error(err.to_string())),
+    };
+    audit
+        .record(
+            &session,
+            "filter.delete",
+            Some(&target),
+            parameters,
+            outcome,
+        )
+        .await;
+    let row = result?;
 
     // Remove from current ingest processing.
     if let Some(row) = row {
@@ -116,25 +179,141 @@ pub(super) async fn delete_filter(
     Ok(Json(json!({})))
 }
 
+/// Days before expiry at which the key listing starts warning, unless
+/// `agents.key-expiry-warning-days` says otherwise.
+const DEFAULT_KEY_EXPIRY_WARNING_DAYS: u64 = 14;
+
+/// Days without contact after which the key listing warns about an idle
+/// key, unless `agents.key-unused-warning-days` says otherwise.
+const DEFAULT_KEY_UNUSED_WARNING_DAYS: u64 = 30;
+
+/// A condition on an agent key an operator should act on.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub(crate) struct AgentKeyWarning {
+    pub code: &'static str,
+    pub message: String,
+}
+
 /// One row in the `GET /api/agents/keys` listing: everything but the
-/// key value itself, which stays reveal-on-demand.
+/// key values themselves, which stay reveal-on-demand.
 #[derive(Debug, Serialize)]
 pub(crate) struct AgentKeyInfo {
     pub id: i64,
     pub name: String,
     pub created_at: crate::datetime::ChronoDateTime,
     pub last_seen: Option<crate::datetime::ChronoDateTime>,
+    pub expires_at: Option<crate::datetime::ChronoDateTime>,
+    pub rotated_at: Option<crate::datetime::ChronoDateTime>,
+    pub previous_key_expires_at: Option<crate::datetime::ChronoDateTime>,
+    pub cert_fingerprint: Option<String>,
+    pub cert_subject: Option<String>,
+    pub warnings: Vec<AgentKeyWarning>,
 }
 
-impl From<AgentKey> for AgentKeyInfo {
-    fn from(row: AgentKey) -> Self {
+impl AgentKeyInfo {
+    fn new(row: AgentKey, config: &crate::server::ServerConfig) -> Self {
+        let warnings = agent_key_warnings(
+            &row,
+            chrono::Utc::now().fixed_offset(),
+            config
+                .agent_key_expiry_warning_days
+                .unwrap_or(DEFAULT_KEY_EXPIRY_WARNING_DAYS),
+            config
+                .agent_key_unused_warning_days
+                .unwrap_or(DEFAULT_KEY_UNUSED_WARNING_DAYS),
+        );
         Self {
             id: row.id,
             name: row.name,
             created_at: row.created_at,
             last_seen: row.last_seen,
+            expires_at: row.expires_at,
+            rotated_at: row.rotated_at,
+            // A lapsed grace period is history, not state.
+            previous_key_expires_at: row
+                .previous_key_expires_at
+                .filter(|expires| *expires > chrono::Utc::now()),
+            cert_fingerprint: row.cert_fingerprint,
+            cert_subject: row.cert_subject,
+            warnings,
+        }
+    }
+}
+
+fn agent_key_warnings(
+    row: &AgentKey,
+    now: crate::datetime::ChronoDateTime,
+    expiry_days: u64,
+    unused_days: u64,
+) -> Vec<AgentKeyWarning> {
+    let mut warnings = Vec::new();
+    if let Some(expires_at) = row.expires_at {
+        let remaining = expires_at - now;
+        if remaining <= chrono::TimeDelta::zero() {
+            warnings.push(AgentKeyWarning {
+                code: "expired",
+                message: "the key has expired; rotate it to reconnect the agent".to_string(),
+            });
+        } else if remaining.num_days() < expiry_days as i64 {
+            let message = match remaining.num_days() {
+                0 => "the key expires in less than a day".to_string(),
+                1 => "the key expires in 1 day".to_string(),
+                days => format!("the key expires in {days} days"),
+            };
+            warnings.push(AgentKeyWarning {
+                code: "expiring",
+                message,
+            });
         }
     }
+    if let Some(previous) = row.previous_key_expires_at
+        && previous > now
+    {
+        warnings.push(AgentKeyWarning {
+            code: "previous-key-valid",
+            message: format!(
+                "the key replaced at the last rotation is accepted until {}",
+                previous.to_rfc3339()
+            ),
+        });
+    }
+    let idle = (now - row.last_seen.unwrap_or(row.created_at)).num_days();
+    if unused_days > 0 && idle >= unused_days as i64 {
+        let message = if row.last_seen.is_some() {
+            format!("no agent has used the key for {idle} days")
+        } else {
+            format!("no agent has used the key since it was created {idle} days ago")
+        };
+        warnings.push(AgentKeyWarning {
+            code: "unused",
+            message,
+        });
+    }
+    warnings
+}
+
+/// Parse an optional duration from a key or token request, such as
+/// `"90d"`.
+pub(super) fn parse_key_duration(
+    field: &str,
+    value: Option<&str>,
+) -> Result<Option<std::time::Duration>, AppError> {
+    value
+        .map(|value| {
+            humantime::parse_duration(value)
+                .map_err(|err| AppError::BadRequest(format!("invalid {field} {value:?}: {err}")))
+        })
+        .transpose()
+}
+
+/// The audit outcome of an action on agent key `id`, which found no key
+/// when the result is `None`.
+fn key_outcome<T, E: std::fmt::Display>(id: i64, result: &Result<Option<T>, E>) -> Outcome {
+    match result {
+        Ok(Some(_)) => Outcome::Ok,
+        Ok(None) => Outcome::Rejected(format!("no agent key with id {id}")),
+        Err(err) => Outcome::Error(err.to_string()),
+    }
 }
 
 fn no_agent_key(id: i64) -> axum::response::Response {
@@ -155,7 +334,7 @@ pub(super) async fn get_agent_keys(
         .list_agent_keys()
         .await?
         .into_iter()
-        .map(AgentKeyInfo::from)
+        .map(|row| AgentKeyInfo::new(row, &context.config))
         .collect();
     Ok(Json(rows))
 }
@@ -163,21 +342,127 @@ pub(super) async fn get_agent_keys(
 #[derive(Debug, Deserialize)]
 pub(crate) struct AddAgentKeyRequest {
     pub name: String,
+    /// How long the key is valid, such as `"90d"`. No expiry when absent.
+    pub expires: Option<String>,
 }
 
 /// `POST /api/agents/keys`: create an agent key. The response is the
 /// full row including the key value for first-time provisioning; the key
 /// stays re-showable later through the reveal endpoint.
 pub(super) async fn add_agent_key(
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
     Extension(context): Extension<Arc<ServerContext>>,
+    audit: Auditor,
     Json(request): Json<AddAgentKeyRequest>,
 ) -> Result<impl IntoResponse, AppError> {
-    let added = context.configdb.add_agent_key(&request.name).await?;
-    info!("Agent key {:?} added from the admin API", added.name);
+    let expires = parse_key_duration("expires", request.expires.as_deref())?;
+    let result = context.configdb.add_agent_key(&request.name, expires).await;
+    let target = result.as_ref().ok().map(|added| added.id.to_string());
+    audit
+        .result(
+            &session,
+            "agent-key.add",
+            target.as_deref(),
+            json!({"name": request.name, "expires": request.expires}),
+            &result,
+        )
+        .await;
+    let added = result?;
+    info!(
+        "Agent key {:?} added from the admin API (expires: {:?})",
+        added.name, added.expires_at
+    );
     Ok(Json(added))
 }
 
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct RotateAgentKeyRequest {
+    /// How long the replaced key stays valid, such as `"24h"`. Absent or
+    /// `"0s"` revokes it immediately.
+    pub grace: Option<String>,
+    /// How long the new key is valid. No expiry when absent.
+    pub expires: Option<String>,
+}
+
+/// `POST /api/agents/keys/{id}/rotate`: replace the key, keeping the
+/// agent's identity and configuration. The response is the full row with
+/// the new key. An agent connected with the replaced key is disconnected
+/// once the grace period ends and must reconnect with the new key.
+pub(super) async fn rotate_agent_key(
+    SessionExtractor(session): SessionExtractor,
+    Extension(context): Extension<Arc<ServerContext>>,
+    audit: Auditor,
+    Path(id): Path<i64>,
+    request: Option<Json<RotateAgentKeyRequest>>,
+) -> Result<impl IntoResponse, AppError> {
+    let Json(request) = request.unwrap_or_default();
+    let grace = parse_key_duration("grace", request.grace.as_deref())?.unwrap_or_default();
+    let expires = parse_key_duration("expires", request.expires.as_deref())?;
+    let result = context.configdb.rotate_agent_key(id, grace, expires).await;
+    audit
+        .record(
+            &session,
+            "agent-key.rotate",
+            Some(&id.to_string()),
+            json!({"grace": request.grace, "expires": request.expires}),
+            key_outcome(id, &result),
+        )
+        .await;
+    match result? {
+        Some(row) => {
+            info!(
+                "Agent key {:?} rotated from the admin API (previous key valid until: {:?}, expires: {:?})",
+                row.name, row.previous_key_expires_at, row.expires_at
+            );
+            Ok(Json(row).into_response())
+        }
+        None => Ok(no_agent_key(id)),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct AgentKeyExpiryRequest {
+    /// How long from now the key stays valid; null removes the expiry.
+    pub expires: Option<String>,
+}
+
+/// `PUT /api/agents/keys/{id}/expiry`: set or clear the current key's
+/// expiry.
+pub(super) async fn put_agent_key_expiry(
+    SessionExtractor(session): SessionExtractor,
+    Extension(context): Extension<Arc<ServerContext>>,
+    audit: Auditor,
+    Path(id): Path<i64>,
+    Json(request): Json<AgentKeyExpiryRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let expires = parse_key_duration("expires", request.expires.as_deref())?;
+    let result = context
+        .configdb
+        .set_agent_key_expiry(id, expires)
+        .await
+        .map(|found| found.then_some(()));
+    audit
+        .record(
+            &session,
+            "agent-key.expiry",
+            Some(&id.to_string()),
+            json!({"expires": request.expires}),
+            key_outcome(id, &result),
+        )
+        .await;
+    if result?.is_none() {
+        return Ok(no_agent_key(id));
+    }
+    let Some(row) = context.configdb.get_agent_key_by_id(id).await? else {
+        return Ok(no_agent_key(id));
+    };
+    info!(
+        "Agent key {:?} expiry set from the admin API: {:?}",
+        row.name, row.expires_at
+    );
+    Ok(Json(AgentKeyInfo::new(row, &context.config)).into_response())
+}
+
 /// `GET /api/agents/keys/{id}`: the full row including the key value.
 /// This is the reveal-on-demand endpoint; the listing never carries keys.
 pub(super) async fn get_agent_key(
@@ -195,13 +480,25 @@ pub(super) async fn get_agent_key(
 /// agent currently authenticated with it. The agent's reconnect attempt will
 /// then fail because the key no longer exists.
 pub(super) async fn delete_agent_key(
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
     Extension(context): Extension<Arc<ServerContext>>,
+    audit: Auditor,
     Path(id): Path<i64>,
 ) -> Result<impl IntoResponse, AppError> {
+    let target = id.to_string();
     match context.configdb.get_agent_key_by_id(id).await? {
         Some(row) => {
-            context.configdb.remove_agent_key_by_id(id).await?;
+            let result = context.configdb.remove_agent_key_by_id(id).await;
+            audit
+                .result(
+                    &session,
+                    "agent-key.delete",
+                    Some(&target),
+                    json!({"name": row.name}),
+                    &result,
+                )
+                .await;
+            result?;
             let disconnected = context.agents.revoke_key(id);
             info!(
                 "Agent key {:?} removed from the admin API (connected agent bumped: {disconnected})",
@@ -209,8 +506,132 @@ pub(super) async fn delete_agent_key(
             );
             Ok(Json(json!({})).into_response())
         }
-        None => Ok(no_agent_key(id)),
+        None => {
+            audit
+                .record(
+                    &session,
+                    "agent-key.delete",
+                    Some(&target),
+                    json!({}),
+                    Outcome::Rejected(format!("no agent key with id {id}")),
+                )
+                .await;
+            Ok(no_agent_key(id))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct AgentKeyCertificateRequest {
+    /// SHA-256 fingerprint of the agent's client certificate.
+    pub fingerprint: Option<String>,
+    /// Subject common name accepted for the agent's client certificates.
+    pub subject: Option<String>,
+}
+
+/// `PUT /api/agents/keys/{id}/certificate`: map client certificates to
+/// the agent holding this key. Absent fields clear their mapping.
+pub(super) async fn put_agent_key_certificate(
+    SessionExtractor(session): SessionExtractor,
+    Extension(context): Extension<Arc<ServerContext>>,
+    audit: Auditor,
+    Path(id): Path<i64>,
+    Json(request): Json<AgentKeyCertificateRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let result = context
+        .configdb
+        .set_agent_key_certificate(
+            id,
+            request.fingerprint.as_deref(),
+            request.subject.as_deref(),
+        )
+        .await
+        .map(|found| found.then_some(()));
+    audit
+        .record(
+            &session,
+            "agent-key.certificate",
+            Some(&id.to_string()),
+            json!({"fingerprint": request.fingerprint, "subject": request.subject}),
+            key_outcome(id, &result),
+        )
+        .await;
+    if result?.is_none() {
+        return Ok(no_agent_key(id));
+    }
+    let Some(row) = context.configdb.get_agent_key_by_id(id).await? else {
+        return Ok(no_agent_key(id));
+    };
+    info!(
+        "Agent key {:?} client certificate set from the admin API: fingerprint={:?} subject={:?}",
+        row.name, row.cert_fingerprint, row.cert_subject
+    );
+    Ok(Json(AgentKeyInfo::new(row, &context.config)).into_response())
+}
+
+/// `GET /api/agents/keys/{id}/config`: the server-managed configuration
+/// for the agent holding this key, and the version it last applied.
+pub(super) async fn get_agent_config(
+    _session: SessionExtractor,
+    Extension(context): Extension<Arc<ServerContext>>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    if context.configdb.get_agent_key_by_id(id).await?.is_none() {
+        return Ok(no_agent_key(id));
     }
+    match context.configdb.get_agent_config(id).await? {
+        Some(row) => Ok(Json(row).into_response()),
+        None => Ok(Json(serde_json::Value::Null).into_response()),
+    }
+}
+
+/// `PUT /api/agents/keys/{id}/config`: store a new configuration version
+/// and push it to the agent if it is connected. A disconnected agent
+/// receives it on its next connect.
+pub(super) async fn put_agent_config(
+    SessionExtractor(session): SessionExtractor,
+    Extension(context): Extension<Arc<ServerContext>>,
+    audit: Auditor,
+    Path(id): Path<i64>,
+    Json(config): Json<WireAgentConfig>,
+) -> Result<impl IntoResponse, AppError> {
+    let target = id.to_string();
+    let parameters = serde_json::to_value(&config).unwrap_or_default();
+    let Some(key) = context.configdb.get_agent_key_by_id(id).await? else {
+        audit
+            .record(
+                &session,
+                "agent-key.config",
+                Some(&target),
+                parameters,
+                Outcome::Rejected(format!("no agent key with id {id}")),
+            )
+            .await;
+        return Ok(no_agent_key(id));
+    };
+    let result = context.configdb.set_agent_config(id, &config).await;
+    audit
+        .result(
+            &session,
+            "agent-key.config",
+            Some(&target),
+            parameters,
+            &result,
+        )
+        .await;
+    let row = result?;
+    let pushed = match context.agents.agent_for_key(id) {
+        Some(entry) if entry.supports(CAPABILITY_CONFIG) => context
+            .agents
+            .try_send_current(&entry, super::agent::config_message(&row))
+            .is_ok(),
+        _ => false,
+    };
+    info!(
+        "Configuration version {} saved for agent {:?} (pushed: {pushed})",
+        row.version, key.name
+    );
+    Ok(Json(row).into_response())
 }
 
 pub(super) async fn kv_get_config(
@@ -222,24 +643,412 @@ pub(super) async fn kv_get_config(
 }
 
 pub(super) async fn kv_set_config(
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
     Extension(context): Extension<Arc<ServerContext>>,
+    audit: Auditor,
     Path(key): Path<String>,
     Json(value): Json<serde_json::Value>,
 ) -> Result<impl IntoResponse, AppError> {
+    let parameters = json!({"value": &value});
     // The pcap routing table shares this kv namespace but has its own
     // endpoint that validates the table, applies it to the live
     // service, and serializes saves; a raw write here would bypass all
     // three and silently diverge the stored and live tables.
-    if key == "config.pcap.routing" {
-        return Err(AppError::BadRequest(
-            "use /api/pcap/routing to modify the pcap routing table".to_string(),
-        ));
+    let invalid = if key == "config.pcap.routing" {
+        Some("use /api/pcap/routing to modify the pcap routing table".to_string())
+    } else if key == totp::POLICY_KEY {
+        serde_json::from_value::<totp::Policy>(value.clone())
+            .err()
+            .map(|err| format!("invalid {key}: {err}"))
+    } else {
+        None
+    };
+    if let Some(message) = invalid {
+        audit
+            .record(
+                &session,
+                "config.set",
+                Some(&key),
+                parameters,
+                Outcome::Rejected(message.clone()),
+            )
+            .await;
+        return Err(AppError::BadRequest(message));
     }
-    context.configdb.kv_set_config(&key, &value).await?;
+    let result = context.configdb.kv_set_config(&key, &value).await;
+    audit
+        .result(&session, "config.set", Some(&key), parameters, &result)
+        .await;
+    result?;
     Ok(())
 }
 
+/// `GET /api/admin/users`: every user with their role, the sensors they
+/// are limited to, whether they have TOTP enabled and whether they must
+/// change their password.
+pub(super) async fn get_users(
+    _session: SessionExtractor,
+    Extension(context): Extension<Arc<ServerContext>>,
+) -> Result<impl IntoResponse, AppError> {
+    let totp_users = context.configdb.get_totp_users().await?;
+    let users: Vec<serde_json::Value> = context
+        .configdb
+        .get_users()
+        .await?
+        .into_iter()
+        .filter(|user| user.username != SYSTEM_USER)
+        .map(|user| {
+            json!({
+                "username": user.username,
+                "role": user.role,
+                "sensors": user.sensors,
+                "totp": totp_users.contains(&user.username),
+                "password_change_required": user.password_change_required,
+            })
+        })
+        .collect();
+    Ok(Json(users))
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct AddUserRequest {
+    pub username: String,
+    pub password: String,
+    pub role: Role,
+    /// Make the user change the password at their first login.
+    #[serde(default)]
+    pub password_change_required: bool,
+}
+
+/// `POST /api/admin/users`: add a user, with a password meeting the
+/// password policy.
+pub(super) async fn add_user(
+    SessionExtractor(session): SessionExtractor,
+    Extension(context): Extension<Arc<ServerContext>>,
+    audit: Auditor,
+    Json(request): Json<AddUserRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let username = request.username.trim();
+    let parameters = json!({
+        "role": request.role,
+        "password_change_required": request.password_change_required,
+    });
+    let refusal = if username.is_empty() || username == SYSTEM_USER {
+        Some((
+            StatusCode::BAD_REQUEST,
+            format!("invalid username {:?}", request.username),
+        ))
+    } else if let Some(error) = context.config.password.check(username, &request.password) {
+        Some((StatusCode::BAD_REQUEST, error))
+    } else if context.configdb.get_user_by_name(username).await.is_ok() {
+        Some((
+            StatusCode::CONFLICT,
+            format!("user {username:?} already exists"),
+        ))
+    } else {
+        None
+    };
+    if let Some(refusal) = refusal {
+        return Ok(rejected(&audit, &session, "user.add", username, parameters, refusal).await);
+    }
+    let result = async {
+        context
+            .configdb
+            .add_user(username, &request.password, request.role)
+            .await?;
+        if request.password_change_required {
+            context
+                .configdb
+                .set_password_change_required(username, true)
+                .await?;
+        }
+        Ok::<_, crate::sqlite::configdb::ConfigDbError>(())
+    }
+    .await;
+    audit
+        .result(&session, "user.add", Some(username), parameters, &result)
+        .await;
+    result?;
+    info!(
+        "User {:?} added with role {} by {:?}",
+        username, request.role, session.username
+    );
+    Ok(Json(json!({
+        "username": username,
+        "role": request.role,
+        "password_change_required": request.password_change_required,
+    }))
+    .into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct SetRoleRequest {
+    pub role: Role,
+}
+
+/// `PUT /api/admin/users/{username}/role`: change a user's role. The
+/// user's sessions pick up the new role on their next request.
+pub(super) async fn put_user_role(
+    SessionExtractor(session): SessionExtractor,
+    Extension(context): Extension<Arc<ServerContext>>,
+    audit: Auditor,
+    Path(username): Path<String>,
+    Json(request): Json<SetRoleRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let Ok(user) = context.configdb.get_user_by_name(&username).await else {
+        let parameters = json!({"role": request.role});
+        let refusal = no_user(&username);
+        return Ok(rejected(
+            &audit,
+            &session,
+            "user.role",
+            &username,
+            parameters,
+            refusal,
+        )
+        .await);
+    };
+    let parameters = json!({"role": request.role, "previous": user.role});
+    if user.role == Role::Admin
+        && request.role != Role::Admin
+        && context.configdb.count_admins().await? <= 1
+    {
+        return Ok(rejected(
+            &audit,
+            &session,
+            "user.role",
+            &username,
+            parameters,
+            last_admin(),
+        )
+        .await);
+    }
+    let result = context
+        .configdb
+        .set_user_role(&username, request.role)
+        .await;
+    audit
+        .result(&session, "user.role", Some(&username), parameters, &result)
+        .await;
+    result?;
+    context.session_store.evict_user(&username);
+    info!(
+        "Role of user {:?} changed from {} to {} by {:?}",
+        username, user.role, request.role, session.username
+    );
+    Ok(Json(json!({"username": username, "role": request.role})).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct SetSensorsRequest {
+    /// The sensors, by host name, whose events the user may see; null
+    /// for all of them.
+    pub sensors: Option<Vec<String>>,
+}
+
+/// `PUT /api/admin/users/{username}/sensors`: limit a user to the
+/// events of some sensors, or with null let them see every sensor. The
+/// user's sessions pick up the change on their next request.
+pub(super) async fn put_user_sensors(
+    SessionExtractor(session): SessionExtractor,
+    Extension(context): Extension<Arc<ServerContext>>,
+    audit: Auditor,
+    Path(username): Path<String>,
+    Json(request): Json<SetSensorsRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    const ACTION: &str = "user.sensors";
+    let sensors = request.sensors.map(|sensors| {
+        let mut sensors: Vec<String> = sensors
+            .iter()
+            .map(|sensor| sensor.trim().to_string())
+            .collect();
+        sensors.sort();
+        sensors.dedup();
+        sensors
+    });
+    let user = match context.configdb.get_user_by_name(&username).await {
+        Ok(user) if username != SYSTEM_USER => user,
+        _ => {
+            let parameters = json!({"sensors": sensors});
+            let refusal = no_user(&username);
+            return Ok(rejected(&audit, &session, ACTION, &username, parameters, refusal).await);
+        }
+    };
+    let parameters = json!({"sensors": sensors, "previous": user.sensors});
+    if sensors.iter().flatten().any(String::is_empty) {
+        let refusal = (
+            StatusCode::BAD_REQUEST,
+            "empty sensor name not allowed".to_string(),
+        );
+        return Ok(rejected(&audit, &session, ACTION, &username, parameters, refusal).await);
+    }
+    let result = context
+        .configdb
+        .set_user_sensors(&username, sensors.as_deref())
+        .await;
+    audit
+        .result(&session, ACTION, Some(&username), parameters, &result)
+        .await;
+    result?;
+    context.session_store.evict_user(&username);
+    info!(
+        "Sensors of user {:?} set to {:?} by {:?}",
+        username, sensors, session.username
+    );
+    Ok(Json(json!({"username": username, "sensors": sensors})).into_response())
+}
+
+/// `DELETE /api/admin/users/{username}`: remove a user and end their
+/// sessions.
+pub(super) async fn delete_user(
+    SessionExtractor(session): SessionExtractor,
+    Extension(context): Extension<Arc<ServerContext>>,
+    audit: Auditor,
+    Path(username): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = match context.configdb.get_user_by_name(&username).await {
+        Ok(user) if username != SYSTEM_USER => user,
+        _ => {
+            let refusal = no_user(&username);
+            return Ok(rejected(
+                &audit,
+                &session,
+                "user.delete",
+                &username,
+                json!({}),
+                refusal,
+            )
+            .await);
+        }
+    };
+    let parameters = json!({"role": user.role});
+    if user.role == Role::Admin && context.configdb.count_admins().await? <= 1 {
+        return Ok(rejected(
+            &audit,
+            &session,
+            "user.delete",
+            &username,
+            parameters,
+            last_admin(),
+        )
+        .await);
+    }
+    let result = context.configdb.remove_user(&username).await;
+    audit
+        .result(
+            &session,
+            "user.delete",
+            Some(&username),
+            parameters,
+            &result,
+        )
+        .await;
+    result?;
+    context.session_store.evict_user(&username);
+    info!("User {:?} removed by {:?}", username, session.username);
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// `DELETE /api/admin/users/{username}/sessions`: log a user out of all
+/// their sessions. Their API tokens are left alone.
+pub(super) async fn delete_user_sessions(
+    SessionExtractor(session): SessionExtractor,
+    Extension(context): Extension<Arc<ServerContext>>,
+    audit: Auditor,
+    Path(username): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    const ACTION: &str = "user.sessions.revoke";
+    if context.configdb.get_user_by_name(&username).await.is_err() {
+        let refusal = no_user(&username);
+        return Ok(rejected(&audit, &session, ACTION, &username, json!({}), refusal).await);
+    }
+    let result = context.configdb.remove_user_sessions(&username).await;
+    audit
+        .result(&session, ACTION, Some(&username), json!({}), &result)
+        .await;
+    let count = result?;
+    context.session_store.evict_user(&username);
+    info!(
+        "User {:?} logged out of {} sessions by {:?}",
+        username, count, session.username
+    );
+    Ok(Json(json!({"sessions": count})).into_response())
+}
+
+/// `DELETE /api/admin/users/{username}/totp`: reset a user's second
+/// factor, for when they've lost it. Under a policy requiring one they
+/// enroll again at their next login.
+pub(super) async fn delete_user_totp(
+    SessionExtractor(session): SessionExtractor,
+    Extension(context): Extension<Arc<ServerContext>>,
+    audit: Auditor,
+    Path(username): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    const ACTION: &str = "user.totp.reset";
+    if context.configdb.get_user_by_name(&username).await.is_err() {
+        let refusal = no_user(&username);
+        return Ok(rejected(&audit, &session, ACTION, &username, json!({}), refusal).await);
+    }
+    let result = context.configdb.remove_totp(&username).await;
+    let outcome = match &result {
+        Ok(false) => Outcome::Rejected(format!("user {username:?} has no second factor")),
+        result => Outcome::of(result),
+    };
+    audit
+        .record(&session, ACTION, Some(&username), json!({}), outcome)
+        .await;
+    if !result? {
+        return Ok(user_error(
+            StatusCode::NOT_FOUND,
+            format!("user {username:?} has no second factor"),
+        ));
+    }
+    info!(
+        "TOTP of user {:?} reset by {:?}",
+        username, session.username
+    );
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// Not a login, it owns data created by the server itself.
+const SYSTEM_USER: &str = "__system__";
+
+fn user_error(status: StatusCode, message: String) -> axum::response::Response {
+    (status, Json(json!({"error": message}))).into_response()
+}
+
+/// Audit a refused user action, and respond with the refusal.
+async fn rejected(
+    audit: &Auditor,
+    session: &Session,
+    action: &str,
+    username: &str,
+    parameters: serde_json::Value,
+    (status, message): (StatusCode, String),
+) -> axum::response::Response {
+    audit
+        .record(
+            session,
+            action,
+            Some(username),
+            parameters,
+            Outcome::Rejected(message.clone()),
+        )
+        .await;
+    user_error(status, message)
+}
+
+fn no_user(username: &str) -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, format!("no user named {username:?}"))
+}
+
+fn last_admin() -> (StatusCode, String) {
+    (
+        StatusCode::CONFLICT,
+        "the last admin can't be removed or demoted".to_string(),
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use serde_json::Value;
@@ -270,6 +1079,20 @@ mod tests {
         tokio::task::JoinHandle<()>,
         tempfile::TempDir,
         Arc<ServerContext>,
+    ) {
+        serve_test_server_with_context(config, |_| {}).await
+    }
+
+    /// Serve a test server, its context set up by `setup` before it
+    /// starts.
+    async fn serve_test_server_with_context(
+        config: ServerConfig,
+        setup: impl FnOnce(&mut ServerContext),
+    ) -> (
+        std::net::SocketAddr,
+        tokio::task::JoinHandle<()>,
+        tempfile::TempDir,
+        Arc<ServerContext>,
     ) {
         // The reqwest test client requires a process-wide TLS provider.
         let _ = rustls::crypto::ring::default_provider().install_default();
@@ -286,12 +1109,14 @@ mod tests {
         let configdb = crate::sqlite::configdb::open(Some(&dir.path().join("config.sqlite")))
             .await
             .unwrap();
-        let context = Arc::new(ServerContext::new(
+        let mut context = ServerContext::new(
             config,
             Arc::new(configdb),
             datastore,
             Arc::new(Metrics::default()),
-        ));
+        );
+        setup(&mut context);
+        let context = Arc::new(context);
         let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
         let address = listener.local_addr().unwrap();
         let service = build_axum_service(context.clone());
@@ -302,60 +1127,373 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn agent_key_endpoints_manage_the_key_lifecycle() {
-        let (address, server, _dir, context) = serve_test_server().await;
+    async fn routes_require_the_role_of_their_group() {
+        let config = ServerConfig {
+            authentication_required: true,
+            ..Default::default()
+        };
+        let (address, server, _dir, context) = serve_test_server_with_config(config).await;
+        for (username, role) in [("vera", Role::Viewer), ("root", Role::Admin)] {
+            context
+                .configdb
+                .add_user(username, "secret", role)
+                .await
+                .unwrap();
+        }
         let client = reqwest::Client::new();
-        let collection = format!("http://{address}/api/agents/keys");
+        let status = |method: reqwest::Method, path: &str, username: Option<&str>| {
+            let mut request = client.request(method, format!("http://{address}{path}"));
+            if let Some(username) = username {
+                request = request.basic_auth(username, Some("secret"));
+            }
+            async move { request.send().await.unwrap().status().as_u16() }
+        };
+        use reqwest::Method;
 
-        let rows: Vec<Value> = client
-            .get(&collection)
+        assert_eq!(status(Method::GET, "/api/events", None).await, 401);
+        assert_eq!(status(Method::GET, "/api/events", Some("vera")).await, 200);
+        for (method, path) in [
+            (Method::DELETE, "/api/admin/elastic/index/evebox"),
+            (Method::GET, "/api/agents/keys"),
+            (Method::POST, "/api/event/1/archive"),
+            (Method::GET, "/api/pcap/sources"),
+            (Method::POST, "/api/pcap/routing"),
+            (Method::GET, "/api/admin/users"),
+        ] {
+            assert_eq!(status(method, path, Some("vera")).await, 403, "{path}");
+        }
+        assert_eq!(
+            status(Method::GET, "/api/agents/keys", Some("root")).await,
+            200
+        );
+
+        // Promote the viewer; the next request has the new role.
+        let users = format!("http://{address}/api/admin/users");
+        let response = client
+            .put(format!("{users}/vera/role"))
+            .basic_auth("root", Some("secret"))
+            .json(&json!({"role": "pcap"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            status(Method::GET, "/api/pcap/sources", Some("vera")).await,
+            200
+        );
+        let user: Value = client
+            .get(format!("http://{address}/api/user"))
+            .basic_auth("vera", Some("secret"))
             .send()
             .await
             .unwrap()
             .json()
             .await
             .unwrap();
-        assert!(rows.is_empty());
+        assert_eq!(user["role"], "pcap");
 
+        // The last admin can't be demoted or removed.
         let response = client
-            .post(&collection)
-            .json(&json!({"name": "sensor-a"}))
+            .put(format!("{users}/root/role"))
+            .basic_auth("root", Some("secret"))
+            .json(&json!({"role": "viewer"}))
             .send()
             .await
             .unwrap();
-        assert_eq!(response.status(), 200);
-        let created: Value = response.json().await.unwrap();
-        assert_eq!(created["name"], "sensor-a");
-        let key = created["key"].as_str().unwrap().to_string();
-        assert!(key.starts_with(crate::sqlite::configdb::AGENT_KEY_PREFIX));
-        let id = created["id"].as_i64().unwrap();
-
-        // Model a live connection authenticated with the key so deletion can
-        // prove it bumps the agent as well as removing the database row.
-        let (outbound, _outbound_rx) =
-            tokio::sync::mpsc::channel(crate::server::agents::OUTBOUND_CAPACITY);
-        let live = context
-            .agents
-            .register(
-                crate::agent::protocol::AgentHandshake {
-                    name: "sensor-a".to_string(),
-                    hostname: "sensor-a.example.test".to_string(),
-                    version: "0.27.0-dev".to_string(),
-                    capabilities: vec![crate::agent::protocol::CAPABILITY_PCAP.to_string()],
-                },
-                Some(crate::server::agents::AgentKeyIdentity {
-                    id,
-                    name: "sensor-a".to_string(),
-                }),
-                "127.0.0.1:12345".parse().unwrap(),
-                outbound,
-            )
+        assert_eq!(response.status(), 409);
+        let response = client
+            .delete(format!("{users}/root"))
+            .basic_auth("root", Some("secret"))
+            .send()
+            .await
             .unwrap();
+        assert_eq!(response.status(), 409);
 
-        // Names that cannot be keyed: already in use, empty, reserved.
-        for name in [
-            "sensor-a".to_string(),
-            "  ".to_string(),
+        let response = client
+            .post(&users)
+            .basic_auth("root", Some("secret"))
+            .json(&json!({"username": "ann", "password": "secret", "role": "analyst"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        assert!(response.text().await.unwrap().contains("at least 8"));
+        let response = client
+            .post(&users)
+            .basic_auth("root", Some("secret"))
+            .json(&json!({"username": "ann", "password": "secret-ann", "role": "analyst"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let listed: Vec<Value> = client
+            .get(&users)
+            .basic_auth("root", Some("secret"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(listed.contains(&json!({
+            "username": "ann",
+            "role": "analyst",
+            "totp": false,
+            "sensors": null,
+            "password_change_required": false,
+        })));
+        assert!(!listed.iter().any(|user| user["username"] == "__system__"));
+        let response = client
+            .post(format!("http://{address}/api/event/1/archive"))
+            .basic_auth("ann", Some("secret-ann"))
+            .send()
+            .await
+            .unwrap();
+        assert_ne!(response.status(), 403);
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn suricata_commands_require_their_role() {
+        let config = ServerConfig {
+            authentication_required: true,
+            ..Default::default()
+        };
+        let (address, server, _dir, context) = serve_test_server_with_config(config).await;
+        for (username, role) in [
+            ("vera", Role::Viewer),
+            ("ann", Role::Analyst),
+            ("sam", Role::Analyst),
+        ] {
+            context
+                .configdb
+                .add_user(username, "secret", role)
+                .await
+                .unwrap();
+        }
+        context
+            .configdb
+            .set_user_sensors("sam", Some(&["other".to_string()]))
+            .await
+            .unwrap();
+        let client = reqwest::Client::new();
+        let status = |method: reqwest::Method, path: &str, username: &str| {
+            let request = client
+                .request(
+                    method,
+                    format!("http://{address}/api/agents/sensor-a{path}"),
+                )
+                .basic_auth(username, Some("secret"));
+            async move { request.send().await.unwrap().status().as_u16() }
+        };
+        use reqwest::Method;
+
+        // Past the role check, the agent is not connected.
+        for (path, username, expected) in [
+            ("/suricata/dump-counters", "vera", 403),
+            ("/suricata/dump-counters", "ann", 404),
+            ("/suricata/reload-rules", "ann", 403),
+            ("/suricata/dump-counters", "sam", 404),
+        ] {
+            assert_eq!(
+                status(Method::POST, path, username).await,
+                expected,
+                "{username} {path}"
+            );
+        }
+        assert_eq!(status(Method::GET, "/rules/update", "vera").await, 404);
+        assert_eq!(status(Method::POST, "/rules/update", "ann").await, 403);
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn api_tokens_authenticate_within_their_scope() {
+        let config = ServerConfig {
+            authentication_required: true,
+            ..Default::default()
+        };
+        let (address, server, _dir, context) = serve_test_server_with_config(config).await;
+        context
+            .configdb
+            .add_user("alice", "secret", Role::Pcap)
+            .await
+            .unwrap();
+        let client = reqwest::Client::new();
+        let tokens = format!("http://{address}/api/user/tokens");
+
+        let response = client
+            .post(&tokens)
+            .basic_auth("alice", Some("secret"))
+            .json(&json!({"name": "script", "scope": "admin"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        let created: Value = client
+            .post(&tokens)
+            .basic_auth("alice", Some("secret"))
+            .json(&json!({"name": "script", "scope": "viewer", "expires": "30d"}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let token = created["token"].as_str().unwrap().to_string();
+        assert_eq!(created["scope"], "viewer");
+        assert!(created["expires_at"].is_string());
+
+        let get = |path: &str, token: &str| {
+            client
+                .get(format!("http://{address}{path}"))
+                .bearer_auth(token)
+                .send()
+        };
+        assert_eq!(get("/api/events", &token).await.unwrap().status(), 200);
+        // The user may download packets, the token may not.
+        assert_eq!(
+            get("/api/pcap/sources", &token).await.unwrap().status(),
+            403
+        );
+        assert_eq!(
+            get("/api/events", "ebt_unknown").await.unwrap().status(),
+            401
+        );
+
+        // A token can't mint tokens.
+        let response = client
+            .post(&tokens)
+            .bearer_auth(&token)
+            .json(&json!({"name": "another"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 403);
+
+        let listed: Vec<Value> = get("/api/user/tokens", &token)
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(listed.len(), 1);
+        assert!(listed[0].get("token").is_none());
+        assert!(listed[0]["last_used"].is_string());
+
+        let id = created["id"].as_i64().unwrap();
+        let response = client
+            .delete(format!("{tokens}/{id}"))
+            .basic_auth("alice", Some("secret"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 204);
+        assert_eq!(get("/api/events", &token).await.unwrap().status(), 401);
+
+        context
+            .configdb
+            .add_user("root", "secret", Role::Admin)
+            .await
+            .unwrap();
+        let audit: Value = client
+            .get(format!("http://{address}/api/audit?action=token"))
+            .basic_auth("root", Some("secret"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let records: Vec<_> = audit["records"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|record| {
+                (
+                    record["action"].as_str().unwrap(),
+                    record["outcome"].as_str().unwrap(),
+                    record["api_token"].as_str(),
+                )
+            })
+            .collect();
+        // Newest first; the token's uses within a minute of its first are
+        // not recorded again.
+        assert_eq!(
+            records,
+            [
+                ("token.revoke", "ok", None),
+                ("token.create", "rejected", Some("script")),
+                ("token.use", "ok", Some("script")),
+                ("token.create", "ok", None),
+                ("token.create", "rejected", None),
+            ]
+        );
+        assert_eq!(audit["records"][0]["target"], id.to_string());
+        assert_eq!(
+            audit["records"][2]["parameters"]["request"],
+            "GET /api/events"
+        );
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn agent_key_endpoints_manage_the_key_lifecycle() {
+        let (address, server, _dir, context) = serve_test_server().await;
+        let client = reqwest::Client::new();
+        let collection = format!("http://{address}/api/agents/keys");
+
+        let rows: Vec<Value> = client
+            .get(&collection)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(rows.is_empty());
+
+        let response = client
+            .post(&collection)
+            .json(&json!({"name": "sensor-a"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let created: Value = response.json().await.unwrap();
+        assert_eq!(created["name"], "sensor-a");
+        let key = created["key"].as_str().unwrap().to_string();
+        assert!(key.starts_with(crate::sqlite::configdb::AGENT_KEY_PREFIX));
+        let id = created["id"].as_i64().unwrap();
+
+        // Model a live connection authenticated with the key so deletion can
+        // prove it bumps the agent as well as removing the database row.
+        let (outbound, _outbound_rx) =
+            tokio::sync::mpsc::channel(crate::server::agents::OUTBOUND_CAPACITY);
+        let live = context
+            .agents
+            .register(
+                crate::agent::protocol::AgentHandshake {
+                    name: "sensor-a".to_string(),
+                    hostname: "sensor-a.example.test".to_string(),
+                    version: "0.27.0-dev".to_string(),
+                    capabilities: vec![crate::agent::protocol::CAPABILITY_PCAP.to_string()],
+                },
+                Some(crate::server::agents::AgentKeyIdentity {
+                    id,
+                    name: "sensor-a".to_string(),
+                }),
+                "127.0.0.1:12345".parse().unwrap(),
+                outbound,
+            )
+            .unwrap();
+
+        // Names that cannot be keyed: already in use, empty, reserved.
+        for name in [
+            "sensor-a".to_string(),
+            "  ".to_string(),
             crate::server::agents::LOCAL_PCAP_SOURCE_NAME.to_string(),
             "x".repeat(crate::server::agents::MAX_AGENT_NAME_BYTES + 1),
         ] {
@@ -418,6 +1556,164 @@ mod tests {
         server.abort();
     }
 
+    #[tokio::test]
+    async fn agent_key_rotation_and_expiry_endpoints() {
+        let (address, server, _dir, context) = serve_test_server().await;
+        let client = reqwest::Client::new();
+        let collection = format!("http://{address}/api/agents/keys");
+
+        let created: Value = client
+            .post(&collection)
+            .json(&json!({"name": "sensor-a", "expires": "10d"}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let id = created["id"].as_i64().unwrap();
+        let original = created["key"].as_str().unwrap().to_string();
+        assert!(created["expires_at"].is_string());
+
+        // Inside the default warning window the listing flags the expiry.
+        let rows: Vec<Value> = client
+            .get(&collection)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(rows[0]["warnings"][0]["code"], "expiring");
+        assert!(rows[0].get("previous_key").is_none());
+
+        let response = client
+            .post(format!("{collection}/{id}/rotate"))
+            .json(&json!({"grace": "1h", "expires": "nonsense"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+
+        let rotated: Value = client
+            .post(format!("{collection}/{id}/rotate"))
+            .json(&json!({"grace": "1h"}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(rotated["id"], id);
+        assert_ne!(rotated["key"], original.as_str());
+        assert!(rotated["expires_at"].is_null());
+        for key in [original.as_str(), rotated["key"].as_str().unwrap()] {
+            assert!(
+                context
+                    .configdb
+                    .verify_agent_key(key)
+                    .await
+                    .unwrap()
+                    .is_some()
+            );
+        }
+        let rows: Vec<Value> = client
+            .get(&collection)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(rows[0]["previous_key_expires_at"].is_string());
+        assert_eq!(rows[0]["warnings"][0]["code"], "previous-key-valid");
+
+        // Without a body the replaced key stops working at once.
+        let response = client
+            .post(format!("{collection}/{id}/rotate"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(
+            context
+                .configdb
+                .verify_agent_key(&original)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let updated: Value = client
+            .put(format!("{collection}/{id}/expiry"))
+            .json(&json!({"expires": "90d"}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(updated["expires_at"].is_string());
+        assert!(updated.get("key").is_none());
+        let updated: Value = client
+            .put(format!("{collection}/{id}/expiry"))
+            .json(&json!({"expires": null}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(updated["expires_at"].is_null());
+
+        for response in [
+            client
+                .post(format!("{collection}/{}/rotate", id + 1))
+                .send(),
+            client
+                .put(format!("{collection}/{}/expiry", id + 1))
+                .json(&json!({"expires": null}))
+                .send(),
+        ] {
+            assert_eq!(response.await.unwrap().status(), 404);
+        }
+
+        server.abort();
+    }
+
+    #[test]
+    fn agent_key_warnings_cover_expiry_and_idle_keys() {
+        let now = chrono::Utc::now().fixed_offset();
+        let days = chrono::TimeDelta::days;
+        let key = |created: i64, last_seen: Option<i64>, expires: Option<i64>| AgentKey {
+            id: 1,
+            name: "sensor-a".to_string(),
+            key: "eba_x".to_string(),
+            created_at: now - days(created),
+            last_seen: last_seen.map(|ago| now - days(ago)),
+            expires_at: expires.map(|from_now| now + days(from_now)),
+            rotated_at: None,
+            previous_key: None,
+            previous_key_expires_at: None,
+            cert_fingerprint: None,
+            cert_subject: None,
+        };
+        let codes = |row: &AgentKey| -> Vec<&'static str> {
+            agent_key_warnings(row, now, 14, 30)
+                .into_iter()
+                .map(|warning| warning.code)
+                .collect()
+        };
+
+        assert!(codes(&key(1, Some(0), None)).is_empty());
+        assert!(codes(&key(1, Some(0), Some(60))).is_empty());
+        assert_eq!(codes(&key(1, Some(0), Some(5))), ["expiring"]);
+        assert_eq!(codes(&key(1, Some(0), Some(-1))), ["expired"]);
+        assert_eq!(codes(&key(90, Some(45), None)), ["unused"]);
+        assert_eq!(codes(&key(31, None, None)), ["unused"]);
+        assert!(agent_key_warnings(&key(90, Some(45), None), now, 14, 0).is_empty());
+    }
+
     /// The key endpoints carry live credentials, so pin their auth
     /// posture: with authentication required, every method rejects a
     /// session-less request. Losing a SessionExtractor parameter from a
@@ -446,16 +1742,35 @@ mod tests {
         );
         assert_eq!(client.get(&item).send().await.unwrap().status(), 401);
         assert_eq!(client.delete(&item).send().await.unwrap().status(), 401);
-
-        server.abort();
-    }
-
-    /// The pcap routing endpoints: a save validates, trims, persists,
-    /// and applies the table live — the GET reads the running service,
-    /// not the database — and the kv row survives for the next server
-    /// start.
-    #[tokio::test]
-    async fn pcap_routing_endpoints_roundtrip() {
+        assert_eq!(
+            client
+                .post(format!("{item}/rotate"))
+                .send()
+                .await
+                .unwrap()
+                .status(),
+            401
+        );
+        assert_eq!(
+            client
+                .put(format!("{item}/expiry"))
+                .json(&json!({"expires": null}))
+                .send()
+                .await
+                .unwrap()
+                .status(),
+            401
+        );
+
+        server.abort();
+    }
+
+    /// The pcap routing endpoints: a save validates, trims, persists,
+    /// and applies the table live — the GET reads the running service,
+    /// not the database — and the kv row survives for the next server
+    /// start.
+    #[tokio::test]
+    async fn pcap_routing_endpoints_roundtrip() {
         let (address, server, dir, _context) = serve_test_server().await;
         let client = reqwest::Client::new();
         let url = format!("http://{address}/api/pcap/routing");
@@ -571,4 +1886,861 @@ mod tests {
 
         server.abort();
     }
+
+    #[tokio::test]
+    async fn login_asks_for_the_second_factor_once_enrolled() {
+        use crate::server::totp::current_code;
+
+        let config = ServerConfig {
+            authentication_required: true,
+            ..Default::default()
+        };
+        let (address, server, _dir, context) = serve_test_server_with_config(config).await;
+        for username in ["alice", "root"] {
+            context
+                .configdb
+                .add_user(username, "secret", Role::Admin)
+                .await
+                .unwrap();
+        }
+        let client = reqwest::Client::new();
+        let login = |code: Option<&str>| {
+            let mut form = vec![("username", "alice"), ("password", "secret")];
+            if let Some(code) = code {
+                form.push(("code", code));
+            }
+            client
+                .post(format!("http://{address}/api/login"))
+                .form(&form)
+                .send()
+        };
+        let totp = format!("http://{address}/api/user/totp");
+
+        // Basic authentication is not a login session.
+        let response = client
+            .post(&totp)
+            .basic_auth("alice", Some("secret"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 403);
+
+        let session: Value = login(None).await.unwrap().json().await.unwrap();
+        let cookie = format!(
+            "x-evebox-session-id={}",
+            session["session_id"].as_str().unwrap()
+        );
+        let enrollment: Value = client
+            .post(&totp)
+            .header("cookie", &cookie)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let secret = enrollment["secret"].as_str().unwrap().to_string();
+        assert!(
+            enrollment["uri"]
+                .as_str()
+                .unwrap()
+                .starts_with("otpauth://totp/")
+        );
+        let confirmed: Value = client
+            .post(format!("{totp}/confirm"))
+            .header("cookie", &cookie)
+            .json(&json!({"code": current_code(&secret, 0)}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let recovery_codes = confirmed["recovery_codes"].as_array().unwrap();
+        assert_eq!(recovery_codes.len(), 10);
+        let response = client
+            .post(&totp)
+            .header("cookie", &cookie)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 409);
+
+        // The password alone is no longer enough, for a form login or
+        // basic authentication.
+        let response = login(None).await.unwrap();
+        assert_eq!(response.status(), 401);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body, json!({"totp": "required"}));
+        let response = login(Some("abcdef")).await.unwrap();
+        assert_eq!(response.status(), 401);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["error"], "invalid code");
+        let response = client
+            .get(format!("http://{address}/api/user/totp"))
+            .basic_auth("alice", Some("secret"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 401);
+
+        let response = login(Some(&current_code(&secret, 1))).await.unwrap();
+        assert_eq!(response.status(), 200);
+        let response = login(recovery_codes[0].as_str()).await.unwrap();
+        assert_eq!(response.status(), 200);
+        let status: Value = client
+            .get(&totp)
+            .header("cookie", &cookie)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(status["enabled"], true);
+        assert_eq!(status["recovery_codes"], 9);
+
+        for (code, status) in [("abcdef", 400), (recovery_codes[1].as_str().unwrap(), 200)] {
+            let response = client
+                .post(format!("{totp}/recovery-codes"))
+                .header("cookie", &cookie)
+                .json(&json!({"code": code}))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status);
+        }
+        let response = client
+            .delete(&totp)
+            .header("cookie", &cookie)
+            .json(&json!({"code": "abcdef"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        let records = context
+            .configdb
+            .search_audit(&crate::sqlite::configdb::AuditQuery {
+                action: Some("totp".to_string()),
+                limit: 100,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let records: Vec<_> = records
+            .iter()
+            .map(|row| {
+                (
+                    row.record.action.as_str(),
+                    row.record.target.as_deref(),
+                    row.record.outcome.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            records,
+            [
+                ("totp.disable", Some("alice"), "rejected"),
+                ("totp.recovery-codes", Some("alice"), "ok"),
+                ("totp.recovery-codes", Some("alice"), "rejected"),
+                ("totp.begin", Some("alice"), "rejected"),
+                ("totp.enable", Some("alice"), "ok"),
+                ("totp.begin", Some("alice"), "ok"),
+            ]
+        );
+
+        // An admin resets a lost second factor.
+        let users: Vec<Value> = client
+            .get(format!("http://{address}/api/admin/users"))
+            .basic_auth("root", Some("secret"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(
+            users
+                .iter()
+                .any(|user| user["username"] == "alice" && user["totp"] == true)
+        );
+        let reset = format!("http://{address}/api/admin/users/alice/totp");
+        let response = client
+            .delete(&reset)
+            .basic_auth("root", Some("secret"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 204);
+        let response = client
+            .delete(&reset)
+            .basic_auth("root", Some("secret"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+        assert_eq!(login(None).await.unwrap().status(), 200);
+
+        // Under the policy, the next login enrolls.
+        let policy = format!("http://{address}/api/admin/kv/config/config.totp");
+        let response = client
+            .post(&policy)
+            .basic_auth("root", Some("secret"))
+            .json(&json!({"required": "yes"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        let response = client
+            .post(&policy)
+            .basic_auth("root", Some("secret"))
+            .json(&json!({"required": true}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let response = login(None).await.unwrap();
+        assert_eq!(response.status(), 401);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["totp"], "enroll");
+        let secret = body["secret"].as_str().unwrap().to_string();
+        let body: Value = login(Some(&current_code(&secret, 0)))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(body["session_id"].is_string());
+        assert_eq!(body["recovery_codes"].as_array().unwrap().len(), 10);
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn wrong_codes_for_second_factor_changes_lock_out() {
+        use crate::server::totp::current_code;
+
+        let config = ServerConfig {
+            authentication_required: true,
+            ..Default::default()
+        };
+        let (address, server, _dir, context) = serve_test_server_with_config(config).await;
+        context
+            .configdb
+            .add_user("alice", "secret", Role::Viewer)
+            .await
+            .unwrap();
+        let client = reqwest::Client::new();
+        let session: Value = client
+            .post(format!("http://{address}/api/login"))
+            .form(&[("username", "alice"), ("password", "secret")])
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let cookie = format!(
+            "x-evebox-session-id={}",
+            session["session_id"].as_str().unwrap()
+        );
+        let totp = format!("http://{address}/api/user/totp");
+        let enrollment: Value = client
+            .post(&totp)
+            .header("cookie", &cookie)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let secret = enrollment["secret"].as_str().unwrap().to_string();
+        let response = client
+            .post(format!("{totp}/confirm"))
+            .header("cookie", &cookie)
+            .json(&json!({"code": current_code(&secret, 0)}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        let disable = |code: String| {
+            client
+                .delete(&totp)
+                .header("cookie", &cookie)
+                .json(&json!({"code": code}))
+                .send()
+        };
+        for _ in 0..5 {
+            assert_eq!(disable("abcdef".to_string()).await.unwrap().status(), 400);
+        }
+        // Locked out, even with the right code.
+        let response = disable(current_code(&secret, 1)).await.unwrap();
+        assert_eq!(response.status(), 429);
+        assert!(response.headers().contains_key("retry-after"));
+        let response = client
+            .post(format!("{totp}/recovery-codes"))
+            .header("cookie", &cookie)
+            .json(&json!({"code": current_code(&secret, 1)}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 429);
+        assert!(
+            context
+                .configdb
+                .get_totp("alice")
+                .await
+                .unwrap()
+                .is_some_and(|totp| totp.enabled)
+        );
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn failed_logins_lock_out_and_sessions_can_be_revoked() {
+        let config = ServerConfig {
+            authentication_required: true,
+            lockout: crate::server::throttle::LockoutConfig {
+                max_failures: 3,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (address, server, _dir, context) = serve_test_server_with_config(config).await;
+        for (username, role) in [("alice", Role::Viewer), ("root", Role::Admin)] {
+            context
+                .configdb
+                .add_user(username, "secret", role)
+                .await
+                .unwrap();
+        }
+        let client = reqwest::Client::new();
+        let login = |username: &'static str, password: &'static str, agent: &'static str| {
+            client
+                .post(format!("http://{address}/api/login"))
+                .header("user-agent", agent)
+                .form(&[("username", username), ("password", password)])
+                .send()
+        };
+        let sessions = format!("http://{address}/api/user/sessions");
+
+        let first = login("alice", "secret", "first").await.unwrap();
+        assert_eq!(first.status(), 200);
+        let cookie = |response: &reqwest::Response| {
+            let cookie = response.headers()["set-cookie"].to_str().unwrap();
+            assert!(cookie.contains(&format!("Max-Age={}", 7 * 86400)));
+            cookie.split(';').next().unwrap().to_string()
+        };
+        let first = cookie(&first);
+        let second = cookie(&login("alice", "secret", "second").await.unwrap());
+
+        let listed: Vec<Value> = client
+            .get(&sessions)
+            .header("cookie", &second)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(listed.len(), 2);
+        let other = listed.iter().find(|s| s["current"] == false).unwrap();
+        assert_eq!(other["user_agent"], "first");
+        assert_eq!(other["remote"], "127.0.0.1");
+        assert!(other.get("token").is_none());
+
+        // Revoking a session logs it out at once.
+        let response = client
+            .delete(format!("{sessions}/{}", other["id"]))
+            .header("cookie", &second)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 204);
+        let status = |cookie: String| {
+            let request = client.get(&sessions).header("cookie", cookie);
+            async move { request.send().await.unwrap().status() }
+        };
+        assert_eq!(status(first.clone()).await, 401);
+        assert_eq!(status(second.clone()).await, 200);
+        let response = client
+            .delete(format!("{sessions}/{}", other["id"]))
+            .header("cookie", &second)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+
+        // An admin logs a user out everywhere.
+        let response: Value = client
+            .delete(format!("http://{address}/api/admin/users/alice/sessions"))
+            .basic_auth("root", Some("secret"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(response["sessions"], 1);
+        assert_eq!(status(second).await, 401);
+
+        // Failed logins lock the username out, even with the password.
+        for _ in 0..3 {
+            let response = login("alice", "wrong", "x").await.unwrap();
+            assert_eq!(response.status(), 401);
+        }
+        let response = login("alice", "secret", "x").await.unwrap();
+        assert_eq!(response.status(), 429);
+        assert!(response.headers().contains_key("retry-after"));
+        let response = client
+            .get(&sessions)
+            .basic_auth("alice", Some("secret"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 429);
+        assert_eq!(login("root", "secret", "x").await.unwrap().status(), 200);
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn admin_actions_are_audited() {
+        let config = ServerConfig {
+            authentication_required: true,
+            ..Default::default()
+        };
+        let (address, server, _dir, context) = serve_test_server_with_config(config).await;
+        context
+            .configdb
+            .add_user("root", "secret", Role::Admin)
+            .await
+            .unwrap();
+        let client = reqwest::Client::new();
+        let url = |path: &str| format!("http://{address}{path}");
+
+        let response = client
+            .post(url("/api/admin/users"))
+            .basic_auth("root", Some("secret"))
+            .json(&json!({"username": "bob", "password": "hunter2-hunter2", "role": "viewer"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let response = client
+            .put(url("/api/admin/users/bob/role"))
+            .basic_auth("root", Some("secret"))
+            .json(&json!({"role": "analyst"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let response = client
+            .delete(url("/api/admin/users/root"))
+            .basic_auth("root", Some("secret"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 409);
+        let response = client
+            .post(url("/api/admin/kv/config/config.totp"))
+            .basic_auth("root", Some("secret"))
+            .json(&json!({"required": false}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        let search = |query: &str| {
+            let request = client
+                .get(url(&format!("/api/audit?{query}")))
+                .basic_auth("root", Some("secret"));
+            async move {
+                let response: Value = request.send().await.unwrap().json().await.unwrap();
+                response["records"].as_array().unwrap().clone()
+            }
+        };
+        let records = search("action=user").await;
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0]["action"], "user.delete");
+        assert_eq!(records[0]["outcome"], "rejected");
+        assert_eq!(
+            records[0]["message"],
+            "the last admin can't be removed or demoted"
+        );
+        assert_eq!(records[1]["action"], "user.role");
+        assert_eq!(records[1]["target"], "bob");
+        assert_eq!(
+            records[1]["parameters"],
+            json!({"role": "analyst", "previous": "viewer"})
+        );
+        assert_eq!(records[2]["action"], "user.add");
+        assert_eq!(records[2]["username"], "root");
+        assert!(
+            records[2]["remote"]
+                .as_str()
+                .unwrap()
+                .starts_with("127.0.0.1:")
+        );
+        assert!(!records[2].to_string().contains("hunter2-hunter2"));
+
+        let records = search("user=root&target=config.totp").await;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["action"], "config.set");
+        assert_eq!(records[0]["outcome"], "ok");
+        assert_eq!(
+            records[0]["parameters"]["value"],
+            json!({"required": false})
+        );
+        assert!(search("outcome=error").await.is_empty());
+
+        let response = client
+            .get(url("/api/audit?format=csv&action=config.set"))
+            .basic_auth("root", Some("secret"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["content-type"], "text/csv");
+        assert_eq!(response.text().await.unwrap().lines().count(), 2);
+
+        // Only admins read the audit trail.
+        let response = client
+            .get(url("/api/audit"))
+            .basic_auth("bob", Some("hunter2-hunter2"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 403);
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn proxy_login_headers_are_only_taken_from_trusted_proxies() {
+        let proxy_auth = |context: &mut ServerContext| {
+            let config =
+                serde_yaml::from_str("groups-header: x-remote-groups\nroles:\n  soc: analyst\n")
+                    .unwrap();
+            context.proxy_auth = Some(Arc::new(
+                crate::server::proxy::ProxyAuth::new(config).unwrap(),
+            ));
+        };
+        let config = |trusted: &str| ServerConfig {
+            authentication_required: true,
+            http_reverse_proxy: true,
+            trusted_proxies: vec![trusted.parse().unwrap()],
+            ..Default::default()
+        };
+
+        let (address, server, _dir, _context) =
+            serve_test_server_with_context(config("127.0.0.0/8"), proxy_auth).await;
+        let client = reqwest::Client::new();
+        let user: Value = client
+            .get(format!("http://{address}/api/user"))
+            .header("x-remote-user", "alice")
+            .header("x-remote-groups", "staff,soc")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(user["username"], "alice");
+        assert_eq!(user["role"], "analyst");
+        let response = client
+            .get(format!("http://{address}/api/admin/users"))
+            .header("x-remote-user", "alice")
+            .header("x-remote-groups", "soc")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 403);
+        server.abort();
+
+        // This test client isn't a trusted proxy.
+        let (address, server, _dir, context) =
+            serve_test_server_with_context(config("10.0.0.0/8"), proxy_auth).await;
+        context
+            .configdb
+            .add_user("root", "secret", Role::Admin)
+            .await
+            .unwrap();
+        let response = client
+            .get(format!("http://{address}/api/user"))
+            .basic_auth("root", Some("secret"))
+            .header("x-remote-user", "alice")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 403);
+        let response = client
+            .get(format!("http://{address}/api/user"))
+            .basic_auth("root", Some("secret"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn users_limited_to_sensors_see_only_their_events() {
+        let config = ServerConfig {
+            authentication_required: true,
+            ..Default::default()
+        };
+        let (address, server, _dir, context) = serve_test_server_with_config(config).await;
+        let configdb = &context.configdb;
+        configdb
+            .add_user("root", "secret", Role::Admin)
+            .await
+            .unwrap();
+        configdb
+            .add_user("bob", "secret", Role::Analyst)
+            .await
+            .unwrap();
+        let mut sink = context.datastore.get_importer().unwrap();
+        let now = crate::datetime::DateTime::now();
+        // The other sensor's event is the earlier.
+        let timestamps = [
+            now.to_eve(),
+            (now - std::time::Duration::from_secs(60)).to_eve(),
+        ];
+        for ((host, src_ip), timestamp) in [("sensor-a", "10.0.0.1"), ("sensor-b", "10.0.0.2")]
+            .into_iter()
+            .zip(&timestamps)
+        {
+            let event = json!({
+                "timestamp": timestamp,
+                "event_type": "alert",
+                "host": host,
+                "src_ip": src_ip,
+                "dest_ip": "192.0.2.1",
+                "alert": {"signature_id": 1, "signature": "test", "severity": 1},
+            });
+            sink.submit(event).await.unwrap();
+        }
+        sink.commit().await.unwrap();
+        let client = reqwest::Client::new();
+        let url = |path: &str| format!("http://{address}{path}");
+        let get = |user: &str, path: &str| {
+            client
+                .get(url(path))
+                .basic_auth(user, Some("secret"))
+                .send()
+        };
+
+        let response = client
+            .put(url("/api/admin/users/bob/sensors"))
+            .basic_auth("root", Some("secret"))
+            .json(&json!({"sensors": ["sensor-a", " sensor-a "]}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let users: Value = get("root", "/api/admin/users")
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let bob = users
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|user| user["username"] == "bob")
+            .unwrap();
+        assert_eq!(bob["sensors"], json!(["sensor-a"]));
+
+        let events: Value = get("root", "/api/events")
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let other = events["events"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|event| event["_source"]["host"] == "sensor-b")
+            .unwrap()["_id"]
+            .to_string();
+
+        let events: Value = get("bob", "/api/events")
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let events = events["events"].as_array().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["_source"]["host"], "sensor-a");
+        let alerts: Value = get("bob", "/api/alerts")
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(alerts["events"].as_array().unwrap().len(), 1);
+        let sensors: Value = get("bob", "/api/sensors")
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(sensors["data"], json!(["sensor-a"]));
+        for (user, timestamp) in [("root", &timestamps[1]), ("bob", &timestamps[0])] {
+            let earliest: Value = get(user, "/api/events/earliest-timestamp")
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            let parse = |timestamp: &str| crate::datetime::parse(timestamp, None).unwrap();
+            assert_eq!(
+                parse(earliest.as_str().unwrap()).to_nanos(),
+                parse(timestamp).to_nanos(),
+                "{user}"
+            );
+        }
+
+        let path = format!("/api/event/{other}");
+        assert_eq!(get("root", &path).await.unwrap().status(), 200);
+        assert_eq!(get("bob", &path).await.unwrap().status(), 404);
+        let response = client
+            .post(url(&format!("/api/event/{other}/archive")))
+            .basic_auth("bob", Some("secret"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+        let archived = context
+            .datastore
+            .get_event_by_id(other.clone())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!archived["_source"]["tags"].to_string().contains("archived"));
+
+        let response = client
+            .put(url("/api/admin/users/bob/sensors"))
+            .basic_auth("root", Some("secret"))
+            .json(&json!({"sensors": null}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(get("bob", &path).await.unwrap().status(), 200);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn new_users_must_change_their_password() {
+        let config = ServerConfig {
+            authentication_required: true,
+            ..Default::default()
+        };
+        let (address, server, _dir, context) = serve_test_server_with_config(config).await;
+        context
+            .configdb
+            .add_user("root", "secret", Role::Admin)
+            .await
+            .unwrap();
+        let client = reqwest::Client::new();
+        let url = |path: &str| format!("http://{address}{path}");
+
+        let response = client
+            .post(url("/api/admin/users"))
+            .basic_auth("root", Some("secret"))
+            .json(&json!({
+                "username": "bob",
+                "password": "temporary",
+                "role": "viewer",
+                "password_change_required": true,
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let login = || {
+            client
+                .post(url("/api/login"))
+                .form(&[("username", "bob"), ("password", "temporary")])
+                .send()
+        };
+        let response = login().await.unwrap();
+        assert_eq!(response.status(), 200);
+        let cookie = |response: &reqwest::Response| {
+            let cookie = response.headers()["set-cookie"].to_str().unwrap();
+            cookie.split(';').next().unwrap().to_string()
+        };
+        let current = cookie(&response);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["password_change_required"], true);
+        let other = cookie(&login().await.unwrap());
+
+        let get = |path: &str, cookie: &str| {
+            client
+                .get(url(path))
+                .header("cookie", cookie.to_string())
+                .send()
+        };
+        assert_eq!(get("/api/events", &current).await.unwrap().status(), 403);
+        let user: Value = get("/api/user", &current)
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(user["password_change_required"], true);
+
+        let change = |current_password: &str, new_password: &str| {
+            client
+                .post(url("/api/user/password"))
+                .header("cookie", current.clone())
+                .json(&json!({
+                    "current_password": current_password,
+                    "new_password": new_password,
+                }))
+                .send()
+        };
+        assert_eq!(
+            change("wrong", "a-new-password").await.unwrap().status(),
+            403
+        );
+        assert_eq!(change("temporary", "short").await.unwrap().status(), 400);
+        assert_eq!(
+            change("temporary", "temporary").await.unwrap().status(),
+            400
+        );
+        let response = change("temporary", "a-new-password").await.unwrap();
+        assert_eq!(response.status(), 200);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["sessions_revoked"], 1);
+
+        assert_eq!(get("/api/events", &current).await.unwrap().status(), 200);
+        assert_eq!(get("/api/events", &other).await.unwrap().status(), 401);
+        let response = client
+            .get(url("/api/events"))
+            .basic_auth("bob", Some("a-new-password"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        let records = context
+            .configdb
+            .search_audit(&crate::sqlite::configdb::AuditQuery {
+                action: Some("user.password".to_string()),
+                limit: 100,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 4);
+        assert!(!format!("{records:?}").contains("a-new-password"));
+        server.abort();
+    }
 }
@@ -30,21 +30,28 @@ use crate::agent::protocol::{
     AGENT_HEADER, AgentHandshake, AgentMessage, CONTROL_MESSAGE_MAX_BYTES, SUBPROTOCOL,
     ServerMessage,
 };
-use crate::agent::protocol::{CAPABILITY_PCAP, PCAP_CONTENT_TYPE};
+use crate::agent::protocol::{
+    CAPABILITY_CONFIG, CAPABILITY_PCAP, CAPABILITY_PCAP_RETENTION, CAPABILITY_RULES_UPDATE,
+    CAPABILITY_SURICATA_COMMAND, PCAP_CONTENT_TYPE, WireSpoolUsage,
+};
 use crate::prelude::*;
 use crate::server::ServerContext;
 use crate::server::agents::{
     AgentConnectionId, AgentKeyIdentity, AgentRegistry, MAX_AGENT_NAME_BYTES, OUTBOUND_CAPACITY,
 };
+use crate::server::client_cert::{CertificateIdentity, PeerCertificate};
 use crate::server::main::SessionExtractor;
 use crate::server::pcap::tasks::{UploadSendError, UploadSink};
-use crate::sqlite::configdb::ConfigDb;
+use crate::sqlite::configdb::{AgentConfigRow, ConfigDb, ConfigDbError};
 
 // JSON escapes can make a maximum-sized agent name several times larger on
 // the wire, so leave ample room for it plus the other handshake fields.
 const HANDSHAKE_HEADER_MAX_SIZE: usize = 128 * 1024;
 const PING_INTERVAL: Duration = Duration::from_secs(30);
 const MAX_MISSED_PONGS: u8 = 2;
+/// How often a connected agent's key is checked again, so an expired or
+/// rotated-out key does not keep its connection indefinitely.
+const KEY_CHECK_INTERVAL: Duration = Duration::from_secs(60);
 const WS_SEND_TIMEOUT: Duration = Duration::from_secs(20);
 
 #[derive(Debug)]
@@ -96,9 +103,11 @@ pub(crate) async fn websocket(
     ws: WebSocketUpgrade,
     State(context): State<Arc<ServerContext>>,
     Extension(ConnectInfo(remote)): Extension<ConnectInfo<SocketAddr>>,
+    certificate: Option<Extension<PeerCertificate>>,
     headers: HeaderMap,
 ) -> Response {
-    let key = match authenticate_agent(&context, &headers, remote).await {
+    let certificate = certificate.and_then(|Extension(PeerCertificate(certificate))| certificate);
+    let key = match authenticate_agent(&context, &headers, certificate.as_ref(), remote).await {
         Ok(key) => key,
         Err(response) => return response,
     };
@@ -146,6 +155,13 @@ pub(crate) async fn websocket(
         ),
     }
 
+    // Keep the presented key to re-check it while the agent stays
+    // connected.
+    let presented_key = key
+        .as_ref()
+        .and_then(|_| headers.typed_get::<Authorization<Bearer>>())
+        .map(|bearer| bearer.token().to_string());
+
     // Control messages should stay small. Keep the limits far below
     // tungstenite's defaults so an unauthenticated peer cannot make the
     // server buffer a huge message or fragmented frame sequence.
@@ -159,6 +175,7 @@ pub(crate) async fn websocket(
                 context.configdb.clone(),
                 handshake,
                 key,
+                presented_key,
                 remote,
             )
         })
@@ -167,55 +184,117 @@ pub(crate) async fn websocket(
 /// Resolve the agent key on a control-channel upgrade.
 ///
 /// A presented key must be valid even under `agents.allow-unauthenticated`:
-/// presented-but-wrong always fails closed. `Ok(None)` is the keyless
+/// presented-but-wrong always fails closed, and so does a client
+/// certificate not mapped to an agent key. When both are presented they
+/// must map to the same key. `Ok(None)` is the keyless
 /// allow-unauthenticated case.
 async fn authenticate_agent(
     context: &ServerContext,
     headers: &HeaderMap,
+    certificate: Option<&CertificateIdentity>,
     remote: SocketAddr,
 ) -> Result<Option<AgentKeyIdentity>, Response> {
-    let Some(bearer) = headers.typed_get::<Authorization<Bearer>>() else {
-        if context.config.agents_allow_unauthenticated {
-            return Ok(None);
-        }
-        debug!("Refusing agent connection from {remote}: no agent key presented");
-        return Err(agent_key_error(
-            StatusCode::UNAUTHORIZED,
-            "agent-key-required",
-            "this server requires an agent key: create one with `evebox config agents add <name>` \
-             and set it as server.key in agent.yaml (or EVEBOX_SERVER_KEY)",
-        ));
+    let verification_failed = |err: ConfigDbError| {
+        error!("Agent key verification failed: {err}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "agent key verification failed",
+        )
+            .into_response()
     };
-    match context.configdb.verify_agent_key(bearer.token()).await {
-        Ok(Some(key)) => {
-            if let Err(err) = context.configdb.touch_agent_key(key.id).await {
+
+    let certificate_key = match certificate {
+        Some(certificate) => match context
+            .configdb
+            .agent_key_for_certificate(&certificate.fingerprint, certificate.subject.as_deref())
+            .await
+        {
+            Ok(Some(key)) => Some(key),
+            Ok(None) => {
                 warn!(
-                    "Failed to update agent key last-seen for {:?}: {err}",
-                    key.name
+                    "Refusing agent connection from {remote}: client certificate {} (subject {:?}) is not mapped to an agent key",
+                    certificate.fingerprint, certificate.subject
                 );
+                return Err(agent_key_error(
+                    StatusCode::UNAUTHORIZED,
+                    "agent-certificate-rejected",
+                    "the client certificate is not mapped to an agent key: map it with \
+                     `evebox config agents certificate <name>`",
+                ));
             }
-            Ok(Some(AgentKeyIdentity {
-                id: key.id,
-                name: key.name,
-            }))
-        }
-        Ok(None) => {
-            warn!("Refusing agent connection from {remote}: unknown agent key");
-            Err(agent_key_error(
+            Err(err) => return Err(verification_failed(err)),
+        },
+        None if context.config.agents_require_certificate => {
+            debug!("Refusing agent connection from {remote}: no client certificate presented");
+            return Err(agent_key_error(
                 StatusCode::UNAUTHORIZED,
-                "agent-key-rejected",
-                "unknown agent key: issue a new one with `evebox config agents add <name>`",
-            ))
-        }
-        Err(err) => {
-            error!("Agent key verification failed: {err}");
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "agent key verification failed",
-            )
-                .into_response())
+                "agent-certificate-required",
+                "this server requires an agent client certificate: issue one with \
+                 `evebox config agents issue-certificate <name>` and set server.certificate \
+                 and server.certificate-key in agent.yaml",
+            ));
         }
+        None => None,
+    };
+
+    let key = match headers.typed_get::<Authorization<Bearer>>() {
+        Some(bearer) => match context.configdb.verify_agent_key(bearer.token()).await {
+            Ok(Some(key)) => key,
+            Ok(None) => {
+                warn!("Refusing agent connection from {remote}: unknown or expired agent key");
+                return Err(agent_key_error(
+                    StatusCode::UNAUTHORIZED,
+                    "agent-key-rejected",
+                    "unknown or expired agent key: issue a new one with `evebox config agents rotate <name>` \
+                     or `evebox config agents add <name>`",
+                ));
+            }
+            Err(err) => return Err(verification_failed(err)),
+        },
+        None => match &certificate_key {
+            Some(key)
+                if context.config.agents_certificate_without_key
+                    || context.config.agents_allow_unauthenticated =>
+            {
+                key.clone()
+            }
+            None if context.config.agents_allow_unauthenticated => return Ok(None),
+            _ => {
+                debug!("Refusing agent connection from {remote}: no agent key presented");
+                return Err(agent_key_error(
+                    StatusCode::UNAUTHORIZED,
+                    "agent-key-required",
+                    "this server requires an agent key: create one with `evebox config agents add <name>` \
+                     and set it as server.key in agent.yaml (or EVEBOX_SERVER_KEY)",
+                ));
+            }
+        },
+    };
+
+    if let Some(certificate_key) = &certificate_key
+        && certificate_key.id != key.id
+    {
+        warn!(
+            "Refusing agent connection from {remote}: its client certificate maps to agent key {:?} but it presented the key for {:?}",
+            certificate_key.name, key.name
+        );
+        return Err(agent_key_error(
+            StatusCode::UNAUTHORIZED,
+            "agent-certificate-mismatch",
+            "the client certificate and the agent key belong to different agents",
+        ));
+    }
+
+    if let Err(err) = context.configdb.touch_agent_key(key.id).await {
+        warn!(
+            "Failed to update agent key last-seen for {:?}: {err}",
+            key.name
+        );
     }
+    Ok(Some(AgentKeyIdentity {
+        id: key.id,
+        name: key.name,
+    }))
 }
 
 fn agent_key_error(status: StatusCode, code: &str, message: &str) -> Response {
@@ -226,12 +305,15 @@ fn agent_key_error(status: StatusCode, code: &str, message: &str) -> Response {
         .into_response()
 }
 
-/// `GET /api/agents`: the general connected-agent read model.
+/// `GET /api/agents`: the general connected-agent read model, limited to
+/// the agents of the user's sensors.
 pub(crate) async fn get_agents(
     State(context): State<Arc<ServerContext>>,
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
 ) -> impl IntoResponse {
-    Json(context.agents.list())
+    let mut agents = context.agents.list();
+    agents.retain(|agent| session.may_see_sensor(Some(&agent.name)));
+    Json(agents)
 }
 
 /// Disable the application's global body limit for the streaming PCAP upload
@@ -432,6 +514,7 @@ async fn connection(
     configdb: Arc<ConfigDb>,
     handshake: AgentHandshake,
     key: Option<AgentKeyIdentity>,
+    presented_key: Option<String>,
     remote: SocketAddr,
 ) {
     let (mut sink, mut stream) = socket.split();
@@ -462,11 +545,38 @@ async fn connection(
         entry.generation
     );
 
+    // The stored configuration follows hello on every connect. An agent
+    // that already runs this version simply acknowledges it again.
+    if let Some(key) = &entry.key
+        && entry.supports(CAPABILITY_CONFIG)
+    {
+        match configdb.get_agent_config(key.id).await {
+            Ok(Some(row)) => {
+                if let Err(err) = entry.try_send(config_message(&row)) {
+                    warn!("Could not queue configuration for agent {name:?}: {err}");
+                }
+            }
+            Ok(None) => {}
+            Err(err) => error!("Failed to load configuration for agent {name:?}: {err}"),
+        }
+    }
+
+    // Likewise the protected windows, so retention keeps escalated flows
+    // without waiting for the next refresh.
+    if entry.supports(CAPABILITY_PCAP_RETENTION)
+        && let Some(message) = agents.protect_message(&name)
+        && let Err(err) = entry.try_send(message)
+    {
+        warn!("Could not queue protected windows for agent {name:?}: {err}");
+    }
+
     let mut ping_interval = tokio::time::interval(PING_INTERVAL);
     ping_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
     ping_interval.tick().await; // consume the interval's immediate first tick
     let mut missed_pongs: u8 = 0;
     let mut last_ping: Option<Instant> = None;
+    let mut key_check = tokio::time::interval(KEY_CHECK_INTERVAL);
+    key_check.tick().await;
 
     loop {
         tokio::select! {
@@ -501,8 +611,13 @@ async fn connection(
                 match incoming {
                     Some(Ok(Message::Text(text))) => {
                         entry.touch();
-                        if deliver_text(&agents, &connection_id, text.as_str()) == Delivery::Fatal {
-                            break;
+                        match deliver_text(&agents, &connection_id, text.as_str()) {
+                            Delivery::Continue => {}
+                            Delivery::Fatal => break,
+                            Delivery::ConfigApplied { version, restart_required } => {
+                                config_applied(&configdb, &entry.key, &name, version, &restart_required).await;
+                            }
+                            Delivery::SpoolUsage(usage) => entry.set_spool_usage(usage.into()),
                         }
                     }
                     Some(Ok(Message::Pong(_))) => {
@@ -544,6 +659,17 @@ async fn connection(
                     break;
                 }
             }
+            _ = key_check.tick(), if presented_key.is_some() => {
+                let presented = presented_key.as_deref().unwrap_or_default();
+                match configdb.verify_agent_key(presented).await {
+                    Ok(Some(_)) => {}
+                    Ok(None) => {
+                        warn!("Agent {name:?}: its agent key has expired or was rotated out; disconnecting");
+                        break;
+                    }
+                    Err(err) => error!("Agent key verification failed for agent {name:?}: {err}"),
+                }
+            }
             // An on-demand ping for a liveness probe. It rides outside the
             // periodic schedule: no missed-pong or RTT accounting, its only
             // job is to elicit a pong for the waiting probe.
@@ -580,13 +706,56 @@ async fn connection(
 }
 
 fn server_capabilities() -> Vec<String> {
-    vec![CAPABILITY_PCAP.to_string()]
+    vec![
+        CAPABILITY_PCAP.to_string(),
+        CAPABILITY_CONFIG.to_string(),
+        CAPABILITY_RULES_UPDATE.to_string(),
+        CAPABILITY_SURICATA_COMMAND.to_string(),
+        CAPABILITY_PCAP_RETENTION.to_string(),
+    ]
+}
+
+/// The control message carrying a stored agent configuration.
+pub(crate) fn config_message(row: &AgentConfigRow) -> ServerMessage {
+    ServerMessage::Config {
+        version: row.version as u64,
+        config: row.config.0.clone(),
+    }
+}
+
+async fn config_applied(
+    configdb: &ConfigDb,
+    key: &Option<AgentKeyIdentity>,
+    name: &str,
+    version: u64,
+    restart_required: &[String],
+) {
+    // Only keyed agents are ever sent a configuration.
+    let Some(key) = key else {
+        debug!("Ignoring a configuration acknowledgement from unauthenticated agent {name:?}");
+        return;
+    };
+    if restart_required.is_empty() {
+        info!("Agent {name:?} applied configuration version {version}");
+    } else {
+        info!(
+            "Agent {name:?} applied configuration version {version}; a restart is required for {restart_required:?}"
+        );
+    }
+    if let Err(err) = configdb.set_agent_config_applied(key.id, version).await {
+        warn!("Failed to record the applied configuration for agent {name:?}: {err}");
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
 enum Delivery {
     Continue,
     Fatal,
+    ConfigApplied {
+        version: u64,
+        restart_required: Vec<String>,
+    },
+    SpoolUsage(WireSpoolUsage),
 }
 
 fn deliver_text(agents: &AgentRegistry, connection: &AgentConnectionId, text: &str) -> Delivery {
@@ -598,6 +767,14 @@ fn deliver_text(agents: &AgentRegistry, connection: &AgentConnectionId, text: &s
             );
             Delivery::Continue
         }
+        Ok(AgentMessage::ConfigApplied {
+            version,
+            restart_required,
+        }) => Delivery::ConfigApplied {
+            version,
+            restart_required,
+        },
+        Ok(AgentMessage::SpoolUsage { usage }) => Delivery::SpoolUsage(usage),
         Ok(message) => {
             agents.handle_message(connection, message);
             Delivery::Continue
@@ -628,6 +805,7 @@ mod tests {
     use tokio_tungstenite::tungstenite::http::Uri;
 
     use crate::agent::channel::{self, ChannelConfig};
+    use crate::agent::config::{AgentConfigHandle, AgentSettings};
     use crate::agent::protocol::{AgentHandshake, PcapResultCode, PcapUploadStatus, WireStats};
     use crate::eventrepo::EventRepo;
     use crate::pcap::SpoolConfig;
@@ -1057,7 +1235,12 @@ mod tests {
     }
 
     async fn add_test_key(context: &ServerContext, name: &str) -> String {
-        context.configdb.add_agent_key(name).await.unwrap().key
+        context
+            .configdb
+            .add_agent_key(name, None)
+            .await
+            .unwrap()
+            .key
     }
 
     async fn serve_remote_test() -> (
@@ -1074,7 +1257,13 @@ mod tests {
             agent_id: "test-sensor".to_string(),
             hostname: "test-host".to_string(),
             server_key: Some(key),
-            spool: SpoolConfig::new(testdata("spool"), None),
+            client_certificate: None,
+            settings: AgentConfigHandle::new(AgentSettings {
+                spool: Some(SpoolConfig::new(testdata("spool"), None)),
+                ..AgentSettings::default()
+            }),
+            suricata: Default::default(),
+            retention: None,
             disable_certificate_check: false,
         }));
         (address, server, agent, context, dir)
@@ -1419,6 +1608,205 @@ mod tests {
         server.abort();
     }
 
+    /// A stored configuration reaches the agent on connect, a saved change
+    /// is pushed live, and each acknowledgement is recorded on the key.
+    #[tokio::test]
+    async fn agent_config_is_pushed_applied_and_acknowledged() {
+        let (address, server, context, _dir) = serve_test_server(PcapSettings::default()).await;
+        let added = context
+            .configdb
+            .add_agent_key("test-sensor", None)
+            .await
+            .unwrap();
+        let client = reqwest::Client::new();
+        let url = format!("http://{address}/api/agents/keys/{}/config", added.id);
+        let response = client
+            .put(&url)
+            .json(&json!({"filters": {"add_fields": {"site": "east"}}}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        let settings = AgentConfigHandle::new(AgentSettings {
+            spool: Some(SpoolConfig::new(testdata("spool"), None)),
+            ..AgentSettings::default()
+        });
+        let agent = tokio::spawn(channel::run(ChannelConfig {
+            server_url: format!("http://{address}"),
+            agent_id: "test-sensor".to_string(),
+            hostname: "test-host".to_string(),
+            server_key: Some(added.key.clone()),
+            client_certificate: None,
+            settings: settings.clone(),
+            suricata: Default::default(),
+            retention: None,
+            disable_certificate_check: false,
+        }));
+
+        let applied = |version: i64| {
+            let context = context.clone();
+            async move {
+                let deadline = Instant::now() + Duration::from_secs(10);
+                loop {
+                    let row = context.configdb.get_agent_config(added.id).await.unwrap();
+                    if row.is_some_and(|row| row.applied_version == Some(version)) {
+                        return;
+                    }
+                    assert!(
+                        Instant::now() < deadline,
+                        "version {version} not acknowledged"
+                    );
+                    tokio::time::sleep(Duration::from_millis(25)).await;
+                }
+            }
+        };
+        applied(1).await;
+        assert_eq!(settings.current().version, Some(1));
+        assert_eq!(
+            settings.current().settings.add_fields.get("site"),
+            Some(&json!("east"))
+        );
+
+        // A live change that leaves packet capture alone keeps the
+        // connection.
+        let generation = context.agents.get("test-sensor").unwrap().generation;
+        client
+            .put(&url)
+            .json(&json!({"geoip": false, "input_paths": ["/data/eve.json"]}))
+            .send()
+            .await
+            .unwrap();
+        applied(2).await;
+        assert_eq!(
+            settings.current().settings.input_paths,
+            vec!["/data/eve.json".to_string()]
+        );
+        assert_eq!(
+            context.agents.get("test-sensor").unwrap().generation,
+            generation
+        );
+
+        let row: Value = client.get(&url).send().await.unwrap().json().await.unwrap();
+        assert_eq!(row["version"], 2);
+        assert_eq!(row["applied_version"], 2);
+        assert_eq!(
+            client
+                .get(format!("http://{address}/api/agents/keys/999/config"))
+                .send()
+                .await
+                .unwrap()
+                .status(),
+            404
+        );
+
+        agent.abort();
+        server.abort();
+    }
+
+    /// A rule update runs on the agent, its output and exit code come back
+    /// over the control channel, and the API reports the finished job.
+    #[tokio::test]
+    async fn rules_update_runs_on_the_agent_and_reports_back() {
+        let (address, server, context, _dir) = serve_test_server(PcapSettings::default()).await;
+        let key = add_test_key(&context, "test-sensor").await;
+        let agent = tokio::spawn(channel::run(ChannelConfig {
+            server_url: format!("http://{address}"),
+            agent_id: "test-sensor".to_string(),
+            hostname: "test-host".to_string(),
+            server_key: Some(key),
+            client_certificate: None,
+            settings: AgentConfigHandle::new(AgentSettings::default()),
+            suricata: crate::agent::suricata::SuricataConfig {
+                command_socket: None,
+                update: Some(crate::agent::suricata::RulesUpdateConfig {
+                    command: vec![
+                        "sh".to_string(),
+                        "-c".to_string(),
+                        "echo 'Writing rules'; exit 1".to_string(),
+                    ],
+                    reload: true,
+                }),
+                commands: Vec::new(),
+            },
+            retention: None,
+            disable_certificate_check: false,
+        }));
+        let client = reqwest::Client::new();
+        let url = format!("http://{address}/api/agents/test-sensor/rules/update");
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while context.agents.get("test-sensor").is_none() {
+            assert!(Instant::now() < deadline, "agent did not connect");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(client.get(&url).send().await.unwrap().status(), 404);
+
+        let response = client.post(&url).send().await.unwrap();
+        assert_eq!(response.status(), 202);
+        let started: Value = response.json().await.unwrap();
+        assert_eq!(started["state"], "running");
+
+        let job = loop {
+            let job: Value = client.get(&url).send().await.unwrap().json().await.unwrap();
+            if job["state"] != "running" {
+                break job;
+            }
+            assert!(Instant::now() < deadline, "rule update did not finish");
+            tokio::time::sleep(Duration::from_millis(25)).await;
+        };
+        assert_eq!(job["id"], started["id"]);
+        assert_eq!(job["state"], "failed");
+        assert_eq!(job["exit_code"], 1);
+        assert_eq!(job["reloaded"], false);
+        assert_eq!(job["output"], json!(["Writing rules"]));
+
+        let missing = client
+            .post(format!("http://{address}/api/agents/other/rules/update"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(missing.status(), 404);
+        let unknown = client
+            .post(format!(
+                "http://{address}/api/agents/test-sensor/suricata/shutdown"
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(unknown.status(), 400);
+
+        let records = context
+            .configdb
+            .search_audit(&crate::sqlite::configdb::AuditQuery {
+                action: Some("agent".to_string()),
+                limit: 100,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let records: Vec<_> = records
+            .iter()
+            .map(|row| {
+                (
+                    row.record.action.as_str(),
+                    row.record.target.as_deref(),
+                    row.record.outcome.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            records,
+            [
+                ("agent.suricata-command", Some("test-sensor"), "rejected"),
+                ("agent.rules-update", Some("other"), "rejected"),
+                ("agent.rules-update", Some("test-sensor"), "ok"),
+            ]
+        );
+
+        agent.abort();
+        server.abort();
+    }
+
     async fn next_server_control(socket: &mut TestAgentSocket) -> ServerMessage {
         loop {
             let message = tokio::time::timeout(Duration::from_secs(3), socket.next())
@@ -2078,4 +2466,199 @@ mod tests {
         agent.abort();
         server.abort();
     }
+
+    /// A TLS server requesting agent client certificates, and the agent CA
+    /// that issues them.
+    async fn serve_tls_test_server(
+        require_certificate: bool,
+        certificate_without_key: bool,
+    ) -> (
+        std::net::SocketAddr,
+        tokio::task::JoinHandle<()>,
+        Arc<ServerContext>,
+        tempfile::TempDir,
+        tempfile::TempDir,
+        crate::cert::AgentCa,
+    ) {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let tls_dir = tempfile::tempdir().unwrap();
+        let (cert_path, key_path) = crate::cert::create_and_write_cert(tls_dir.path()).unwrap();
+        let ca = crate::cert::get_or_create_agent_ca(tls_dir.path()).unwrap();
+        let server_config = ServerConfig {
+            tls_enabled: true,
+            tls_cert_filename: Some(cert_path),
+            tls_key_filename: Some(key_path),
+            agents_client_ca: Some(ca.cert_path.clone()),
+            agents_require_certificate: require_certificate,
+            agents_certificate_without_key: certificate_without_key,
+            ..ServerConfig::default()
+        };
+        let tls_config = crate::server::main::rustls_config(&server_config)
+            .await
+            .unwrap();
+        let (_address, plain, context, dir) =
+            serve_test_server_with_config(PcapSettings::default(), server_config).await;
+        plain.abort();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let address = listener.local_addr().unwrap();
+        let service = build_axum_service(context.clone());
+        let server = tokio::spawn(async move {
+            axum_server::from_tcp_rustls(listener, tls_config)
+                .unwrap()
+                .map(crate::server::client_cert::PeerCertificateAcceptor::new)
+                .serve(service)
+                .await
+                .unwrap();
+        });
+        (address, server, context, dir, tls_dir, ca)
+    }
+
+    /// Issue a client certificate for `name` and write it under `dir`.
+    fn issue_test_certificate(
+        ca: &crate::cert::AgentCa,
+        dir: &Path,
+        name: &str,
+    ) -> (crate::agent::tls::ClientCertificate, String) {
+        let issued = ca.issue(name, 1).unwrap();
+        let client_certificate = crate::agent::tls::ClientCertificate {
+            certificate: dir.join(format!("{name}.pem")),
+            key: dir.join(format!("{name}-key.pem")),
+        };
+        std::fs::write(&client_certificate.certificate, &issued.cert_pem).unwrap();
+        std::fs::write(&client_certificate.key, &issued.key_pem).unwrap();
+        (client_certificate, issued.fingerprint)
+    }
+
+    /// Attempt a control-channel upgrade over TLS, returning the refusal's
+    /// error code, or None when the upgrade succeeds.
+    async fn tls_upgrade(
+        address: std::net::SocketAddr,
+        key: Option<&str>,
+        certificate: Option<&crate::agent::tls::ClientCertificate>,
+    ) -> Option<String> {
+        let uri: Uri = format!("wss://localhost:{}/api/agent/ws", address.port())
+            .parse()
+            .unwrap();
+        let handshake = serde_json::to_string(&AgentHandshake {
+            name: "test-sensor".to_string(),
+            hostname: "test-host".to_string(),
+            version: "test".to_string(),
+            capabilities: vec![CAPABILITY_PCAP.to_string()],
+        })
+        .unwrap();
+        let mut request = ClientRequestBuilder::new(uri)
+            .with_sub_protocol(SUBPROTOCOL)
+            .with_header(AGENT_HEADER, handshake);
+        if let Some(key) = key {
+            request = request.with_header("authorization", format!("Bearer {key}"));
+        }
+        let connector = crate::agent::tls::connector(true, certificate).unwrap();
+        let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        match tokio_tungstenite::client_async_tls_with_config(request, stream, None, connector)
+            .await
+        {
+            Ok(_) => None,
+            Err(tokio_tungstenite::tungstenite::Error::Http(response)) => {
+                assert_eq!(response.status(), 401);
+                let body: Value =
+                    serde_json::from_slice(response.body().as_ref().unwrap()).unwrap();
+                Some(body["error"]["code"].as_str().unwrap().to_string())
+            }
+            Err(err) => panic!("unexpected upgrade error: {err}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn client_certificates_authenticate_agents_with_their_key() {
+        let (address, server, context, dir, _tls_dir, ca) =
+            serve_tls_test_server(true, false).await;
+        let key = add_test_key(&context, "test-sensor").await;
+        let other_key = add_test_key(&context, "other-sensor").await;
+        let (certificate, fingerprint) = issue_test_certificate(&ca, dir.path(), "test-sensor");
+        let (unmapped, _) = issue_test_certificate(&ca, dir.path(), "unmapped");
+        let row = context
+            .configdb
+            .verify_agent_key(&key)
+            .await
+            .unwrap()
+            .unwrap();
+        context
+            .configdb
+            .set_agent_key_certificate(row.id, Some(&fingerprint), None)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            tls_upgrade(address, Some(&key), None).await.as_deref(),
+            Some("agent-certificate-required")
+        );
+        assert_eq!(
+            tls_upgrade(address, None, Some(&certificate))
+                .await
+                .as_deref(),
+            Some("agent-key-required")
+        );
+        assert_eq!(
+            tls_upgrade(address, Some(&other_key), Some(&certificate))
+                .await
+                .as_deref(),
+            Some("agent-certificate-mismatch")
+        );
+        assert_eq!(
+            tls_upgrade(address, Some(&key), Some(&unmapped))
+                .await
+                .as_deref(),
+            Some("agent-certificate-rejected")
+        );
+        assert_eq!(
+            tls_upgrade(address, Some(&key), Some(&certificate)).await,
+            None
+        );
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn a_mapped_client_certificate_can_replace_the_agent_key() {
+        let (address, server, context, dir, _tls_dir, ca) =
+            serve_tls_test_server(false, true).await;
+        let key = add_test_key(&context, "test-sensor").await;
+        let (certificate, _) = issue_test_certificate(&ca, dir.path(), "test-sensor");
+        let row = context
+            .configdb
+            .verify_agent_key(&key)
+            .await
+            .unwrap()
+            .unwrap();
+        // Map by subject: the CA vouches for the common name.
+        context
+            .configdb
+            .set_agent_key_certificate(row.id, None, Some("test-sensor"))
+            .await
+            .unwrap();
+
+        // Without a certificate the key is still required.
+        assert_eq!(
+            tls_upgrade(address, None, None).await.as_deref(),
+            Some("agent-key-required")
+        );
+
+        let agent = tokio::spawn(channel::run(ChannelConfig {
+            server_url: format!("https://localhost:{}", address.port()),
+            agent_id: "test-sensor".to_string(),
+            hostname: "test-host".to_string(),
+            server_key: None,
+            client_certificate: Some(certificate),
+            settings: AgentConfigHandle::new(AgentSettings::default()),
+            suricata: Default::default(),
+            retention: None,
+            disable_certificate_check: true,
+        }));
+        wait_until(|| context.agents.connected() == 1).await;
+        let listed = context.agents.list();
+        assert_eq!(listed[0].name, "test-sensor");
+
+        agent.abort();
+        server.abort();
+    }
 }
@@ -49,7 +49,7 @@ fn default_order() -> String {
 }
 
 pub(crate) async fn agg_sse(
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
     Extension(context): Extension<Arc<ServerContext>>,
     Form(form): Form<AggParams>,
 ) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
@@ -73,6 +73,7 @@ pub(crate) async fn agg_sse(
         negated: false,
         value: QueryValue::From(min_timestamp.into()),
     });
+    query_string.extend(session.sensor_filter());
 
     if let EventRepo::Elastic(ds) = &context.datastore {
         // For Elastic we delay the SSE reponse until we have data
@@ -149,7 +150,7 @@ pub(crate) async fn agg_sse(
 }
 
 pub(crate) async fn agg(
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
     State(context): State<Arc<ServerContext>>,
     Form(form): Form<AggParams>,
 ) -> Result<impl IntoResponse, AppError> {
@@ -169,6 +170,7 @@ pub(crate) async fn agg(
         negated: false,
         value: QueryValue::From(min_timestamp.into()),
     });
+    query_string.extend(session.sensor_filter());
 
     let results = context
         .datastore
@@ -189,7 +191,7 @@ pub(crate) struct EventTypesParams {
 }
 
 pub(crate) async fn event_types(
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
     State(context): State<Arc<ServerContext>>,
     Form(form): Form<EventTypesParams>,
 ) -> Result<impl IntoResponse, AppError> {
@@ -201,10 +203,11 @@ pub(crate) async fn event_types(
         negated: false,
         value: QueryValue::From(min_timestamp.into()),
     });
+    query_string.extend(session.sensor_filter());
 
     match &context.datastore {
         crate::eventrepo::EventRepo::Elastic(ds) => {
-            let results = ds.get_event_types().await?;
+            let results = ds.get_event_types(session.sensors.as_deref()).await?;
             Ok(Json(results))
         }
         crate::eventrepo::EventRepo::SQLite(ds) => {
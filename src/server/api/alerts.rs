@@ -11,13 +11,14 @@ use axum_extra::extract::Form;
 use super::{DateTime, GenericQuery, ServerContext, SessionExtractor, parse_then_from_duration};
 
 pub(crate) async fn alerts(
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
     Extension(context): Extension<Arc<ServerContext>>,
     Form(query): Form<GenericQuery>,
 ) -> Result<impl IntoResponse, AppError> {
     let mut options = elastic::AlertQueryOptions {
         query_string: query.query_string,
         sensor: query.sensor,
+        sensors: session.sensors.clone(),
         timeout: query.timeout,
         ..elastic::AlertQueryOptions::default()
     };
@@ -0,0 +1,352 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! `/api/analyze/pcap`: upload a capture for Suricata analysis and follow
+//! its import.
+
+use std::net::SocketAddr;
+
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Extension, Json, Path, Query, State};
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
+use futures::StreamExt;
+use tokio::io::AsyncWriteExt;
+
+use crate::prelude::*;
+use crate::server::ServerContext;
+use crate::server::analyze::{Analysis, Analyzer};
+use crate::server::api::pcap::{error, remote_addr};
+use crate::server::main::SessionExtractor;
+use crate::server::pcap::audit::PayloadDigest;
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct UploadQuery {
+    /// The name of the uploaded file, for display.
+    filename: Option<String>,
+}
+
+/// `POST /api/analyze/pcap`: the request body is the capture. It is
+/// stored, then analyzed in the background; the queued analysis is
+/// returned, to be polled with `GET /api/analyze/pcap/{id}`.
+pub(crate) async fn post_pcap(
+    State(context): State<Arc<ServerContext>>,
+    SessionExtractor(session): SessionExtractor,
+    Extension(ConnectInfo(remote)): Extension<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Query(query): Query<UploadQuery>,
+    body: Body,
+) -> Response {
+    let Some(analyzer) = context.analyzer.clone() else {
+        return not_enabled();
+    };
+    let user = session.username.clone().unwrap_or_else(|| "-".to_string());
+    let remote = remote_addr(&context, &headers, remote);
+    let filename = display_filename(query.filename.as_deref());
+
+    let mut analysis = match analyzer.create(&user, &filename) {
+        Ok(analysis) => analysis,
+        Err(err) => {
+            error!("Failed to create an analysis directory: {err}");
+            return error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "io",
+                "failed to store the upload",
+            );
+        }
+    };
+    let digest = match receive(&analyzer, &analysis, body).await {
+        Ok(digest) => digest,
+        Err(response) => {
+            analyzer.discard(&analysis.id);
+            warn!(
+                "analyze: user={:?} remote={:?} filename={:?} outcome=rejected status={}",
+                user,
+                remote,
+                filename,
+                response.status()
+            );
+            return response;
+        }
+    };
+    analysis.bytes = digest.bytes();
+    analysis.sha256 = digest.sha256().unwrap_or_default();
+    analyzer.update(&analysis);
+    info!(
+        "analyze: user={:?} remote={:?} filename={:?} analysis={} bytes={} sha256={} outcome=queued",
+        user, remote, filename, analysis.id, analysis.bytes, analysis.sha256
+    );
+    tokio::spawn(crate::server::analyze::run(
+        context.clone(),
+        analyzer,
+        analysis.clone(),
+    ));
+    (StatusCode::ACCEPTED, Json(analysis)).into_response()
+}
+
+/// Write the upload to the analysis' capture file, within the size
+/// limit, and check it is a capture Suricata can read.
+async fn receive(
+    analyzer: &Analyzer,
+    analysis: &Analysis,
+    body: Body,
+) -> Result<PayloadDigest, Response> {
+    let path = analyzer.capture_path(&analysis.id);
+    let write_failed = |err: std::io::Error| {
+        error!("Failed to write upload {}: {err}", path.display());
+        error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "io",
+            "failed to store the upload",
+        )
+    };
+    let mut file = tokio::fs::File::create(&path).await.map_err(write_failed)?;
+    let digest = PayloadDigest::default();
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|_| {
+            error(
+                StatusCode::BAD_REQUEST,
+                "bad-request",
+                "the upload was interrupted",
+            )
+        })?;
+        if digest.bytes() + chunk.len() as u64 > analyzer.settings.max_bytes {
+            return Err(error(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "too-large",
+                &format!(
+                    "uploads are limited to {} bytes",
+                    analyzer.settings.max_bytes
+                ),
+            ));
+        }
+        file.write_all(&chunk).await.map_err(write_failed)?;
+        digest.update(&chunk);
+    }
+    file.flush().await.map_err(write_failed)?;
+    if digest.bytes() == 0 {
+        return Err(error(
+            StatusCode::BAD_REQUEST,
+            "bad-request",
+            "the upload is empty",
+        ));
+    }
+    crate::cli::oneshot::validate_pcap(&path).map_err(|_| {
+        error(
+            StatusCode::BAD_REQUEST,
+            "bad-format",
+            "the upload is not an uncompressed pcap file",
+        )
+    })?;
+    Ok(digest)
+}
+
+/// `GET /api/analyze/pcap`: every analysis the user may see the events
+/// of, newest first.
+pub(crate) async fn get_analyses(
+    State(context): State<Arc<ServerContext>>,
+    SessionExtractor(session): SessionExtractor,
+) -> Response {
+    match &context.analyzer {
+        Some(analyzer) => {
+            let mut analyses = analyzer.list();
+            analyses.retain(|analysis| session.may_see_sensor(Some(&analysis.sensor)));
+            Json(json!({ "analyses": analyses })).into_response()
+        }
+        None => not_enabled(),
+    }
+}
+
+/// `GET /api/analyze/pcap/{id}`: an analysis' state and event count.
+pub(crate) async fn get_analysis(
+    State(context): State<Arc<ServerContext>>,
+    SessionExtractor(session): SessionExtractor,
+    Path(id): Path<String>,
+) -> Response {
+    let Some(analyzer) = &context.analyzer else {
+        return not_enabled();
+    };
+    match analyzer.get(&id) {
+        Some(analysis) if session.may_see_sensor(Some(&analysis.sensor)) => {
+            Json(analysis).into_response()
+        }
+        _ => error(StatusCode::NOT_FOUND, "not-found", "no such analysis"),
+    }
+}
+
+fn not_enabled() -> Response {
+    error(
+        StatusCode::NOT_FOUND,
+        "not-enabled",
+        "pcap analysis is not enabled",
+    )
+}
+
+/// The last path component of the uploaded name, or `upload.pcap`.
+fn display_filename(filename: Option<&str>) -> String {
+    filename
+        .and_then(|filename| filename.rsplit(['/', '\\']).next())
+        .map(|filename| filename.trim())
+        .filter(|filename| !filename.is_empty())
+        .map(|filename| filename.chars().take(255).collect())
+        .unwrap_or_else(|| "upload.pcap".to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::eventrepo::EventRepo;
+    use crate::server::analyze::AnalyzeSettings;
+    use crate::server::metrics::Metrics;
+    use crate::server::session::{Role, Session};
+    use crate::server::{ServerConfig, ServerContext};
+    use crate::sqlite::connection::{ConnectionBuilder, init_event_db};
+    use crate::sqlite::eventrepo::SqliteEventRepo;
+
+    async fn context(dir: &std::path::Path) -> Arc<ServerContext> {
+        let builder = ConnectionBuilder::filename(Some(&dir.join("events.sqlite")));
+        let mut writer = builder.open_connection(true).await.unwrap();
+        init_event_db(&mut writer).await.unwrap();
+        let pool = builder.open_pool(false).await.unwrap();
+        let datastore = EventRepo::SQLite(SqliteEventRepo::new(
+            Arc::new(tokio::sync::Mutex::new(writer)),
+            pool,
+            Arc::new(Metrics::default()),
+        ));
+        let configdb = crate::sqlite::configdb::open(Some(&dir.join("config.sqlite")))
+            .await
+            .unwrap();
+        let mut context = ServerContext::new(
+            ServerConfig::default(),
+            Arc::new(configdb),
+            datastore,
+            Arc::new(Metrics::default()),
+        );
+        context.analyzer = Some(Arc::new(
+            Analyzer::open(AnalyzeSettings {
+                directory: dir.join("analyze"),
+                max_bytes: 64,
+                suricata: Default::default(),
+            })
+            .unwrap(),
+        ));
+        Arc::new(context)
+    }
+
+    async fn upload(context: &Arc<ServerContext>, body: Vec<u8>) -> Response {
+        post_pcap(
+            State(context.clone()),
+            SessionExtractor(Arc::new(Session::anonymous(None))),
+            Extension(ConnectInfo("127.0.0.1:1".parse().unwrap())),
+            HeaderMap::new(),
+            Query(UploadQuery {
+                filename: Some("/tmp/capture.pcap".to_string()),
+            }),
+            Body::from(body),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn bad_uploads_are_rejected_and_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let context = context(dir.path()).await;
+
+        let response = upload(&context, b"not a capture".to_vec()).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = upload(&context, vec![0xd4; 65]).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let response = upload(&context, Vec::new()).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        assert!(context.analyzer.as_ref().unwrap().list().is_empty());
+        assert_eq!(
+            std::fs::read_dir(dir.path().join("analyze"))
+                .unwrap()
+                .count(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn imported_events_carry_the_analysis_sensor() {
+        let dir = tempfile::tempdir().unwrap();
+        let context = context(dir.path()).await;
+        let analyzer = context.analyzer.clone().unwrap();
+        let mut analysis = analyzer.create("admin", "capture.pcap").unwrap();
+        let eve = dir.path().join("eve.json");
+        std::fs::write(
+            &eve,
+            concat!(
+                r#"{"timestamp":"2026-01-02T03:04:05.000000+0000","event_type":"alert","src_ip":"10.0.0.1","dest_ip":"10.0.0.2","alert":{"signature_id":1}}"#,
+                "\n",
+                r#"{"timestamp":"2026-01-02T03:04:06.000000+0000","event_type":"dns","src_ip":"10.0.0.1","dest_ip":"10.0.0.2"}"#,
+                "\n",
+            ),
+        )
+        .unwrap();
+        crate::server::analyze::import(&context, &analyzer, &mut analysis, &eve)
+            .await
+            .unwrap();
+        assert_eq!(analysis.events, 2);
+
+        let events = context.datastore.events(Default::default()).await.unwrap();
+        let events = events["events"].as_array().unwrap();
+        assert_eq!(events.len(), 2);
+        for event in events {
+            assert_eq!(event["_source"]["host"], analysis.sensor.as_str());
+            assert_eq!(
+                event["_source"]["evebox"]["analysis"]["id"],
+                analysis.id.as_str()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn sensor_limited_users_see_only_their_analyses() {
+        let dir = tempfile::tempdir().unwrap();
+        let context = context(dir.path()).await;
+        let analyzer = context.analyzer.clone().unwrap();
+        let analysis = analyzer.create("admin", "capture.pcap").unwrap();
+        analyzer.update(&analysis);
+
+        for (sensor, visible) in [(analysis.sensor.as_str(), true), ("other-sensor", false)] {
+            let mut session = Session::with_username("bob", Role::Viewer);
+            session.sensors = Some(vec![sensor.to_string()]);
+            let session = Arc::new(session);
+
+            let response =
+                get_analyses(State(context.clone()), SessionExtractor(session.clone())).await;
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(
+                body["analyses"].as_array().unwrap().len(),
+                usize::from(visible)
+            );
+
+            let response = get_analysis(
+                State(context.clone()),
+                SessionExtractor(session),
+                Path(analysis.id.clone()),
+            )
+            .await;
+            let status = if visible {
+                StatusCode::OK
+            } else {
+                StatusCode::NOT_FOUND
+            };
+            assert_eq!(response.status(), status);
+        }
+    }
+
+    #[test]
+    fn filenames_are_reduced_to_their_last_component() {
+        assert_eq!(display_filename(Some("/tmp/capture.pcap")), "capture.pcap");
+        assert_eq!(display_filename(Some("C:\\captures\\a.pcap")), "a.pcap");
+        assert_eq!(display_filename(Some("dir/")), "upload.pcap");
+        assert_eq!(display_filename(None), "upload.pcap");
+    }
+}
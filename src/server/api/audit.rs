@@ -0,0 +1,187 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! `GET /api/audit`: search the audit trail of user and admin actions,
+//! as JSON or as a CSV export.
+
+use axum::Json;
+use axum::extract::{Query, State};
+use axum::http::HeaderValue;
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use axum::response::{IntoResponse, Response};
+
+use super::pcap::audit::csv_field;
+use crate::prelude::*;
+use crate::server::ServerContext;
+use crate::server::main::SessionExtractor;
+use crate::sqlite::configdb::{AuditQuery, AuditRow};
+
+const DEFAULT_LIMIT: u32 = 100;
+const MAX_LIMIT: u32 = 10_000;
+
+const CSV_COLUMNS: &[&str] = &[
+    "id",
+    "timestamp",
+    "username",
+    "api_token",
+    "remote",
+    "action",
+    "target",
+    "parameters",
+    "outcome",
+    "message",
+];
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct AuditSearch {
+    user: Option<String>,
+    /// An action, such as `user.add`, or an object, such as `user` for
+    /// all of its actions.
+    action: Option<String>,
+    target: Option<String>,
+    outcome: Option<String>,
+    /// Records at or after this time.
+    from: Option<String>,
+    /// Records before this time.
+    to: Option<String>,
+    tz_offset: Option<String>,
+    limit: Option<u32>,
+    /// `json` (the default) or `csv`.
+    format: Option<String>,
+}
+
+pub(crate) async fn get_audit(
+    State(context): State<Arc<ServerContext>>,
+    _session: SessionExtractor,
+    Query(search): Query<AuditSearch>,
+) -> Result<Response, AppError> {
+    let csv = match search.format.as_deref() {
+        None | Some("json") => false,
+        Some("csv") => true,
+        Some(other) => {
+            return Err(AppError::BadRequest(format!(
+                "unsupported audit format: {other}"
+            )));
+        }
+    };
+    let query = build_query(&search).map_err(AppError::BadRequest)?;
+    let rows = context.configdb.search_audit(&query).await?;
+    if csv {
+        Ok((
+            [
+                (CONTENT_TYPE, HeaderValue::from_static("text/csv")),
+                (
+                    CONTENT_DISPOSITION,
+                    HeaderValue::from_static("attachment; filename=audit.csv"),
+                ),
+            ],
+            to_csv(&rows),
+        )
+            .into_response())
+    } else {
+        Ok(Json(json!({ "records": rows })).into_response())
+    }
+}
+
+fn build_query(search: &AuditSearch) -> Result<AuditQuery, String> {
+    let present = |value: &Option<String>| {
+        value
+            .as_deref()
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+    };
+    let time = |value: &Option<String>, field: &str| -> Result<Option<i64>, String> {
+        match present(value) {
+            Some(value) => crate::datetime::parse(&value, search.tz_offset.as_deref())
+                .map(|datetime| Some(datetime.to_seconds()))
+                .map_err(|err| format!("bad {field}: {err}")),
+            None => Ok(None),
+        }
+    };
+    Ok(AuditQuery {
+        username: present(&search.user),
+        action: present(&search.action),
+        target: present(&search.target),
+        outcome: present(&search.outcome),
+        from: time(&search.from, "from")?,
+        to: time(&search.to, "to")?,
+        limit: search.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT),
+    })
+}
+
+fn to_csv(rows: &[AuditRow]) -> String {
+    let mut out = CSV_COLUMNS.join(",");
+    out.push_str("\r\n");
+    for row in rows {
+        let record = &row.record;
+        let optional = |value: &Option<String>| value.clone().unwrap_or_default();
+        let fields = [
+            row.id.to_string(),
+            row.timestamp.to_rfc3339(),
+            record.username.clone(),
+            optional(&record.api_token),
+            record.remote.clone(),
+            record.action.clone(),
+            optional(&record.target),
+            record.parameters.0.to_string(),
+            record.outcome.clone(),
+            optional(&record.message),
+        ];
+        let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        out.push_str(&fields.join(","));
+        out.push_str("\r\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sqlite::configdb::AuditRecord;
+
+    #[test]
+    fn csv_carries_parameters_as_json() {
+        let row = AuditRow {
+            id: 3,
+            timestamp: chrono::DateTime::parse_from_rfc3339("2026-01-02T03:04:05+00:00").unwrap(),
+            record: AuditRecord {
+                username: "admin".to_string(),
+                remote: "192.0.2.1:5000".to_string(),
+                action: "user.role".to_string(),
+                target: Some("bob".to_string()),
+                parameters: sqlx::types::Json(json!({"role": "admin"})),
+                outcome: "ok".to_string(),
+                ..Default::default()
+            },
+        };
+        let csv = to_csv(&[row]);
+        let lines: Vec<&str> = csv.split("\r\n").collect();
+        assert_eq!(lines[0], CSV_COLUMNS.join(","));
+        assert_eq!(
+            lines[1],
+            "3,2026-01-02T03:04:05+00:00,admin,,192.0.2.1:5000,user.role,bob,\"{\"\"role\"\":\"\"admin\"\"}\",ok,"
+        );
+    }
+
+    #[test]
+    fn search_fields_are_normalized() {
+        let query = build_query(&AuditSearch {
+            action: Some(" agent-key ".to_string()),
+            target: Some("".to_string()),
+            limit: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(query.action, Some("agent-key".to_string()));
+        assert_eq!(query.target, None);
+        assert_eq!(query.limit, 1);
+        assert!(
+            build_query(&AuditSearch {
+                from: Some("not a time".to_string()),
+                ..Default::default()
+            })
+            .is_err()
+        );
+    }
+}
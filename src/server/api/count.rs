@@ -23,11 +23,11 @@ pub(crate) struct OccurrencesOfForm {
 }
 
 pub(crate) async fn count(
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
     Extension(context): Extension<Arc<ServerContext>>,
     Form(form): Form<OccurrencesOfForm>,
 ) -> Result<impl IntoResponse, AppError> {
-    let q = form
+    let mut q = form
         .q
         .clone()
         .map(|q| queryparser::parse(&q, None))
@@ -36,6 +36,7 @@ pub(crate) async fn count(
     if q.is_empty() {
         return Ok((StatusCode::BAD_REQUEST, "query required").into_response());
     }
+    q.extend(session.sensor_filter());
 
     let result = match &context.datastore {
         crate::eventrepo::EventRepo::Elastic(ds) => elastic_count(ds, q).await?,
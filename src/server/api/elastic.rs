@@ -5,6 +5,7 @@ use axum::extract::Path;
 
 use super::prelude::*;
 use crate::prelude::*;
+use crate::server::audit::{Auditor, Outcome};
 
 pub(crate) async fn indices(
     _session: SessionExtractor,
@@ -23,16 +24,32 @@ pub(crate) async fn indices(
 }
 
 pub(crate) async fn delete(
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
     Extension(context): Extension<Arc<ServerContext>>,
+    audit: Auditor,
     Path(name): Path<String>,
 ) -> Result<impl IntoResponse, AppError> {
     match &context.datastore {
         crate::eventrepo::EventRepo::SQLite(_) => Err(AppError::InternalServerError),
         crate::eventrepo::EventRepo::Elastic(elastic) => {
             info!("Deleting index: {}", name);
-            let status = elastic.get_client().delete_index(&name).await?;
-            let status = status.as_u16();
+            let result = elastic.get_client().delete_index(&name).await;
+            let outcome = match &result {
+                Ok(status) if !status.is_success() => {
+                    Outcome::Error(format!("Elasticsearch responded with {status}"))
+                }
+                result => Outcome::of(result),
+            };
+            audit
+                .record(
+                    &session,
+                    "elastic.index.delete",
+                    Some(&name),
+                    json!({}),
+                    outcome,
+                )
+                .await;
+            let status = result?.as_u16();
             let status = StatusCode::from_u16(status).map_err(|_| {
                 AppError::StringError("invalid status code returned from elasticsearch".to_string())
             })?;
@@ -20,6 +20,10 @@ use crate::error::AppError;
 pub(crate) struct PcapForm {
     pub what: String,
     pub event: String,
+    /// `pcap` (the default) or `pcapng`, which comments the packet with
+    /// the event's flow id and signature.
+    #[serde(default)]
+    pub format: Option<String>,
 }
 
 pub(crate) async fn handler(
@@ -27,19 +31,29 @@ pub(crate) async fn handler(
     _session: SessionExtractor,
     Form(form): Form<PcapForm>,
 ) -> Result<impl IntoResponse, AppError> {
+    let (content_type, extension) = match form.format.as_deref() {
+        None | Some("") | Some("pcap") => ("application/vnd.tcpdump.pcap", "pcap"),
+        Some("pcapng") => ("application/x-pcapng", "pcapng"),
+        Some(other) => {
+            return Err(AppError::BadRequest(format!(
+                "unsupported pcap format: {other}"
+            )));
+        }
+    };
     let mut hmap = HeaderMap::new();
-    hmap.insert(
-        CONTENT_TYPE,
-        "application/vnd.tcpdump.pcap".parse().unwrap(),
-    );
+    hmap.insert(CONTENT_TYPE, content_type.parse().unwrap());
 
     let event: serde_json::Value = serde_json::from_str(&form.event)
         .map_err(|err| AppError::BadRequest(format!("failed to decode event: {err}")))?;
 
-    let pcap_buffer = pcap::eve_to_pcap(&form.what, &event)?;
+    let pcap_buffer = if extension == "pcapng" {
+        pcap::eve_to_pcapng(&form.what, &event)?
+    } else {
+        pcap::eve_to_pcap(&form.what, &event)?
+    };
     let filename = generate_filename(&event);
 
-    let cs_hdr_value = format!("attachment; filename={filename}.pcap");
+    let cs_hdr_value = format!("attachment; filename={filename}.{extension}");
     hmap.insert(CONTENT_DISPOSITION, cs_hdr_value.parse().unwrap());
 
     Ok((hmap, pcap_buffer))
@@ -17,7 +17,7 @@ use tokio_stream::wrappers::BroadcastStream;
 use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
 
 pub(crate) async fn sse(
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
     Extension(context): Extension<Arc<ServerContext>>,
 ) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
     let mut firehose = context.firehose.subscribe();
@@ -27,6 +27,9 @@ pub(crate) async fn sse(
 
     tokio::spawn(async move {
         while let Ok(event) = firehose.recv().await {
+            if !session.may_see_event(&event) {
+                continue;
+            }
             let event = match Event::default().json_data(event) {
                 Ok(event) => event,
                 Err(_) => return,
@@ -46,13 +49,14 @@ pub(crate) async fn sse(
 }
 
 pub(crate) async fn stream(
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
     Extension(context): Extension<Arc<ServerContext>>,
 ) -> impl IntoResponse {
     let firehose: tokio::sync::broadcast::Receiver<serde_json::Value> =
         context.firehose.subscribe();
 
-    let stream = BroadcastStream::new(firehose).filter_map(|result| match result {
+    let stream = BroadcastStream::new(firehose).filter_map(move |result| match result {
+        Ok(value) if !session.may_see_event(&value) => None,
         Ok(value) => {
             let mut string = value.to_string();
             string.push('\n');
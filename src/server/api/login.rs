@@ -2,24 +2,31 @@
 // SPDX-License-Identifier: MIT
 
 use axum::Json;
-use axum::extract::Extension;
+use axum::extract::{ConnectInfo, Extension, Query};
 use axum::http::StatusCode;
 use axum::http::header::HeaderMap;
-use axum::http::header::SET_COOKIE;
-use axum::response::IntoResponse;
+use axum::http::header::{LOCATION, RETRY_AFTER, SET_COOKIE, USER_AGENT};
+use axum::response::{IntoResponse, Response};
+use axum_extra::extract::CookieJar;
 use serde::Deserialize;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tracing::{error, info, warn};
 
 use crate::server::ServerContext;
+use crate::server::ldap::LdapError;
 use crate::server::main::SessionExtractor;
+use crate::server::oidc::OidcError;
 use crate::server::session::Session;
-use crate::sqlite::configdb::ConfigDbError;
+use crate::server::totp::{self, LoginStep};
+use crate::sqlite::configdb::{ConfigDbError, User};
 
 #[derive(Debug, Deserialize)]
 pub(crate) struct LoginForm {
     pub username: Option<String>,
     pub password: Option<String>,
+    /// A TOTP or recovery code, for users with a second factor.
+    pub code: Option<String>,
 }
 
 pub(crate) async fn options(
@@ -28,13 +35,16 @@ pub(crate) async fn options(
     let response = json!({
         "authentication": {
             "required": context.config.authentication_required,
-        }
+        },
+        "oidc": context.oidc.as_ref().map(|oidc| json!({"label": oidc.label()})),
     });
     Json(response)
 }
 
 pub(crate) async fn post(
     context: Extension<Arc<ServerContext>>,
+    Extension(ConnectInfo(remote)): Extension<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
     //_session: Option<SessionExtractor>,
     form: axum::extract::Form<LoginForm>,
 ) -> impl IntoResponse {
@@ -54,64 +64,285 @@ pub(crate) async fn post(
             Some(password) => password.to_owned(),
         };
 
-        let user = match context
-            .configdb
-            .get_user_by_username_password(&username, &password)
-            .await
-        {
-            Ok(user) => user,
-            Err(err) => match err {
-                ConfigDbError::UsernameNotFound(_)
-                | ConfigDbError::BadPassword(_)
-                | ConfigDbError::NoUser(_) => {
-                    warn!("Login failure for username={}, error={:?}", &username, err);
-                    return (StatusCode::UNAUTHORIZED, "").into_response();
+        let client = Client::new(&context, &headers, remote);
+        if let Some(retry_after) = context.login_throttle.check(&username, &client.addr) {
+            warn!(
+                "Login refused for username={} from {}: locked out",
+                &username, client.addr
+            );
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(RETRY_AFTER, retry_after.as_secs().max(1).to_string())],
+                Json(json!({"error": "too many failed logins, try again later"})),
+            )
+                .into_response();
+        }
+
+        let user = match check_password(&context, &username, &password).await {
+            Ok(Some(user)) => user,
+            Ok(None) => {
+                context.login_throttle.failure(&username, &client.addr);
+                return (StatusCode::UNAUTHORIZED, "").into_response();
+            }
+            Err(err) => {
+                error!("Login failure for username={}, error={:?}", &username, err);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+            }
+        };
+
+        let recovery_codes =
+            match totp::login_step(&context.configdb, &user, form.code.as_deref()).await {
+                Ok(LoginStep::Done { recovery_codes }) => recovery_codes,
+                Ok(LoginStep::Code { invalid }) => {
+                    let mut response = json!({"totp": "required"});
+                    if invalid {
+                        warn!(
+                            "Login failure for username={}: invalid TOTP code",
+                            &username
+                        );
+                        context.login_throttle.failure(&username, &client.addr);
+                        response["error"] = "invalid code".into();
+                    }
+                    return (StatusCode::UNAUTHORIZED, Json(response)).into_response();
+                }
+                Ok(LoginStep::Enroll {
+                    secret,
+                    uri,
+                    invalid,
+                }) => {
+                    let mut response = json!({"totp": "enroll", "secret": secret, "uri": uri});
+                    if invalid {
+                        context.login_throttle.failure(&username, &client.addr);
+                        response["error"] = "invalid code".into();
+                    }
+                    return (StatusCode::UNAUTHORIZED, Json(response)).into_response();
                 }
-                _ => {
+                Err(err) => {
                     error!("Login failure for username={}, error={:?}", &username, err);
                     return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
                 }
-            },
-        };
+            };
 
-        info!("Creating session for user {:?}", &username);
-        let mut session = Session::new();
-        session.username = Some(user.username);
-        let session = Arc::new(session);
-        context.session_store.put(session.clone()).unwrap();
-
-        // Create expiry data one week in the future.
-        let expiry = chrono::Utc::now() + chrono::Duration::weeks(1);
-        if let Err(err) = context
-            .configdb
-            .save_session(
-                session.session_id.as_ref().unwrap(),
-                &user.uuid,
-                expiry.timestamp(),
-            )
-            .await
-        {
-            error!("Failed to save session: {:?}", err);
+        context.login_throttle.success(&username);
+        let (session, headers) = start_session(&context, user, &client).await;
+        let mut response = json!({
+            "session_id": session.session_id,
+        });
+        if session.password_change_required {
+            response["password_change_required"] = true.into();
         }
+        if let Some(recovery_codes) = recovery_codes {
+            response["recovery_codes"] = recovery_codes.into();
+        }
+        (headers, Json(response)).into_response()
+    }
+}
+
+/// Check a username and password: against the LDAP server when enabled,
+/// and the local users when not or as its fallback. `None` is a failed
+/// login, which has been logged.
+pub(crate) async fn check_password(
+    context: &ServerContext,
+    username: &str,
+    password: &str,
+) -> anyhow::Result<Option<User>> {
+    if let Some(ldap) = &context.ldap {
+        match ldap.login(&context.configdb, username, password).await {
+            Ok(user) => return Ok(Some(user)),
+            Err(err) if ldap.local_fallback() => {
+                warn!(
+                    "LDAP login failure for username={}, trying local users: {}",
+                    username, err
+                );
+            }
+            Err(err @ (LdapError::Server(_) | LdapError::ConfigDb(_))) => return Err(err.into()),
+            Err(err) => {
+                warn!("LDAP login failure for username={}: {}", username, err);
+                return Ok(None);
+            }
+        }
+    }
+    match context
+        .configdb
+        .get_user_by_username_password(username, password)
+        .await
+    {
+        Ok(user) => Ok(Some(user)),
+        Err(
+            err @ (ConfigDbError::UsernameNotFound(_)
+            | ConfigDbError::BadPassword(_)
+            | ConfigDbError::NoUser(_)),
+        ) => {
+            warn!("Login failure for username={}, error={:?}", username, err);
+            Ok(None)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Where a login came from.
+struct Client {
+    addr: String,
+    user_agent: Option<String>,
+}
+
+impl Client {
+    fn new(context: &ServerContext, headers: &HeaderMap, remote: SocketAddr) -> Self {
+        Self {
+            addr: crate::server::proxy::client_ip(&context.config, headers, remote),
+            user_agent: headers
+                .get(USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.chars().take(256).collect()),
+        }
+    }
+}
 
-        let mut headers = HeaderMap::new();
-        if let Some(session_id) = &session.session_id {
+/// Create a session for a logged in user, returning it with the headers
+/// setting its cookie.
+async fn start_session(
+    context: &ServerContext,
+    user: User,
+    client: &Client,
+) -> (Arc<Session>, HeaderMap) {
+    info!(
+        "Creating session for user {:?} from {}",
+        &user.username, client.addr
+    );
+    let now = chrono::Utc::now().timestamp();
+    let lifetime = context.config.session.absolute_timeout.as_secs() as i64;
+    let mut session = Session::new();
+    session.username = Some(user.username);
+    session.role = user.role;
+    session.sensors = user.sensors;
+    session.password_change_required = user.password_change_required;
+    session.expires_at = Some(now + lifetime);
+    let session = Arc::new(session);
+    context.session_store.put(session.clone(), now).unwrap();
+
+    if let Err(err) = context
+        .configdb
+        .save_session(
+            session.session_id.as_ref().unwrap(),
+            &user.uuid,
+            now + lifetime,
+            Some(&client.addr),
+            client.user_agent.as_deref(),
+        )
+        .await
+    {
+        error!("Failed to save session: {:?}", err);
+    }
+
+    let mut headers = HeaderMap::new();
+    if let Some(session_id) = &session.session_id {
+        let cookie = format!(
+            "x-evebox-session-id={}; Path=/; HttpOnly; SameSite=Strict; Max-Age={}",
+            session_id, lifetime
+        );
+        headers.insert(SET_COOKIE, cookie.parse().unwrap());
+    }
+    (session, headers)
+}
+
+/// Binds an OpenID Connect login to the browser that started it. Lax, as
+/// the provider's redirect back is a cross-site navigation.
+const OIDC_STATE_COOKIE: &str = "x-evebox-oidc-state";
+
+/// Seconds the state cookie lives, the provider's time to log the user
+/// in.
+const OIDC_STATE_MAX_AGE: u64 = 600;
+
+/// The web application's root, relative to the callback at
+/// `/api/login/oidc/callback`, so a path prefix added by a reverse proxy
+/// is kept.
+const OIDC_APP_ROOT: &str = "../../../";
+
+/// `GET /api/login/oidc`: send the browser to the OpenID Connect
+/// provider.
+pub(crate) async fn oidc_login(Extension(context): Extension<Arc<ServerContext>>) -> Response {
+    let Some(oidc) = &context.oidc else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    match oidc.begin().await {
+        Ok((url, state)) => {
             let cookie = format!(
-                "x-evebox-session-id={}; Path=/; HttpOnly; SameSite=Strict; Max-Age={}",
-                session_id,
-                chrono::Duration::days(365).num_seconds()
+                "{OIDC_STATE_COOKIE}={state}; Path=/; HttpOnly; SameSite=Lax; Max-Age={OIDC_STATE_MAX_AGE}"
             );
-            headers.insert(SET_COOKIE, cookie.parse().unwrap());
+            (
+                StatusCode::SEE_OTHER,
+                [(SET_COOKIE, cookie), (LOCATION, url)],
+            )
+                .into_response()
+        }
+        Err(err) => {
+            error!("Failed to start OIDC login: {err}");
+            (StatusCode::BAD_GATEWAY, "").into_response()
         }
+    }
+}
 
-        (
-            headers,
-            Json(serde_json::json!({
-                "session_id": session.session_id,
-            })),
-        )
-            .into_response()
+#[derive(Debug, Deserialize)]
+pub(crate) struct OidcCallback {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+/// `GET /api/login/oidc/callback`: the provider's redirect back. On
+/// success the browser gets a session and is sent to the application,
+/// otherwise to the login page with an error.
+pub(crate) async fn oidc_callback(
+    Extension(context): Extension<Arc<ServerContext>>,
+    Extension(ConnectInfo(remote)): Extension<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Query(query): Query<OidcCallback>,
+) -> Response {
+    let Some(oidc) = &context.oidc else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let result = async {
+        if let Some(error) = &query.error {
+            return Err(OidcError::Provider(format!(
+                "{error}: {}",
+                query.error_description.as_deref().unwrap_or_default()
+            )));
+        }
+        let (Some(code), Some(state)) = (&query.code, &query.state) else {
+            return Err(OidcError::State);
+        };
+        let cookies = CookieJar::from_headers(&headers);
+        if cookies.get(OIDC_STATE_COOKIE).map(|cookie| cookie.value()) != Some(state.as_str()) {
+            return Err(OidcError::State);
+        }
+        let identity = oidc.finish(state, code).await?;
+        oidc.provision(&context.configdb, &identity).await
     }
+    .await;
+
+    let clear_state = format!("{OIDC_STATE_COOKIE}=; Path=/; HttpOnly; SameSite=Lax; Max-Age=0");
+    let (mut headers, location) = match result {
+        Ok(user) => {
+            info!(
+                "OIDC login for user {:?} with role {}",
+                user.username, user.role
+            );
+            let client = Client::new(&context, &headers, remote);
+            let (_session, headers) = start_session(&context, user, &client).await;
+            (headers, OIDC_APP_ROOT.to_string())
+        }
+        Err(err) => {
+            warn!("OIDC login failed: {err}");
+            (
+                HeaderMap::new(),
+                format!("{OIDC_APP_ROOT}#/login?error=sso"),
+            )
+        }
+    };
+    headers.append(SET_COOKIE, clear_state.parse().unwrap());
+    headers.insert(LOCATION, location.parse().unwrap());
+    (StatusCode::SEE_OTHER, headers).into_response()
 }
 
 pub(crate) async fn logout(
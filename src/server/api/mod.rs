@@ -10,18 +10,21 @@ use crate::queryparser;
 use crate::queryparser::{QueryElement, QueryValue};
 use crate::server::ServerContext;
 use crate::server::api::genericquery::GenericQuery;
+use crate::server::audit::{Auditor, Outcome};
 use crate::server::main::SessionExtractor;
+use crate::server::session::{RequiredRole, Role, Session};
 use axum::Json;
-use axum::extract::{Extension, Form, Path, State};
-use axum::http::StatusCode;
+use axum::extract::{ConnectInfo, Extension, Form, Path, State};
+use axum::http::{HeaderMap, StatusCode};
 use axum::response::IntoResponse;
 use axum::response::Response;
 use axum::routing::delete;
-use axum::routing::{get, post};
-use serde::Deserialize;
+use axum::routing::{get, post, put};
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use stats::earliest_timestamp;
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::ops::Sub;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -33,98 +36,225 @@ pub(crate) mod admin;
 pub(crate) mod agent;
 pub(crate) mod agg;
 pub(crate) mod alerts;
+pub(crate) mod analyze;
+pub(crate) mod audit;
 pub(crate) mod count;
 pub(crate) mod elastic;
 pub(crate) mod eve2pcap;
 pub(crate) mod firehose;
 pub(crate) mod genericquery;
 pub(crate) mod login;
+pub(crate) mod password;
 pub(crate) mod pcap;
 pub(crate) mod prelude;
+pub(crate) mod sessions;
 pub(crate) mod sqlite;
 pub(crate) mod stats;
 pub(crate) mod submit;
+pub(crate) mod suricata;
+pub(crate) mod tokens;
+pub(crate) mod totp;
 pub(crate) mod util;
 
+/// The API routes, in groups by the least role they require. The
+/// `SessionExtractor` checks the role; routes outside the groups are
+/// open to any session, or authenticate agents themselves.
 pub(crate) fn router() -> axum::Router<Arc<ServerContext>> {
-    let router = axum::Router::new()
+    axum::Router::new()
         .route("/api/login", post(login::post).get(login::options))
+        .route("/api/login/oidc", get(login::oidc_login))
+        .route("/api/login/oidc/callback", get(login::oidc_callback))
         .route("/api/logout", post(login::logout))
         .route("/api/config", get(config))
         .route("/api/version", get(get_version))
         .route("/api/user", get(get_user))
+        // Open to users who must change their password, so they can.
+        .route("/api/user/password", post(password::change_password))
         .route("/api/agent/ws", get(agent::websocket))
+        .route(
+            crate::agent::protocol::AGENT_PCAP_UPLOAD_ROUTE,
+            post(agent::upload_pcap).layer(agent::upload_body_limit()),
+        )
+        .route("/api/submit", post(submit::handler))
+        // Keep this around for older agents.
+        .route("/api/1/submit", post(submit::handler))
+        .merge(require(Role::Viewer, viewer_routes()))
+        .merge(require(Role::Analyst, analyst_routes()))
+        .merge(require(Role::Pcap, pcap_routes()))
+        .merge(require(Role::Admin, admin_routes()))
+}
+
+fn require(
+    role: Role,
+    router: axum::Router<Arc<ServerContext>>,
+) -> axum::Router<Arc<ServerContext>> {
+    router.route_layer(Extension(RequiredRole(role)))
+}
+
+/// Reading events, reports and statistics, and the user's own sessions,
+/// API tokens and second factor. Suricata commands check their own role.
+fn viewer_routes() -> axum::Router<Arc<ServerContext>> {
+    axum::Router::new()
         .route("/api/agents", get(agent::get_agents))
         .route(
-            "/api/agents/keys",
-            get(admin::get_agent_keys).post(admin::add_agent_key),
+            "/api/agents/{name}/rules/update",
+            get(suricata::get_rules_update),
         )
         .route(
-            "/api/agents/keys/{id}",
-            get(admin::get_agent_key).delete(admin::delete_agent_key),
+            "/api/agents/{name}/suricata/{command}",
+            post(suricata::post_suricata_command),
         )
         .route("/api/alerts", get(alerts::alerts))
         .route("/api/events", get(events))
         .route("/api/event/{id}", get(get_event_by_id))
-        .route("/api/alert-group/star", post(alert_group_star))
-        .route("/api/alert-group/unstar", post(alert_group_unstar))
-        .route("/api/alert-group/archive", post(alert_group_archive))
-        .route("/api/event/{id}/archive", post(archive_event_by_id))
-        .route("/api/event/{id}/escalate", post(escalate_event_by_id))
-        .route("/api/event/{id}/comment", post(comment_by_event_id))
-        .route("/api/event/{id}/de-escalate", post(deescalate_event_by_id))
         .route("/api/report/histogram/time", get(histogram_time))
         .route("/api/dhcp/ack", get(dhcp_ack))
         .route("/api/dhcp/request", get(dhcp_request))
-        .route("/api/eve2pcap", post(eve2pcap::handler))
-        .route("/api/submit", post(submit::handler))
-        // Keep this around for older agents.
-        .route("/api/1/submit", post(submit::handler))
         .route("/api/sensors", get(stats::get_sensor_names))
         .route("/api/agg", get(agg::agg))
         .route("/api/event_types", get(agg::event_types))
         .route("/api/sqlite/info", get(sqlite::info))
         .route("/api/sqlite/fts/check", get(sqlite::fts_check))
-        .route("/api/sqlite/fts/enable", post(sqlite::fts_enable))
-        .route("/api/sqlite/fts/disable", post(sqlite::fts_disable))
         .route("/api/ja4db/{fingerprint}", get(ja4db))
         .route("/api/find-dns", get(find_dns))
         .route("/api/events/count", get(count::count))
         .route("/api/events/earliest-timestamp", get(earliest_timestamp))
         .route("/api/sse/agg", get(agg::agg_sse))
         .route("/api/admin/filters", get(admin::get_filters))
-        .route("/api/admin/filter/add", post(admin::add_filter))
-        .route("/api/admin/filter/{id}", delete(admin::delete_filter))
-        .route("/api/admin/update/ja4db", post(admin::update_ja4db))
-        .route("/api/admin/kv/config", get(admin::kv_get_config))
-        .route("/api/admin/kv/config/{key}", post(admin::kv_set_config))
         .route("/api/firehose/sse", get(firehose::sse))
         .route("/api/firehose", get(firehose::stream))
         .route("/api/metrics", get(metrics))
-        .route("/api/admin/elastic/indices", get(elastic::indices))
-        .route("/api/admin/elastic/index/{name}", delete(elastic::delete))
         .route("/api/stats/agg", get(stats::agg))
         .route("/api/stats/agg/diff", get(stats::agg_differential))
         .route("/api/stats/agg/by-sensor", get(stats::agg_by_sensor))
         .route(
             "/api/stats/agg/diff/by-sensor",
             get(stats::agg_differential_by_sensor),
-        );
-    router
+        )
+        .route("/api/analyze/pcap", get(analyze::get_analyses))
+        .route("/api/analyze/pcap/{id}", get(analyze::get_analysis))
+        .route("/api/user/sessions", get(sessions::get_sessions))
+        .route("/api/user/sessions/{id}", delete(sessions::delete_session))
         .route(
-            crate::agent::protocol::AGENT_PCAP_UPLOAD_ROUTE,
-            post(agent::upload_pcap).layer(agent::upload_body_limit()),
+            "/api/user/tokens",
+            get(tokens::get_tokens).post(tokens::add_token),
+        )
+        .route("/api/user/tokens/{id}", delete(tokens::delete_token))
+        .route(
+            "/api/user/totp",
+            get(totp::get_totp).post(totp::begin).delete(totp::delete),
+        )
+        .route("/api/user/totp/confirm", post(totp::confirm))
+        .route(
+            "/api/user/totp/recovery-codes",
+            post(totp::regenerate_recovery_codes),
+        )
+}
+
+/// Triage: starring, archiving, escalating and commenting.
+fn analyst_routes() -> axum::Router<Arc<ServerContext>> {
+    axum::Router::new()
+        .route("/api/alert-group/star", post(alert_group_star))
+        .route("/api/alert-group/unstar", post(alert_group_unstar))
+        .route("/api/alert-group/archive", post(alert_group_archive))
+        .route("/api/event/{id}/archive", post(archive_event_by_id))
+        .route("/api/event/{id}/escalate", post(escalate_event_by_id))
+        .route("/api/event/{id}/comment", post(comment_by_event_id))
+        .route("/api/event/{id}/de-escalate", post(deescalate_event_by_id))
+}
+
+/// Packet downloads and capture analysis.
+fn pcap_routes() -> axum::Router<Arc<ServerContext>> {
+    axum::Router::new()
+        .route("/api/eve2pcap", post(eve2pcap::handler))
+        .route(
+            "/api/analyze/pcap",
+            post(analyze::post_pcap).layer(agent::upload_body_limit()),
         )
         .route("/api/pcap", post(pcap::post_pcap).get(pcap::get_pcap))
         .route("/api/pcap/validate", get(pcap::validate_pcap))
+        .route("/api/pcap/bundle", post(pcap::post_bundle))
+        .route("/api/pcap/stream", post(pcap::post_stream))
+        .route("/api/pcap/jobs", post(pcap::post_job).get(pcap::get_jobs))
+        .route(
+            "/api/pcap/jobs/{id}",
+            get(pcap::get_job).delete(pcap::delete_job),
+        )
+        .route("/api/pcap/jobs/{id}/download", get(pcap::get_job_download))
         .route("/api/pcap/sources", get(pcap::get_sources))
+        .route("/api/pcap/evidence/{sha256}", get(pcap::get_evidence))
+}
+
+/// Users, agents, filters, the datastore and audit records.
+fn admin_routes() -> axum::Router<Arc<ServerContext>> {
+    axum::Router::new()
+        .route(
+            "/api/admin/users",
+            get(admin::get_users).post(admin::add_user),
+        )
+        .route("/api/admin/users/{username}", delete(admin::delete_user))
+        .route(
+            "/api/admin/users/{username}/role",
+            put(admin::put_user_role),
+        )
+        .route(
+            "/api/admin/users/{username}/sensors",
+            put(admin::put_user_sensors),
+        )
+        .route(
+            "/api/admin/users/{username}/sessions",
+            delete(admin::delete_user_sessions),
+        )
+        .route(
+            "/api/admin/users/{username}/totp",
+            delete(admin::delete_user_totp),
+        )
+        .route(
+            "/api/agents/keys",
+            get(admin::get_agent_keys).post(admin::add_agent_key),
+        )
+        .route(
+            "/api/agents/keys/{id}",
+            get(admin::get_agent_key).delete(admin::delete_agent_key),
+        )
+        .route(
+            "/api/agents/keys/{id}/rotate",
+            post(admin::rotate_agent_key),
+        )
+        .route(
+            "/api/agents/keys/{id}/expiry",
+            put(admin::put_agent_key_expiry),
+        )
+        .route(
+            "/api/agents/keys/{id}/certificate",
+            put(admin::put_agent_key_certificate),
+        )
+        .route(
+            "/api/agents/keys/{id}/config",
+            get(admin::get_agent_config).put(admin::put_agent_config),
+        )
+        .route(
+            "/api/agents/{name}/rules/update",
+            post(suricata::post_rules_update),
+        )
+        .route("/api/sqlite/fts/enable", post(sqlite::fts_enable))
+        .route("/api/sqlite/fts/disable", post(sqlite::fts_disable))
+        .route("/api/admin/filter/add", post(admin::add_filter))
+        .route("/api/admin/filter/{id}", delete(admin::delete_filter))
+        .route("/api/admin/update/ja4db", post(admin::update_ja4db))
+        .route("/api/admin/kv/config", get(admin::kv_get_config))
+        .route("/api/admin/kv/config/{key}", post(admin::kv_set_config))
+        .route("/api/admin/elastic/indices", get(elastic::indices))
+        .route("/api/admin/elastic/index/{name}", delete(elastic::delete))
+        .route("/api/audit", get(audit::get_audit))
+        .route("/api/audit/pcap", get(pcap::get_audit))
         .route(
             "/api/pcap/routing",
             get(pcap::get_routing).post(pcap::post_routing),
         )
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub(crate) struct AlertGroupSpec {
     pub signature_id: u64,
     pub src_ip: Option<String>,
@@ -134,6 +264,10 @@ pub(crate) struct AlertGroupSpec {
     pub tls_sni: Option<String>,
     pub min_timestamp: String,
     pub max_timestamp: String,
+    /// The sensors of the requesting user, when limited; taken from the
+    /// session, never the request.
+    #[serde(skip)]
+    pub sensors: Option<Vec<String>>,
 }
 
 pub(crate) async fn config(
@@ -154,19 +288,20 @@ pub(crate) async fn config(
         }),
         EventRepo::SQLite(_) => None,
     };
-    // Non-null only when packet capture is actually available: a local source
-    // is configured or a pcap-capable agent is connected, and the feature is
-    // compiled in (not Windows). The value carries the defaults the
-    // download UI pre-fills without a second request, and the webapp
-    // keys the PCAP controls off its presence.
-    let pcap = if context.pcap.has_source() || context.agents.has_pcap() {
-        let settings = &context.pcap.settings;
-        json!({
-            "max_size_bytes": settings.max_bytes,
-        })
-    } else {
-        serde_json::Value::Null
-    };
+    // Non-null only when packet capture is actually available: there is a
+    // local source, a held analysis capture or a pcap-capable agent, and
+    // the feature is compiled in (not Windows). The value carries the
+    // defaults the download UI pre-fills without a second request, and
+    // the webapp keys the PCAP controls off its presence.
+    let pcap =
+        if context.pcap.has_source() || context.pcap.has_analyses() || context.agents.has_pcap() {
+            let settings = &context.pcap.settings;
+            json!({
+                "max_size_bytes": settings.max_bytes,
+            })
+        } else {
+            serde_json::Value::Null
+        };
     let config = json!({
         "ElasticSearchIndex": context.config.elastic_index,
         "event-services": context.event_services,
@@ -183,6 +318,8 @@ pub(crate) async fn get_user(SessionExtractor(session): SessionExtractor) -> imp
     let user = json!({
         "anonymous": session.session_id.is_none(),
         "username": session.username,
+        "role": session.role,
+        "password_change_required": session.password_change_required,
     });
     Json(user)
 }
@@ -202,7 +339,7 @@ pub(crate) struct DhcpAckQuery {
 }
 
 pub(crate) async fn dhcp_ack(
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
     State(context): State<Arc<ServerContext>>,
     Form(query): Form<DhcpAckQuery>,
 ) -> Result<impl IntoResponse, AppError> {
@@ -212,8 +349,14 @@ pub(crate) async fn dhcp_ack(
         .transpose()?;
 
     let response = match &context.datastore {
-        EventRepo::Elastic(ds) => ds.dhcp_ack(earliest, query.sensor).await?,
-        EventRepo::SQLite(ds) => ds.dhcp_ack(earliest, query.sensor).await?,
+        EventRepo::Elastic(ds) => {
+            ds.dhcp_ack(earliest, query.sensor, session.sensors.as_deref())
+                .await?
+        }
+        EventRepo::SQLite(ds) => {
+            ds.dhcp_ack(earliest, query.sensor, session.sensors.as_deref())
+                .await?
+        }
     };
 
     #[rustfmt::skip]
@@ -225,7 +368,7 @@ pub(crate) async fn dhcp_ack(
 }
 
 pub(crate) async fn dhcp_request(
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
     State(context): State<Arc<ServerContext>>,
     Form(query): Form<DhcpAckQuery>,
 ) -> Result<impl IntoResponse, AppError> {
@@ -235,8 +378,14 @@ pub(crate) async fn dhcp_request(
         .transpose()?;
 
     let response = match &context.datastore {
-        EventRepo::Elastic(ds) => ds.dhcp_request(earliest, query.sensor).await?,
-        EventRepo::SQLite(ds) => ds.dhcp_request(earliest, query.sensor).await?,
+        EventRepo::Elastic(ds) => {
+            ds.dhcp_request(earliest, query.sensor, session.sensors.as_deref())
+                .await?
+        }
+        EventRepo::SQLite(ds) => {
+            ds.dhcp_request(earliest, query.sensor, session.sensors.as_deref())
+                .await?
+        }
     };
 
     #[rustfmt::skip]
@@ -250,37 +399,59 @@ pub(crate) async fn dhcp_request(
 pub(crate) async fn alert_group_star(
     Extension(context): Extension<Arc<ServerContext>>,
     SessionExtractor(session): SessionExtractor,
-    Json(request): Json<AlertGroupSpec>,
+    Extension(ConnectInfo(remote)): Extension<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    audit: Auditor,
+    Json(mut request): Json<AlertGroupSpec>,
 ) -> impl IntoResponse {
+    request.sensors = session.sensors.clone();
     info!("Escalated alert group: {:?}", request);
-    context
+    let user = session.username.clone();
+    let result = context
         .datastore
-        .escalate_by_alert_group(request, session)
-        .await
-        .unwrap();
+        .escalate_by_alert_group(request.clone(), session.clone())
+        .await;
+    audit_alert_group(&audit, &session, "alert-group.star", &request, &result).await;
+    result.unwrap();
+    pcap::spawn_pin(
+        &context,
+        pcap::PinTarget::Group(request),
+        user,
+        pcap::remote_addr(&context, &headers, remote),
+    );
     StatusCode::OK
 }
 
 pub(crate) async fn alert_group_unstar(
     Extension(context): Extension<Arc<ServerContext>>,
     SessionExtractor(session): SessionExtractor,
-    Json(request): Json<AlertGroupSpec>,
+    audit: Auditor,
+    Json(mut request): Json<AlertGroupSpec>,
 ) -> impl IntoResponse {
+    request.sensors = session.sensors.clone();
     info!("De-escalating alert group: {:?}", request);
-    context
+    let result = context
         .datastore
-        .deescalate_by_alert_group(session, request)
-        .await
-        .unwrap();
+        .deescalate_by_alert_group(session.clone(), request.clone())
+        .await;
+    audit_alert_group(&audit, &session, "alert-group.unstar", &request, &result).await;
+    result.unwrap();
     StatusCode::OK
 }
 
 pub(crate) async fn alert_group_archive(
     Extension(context): Extension<Arc<ServerContext>>,
-    SessionExtractor(_session): SessionExtractor,
-    Json(request): Json<AlertGroupSpec>,
+    SessionExtractor(session): SessionExtractor,
+    audit: Auditor,
+    Json(mut request): Json<AlertGroupSpec>,
 ) -> impl IntoResponse {
-    match context.datastore.archive_by_alert_group(request).await {
+    request.sensors = session.sensors.clone();
+    let result = context
+        .datastore
+        .archive_by_alert_group(request.clone())
+        .await;
+    audit_alert_group(&audit, &session, "alert-group.archive", &request, &result).await;
+    match result {
         Ok(n) => {
             context.metrics.incr_autoarchived_by_user(n);
             Json(json!({ "updated": n })).into_response()
@@ -292,8 +463,22 @@ pub(crate) async fn alert_group_archive(
     }
 }
 
+async fn audit_alert_group<T, E: std::fmt::Display>(
+    audit: &Auditor,
+    session: &crate::server::session::Session,
+    action: &str,
+    request: &AlertGroupSpec,
+    result: &Result<T, E>,
+) {
+    let target = request.signature_id.to_string();
+    let parameters = serde_json::to_value(request).unwrap_or_default();
+    audit
+        .result(session, action, Some(&target), parameters, result)
+        .await;
+}
+
 pub(crate) async fn histogram_time(
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
     Extension(context): Extension<Arc<ServerContext>>,
     Form(query): Form<GenericQuery>,
 ) -> Result<impl IntoResponse, AppError> {
@@ -331,6 +516,7 @@ pub(crate) async fn histogram_time(
             value: QueryValue::From(min_timestamp.into()),
         });
     }
+    query_string.extend(session.sensor_filter());
 
     let results = match &context.datastore {
         EventRepo::Elastic(ds) => ds.histogram_time(interval, &query_string).await,
@@ -347,10 +533,13 @@ pub(crate) async fn histogram_time(
 pub(crate) async fn get_event_by_id(
     Extension(context): Extension<Arc<ServerContext>>,
     Path(event_id): axum::extract::Path<String>,
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
 ) -> impl IntoResponse {
     match context.datastore.get_event_by_id(event_id.clone()).await {
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+        Ok(Some(event)) if !session.may_see_event(&event) => {
+            (StatusCode::NOT_FOUND, "not found").into_response()
+        }
         Ok(None) => (StatusCode::NOT_FOUND, "not found").into_response(),
         Ok(Some(mut event)) => {
             if let Some(ja4) = event["_source"]["tls"]["ja4"].as_str()
@@ -374,9 +563,25 @@ pub(crate) async fn get_event_by_id(
 pub(crate) async fn archive_event_by_id(
     Extension(context): Extension<Arc<ServerContext>>,
     Path(event_id): axum::extract::Path<String>,
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
+    audit: Auditor,
 ) -> impl IntoResponse {
-    match context.datastore.archive_event_by_id(&event_id).await {
+    if let Some(status) =
+        refuse_unseen_event(&context, &session, &audit, "event.archive", &event_id).await
+    {
+        return status;
+    }
+    let result = context.datastore.archive_event_by_id(&event_id).await;
+    audit
+        .result(
+            &session,
+            "event.archive",
+            Some(&event_id),
+            json!({}),
+            &result,
+        )
+        .await;
+    match result {
         Ok(()) => {
             // Assume success as far as metrics are concerned.
             context.metrics.incr_autoarchived_by_user(1);
@@ -395,10 +600,36 @@ pub(crate) async fn archive_event_by_id(
 pub(crate) async fn escalate_event_by_id(
     Extension(context): Extension<Arc<ServerContext>>,
     Path(event_id): axum::extract::Path<String>,
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
+    Extension(ConnectInfo(remote)): Extension<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    audit: Auditor,
 ) -> impl IntoResponse {
-    match context.datastore.escalate_event_by_id(&event_id).await {
-        Ok(()) => StatusCode::OK,
+    if let Some(status) =
+        refuse_unseen_event(&context, &session, &audit, "event.escalate", &event_id).await
+    {
+        return status;
+    }
+    let result = context.datastore.escalate_event_by_id(&event_id).await;
+    audit
+        .result(
+            &session,
+            "event.escalate",
+            Some(&event_id),
+            json!({}),
+            &result,
+        )
+        .await;
+    match result {
+        Ok(()) => {
+            pcap::spawn_pin(
+                &context,
+                pcap::PinTarget::Event(event_id),
+                session.username.clone(),
+                pcap::remote_addr(&context, &headers, remote),
+            );
+            StatusCode::OK
+        }
         Err(err) => {
             error!(
                 "Failed to escalate event by ID: id={}, err={:?}",
@@ -412,9 +643,25 @@ pub(crate) async fn escalate_event_by_id(
 pub(crate) async fn deescalate_event_by_id(
     Extension(context): Extension<Arc<ServerContext>>,
     Path(event_id): axum::extract::Path<String>,
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
+    audit: Auditor,
 ) -> impl IntoResponse {
-    match context.datastore.deescalate_event_by_id(&event_id).await {
+    if let Some(status) =
+        refuse_unseen_event(&context, &session, &audit, "event.de-escalate", &event_id).await
+    {
+        return status;
+    }
+    let result = context.datastore.deescalate_event_by_id(&event_id).await;
+    audit
+        .result(
+            &session,
+            "event.de-escalate",
+            Some(&event_id),
+            json!({}),
+            &result,
+        )
+        .await;
+    match result {
         Ok(()) => StatusCode::OK,
         Err(err) => {
             error!(
@@ -430,13 +677,28 @@ pub(crate) async fn comment_by_event_id(
     Extension(context): Extension<Arc<ServerContext>>,
     Path(event_id): axum::extract::Path<String>,
     SessionExtractor(session): SessionExtractor,
+    audit: Auditor,
     Json(body): Json<EventCommentRequestBody>,
 ) -> impl IntoResponse {
-    match context
-        .datastore
-        .comment_event_by_id(&event_id, body.comment.to_string(), session)
-        .await
+    if let Some(status) =
+        refuse_unseen_event(&context, &session, &audit, "event.comment", &event_id).await
     {
+        return status;
+    }
+    let result = context
+        .datastore
+        .comment_event_by_id(&event_id, body.comment.to_string(), session.clone())
+        .await;
+    audit
+        .result(
+            &session,
+            "event.comment",
+            Some(&event_id),
+            json!({"comment": body.comment}),
+            &result,
+        )
+        .await;
+    match result {
         Ok(()) => StatusCode::OK,
         Err(err) => {
             error!(
@@ -448,9 +710,43 @@ pub(crate) async fn comment_by_event_id(
     }
 }
 
+/// Refuse an action on an event from a sensor the user may not see, as
+/// if the event didn't exist, recording the refusal.
+async fn refuse_unseen_event(
+    context: &ServerContext,
+    session: &Session,
+    audit: &Auditor,
+    action: &str,
+    event_id: &str,
+) -> Option<StatusCode> {
+    session.sensors.as_ref()?;
+    let (status, outcome) = match context
+        .datastore
+        .get_event_by_id(event_id.to_string())
+        .await
+    {
+        Ok(Some(event)) if session.may_see_event(&event) => return None,
+        Ok(_) => (
+            StatusCode::NOT_FOUND,
+            Outcome::Rejected("event not found".to_string()),
+        ),
+        Err(err) => {
+            error!("Failed to get event by ID: id={}, err={:?}", event_id, err);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Outcome::Error(err.to_string()),
+            )
+        }
+    };
+    audit
+        .record(session, action, Some(event_id), json!({}), outcome)
+        .await;
+    Some(status)
+}
+
 /// Find a DNS record.
 async fn find_dns(
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
     Extension(context): Extension<Arc<ServerContext>>,
     Form(form): Form<HashMap<String, String>>,
 ) -> Result<impl IntoResponse, AppError> {
@@ -475,12 +771,20 @@ async fn find_dns(
 
     match &context.datastore {
         EventRepo::Elastic(e) => {
-            let response = e.dns_reverse_lookup(before, host, src_ip, dest_ip).await?;
+            let response = e
+                .dns_reverse_lookup(before, host, session.sensors.as_deref(), src_ip, dest_ip)
+                .await?;
             Ok(Json(response))
         }
         EventRepo::SQLite(s) => {
             let response = s
-                .dns_reverse_lookup(before.clone(), host, src_ip, dest_ip)
+                .dns_reverse_lookup(
+                    before.clone(),
+                    host,
+                    session.sensors.as_deref(),
+                    src_ip,
+                    dest_ip,
+                )
                 .await?;
             Ok(Json(response))
         }
@@ -498,7 +802,7 @@ pub(crate) async fn metrics(
 }
 
 pub(crate) async fn events(
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
     Extension(context): Extension<Arc<ServerContext>>,
     Form(query): Form<GenericQuery>,
 ) -> Result<impl IntoResponse, AppError> {
@@ -533,6 +837,7 @@ pub(crate) async fn events(
         .transpose()?
         .unwrap_or_default();
     params.query_string = query_string;
+    params.query_string.extend(session.sensor_filter());
 
     let results = context.datastore.events(params).await?;
     Ok(Json(results).into_response())
@@ -0,0 +1,158 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! `/api/user/password`: a user's own password.
+
+use std::net::SocketAddr;
+
+use axum::extract::{ConnectInfo, Extension, Json};
+use axum::http::HeaderMap;
+use axum::http::header::RETRY_AFTER;
+use axum::response::{IntoResponse, Response};
+
+use crate::prelude::*;
+use crate::server::ServerContext;
+use crate::server::audit::{Auditor, Outcome};
+use crate::server::main::SessionExtractor;
+use crate::server::session::Session;
+use crate::sqlite::configdb::{ConfigDbError, LOCAL_SOURCE};
+
+#[derive(Deserialize)]
+pub(crate) struct ChangePasswordRequest {
+    current_password: String,
+    new_password: String,
+}
+
+async fn refuse(
+    audit: &Auditor,
+    session: &Session,
+    username: &str,
+    status: StatusCode,
+    error: &str,
+) -> Response {
+    audit
+        .record(
+            session,
+            "user.password",
+            Some(username),
+            json!({}),
+            Outcome::Rejected(error.to_string()),
+        )
+        .await;
+    (status, Json(json!({"error": error}))).into_response()
+}
+
+/// `POST /api/user/password`: change the user's password, given their
+/// current one. A wrong current password counts as a failed login. The
+/// user's other sessions are logged out.
+pub(crate) async fn change_password(
+    SessionExtractor(session): SessionExtractor,
+    Extension(context): Extension<Arc<ServerContext>>,
+    Extension(ConnectInfo(remote)): Extension<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    audit: Auditor,
+    Json(request): Json<ChangePasswordRequest>,
+) -> Result<Response, AppError> {
+    let Some(username) = session.username.as_deref() else {
+        return Err(AppError::BadRequest(
+            "password changes require a login".to_string(),
+        ));
+    };
+    match context.configdb.get_user_source(username).await? {
+        Some(source) if source == LOCAL_SOURCE => {}
+        _ => {
+            let error = "password is not managed by EveBox";
+            return Ok(refuse(&audit, &session, username, StatusCode::BAD_REQUEST, error).await);
+        }
+    }
+
+    let client = crate::server::proxy::client_ip(&context.config, &headers, remote);
+    if let Some(retry_after) = context.login_throttle.check(username, &client) {
+        warn!(
+            "Password change refused for username={} from {}: locked out",
+            username, client
+        );
+        let error = "too many failed logins, try again later";
+        let mut response = refuse(
+            &audit,
+            &session,
+            username,
+            StatusCode::TOO_MANY_REQUESTS,
+            error,
+        )
+        .await;
+        response.headers_mut().insert(
+            RETRY_AFTER,
+            retry_after.as_secs().max(1).to_string().parse().unwrap(),
+        );
+        return Ok(response);
+    }
+    let user = match context
+        .configdb
+        .get_user_by_username_password(username, &request.current_password)
+        .await
+    {
+        Ok(user) => user,
+        Err(ConfigDbError::BadPassword(_)) => {
+            warn!(
+                "Password change for username={} from {}: wrong current password",
+                username, client
+            );
+            context.login_throttle.failure(username, &client);
+            let error = "current password is wrong";
+            return Ok(refuse(&audit, &session, username, StatusCode::FORBIDDEN, error).await);
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    let refusal = if request.new_password == request.current_password {
+        Some("new password must differ from the current one".to_string())
+    } else {
+        context
+            .config
+            .password
+            .check(username, &request.new_password)
+    };
+    if let Some(error) = refusal {
+        return Ok(refuse(&audit, &session, username, StatusCode::BAD_REQUEST, &error).await);
+    }
+
+    if let Err(err) = context
+        .configdb
+        .update_password_by_id(&user.uuid, &request.new_password)
+        .await
+    {
+        let outcome = Outcome::Error(err.to_string());
+        audit
+            .record(
+                &session,
+                "user.password",
+                Some(username),
+                json!({}),
+                outcome,
+            )
+            .await;
+        return Err(err.into());
+    }
+    let revoked = context
+        .configdb
+        .remove_other_user_sessions(username, session.session_id.as_deref())
+        .await?;
+    // The remaining session is reloaded without the required change.
+    context.session_store.evict_user(username);
+    audit
+        .record(
+            &session,
+            "user.password",
+            Some(username),
+            json!({"sessions_revoked": revoked.len()}),
+            Outcome::Ok,
+        )
+        .await;
+    info!(
+        "Password of user {:?} changed, {} other sessions logged out",
+        username,
+        revoked.len()
+    );
+    Ok(Json(json!({"sessions_revoked": revoked.len()})).into_response())
+}
@@ -26,20 +26,38 @@ use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
 
 use crate::agent::protocol::{
-    CONTROL_MESSAGE_MAX_BYTES, PcapResult, PcapResultCode, PcapUploadStatus, ServerMessage,
-    WireLimits, WirePcapFilter,
+    CAPABILITY_PCAP_FIELDS, CAPABILITY_PCAPNG, CONTROL_MESSAGE_MAX_BYTES, PcapResult,
+    PcapResultCode, PcapUploadStatus, ServerMessage, WireLimits, WirePcapFilter,
 };
 #[cfg(all(test, not(windows)))]
 use crate::pcap::SpoolConfig;
-use crate::pcap::{self, FetchStats, FlowSelector, Limits, PcapFilter, PcapRequest};
+use crate::pcap::{
+    self, FetchProgress, FetchStats, FieldDirection, FilterFields, FlowSelector, Limits,
+    OutputFormat, PcapFilter, PcapRequest,
+};
 #[cfg(not(windows))]
 use crate::pcap::{FetchError, PcapSource};
 use crate::prelude::*;
 use crate::server::ServerContext;
 use crate::server::agents::AgentEntry;
 use crate::server::main::SessionExtractor;
+use crate::server::pcap::audit::{AuditLog, PayloadDigest, SHA256_HEADER};
 use crate::server::pcap::tasks::{self, RemoteOutcome, UploadState};
 use crate::server::pcap::{PcapRouting, ResolvedPcapSource, RouteError};
+use crate::server::session::sensor_allowed;
+use crate::sqlite::configdb::PcapAuditRecord;
+
+pub(super) mod audit;
+mod bundle;
+mod evidence;
+mod jobs;
+mod stream;
+
+pub(crate) use audit::get_audit;
+pub(crate) use bundle::post_bundle;
+pub(crate) use evidence::{PinTarget, get_evidence, spawn_pin};
+pub(crate) use jobs::{delete_job, get_job, get_job_download, get_jobs, post_job};
+pub(crate) use stream::post_stream;
 
 /// Chunk size streamed to the client; the writer buffers extraction
 /// output up to this before pushing a frame.
@@ -49,11 +67,13 @@ const CHUNK_SIZE: usize = 64 * 1024;
 static PCAP_CONTENT_TYPE: HeaderValue =
     HeaderValue::from_static(crate::agent::protocol::PCAP_CONTENT_TYPE);
 
+static PCAPNG_CONTENT_TYPE: HeaderValue = HeaderValue::from_static("application/x-pcapng");
+
 /// The `POST /api/pcap` request body. All fields are optional; the
 /// combination present selects the mode (see [`build_request`]). Old
 /// clients sending only `{event_id}` keep the default auto-derive
 /// behavior.
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
 pub(crate) struct PcapRequestBody {
     /// The event to build the capture from. Absent for a standalone
     /// (no-event) request.
@@ -83,11 +103,40 @@ pub(crate) struct PcapRequestBody {
     /// bounded. Absent keeps the server default.
     #[serde(default)]
     pub max_size: Option<String>,
+    /// Structured alternative to `filter`; the fields given must all
+    /// match. Comma separated hosts, any of which matches.
+    #[serde(default)]
+    pub host: Option<String>,
+    /// Comma separated networks in CIDR notation.
+    #[serde(default)]
+    pub net: Option<String>,
+    /// Comma separated ports and inclusive ranges (`53,8000-8100`).
+    #[serde(default)]
+    pub port: Option<String>,
+    /// A protocol name (`tcp`, `udp`, `icmp`, ...) or number.
+    #[serde(default)]
+    pub proto: Option<String>,
+    /// `src`, `dst` or `either` (the default): the end of a packet
+    /// `host`, `net` and `port` match.
+    #[serde(default)]
+    pub direction: Option<String>,
+    /// Only packets tagged with this VLAN id.
+    #[serde(default)]
+    pub vlan: Option<String>,
     /// Optional pcap source name. Event requests normally route by
     /// sensor identity; standalone requests use this when more than
     /// one local/remote source is available.
     #[serde(default)]
     pub source: Option<String>,
+    /// Output file format: `pcap` (the default) or `pcapng`. pcapng
+    /// output comments each packet with the event it was extracted
+    /// for and keeps packets of every link type.
+    #[serde(default)]
+    pub format: Option<String>,
+    /// The sensors of the requesting user, when limited; taken from the
+    /// session, never the request.
+    #[serde(skip)]
+    pub sensors: Option<Vec<String>>,
 }
 
 /// `POST /api/pcap`: bounded, buffered quick extraction for an event's flow.
@@ -96,9 +145,10 @@ pub(crate) async fn post_pcap(
     SessionExtractor(session): SessionExtractor,
     Extension(ConnectInfo(remote)): Extension<ConnectInfo<SocketAddr>>,
     headers: HeaderMap,
-    Json(body): Json<PcapRequestBody>,
+    Json(mut body): Json<PcapRequestBody>,
 ) -> Response {
     let user = session.username.clone().unwrap_or_else(|| "-".to_string());
+    body.sensors = session.sensors.clone();
     let remote = remote_addr(&context, &headers, remote);
 
     // `native = false`: the POST caller buffers the response and reads
@@ -122,9 +172,10 @@ pub(crate) async fn get_pcap(
     SessionExtractor(session): SessionExtractor,
     Extension(ConnectInfo(remote)): Extension<ConnectInfo<SocketAddr>>,
     headers: HeaderMap,
-    Query(body): Query<PcapRequestBody>,
+    Query(mut body): Query<PcapRequestBody>,
 ) -> Response {
     let user = session.username.clone().unwrap_or_else(|| "-".to_string());
+    body.sensors = session.sensors.clone();
     let remote = remote_addr(&context, &headers, remote);
     // `native = true`: the browser streams this straight to disk and
     // never reads the response, so an empty result becomes a valid
@@ -152,13 +203,32 @@ fn list_sources(context: &ServerContext) -> serde_json::Value {
         kind: &'static str,
         #[serde(skip_serializing_if = "Option::is_none")]
         hostname: Option<String>,
+        /// Spool size and age from the source's last retention pass.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        spool: Option<Spool>,
+    }
+    #[derive(Serialize)]
+    struct Spool {
+        files: u64,
+        bytes: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        oldest: Option<String>,
     }
+    let spool = |usage: crate::pcap::SpoolUsage| Spool {
+        files: usage.files,
+        bytes: usage.bytes,
+        oldest: usage
+            .oldest_us
+            .and_then(|us| i64::try_from(us.saturating_mul(1000)).ok())
+            .map(|nanos| crate::datetime::DateTime::from_nanos(nanos).to_rfc3339_utc()),
+    };
     let mut sources = Vec::new();
     if context.pcap.has_source() {
         sources.push(Source {
             name: crate::server::agents::LOCAL_PCAP_SOURCE_NAME.to_string(),
             kind: "server",
             hostname: None,
+            spool: context.pcap.spool_usage().map(spool),
         });
     }
     sources.extend(
@@ -170,6 +240,19 @@ fn list_sources(context: &ServerContext) -> serde_json::Value {
                 name: agent.name.clone(),
                 kind: "agent",
                 hostname: Some(agent.hostname.clone()),
+                spool: agent.spool_usage().map(spool),
+            }),
+    );
+    sources.extend(
+        context
+            .pcap
+            .analysis_sources()
+            .into_iter()
+            .map(|name| Source {
+                name,
+                kind: "analysis",
+                hostname: None,
+                spool: None,
             }),
     );
     serde_json::json!({ "sources": sources })
@@ -253,26 +336,24 @@ pub(crate) async fn validate_pcap(
     SessionExtractor(session): SessionExtractor,
     Extension(ConnectInfo(remote)): Extension<ConnectInfo<SocketAddr>>,
     headers: HeaderMap,
-    Query(body): Query<PcapRequestBody>,
+    Query(mut body): Query<PcapRequestBody>,
 ) -> Response {
     let user = session.username.clone().unwrap_or_else(|| "-".to_string());
+    body.sensors = session.sensors.clone();
     let remote = remote_addr(&context, &headers, remote);
-    match handle_inner(&context, &body, &user, remote, true, false).await {
+    match handle_inner(&context, &body, &user, remote, true, false, None).await {
         Ok(response) | Err(response) => response,
     }
 }
 
-/// The client address for the audit log: `x-forwarded-for` when
-/// reverse-proxy support is enabled, else the socket peer.
-fn remote_addr(context: &ServerContext, headers: &HeaderMap, remote: SocketAddr) -> String {
-    if context.config.http_reverse_proxy
-        && let Some(forwarded) = headers
-            .get("x-forwarded-for")
-            .and_then(|value| value.to_str().ok())
-    {
-        return forwarded.to_string();
-    }
-    remote.to_string()
+/// The client address for the audit log: from `x-forwarded-for` when the
+/// peer is a trusted reverse proxy, else the socket peer.
+pub(super) fn remote_addr(
+    context: &ServerContext,
+    headers: &HeaderMap,
+    remote: SocketAddr,
+) -> String {
+    crate::server::proxy::client_addr(&context.config, headers, remote)
 }
 
 /// Extract-and-stream entry point (real download): validate, take
@@ -284,7 +365,7 @@ async fn handle(
     remote: String,
     native: bool,
 ) -> Result<Response, Response> {
-    handle_inner(context, body, user, remote, false, native).await
+    handle_inner(context, body, user, remote, false, native, None).await
 }
 
 /// Shared request handling. With `dry_run` it stops after structural
@@ -299,6 +380,7 @@ async fn handle_inner(
     remote: String,
     dry_run: bool,
     native: bool,
+    progress: Option<Arc<FetchProgress>>,
 ) -> Result<Response, Response> {
     // The audit line fields, filled in as the request is understood.
     // Every outcome — success or failure, however early — logs
@@ -313,17 +395,38 @@ async fn handle_inner(
         window: "-".to_string(),
         source: "-".to_string(),
         native,
+        pcapng: false,
+        digest: PayloadDigest::default(),
+        log: context.pcap.audit.clone(),
+        progress: progress.clone(),
     };
 
     let settings = &context.pcap.settings;
 
+    audit.pcapng = match present(&body.format) {
+        None | Some("pcap") => false,
+        Some("pcapng") => true,
+        Some(other) => {
+            return Err(fail(
+                &audit,
+                StatusCode::BAD_REQUEST,
+                "bad-format",
+                &format!("unsupported pcap format: {other}"),
+            ));
+        }
+    };
+
     // Load the event only when an event_id is given: needed for event
     // context, the event-relative window, and default mode.
     // Standalone requests never touch the datastore.
     let event = match &body.event_id {
         Some(event_id) => match context.datastore.get_event_by_id(event_id.clone()).await {
-            Ok(Some(event)) => Some(event),
-            Ok(None) => {
+            Ok(Some(event))
+                if sensor_allowed(body.sensors.as_deref(), event["_source"]["host"].as_str()) =>
+            {
+                Some(event)
+            }
+            Ok(_) => {
                 return Err(fail(
                     &audit,
                     StatusCode::NOT_FOUND,
@@ -348,6 +451,17 @@ async fn handle_inner(
     };
     let event_source = event.as_ref().map(|event| &event["_source"]);
 
+    // A user limited to some sensors gets only the packets of their
+    // events, from the source their sensor routes to.
+    if body.sensors.is_some() && (event.is_none() || present(&body.source).is_some()) {
+        return Err(fail(
+            &audit,
+            StatusCode::FORBIDDEN,
+            "sensor-scope",
+            "users limited to some sensors may only extract packets for their events",
+        ));
+    }
+
     // WINDOW + FILTER: build the engine filter and time window from
     // the request mode, so a bad time or duration fails before any
     // permit is taken or extraction spawned. A supplied BPF filter is
@@ -381,8 +495,9 @@ async fn handle_inner(
     // bounded by the fixed server default; callers that need a larger
     // or unlimited response must use the native GET path, which streams
     // directly to the browser. The dry-run pre-flight remains permissive
-    // because it validates requests for that GET path.
-    if !dry_run && !native && max_bytes > settings.max_bytes {
+    // because it validates requests for that GET path, and an export
+    // job writes to a file on the server instead of buffering.
+    if !dry_run && !native && progress.is_none() && max_bytes > settings.max_bytes {
         return Err(fail(
             &audit,
             StatusCode::PAYLOAD_TOO_LARGE,
@@ -442,36 +557,46 @@ async fn handle_inner(
                     "pcap: user={:?} remote={:?} event={:?} outcome=ambiguous-source",
                     audit.user, audit.remote, audit.event_id
                 );
+                audit.record(
+                    "ambiguous-source",
+                    Some(&candidates.join(", ")),
+                    None,
+                    false,
+                );
                 return Err((StatusCode::CONFLICT, Json(response)).into_response());
             }
         };
     audit.source = source.name().to_string();
 
-    let filename = filename(event_source, window.start.to_seconds());
+    let mut filename = filename(event_source, window.start.to_seconds());
+    let format = if audit.pcapng {
+        filename.push_str("ng");
+        OutputFormat::Pcapng {
+            section_comment: Some(format!("EveBox capture from source {}", audit.source)),
+            packet_comment: event.as_ref().map(|event| {
+                crate::util::pcap::event_packet_comment(&event["_source"], Some(&audit.event_id))
+            }),
+        }
+    } else {
+        OutputFormat::Pcap
+    };
 
     // Pre-flight (native-download probe): event, source, window and max-size
     // are valid, so report success without taking a permit or spawning
     // extraction. BPF compilation remains on the local engine or serving
     // agent; a failure is returned as structured JSON to the browser frame.
     if dry_run {
-        return Ok(axum::Json(serde_json::json!({
+        let mut summary = serde_json::json!({
             "ok": true,
             "filename": filename,
-        }))
-        .into_response());
+        });
+        // Structured fields are rendered here, so the caller can show
+        // the expression they stand for.
+        if let Some(PcapFilter::Fields(fields)) = &filter {
+            summary["bpf"] = fields.to_bpf().into();
+        }
+        return Ok(axum::Json(summary).into_response());
     }
-    // Concurrency is effectively unbounded, but a single global slot
-    // still backstops the shared blocking pool. The slot doubles as
-    // the supervisor's release signal when the request settles.
-    let Some(global_permit) = context.pcap.try_acquire() else {
-        return Err(fail(
-            &audit,
-            StatusCode::TOO_MANY_REQUESTS,
-            "busy",
-            "too many concurrent pcap requests",
-        ));
-    };
-
     // The engine takes the built filter (a derived flow, a raw BPF
     // expression, or None for all packets) and the window bounds as
     // unix microseconds; the request timeout doubles as the
@@ -485,6 +610,34 @@ async fn handle_inner(
             deadline: Some(settings.request_timeout),
             ..Limits::default()
         },
+        format,
+        progress,
+    };
+
+    dispatch(context, source, request, filename, audit).await
+}
+
+/// Take the global and per-source permits and run a normalized request
+/// against its resolved source, streaming the result. Shared by the
+/// single-event API and the bundle endpoint, which dispatches one
+/// request per flow.
+async fn dispatch(
+    context: &Arc<ServerContext>,
+    source: ResolvedPcapSource,
+    request: PcapRequest,
+    filename: String,
+    audit: AuditContext,
+) -> Result<Response, Response> {
+    // Concurrency is effectively unbounded, but a single global slot
+    // still backstops the shared blocking pool. The slot doubles as
+    // the supervisor's release signal when the request settles.
+    let Some(global_permit) = context.pcap.try_acquire() else {
+        return Err(fail(
+            &audit,
+            StatusCode::TOO_MANY_REQUESTS,
+            "busy",
+            "too many concurrent pcap requests",
+        ));
     };
 
     let Some(source_permit) = source.try_acquire() else {
@@ -510,7 +663,14 @@ async fn handle_inner(
         ResolvedPcapSource::Local { source, .. } => {
             // A wedged local source can accumulate detached extraction threads.
             let backlog = context.pcap.inflight.load(Ordering::SeqCst);
-            if backlog >= settings.max_concurrent.saturating_mul(2).max(2) {
+            if backlog
+                >= context
+                    .pcap
+                    .settings
+                    .max_concurrent
+                    .saturating_mul(2)
+                    .max(2)
+            {
                 return Err(fail(
                     &audit,
                     StatusCode::SERVICE_UNAVAILABLE,
@@ -530,6 +690,24 @@ async fn handle_inner(
             .await
         }
         ResolvedPcapSource::Agent(entry) => {
+            if matches!(request.filter, Some(PcapFilter::Fields(_)))
+                && !entry.supports(CAPABILITY_PCAP_FIELDS)
+            {
+                return Err(fail(
+                    &audit,
+                    StatusCode::CONFLICT,
+                    "unsupported-filter",
+                    "the agent serving this source cannot render structured filters; upgrade it or send a BPF filter",
+                ));
+            }
+            if request.format != OutputFormat::Pcap && !entry.supports(CAPABILITY_PCAPNG) {
+                return Err(fail(
+                    &audit,
+                    StatusCode::CONFLICT,
+                    "unsupported-format",
+                    "the agent serving this source cannot write pcapng; upgrade it or request pcap",
+                ));
+            }
             stream_agent(
                 context.clone(),
                 entry,
@@ -570,12 +748,50 @@ struct AuditContext {
     /// cannot read an error body, so an empty result becomes a valid,
     /// openable empty pcap instead of a JSON error saved as a `.pcap`.
     native: bool,
+    /// pcapng output: sets the content type and the empty capture.
+    pcapng: bool,
+    /// The size and SHA-256 of the capture bytes sent so far.
+    digest: PayloadDigest,
+    /// Where the request's audit record goes.
+    log: AuditLog,
+    /// An export job's progress counters, given the final statistics of
+    /// a successful extraction, including a remote one.
+    progress: Option<Arc<FetchProgress>>,
+}
+
+impl AuditContext {
+    /// Send the request's durable audit record, written alongside its
+    /// `pcap:` log line. The size and hash are of the bytes sent.
+    fn record(&self, outcome: &str, message: Option<&str>, packets: Option<u64>, truncated: bool) {
+        let known = |value: &str| (value != "-").then(|| value.to_string());
+        self.log.record(PcapAuditRecord {
+            username: self.user.clone(),
+            remote: self.remote.clone(),
+            event_id: known(&self.event_id),
+            mode: known(self.mode),
+            filter: known(&self.filter),
+            window: known(&self.window),
+            source: known(&self.source),
+            format: if self.pcapng { "pcapng" } else { "pcap" }.to_string(),
+            outcome: outcome.to_string(),
+            message: message.map(str::to_string),
+            packets: packets.map(|packets| i64::try_from(packets).unwrap_or(i64::MAX)),
+            bytes: i64::try_from(self.digest.bytes()).unwrap_or(i64::MAX),
+            truncated,
+            sha256: self.digest.sha256(),
+        });
+    }
 }
 
 /// Response headers for a successful pcap download.
 fn pcap_headers(audit: &AuditContext, filename: &str) -> HeaderMap {
     let mut headers = HeaderMap::new();
-    headers.insert(CONTENT_TYPE, PCAP_CONTENT_TYPE.clone());
+    let content_type = if audit.pcapng {
+        &PCAPNG_CONTENT_TYPE
+    } else {
+        &PCAP_CONTENT_TYPE
+    };
+    headers.insert(CONTENT_TYPE, content_type.clone());
     if let Ok(value) = HeaderValue::from_str(&format!("attachment; filename={filename}")) {
         headers.insert(CONTENT_DISPOSITION, value);
     }
@@ -587,6 +803,25 @@ fn pcap_headers(audit: &AuditContext, filename: &str) -> HeaderMap {
     headers
 }
 
+/// Headers for a streamed download, whose hash follows the body in a
+/// trailer. Clients receive it when they ask for trailers with
+/// `TE: trailers`; every download's hash is also in its audit record.
+fn streaming_headers(audit: &AuditContext, filename: &str) -> HeaderMap {
+    let mut headers = pcap_headers(audit, filename);
+    headers.insert(
+        axum::http::header::TRAILER,
+        HeaderValue::from_static(SHA256_HEADER),
+    );
+    headers
+}
+
+/// Add the SHA-256 of a capture sent whole.
+fn insert_sha256(headers: &mut HeaderMap, data: &[u8]) {
+    if let Ok(value) = HeaderValue::from_str(&crate::server::pcap::audit::sha256(data)) {
+        headers.insert(HeaderName::from_static(SHA256_HEADER), value);
+    }
+}
+
 /// Buffer the POST response until its body observes the producer's terminal
 /// frame. The browser POST client buffers the body as a Blob anyway, and
 /// waiting here lets a multi-chunk capture report limit truncation before the
@@ -613,6 +848,10 @@ async fn buffer_post_body(response: Response, max_bytes: u64) -> Response {
                     HeaderValue::from_static("true"),
                 );
             }
+            // The whole body is known now, so its hash moves from the
+            // trailer to a header.
+            parts.headers.remove(axum::http::header::TRAILER);
+            insert_sha256(&mut parts.headers, &bytes);
             Response::from_parts(parts, Body::from(bytes))
         }
         Err(_) => error(StatusCode::BAD_GATEWAY, "io", "pcap extraction failed"),
@@ -672,6 +911,7 @@ async fn stream_local(
     let fetch_cancel = cancel.clone();
     let inflight = context.pcap.inflight.clone();
     let writer_progress = last_progress.clone();
+    let writer_digest = audit.digest.clone();
     let mut handle = tokio::task::spawn_blocking(move || {
         let _inflight = InflightGuard::arm(inflight);
         let mut writer = ChannelWriter {
@@ -681,6 +921,7 @@ async fn stream_local(
             stall_timeout,
             started,
             last_progress: writer_progress,
+            digest: writer_digest,
         };
         let result = run_extraction(&source, &request, &mut writer, &fetch_cancel);
         finish_producer(result, &mut writer)
@@ -739,6 +980,7 @@ async fn stream_local(
                         "pcap: user={:?} remote={:?} event={:?} outcome=wedged",
                         audit.user, audit.remote, audit.event_id
                     );
+                    audit.record("wedged", None, None, false);
                     warn!(
                         "PCAP extraction for event {:?} did not stop (no output progress for {:?}); its thread is still running detached, releasing its slots anyway",
                         audit.event_id, idle_bound
@@ -749,6 +991,7 @@ async fn stream_local(
                         "pcap: user={:?} remote={:?} event={:?} outcome=join-error error={}",
                         audit.user, audit.remote, audit.event_id, err
                     );
+                    audit.record("join-error", Some(&err.to_string()), None, false);
                 }
                 Some(Ok(Ok(stats))) => match reason.get() {
                     CancelCause::Timeout => {
@@ -756,12 +999,19 @@ async fn stream_local(
                             "pcap: user={:?} remote={:?} event={:?} outcome=timeout",
                             audit.user, audit.remote, audit.event_id
                         );
+                        audit.record("timeout", None, Some(stats.packets), stats.truncated);
                     }
                     CancelCause::Client => {
                         warn!(
                             "pcap: user={:?} remote={:?} event={:?} outcome=aborted reason=client-closed bytes={}",
                             audit.user, audit.remote, audit.event_id, stats.bytes
                         );
+                        audit.record(
+                            "aborted",
+                            Some("client-closed"),
+                            Some(stats.packets),
+                            stats.truncated,
+                        );
                     }
                     // Defensive: a cancelled token always has its
                     // cause set first, but never log a cancelled
@@ -771,6 +1021,12 @@ async fn stream_local(
                             "pcap: user={:?} remote={:?} event={:?} outcome=aborted reason=client-closed bytes={}",
                             audit.user, audit.remote, audit.event_id, stats.bytes
                         );
+                        audit.record(
+                            "aborted",
+                            Some("client-closed"),
+                            Some(stats.packets),
+                            stats.truncated,
+                        );
                     }
                     CancelCause::None => {
                         log_success(&audit, &stats);
@@ -782,6 +1038,7 @@ async fn stream_local(
                             "pcap: user={:?} remote={:?} event={:?} outcome=timeout",
                             audit.user, audit.remote, audit.event_id
                         );
+                        audit.record("timeout", None, None, false);
                     } else {
                         match &err {
                             FetchError::Io(io) if io.kind() == std::io::ErrorKind::TimedOut => {
@@ -792,6 +1049,7 @@ async fn stream_local(
                                     "pcap: user={:?} remote={:?} event={:?} outcome=aborted reason=client-stalled",
                                     audit.user, audit.remote, audit.event_id
                                 );
+                                audit.record("aborted", Some("client-stalled"), None, false);
                             }
                             FetchError::Io(io) if io.kind() == std::io::ErrorKind::BrokenPipe => {
                                 // The channel receiver vanished
@@ -801,34 +1059,40 @@ async fn stream_local(
                                     "pcap: user={:?} remote={:?} event={:?} outcome=aborted reason=client-closed",
                                     audit.user, audit.remote, audit.event_id
                                 );
+                                audit.record("aborted", Some("client-closed"), None, false);
                             }
                             FetchError::NoCandidateFiles => {
                                 warn!(
                                     "pcap: user={:?} remote={:?} event={:?} outcome=no-candidate-files",
                                     audit.user, audit.remote, audit.event_id
                                 );
+                                audit.record("no-candidate-files", None, Some(0), false);
                             }
                             FetchError::NoMatch(stats) => {
                                 warn!(
-                                    "pcap: user={:?} remote={:?} event={:?} outcome=no-match files_scanned={} files_vanished={}",
+                                    "pcap: user={:?} remote={:?} event={:?} outcome=no-match files_scanned={} files_vanished={} files_skipped={}",
                                     audit.user,
                                     audit.remote,
                                     audit.event_id,
                                     stats.files_scanned,
                                     stats.files_vanished,
+                                    stats.files_skipped,
                                 );
+                                audit.record("no-match", None, Some(0), false);
                             }
                             FetchError::Format(message) => {
                                 warn!(
                                     "pcap: user={:?} remote={:?} event={:?} outcome=format error={:?}",
                                     audit.user, audit.remote, audit.event_id, message
                                 );
+                                audit.record("format", Some(message), None, false);
                             }
                             FetchError::Io(io) => {
                                 error!(
                                     "pcap: user={:?} remote={:?} event={:?} outcome=io error={}",
                                     audit.user, audit.remote, audit.event_id, io
                                 );
+                                audit.record("io", Some(&io.to_string()), None, false);
                             }
                         }
                     }
@@ -925,6 +1189,7 @@ async fn stream_local(
                         );
                     }
                     let chunk = queued.pop_front().unwrap_or_default();
+                    insert_sha256(&mut headers, &chunk);
                     // The producer is done: a clean single-chunk body,
                     // no guard needed.
                     Ok((headers, Body::from(chunk)).into_response())
@@ -937,7 +1202,7 @@ async fn stream_local(
 
     // Streaming: the disconnect guard rides in the body stream state
     // from here — dropping the body mid-stream is the client abort.
-    let headers = pcap_headers(&audit, &filename);
+    let headers = streaming_headers(&audit, &filename);
     let completion = (!audit.native).then(PostCompletion::default);
     let guard = DisconnectGuard {
         reason,
@@ -951,8 +1216,9 @@ async fn stream_local(
         queued,
         finished: false,
         completion: completion.clone(),
+        digest: audit.digest.clone(),
     };
-    let mut response = (headers, Body::from_stream(body_stream(state))).into_response();
+    let mut response = (headers, stream_body(state)).into_response();
     if let Some(completion) = completion {
         response.extensions_mut().insert(completion);
     }
@@ -1060,6 +1326,7 @@ async fn stream_agent(
         start_us,
         end_us,
         limits,
+        pcapng: (&request.format).into(),
     };
     let control_bytes = serde_json::to_vec(&message).map_err(|_| {
         fail(
@@ -1207,7 +1474,8 @@ async fn stream_agent(
         }
         RemoteFirst::Data(first) => {
             let audit = Arc::new(audit);
-            let headers = pcap_headers(&audit, &filename);
+            audit.digest.update(&first);
+            let headers = streaming_headers(&audit, &filename);
             let completion = (!audit.native).then(PostCompletion::default);
             let (frame_tx, frame_rx) = mpsc::channel::<Frame>(2);
             let supervisor_cancel = cancel.clone();
@@ -1310,11 +1578,14 @@ async fn stream_agent(
                             }
                             match tokio::time::timeout(
                                 stall_timeout,
-                                frame_tx.send(Frame::Data(chunk)),
+                                frame_tx.send(Frame::Data(chunk.clone())),
                             )
                             .await
                             {
-                                Ok(Ok(())) => bytes = total,
+                                Ok(Ok(())) => {
+                                    supervisor_audit.digest.update(&chunk);
+                                    bytes = total;
+                                }
                                 Ok(Err(_)) => {
                                     failure = (
                                         "client-closed",
@@ -1414,9 +1685,10 @@ async fn stream_agent(
                 queued,
                 finished: false,
                 completion: completion.clone(),
+                digest: audit.digest.clone(),
             };
             prestream.disarm();
-            let mut response = (headers, Body::from_stream(body_stream(state))).into_response();
+            let mut response = (headers, stream_body(state)).into_response();
             if let Some(completion) = completion {
                 response.extensions_mut().insert(completion);
             }
@@ -1441,7 +1713,7 @@ fn log_remote_failure(
 ) {
     let stats = stats.unwrap_or_default();
     warn!(
-        "pcap: user={:?} remote={:?} event={:?} source={:?} mode={} filter={:?} window={:?} outcome={} message={:?} packets={} bytes={} files_scanned={} files_vanished={} truncated={}",
+        "pcap: user={:?} remote={:?} event={:?} source={:?} mode={} filter={:?} window={:?} outcome={} message={:?} packets={} bytes={} files_scanned={} files_vanished={} files_skipped={} truncated={}",
         audit.user,
         audit.remote,
         audit.event_id,
@@ -1455,8 +1727,10 @@ fn log_remote_failure(
         stats.bytes,
         stats.files_scanned,
         stats.files_vanished,
+        stats.files_skipped,
         stats.truncated,
     );
+    audit.record(outcome, Some(message), Some(stats.packets), stats.truncated);
 }
 
 /// Map a classified terminal outcome to the empty (no body bytes streamed)
@@ -1728,6 +2002,8 @@ struct BodyState {
     /// Present only for the buffered POST path, whose wrapper waits for the
     /// body to observe Done before committing response headers.
     completion: Option<PostCompletion>,
+    /// The hash sent in the trailer of a clean end.
+    digest: PayloadDigest,
 }
 
 /// Terminal status shared between the streaming body and the POST wrapper.
@@ -1751,16 +2027,18 @@ impl PostCompletion {
 /// is reserved for producer success, including deliberate
 /// limit-truncation where delivering the partial capture is the
 /// point.
-fn body_stream(state: BodyState) -> impl futures::Stream<Item = Result<Bytes, std::io::Error>> {
+fn body_stream(
+    state: BodyState,
+) -> impl futures::Stream<Item = Result<http_body::Frame<Bytes>, std::io::Error>> {
     futures::stream::unfold(state, |mut state| async move {
         if let Some(chunk) = state.queued.pop_front() {
-            return Some((Ok(chunk), state));
+            return Some((Ok(http_body::Frame::data(chunk)), state));
         }
         if state.finished {
             return None;
         }
         match state.rx.recv().await {
-            Some(Frame::Data(chunk)) => Some((Ok(chunk), state)),
+            Some(Frame::Data(chunk)) => Some((Ok(http_body::Frame::data(chunk)), state)),
             Some(Frame::Done(end)) => {
                 if matches!(
                     &end,
@@ -1778,7 +2056,19 @@ fn body_stream(state: BodyState) -> impl futures::Stream<Item = Result<Bytes, st
                     ProducerEnd::Format(_) | ProducerEnd::Io => {
                         Some((Err(std::io::Error::other("pcap extraction failed")), state))
                     }
-                    _ => None,
+                    _ => {
+                        // The producer hashed every chunk before handing
+                        // it off, so the digest is complete.
+                        let mut trailers = HeaderMap::new();
+                        if let Some(value) = state
+                            .digest
+                            .sha256()
+                            .and_then(|sha256| HeaderValue::from_str(&sha256).ok())
+                        {
+                            trailers.insert(HeaderName::from_static(SHA256_HEADER), value);
+                        }
+                        Some((Ok(http_body::Frame::trailers(trailers)), state))
+                    }
                 }
             }
             None => {
@@ -1796,6 +2086,10 @@ fn body_stream(state: BodyState) -> impl futures::Stream<Item = Result<Bytes, st
     })
 }
 
+fn stream_body(state: BodyState) -> Body {
+    Body::new(http_body_util::StreamBody::new(body_stream(state)))
+}
+
 /// Cancels the fetch as client-caused when dropped while still
 /// armed. One instance covers the pre-stream window (the request
 /// future dropped before the body was built — a client abort before
@@ -1851,11 +2145,16 @@ fn empty_response(end: ProducerEnd, audit: &AuditContext, filename: &str) -> Res
         return match end {
             ProducerEnd::Format(message) => error(StatusCode::BAD_GATEWAY, "format", &message),
             ProducerEnd::Io => error(StatusCode::BAD_GATEWAY, "io", "pcap extraction failed"),
-            _ => (
-                pcap_headers(audit, filename),
-                Body::from(empty_pcap_bytes().to_vec()),
-            )
-                .into_response(),
+            _ => {
+                let empty = if audit.pcapng {
+                    crate::util::pcap::create_pcapng_section_header(None)
+                } else {
+                    empty_pcap_bytes().to_vec()
+                };
+                let mut headers = pcap_headers(audit, filename);
+                insert_sha256(&mut headers, &empty);
+                (headers, Body::from(empty)).into_response()
+            }
         };
     }
     match end {
@@ -1887,7 +2186,7 @@ fn empty_response(end: ProducerEnd, audit: &AuditContext, filename: &str) -> Res
 
 fn log_success(audit: &AuditContext, stats: &FetchStats) {
     info!(
-        "pcap: user={:?} remote={:?} event={:?} source={:?} mode={} filter={:?} window={:?} outcome=ok packets={} bytes={} files_scanned={} files_vanished={} truncated={}",
+        "pcap: user={:?} remote={:?} event={:?} source={:?} mode={} filter={:?} window={:?} outcome=ok packets={} bytes={} files_scanned={} files_vanished={} files_skipped={} truncated={}",
         audit.user,
         audit.remote,
         audit.event_id,
@@ -1899,8 +2198,13 @@ fn log_success(audit: &AuditContext, stats: &FetchStats) {
         stats.bytes,
         stats.files_scanned,
         stats.files_vanished,
+        stats.files_skipped,
         stats.truncated,
     );
+    if let Some(progress) = &audit.progress {
+        progress.update(stats);
+    }
+    audit.record("ok", None, Some(stats.packets), stats.truncated);
 }
 
 /// A `std::io::Write` that frames output into channel messages.
@@ -1928,6 +2232,8 @@ struct ChannelWriter {
     /// successful send so a live-but-slow client keeps proving output
     /// progress.
     last_progress: Arc<AtomicU64>,
+    /// The request's hash of the bytes handed off.
+    digest: PayloadDigest,
 }
 
 #[cfg(not(windows))]
@@ -1941,7 +2247,9 @@ impl ChannelWriter {
             &mut self.buf,
             Vec::with_capacity(CHUNK_SIZE),
         ));
-        self.send_frame(Frame::Data(chunk))
+        self.send_frame(Frame::Data(chunk.clone()))?;
+        self.digest.update(&chunk);
+        Ok(())
     }
 
     /// Bounded send of any frame: fails BrokenPipe when the receiver
@@ -2002,13 +2310,13 @@ impl Write for ChannelWriter {
 }
 
 /// `{"error": {"code": ..., "message": ...}}` with a status.
-fn error(status: StatusCode, code: &str, message: &str) -> Response {
+pub(super) fn error(status: StatusCode, code: &str, message: &str) -> Response {
     let body = json!({ "error": { "code": code, "message": message } });
     (status, Json(body)).into_response()
 }
 
-/// Log the request's audit line with the error code as the outcome,
-/// and build the error response.
+/// Log the request's audit line and record with the error code as the
+/// outcome, and build the error response.
 fn fail(audit: &AuditContext, status: StatusCode, code: &str, message: &str) -> Response {
     warn!(
         "pcap: user={:?} remote={:?} event={:?} source={:?} mode={} filter={:?} window={:?} outcome={} message={:?}",
@@ -2022,6 +2330,7 @@ fn fail(audit: &AuditContext, status: StatusCode, code: &str, message: &str) ->
         code,
         message
     );
+    audit.record(code, Some(message), None, false);
     error(status, code, message)
 }
 
@@ -2068,6 +2377,7 @@ fn describe_window(window: &crate::pcap::Window) -> String {
 /// `{error:{code,message}}` body. Kept small (rather than a full
 /// `Response`) so the request-building helpers' `Result`s do not trip
 /// `clippy::result_large_err`.
+#[derive(Debug)]
 struct RequestError {
     status: StatusCode,
     code: &'static str,
@@ -2124,7 +2434,7 @@ fn build_request(
         let window = pcap::window_from_start(&start, span)
             .map_err(|err| RequestError::bad_request(err.to_string()))?;
         audit.window = describe_window(&window);
-        let filter = build_filter_or_all(audit, &body.filter)?;
+        let filter = build_filter_or_all(audit, body)?;
         Ok((filter, window))
     } else if present(&body.before).is_some() || present(&body.after).is_some() {
         // Event-relative: the event's window shifted by before/after.
@@ -2138,14 +2448,13 @@ fn build_request(
         let window = pcap::window_around_event(source, before, after)
             .map_err(|err| RequestError::bad_event(err.to_string()))?;
         audit.window = describe_window(&window);
-        // A non-empty filter overrides the derived flow.
-        let filter = match present(&body.filter) {
-            Some(expression) => {
-                audit.filter = expression.to_string();
-                Some(PcapFilter::Expression(expression.to_string()))
-            }
-            None => Some(PcapFilter::Flow(derive_flow(audit, source)?)),
+        // A non-empty filter or structured fields override the
+        // derived flow.
+        let filter = match explicit_filter(audit, body)? {
+            Some(filter) => filter,
+            None => PcapFilter::Flow(derive_flow(audit, source)?),
         };
+        let filter = Some(filter);
         Ok((filter, window))
     } else if let Some(source) = source {
         // Default (unchanged): derived flow + derived window.
@@ -2160,22 +2469,113 @@ fn build_request(
     }
 }
 
-/// The free-form filter: the raw BPF expression when non-empty, else
-/// `None` for all packets in the window.
+/// The free-form filter: the caller's filter when given, else `None`
+/// for all packets in the window.
 fn build_filter_or_all(
     audit: &mut AuditContext,
-    filter: &Option<String>,
+    body: &PcapRequestBody,
 ) -> Result<Option<PcapFilter>, RequestError> {
-    match present(filter) {
-        Some(expression) => {
+    let filter = explicit_filter(audit, body)?;
+    if filter.is_none() {
+        audit.filter = "all".to_string();
+    }
+    Ok(filter)
+}
+
+/// The filter the caller chose: the raw BPF expression when non-empty,
+/// else the structured fields when any are given. Giving both is an
+/// error rather than one silently winning.
+fn explicit_filter(
+    audit: &mut AuditContext,
+    body: &PcapRequestBody,
+) -> Result<Option<PcapFilter>, RequestError> {
+    match (present(&body.filter), build_fields(body)?) {
+        (Some(_), Some(_)) => Err(RequestError::bad_request(
+            "filter cannot be combined with host, net, port, proto or vlan",
+        )),
+        (Some(expression), None) => {
             audit.filter = expression.to_string();
             Ok(Some(PcapFilter::Expression(expression.to_string())))
         }
-        None => {
-            audit.filter = "all".to_string();
-            Ok(None)
+        (None, Some(fields)) => {
+            audit.filter = fields.to_bpf();
+            Ok(Some(PcapFilter::Fields(fields)))
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+/// Parse the structured filter fields, or `None` when none are given.
+fn build_fields(body: &PcapRequestBody) -> Result<Option<FilterFields>, RequestError> {
+    let list = |value: &Option<String>| -> Vec<String> {
+        present(value)
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let mut fields = FilterFields::default();
+    for host in list(&body.host) {
+        let host = host
+            .parse()
+            .map_err(|_| RequestError::bad_request(format!("bad host: {host}")))?;
+        fields.hosts.push(host);
+    }
+    for net in list(&body.net) {
+        fields
+            .nets
+            .push(pcap::parse_net(&net).map_err(RequestError::bad_request)?);
+    }
+    for port in list(&body.port) {
+        fields
+            .ports
+            .push(pcap::parse_port_range(&port).map_err(RequestError::bad_request)?);
+    }
+    if let Some(proto) = present(&body.proto) {
+        let proto = match proto.to_ascii_lowercase().as_str() {
+            "icmp6" => 58,
+            _ => pcap::normalize_proto(proto, false)
+                .map_err(|err| RequestError::bad_request(err.to_string()))?,
+        };
+        if !fields.ports.is_empty() && !pcap::has_ports(proto) {
+            return Err(RequestError::bad_request("port requires tcp, udp or sctp"));
+        }
+        fields.proto = Some(proto);
+    }
+    fields.direction = match present(&body.direction)
+        .map(str::to_ascii_lowercase)
+        .as_deref()
+    {
+        None | Some("either") => None,
+        Some("src") => Some(FieldDirection::Src),
+        Some("dst") => Some(FieldDirection::Dst),
+        Some(other) => {
+            return Err(RequestError::bad_request(format!("bad direction: {other}")));
         }
+    };
+    if fields.direction.is_some()
+        && fields.hosts.is_empty()
+        && fields.nets.is_empty()
+        && fields.ports.is_empty()
+    {
+        return Err(RequestError::bad_request(
+            "direction requires host, net or port",
+        ));
     }
+    if let Some(vlan) = present(&body.vlan) {
+        let vlan = vlan
+            .parse::<u16>()
+            .ok()
+            .filter(|vlan| *vlan <= 4095)
+            .ok_or_else(|| RequestError::bad_request(format!("bad vlan: {vlan}")))?;
+        fields.vlan = Some(vlan);
+    }
+    Ok((!fields.is_empty()).then_some(fields))
 }
 
 /// Derive the flow selector from the event, recording it on the audit
@@ -2236,7 +2636,7 @@ mod test {
     use crate::sqlite::connection::{ConnectionBuilder, init_event_db};
     use crate::sqlite::eventrepo::SqliteEventRepo;
 
-    fn testdata(name: &str) -> PathBuf {
+    pub(super) fn testdata(name: &str) -> PathBuf {
         Path::new(env!("CARGO_MANIFEST_DIR"))
             .join("src/pcap/testdata")
             .join(name)
@@ -2245,7 +2645,7 @@ mod test {
     /// An event whose flow matches the golden fixture spool flow
     /// (udp 10.1.1.5:4000 <-> 192.0.2.10:53, T0=1700000000). The
     /// window covers all four flow packets (offsets 10/20/30/110).
-    fn matching_event() -> serde_json::Value {
+    pub(super) fn matching_event() -> serde_json::Value {
         json!({
             "timestamp": "2023-11-14T22:15:20.000000+0000",
             "event_type": "alert",
@@ -2273,7 +2673,7 @@ mod test {
 
     /// Ingest one event, returning a context configured with the
     /// golden fixture spool.
-    async fn context_with_event(
+    pub(super) async fn context_with_event(
         dir: &Path,
         event: serde_json::Value,
         settings: PcapSettings,
@@ -2427,10 +2827,19 @@ mod test {
         context: &Arc<ServerContext>,
         body: PcapRequestBody,
     ) -> (StatusCode, Vec<u8>) {
-        let response =
-            match handle_inner(context, &body, "tester", "test".to_string(), true, false).await {
-                Ok(response) | Err(response) => response,
-            };
+        let response = match handle_inner(
+            context,
+            &body,
+            "tester",
+            "test".to_string(),
+            true,
+            false,
+            None,
+        )
+        .await
+        {
+            Ok(response) | Err(response) => response,
+        };
         let status = response.status();
         let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
         (status, bytes.to_vec())
@@ -2586,6 +2995,104 @@ mod test {
         assert_eq!(body, empty_pcap_bytes());
     }
 
+    #[tokio::test]
+    async fn pcapng_download_comments_packets() {
+        let dir = tempfile::tempdir().unwrap();
+        let context =
+            context_with_event(dir.path(), matching_event(), PcapSettings::default()).await;
+        let body = PcapRequestBody {
+            format: Some("pcapng".to_string()),
+            ..request("1")
+        };
+        let (status, headers, body) = run(&context, body).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers.get(CONTENT_TYPE).unwrap(), "application/x-pcapng");
+        let disposition = headers.get(CONTENT_DISPOSITION).unwrap().to_str().unwrap();
+        assert!(
+            disposition.ends_with(".pcapng"),
+            "disposition={disposition}"
+        );
+        assert_eq!(&body[0..4], &0x0A0D_0D0Au32.to_le_bytes());
+        let text = String::from_utf8_lossy(&body);
+        assert!(text.contains("EveBox capture from source (server)"));
+        assert!(text.contains("EveBox event: 1"));
+        wait_for("supervisor settled", || permits_free(&context)).await;
+    }
+
+    #[tokio::test]
+    async fn native_pcapng_no_candidate_files_returns_section_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut event = matching_event();
+        event["timestamp"] = json!("2020-01-01T00:00:00.000000+0000");
+        event["flow"]["start"] = json!("2020-01-01T00:00:00.000000+0000");
+        let context = context_with_event(dir.path(), event, PcapSettings::default()).await;
+        let body = PcapRequestBody {
+            format: Some("pcapng".to_string()),
+            ..request("1")
+        };
+        let (status, headers, body) = run_native(&context, body).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers.get(CONTENT_TYPE).unwrap(), "application/x-pcapng");
+        assert_eq!(body, crate::util::pcap::create_pcapng_section_header(None));
+    }
+
+    #[tokio::test]
+    async fn bad_format_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let context =
+            context_with_event(dir.path(), matching_event(), PcapSettings::default()).await;
+        let body = PcapRequestBody {
+            format: Some("erf".to_string()),
+            ..request("1")
+        };
+        let (status, _headers, out) = run(&context, body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["error"]["code"], "bad-format");
+        assert!(permits_free(&context));
+    }
+
+    /// An agent that does not advertise pcapng would silently upload
+    /// classic pcap, so the request is refused before dispatch.
+    #[tokio::test]
+    async fn pcapng_requires_agent_capability() {
+        let dir = tempfile::tempdir().unwrap();
+        let context = context_with_event_and_optional_source(
+            dir.path(),
+            matching_event(),
+            PcapSettings::default(),
+            None,
+        )
+        .await;
+        let (tx, _rx) = tokio::sync::mpsc::channel(4);
+        context
+            .agents
+            .register(
+                crate::agent::protocol::AgentHandshake {
+                    name: "remote".to_string(),
+                    hostname: "remote-host".to_string(),
+                    version: "test".to_string(),
+                    capabilities: vec![crate::agent::protocol::CAPABILITY_PCAP.to_string()],
+                },
+                None,
+                "127.0.0.1:0".parse().unwrap(),
+                tx,
+            )
+            .unwrap();
+        let body = PcapRequestBody {
+            start: Some(FIXTURE_START.to_string()),
+            duration: Some("5m".to_string()),
+            source: Some("remote".to_string()),
+            format: Some("pcapng".to_string()),
+            ..Default::default()
+        };
+        let (status, _headers, out) = run(&context, body).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["error"]["code"], "unsupported-format");
+        assert!(permits_free(&context));
+    }
+
     #[tokio::test]
     async fn unstamped_event_host_does_not_affect_local_spool() {
         // An event without an EveBox agent stamp was ingested by this
@@ -3010,6 +3517,7 @@ mod test {
                     stall_timeout: std::time::Duration::from_secs(60),
                     started,
                     last_progress: writer_progress,
+                    digest: PayloadDigest::default(),
                 };
                 // First chunk goes out and advances progress.
                 writer
@@ -3072,6 +3580,7 @@ mod test {
             stall_timeout: std::time::Duration::from_millis(100),
             started: std::time::Instant::now(),
             last_progress: Arc::new(AtomicU64::new(0)),
+            digest: PayloadDigest::default(),
         };
         let chunk = vec![0u8; CHUNK_SIZE];
         // Fills the only channel slot.
@@ -3094,6 +3603,7 @@ mod test {
             stall_timeout: std::time::Duration::from_secs(60),
             started: std::time::Instant::now(),
             last_progress: Arc::new(AtomicU64::new(0)),
+            digest: PayloadDigest::default(),
         };
         let chunk = vec![0u8; CHUNK_SIZE];
         writer.write_all(&chunk).unwrap();
@@ -3153,6 +3663,7 @@ mod test {
             queued: VecDeque::from([Bytes::from_static(b"first")]),
             finished: false,
             completion: None,
+            digest: PayloadDigest::default(),
         }
     }
 
@@ -3167,7 +3678,7 @@ mod test {
             .unwrap();
         tx.try_send(Frame::Done(ProducerEnd::Io)).unwrap();
         let cancel = CancellationToken::new();
-        let body = Body::from_stream(body_stream(body_state(rx, &cancel)));
+        let body = stream_body(body_state(rx, &cancel));
         let result = to_bytes(body, usize::MAX).await;
         assert!(
             result.is_err(),
@@ -3185,7 +3696,7 @@ mod test {
         let (tx, rx) = mpsc::channel::<Frame>(16);
         drop(tx);
         let cancel = CancellationToken::new();
-        let body = Body::from_stream(body_stream(body_state(rx, &cancel)));
+        let body = stream_body(body_state(rx, &cancel));
         assert!(to_bytes(body, usize::MAX).await.is_err());
         assert!(!cancel.is_cancelled());
     }
@@ -3202,12 +3713,34 @@ mod test {
             .unwrap();
         let cancel = CancellationToken::new();
         let state = body_state(rx, &cancel);
-        let body = Body::from_stream(body_stream(state));
+        let body = stream_body(state);
         let bytes = to_bytes(body, usize::MAX).await.unwrap();
         assert_eq!(bytes.as_ref(), b"first second");
         assert!(!cancel.is_cancelled());
     }
 
+    /// A clean end carries the hash of everything streamed as a trailer.
+    #[tokio::test]
+    async fn body_stream_trailer_carries_the_sha256() {
+        use http_body_util::BodyExt;
+
+        let (tx, rx) = mpsc::channel::<Frame>(16);
+        tx.try_send(Frame::Data(Bytes::from_static(b" second")))
+            .unwrap();
+        tx.try_send(Frame::Done(ProducerEnd::Complete { truncated: false }))
+            .unwrap();
+        let cancel = CancellationToken::new();
+        let state = body_state(rx, &cancel);
+        state.digest.update(b"first second");
+        let collected = stream_body(state).collect().await.unwrap();
+        let trailers = collected.trailers().cloned().unwrap();
+        assert_eq!(
+            trailers[SHA256_HEADER],
+            crate::server::pcap::audit::sha256(b"first second").as_str()
+        );
+        assert_eq!(collected.to_bytes().as_ref(), b"first second");
+    }
+
     /// The final flush's error must propagate out of the producer: a
     /// client that stalls at the very tail — fetch complete, tail
     /// chunk unsendable — returns TimedOut instead of swallowing the
@@ -3225,6 +3758,7 @@ mod test {
             stall_timeout: std::time::Duration::from_millis(50),
             started: std::time::Instant::now(),
             last_progress: Arc::new(AtomicU64::new(0)),
+            digest: PayloadDigest::default(),
         };
         // The whole fixture output is far below one chunk, so all of
         // it rides on the final flush.
@@ -3247,6 +3781,7 @@ mod test {
             stall_timeout: std::time::Duration::from_millis(50),
             started: std::time::Instant::now(),
             last_progress: Arc::new(AtomicU64::new(0)),
+            digest: PayloadDigest::default(),
         }
     }
 
@@ -3672,6 +4207,69 @@ mod test {
         assert_eq!(json["error"]["code"], "bad-request");
     }
 
+    /// Structured filter fields are checked and rendered by the
+    /// pre-flight.
+    #[tokio::test]
+    async fn validate_renders_structured_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let context =
+            context_with_event(dir.path(), matching_event(), PcapSettings::default()).await;
+        let freeform = || PcapRequestBody {
+            start: Some(FIXTURE_START.to_string()),
+            duration: Some("5m".to_string()),
+            ..Default::default()
+        };
+        let body = PcapRequestBody {
+            host: Some("10.0.0.1, 10.0.0.2".to_string()),
+            port: Some("53,8000-8100".to_string()),
+            proto: Some("UDP".to_string()),
+            direction: Some("dst".to_string()),
+            ..freeform()
+        };
+        let (status, out) = run_validate(&context, body).await;
+        assert_eq!(status, StatusCode::OK);
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(
+            json["bpf"],
+            "udp and (dst host 10.0.0.1 or dst host 10.0.0.2) and (dst port 53 or dst portrange 8000-8100)"
+        );
+
+        let bad = [
+            PcapRequestBody {
+                host: Some("10.0.0.1".to_string()),
+                filter: Some("udp".to_string()),
+                ..freeform()
+            },
+            PcapRequestBody {
+                port: Some("53".to_string()),
+                proto: Some("icmp".to_string()),
+                ..freeform()
+            },
+            PcapRequestBody {
+                direction: Some("src".to_string()),
+                vlan: Some("10".to_string()),
+                ..freeform()
+            },
+            PcapRequestBody {
+                vlan: Some("4096".to_string()),
+                ..freeform()
+            },
+            PcapRequestBody {
+                net: Some("10.0.0.0/40".to_string()),
+                ..freeform()
+            },
+        ];
+        for body in bad {
+            let (status, out) = run_validate(&context, body).await;
+            assert_eq!(
+                status,
+                StatusCode::BAD_REQUEST,
+                "{}",
+                String::from_utf8_lossy(&out)
+            );
+        }
+    }
+
     /// Standalone free-form (no event_id) with start + duration and no
     /// filter extracts all packets in the window.
     #[tokio::test]
@@ -3772,6 +4370,38 @@ mod test {
                 { "name": "remote", "kind": "agent", "hostname": "remote-host" },
             ]})
         );
+
+        // Retention passes add the spool size and age.
+        context.pcap.set_spool_usage(crate::pcap::SpoolUsage {
+            files: 2,
+            bytes: 2048,
+            oldest_us: Some(1_700_000_000_000_000),
+        });
+        context
+            .agents
+            .get("remote")
+            .unwrap()
+            .set_spool_usage(crate::pcap::SpoolUsage::default());
+        assert_eq!(
+            list_sources(&context),
+            json!({ "sources": [
+                {
+                    "name": "(server)",
+                    "kind": "server",
+                    "spool": {
+                        "files": 2,
+                        "bytes": 2048,
+                        "oldest": "2023-11-14T22:13:20.000000Z",
+                    },
+                },
+                {
+                    "name": "remote",
+                    "kind": "agent",
+                    "hostname": "remote-host",
+                    "spool": { "files": 0, "bytes": 0 },
+                },
+            ]})
+        );
     }
 
     #[tokio::test]
@@ -3887,6 +4517,10 @@ mod test {
             window: "test".to_string(),
             source: "sensor-a".to_string(),
             native: false,
+            pcapng: false,
+            digest: PayloadDigest::default(),
+            log: AuditLog::default(),
+            progress: None,
         }
     }
 
@@ -3923,6 +4557,44 @@ mod test {
         assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
     }
 
+    #[test]
+    fn every_outcome_sends_an_audit_record() {
+        let (log, mut records) = AuditLog::channel();
+        let audit = AuditContext {
+            event_id: "-".to_string(),
+            log,
+            ..remote_audit()
+        };
+
+        fail(&audit, StatusCode::NOT_FOUND, "no-match", "no packets");
+        let record = records.try_recv().unwrap();
+        assert_eq!(record.username, "tester");
+        assert_eq!(record.event_id, None);
+        assert_eq!(record.source.as_deref(), Some("sensor-a"));
+        assert_eq!(record.outcome, "no-match");
+        assert_eq!(record.message.as_deref(), Some("no packets"));
+        assert_eq!(record.bytes, 0);
+        assert_eq!(record.sha256, None);
+
+        audit.digest.update(b"packets");
+        let stats = FetchStats {
+            packets: 1,
+            truncated: true,
+            ..Default::default()
+        };
+        log_success(&audit, &stats);
+        let record = records.try_recv().unwrap();
+        assert_eq!(record.outcome, "ok");
+        assert_eq!(record.packets, Some(1));
+        assert_eq!(record.bytes, 7);
+        assert!(record.truncated);
+        assert_eq!(
+            record.sha256,
+            Some(crate::server::pcap::audit::sha256(b"packets"))
+        );
+        assert!(records.try_recv().is_err());
+    }
+
     #[test]
     fn protocol_outcomes_map_to_bad_gateway() {
         let audit = remote_audit();
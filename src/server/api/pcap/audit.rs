@@ -0,0 +1,246 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! `GET /api/audit/pcap`: search the PCAP download audit trail, as JSON
+//! or as a CSV export.
+
+use axum::Json;
+use axum::extract::{Query, State};
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use axum::http::{HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+
+use super::error;
+use crate::prelude::*;
+use crate::server::ServerContext;
+use crate::server::main::SessionExtractor;
+use crate::sqlite::configdb::{PcapAuditQuery, PcapAuditRow};
+
+const DEFAULT_LIMIT: u32 = 100;
+const MAX_LIMIT: u32 = 10_000;
+
+const CSV_COLUMNS: &[&str] = &[
+    "id",
+    "timestamp",
+    "username",
+    "remote",
+    "event_id",
+    "mode",
+    "filter",
+    "window",
+    "source",
+    "format",
+    "outcome",
+    "message",
+    "packets",
+    "bytes",
+    "truncated",
+    "sha256",
+];
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct AuditSearch {
+    user: Option<String>,
+    event_id: Option<String>,
+    source: Option<String>,
+    outcome: Option<String>,
+    sha256: Option<String>,
+    /// Records at or after this time.
+    from: Option<String>,
+    /// Records before this time.
+    to: Option<String>,
+    tz_offset: Option<String>,
+    limit: Option<u32>,
+    /// `json` (the default) or `csv`.
+    format: Option<String>,
+}
+
+pub(crate) async fn get_audit(
+    State(context): State<Arc<ServerContext>>,
+    _session: SessionExtractor,
+    Query(search): Query<AuditSearch>,
+) -> Response {
+    let csv = match search.format.as_deref() {
+        None | Some("json") => false,
+        Some("csv") => true,
+        Some(other) => {
+            return error(
+                StatusCode::BAD_REQUEST,
+                "bad-format",
+                &format!("unsupported audit format: {other}"),
+            );
+        }
+    };
+    let query = match build_query(&search) {
+        Ok(query) => query,
+        Err(message) => return error(StatusCode::BAD_REQUEST, "bad-request", &message),
+    };
+    let rows = match context.configdb.search_pcap_audit(&query).await {
+        Ok(rows) => rows,
+        Err(err) => {
+            error!("Failed to search the pcap audit trail: {err}");
+            return error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "internal",
+                "failed to search the pcap audit trail",
+            );
+        }
+    };
+    if csv {
+        (
+            [
+                (CONTENT_TYPE, HeaderValue::from_static("text/csv")),
+                (
+                    CONTENT_DISPOSITION,
+                    HeaderValue::from_static("attachment; filename=pcap-audit.csv"),
+                ),
+            ],
+            to_csv(&rows),
+        )
+            .into_response()
+    } else {
+        Json(json!({ "records": rows })).into_response()
+    }
+}
+
+fn build_query(search: &AuditSearch) -> Result<PcapAuditQuery, String> {
+    let present = |value: &Option<String>| {
+        value
+            .as_deref()
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+    };
+    let time = |value: &Option<String>, field: &str| -> Result<Option<i64>, String> {
+        match present(value) {
+            Some(value) => crate::datetime::parse(&value, search.tz_offset.as_deref())
+                .map(|datetime| Some(datetime.to_seconds()))
+                .map_err(|err| format!("bad {field}: {err}")),
+            None => Ok(None),
+        }
+    };
+    Ok(PcapAuditQuery {
+        username: present(&search.user),
+        event_id: present(&search.event_id),
+        source: present(&search.source),
+        outcome: present(&search.outcome),
+        sha256: present(&search.sha256).map(|sha256| sha256.to_ascii_lowercase()),
+        from: time(&search.from, "from")?,
+        to: time(&search.to, "to")?,
+        limit: search.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT),
+    })
+}
+
+fn to_csv(rows: &[PcapAuditRow]) -> String {
+    let mut out = CSV_COLUMNS.join(",");
+    out.push_str("\r\n");
+    for row in rows {
+        let record = &row.record;
+        let optional = |value: &Option<String>| value.clone().unwrap_or_default();
+        let fields = [
+            row.id.to_string(),
+            row.timestamp.to_rfc3339(),
+            record.username.clone(),
+            record.remote.clone(),
+            optional(&record.event_id),
+            optional(&record.mode),
+            optional(&record.filter),
+            optional(&record.window),
+            optional(&record.source),
+            record.format.clone(),
+            record.outcome.clone(),
+            optional(&record.message),
+            record
+                .packets
+                .map(|packets| packets.to_string())
+                .unwrap_or_default(),
+            record.bytes.to_string(),
+            record.truncated.to_string(),
+            optional(&record.sha256),
+        ];
+        let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        out.push_str(&fields.join(","));
+        out.push_str("\r\n");
+    }
+    out
+}
+
+/// Quote a field per RFC 4180 when it holds a separator, quote or line
+/// break. A field a spreadsheet would read as a formula is prefixed with
+/// `'` first, so it opens as text.
+pub(crate) fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@']) {
+        format!("'{value}")
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sqlite::configdb::PcapAuditRecord;
+
+    #[test]
+    fn csv_quotes_fields_that_need_it() {
+        let row = PcapAuditRow {
+            id: 7,
+            timestamp: chrono::DateTime::parse_from_rfc3339("2026-01-02T03:04:05+00:00").unwrap(),
+            record: PcapAuditRecord {
+                username: "admin".to_string(),
+                remote: "192.0.2.1:5000".to_string(),
+                filter: Some("host 10.0.0.1, \"quoted\"".to_string()),
+                format: "pcap".to_string(),
+                outcome: "ok".to_string(),
+                packets: Some(3),
+                bytes: 300,
+                ..Default::default()
+            },
+        };
+        let csv = to_csv(&[row]);
+        let lines: Vec<&str> = csv.split("\r\n").collect();
+        assert_eq!(lines[0], CSV_COLUMNS.join(","));
+        assert_eq!(
+            lines[1],
+            "7,2026-01-02T03:04:05+00:00,admin,192.0.2.1:5000,,,\"host 10.0.0.1, \"\"quoted\"\"\",,,pcap,ok,,3,300,false,"
+        );
+    }
+
+    #[test]
+    fn csv_fields_are_not_read_as_formulas() {
+        assert_eq!(csv_field("=HYPERLINK(\"x\")"), "\"'=HYPERLINK(\"\"x\"\")\"");
+        assert_eq!(csv_field("+1"), "'+1");
+        assert_eq!(csv_field("-2"), "'-2");
+        assert_eq!(csv_field("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(csv_field("a=b"), "a=b");
+    }
+
+    #[test]
+    fn search_times_and_limits_are_normalized() {
+        let query = build_query(&AuditSearch {
+            user: Some(" ".to_string()),
+            from: Some("2026-01-02T03:04:05Z".to_string()),
+            sha256: Some("AB".repeat(32)),
+            limit: Some(1_000_000),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(query.username, None);
+        assert_eq!(query.from, Some(1_767_323_045));
+        assert_eq!(query.sha256, Some("ab".repeat(32)));
+        assert_eq!(query.limit, MAX_LIMIT);
+
+        assert!(
+            build_query(&AuditSearch {
+                to: Some("yesterday-ish".to_string()),
+                ..Default::default()
+            })
+            .is_err()
+        );
+    }
+}
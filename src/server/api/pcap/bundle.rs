@@ -0,0 +1,1092 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! Multi-event capture bundles: `POST /api/pcap/bundle` extracts the
+//! flows of every event in an alert group, or of every event matching
+//! an event query, as one time-ordered capture or a tar of per-flow
+//! captures.
+//!
+//! Each event's flow selector and window are derived exactly as for a
+//! single-event download. Events of the same flow (in either direction)
+//! on the same source collapse into one extraction over the union of
+//! their windows. The flows are then extracted one after another through
+//! the regular routing and extraction path, so every flow logs its own
+//! `pcap:` audit line, and the bundle as a whole stays within the
+//! buffered download's byte limit and the request timeout.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Extension, Json, State};
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use bytes::Bytes;
+
+use super::{
+    AuditContext, PCAP_CONTENT_TYPE, RequestError, buffer_post_body, describe_selector,
+    describe_window, dispatch, error, filename, parse_max_bytes, present, remote_addr, to_micros,
+};
+use crate::datetime::DateTime;
+use crate::eventrepo::EventQueryParams;
+use crate::pcap::{self, FlowSelector, Limits, OutputFormat, PcapFilter, PcapRequest};
+use crate::prelude::*;
+use crate::queryparser::{self, QueryElement, QueryValue};
+use crate::server::ServerContext;
+use crate::server::api::AlertGroupSpec;
+use crate::server::main::SessionExtractor;
+use crate::server::pcap::audit::{self, AuditLog, PayloadDigest, SHA256_HEADER};
+use crate::server::pcap::{ResolvedPcapSource, RouteError};
+use crate::sqlite::configdb::PcapAuditRecord;
+use crate::util::pcap::{FILE_HEADER_LEN, PCAP_RECORD_HEADER_SIZE};
+
+/// The most events a bundle considers. A selection matching more is
+/// bundled from its most recent events and marked truncated.
+const MAX_EVENTS: usize = 500;
+
+static TAR_CONTENT_TYPE: HeaderValue = HeaderValue::from_static("application/x-tar");
+
+/// The `POST /api/pcap/bundle` request body: exactly one of `group` or
+/// `query` selects the events.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct BundleRequestBody {
+    /// An alert group, as sent by the alert view's group actions.
+    #[serde(default)]
+    pub group: Option<AlertGroupSpec>,
+    /// An event query string in the events view syntax.
+    #[serde(default)]
+    pub query: Option<String>,
+    /// Inclusive time bounds for `query`.
+    #[serde(default)]
+    pub from: Option<String>,
+    #[serde(default)]
+    pub to: Option<String>,
+    /// Restrict `query` to one sensor.
+    #[serde(default)]
+    pub sensor: Option<String>,
+    /// Default timezone offset for times in `query`.
+    #[serde(default)]
+    pub tz_offset: Option<String>,
+    /// `pcap` (the default) for one merged, time-ordered capture, or
+    /// `tar` for one capture per flow plus a `manifest.json`.
+    #[serde(default)]
+    pub format: Option<String>,
+    /// Output cap for the whole bundle, as for `POST /api/pcap`: it may
+    /// keep or lower the server default, never raise it.
+    #[serde(default)]
+    pub max_size: Option<String>,
+    /// Serve every flow from this pcap source instead of routing each
+    /// event by its sensor.
+    #[serde(default)]
+    pub source: Option<String>,
+    /// The sensors of the requesting user, when limited; taken from the
+    /// session, never the request.
+    #[serde(skip)]
+    pub sensors: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BundleFormat {
+    Merged,
+    Tar,
+}
+
+/// `POST /api/pcap/bundle`: a buffered capture of every flow in an alert
+/// group or event query.
+pub(crate) async fn post_bundle(
+    State(context): State<Arc<ServerContext>>,
+    SessionExtractor(session): SessionExtractor,
+    Extension(ConnectInfo(remote)): Extension<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Json(mut body): Json<BundleRequestBody>,
+) -> Response {
+    let user = session.username.clone().unwrap_or_else(|| "-".to_string());
+    body.sensors = session.sensors.clone();
+    let remote = remote_addr(&context, &headers, remote);
+    let audit = BundleAudit {
+        user,
+        remote,
+        selection: describe_selection(&body),
+        log: context.pcap.audit.clone(),
+    };
+    match bundle(&context, &body, &audit).await {
+        Ok(response) | Err(response) => response,
+    }
+}
+
+/// Fields for the bundle's own `pcap-bundle:` audit line, logged once per
+/// request in addition to each flow's `pcap:` line.
+struct BundleAudit {
+    user: String,
+    remote: String,
+    /// The alert group or query the events were selected by.
+    selection: String,
+    log: AuditLog,
+}
+
+impl BundleAudit {
+    fn fail(&self, status: StatusCode, code: &str, message: &str) -> Response {
+        warn!(
+            "pcap-bundle: user={:?} remote={:?} selection={:?} outcome={} message={:?}",
+            self.user, self.remote, self.selection, code, message
+        );
+        self.record("pcap", code, Some(message), None, false);
+        error(status, code, message)
+    }
+
+    /// The bundle's own audit record; each flow has its own as well.
+    fn record(
+        &self,
+        format: &str,
+        outcome: &str,
+        message: Option<&str>,
+        output: Option<&[u8]>,
+        truncated: bool,
+    ) {
+        self.log.record(PcapAuditRecord {
+            username: self.user.clone(),
+            remote: self.remote.clone(),
+            mode: Some("bundle".to_string()),
+            filter: Some(self.selection.clone()),
+            format: format.to_string(),
+            outcome: outcome.to_string(),
+            message: message.map(str::to_string),
+            bytes: output.map_or(0, |output| output.len() as i64),
+            truncated,
+            sha256: output.map(audit::sha256),
+            ..Default::default()
+        });
+    }
+
+    fn reject(&self, err: RequestError) -> Response {
+        self.fail(err.status, err.code, &err.message)
+    }
+}
+
+fn describe_selection(body: &BundleRequestBody) -> String {
+    match (&body.group, present(&body.query)) {
+        (Some(group), _) => format!(
+            "group sid={} src={} dest={} {}..{}",
+            group.signature_id,
+            group.src_ip.as_deref().unwrap_or("-"),
+            group.dest_ip.as_deref().unwrap_or("-"),
+            group.min_timestamp,
+            group.max_timestamp,
+        ),
+        (None, Some(query)) => format!("query={query}"),
+        (None, None) => "-".to_string(),
+    }
+}
+
+/// One flow of the bundle: the events sharing a flow selector and
+/// source, extracted once over the union of their windows.
+pub(super) struct BundleFlow {
+    pub(super) source: ResolvedPcapSource,
+    pub(super) selector: FlowSelector,
+    pub(super) start: DateTime,
+    pub(super) end: DateTime,
+    pub(super) event_ids: Vec<String>,
+    /// The flow's first event, naming its capture in a tar bundle.
+    pub(super) event: serde_json::Value,
+}
+
+/// An event left out of the bundle, with the error a single-event
+/// download of it would have returned.
+#[derive(Debug, Serialize)]
+pub(super) struct SkippedEvent {
+    pub(super) event_id: String,
+    pub(super) code: &'static str,
+    message: String,
+    #[serde(skip)]
+    status: StatusCode,
+}
+
+/// The outcome of one flow's extraction, reported in the tar manifest.
+#[derive(Debug, Serialize)]
+struct FlowReport {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+    event_ids: Vec<String>,
+    source: String,
+    filter: String,
+    window: String,
+    bytes: usize,
+    truncated: bool,
+    /// `ok`, or the error code the flow's extraction ended with.
+    outcome: String,
+}
+
+async fn bundle(
+    context: &Arc<ServerContext>,
+    body: &BundleRequestBody,
+    audit: &BundleAudit,
+) -> Result<Response, Response> {
+    let settings = &context.pcap.settings;
+
+    let format = match present(&body.format).unwrap_or("pcap") {
+        "pcap" => BundleFormat::Merged,
+        "tar" => BundleFormat::Tar,
+        other => {
+            return Err(audit.fail(
+                StatusCode::BAD_REQUEST,
+                "bad-request",
+                &format!("unknown bundle format {other:?}, expected pcap or tar"),
+            ));
+        }
+    };
+
+    // The bundle is buffered like a POST download, so it keeps the same
+    // ceiling: the server default, lowered but never raised.
+    let max_bytes = match present(&body.max_size) {
+        Some(raw) => parse_max_bytes(raw).map_err(|err| {
+            audit.fail(
+                StatusCode::BAD_REQUEST,
+                "bad-max-size",
+                &format!("bad max-size: {err}"),
+            )
+        })?,
+        None => settings.max_bytes,
+    };
+    if max_bytes > settings.max_bytes {
+        return Err(audit.fail(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "max-size-too-large",
+            "pcap bundles cannot exceed the server default download size",
+        ));
+    }
+    // As for single event downloads, flows of a user limited to some
+    // sensors are served by the source their sensor routes to.
+    if body.sensors.is_some() && present(&body.source).is_some() {
+        return Err(audit.fail(
+            StatusCode::FORBIDDEN,
+            "sensor-scope",
+            "users limited to some sensors may not choose the pcap source",
+        ));
+    }
+    let deadline = Instant::now() + settings.request_timeout;
+
+    let (events, more) = load_events(context, body)
+        .await
+        .map_err(|err| audit.reject(err))?;
+    if events.is_empty() {
+        return Err(audit.fail(
+            StatusCode::NOT_FOUND,
+            "no-events",
+            "no events matched the bundle selection",
+        ));
+    }
+    let event_count = events.len();
+    let (flows, skipped) = collect_flows(context, events, present(&body.source));
+    if flows.is_empty() {
+        // Nothing to extract: every event failed the way its single
+        // download would have, so answer with the first such error.
+        let first = &skipped[0];
+        return Err(audit.fail(first.status, first.code, &first.message));
+    }
+
+    // Flows are ordered by window start, so the first names the bundle.
+    let stamp = flows[0].start.to_seconds();
+    let mut truncated = more;
+    let mut used: u64 = 0;
+    let mut captures: Vec<(usize, String, Bytes)> = Vec::new();
+    let mut reports: Vec<FlowReport> = Vec::new();
+    let mut first_error: Option<Response> = None;
+    let mut failed = 0usize;
+    let flow_count = flows.len();
+
+    for flow in flows {
+        let window = pcap::Window {
+            start: flow.start,
+            end: flow.end,
+        };
+        let source_name = flow.source.name().to_string();
+        let filter = describe_selector(&flow.selector);
+        let window_text = describe_window(&window);
+        let report = |outcome: &str, bytes: usize, truncated: bool| FlowReport {
+            file: None,
+            event_ids: flow.event_ids.clone(),
+            source: source_name.clone(),
+            filter: filter.clone(),
+            window: window_text.clone(),
+            bytes,
+            truncated,
+            outcome: outcome.to_string(),
+        };
+
+        // A merged capture keeps a single file header, so every capture
+        // after the first may spend its header's bytes again.
+        let allowance = match format {
+            BundleFormat::Merged if used > 0 => FILE_HEADER_LEN as u64,
+            _ => 0,
+        };
+        let remaining_bytes = max_bytes.saturating_sub(used).saturating_add(allowance);
+        let remaining_time = deadline.saturating_duration_since(Instant::now());
+        if remaining_bytes <= (FILE_HEADER_LEN + PCAP_RECORD_HEADER_SIZE) as u64
+            || remaining_time.is_zero()
+        {
+            truncated = true;
+            reports.push(report("not-extracted", 0, true));
+            continue;
+        }
+
+        // The previous flow's extraction may still hold this source's
+        // slot for a moment after its body completed.
+        if !flow.source.wait_idle(remaining_time).await {
+            truncated = true;
+            reports.push(report("not-extracted", 0, true));
+            continue;
+        }
+
+        let flow_audit = AuditContext {
+            user: audit.user.clone(),
+            remote: audit.remote.clone(),
+            event_id: flow.event_ids[0].clone(),
+            mode: "bundle",
+            filter: filter.clone(),
+            window: window_text.clone(),
+            source: source_name.clone(),
+            native: false,
+            pcapng: false,
+            digest: PayloadDigest::default(),
+            log: context.pcap.audit.clone(),
+            progress: None,
+        };
+        let request = PcapRequest {
+            filter: Some(PcapFilter::Flow(flow.selector)),
+            start: Some(to_micros(&window.start)),
+            end: Some(to_micros(&window.end)),
+            limits: Limits {
+                max_bytes: remaining_bytes,
+                deadline: Some(remaining_time),
+                ..Limits::default()
+            },
+            format: OutputFormat::Pcap,
+            progress: None,
+        };
+        let name = filename(Some(&flow.event), window.start.to_seconds());
+        let entry_name = format!("{:03}-{name}", reports.len() + 1);
+        let response = match dispatch(context, flow.source, request, name, flow_audit).await {
+            Ok(response) | Err(response) => response,
+        };
+        let response = buffer_post_body(response, remaining_bytes).await;
+        let status = response.status();
+        let flow_truncated = response
+            .headers()
+            .contains_key(HeaderName::from_static("x-evebox-pcap-truncated"));
+        let (parts, body) = response.into_parts();
+        let Ok(data) = axum::body::to_bytes(body, usize::MAX).await else {
+            reports.push(report("io", 0, false));
+            failed += 1;
+            continue;
+        };
+        if status != StatusCode::OK {
+            let code = error_code(&data);
+            reports.push(report(&code, 0, false));
+            // No packets for this flow is an expected outcome; anything
+            // else is kept to answer with if no flow produced packets.
+            if status != StatusCode::NOT_FOUND {
+                failed += 1;
+                if first_error.is_none() {
+                    first_error = Some(Response::from_parts(parts, Body::from(data)));
+                }
+            }
+            continue;
+        }
+        truncated |= flow_truncated;
+        reports.push(report("ok", data.len(), flow_truncated));
+        if !data.is_empty() {
+            used = used
+                .saturating_add(data.len() as u64)
+                .saturating_sub(allowance);
+            captures.push((reports.len() - 1, entry_name, data));
+        }
+    }
+
+    if captures.is_empty() {
+        if let Some(response) = first_error {
+            warn!(
+                "pcap-bundle: user={:?} remote={:?} selection={:?} events={} flows={} skipped={} outcome=failed",
+                audit.user,
+                audit.remote,
+                audit.selection,
+                event_count,
+                flow_count,
+                skipped.len()
+            );
+            audit.record("pcap", "failed", None, None, false);
+            return Err(response);
+        }
+        if !truncated {
+            return Err(audit.fail(
+                StatusCode::NOT_FOUND,
+                "no-match",
+                "no packets matched the bundled flows",
+            ));
+        }
+    }
+
+    let stem = match &body.group {
+        Some(group) => format!("bundle-{}-{stamp}", group.signature_id),
+        None => format!("bundle-{stamp}"),
+    };
+
+    let (content_type, filename, output) = match format {
+        BundleFormat::Merged => {
+            let output = if captures.is_empty() {
+                Vec::new()
+            } else {
+                let data: Vec<&[u8]> = captures.iter().map(|(_, _, data)| &data[..]).collect();
+                merge_captures(&data).map_err(|message| {
+                    audit.fail(StatusCode::CONFLICT, "mixed-linktype", &message)
+                })?
+            };
+            (&PCAP_CONTENT_TYPE, format!("{stem}.pcap"), output)
+        }
+        BundleFormat::Tar => {
+            let mut entries = Vec::new();
+            for (index, name, data) in &captures {
+                reports[*index].file = Some(name.clone());
+                entries.push((name.as_str(), &data[..]));
+            }
+            let manifest = json!({
+                "selection": audit.selection,
+                "events": event_count,
+                "truncated": truncated,
+                "flows": reports,
+                "skipped": skipped,
+            });
+            let output = build_tar(&entries, &manifest).map_err(|err| {
+                error!("Failed to build pcap bundle archive: {err}");
+                audit.fail(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "internal",
+                    "failed to build the bundle archive",
+                )
+            })?;
+            (&TAR_CONTENT_TYPE, format!("{stem}.tar"), output)
+        }
+    };
+
+    let format_name = match format {
+        BundleFormat::Merged => "pcap",
+        BundleFormat::Tar => "tar",
+    };
+    info!(
+        "pcap-bundle: user={:?} remote={:?} selection={:?} format={} events={} flows={} skipped={} failed={} outcome=ok bytes={} truncated={}",
+        audit.user,
+        audit.remote,
+        audit.selection,
+        format_name,
+        event_count,
+        flow_count,
+        skipped.len(),
+        failed,
+        output.len(),
+        truncated,
+    );
+    audit.record(format_name, "ok", None, Some(&output), truncated);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, content_type.clone());
+    if let Ok(value) = HeaderValue::from_str(&format!("attachment; filename={filename}")) {
+        headers.insert(CONTENT_DISPOSITION, value);
+    }
+    headers.insert(
+        HeaderName::from_static("x-evebox-pcap-flows"),
+        HeaderValue::from(flow_count),
+    );
+    headers.insert(
+        HeaderName::from_static("x-evebox-pcap-skipped"),
+        HeaderValue::from(skipped.len()),
+    );
+    headers.insert(
+        HeaderName::from_static("x-evebox-pcap-failed"),
+        HeaderValue::from(failed),
+    );
+    if truncated {
+        headers.insert(
+            HeaderName::from_static("x-evebox-pcap-truncated"),
+            HeaderValue::from_static("true"),
+        );
+    }
+    if let Ok(value) = HeaderValue::from_str(&audit::sha256(&output)) {
+        headers.insert(HeaderName::from_static(SHA256_HEADER), value);
+    }
+    Ok((headers, Body::from(output)).into_response())
+}
+
+/// Load the selected events, most recent first, and whether the
+/// selection matched more than [`MAX_EVENTS`] of them.
+pub(super) async fn load_events(
+    context: &ServerContext,
+    body: &BundleRequestBody,
+) -> Result<(Vec<serde_json::Value>, bool), RequestError> {
+    let mut params = EventQueryParams {
+        size: Some(MAX_EVENTS as u64 + 1),
+        ..Default::default()
+    };
+    match (&body.group, present(&body.query)) {
+        (Some(_), Some(_)) => {
+            return Err(RequestError::bad_request(
+                "a bundle takes a group or a query, not both",
+            ));
+        }
+        (None, None) => {
+            return Err(RequestError::bad_request(
+                "a bundle requires a group or a query",
+            ));
+        }
+        (Some(group), None) => {
+            params.event_type = Some("alert".to_string());
+            params.sensor = group.sensor.clone();
+            params.from = Some(parse_time(&group.min_timestamp, None, "min_timestamp")?);
+            params.to = Some(parse_time(&group.max_timestamp, None, "max_timestamp")?);
+            params.query_string = group_query(group);
+        }
+        (None, Some(query)) => {
+            let tz_offset = present(&body.tz_offset);
+            params.query_string = queryparser::parse(query, tz_offset)
+                .map_err(|err| RequestError::bad_request(format!("bad query: {err}")))?;
+            params.sensor = present(&body.sensor).map(str::to_string);
+            params.from = present(&body.from)
+                .map(|ts| parse_time(ts, tz_offset, "from"))
+                .transpose()?;
+            params.to = present(&body.to)
+                .map(|ts| parse_time(ts, tz_offset, "to"))
+                .transpose()?;
+        }
+    }
+
+    if let Some(sensors) = &body.sensors {
+        params.query_string.push(QueryElement {
+            negated: false,
+            value: QueryValue::Sensors(sensors.clone()),
+        });
+    }
+
+    let mut response = context.datastore.events(params).await.map_err(|err| {
+        error!("PCAP bundle failed to load events: {err}");
+        RequestError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal",
+            "failed to load events",
+        )
+    })?;
+    let mut events = match response["events"].take() {
+        serde_json::Value::Array(events) => events,
+        _ => Vec::new(),
+    };
+    let more = events.len() > MAX_EVENTS;
+    events.truncate(MAX_EVENTS);
+    Ok((events, more))
+}
+
+fn parse_time(input: &str, tz_offset: Option<&str>, field: &str) -> Result<DateTime, RequestError> {
+    crate::datetime::parse(input, tz_offset)
+        .map_err(|err| RequestError::bad_request(format!("bad {field}: {err}")))
+}
+
+/// The query elements selecting an alert group's events, matching the
+/// fields the alert view groups on.
+fn group_query(group: &AlertGroupSpec) -> Vec<QueryElement> {
+    let mut fields = vec![("alert.signature_id", Some(group.signature_id.to_string()))];
+    fields.push(("src_ip", group.src_ip.clone()));
+    fields.push(("dest_ip", group.dest_ip.clone()));
+    fields.push(("dns.rrname", group.dns_rrname.clone()));
+    fields.push(("tls.sni", group.tls_sni.clone()));
+    fields
+        .into_iter()
+        .filter_map(|(key, value)| {
+            value
+                .filter(|value| !value.is_empty())
+                .map(|value| QueryElement {
+                    negated: false,
+                    value: QueryValue::KeyValue(key.to_string(), value),
+                })
+        })
+        .collect()
+}
+
+/// Identifies a flow independent of direction: the source serving it,
+/// the protocol, and its two endpoints in sorted order.
+type FlowKey = (String, u8, (IpAddr, Option<u16>), (IpAddr, Option<u16>));
+
+fn flow_key(source: &str, selector: &FlowSelector) -> FlowKey {
+    let (a, b) = if selector.a <= selector.b {
+        (selector.a, selector.b)
+    } else {
+        (selector.b, selector.a)
+    };
+    (source.to_string(), selector.proto, a, b)
+}
+
+/// Group the events into flows ordered by window start, setting aside
+/// the events whose flow cannot be derived or routed.
+pub(super) fn collect_flows(
+    context: &ServerContext,
+    events: Vec<serde_json::Value>,
+    source: Option<&str>,
+) -> (Vec<BundleFlow>, Vec<SkippedEvent>) {
+    let mut flows: Vec<BundleFlow> = Vec::new();
+    let mut index: HashMap<FlowKey, usize> = HashMap::new();
+    let mut skipped = Vec::new();
+
+    for mut event in events {
+        let event_id = match &event["_id"] {
+            serde_json::Value::String(id) => id.clone(),
+            id => id.to_string(),
+        };
+        let event = event["_source"].take();
+        let derived = pcap::selector_from_event(&event)
+            .and_then(|selector| Ok((selector, pcap::derive_window(&event)?)));
+        let (selector, window) = match derived {
+            Ok(derived) => derived,
+            Err(err) => {
+                skipped.push(SkippedEvent {
+                    event_id,
+                    code: "bad-event",
+                    message: err.to_string(),
+                    status: StatusCode::BAD_REQUEST,
+                });
+                continue;
+            }
+        };
+        let resolved = match context
+            .pcap
+            .resolve_source(&context.agents, Some(&event), source)
+        {
+            Ok(resolved) => resolved,
+            Err(err) => {
+                let (status, code, message) = route_error(err);
+                skipped.push(SkippedEvent {
+                    event_id,
+                    code,
+                    message,
+                    status,
+                });
+                continue;
+            }
+        };
+
+        let key = flow_key(resolved.name(), &selector);
+        match index.get(&key) {
+            Some(&i) => {
+                let flow = &mut flows[i];
+                if window.start.datetime < flow.start.datetime {
+                    flow.start = window.start;
+                }
+                if window.end.datetime > flow.end.datetime {
+                    flow.end = window.end;
+                }
+                flow.event_ids.push(event_id);
+            }
+            None => {
+                index.insert(key, flows.len());
+                flows.push(BundleFlow {
+                    source: resolved,
+                    selector,
+                    start: window.start,
+                    end: window.end,
+                    event_ids: vec![event_id],
+                    event,
+                });
+            }
+        }
+    }
+
+    flows.sort_by_key(|flow| flow.start.datetime);
+    (flows, skipped)
+}
+
+/// The status, code, and message a single-event download reports for a
+/// routing failure.
+pub(super) fn route_error(err: RouteError) -> (StatusCode, &'static str, String) {
+    match err {
+        RouteError::NoSource(sensor) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "no-source",
+            sensor
+                .map(|sensor| format!("no pcap source connected for {sensor}"))
+                .unwrap_or_else(|| "no pcap source is configured or connected".to_string()),
+        ),
+        RouteError::NoRule(sensor) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "no-source",
+            sensor
+                .map(|sensor| format!("no pcap routing rule matches sensor {sensor}"))
+                .unwrap_or_else(|| {
+                    "no pcap routing rule matches this event and no default source is set"
+                        .to_string()
+                }),
+        ),
+        RouteError::Ambiguous(candidates) => (
+            StatusCode::CONFLICT,
+            "ambiguous-source",
+            format!(
+                "multiple pcap sources could serve this event: {}",
+                candidates.join(", ")
+            ),
+        ),
+    }
+}
+
+/// The `code` of a structured `{error:{code,message}}` body.
+pub(super) fn error_code(body: &[u8]) -> String {
+    serde_json::from_slice::<serde_json::Value>(body)
+        .ok()
+        .and_then(|body| body["error"]["code"].as_str().map(str::to_string))
+        .unwrap_or_else(|| "error".to_string())
+}
+
+/// Merge classic pcap captures, as written by the extraction engine,
+/// into one capture ordered by packet time. Packets present in more than
+/// one capture (IPv4 fragment continuations match every flow between
+/// the same two hosts) are written once. The captures must share a link
+/// type.
+fn merge_captures(captures: &[&[u8]]) -> Result<Vec<u8>, String> {
+    let mut header: Option<&[u8]> = None;
+    let mut records: Vec<((u32, u32), &[u8])> = Vec::new();
+    for capture in captures {
+        if capture.len() < FILE_HEADER_LEN || capture[0..4] != 0xa1b2_c3d4u32.to_le_bytes() {
+            return Err("unexpected capture format".to_string());
+        }
+        let this = &capture[..FILE_HEADER_LEN];
+        match header {
+            // The link type is the header's last field.
+            Some(first) if first[20..24] != this[20..24] => {
+                return Err(
+                    "the bundled flows were captured on different link types; request the tar format instead"
+                        .to_string(),
+                );
+            }
+            Some(_) => {}
+            None => header = Some(this),
+        }
+        let mut offset = FILE_HEADER_LEN;
+        while offset < capture.len() {
+            let record = &capture[offset..];
+            if record.len() < PCAP_RECORD_HEADER_SIZE {
+                return Err("truncated capture record".to_string());
+            }
+            let field = |at: usize| u32::from_le_bytes(record[at..at + 4].try_into().unwrap());
+            let len = PCAP_RECORD_HEADER_SIZE + field(8) as usize;
+            if record.len() < len {
+                return Err("truncated capture record".to_string());
+            }
+            records.push(((field(0), field(4)), &record[..len]));
+            offset += len;
+        }
+    }
+
+    // Stable: packets with equal timestamps keep their capture order.
+    records.sort_by_key(|(ts, _)| *ts);
+    records.dedup_by(|a, b| a == b);
+
+    let mut output = header.map(<[u8]>::to_vec).unwrap_or_default();
+    for (_, record) in records {
+        output.extend_from_slice(record);
+    }
+    Ok(output)
+}
+
+/// A tar archive of the per-flow captures followed by the manifest.
+fn build_tar(entries: &[(&str, &[u8])], manifest: &serde_json::Value) -> std::io::Result<Vec<u8>> {
+    let mtime = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    let manifest = serde_json::to_vec_pretty(manifest)?;
+    let mut builder = tar::Builder::new(Vec::new());
+    for (name, data) in entries
+        .iter()
+        .copied()
+        .chain(std::iter::once(("manifest.json", &manifest[..])))
+    {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        header.set_cksum();
+        builder.append_data(&mut header, name, data)?;
+    }
+    builder.into_inner()
+}
+
+// The end-to-end cases drive the local extraction path, which Windows
+// builds omit.
+#[cfg(all(test, not(windows)))]
+mod test {
+    use super::*;
+    use crate::server::api::pcap::test::{context_with_event, matching_event, testdata};
+    use crate::server::pcap::PcapSettings;
+    use crate::util::pcap::{create_header, create_record_raw};
+
+    fn capture(linktype: u32, packets: &[(u32, u32, &[u8])]) -> Vec<u8> {
+        let mut out = create_header(linktype);
+        for (sec, usec, data) in packets {
+            out.extend_from_slice(&create_record_raw(*sec, *usec, data.len() as u32, data));
+        }
+        out
+    }
+
+    fn group() -> AlertGroupSpec {
+        AlertGroupSpec {
+            signature_id: 2000001,
+            src_ip: Some("10.1.1.5".to_string()),
+            dest_ip: Some("192.0.2.10".to_string()),
+            sensor: None,
+            dns_rrname: None,
+            tls_sni: None,
+            min_timestamp: "2023-11-14T22:00:00.000000+0000".to_string(),
+            max_timestamp: "2023-11-14T23:00:00.000000+0000".to_string(),
+            sensors: None,
+        }
+    }
+
+    async fn add_events(context: &ServerContext, events: Vec<serde_json::Value>) {
+        let mut sink = context.datastore.get_importer().unwrap();
+        for event in events {
+            sink.submit(event).await.unwrap();
+        }
+        sink.commit().await.unwrap();
+    }
+
+    async fn run(
+        context: &Arc<ServerContext>,
+        body: BundleRequestBody,
+    ) -> (StatusCode, HeaderMap, Vec<u8>) {
+        let audit = BundleAudit {
+            user: "tester".to_string(),
+            remote: "test".to_string(),
+            selection: describe_selection(&body),
+            log: AuditLog::default(),
+        };
+        let response = match bundle(context, &body, &audit).await {
+            Ok(response) | Err(response) => response,
+        };
+        let status = response.status();
+        let headers = response.headers().clone();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, headers, bytes.to_vec())
+    }
+
+    #[test]
+    fn merge_orders_packets_by_time_and_drops_duplicates() {
+        let first = capture(1, &[(10, 0, b"a"), (30, 0, b"c"), (40, 0, b"shared")]);
+        let second = capture(1, &[(20, 0, b"b"), (40, 0, b"shared"), (40, 5, b"d")]);
+        let merged = merge_captures(&[&first, &second]).unwrap();
+        let expected = capture(
+            1,
+            &[
+                (10, 0, b"a"),
+                (20, 0, b"b"),
+                (30, 0, b"c"),
+                (40, 0, b"shared"),
+                (40, 5, b"d"),
+            ],
+        );
+        assert_eq!(merged, expected);
+    }
+
+    #[test]
+    fn merge_rejects_mixed_link_types_and_bad_captures() {
+        let ethernet = capture(1, &[(10, 0, b"a")]);
+        let raw = capture(101, &[(20, 0, b"b")]);
+        assert!(merge_captures(&[&ethernet, &raw]).is_err());
+
+        let truncated = &ethernet[..ethernet.len() - 1];
+        assert!(merge_captures(&[truncated]).is_err());
+        assert!(merge_captures(&[b"not a capture".as_slice()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn events_of_one_flow_share_an_extraction() {
+        let dir = tempfile::tempdir().unwrap();
+        let context =
+            context_with_event(dir.path(), matching_event(), PcapSettings::default()).await;
+
+        // The reply direction of the same flow, a minute later.
+        let mut reply = matching_event();
+        reply["timestamp"] = "2023-11-14T22:16:20.000000+0000".into();
+        reply["src_ip"] = "192.0.2.10".into();
+        reply["src_port"] = 53.into();
+        reply["dest_ip"] = "10.1.1.5".into();
+        reply["dest_port"] = 4000.into();
+        // Another flow between the same hosts.
+        let mut other = matching_event();
+        other["src_port"] = 4001.into();
+        // No protocol: no flow can be derived.
+        let mut flowless = matching_event();
+        flowless.as_object_mut().unwrap().remove("proto");
+        add_events(&context, vec![reply, other, flowless]).await;
+
+        let (events, more) = load_events(
+            &context,
+            &BundleRequestBody {
+                group: Some(AlertGroupSpec {
+                    src_ip: None,
+                    dest_ip: None,
+                    ..group()
+                }),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(events.len(), 4);
+        assert!(!more);
+
+        let (flows, skipped) = collect_flows(&context, events, None);
+        assert_eq!(flows.len(), 2);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].code, "bad-event");
+
+        let shared = flows
+            .iter()
+            .find(|flow| flow.event_ids.len() == 2)
+            .expect("both directions collapse into one flow");
+        // The union of both events' windows: from a second before the
+        // flow start to a minute after the later event.
+        assert_eq!(
+            shared.start.to_rfc3339_utc(),
+            crate::datetime::parse("2023-11-14T22:13:19Z", None)
+                .unwrap()
+                .to_rfc3339_utc()
+        );
+        assert_eq!(
+            shared.end.to_rfc3339_utc(),
+            crate::datetime::parse("2023-11-14T22:17:20Z", None)
+                .unwrap()
+                .to_rfc3339_utc()
+        );
+    }
+
+    #[tokio::test]
+    async fn bundle_requires_exactly_one_selection() {
+        let dir = tempfile::tempdir().unwrap();
+        let context =
+            context_with_event(dir.path(), matching_event(), PcapSettings::default()).await;
+
+        let (status, _, _) = run(&context, BundleRequestBody::default()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let both = BundleRequestBody {
+            group: Some(group()),
+            query: Some("alert.signature_id:2000001".to_string()),
+            ..Default::default()
+        };
+        let (status, _, _) = run(&context, both).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let format = BundleRequestBody {
+            group: Some(group()),
+            format: Some("zip".to_string()),
+            ..Default::default()
+        };
+        let (status, _, _) = run(&context, format).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn group_without_events_returns_404() {
+        let dir = tempfile::tempdir().unwrap();
+        let context =
+            context_with_event(dir.path(), matching_event(), PcapSettings::default()).await;
+        let body = BundleRequestBody {
+            group: Some(AlertGroupSpec {
+                signature_id: 1,
+                ..group()
+            }),
+            ..Default::default()
+        };
+        let (status, _, body) = run(&context, body).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(error_code(&body), "no-events");
+    }
+
+    #[tokio::test]
+    async fn max_size_above_the_default_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let context =
+            context_with_event(dir.path(), matching_event(), PcapSettings::default()).await;
+        let body = BundleRequestBody {
+            group: Some(group()),
+            max_size: Some("unlimited".to_string()),
+            ..Default::default()
+        };
+        let (status, _, _) = run(&context, body).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn group_bundle_matches_golden_fixture() {
+        let dir = tempfile::tempdir().unwrap();
+        let context =
+            context_with_event(dir.path(), matching_event(), PcapSettings::default()).await;
+        // A second alert on the same flow adds no packets: one extraction
+        // over the merged window.
+        let mut again = matching_event();
+        again["timestamp"] = "2023-11-14T22:15:21.000000+0000".into();
+        add_events(&context, vec![again]).await;
+
+        let body = BundleRequestBody {
+            group: Some(group()),
+            ..Default::default()
+        };
+        let (status, headers, body) = run(&context, body).await;
+        assert_eq!(
+            status,
+            StatusCode::OK,
+            "body={:?}",
+            String::from_utf8_lossy(&body)
+        );
+        assert_eq!(headers.get("x-evebox-pcap-flows").unwrap(), "1");
+        assert!(headers.get("x-evebox-pcap-truncated").is_none());
+        assert_eq!(body, std::fs::read(testdata("expected.pcap")).unwrap());
+    }
+
+    #[tokio::test]
+    async fn tar_bundle_holds_flow_captures_and_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let context =
+            context_with_event(dir.path(), matching_event(), PcapSettings::default()).await;
+        let body = BundleRequestBody {
+            query: Some("alert.signature_id:2000001".to_string()),
+            format: Some("tar".to_string()),
+            ..Default::default()
+        };
+        let (status, headers, body) = run(&context, body).await;
+        assert_eq!(
+            status,
+            StatusCode::OK,
+            "body={:?}",
+            String::from_utf8_lossy(&body)
+        );
+        assert_eq!(headers.get(CONTENT_TYPE).unwrap(), "application/x-tar");
+
+        let mut archive = tar::Archive::new(&body[..]);
+        let mut files = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().to_string_lossy().to_string();
+            let mut data = Vec::new();
+            std::io::Read::read_to_end(&mut entry, &mut data).unwrap();
+            files.push((name, data));
+        }
+        assert_eq!(files.len(), 2);
+        assert!(files[0].0.starts_with("001-2000001-"), "{}", files[0].0);
+        assert_eq!(
+            files[0].1,
+            std::fs::read(testdata("expected.pcap")).unwrap()
+        );
+        assert_eq!(files[1].0, "manifest.json");
+        let manifest: serde_json::Value = serde_json::from_slice(&files[1].1).unwrap();
+        assert_eq!(manifest["flows"][0]["outcome"], "ok");
+        assert_eq!(manifest["flows"][0]["file"], files[0].0.as_str());
+    }
+}
@@ -0,0 +1,468 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! Pinning the captures of escalated events.
+//!
+//! With `pcap.evidence.enabled` set, escalating an event or an alert
+//! group extracts the flows of its events in the background, exactly as
+//! a bundle would, and keeps each capture in the evidence store so it
+//! outlives the rolling spool. Every event gets a `pcap-pinned` history
+//! entry with its capture's SHA-256, or a `pcap-pin-failed` entry with
+//! the error code its download would have returned.
+//! `GET /api/pcap/evidence/{sha256}` downloads a pinned capture.
+
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+
+use super::bundle::{self, BundleFlow, BundleRequestBody};
+use super::{
+    AuditContext, PCAP_CONTENT_TYPE, buffer_post_body, describe_selector, describe_window,
+    dispatch, error, filename, to_micros,
+};
+use crate::elastic::{HistoryEntry, HistoryEntryBuilder, HistoryPcap};
+use crate::pcap::{self, Limits, OutputFormat, PcapFilter, PcapRequest};
+use crate::prelude::*;
+use crate::server::ServerContext;
+use crate::server::api::AlertGroupSpec;
+use crate::server::main::SessionExtractor;
+use crate::server::pcap::audit::PayloadDigest;
+use crate::server::pcap::evidence::{CaptureInfo, EvidenceRecord, EvidenceStore};
+
+/// Extraction attempts per flow while its source or the server is busy.
+const PIN_ATTEMPTS: u32 = 5;
+
+const PIN_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// What an escalation escalated.
+pub(crate) enum PinTarget {
+    Event(String),
+    Group(AlertGroupSpec),
+}
+
+/// Pin the target's captures in the background when pinning is on.
+pub(crate) fn spawn_pin(
+    context: &Arc<ServerContext>,
+    target: PinTarget,
+    user: Option<String>,
+    remote: String,
+) {
+    if context.pcap.evidence.is_none() {
+        return;
+    }
+    let context = context.clone();
+    tokio::spawn(async move {
+        pin(&context, target, user, remote).await;
+    });
+}
+
+async fn pin(
+    context: &Arc<ServerContext>,
+    target: PinTarget,
+    user: Option<String>,
+    remote: String,
+) {
+    let Some(store) = &context.pcap.evidence else {
+        return;
+    };
+    let _pinning = store.lock().await;
+
+    let events = match target {
+        PinTarget::Event(event_id) => match context.datastore.get_event_by_id(event_id).await {
+            Ok(event) => event.into_iter().collect(),
+            Err(err) => {
+                warn!("PCAP pin failed to load event: {err}");
+                return;
+            }
+        },
+        PinTarget::Group(group) => {
+            let body = BundleRequestBody {
+                sensors: group.sensors.clone(),
+                group: Some(group),
+                ..Default::default()
+            };
+            match bundle::load_events(context, &body).await {
+                Ok((events, more)) => {
+                    if more {
+                        warn!(
+                            "PCAP pin: only the newest {} events of the alert group are pinned",
+                            events.len()
+                        );
+                    }
+                    events
+                }
+                Err(err) => {
+                    warn!("PCAP pin failed to load events: {}", err.message);
+                    return;
+                }
+            }
+        }
+    };
+
+    let (flows, skipped) = bundle::collect_flows(context, events, None);
+    for event in skipped {
+        let entry = HistoryEntryBuilder::new_pcap_pin_failed(event.code).build();
+        record(context, &event.event_id, &entry).await;
+    }
+    let audit_user = user.clone().unwrap_or_else(|| "-".to_string());
+    for flow in flows {
+        let event_ids = flow.event_ids.clone();
+        let entry = match pin_flow(context, store, flow, &audit_user, &remote).await {
+            Ok(stored) => HistoryEntryBuilder::new_pcap_pinned(HistoryPcap {
+                sha256: stored.sha256,
+                bytes: stored.bytes,
+                source: stored.info.source,
+                truncated: stored.info.truncated,
+            })
+            .username(user.clone()),
+            Err(code) => HistoryEntryBuilder::new_pcap_pin_failed(code),
+        }
+        .build();
+        for event_id in &event_ids {
+            record(context, event_id, &entry).await;
+        }
+    }
+}
+
+async fn record(context: &ServerContext, event_id: &str, entry: &HistoryEntry) {
+    if let Err(err) = context.datastore.add_history_by_id(event_id, entry).await {
+        warn!("Failed to record PCAP pin in the history of event {event_id}: {err}");
+    }
+}
+
+/// Extract one flow into the store, or fail with the error code its
+/// download would have returned.
+async fn pin_flow(
+    context: &Arc<ServerContext>,
+    store: &EvidenceStore,
+    flow: BundleFlow,
+    user: &str,
+    remote: &str,
+) -> Result<EvidenceRecord, String> {
+    let settings = &context.pcap.settings;
+    let window = pcap::Window {
+        start: flow.start,
+        end: flow.end,
+    };
+    let filter = describe_selector(&flow.selector);
+    let window_text = describe_window(&window);
+    let name = filename(Some(&flow.event), window.start.to_seconds());
+
+    let mut resolved = Some(flow.source);
+    for attempt in 1..=PIN_ATTEMPTS {
+        if attempt > 1 {
+            tokio::time::sleep(PIN_RETRY_DELAY).await;
+        }
+        // Routing is repeated on a retry: the agent may have reconnected.
+        let source = match resolved.take() {
+            Some(source) => source,
+            None => context
+                .pcap
+                .resolve_source(&context.agents, Some(&flow.event), None)
+                .map_err(|err| bundle::route_error(err).1.to_string())?,
+        };
+        if !source.wait_idle(settings.request_timeout).await {
+            continue;
+        }
+        let source_name = source.name().to_string();
+        let audit = AuditContext {
+            user: user.to_string(),
+            remote: remote.to_string(),
+            event_id: flow.event_ids[0].clone(),
+            mode: "evidence",
+            filter: filter.clone(),
+            window: window_text.clone(),
+            source: source_name.clone(),
+            native: false,
+            pcapng: false,
+            digest: PayloadDigest::default(),
+            log: context.pcap.audit.clone(),
+            progress: None,
+        };
+        let request = PcapRequest {
+            filter: Some(PcapFilter::Flow(flow.selector.clone())),
+            start: Some(to_micros(&window.start)),
+            end: Some(to_micros(&window.end)),
+            limits: Limits {
+                max_bytes: settings.max_bytes,
+                deadline: Some(settings.request_timeout),
+                ..Limits::default()
+            },
+            format: OutputFormat::Pcap,
+            progress: None,
+        };
+        let response = match dispatch(context, source, request, name.clone(), audit).await {
+            Ok(response) | Err(response) => response,
+        };
+        let response = buffer_post_body(response, settings.max_bytes).await;
+        let status = response.status();
+        let truncated = response
+            .headers()
+            .contains_key(HeaderName::from_static("x-evebox-pcap-truncated"));
+        let Ok(data) = axum::body::to_bytes(response.into_body(), usize::MAX).await else {
+            return Err("io".to_string());
+        };
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            continue;
+        }
+        if status != StatusCode::OK {
+            return Err(bundle::error_code(&data));
+        }
+        if data.is_empty() {
+            // A limit stopped the extraction before its first packet.
+            return Err("truncated".to_string());
+        }
+
+        let info = CaptureInfo {
+            filename: name.clone(),
+            source: source_name,
+            filter: filter.clone(),
+            window: window_text.clone(),
+            truncated,
+            event_ids: flow.event_ids.clone(),
+        };
+        return store.store(&data, info).map_err(|err| {
+            error!(
+                "Failed to store pinned capture in {}: {err}",
+                store.directory().display()
+            );
+            "io".to_string()
+        });
+    }
+    Err("busy".to_string())
+}
+
+/// `GET /api/pcap/evidence/{sha256}`: download a pinned capture. Users
+/// limited to some sensors get only captures of events they may see.
+pub(crate) async fn get_evidence(
+    State(context): State<Arc<ServerContext>>,
+    SessionExtractor(session): SessionExtractor,
+    Path(sha256): Path<String>,
+) -> Response {
+    let Some(store) = &context.pcap.evidence else {
+        return error(
+            StatusCode::NOT_FOUND,
+            "not-enabled",
+            "capture pinning is not enabled",
+        );
+    };
+    let not_found = || {
+        error(
+            StatusCode::NOT_FOUND,
+            "not-found",
+            "no pinned capture with this hash",
+        )
+    };
+    let (path, record) = match store.get(&sha256) {
+        Ok(Some(found)) => found,
+        Ok(None) => return not_found(),
+        Err(err) => {
+            error!("Failed to read pinned capture {sha256}: {err}");
+            return error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "io",
+                "failed to read the pinned capture",
+            );
+        }
+    };
+    if session.sensors.is_some() {
+        for event_id in &record.info.event_ids {
+            match context.datastore.get_event_by_id(event_id.clone()).await {
+                Ok(Some(event)) if session.may_see_event(&event) => {}
+                Ok(_) => return not_found(),
+                Err(err) => {
+                    error!("Failed to get event by ID: id={event_id}, err={err:?}");
+                    return error(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "internal",
+                        "failed to look up the capture's events",
+                    );
+                }
+            }
+        }
+    }
+    let data = match tokio::fs::read(&path).await {
+        Ok(data) => data,
+        Err(err) => {
+            error!(
+                "Failed to read pinned capture {sha256}: {}: {err}",
+                path.display()
+            );
+            return error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "io",
+                "failed to read the pinned capture",
+            );
+        }
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, PCAP_CONTENT_TYPE.clone());
+    if let Ok(value) =
+        HeaderValue::from_str(&format!("attachment; filename={}", record.info.filename))
+    {
+        headers.insert(CONTENT_DISPOSITION, value);
+    }
+    if let Ok(value) = HeaderValue::from_str(&record.sha256) {
+        headers.insert(HeaderName::from_static("x-evebox-pcap-sha256"), value);
+    }
+    (headers, Body::from(data)).into_response()
+}
+
+#[cfg(all(test, not(windows)))]
+mod test {
+    use super::*;
+    use crate::pcap::{PcapSource, SpoolConfig};
+    use crate::server::api::pcap::test::{context_with_event, matching_event, testdata};
+    use crate::server::pcap::{PcapService, PcapSettings};
+    use crate::server::session::{Role, Session};
+
+    fn with_evidence(
+        context: Arc<ServerContext>,
+        directory: &std::path::Path,
+    ) -> Arc<ServerContext> {
+        let mut context = Arc::into_inner(context).unwrap();
+        let mut service = PcapService::new(
+            PcapSettings::default(),
+            Some(PcapSource::Spool(SpoolConfig::new(testdata("spool"), None))),
+        );
+        service.evidence = Some(EvidenceStore::new(directory.to_path_buf()));
+        context.pcap = Arc::new(service);
+        Arc::new(context)
+    }
+
+    #[tokio::test]
+    async fn escalated_event_capture_is_pinned_with_its_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let context =
+            context_with_event(dir.path(), matching_event(), PcapSettings::default()).await;
+        let context = with_evidence(context, &dir.path().join("evidence"));
+
+        let events = context.datastore.events(Default::default()).await.unwrap();
+        let event_id = events["events"][0]["_id"].to_string();
+        pin(
+            &context,
+            PinTarget::Event(event_id.clone()),
+            Some("analyst".to_string()),
+            "test".to_string(),
+        )
+        .await;
+
+        let event = context
+            .datastore
+            .get_event_by_id(event_id.clone())
+            .await
+            .unwrap()
+            .unwrap();
+        let history = event["_source"]["evebox"]["history"].as_array().unwrap();
+        let entry = history.last().unwrap();
+        assert_eq!(entry["action"], "pcap-pinned");
+        assert_eq!(entry["username"], "analyst");
+        let sha256 = entry["pcap"]["sha256"].as_str().unwrap();
+
+        let (path, record) = context
+            .pcap
+            .evidence
+            .as_ref()
+            .unwrap()
+            .get(sha256)
+            .unwrap()
+            .unwrap();
+        let data = std::fs::read(path).unwrap();
+        assert_eq!(crate::cert::fingerprint(&data), sha256);
+        assert_eq!(entry["pcap"]["bytes"], data.len() as u64);
+        assert_eq!(record.info.event_ids, [event_id]);
+
+        let response = get_evidence(
+            State(context.clone()),
+            SessionExtractor(Arc::new(Session::anonymous(None))),
+            Path(sha256.to_string()),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], &data[..]);
+    }
+
+    #[tokio::test]
+    async fn sensor_limited_users_get_only_captures_of_their_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let context =
+            context_with_event(dir.path(), matching_event(), PcapSettings::default()).await;
+        let context = with_evidence(context, &dir.path().join("evidence"));
+        let events = context.datastore.events(Default::default()).await.unwrap();
+        let event_id = events["events"][0]["_id"].to_string();
+        let record = context
+            .pcap
+            .evidence
+            .as_ref()
+            .unwrap()
+            .store(
+                b"packets",
+                CaptureInfo {
+                    filename: "capture.pcap".to_string(),
+                    source: "spool".to_string(),
+                    filter: "-".to_string(),
+                    window: "-".to_string(),
+                    truncated: false,
+                    event_ids: vec![event_id],
+                },
+            )
+            .unwrap();
+
+        for (sensor, status) in [
+            ("test-sensor", StatusCode::OK),
+            ("other-sensor", StatusCode::NOT_FOUND),
+        ] {
+            let mut session = Session::with_username("bob", Role::Viewer);
+            session.sensors = Some(vec![sensor.to_string()]);
+            let response = get_evidence(
+                State(context.clone()),
+                SessionExtractor(Arc::new(session)),
+                Path(record.sha256.clone()),
+            )
+            .await;
+            assert_eq!(response.status(), status, "{sensor}");
+        }
+    }
+
+    #[tokio::test]
+    async fn events_without_a_flow_record_the_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut event = matching_event();
+        event["src_ip"] = serde_json::Value::Null;
+        let context = context_with_event(dir.path(), event, PcapSettings::default()).await;
+        let context = with_evidence(context, &dir.path().join("evidence"));
+
+        let events = context.datastore.events(Default::default()).await.unwrap();
+        let event_id = events["events"][0]["_id"].to_string();
+        pin(
+            &context,
+            PinTarget::Event(event_id.clone()),
+            None,
+            "test".to_string(),
+        )
+        .await;
+
+        let event = context
+            .datastore
+            .get_event_by_id(event_id)
+            .await
+            .unwrap()
+            .unwrap();
+        let entry = event["_source"]["evebox"]["history"]
+            .as_array()
+            .unwrap()
+            .last()
+            .unwrap()
+            .clone();
+        assert_eq!(entry["action"], "pcap-pin-failed");
+        assert_eq!(entry["cause"], "bad-event");
+    }
+}
@@ -0,0 +1,694 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! `/api/pcap/jobs`: asynchronous exports for captures too large or too
+//! slow for a browser download.
+//!
+//! A job takes the same request body as `POST /api/pcap`. The request
+//! is validated up front, then extracted in the background through the
+//! regular routing and extraction path into a file on the server. The
+//! caller polls the job for its progress and downloads the finished
+//! capture, with range requests to resume, until the job expires.
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Extension, Json, Path, State};
+use axum::http::header::{
+    ACCEPT_RANGES, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG,
+    IF_RANGE, RANGE,
+};
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use axum::response::{IntoResponse, Response};
+use futures::StreamExt;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use super::{
+    PCAP_CONTENT_TYPE, PCAPNG_CONTENT_TYPE, PcapRequestBody, PostCompletion, error, handle_inner,
+    present, remote_addr,
+};
+use crate::prelude::*;
+use crate::server::ServerContext;
+use crate::server::main::SessionExtractor;
+use crate::server::pcap::audit::{PayloadDigest, SHA256_HEADER};
+use crate::server::pcap::jobs::{CreateError, JobState, PcapJob};
+use crate::sqlite::configdb::PcapAuditRecord;
+
+/// How long a job waits before retrying a busy capture source.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Read size when serving a capture.
+const READ_SIZE: usize = 64 * 1024;
+
+/// `POST /api/pcap/jobs`: start an export. A request that would fail
+/// as a download fails here with the same error; otherwise the queued
+/// job is returned, to be polled with `GET /api/pcap/jobs/{id}`.
+pub(crate) async fn post_job(
+    State(context): State<Arc<ServerContext>>,
+    SessionExtractor(session): SessionExtractor,
+    Extension(ConnectInfo(remote)): Extension<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Json(mut body): Json<PcapRequestBody>,
+) -> Response {
+    let user = session.username.clone().unwrap_or_else(|| "-".to_string());
+    body.sensors = session.sensors.clone();
+    let remote = remote_addr(&context, &headers, remote);
+
+    let filename =
+        match handle_inner(&context, &body, &user, remote.clone(), true, false, None).await {
+            Ok(response) => match axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .ok()
+                .and_then(|body| serde_json::from_slice::<serde_json::Value>(&body).ok())
+                .and_then(|json| json["filename"].as_str().map(str::to_string))
+            {
+                Some(filename) => filename,
+                None => {
+                    return error(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "internal",
+                        "failed to validate the pcap request",
+                    );
+                }
+            },
+            Err(response) => return response,
+        };
+
+    let pcapng = present(&body.format) == Some("pcapng");
+    let (job, file) = match context
+        .pcap
+        .jobs
+        .create(&user, filename, pcapng, body.event_id.clone())
+    {
+        Ok(created) => created,
+        Err(CreateError::TooMany(max)) => {
+            return error(
+                StatusCode::TOO_MANY_REQUESTS,
+                "too-many-jobs",
+                &format!("the server keeps at most {max} pcap jobs; delete finished ones"),
+            );
+        }
+        Err(CreateError::Io(message)) => {
+            error!("Failed to create a pcap job file: {message}");
+            return error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "io",
+                "failed to create the pcap job file",
+            );
+        }
+    };
+    info!(
+        "pcap-job: user={:?} remote={:?} event={:?} job={} outcome=started",
+        user,
+        remote,
+        body.event_id.as_deref().unwrap_or("-"),
+        job.id
+    );
+    let status = job.status();
+    tokio::spawn(run(
+        context,
+        job,
+        tokio::fs::File::from_std(file),
+        body,
+        user,
+        remote,
+    ));
+    (StatusCode::ACCEPTED, Json(status)).into_response()
+}
+
+/// `GET /api/pcap/jobs`: the caller's jobs, newest first.
+pub(crate) async fn get_jobs(
+    State(context): State<Arc<ServerContext>>,
+    SessionExtractor(session): SessionExtractor,
+) -> Response {
+    let user = session.username.clone().unwrap_or_else(|| "-".to_string());
+    let jobs: Vec<_> = context
+        .pcap
+        .jobs
+        .list(&user)
+        .iter()
+        .map(|job| job.status())
+        .collect();
+    Json(json!({ "jobs": jobs })).into_response()
+}
+
+/// `GET /api/pcap/jobs/{id}`: a job's state and progress.
+pub(crate) async fn get_job(
+    State(context): State<Arc<ServerContext>>,
+    SessionExtractor(session): SessionExtractor,
+    Path(id): Path<String>,
+) -> Response {
+    let user = session.username.clone().unwrap_or_else(|| "-".to_string());
+    match context.pcap.jobs.get(&user, &id) {
+        Some(job) => Json(job.status()).into_response(),
+        None => not_found(),
+    }
+}
+
+/// `DELETE /api/pcap/jobs/{id}`: cancel a running job, or discard a
+/// finished one, and remove its capture.
+pub(crate) async fn delete_job(
+    State(context): State<Arc<ServerContext>>,
+    SessionExtractor(session): SessionExtractor,
+    Path(id): Path<String>,
+) -> Response {
+    let user = session.username.clone().unwrap_or_else(|| "-".to_string());
+    match context.pcap.jobs.remove(&user, &id) {
+        Some(job) => {
+            info!(
+                "pcap-job: user={:?} job={} state={:?} outcome=deleted",
+                user,
+                job.id,
+                job.state()
+            );
+            Json(json!({})).into_response()
+        }
+        None => not_found(),
+    }
+}
+
+/// `GET /api/pcap/jobs/{id}/download`: the finished capture. A single
+/// `Range` is honoured, so an interrupted download resumes where it
+/// stopped; `If-Range` takes the capture's SHA-256 ETag.
+pub(crate) async fn get_job_download(
+    State(context): State<Arc<ServerContext>>,
+    SessionExtractor(session): SessionExtractor,
+    Extension(ConnectInfo(remote)): Extension<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Response {
+    let user = session.username.clone().unwrap_or_else(|| "-".to_string());
+    let remote = remote_addr(&context, &headers, remote);
+    let Some(job) = context.pcap.jobs.get(&user, &id) else {
+        return not_found();
+    };
+    let status = job.status();
+    if status.state != JobState::Complete {
+        return error(
+            StatusCode::CONFLICT,
+            "not-ready",
+            &format!("the pcap job is {:?}", status.state).to_lowercase(),
+        );
+    }
+    let sha256 = status.sha256.clone().unwrap_or_default();
+    let etag = format!("\"{sha256}\"");
+    let size = status.bytes;
+
+    let range = match headers.get(IF_RANGE) {
+        Some(if_range) if if_range.as_bytes() != etag.as_bytes() => ByteRange::Full,
+        _ => parse_range(
+            headers.get(RANGE).and_then(|value| value.to_str().ok()),
+            size,
+        ),
+    };
+    let (start, end) = match range {
+        ByteRange::Full => (0, size),
+        ByteRange::Partial(start, last) => (start, last + 1),
+        ByteRange::Unsatisfiable => {
+            let mut response = error(
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                "bad-range",
+                "the requested range is outside the capture",
+            );
+            if let Ok(value) = HeaderValue::from_str(&format!("bytes */{size}")) {
+                response.headers_mut().insert(CONTENT_RANGE, value);
+            }
+            return response;
+        }
+    };
+
+    let mut file = match tokio::fs::File::open(job.path()).await {
+        Ok(file) => file,
+        Err(err) => {
+            error!("Failed to open pcap job {}: {err}", job.id);
+            return error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "io",
+                "failed to read the pcap job capture",
+            );
+        }
+    };
+    if start > 0
+        && let Err(err) = file.seek(std::io::SeekFrom::Start(start)).await
+    {
+        error!("Failed to seek pcap job {}: {err}", job.id);
+        return error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "io",
+            "failed to read the pcap job capture",
+        );
+    }
+
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(
+        CONTENT_TYPE,
+        if job.pcapng {
+            PCAPNG_CONTENT_TYPE.clone()
+        } else {
+            PCAP_CONTENT_TYPE.clone()
+        },
+    );
+    if let Ok(value) = HeaderValue::from_str(&format!("attachment; filename={}", job.filename)) {
+        response_headers.insert(CONTENT_DISPOSITION, value);
+    }
+    response_headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    response_headers.insert(CONTENT_LENGTH, HeaderValue::from(end - start));
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        response_headers.insert(ETAG, value);
+    }
+    if let Ok(value) = HeaderValue::from_str(&sha256) {
+        response_headers.insert(HeaderName::from_static(SHA256_HEADER), value);
+    }
+    if status.truncated {
+        response_headers.insert(
+            HeaderName::from_static("x-evebox-pcap-truncated"),
+            HeaderValue::from_static("true"),
+        );
+    }
+    let status_code = if matches!(range, ByteRange::Partial(..)) {
+        if let Ok(value) =
+            HeaderValue::from_str(&format!("bytes {start}-{}/{size}", end.saturating_sub(1)))
+        {
+            response_headers.insert(CONTENT_RANGE, value);
+        }
+        StatusCode::PARTIAL_CONTENT
+    } else {
+        StatusCode::OK
+    };
+
+    info!(
+        "pcap-job: user={:?} remote={:?} job={} outcome=download bytes={}-{}/{}",
+        user, remote, job.id, start, end, size
+    );
+    context.pcap.audit.record(PcapAuditRecord {
+        username: user,
+        remote,
+        event_id: job.event_id.clone(),
+        mode: Some("job".to_string()),
+        format: if job.pcapng { "pcapng" } else { "pcap" }.to_string(),
+        outcome: "download".to_string(),
+        message: Some(format!("job {} bytes {start}-{end}/{size}", job.id)),
+        bytes: i64::try_from(end - start).unwrap_or(i64::MAX),
+        truncated: status.truncated,
+        sha256: status.sha256,
+        ..Default::default()
+    });
+
+    (
+        status_code,
+        response_headers,
+        Body::from_stream(read_stream(file, end - start)),
+    )
+        .into_response()
+}
+
+fn not_found() -> Response {
+    error(StatusCode::NOT_FOUND, "not-found", "no such pcap job")
+}
+
+/// Run a job to completion: extract through the regular path, waiting
+/// for a busy source up to the request timeout, and write the capture
+/// to the job's file.
+async fn run(
+    context: Arc<ServerContext>,
+    job: Arc<PcapJob>,
+    mut file: tokio::fs::File,
+    body: PcapRequestBody,
+    user: String,
+    remote: String,
+) {
+    let queued = Instant::now();
+    let response = loop {
+        job.set_state(JobState::Running);
+        let response = tokio::select! {
+            _ = job.cancel.cancelled() => return,
+            response = handle_inner(
+                &context,
+                &body,
+                &user,
+                remote.clone(),
+                false,
+                false,
+                Some(job.progress.clone()),
+            ) => match response {
+                Ok(response) | Err(response) => response,
+            },
+        };
+        if response.status() != StatusCode::TOO_MANY_REQUESTS
+            || queued.elapsed() >= context.pcap.settings.request_timeout
+        {
+            break response;
+        }
+        job.set_state(JobState::Queued);
+        tokio::select! {
+            _ = job.cancel.cancelled() => return,
+            _ = tokio::time::sleep(RETRY_INTERVAL) => {}
+        }
+    };
+
+    if response.status() != StatusCode::OK {
+        let status = response.status();
+        let json = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .ok()
+            .and_then(|body| serde_json::from_slice::<serde_json::Value>(&body).ok())
+            .unwrap_or_default();
+        let code = json["error"]["code"].as_str().unwrap_or("failed");
+        let message = json["error"]["message"]
+            .as_str()
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("pcap extraction failed with status {status}"));
+        finish_failed(&job, &user, code, &message);
+        return;
+    }
+
+    let completion = response.extensions().get::<PostCompletion>().cloned();
+    let truncated_header = response
+        .headers()
+        .contains_key(HeaderName::from_static("x-evebox-pcap-truncated"));
+    let digest = PayloadDigest::default();
+    let mut stream = response.into_body().into_data_stream();
+    loop {
+        let chunk = tokio::select! {
+            _ = job.cancel.cancelled() => return,
+            chunk = stream.next() => chunk,
+        };
+        match chunk {
+            Some(Ok(chunk)) => {
+                if let Err(err) = file.write_all(&chunk).await {
+                    error!("Failed to write pcap job {}: {err}", job.id);
+                    finish_failed(&job, &user, "io", "failed to write the capture");
+                    return;
+                }
+                digest.update(&chunk);
+                job.add_bytes(chunk.len() as u64);
+            }
+            Some(Err(_)) => {
+                finish_failed(&job, &user, "io", "pcap extraction failed");
+                return;
+            }
+            None => break,
+        }
+    }
+    if let Err(err) = file.flush().await {
+        error!("Failed to write pcap job {}: {err}", job.id);
+        finish_failed(&job, &user, "io", "failed to write the capture");
+        return;
+    }
+
+    let truncated = truncated_header || completion.is_some_and(|completion| completion.truncated());
+    info!(
+        "pcap-job: user={:?} job={} outcome=complete bytes={} truncated={}",
+        user,
+        job.id,
+        digest.bytes(),
+        truncated
+    );
+    job.complete(truncated, digest.sha256());
+}
+
+fn finish_failed(job: &PcapJob, user: &str, code: &str, message: &str) {
+    warn!(
+        "pcap-job: user={:?} job={} outcome={} message={:?}",
+        user, job.id, code, message
+    );
+    job.fail(code, message);
+}
+
+/// The part of a capture a download asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ByteRange {
+    Full,
+    /// First and last byte, inclusive.
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+/// Parse a `Range` header for a capture of `size` bytes. Only a single
+/// byte range is served; a malformed header or a multi-range request is
+/// answered with the whole capture, as RFC 9110 permits.
+fn parse_range(header: Option<&str>, size: u64) -> ByteRange {
+    let Some(spec) = header
+        .map(str::trim)
+        .and_then(|header| header.strip_prefix("bytes="))
+    else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((first, last)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+    let parse = |value: &str| value.trim().parse::<u64>().ok();
+    match (first.trim().is_empty(), last.trim().is_empty()) {
+        // A suffix: the last n bytes.
+        (true, false) => match parse(last) {
+            Some(0) => ByteRange::Unsatisfiable,
+            Some(_) if size == 0 => ByteRange::Unsatisfiable,
+            Some(n) => ByteRange::Partial(size.saturating_sub(n), size - 1),
+            None => ByteRange::Full,
+        },
+        (false, true) => match parse(first) {
+            Some(first) if first >= size => ByteRange::Unsatisfiable,
+            Some(first) => ByteRange::Partial(first, size - 1),
+            None => ByteRange::Full,
+        },
+        (false, false) => match (parse(first), parse(last)) {
+            (Some(first), Some(last)) if first <= last => {
+                if first >= size {
+                    ByteRange::Unsatisfiable
+                } else {
+                    ByteRange::Partial(first, last.min(size - 1))
+                }
+            }
+            _ => ByteRange::Full,
+        },
+        (true, true) => ByteRange::Full,
+    }
+}
+
+/// Stream `len` bytes of `file` from its current position.
+fn read_stream(
+    file: tokio::fs::File,
+    len: u64,
+) -> impl futures::Stream<Item = Result<bytes::Bytes, std::io::Error>> {
+    futures::stream::unfold((file, len), |(mut file, remaining)| async move {
+        if remaining == 0 {
+            return None;
+        }
+        let mut buf = vec![0; READ_SIZE.min(usize::try_from(remaining).unwrap_or(READ_SIZE))];
+        match file.read(&mut buf).await {
+            Ok(0) => Some((
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "pcap job capture is shorter than expected",
+                )),
+                (file, 0),
+            )),
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok(bytes::Bytes::from(buf)), (file, remaining - n as u64)))
+            }
+            Err(err) => Some((Err(err), (file, 0))),
+        }
+    })
+}
+
+#[cfg(all(test, not(windows)))]
+mod test {
+    use super::*;
+    use crate::pcap::{PcapSource, SpoolConfig};
+    use crate::server::api::pcap::test::{context_with_event, matching_event, testdata};
+    use crate::server::pcap::jobs::{JobRegistry, JobSettings};
+    use crate::server::pcap::{PcapService, PcapSettings};
+    use crate::server::session::Session;
+
+    fn with_jobs(context: Arc<ServerContext>, directory: &std::path::Path) -> Arc<ServerContext> {
+        let mut context = Arc::into_inner(context).unwrap();
+        let mut service = PcapService::new(
+            PcapSettings::default(),
+            Some(PcapSource::Spool(SpoolConfig::new(testdata("spool"), None))),
+        );
+        service.jobs = JobRegistry::new(JobSettings {
+            directory: directory.to_path_buf(),
+            ..Default::default()
+        });
+        context.pcap = Arc::new(service);
+        Arc::new(context)
+    }
+
+    fn session() -> SessionExtractor {
+        SessionExtractor(Arc::new(Session::anonymous(None)))
+    }
+
+    fn remote() -> Extension<ConnectInfo<SocketAddr>> {
+        Extension(ConnectInfo("127.0.0.1:1".parse().unwrap()))
+    }
+
+    async fn download(
+        context: &Arc<ServerContext>,
+        id: &str,
+        headers: &[(HeaderName, &str)],
+    ) -> Response {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(name.clone(), HeaderValue::from_str(value).unwrap());
+        }
+        get_job_download(
+            State(context.clone()),
+            session(),
+            remote(),
+            map,
+            Path(id.to_string()),
+        )
+        .await
+    }
+
+    async fn body(response: Response) -> bytes::Bytes {
+        axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn ranges_are_parsed() {
+        assert_eq!(parse_range(None, 100), ByteRange::Full);
+        assert_eq!(
+            parse_range(Some("bytes=0-9"), 100),
+            ByteRange::Partial(0, 9)
+        );
+        assert_eq!(
+            parse_range(Some("bytes=90-"), 100),
+            ByteRange::Partial(90, 99)
+        );
+        assert_eq!(
+            parse_range(Some("bytes=90-1000"), 100),
+            ByteRange::Partial(90, 99)
+        );
+        assert_eq!(
+            parse_range(Some("bytes=-10"), 100),
+            ByteRange::Partial(90, 99)
+        );
+        assert_eq!(
+            parse_range(Some("bytes=-1000"), 100),
+            ByteRange::Partial(0, 99)
+        );
+        assert_eq!(
+            parse_range(Some("bytes=100-"), 100),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(parse_range(Some("bytes=-0"), 100), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=0-"), 0), ByteRange::Unsatisfiable);
+
+        // Malformed and multi-range requests get the whole capture.
+        assert_eq!(parse_range(Some("bytes=9-0"), 100), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=0-1,5-6"), 100), ByteRange::Full);
+        assert_eq!(parse_range(Some("items=0-1"), 100), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=a-b"), 100), ByteRange::Full);
+    }
+
+    #[tokio::test]
+    async fn finished_captures_download_by_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let context =
+            context_with_event(dir.path(), matching_event(), PcapSettings::default()).await;
+        let context = with_jobs(context, dir.path());
+
+        let (job, mut file) = context
+            .pcap
+            .jobs
+            .create("-", "capture.pcap".to_string(), false, None)
+            .unwrap();
+        let response = download(&context, &job.id, &[]).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let data = b"0123456789";
+        std::io::Write::write_all(&mut file, data).unwrap();
+        job.add_bytes(data.len() as u64);
+        let sha256 = crate::server::pcap::audit::sha256(data);
+        job.complete(false, Some(sha256.clone()));
+
+        let response = download(&context, &job.id, &[]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[ACCEPT_RANGES], "bytes");
+        assert_eq!(response.headers()[SHA256_HEADER], sha256.as_str());
+        assert_eq!(&body(response).await[..], data);
+
+        let response = download(&context, &job.id, &[(RANGE, "bytes=4-")]).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[CONTENT_RANGE], "bytes 4-9/10");
+        assert_eq!(response.headers()[CONTENT_LENGTH], "6");
+        assert_eq!(&body(response).await[..], b"456789");
+
+        // A stale If-Range gets the whole capture.
+        let response = download(
+            &context,
+            &job.id,
+            &[(RANGE, "bytes=4-"), (IF_RANGE, "\"other\"")],
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = download(&context, &job.id, &[(RANGE, "bytes=10-")]).await;
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[CONTENT_RANGE], "bytes */10");
+
+        let response = download(&context, "no-such-job", &[]).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn event_capture_is_exported_to_a_job() {
+        let dir = tempfile::tempdir().unwrap();
+        let context =
+            context_with_event(dir.path(), matching_event(), PcapSettings::default()).await;
+        let context = with_jobs(context, dir.path());
+
+        let events = context.datastore.events(Default::default()).await.unwrap();
+        let event_id = events["events"][0]["_id"].to_string();
+        let response = post_job(
+            State(context.clone()),
+            session(),
+            remote(),
+            HeaderMap::new(),
+            Json(PcapRequestBody {
+                event_id: Some(event_id),
+                ..Default::default()
+            }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let status: serde_json::Value = serde_json::from_slice(&body(response).await).unwrap();
+        let id = status["id"].as_str().unwrap().to_string();
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let status = loop {
+            let response = get_job(State(context.clone()), session(), Path(id.clone())).await;
+            let status: serde_json::Value = serde_json::from_slice(&body(response).await).unwrap();
+            if status["state"] != "queued" && status["state"] != "running" {
+                break status;
+            }
+            assert!(Instant::now() < deadline, "the job did not finish");
+            tokio::time::sleep(Duration::from_millis(25)).await;
+        };
+        assert_eq!(status["state"], "complete", "{status}");
+        assert_eq!(status["packets"], 4);
+        assert!(status["files_scanned"].as_u64().unwrap() > 0);
+
+        let response = download(&context, &id, &[]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let data = body(response).await;
+        assert_eq!(status["bytes"], data.len() as u64);
+        assert_eq!(
+            status["sha256"],
+            crate::server::pcap::audit::sha256(&data).as_str()
+        );
+
+        let response = delete_job(State(context.clone()), session(), Path(id.clone())).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = get_job(State(context.clone()), session(), Path(id)).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
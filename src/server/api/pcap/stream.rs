@@ -0,0 +1,243 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! `POST /api/pcap/stream`: the reassembled TCP conversation of a flow,
+//! to read it without downloading the capture.
+//!
+//! The request body is that of `POST /api/pcap`, and the capture is
+//! extracted through the regular routing and extraction path within the
+//! buffered download's limits. Both directions of each TCP connection
+//! are returned as data with hex and ASCII renderings, their segments in
+//! capture order, and for HTTP/1 the requests and responses.
+
+use std::fmt::Write;
+use std::net::SocketAddr;
+
+use axum::extract::{ConnectInfo, Extension, Json, State};
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
+use base64::prelude::*;
+
+use super::{PcapRequestBody, buffer_post_body, error, handle_inner, remote_addr};
+use crate::datetime::DateTime;
+use crate::pcap::reassembly::{self, Connection, Direction, HttpMessage, Stream};
+use crate::prelude::*;
+use crate::server::ServerContext;
+use crate::server::main::SessionExtractor;
+
+/// The most bytes of each direction given hex and ASCII renderings; the
+/// data itself is always returned whole.
+const RENDER_LIMIT: usize = 256 * 1024;
+
+/// `POST /api/pcap/stream`: extract a flow and reassemble it.
+pub(crate) async fn post_stream(
+    State(context): State<Arc<ServerContext>>,
+    SessionExtractor(session): SessionExtractor,
+    Extension(ConnectInfo(remote)): Extension<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Json(mut body): Json<PcapRequestBody>,
+) -> Response {
+    let user = session.username.clone().unwrap_or_else(|| "-".to_string());
+    let remote = remote_addr(&context, &headers, remote);
+
+    body.sensors = session.sensors.clone();
+    // Reassembly reads classic pcap.
+    body.format = None;
+    let response = match handle_inner(&context, &body, &user, remote, false, false, None).await {
+        Ok(response) | Err(response) => response,
+    };
+    let response = buffer_post_body(response, context.pcap.settings.max_bytes).await;
+    if response.status() != StatusCode::OK {
+        return response;
+    }
+    let truncated = response.headers().get("x-evebox-pcap-truncated").is_some();
+    let Ok(capture) = axum::body::to_bytes(response.into_body(), usize::MAX).await else {
+        return error(StatusCode::BAD_GATEWAY, "io", "pcap extraction failed");
+    };
+    let result = tokio::task::spawn_blocking(move || {
+        reassembly::reassemble(&capture).map(|reassembly| render(&reassembly, truncated))
+    })
+    .await;
+    match result {
+        Ok(Ok(json)) => Json(json).into_response(),
+        Ok(Err(message)) => error(StatusCode::BAD_GATEWAY, "format", &message),
+        Err(err) => {
+            error!("PCAP stream reassembly failed: {err}");
+            error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "internal",
+                "stream reassembly failed",
+            )
+        }
+    }
+}
+
+fn render(reassembly: &reassembly::Reassembly, truncated: bool) -> serde_json::Value {
+    let connections: Vec<serde_json::Value> = reassembly
+        .connections
+        .iter()
+        .map(render_connection)
+        .collect();
+    json!({
+        "truncated": truncated,
+        "packets": reassembly.packets,
+        "connections": connections,
+        "omitted": reassembly.omitted,
+    })
+}
+
+fn render_connection(connection: &Connection) -> serde_json::Value {
+    let segments: Vec<serde_json::Value> = connection
+        .chunks
+        .iter()
+        .map(|chunk| {
+            json!({
+                "direction": direction(chunk.direction),
+                "timestamp": timestamp(chunk.timestamp),
+                "offset": chunk.offset,
+                "length": chunk.len,
+            })
+        })
+        .collect();
+    let http = reassembly::http_exchanges(connection).map(|exchanges| {
+        exchanges
+            .iter()
+            .map(|exchange| {
+                json!({
+                    "request": render_http(&exchange.request),
+                    "response": exchange.response.as_ref().map(render_http),
+                })
+            })
+            .collect::<Vec<_>>()
+    });
+    json!({
+        "client": endpoint(connection.client),
+        "server": endpoint(connection.server),
+        "handshake": connection.handshake,
+        "first_seen": timestamp(connection.first_seen),
+        "last_seen": timestamp(connection.last_seen),
+        "to_server": render_stream(connection.stream(Direction::ToServer)),
+        "to_client": render_stream(connection.stream(Direction::ToClient)),
+        "segments": segments,
+        "http": http,
+    })
+}
+
+fn render_stream(stream: &Stream) -> serde_json::Value {
+    let rendered = &stream.data[..stream.data.len().min(RENDER_LIMIT)];
+    let gaps: Vec<serde_json::Value> = stream
+        .gaps
+        .iter()
+        .map(|(offset, missing)| json!({ "offset": offset, "missing": missing }))
+        .collect();
+    json!({
+        "bytes": stream.data.len(),
+        "packets": stream.packets,
+        "gaps": gaps,
+        "retransmitted": stream.retransmitted,
+        "fin": stream.fin,
+        "rst": stream.rst,
+        "data": BASE64_STANDARD.encode(&stream.data),
+        "hex": hexdump(rendered),
+        "ascii": ascii(rendered),
+        "rendered_truncated": rendered.len() < stream.data.len(),
+    })
+}
+
+fn render_http(message: &HttpMessage) -> serde_json::Value {
+    let headers: Vec<[&str; 2]> = message
+        .headers
+        .iter()
+        .map(|(name, value)| [name.as_str(), value.as_str()])
+        .collect();
+    json!({
+        "offset": message.offset,
+        "start_line": message.start_line,
+        "headers": headers,
+        "body_offset": message.body.0,
+        "body_length": message.body.1,
+        "complete": message.complete,
+    })
+}
+
+fn direction(direction: Direction) -> &'static str {
+    match direction {
+        Direction::ToServer => "to_server",
+        Direction::ToClient => "to_client",
+    }
+}
+
+fn endpoint((addr, port): reassembly::Endpoint) -> serde_json::Value {
+    json!({ "addr": addr.to_string(), "port": port })
+}
+
+fn timestamp(micros: u64) -> String {
+    DateTime::from_nanos(micros as i64 * 1000).to_rfc3339_utc()
+}
+
+/// A classic 16 bytes per line hex dump with offsets and an ASCII
+/// column.
+fn hexdump(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len() * 5);
+    for (line, bytes) in data.chunks(16).enumerate() {
+        let _ = write!(out, "{:08x} ", line * 16);
+        for i in 0..16 {
+            if i == 8 {
+                out.push(' ');
+            }
+            match bytes.get(i) {
+                Some(byte) => {
+                    let _ = write!(out, " {byte:02x}");
+                }
+                None => out.push_str("   "),
+            }
+        }
+        out.push_str("  |");
+        out.extend(bytes.iter().map(|byte| printable(*byte)));
+        out.push_str("|\n");
+    }
+    out
+}
+
+/// The data as text: line feeds and tabs are kept, carriage returns
+/// dropped, and other unprintable bytes shown as `.`.
+fn ascii(data: &[u8]) -> String {
+    data.iter()
+        .filter(|byte| **byte != b'\r')
+        .map(|byte| match byte {
+            b'\n' | b'\t' => char::from(*byte),
+            _ => printable(*byte),
+        })
+        .collect()
+}
+
+fn printable(byte: u8) -> char {
+    if byte.is_ascii_graphic() || byte == b' ' {
+        char::from(byte)
+    } else {
+        '.'
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hexdump_has_offsets_and_an_ascii_column() {
+        let dump = hexdump(b"GET / HTTP/1.1\r\n\x00");
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "00000000  47 45 54 20 2f 20 48 54  54 50 2f 31 2e 31 0d 0a  |GET / HTTP/1.1..|",
+                "00000010  00                                                |.|",
+            ]
+        );
+    }
+
+    #[test]
+    fn ascii_keeps_line_breaks() {
+        assert_eq!(ascii(b"a\r\nb\tc\x01"), "a\nb\tc.");
+    }
+}
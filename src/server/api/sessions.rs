@@ -0,0 +1,78 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! `/api/user/sessions`: a user's own login sessions.
+
+use axum::extract::{Extension, Json, Path};
+use axum::response::{IntoResponse, Response};
+
+use crate::datetime::DateTime;
+use crate::prelude::*;
+use crate::server::ServerContext;
+use crate::server::main::SessionExtractor;
+use crate::sqlite::configdb::SessionInfo;
+
+fn timestamp(seconds: Option<i64>) -> serde_json::Value {
+    seconds
+        .map(|seconds| DateTime::from_seconds(seconds).to_rfc3339_utc())
+        .into()
+}
+
+fn session_json(session: &SessionInfo, current: Option<&str>) -> serde_json::Value {
+    json!({
+        "id": session.id,
+        "created_at": timestamp(session.created_at),
+        "last_seen": timestamp(session.last_seen),
+        "expires_at": timestamp(Some(session.expires_at)),
+        "remote": session.remote,
+        "user_agent": session.user_agent,
+        "current": Some(session.token.as_str()) == current,
+    })
+}
+
+/// `GET /api/user/sessions`: the user's login sessions, marking the one
+/// making the request.
+pub(crate) async fn get_sessions(
+    SessionExtractor(session): SessionExtractor,
+    Extension(context): Extension<Arc<ServerContext>>,
+) -> Result<Response, AppError> {
+    let Some(username) = &session.username else {
+        return Err(AppError::BadRequest("sessions require a login".to_string()));
+    };
+    let sessions: Vec<serde_json::Value> = context
+        .configdb
+        .list_sessions(username)
+        .await?
+        .iter()
+        .map(|row| session_json(row, session.session_id.as_deref()))
+        .collect();
+    Ok(Json(sessions).into_response())
+}
+
+/// `DELETE /api/user/sessions/{id}`: log out one of the user's sessions.
+pub(crate) async fn delete_session(
+    SessionExtractor(session): SessionExtractor,
+    Extension(context): Extension<Arc<ServerContext>>,
+    Path(id): Path<i64>,
+) -> Result<Response, AppError> {
+    let Some(username) = &session.username else {
+        return Err(AppError::BadRequest("sessions require a login".to_string()));
+    };
+    if session.api_token.is_some() {
+        return Ok((
+            StatusCode::FORBIDDEN,
+            Json(json!({"error": "API tokens can't log out sessions"})),
+        )
+            .into_response());
+    }
+    let Some(token) = context.configdb.remove_user_session(username, id).await? else {
+        return Ok((
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": format!("no session with id {id}")})),
+        )
+            .into_response());
+    };
+    context.session_store.delete(&token);
+    info!("Session {} of user {:?} revoked", id, username);
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
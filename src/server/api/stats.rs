@@ -63,7 +63,7 @@ impl StatsAggQuery {
 }
 
 pub(crate) async fn agg(
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
     State(context): State<Arc<ServerContext>>,
     Form(form): Form<StatsAggQuery>,
 ) -> Result<impl IntoResponse, AppError> {
@@ -74,6 +74,7 @@ pub(crate) async fn agg(
     let params = eventrepo::StatsAggQueryParams {
         field: form.field.to_string(),
         sensor_name: form.sensor_name.clone(),
+        sensors: session.sensors.clone(),
         start_time,
         end_time,
     };
@@ -91,7 +92,7 @@ pub(crate) async fn agg(
 }
 
 pub(crate) async fn agg_differential(
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
     State(context): State<Arc<ServerContext>>,
     Form(form): Form<StatsAggQuery>,
 ) -> Result<impl IntoResponse, AppError> {
@@ -102,6 +103,7 @@ pub(crate) async fn agg_differential(
     let params = eventrepo::StatsAggQueryParams {
         field: form.field.to_string(),
         sensor_name: form.sensor_name.clone(),
+        sensors: session.sensors.clone(),
         start_time,
         end_time,
     };
@@ -120,10 +122,10 @@ pub(crate) async fn agg_differential(
 
 // Doesn't really belong in this module.
 pub(crate) async fn get_sensor_names(
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
     State(context): State<Arc<ServerContext>>,
 ) -> Result<impl IntoResponse, AppError> {
-    let sensors = if let EventRepo::Elastic(elastic) = &context.datastore {
+    let mut sensors = if let EventRepo::Elastic(elastic) = &context.datastore {
         elastic.get_sensors().await.map_err(|err| {
             error!("Failed to get sensors: {:?}", err);
             AppError::InternalServerError
@@ -136,6 +138,7 @@ pub(crate) async fn get_sensor_names(
     } else {
         return Ok((StatusCode::NOT_IMPLEMENTED, "").into_response());
     };
+    sensors.retain(|sensor| session.may_see_sensor(Some(sensor)));
 
     let response = json!({
         "data": sensors,
@@ -145,15 +148,18 @@ pub(crate) async fn get_sensor_names(
 }
 
 pub(crate) async fn earliest_timestamp(
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
     Extension(context): Extension<Arc<ServerContext>>,
 ) -> Result<impl IntoResponse, AppError> {
-    let ts = context.datastore.earliest_timestamp().await?;
+    let ts = context
+        .datastore
+        .earliest_timestamp(session.sensors.as_deref())
+        .await?;
     Ok(Json(ts))
 }
 
 pub(crate) async fn agg_by_sensor(
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
     State(context): State<Arc<ServerContext>>,
     Form(form): Form<StatsAggQuery>,
 ) -> Result<impl IntoResponse, AppError> {
@@ -166,6 +172,7 @@ pub(crate) async fn agg_by_sensor(
     let params = eventrepo::StatsAggQueryParams {
         field: form.field.to_string(),
         sensor_name: None, // We don't filter by sensor, we group by all sensors
+        sensors: session.sensors.clone(),
         start_time,
         end_time,
     };
@@ -190,7 +197,7 @@ pub(crate) async fn agg_by_sensor(
 }
 
 pub(crate) async fn agg_differential_by_sensor(
-    _session: SessionExtractor,
+    SessionExtractor(session): SessionExtractor,
     State(context): State<Arc<ServerContext>>,
     Form(form): Form<StatsAggQuery>,
 ) -> Result<impl IntoResponse, AppError> {
@@ -203,6 +210,7 @@ pub(crate) async fn agg_differential_by_sensor(
     let params = eventrepo::StatsAggQueryParams {
         field: form.field.to_string(),
         sensor_name: None, // We don't filter by sensor, we group by all sensors
+        sensors: session.sensors.clone(),
         start_time,
         end_time,
     };
@@ -0,0 +1,298 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! Suricata management on agent hosts, carried over the agent control
+//! channel.
+
+use std::net::SocketAddr;
+
+use axum::extract::{ConnectInfo, Extension, Json, Path, State};
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
+
+use crate::agent::protocol::SURICATA_COMMANDS;
+use crate::prelude::*;
+use crate::server::agent_jobs::{CommandError, StartError};
+use crate::server::api::pcap::{error, remote_addr};
+use crate::server::audit::{Auditor, Outcome};
+use crate::server::main::SessionExtractor;
+use crate::server::session::{Role, Session};
+use crate::server::{ServerConfig, ServerContext};
+
+/// `POST /api/agents/{name}/rules/update`: ask an agent to run its rule
+/// update command and reload Suricata. Returns the job, which is then
+/// polled with `GET`.
+pub(crate) async fn post_rules_update(
+    State(context): State<Arc<ServerContext>>,
+    SessionExtractor(session): SessionExtractor,
+    Extension(ConnectInfo(remote)): Extension<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    audit: Auditor,
+    Path(name): Path<String>,
+) -> Response {
+    let user = session.username.clone().unwrap_or_else(|| "-".to_string());
+    let remote = remote_addr(&context, &headers, remote);
+    match context
+        .agent_jobs
+        .start_rules_update(&context.agents, &name, &user, &remote)
+    {
+        Ok(job) => {
+            info!(
+                "rules-update: user={:?} remote={:?} agent={:?} job={} outcome=started",
+                user, remote, name, job.id
+            );
+            audit
+                .record(
+                    &session,
+                    "agent.rules-update",
+                    Some(&name),
+                    json!({"job": job.id}),
+                    Outcome::Ok,
+                )
+                .await;
+            (StatusCode::ACCEPTED, Json(job)).into_response()
+        }
+        Err(err) => {
+            let (status, code, message) = match err {
+                StartError::NotConnected => (
+                    StatusCode::NOT_FOUND,
+                    "agent_not_connected",
+                    "the agent is not connected",
+                ),
+                StartError::Unsupported => (
+                    StatusCode::BAD_REQUEST,
+                    "not_supported",
+                    "the agent has no rule update command configured",
+                ),
+                StartError::Busy => (
+                    StatusCode::CONFLICT,
+                    "busy",
+                    "a rule update is already running on this agent",
+                ),
+                StartError::Unavailable => (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "agent_unavailable",
+                    "the agent's control channel is unavailable",
+                ),
+            };
+            warn!(
+                "rules-update: user={:?} remote={:?} agent={:?} outcome={} message={:?}",
+                user, remote, name, code, message
+            );
+            audit
+                .record(
+                    &session,
+                    "agent.rules-update",
+                    Some(&name),
+                    json!({}),
+                    outcome(status, message),
+                )
+                .await;
+            error(status, code, message)
+        }
+    }
+}
+
+/// `GET /api/agents/{name}/rules/update`: the agent's latest rule update.
+pub(crate) async fn get_rules_update(
+    State(context): State<Arc<ServerContext>>,
+    SessionExtractor(session): SessionExtractor,
+    Path(name): Path<String>,
+) -> Response {
+    match context.agent_jobs.rules_update(&name) {
+        Some(job) if session.may_see_sensor(Some(&name)) => Json(job).into_response(),
+        _ => error(
+            StatusCode::NOT_FOUND,
+            "not_found",
+            "no rule update has been run on this agent",
+        ),
+    }
+}
+
+/// Commands that change Suricata's state. Unless
+/// `agents.suricata-commands` says otherwise they need an admin, and the
+/// read-only commands an analyst.
+const PRIVILEGED_COMMANDS: &[&str] = &["reload-rules"];
+
+/// The least role that may run `command`.
+fn command_role(config: &ServerConfig, command: &str) -> Role {
+    match config.suricata_command_roles.get(command) {
+        Some(role) => *role,
+        None if PRIVILEGED_COMMANDS.contains(&command) => Role::Admin,
+        None => Role::Analyst,
+    }
+}
+
+/// `POST /api/agents/{name}/suricata/{command}`: run an allow-listed
+/// command on the agent's Suricata command socket and return Suricata's
+/// response. An optional JSON object body carries the command's
+/// arguments, for example `{"iface": "eth0"}` for `iface-stat`.
+pub(crate) async fn post_suricata_command(
+    State(context): State<Arc<ServerContext>>,
+    SessionExtractor(session): SessionExtractor,
+    Extension(ConnectInfo(remote)): Extension<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    audit: Auditor,
+    Path((name, command)): Path<(String, String)>,
+    body: Option<Json<serde_json::Value>>,
+) -> Response {
+    let user = session.username.clone().unwrap_or_else(|| "-".to_string());
+    let remote = remote_addr(&context, &headers, remote);
+    let arguments = body.map(|Json(arguments)| arguments);
+    let result = run_suricata_command(&context, &session, &name, &command, &arguments).await;
+    let parameters = json!({"command": command, "arguments": arguments});
+    let arguments = arguments
+        .as_ref()
+        .map_or("-".to_string(), |value| value.to_string());
+    match result {
+        Ok(response) => {
+            info!(
+                "suricata-command: user={:?} remote={:?} agent={:?} command={:?} arguments={} outcome=ok",
+                user, remote, name, command, arguments,
+            );
+            audit
+                .record(
+                    &session,
+                    "agent.suricata-command",
+                    Some(&name),
+                    parameters,
+                    Outcome::Ok,
+                )
+                .await;
+            Json(json!({ "response": response })).into_response()
+        }
+        Err((status, code, message)) => {
+            warn!(
+                "suricata-command: user={:?} remote={:?} agent={:?} command={:?} arguments={} outcome={} message={:?}",
+                user, remote, name, command, arguments, code, message
+            );
+            audit
+                .record(
+                    &session,
+                    "agent.suricata-command",
+                    Some(&name),
+                    parameters,
+                    outcome(status, &message),
+                )
+                .await;
+            error(status, code, &message)
+        }
+    }
+}
+
+/// Suricata's response to the command, or the status, error code and
+/// message of why there is none.
+async fn run_suricata_command(
+    context: &ServerContext,
+    session: &Session,
+    name: &str,
+    command: &str,
+    arguments: &Option<serde_json::Value>,
+) -> Result<Option<serde_json::Value>, (StatusCode, &'static str, String)> {
+    // The agents of other sensors are not there for this user.
+    if !session.may_see_sensor(Some(name)) {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "agent_not_connected",
+            "the agent is not connected".to_string(),
+        ));
+    }
+    if !SURICATA_COMMANDS.contains(&command) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "unknown_command",
+            format!("supported commands are {}", SURICATA_COMMANDS.join(", ")),
+        ));
+    }
+    let role = command_role(&context.config, command);
+    if session.role < role {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "forbidden",
+            format!("{command} requires the {role} role"),
+        ));
+    }
+    if arguments
+        .as_ref()
+        .is_some_and(|arguments| !arguments.is_object())
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "invalid_arguments",
+            "command arguments must be a JSON object".to_string(),
+        ));
+    }
+
+    let result = context
+        .agent_jobs
+        .suricata_command(&context.agents, name, command, arguments.clone())
+        .await;
+    match result {
+        Ok(result) => match (result.response, result.error) {
+            (response, None) => Ok(response),
+            (_, Some(message)) => Err((StatusCode::BAD_GATEWAY, "command_failed", message)),
+        },
+        Err(err) => {
+            let (status, code, message) = match err {
+                CommandError::NotConnected => (
+                    StatusCode::NOT_FOUND,
+                    "agent_not_connected",
+                    "the agent is not connected",
+                ),
+                CommandError::Unsupported => (
+                    StatusCode::BAD_REQUEST,
+                    "not_supported",
+                    "the agent does not proxy Suricata commands",
+                ),
+                CommandError::Unavailable => (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "agent_unavailable",
+                    "the agent's control channel is unavailable",
+                ),
+                CommandError::Disconnected => (
+                    StatusCode::BAD_GATEWAY,
+                    "agent_disconnected",
+                    "the agent disconnected before replying",
+                ),
+                CommandError::TimedOut => (
+                    StatusCode::GATEWAY_TIMEOUT,
+                    "timeout",
+                    "the agent did not reply in time",
+                ),
+            };
+            Err((status, code, message.to_string()))
+        }
+    }
+}
+
+/// Refusals of the request are rejected, failures on the agent errors.
+fn outcome(status: StatusCode, message: &str) -> Outcome {
+    if status.is_client_error() {
+        Outcome::Rejected(message.to_string())
+    } else {
+        Outcome::Error(message.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_need_their_role() {
+        let mut config = ServerConfig::default();
+        assert_eq!(command_role(&config, "iface-stat"), Role::Analyst);
+        assert_eq!(command_role(&config, "dump-counters"), Role::Analyst);
+        assert_eq!(command_role(&config, "reload-rules"), Role::Admin);
+
+        config
+            .suricata_command_roles
+            .insert("reload-rules".to_string(), Role::Analyst);
+        config
+            .suricata_command_roles
+            .insert("dump-counters".to_string(), Role::Viewer);
+        assert_eq!(command_role(&config, "reload-rules"), Role::Analyst);
+        assert_eq!(command_role(&config, "dump-counters"), Role::Viewer);
+        assert_eq!(command_role(&config, "uptime"), Role::Analyst);
+    }
+}
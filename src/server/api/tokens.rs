@@ -0,0 +1,137 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! `/api/user/tokens`: a user's personal API tokens.
+
+use axum::extract::{Extension, Json, Path};
+use axum::response::{IntoResponse, Response};
+
+use super::admin::parse_key_duration;
+use crate::prelude::*;
+use crate::server::ServerContext;
+use crate::server::audit::{Auditor, Outcome};
+use crate::server::main::SessionExtractor;
+use crate::server::session::{Role, Session};
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct AddTokenRequest {
+    pub name: String,
+    /// The most the token may do; the user's own role when absent.
+    pub scope: Option<Role>,
+    /// How long the token is valid, such as `"90d"`. No expiry when
+    /// absent.
+    pub expires: Option<String>,
+}
+
+/// `GET /api/user/tokens`: the user's tokens, without the tokens
+/// themselves.
+pub(crate) async fn get_tokens(
+    SessionExtractor(session): SessionExtractor,
+    Extension(context): Extension<Arc<ServerContext>>,
+) -> Result<Response, AppError> {
+    let username = username(&session)?;
+    let tokens = context.configdb.list_api_tokens(username).await?;
+    Ok(Json(tokens).into_response())
+}
+
+/// `POST /api/user/tokens`: create a token. The response is the only
+/// time the token is shown.
+pub(crate) async fn add_token(
+    SessionExtractor(session): SessionExtractor,
+    Extension(context): Extension<Arc<ServerContext>>,
+    audit: Auditor,
+    Json(request): Json<AddTokenRequest>,
+) -> Result<Response, AppError> {
+    let username = username(&session)?;
+    let params = json!({"scope": request.scope, "expires": request.expires});
+    if session.api_token.is_some() {
+        let error = "API tokens can't create tokens";
+        audit
+            .record(
+                &session,
+                "token.create",
+                Some(&request.name),
+                params,
+                Outcome::Rejected(error.to_string()),
+            )
+            .await;
+        return Ok((StatusCode::FORBIDDEN, Json(json!({"error": error}))).into_response());
+    }
+    let scope = request.scope.unwrap_or(session.role);
+    if scope > session.role {
+        let error = format!("scope {scope} exceeds your role {}", session.role);
+        audit
+            .record(
+                &session,
+                "token.create",
+                Some(&request.name),
+                params,
+                Outcome::Rejected(error.clone()),
+            )
+            .await;
+        return Err(AppError::BadRequest(error));
+    }
+    let expires = parse_key_duration("expires", request.expires.as_deref())?;
+    let result = context
+        .configdb
+        .add_api_token(username, &request.name, scope, expires)
+        .await;
+    audit
+        .result(
+            &session,
+            "token.create",
+            Some(&request.name),
+            params,
+            &result,
+        )
+        .await;
+    let (row, token) = result?;
+    info!(
+        "api-token: user={:?} token={:?} scope={} expires={:?} outcome=created",
+        username, row.name, row.scope, row.expires_at
+    );
+    let mut response = serde_json::to_value(&row)?;
+    response["token"] = token.into();
+    Ok(Json(response).into_response())
+}
+
+/// `DELETE /api/user/tokens/{id}`: revoke a token.
+pub(crate) async fn delete_token(
+    SessionExtractor(session): SessionExtractor,
+    Extension(context): Extension<Arc<ServerContext>>,
+    audit: Auditor,
+    Path(id): Path<i64>,
+) -> Result<Response, AppError> {
+    let username = username(&session)?;
+    let target = id.to_string();
+    let result = context.configdb.remove_api_token(username, id).await;
+    let outcome = match &result {
+        Ok(true) => Outcome::Ok,
+        Ok(false) => Outcome::Rejected("token not found".to_string()),
+        Err(err) => Outcome::Error(err.to_string()),
+    };
+    audit
+        .record(&session, "token.revoke", Some(&target), json!({}), outcome)
+        .await;
+    if !result? {
+        return Ok((
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": format!("no API token with id {id}")})),
+        )
+            .into_response());
+    }
+    info!(
+        "api-token: user={:?} id={} via={:?} outcome=revoked",
+        username, id, session.api_token
+    );
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// Tokens belong to a user, so there are none without a login.
+fn username(session: &Session) -> Result<&str, AppError> {
+    session
+        .username
+        .as_deref()
+        .filter(|_| session.session_id.is_some())
+        .ok_or_else(|| AppError::BadRequest("API tokens require a login".to_string()))
+}
@@ -0,0 +1,348 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! `/api/user/totp`: a user's own TOTP second factor.
+
+use std::net::SocketAddr;
+
+use axum::extract::{ConnectInfo, Extension, Json};
+use axum::http::HeaderMap;
+use axum::http::header::RETRY_AFTER;
+use axum::response::{IntoResponse, Response};
+
+use crate::prelude::*;
+use crate::server::ServerContext;
+use crate::server::audit::{Auditor, Outcome};
+use crate::server::main::SessionExtractor;
+use crate::server::session::Session;
+use crate::server::totp;
+use crate::sqlite::configdb::LOCAL_SOURCE;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct CodeRequest {
+    /// A code from the user's authenticator app; a recovery code is also
+    /// accepted where TOTP is already enabled.
+    pub code: String,
+}
+
+/// `GET /api/user/totp`: whether the user has a second factor, and how
+/// many recovery codes they have left.
+pub(crate) async fn get_totp(
+    SessionExtractor(session): SessionExtractor,
+    Extension(context): Extension<Arc<ServerContext>>,
+) -> Result<Response, AppError> {
+    let username = match local_user(&context, &session).await? {
+        Ok(username) => username,
+        Err(response) => return Ok(response),
+    };
+    let enabled = context
+        .configdb
+        .get_totp(username)
+        .await?
+        .is_some_and(|totp| totp.enabled);
+    let recovery_codes = context.configdb.count_recovery_codes(username).await?;
+    Ok(Json(json!({
+        "enabled": enabled,
+        "required": totp::policy(&context.configdb).await.required,
+        "recovery_codes": recovery_codes,
+    }))
+    .into_response())
+}
+
+/// `POST /api/user/totp`: start enrollment, returning a new secret and
+/// its provisioning URI. Enrollment finishes when a code from it is
+/// confirmed.
+pub(crate) async fn begin(
+    SessionExtractor(session): SessionExtractor,
+    Extension(context): Extension<Arc<ServerContext>>,
+    audit: Auditor,
+) -> Result<Response, AppError> {
+    let username = match local_user(&context, &session).await? {
+        Ok(username) => username,
+        Err(response) => return Ok(response),
+    };
+    let secret = match totp::begin_enrollment(&context.configdb, username).await {
+        Ok(Some(secret)) => secret,
+        Ok(None) => {
+            return Ok(refuse(
+                &audit,
+                &session,
+                "totp.begin",
+                StatusCode::CONFLICT,
+                "TOTP is already enabled",
+            )
+            .await);
+        }
+        Err(err) => {
+            audit
+                .record(
+                    &session,
+                    "totp.begin",
+                    Some(username),
+                    json!({}),
+                    Outcome::Error(err.to_string()),
+                )
+                .await;
+            return Err(err.into());
+        }
+    };
+    audit
+        .record(
+            &session,
+            "totp.begin",
+            Some(username),
+            json!({}),
+            Outcome::Ok,
+        )
+        .await;
+    Ok(Json(json!({
+        "uri": totp::provisioning_uri(username, &secret),
+        "secret": secret,
+    }))
+    .into_response())
+}
+
+/// `POST /api/user/totp/confirm`: enable TOTP with a code from the new
+/// secret. The response is the only time the recovery codes are shown.
+pub(crate) async fn confirm(
+    SessionExtractor(session): SessionExtractor,
+    Extension(context): Extension<Arc<ServerContext>>,
+    audit: Auditor,
+    Json(request): Json<CodeRequest>,
+) -> Result<Response, AppError> {
+    let username = match local_user(&context, &session).await? {
+        Ok(username) => username,
+        Err(response) => return Ok(response),
+    };
+    let result = totp::confirm_enrollment(&context.configdb, username, &request.code).await;
+    let recovery_codes = match result {
+        Ok(Some(recovery_codes)) => recovery_codes,
+        Ok(None) => {
+            return Ok(refuse(
+                &audit,
+                &session,
+                "totp.enable",
+                StatusCode::BAD_REQUEST,
+                "invalid code",
+            )
+            .await);
+        }
+        Err(err) => {
+            audit
+                .record(
+                    &session,
+                    "totp.enable",
+                    Some(username),
+                    json!({}),
+                    Outcome::Error(err.to_string()),
+                )
+                .await;
+            return Err(err.into());
+        }
+    };
+    audit
+        .record(
+            &session,
+            "totp.enable",
+            Some(username),
+            json!({}),
+            Outcome::Ok,
+        )
+        .await;
+    info!("totp: user={:?} outcome=enabled", username);
+    Ok(Json(json!({"recovery_codes": recovery_codes})).into_response())
+}
+
+/// `POST /api/user/totp/recovery-codes`: replace the recovery codes.
+pub(crate) async fn regenerate_recovery_codes(
+    SessionExtractor(session): SessionExtractor,
+    Extension(context): Extension<Arc<ServerContext>>,
+    Extension(ConnectInfo(remote)): Extension<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    audit: Auditor,
+    Json(request): Json<CodeRequest>,
+) -> Result<Response, AppError> {
+    let username = match local_user(&context, &session).await? {
+        Ok(username) => username,
+        Err(response) => return Ok(response),
+    };
+    let client = crate::server::proxy::client_ip(&context.config, &headers, remote);
+    let action = "totp.recovery-codes";
+    if let Some(response) =
+        verify(&context, &audit, &session, action, &client, &request.code).await?
+    {
+        return Ok(response);
+    }
+    let result = totp::regenerate_recovery_codes(&context.configdb, username).await;
+    audit
+        .result(
+            &session,
+            "totp.recovery-codes",
+            Some(username),
+            json!({}),
+            &result,
+        )
+        .await;
+    let recovery_codes = result?;
+    info!("totp: user={:?} outcome=recovery-codes-replaced", username);
+    Ok(Json(json!({"recovery_codes": recovery_codes})).into_response())
+}
+
+/// `DELETE /api/user/totp`: remove the second factor, unless the policy
+/// requires one.
+pub(crate) async fn delete(
+    SessionExtractor(session): SessionExtractor,
+    Extension(context): Extension<Arc<ServerContext>>,
+    Extension(ConnectInfo(remote)): Extension<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    audit: Auditor,
+    Json(request): Json<CodeRequest>,
+) -> Result<Response, AppError> {
+    let username = match local_user(&context, &session).await? {
+        Ok(username) => username,
+        Err(response) => return Ok(response),
+    };
+    if totp::policy(&context.configdb).await.required {
+        return Ok(refuse(
+            &audit,
+            &session,
+            "totp.disable",
+            StatusCode::FORBIDDEN,
+            "two-factor authentication is required",
+        )
+        .await);
+    }
+    let client = crate::server::proxy::client_ip(&context.config, &headers, remote);
+    if let Some(response) = verify(
+        &context,
+        &audit,
+        &session,
+        "totp.disable",
+        &client,
+        &request.code,
+    )
+    .await?
+    {
+        return Ok(response);
+    }
+    let result = context.configdb.remove_totp(username).await;
+    audit
+        .result(&session, "totp.disable", Some(username), json!({}), &result)
+        .await;
+    result?;
+    info!("totp: user={:?} outcome=disabled", username);
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// Check a code from the user's authenticator app, or a recovery code,
+/// as a login would be: a wrong code counts as a failed login. The
+/// refusal of the change, or None when the code is accepted.
+async fn verify(
+    context: &ServerContext,
+    audit: &Auditor,
+    session: &Session,
+    action: &str,
+    client: &str,
+    code: &str,
+) -> Result<Option<Response>, AppError> {
+    let username = session.username.as_deref().unwrap_or_default();
+    if let Some(retry_after) = context.login_throttle.check(username, client) {
+        warn!(
+            "Two-factor change refused for username={} from {}: locked out",
+            username, client
+        );
+        let error = "too many failed logins, try again later";
+        let mut response =
+            refuse(audit, session, action, StatusCode::TOO_MANY_REQUESTS, error).await;
+        response.headers_mut().insert(
+            RETRY_AFTER,
+            retry_after.as_secs().max(1).to_string().parse().unwrap(),
+        );
+        return Ok(Some(response));
+    }
+    if !totp::verify_code(&context.configdb, username, code).await? {
+        warn!(
+            "Two-factor change for username={} from {}: invalid code",
+            username, client
+        );
+        context.login_throttle.failure(username, client);
+        let response = refuse(
+            audit,
+            session,
+            action,
+            StatusCode::BAD_REQUEST,
+            "invalid code",
+        )
+        .await;
+        return Ok(Some(response));
+    }
+    context.login_throttle.success(username);
+    Ok(None)
+}
+
+/// Only local users in a login session manage a second factor: external
+/// users have their provider's, and neither an API token nor basic
+/// authentication, which can't give a code, may change how the user logs
+/// in. Every request has a session ID, so a login session is one found
+/// in the database.
+async fn local_user<'a>(
+    context: &ServerContext,
+    session: &'a Session,
+) -> Result<Result<&'a str, Response>, AppError> {
+    let Some(username) = session.username.as_deref() else {
+        return Err(AppError::BadRequest(
+            "two-factor authentication requires a login".to_string(),
+        ));
+    };
+    if session.api_token.is_some() {
+        return Ok(Err(error(
+            StatusCode::FORBIDDEN,
+            "API tokens can't manage two-factor authentication",
+        )));
+    }
+    let login = match &session.session_id {
+        Some(session_id) => context
+            .configdb
+            .get_active_session(session_id, context.config.session.idle_timeout_secs())
+            .await?
+            .is_some_and(|(user, _)| user.username == username),
+        None => false,
+    };
+    if !login {
+        return Ok(Err(error(
+            StatusCode::FORBIDDEN,
+            "two-factor authentication is managed from a login session",
+        )));
+    }
+    if context.configdb.get_user_source(username).await?.as_deref() != Some(LOCAL_SOURCE) {
+        return Ok(Err(error(
+            StatusCode::FORBIDDEN,
+            "two-factor authentication is managed by your login provider",
+        )));
+    }
+    Ok(Ok(username))
+}
+
+/// Refuse a change to the user's second factor, recording the refusal.
+async fn refuse(
+    audit: &Auditor,
+    session: &Session,
+    action: &str,
+    status: StatusCode,
+    message: &str,
+) -> Response {
+    audit
+        .record(
+            session,
+            action,
+            session.username.as_deref(),
+            json!({}),
+            Outcome::Rejected(message.to_string()),
+        )
+        .await;
+    error(status, message)
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({"error": message}))).into_response()
+}
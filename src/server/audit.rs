@@ -0,0 +1,162 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! The audit trail of user and admin actions.
+//!
+//! Handlers that change something record who did it, to what, with which
+//! parameters, from where, and how it ended, through an [`Auditor`]
+//! extracted from the request. The record is written before the response
+//! is sent; a failed write is logged but doesn't fail the action. PCAP
+//! downloads have their own trail in `pcap::audit`, and both are pruned
+//! by the `audit.retention` setting.
+
+use std::convert::Infallible;
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::extract::{ConnectInfo, Extension, FromRequestParts};
+
+use crate::datetime::DateTime;
+use crate::prelude::*;
+use crate::server::ServerContext;
+use crate::server::session::Session;
+use crate::sqlite::configdb::{AuditRecord, ConfigDb};
+
+/// How long audit records are kept when `audit.retention` is unset.
+const DEFAULT_RETENTION: Duration = Duration::from_secs(365 * 86400);
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// How an audited action ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Ok,
+    /// Refused, such as for a missing object or the last admin.
+    Rejected(String),
+    /// Failed while being carried out.
+    Error(String),
+}
+
+impl Outcome {
+    pub(crate) fn of<T, E: Display>(result: &Result<T, E>) -> Self {
+        match result {
+            Ok(_) => Self::Ok,
+            Err(err) => Self::Error(err.to_string()),
+        }
+    }
+
+    fn parts(self) -> (&'static str, Option<String>) {
+        match self {
+            Self::Ok => ("ok", None),
+            Self::Rejected(message) => ("rejected", Some(message)),
+            Self::Error(message) => ("error", Some(message)),
+        }
+    }
+}
+
+/// Records actions of the request's user.
+pub(crate) struct Auditor {
+    configdb: Arc<ConfigDb>,
+    remote: String,
+}
+
+impl<S> FromRequestParts<S> for Auditor
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        req: &mut axum::http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let Extension(context) =
+            <Extension<Arc<ServerContext>> as FromRequestParts<S>>::from_request_parts(req, state)
+                .await
+                .unwrap();
+        let remote = match req.extensions.get::<ConnectInfo<SocketAddr>>() {
+            Some(ConnectInfo(peer)) => {
+                crate::server::proxy::client_addr(&context.config, &req.headers, *peer)
+            }
+            None => "-".to_string(),
+        };
+        Ok(Self {
+            configdb: context.configdb.clone(),
+            remote,
+        })
+    }
+}
+
+impl Auditor {
+    /// An auditor for actions outside a handler, such as authenticating a
+    /// request.
+    pub(crate) fn new(configdb: Arc<ConfigDb>, remote: String) -> Self {
+        Self { configdb, remote }
+    }
+
+    pub(crate) async fn record(
+        &self,
+        session: &Session,
+        action: &str,
+        target: Option<&str>,
+        parameters: serde_json::Value,
+        outcome: Outcome,
+    ) {
+        let (outcome, message) = outcome.parts();
+        let record = AuditRecord {
+            username: session.username.clone().unwrap_or_else(|| "-".to_string()),
+            api_token: session.api_token.clone(),
+            remote: self.remote.clone(),
+            action: action.to_string(),
+            target: target.map(str::to_string),
+            parameters: sqlx::types::Json(parameters),
+            outcome: outcome.to_string(),
+            message,
+        };
+        if let Err(err) = self.configdb.add_audit(&record).await {
+            error!("Failed to store audit record {:?}: {err}", record);
+        }
+    }
+
+    /// Record the action as ended by `result`.
+    pub(crate) async fn result<T, E: Display>(
+        &self,
+        session: &Session,
+        action: &str,
+        target: Option<&str>,
+        parameters: serde_json::Value,
+        result: &Result<T, E>,
+    ) {
+        self.record(session, action, target, parameters, Outcome::of(result))
+            .await
+    }
+}
+
+/// `audit.retention`: how long audit records are kept, `None` for
+/// forever.
+pub(crate) fn configure_retention(config: &crate::config::Config) -> Result<Option<Duration>> {
+    let retention = config
+        .get_duration("audit.retention")?
+        .unwrap_or(DEFAULT_RETENTION);
+    Ok(Some(retention).filter(|retention| !retention.is_zero()))
+}
+
+/// Prune records older than `retention` now and every hour after.
+pub(crate) fn start_retention_task(configdb: Arc<ConfigDb>, retention: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let before = DateTime::now().to_seconds() - retention.as_secs() as i64;
+            match configdb.prune_audit(before).await {
+                Ok(0) => {}
+                Ok(n) => info!(
+                    "Removed {n} audit records older than {}",
+                    humantime::format_duration(retention)
+                ),
+                Err(err) => error!("Failed to prune audit records: {err}"),
+            }
+        }
+    });
+}
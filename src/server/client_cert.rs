@@ -0,0 +1,157 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! TLS client certificates for agent authentication.
+//!
+//! With `agents.client-certificates.ca` set the TLS server asks every peer
+//! for a certificate and verifies any it receives against that CA, but does
+//! not require one: browsers connect as before. The verified certificate is
+//! attached to each request as a [`PeerCertificate`] extension, and the
+//! agent control channel maps it to an agent key by fingerprint or subject
+//! common name.
+
+use std::io;
+use std::path::Path;
+
+use futures::future::BoxFuture;
+use rustls::server::WebPkiClientVerifier;
+use rustls::server::danger::ClientCertVerifier;
+use rustls_pki_types::CertificateDer;
+use rustls_pki_types::pem::PemObject;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tower_http::add_extension::AddExtension;
+
+use axum_server::accept::Accept;
+use axum_server::tls_rustls::RustlsAcceptor;
+
+use crate::prelude::*;
+
+/// The identity of a verified client certificate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CertificateIdentity {
+    /// SHA-256 of the certificate, lowercase hex.
+    pub(crate) fingerprint: String,
+    /// Subject common name.
+    pub(crate) subject: Option<String>,
+}
+
+impl CertificateIdentity {
+    pub(crate) fn from_der(der: &[u8]) -> Self {
+        Self {
+            fingerprint: crate::cert::fingerprint(der),
+            subject: crate::cert::common_name(der),
+        }
+    }
+}
+
+/// Request extension carrying the connection's client certificate. Present
+/// on every request served over TLS, empty when the peer sent none.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PeerCertificate(pub(crate) Option<CertificateIdentity>);
+
+/// Verify client certificates against the CA bundle at `path` without
+/// requiring them.
+pub(crate) fn client_verifier(path: &Path) -> io::Result<Arc<dyn ClientCertVerifier>> {
+    let pem = std::fs::read(path)?;
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in CertificateDer::pem_slice_iter(&pem) {
+        let cert = cert.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        roots
+            .add(cert)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    }
+    if roots.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no certificates found in the client CA file",
+        ));
+    }
+    WebPkiClientVerifier::builder_with_provider(
+        Arc::new(roots),
+        Arc::new(rustls::crypto::ring::default_provider()),
+    )
+    .allow_unauthenticated()
+    .build()
+    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// A rustls acceptor that records the peer's certificate on the
+/// connection's requests.
+#[derive(Clone)]
+pub(crate) struct PeerCertificateAcceptor {
+    inner: RustlsAcceptor,
+}
+
+impl PeerCertificateAcceptor {
+    pub(crate) fn new(inner: RustlsAcceptor) -> Self {
+        Self { inner }
+    }
+}
+
+impl<I, S> Accept<I, S> for PeerCertificateAcceptor
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = <RustlsAcceptor as Accept<I, S>>::Stream;
+    type Service = AddExtension<S, PeerCertificate>;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let inner = self.inner.clone();
+        Box::pin(async move {
+            let (stream, service) = inner.accept(stream, service).await?;
+            let peer = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .map(|cert| CertificateIdentity::from_der(cert));
+            Ok((stream, AddExtension::new(service, PeerCertificate(peer))))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustls::server::danger::ClientCertVerified;
+    use rustls_pki_types::UnixTime;
+
+    use super::*;
+
+    #[test]
+    fn issued_certificates_verify_against_the_agent_ca() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca = crate::cert::get_or_create_agent_ca(dir.path()).unwrap();
+        let first = ca.issue("sensor-a", 30).unwrap();
+        // A CA reloaded from its key issues certificates that verify
+        // against the CA certificate written on creation.
+        let second = crate::cert::get_or_create_agent_ca(dir.path())
+            .unwrap()
+            .issue("sensor-b", 30)
+            .unwrap();
+
+        let verifier = client_verifier(&ca.cert_path).unwrap();
+        assert!(!verifier.client_auth_mandatory());
+        for issued in [&first, &second] {
+            let der = CertificateDer::from_pem_slice(issued.cert_pem.as_bytes()).unwrap();
+            assert!(matches!(
+                verifier.verify_client_cert(&der, &[], UnixTime::now()),
+                Ok(ClientCertVerified { .. })
+            ));
+        }
+
+        // A certificate from some other CA is refused.
+        let other = tempfile::tempdir().unwrap();
+        let stranger = crate::cert::get_or_create_agent_ca(other.path())
+            .unwrap()
+            .issue("sensor-a", 30)
+            .unwrap();
+        let der = CertificateDer::from_pem_slice(stranger.cert_pem.as_bytes()).unwrap();
+        assert!(
+            verifier
+                .verify_client_cert(&der, &[], UnixTime::now())
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,1584 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! LDAP login: checking a username and password with a bind against an
+//! LDAP server or Active Directory, either to a DN made from the username
+//! or to the DN found by a search, and mapping the user's groups to a
+//! role. Only the few LDAPv3 operations this needs are implemented: bind,
+//! search, StartTLS and unbind.
+//!
+//! The `ldap3` crate is not used as its TLS support needs either OpenSSL
+//! or an older rustls and ring beside the ones the server already uses.
+//! Responses are decoded defensively: lengths are bounded by the data and
+//! `MAX_MESSAGE`, and malformed input is an error, never a panic.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+use crate::prelude::*;
+use crate::server::session::{GroupRoles, Role};
+use crate::sqlite::configdb::{ConfigDb, ConfigDbError, User};
+
+/// The source of users provisioned through LDAP.
+pub(crate) const SOURCE: &str = "ldap";
+
+/// The StartTLS extended operation.
+const STARTTLS_OID: &str = "1.3.6.1.4.1.1466.20037";
+
+/// The largest message accepted from the server.
+const MAX_MESSAGE: usize = 1 << 20;
+
+// LDAP result codes.
+const SUCCESS: i64 = 0;
+const SIZE_LIMIT_EXCEEDED: i64 = 4;
+const INVALID_CREDENTIALS: i64 = 49;
+
+/// `authentication.ldap` in the configuration file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct LdapConfig {
+    /// `ldaps://host[:port]`, or `ldap://host[:port]` with `starttls`.
+    pub url: String,
+    #[serde(default)]
+    pub starttls: bool,
+    /// A PEM file of CA certificates trusted in addition to the system's.
+    pub ca_certificate: Option<PathBuf>,
+    #[serde(default)]
+    pub no_check_certificate: bool,
+    /// Bind directly to this DN, with `{username}` replaced, instead of
+    /// searching for the user first. For Active Directory this may be
+    /// `{username}@example.com`.
+    pub user_dn: Option<String>,
+    /// The account to search with; anonymous without it.
+    pub bind_dn: Option<String>,
+    pub bind_password: Option<String>,
+    /// Where users are searched for.
+    pub base_dn: Option<String>,
+    /// Finds the user's entry, with `{username}` replaced.
+    #[serde(default = "default_user_filter")]
+    pub user_filter: String,
+    /// The user entry's attribute listing their groups.
+    #[serde(default = "default_group_attribute")]
+    pub group_attribute: String,
+    /// Also search here for groups the user is a member of, for servers
+    /// without `memberOf`.
+    pub group_base_dn: Option<String>,
+    /// Finds the user's groups, with `{username}` and `{dn}` replaced.
+    #[serde(default = "default_group_filter")]
+    pub group_filter: String,
+    /// `roles`, `default-role` and `auto-provision`; the groups are DNs,
+    /// compared without case.
+    #[serde(flatten)]
+    pub group_roles: GroupRoles,
+    /// Check local users when the LDAP login fails.
+    #[serde(default)]
+    pub local_fallback: bool,
+    /// Seconds a login may take at the server.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_user_filter() -> String {
+    "(uid={username})".to_string()
+}
+
+fn default_group_attribute() -> String {
+    "memberOf".to_string()
+}
+
+fn default_group_filter() -> String {
+    "(member={dn})".to_string()
+}
+
+fn default_timeout() -> u64 {
+    10
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum LdapError {
+    #[error("invalid credentials")]
+    InvalidCredentials,
+    #[error("{0}")]
+    Denied(String),
+    #[error("LDAP server: {0}")]
+    Server(String),
+    #[error("configuration database: {0}")]
+    ConfigDb(#[from] ConfigDbError),
+}
+
+impl From<std::io::Error> for LdapError {
+    fn from(err: std::io::Error) -> Self {
+        LdapError::Server(err.to_string())
+    }
+}
+
+/// Who the server says logged in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Identity {
+    pub username: String,
+    pub dn: String,
+    pub groups: Vec<String>,
+}
+
+pub(crate) struct Ldap {
+    config: LdapConfig,
+    host: String,
+    port: u16,
+    ldaps: bool,
+    tls: Option<TlsConnector>,
+}
+
+impl Ldap {
+    pub(crate) fn new(mut config: LdapConfig) -> Result<Self> {
+        let url = reqwest::Url::parse(&config.url)
+            .with_context(|| format!("invalid LDAP URL {:?}", config.url))?;
+        let ldaps = match url.scheme() {
+            "ldaps" => true,
+            "ldap" => false,
+            scheme => bail!("unsupported LDAP URL scheme {scheme:?}, expected ldap or ldaps"),
+        };
+        if ldaps && config.starttls {
+            bail!("starttls can't be used with an ldaps URL");
+        }
+        let Some(host) = url.host_str() else {
+            bail!("LDAP URL {:?} has no host", config.url);
+        };
+        let host = host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let port = url.port().unwrap_or(if ldaps { 636 } else { 389 });
+
+        if config.user_dn.is_none() && config.base_dn.is_none() {
+            bail!("one of user-dn or base-dn is required");
+        }
+        if config.bind_dn.is_some() != config.bind_password.is_some() {
+            bail!("bind-dn and bind-password must be set together");
+        }
+        // Check the filters parse, with a username needing escapes.
+        let test = "a*(b)\\";
+        Filter::parse(&template(&config.user_filter, test, ""))
+            .map_err(|err| anyhow!("invalid user-filter: {err}"))?;
+        Filter::parse(&template(&config.group_filter, test, test))
+            .map_err(|err| anyhow!("invalid group-filter: {err}"))?;
+
+        let tls = if ldaps || config.starttls {
+            Some(tls_connector(&config)?)
+        } else {
+            None
+        };
+        // DNs compare without case.
+        config.group_roles.roles = config
+            .group_roles
+            .roles
+            .into_iter()
+            .map(|(group, role)| (group.to_lowercase(), role))
+            .collect();
+        Ok(Self {
+            config,
+            host,
+            port,
+            ldaps,
+            tls,
+        })
+    }
+
+    pub(crate) fn local_fallback(&self) -> bool {
+        self.config.local_fallback
+    }
+
+    /// Check a username and password, returning the user with their
+    /// groups.
+    pub(crate) async fn authenticate(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<Identity, LdapError> {
+        // A bind without a password is an anonymous bind, which servers
+        // allow.
+        if username.is_empty() || password.is_empty() {
+            return Err(LdapError::InvalidCredentials);
+        }
+        let timeout = Duration::from_secs(self.config.timeout);
+        tokio::time::timeout(timeout, self.lookup(username, password))
+            .await
+            .map_err(|_| LdapError::Server(format!("no response in {timeout:?}")))?
+    }
+
+    async fn lookup(&self, username: &str, password: &str) -> Result<Identity, LdapError> {
+        let mut conn = self.connect().await?;
+        let entry = if let Some(user_dn) = &self.config.user_dn {
+            let dn = user_dn.replace("{username}", &escape_dn_value(username));
+            conn.bind(&dn, password).await?;
+            match &self.config.base_dn {
+                Some(base_dn) => self.find_user(&mut conn, base_dn, username).await?,
+                None => conn
+                    .search(
+                        &dn,
+                        Scope::Base,
+                        &Filter::Present("objectClass".to_string()),
+                        &[&self.config.group_attribute],
+                        1,
+                    )
+                    .await?
+                    .pop()
+                    .ok_or_else(|| LdapError::Denied(format!("no entry for {dn:?}")))?,
+            }
+        } else {
+            if let (Some(bind_dn), Some(bind_password)) =
+                (&self.config.bind_dn, &self.config.bind_password)
+            {
+                conn.bind(bind_dn, bind_password)
+                    .await
+                    .map_err(|err| match err {
+                        LdapError::InvalidCredentials => {
+                            LdapError::Server("invalid bind-dn credentials".to_string())
+                        }
+                        err => err,
+                    })?;
+            }
+            let base_dn = self.config.base_dn.as_deref().unwrap_or_default();
+            self.find_user(&mut conn, base_dn, username).await?
+        };
+
+        let mut groups = entry.values(&self.config.group_attribute);
+        if let Some(group_base_dn) = &self.config.group_base_dn {
+            let filter = Filter::parse(&template(&self.config.group_filter, username, &entry.dn))
+                .map_err(LdapError::Server)?;
+            for group in conn
+                .search(group_base_dn, Scope::Subtree, &filter, &["1.1"], 0)
+                .await?
+            {
+                groups.push(group.dn);
+            }
+        }
+
+        // Found by a search, the password is checked last.
+        if self.config.user_dn.is_none() {
+            conn.bind(&entry.dn, password).await?;
+        }
+        conn.unbind().await;
+
+        Ok(Identity {
+            username: username.to_lowercase(),
+            dn: entry.dn,
+            groups,
+        })
+    }
+
+    async fn find_user(
+        &self,
+        conn: &mut Connection,
+        base_dn: &str,
+        username: &str,
+    ) -> Result<Entry, LdapError> {
+        let filter = Filter::parse(&template(&self.config.user_filter, username, ""))
+            .map_err(LdapError::Server)?;
+        let mut entries = conn
+            .search(
+                base_dn,
+                Scope::Subtree,
+                &filter,
+                &[&self.config.group_attribute],
+                2,
+            )
+            .await?;
+        match entries.len() {
+            1 => Ok(entries.remove(0)),
+            0 => Err(LdapError::Denied(format!("user {username:?} not found"))),
+            _ => Err(LdapError::Denied(format!(
+                "user {username:?} matches more than one entry"
+            ))),
+        }
+    }
+
+    async fn connect(&self) -> Result<Connection, LdapError> {
+        let mut tcp = TcpStream::connect((self.host.as_str(), self.port)).await?;
+        let Some(tls) = &self.tls else {
+            return Ok(Connection::new(Box::new(tcp)));
+        };
+        let mut message_id = 1;
+        if !self.ldaps {
+            write_message(&mut tcp, message_id, &extended_request(STARTTLS_OID)).await?;
+            let (tag, body) = read_response(&mut tcp, message_id).await?;
+            if tag != 0x78 {
+                return Err(unexpected(tag));
+            }
+            check_result(&body).map_err(|(code, message)| {
+                LdapError::Server(format!("StartTLS failed with result {code}: {message}"))
+            })?;
+            message_id += 1;
+        }
+        let name = ServerName::try_from(self.host.clone())
+            .map_err(|err| LdapError::Server(err.to_string()))?;
+        let stream = tls.connect(name, tcp).await?;
+        let mut conn = Connection::new(Box::new(stream));
+        conn.message_id = message_id;
+        Ok(conn)
+    }
+
+    /// The highest role of the groups, or the default role.
+    pub(crate) fn role_for(&self, groups: &[String]) -> Option<Role> {
+        let groups: Vec<String> = groups.iter().map(|group| group.to_lowercase()).collect();
+        self.config.group_roles.role_for(&groups)
+    }
+
+    /// The user for an identity, created on first login when
+    /// auto-provisioning. The role follows the user's groups on every
+    /// login. Local users can't be logged into through LDAP.
+    pub(crate) async fn provision(
+        &self,
+        configdb: &ConfigDb,
+        identity: &Identity,
+    ) -> Result<User, LdapError> {
+        let username = &identity.username;
+        let role = self.role_for(&identity.groups).ok_or_else(|| {
+            LdapError::Denied(format!(
+                "no role for user {username:?} with groups {:?}",
+                identity.groups
+            ))
+        })?;
+        configdb
+            .provision_external_user(
+                username,
+                role,
+                SOURCE,
+                self.config.group_roles.auto_provision,
+            )
+            .await
+            .map_err(|err| match err {
+                ConfigDbError::UserSourceMismatch(..) | ConfigDbError::NoUser(_) => {
+                    LdapError::Denied(err.to_string())
+                }
+                err => err.into(),
+            })
+    }
+
+    /// Authenticate and provision.
+    pub(crate) async fn login(
+        &self,
+        configdb: &ConfigDb,
+        username: &str,
+        password: &str,
+    ) -> Result<User, LdapError> {
+        let identity = self.authenticate(username, password).await?;
+        debug!(
+            "LDAP user {:?} is {:?} in groups {:?}",
+            identity.username, identity.dn, identity.groups
+        );
+        self.provision(configdb, &identity).await
+    }
+}
+
+fn tls_connector(config: &LdapConfig) -> Result<TlsConnector> {
+    let builder = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()?;
+    let tls = if config.no_check_certificate {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(crate::agent::tls::NoVerifier::new()))
+            .with_no_client_auth()
+    } else {
+        let mut roots = rustls::RootCertStore::empty();
+        let native = rustls_native_certs::load_native_certs();
+        for err in &native.errors {
+            warn!("ldap: failed to load a native root certificate: {err}");
+        }
+        roots.add_parsable_certificates(native.certs);
+        if let Some(path) = &config.ca_certificate {
+            let pem = std::fs::read(path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            for cert in CertificateDer::pem_slice_iter(&pem) {
+                let cert =
+                    cert.with_context(|| format!("invalid certificate file {}", path.display()))?;
+                roots.add(cert)?;
+            }
+        }
+        builder.with_root_certificates(roots).with_no_client_auth()
+    };
+    Ok(TlsConnector::from(Arc::new(tls)))
+}
+
+/// Replace `{username}` and `{dn}` in a filter with their escaped
+/// values, in one pass so a value is never itself substituted into.
+fn template(filter: &str, username: &str, dn: &str) -> String {
+    let mut out = String::with_capacity(filter.len());
+    let mut rest = filter;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("{username}") {
+            out.push_str(&escape_filter_value(username));
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("{dn}") {
+            out.push_str(&escape_filter_value(dn));
+            rest = after;
+        } else {
+            out.push('{');
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Escape a value for a search filter (RFC 4515).
+fn escape_filter_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '*' | '(' | ')' | '\\' | '\0' => escaped.push_str(&format!("\\{:02x}", c as u8)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escape a value for a DN attribute value (RFC 4514).
+fn escape_dn_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    let last = value.chars().count().saturating_sub(1);
+    for (i, c) in value.chars().enumerate() {
+        match c {
+            '"' | '+' | ',' | ';' | '<' | '>' | '\\' | '=' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '#' if i == 0 => escaped.push_str("\\#"),
+            ' ' if i == 0 || i == last => escaped.push_str("\\ "),
+            '\0' => escaped.push_str("\\00"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Scope {
+    Base = 0,
+    Subtree = 2,
+}
+
+/// A search filter, as much of RFC 4515 as finding users and groups
+/// needs: no extensible matches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Filter {
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+    Equal(String, Vec<u8>),
+    Substrings {
+        attribute: String,
+        initial: Option<Vec<u8>>,
+        any: Vec<Vec<u8>>,
+        last: Option<Vec<u8>>,
+    },
+    GreaterOrEqual(String, Vec<u8>),
+    LessOrEqual(String, Vec<u8>),
+    Present(String),
+    Approx(String, Vec<u8>),
+}
+
+impl Filter {
+    fn parse(filter: &str) -> Result<Filter, String> {
+        let mut parser = FilterParser {
+            input: filter.trim().as_bytes(),
+            pos: 0,
+        };
+        let filter = parser.filter()?;
+        if parser.pos != parser.input.len() {
+            return Err(format!("trailing characters at offset {}", parser.pos));
+        }
+        Ok(filter)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        fn assertion(tag: u8, attribute: &str, value: &[u8]) -> Vec<u8> {
+            tlv(
+                tag,
+                &[octets(0x04, attribute.as_bytes()), octets(0x04, value)].concat(),
+            )
+        }
+        match self {
+            Filter::And(filters) => tlv(
+                0xa0,
+                &filters.iter().flat_map(Filter::encode).collect::<Vec<_>>(),
+            ),
+            Filter::Or(filters) => tlv(
+                0xa1,
+                &filters.iter().flat_map(Filter::encode).collect::<Vec<_>>(),
+            ),
+            Filter::Not(filter) => tlv(0xa2, &filter.encode()),
+            Filter::Equal(attribute, value) => assertion(0xa3, attribute, value),
+            Filter::Substrings {
+                attribute,
+                initial,
+                any,
+                last,
+            } => {
+                let mut substrings = Vec::new();
+                if let Some(initial) = initial {
+                    substrings.extend(octets(0x80, initial));
+                }
+                for any in any {
+                    substrings.extend(octets(0x81, any));
+                }
+                if let Some(last) = last {
+                    substrings.extend(octets(0x82, last));
+                }
+                tlv(
+                    0xa4,
+                    &[octets(0x04, attribute.as_bytes()), tlv(0x30, &substrings)].concat(),
+                )
+            }
+            Filter::GreaterOrEqual(attribute, value) => assertion(0xa5, attribute, value),
+            Filter::LessOrEqual(attribute, value) => assertion(0xa6, attribute, value),
+            Filter::Present(attribute) => octets(0x87, attribute.as_bytes()),
+            Filter::Approx(attribute, value) => assertion(0xa8, attribute, value),
+        }
+    }
+}
+
+struct FilterParser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl FilterParser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), String> {
+        if self.peek() != Some(c) {
+            return Err(format!("expected {:?} at offset {}", c as char, self.pos));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn filter(&mut self) -> Result<Filter, String> {
+        self.expect(b'(')?;
+        let filter = match self.peek() {
+            Some(b'&') => {
+                self.pos += 1;
+                Filter::And(self.list()?)
+            }
+            Some(b'|') => {
+                self.pos += 1;
+                Filter::Or(self.list()?)
+            }
+            Some(b'!') => {
+                self.pos += 1;
+                Filter::Not(Box::new(self.filter()?))
+            }
+            _ => self.item()?,
+        };
+        self.expect(b')')?;
+        Ok(filter)
+    }
+
+    fn list(&mut self) -> Result<Vec<Filter>, String> {
+        let mut filters = Vec::new();
+        while self.peek() == Some(b'(') {
+            filters.push(self.filter()?);
+        }
+        if filters.is_empty() {
+            return Err(format!("empty filter list at offset {}", self.pos));
+        }
+        Ok(filters)
+    }
+
+    fn item(&mut self) -> Result<Filter, String> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphanumeric() || c == b'-' || c == b'.' || c == b';' {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        if self.pos == start {
+            return Err(format!("expected an attribute at offset {start}"));
+        }
+        let attribute = String::from_utf8_lossy(&self.input[start..self.pos]).to_string();
+        let op = match (self.peek(), self.input.get(self.pos + 1)) {
+            (Some(b'='), _) => {
+                self.pos += 1;
+                b'='
+            }
+            (Some(c @ (b'~' | b'>' | b'<')), Some(b'=')) => {
+                self.pos += 2;
+                c
+            }
+            _ => return Err(format!("unsupported filter at offset {}", self.pos)),
+        };
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c == b')' || c == b'(' {
+                break;
+            }
+            self.pos += 1;
+        }
+        let raw = &self.input[start..self.pos];
+        let filter = match op {
+            b'~' => Filter::Approx(attribute, unescape(raw)?),
+            b'>' => Filter::GreaterOrEqual(attribute, unescape(raw)?),
+            b'<' => Filter::LessOrEqual(attribute, unescape(raw)?),
+            _ if raw == b"*" => Filter::Present(attribute),
+            _ if raw.contains(&b'*') => {
+                let parts = raw
+                    .split(|c| *c == b'*')
+                    .map(unescape)
+                    .collect::<Result<Vec<_>, _>>()?;
+                let (first, rest) = parts.split_first().unwrap();
+                let (last, any) = rest.split_last().unwrap();
+                Filter::Substrings {
+                    attribute,
+                    initial: (!first.is_empty()).then(|| first.clone()),
+                    any: any.iter().filter(|any| !any.is_empty()).cloned().collect(),
+                    last: (!last.is_empty()).then(|| last.clone()),
+                }
+            }
+            _ => Filter::Equal(attribute, unescape(raw)?),
+        };
+        Ok(filter)
+    }
+}
+
+/// Decode the `\XX` escapes of a filter value.
+fn unescape(raw: &[u8]) -> Result<Vec<u8>, String> {
+    let mut value = Vec::with_capacity(raw.len());
+    let mut i = 0;
+    while i < raw.len() {
+        if raw[i] == b'\\' {
+            let byte = raw
+                .get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| "invalid escape in filter value".to_string())?;
+            value.push(byte);
+            i += 3;
+        } else {
+            value.push(raw[i]);
+            i += 1;
+        }
+    }
+    Ok(value)
+}
+
+/// A search result.
+#[derive(Debug, Default)]
+struct Entry {
+    dn: String,
+    /// By lowercase attribute name.
+    attributes: HashMap<String, Vec<Vec<u8>>>,
+}
+
+impl Entry {
+    fn values(&self, attribute: &str) -> Vec<String> {
+        self.attributes
+            .get(&attribute.to_lowercase())
+            .into_iter()
+            .flatten()
+            .map(|value| String::from_utf8_lossy(value).to_string())
+            .collect()
+    }
+
+    fn decode(body: &[u8]) -> Result<Entry, LdapError> {
+        let mut reader = Reader::new(body);
+        let dn = String::from_utf8_lossy(reader.expect(0x04)?).to_string();
+        let mut attributes: HashMap<String, Vec<Vec<u8>>> = HashMap::new();
+        let mut list = Reader::new(reader.expect(0x30)?);
+        while !list.is_empty() {
+            let mut attribute = Reader::new(list.expect(0x30)?);
+            let name = String::from_utf8_lossy(attribute.expect(0x04)?).to_lowercase();
+            let mut values = Reader::new(attribute.expect(0x31)?);
+            let entry = attributes.entry(name).or_default();
+            while !values.is_empty() {
+                entry.push(values.expect(0x04)?.to_vec());
+            }
+        }
+        Ok(Entry { dn, attributes })
+    }
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+struct Connection {
+    stream: Box<dyn Stream>,
+    message_id: i64,
+}
+
+impl Connection {
+    fn new(stream: Box<dyn Stream>) -> Self {
+        Self {
+            stream,
+            message_id: 0,
+        }
+    }
+
+    async fn send(&mut self, op: &[u8]) -> Result<i64, LdapError> {
+        self.message_id += 1;
+        write_message(&mut self.stream, self.message_id, op).await?;
+        Ok(self.message_id)
+    }
+
+    async fn bind(&mut self, dn: &str, password: &str) -> Result<(), LdapError> {
+        let op = tlv(
+            0x60,
+            &[
+                integer(0x02, 3),
+                octets(0x04, dn.as_bytes()),
+                octets(0x80, password.as_bytes()),
+            ]
+            .concat(),
+        );
+        let id = self.send(&op).await?;
+        let (tag, body) = read_response(&mut self.stream, id).await?;
+        if tag != 0x61 {
+            return Err(unexpected(tag));
+        }
+        match check_result(&body) {
+            Ok(()) => Ok(()),
+            Err((INVALID_CREDENTIALS, _)) => Err(LdapError::InvalidCredentials),
+            Err((code, message)) => Err(LdapError::Server(format!(
+                "bind failed with result {code}: {message}"
+            ))),
+        }
+    }
+
+    async fn search(
+        &mut self,
+        base: &str,
+        scope: Scope,
+        filter: &Filter,
+        attributes: &[&str],
+        size_limit: i64,
+    ) -> Result<Vec<Entry>, LdapError> {
+        let attributes: Vec<u8> = attributes
+            .iter()
+            .flat_map(|attribute| octets(0x04, attribute.as_bytes()))
+            .collect();
+        let op = tlv(
+            0x63,
+            &[
+                octets(0x04, base.as_bytes()),
+                integer(0x0a, scope as i64),
+                integer(0x0a, 0),
+                integer(0x02, size_limit),
+                integer(0x02, 0),
+                vec![0x01, 0x01, 0x00],
+                filter.encode(),
+                tlv(0x30, &attributes),
+            ]
+            .concat(),
+        );
+        let id = self.send(&op).await?;
+        let mut entries = Vec::new();
+        loop {
+            let (tag, body) = read_response(&mut self.stream, id).await?;
+            match tag {
+                // SearchResultEntry.
+                0x64 => entries.push(Entry::decode(&body)?),
+                // SearchResultReference, referrals aren't followed.
+                0x73 => {}
+                // SearchResultDone.
+                0x65 => {
+                    return match check_result(&body) {
+                        Ok(()) | Err((SIZE_LIMIT_EXCEEDED, _)) => Ok(entries),
+                        Err((code, message)) => Err(LdapError::Server(format!(
+                            "search of {base:?} failed with result {code}: {message}"
+                        ))),
+                    };
+                }
+                tag => return Err(unexpected(tag)),
+            }
+        }
+    }
+
+    async fn unbind(mut self) {
+        let _ = self.send(&[0x42, 0x00]).await;
+        let _ = self.stream.shutdown().await;
+    }
+}
+
+fn extended_request(oid: &str) -> Vec<u8> {
+    tlv(0x77, &octets(0x80, oid.as_bytes()))
+}
+
+fn unexpected(tag: u8) -> LdapError {
+    LdapError::Server(format!("unexpected response of type {tag:#04x}"))
+}
+
+/// The result code and diagnostic message of an unsuccessful LDAPResult.
+fn check_result(body: &[u8]) -> Result<(), (i64, String)> {
+    fn parse(body: &[u8]) -> Result<(i64, String), LdapError> {
+        let mut reader = Reader::new(body);
+        let code = decode_integer(reader.expect(0x0a)?)?;
+        let _matched_dn = reader.expect(0x04)?;
+        let message = String::from_utf8_lossy(reader.expect(0x04)?).to_string();
+        Ok((code, message))
+    }
+    match parse(body) {
+        Ok((SUCCESS, _)) => Ok(()),
+        Ok(result) => Err(result),
+        Err(err) => Err((-1, err.to_string())),
+    }
+}
+
+async fn write_message<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    id: i64,
+    op: &[u8],
+) -> Result<(), LdapError> {
+    let message = tlv(0x30, &[integer(0x02, id), op.to_vec()].concat());
+    stream.write_all(&message).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Read the response to a request, as its operation's tag and contents.
+async fn read_response<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    id: i64,
+) -> Result<(u8, Vec<u8>), LdapError> {
+    let message = read_message(stream).await?;
+    let mut reader = Reader::new(&message);
+    let message_id = decode_integer(reader.expect(0x02)?)?;
+    let (tag, body) = reader.next()?;
+    if message_id != id {
+        // Message ID 0 is a notice from the server, usually that it's
+        // closing the connection.
+        let message = match check_result(body) {
+            Err((_, message)) => message,
+            Ok(()) => String::new(),
+        };
+        return Err(LdapError::Server(format!(
+            "unexpected message {message_id}: {message}"
+        )));
+    }
+    Ok((tag, body.to_vec()))
+}
+
+/// Read an LDAPMessage, returning its contents.
+async fn read_message<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Vec<u8>, LdapError> {
+    let mut header = [0; 2];
+    stream.read_exact(&mut header).await?;
+    if header[0] != 0x30 {
+        return Err(LdapError::Server(format!(
+            "invalid message type {:#04x}",
+            header[0]
+        )));
+    }
+    let len = if header[1] & 0x80 == 0 {
+        header[1] as usize
+    } else {
+        let count = (header[1] & 0x7f) as usize;
+        if count == 0 || count > 4 {
+            return Err(LdapError::Server("invalid message length".to_string()));
+        }
+        let mut bytes = [0; 4];
+        stream.read_exact(&mut bytes[4 - count..]).await?;
+        u32::from_be_bytes(bytes) as usize
+    };
+    if len > MAX_MESSAGE {
+        return Err(LdapError::Server(format!(
+            "message of {len} bytes too large"
+        )));
+    }
+    let mut message = vec![0; len];
+    stream.read_exact(&mut message).await?;
+    Ok(message)
+}
+
+/// Encode a BER tag, length and contents.
+fn tlv(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![tag];
+    let len = contents.len();
+    if len < 0x80 {
+        encoded.push(len as u8);
+    } else {
+        let bytes = (len as u32).to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count();
+        encoded.push(0x80 | (4 - skip) as u8);
+        encoded.extend(&bytes[skip..]);
+    }
+    encoded.extend(contents);
+    encoded
+}
+
+fn octets(tag: u8, value: &[u8]) -> Vec<u8> {
+    tlv(tag, value)
+}
+
+/// Encode a non-negative integer or enumerated value.
+fn integer(tag: u8, value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let mut start = bytes.iter().take_while(|b| **b == 0).count().min(7);
+    if bytes[start] & 0x80 != 0 {
+        start -= 1;
+    }
+    tlv(tag, &bytes[start..])
+}
+
+fn decode_integer(bytes: &[u8]) -> Result<i64, LdapError> {
+    if bytes.is_empty() || bytes.len() > 8 {
+        return Err(LdapError::Server("invalid integer".to_string()));
+    }
+    let negative = bytes[0] & 0x80 != 0;
+    Ok(bytes
+        .iter()
+        .fold(if negative { -1 } else { 0 }, |value, b| {
+            (value << 8) | *b as i64
+        }))
+}
+
+/// Reads the BER elements of a constructed value.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn next(&mut self) -> Result<(u8, &'a [u8]), LdapError> {
+        let invalid = || LdapError::Server("truncated or invalid response".to_string());
+        let (&tag, rest) = self.data.split_first().ok_or_else(invalid)?;
+        let (&first, mut rest) = rest.split_first().ok_or_else(invalid)?;
+        let len = if first & 0x80 == 0 {
+            first as usize
+        } else {
+            let count = (first & 0x7f) as usize;
+            if count == 0 || count > 4 || rest.len() < count {
+                return Err(invalid());
+            }
+            let len = rest[..count]
+                .iter()
+                .fold(0usize, |len, b| (len << 8) | *b as usize);
+            rest = &rest[count..];
+            len
+        };
+        if rest.len() < len {
+            return Err(invalid());
+        }
+        let (contents, rest) = rest.split_at(len);
+        self.data = rest;
+        Ok((tag, contents))
+    }
+
+    fn expect(&mut self, tag: u8) -> Result<&'a [u8], LdapError> {
+        match self.next()? {
+            (found, contents) if found == tag => Ok(contents),
+            (found, _) => Err(LdapError::Server(format!(
+                "expected type {tag:#04x}, found {found:#04x}"
+            ))),
+        }
+    }
+}
+
+pub(crate) fn configure(config: &crate::config::Config) -> Result<Option<Ldap>> {
+    if !config.get_bool("authentication.ldap.enabled")? {
+        return Ok(None);
+    }
+    let Some(ldap) = config.get_value::<LdapConfig>("authentication.ldap")? else {
+        bail!("authentication.ldap is enabled but not configured");
+    };
+    if ldap.url.starts_with("ldap://") && !ldap.starttls {
+        warn!(
+            "LDAP login to {} is not encrypted, passwords are sent in the clear",
+            ldap.url
+        );
+    }
+    info!(
+        "LDAP login enabled with {}, local users {}",
+        ldap.url,
+        if ldap.local_fallback {
+            "as a fallback"
+        } else {
+            "disabled"
+        }
+    );
+    Ok(Some(Ldap::new(ldap).context("authentication.ldap")?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+    use tokio_rustls::TlsAcceptor;
+
+    const ALICE: &str = "uid=alice,ou=people,dc=example,dc=com";
+    const BOB: &str = "uid=bob,ou=people,dc=example,dc=com";
+    const SERVICE: &str = "cn=evebox,dc=example,dc=com";
+    const SOC: &str = "cn=soc,ou=groups,dc=example,dc=com";
+    const ADMINS: &str = "cn=admins,ou=groups,dc=example,dc=com";
+
+    struct MockEntry {
+        dn: &'static str,
+        password: Option<&'static str>,
+        attributes: Vec<(&'static str, Vec<&'static str>)>,
+    }
+
+    /// A directory of a service account, alice in soc by `memberOf` and
+    /// bob in admins by the group's `member`.
+    fn directory() -> Vec<MockEntry> {
+        vec![
+            MockEntry {
+                dn: SERVICE,
+                password: Some("service-secret"),
+                attributes: vec![("objectClass", vec!["person"])],
+            },
+            MockEntry {
+                dn: ALICE,
+                password: Some("secret"),
+                attributes: vec![
+                    ("objectClass", vec!["person"]),
+                    ("uid", vec!["alice"]),
+                    ("memberOf", vec![SOC]),
+                ],
+            },
+            MockEntry {
+                dn: BOB,
+                password: Some("hunter2"),
+                attributes: vec![("objectClass", vec!["person"]), ("uid", vec!["bob"])],
+            },
+            MockEntry {
+                dn: ADMINS,
+                password: None,
+                attributes: vec![("objectClass", vec!["groupOfNames"]), ("member", vec![BOB])],
+            },
+        ]
+    }
+
+    impl MockEntry {
+        fn values(&self, attribute: &[u8]) -> Vec<&'static str> {
+            self.attributes
+                .iter()
+                .filter(|(name, _)| name.as_bytes().eq_ignore_ascii_case(attribute))
+                .flat_map(|(_, values)| values.iter().copied())
+                .collect()
+        }
+
+        /// Evaluate the subset of filters the tests use.
+        fn matches(&self, tag: u8, filter: &[u8]) -> bool {
+            let mut reader = Reader::new(filter);
+            match tag {
+                0xa0 | 0xa1 => {
+                    let mut results = Vec::new();
+                    while !reader.is_empty() {
+                        let (tag, filter) = reader.next().unwrap();
+                        results.push(self.matches(tag, filter));
+                    }
+                    if tag == 0xa0 {
+                        results.iter().all(|r| *r)
+                    } else {
+                        results.iter().any(|r| *r)
+                    }
+                }
+                0xa2 => {
+                    let (tag, filter) = reader.next().unwrap();
+                    !self.matches(tag, filter)
+                }
+                0xa3 => {
+                    let attribute = reader.expect(0x04).unwrap();
+                    let value = reader.expect(0x04).unwrap();
+                    self.values(attribute)
+                        .iter()
+                        .any(|v| v.as_bytes().eq_ignore_ascii_case(value))
+                }
+                0x87 => !self.values(filter).is_empty(),
+                tag => panic!("unsupported filter {tag:#04x}"),
+            }
+        }
+
+        fn encode(&self) -> Vec<u8> {
+            let attributes: Vec<u8> = self
+                .attributes
+                .iter()
+                .flat_map(|(name, values)| {
+                    let values: Vec<u8> = values
+                        .iter()
+                        .flat_map(|value| octets(0x04, value.as_bytes()))
+                        .collect();
+                    tlv(
+                        0x30,
+                        &[octets(0x04, name.as_bytes()), tlv(0x31, &values)].concat(),
+                    )
+                })
+                .collect();
+            tlv(
+                0x64,
+                &[octets(0x04, self.dn.as_bytes()), tlv(0x30, &attributes)].concat(),
+            )
+        }
+    }
+
+    fn result(tag: u8, code: i64) -> Vec<u8> {
+        tlv(
+            tag,
+            &[integer(0x0a, code), octets(0x04, b""), octets(0x04, b"")].concat(),
+        )
+    }
+
+    struct MockServer {
+        url: String,
+        ca: PathBuf,
+        /// Connections accepted.
+        connections: Mutex<usize>,
+        _dir: tempfile::TempDir,
+    }
+
+    /// Serve the directory, over TLS from the start when `ldaps`.
+    async fn serve_directory(ldaps: bool) -> Arc<MockServer> {
+        let dir = tempfile::tempdir().unwrap();
+        let (cert, key) = crate::cert::create_and_write_cert(dir.path()).unwrap();
+        let certs = CertificateDer::pem_file_iter(&cert)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let key = rustls::pki_types::PrivateKeyDer::from_pem_file(&key).unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(
+            rustls::ServerConfig::builder_with_provider(Arc::new(
+                rustls::crypto::ring::default_provider(),
+            ))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .unwrap(),
+        ));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let scheme = if ldaps { "ldaps" } else { "ldap" };
+        let server = Arc::new(MockServer {
+            url: format!(
+                "{scheme}://localhost:{}",
+                listener.local_addr().unwrap().port()
+            ),
+            ca: cert,
+            connections: Mutex::new(0),
+            _dir: dir,
+        });
+        let counter = server.clone();
+        tokio::spawn(async move {
+            loop {
+                let (tcp, _) = listener.accept().await.unwrap();
+                *counter.connections.lock().unwrap() += 1;
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let stream: Box<dyn Stream> = if ldaps {
+                        Box::new(acceptor.accept(tcp).await.unwrap())
+                    } else {
+                        Box::new(tcp)
+                    };
+                    serve_connection(stream, acceptor).await;
+                });
+            }
+        });
+        server
+    }
+
+    async fn serve_connection(mut stream: Box<dyn Stream>, acceptor: TlsAcceptor) {
+        let directory = directory();
+        while let Ok(message) = read_message(&mut stream).await {
+            let mut reader = Reader::new(&message);
+            let id = decode_integer(reader.expect(0x02).unwrap()).unwrap();
+            let (tag, body) = reader.next().unwrap();
+            let mut body = Reader::new(body);
+            match tag {
+                0x60 => {
+                    body.expect(0x02).unwrap();
+                    let dn = body.expect(0x04).unwrap();
+                    let password = body.expect(0x80).unwrap();
+                    let valid = (dn.is_empty() && password.is_empty())
+                        || directory.iter().any(|entry| {
+                            entry.dn.as_bytes() == dn
+                                && entry.password.map(str::as_bytes) == Some(password)
+                        });
+                    let code = if valid { SUCCESS } else { INVALID_CREDENTIALS };
+                    write_message(&mut stream, id, &result(0x61, code))
+                        .await
+                        .unwrap();
+                }
+                0x63 => {
+                    let base = String::from_utf8(body.expect(0x04).unwrap().to_vec()).unwrap();
+                    let scope = decode_integer(body.expect(0x0a).unwrap()).unwrap();
+                    for _ in 0..3 {
+                        body.next().unwrap();
+                    }
+                    body.expect(0x01).unwrap();
+                    let (tag, filter) = body.next().unwrap();
+                    for entry in &directory {
+                        let in_scope = if scope == Scope::Base as i64 {
+                            entry.dn == base
+                        } else {
+                            entry.dn.ends_with(&base)
+                        };
+                        if in_scope && entry.matches(tag, filter) {
+                            write_message(&mut stream, id, &entry.encode())
+                                .await
+                                .unwrap();
+                        }
+                    }
+                    write_message(&mut stream, id, &result(0x65, SUCCESS))
+                        .await
+                        .unwrap();
+                }
+                0x77 => {
+                    assert_eq!(body.expect(0x80).unwrap(), STARTTLS_OID.as_bytes());
+                    write_message(&mut stream, id, &result(0x78, SUCCESS))
+                        .await
+                        .unwrap();
+                    let tls = acceptor.accept(stream).await.unwrap();
+                    return Box::pin(serve_connection(Box::new(tls), acceptor)).await;
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn client(server: &MockServer, config: &str) -> Ldap {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let config: LdapConfig = serde_yaml::from_str(&format!(
+            r#"
+            url: {}
+            ca-certificate: {}
+            roles:
+              CN=SOC,ou=groups,dc=example,dc=com: analyst
+              cn=admins,ou=groups,dc=example,dc=com: admin
+            {config}
+            "#,
+            server.url,
+            server.ca.display(),
+        ))
+        .unwrap();
+        Ldap::new(config).unwrap()
+    }
+
+    const SEARCH: &str = r#"
+            bind-dn: cn=evebox,dc=example,dc=com
+            bind-password: service-secret
+            base-dn: ou=people,dc=example,dc=com
+            group-base-dn: ou=groups,dc=example,dc=com
+            starttls: true"#;
+
+    #[test]
+    fn filters_are_parsed_and_encoded() {
+        assert_eq!(
+            Filter::parse("(&(objectClass=person)(|(uid=a\\2a)(cn=b*c*))(!(mail=*)))").unwrap(),
+            Filter::And(vec![
+                Filter::Equal("objectClass".to_string(), b"person".to_vec()),
+                Filter::Or(vec![
+                    Filter::Equal("uid".to_string(), b"a*".to_vec()),
+                    Filter::Substrings {
+                        attribute: "cn".to_string(),
+                        initial: Some(b"b".to_vec()),
+                        any: vec![b"c".to_vec()],
+                        last: None,
+                    },
+                ]),
+                Filter::Not(Box::new(Filter::Present("mail".to_string()))),
+            ])
+        );
+        assert_eq!(
+            Filter::parse("(uid=x)").unwrap().encode(),
+            [0xa3, 0x08, 0x04, 0x03, b'u', b'i', b'd', 0x04, 0x01, b'x']
+        );
+        assert!(Filter::parse("(uid=x").is_err());
+        assert!(Filter::parse("(uid=x))").is_err());
+        assert!(Filter::parse("(&)").is_err());
+        assert!(Filter::parse("(uid:dn:=x)").is_err());
+
+        assert_eq!(escape_filter_value("a*(b)\\"), "a\\2a\\28b\\29\\5c");
+        assert_eq!(escape_dn_value(" a,b=c#"), "\\ a\\,b\\=c#");
+        assert_eq!(escape_dn_value("#a "), "\\#a\\ ");
+        assert_eq!(
+            Filter::parse(&template("(uid={username})", "*)(uid=*", "")).unwrap(),
+            Filter::Equal("uid".to_string(), b"*)(uid=*".to_vec())
+        );
+        // A value is never substituted into.
+        assert_eq!(
+            template("(&(member={dn})(cn={username}))", "{dn}", "cn=a"),
+            "(&(member=cn=a)(cn={dn}))"
+        );
+        assert_eq!(template("({x}={username})", "a", ""), "({x}=a)");
+    }
+
+    #[test]
+    fn ber_integers_round_trip() {
+        for value in [0, 1, 127, 128, 255, 256, 65535, i32::MAX as i64] {
+            let encoded = integer(0x02, value);
+            let mut reader = Reader::new(&encoded);
+            assert_eq!(decode_integer(reader.expect(0x02).unwrap()).unwrap(), value);
+        }
+        assert_eq!(integer(0x02, 128), [0x02, 0x02, 0x00, 0x80]);
+        let long = tlv(0x04, &[0; 300]);
+        assert_eq!(long[..4], [0x04, 0x82, 0x01, 0x2c]);
+    }
+
+    #[tokio::test]
+    async fn users_log_in_by_search_then_bind() {
+        let server = serve_directory(false).await;
+        let ldap = client(&server, SEARCH);
+
+        let alice = ldap.authenticate("Alice", "secret").await.unwrap();
+        assert_eq!(alice.username, "alice");
+        assert_eq!(alice.dn, ALICE);
+        assert_eq!(alice.groups, [SOC]);
+        assert_eq!(ldap.role_for(&alice.groups), Some(Role::Analyst));
+
+        // Groups found by their members.
+        let bob = ldap.authenticate("bob", "hunter2").await.unwrap();
+        assert_eq!(bob.groups, [ADMINS]);
+        assert_eq!(ldap.role_for(&bob.groups), Some(Role::Admin));
+
+        assert!(matches!(
+            ldap.authenticate("alice", "wrong").await,
+            Err(LdapError::InvalidCredentials)
+        ));
+        assert!(matches!(
+            ldap.authenticate("*", "secret").await,
+            Err(LdapError::Denied(_))
+        ));
+
+        // No anonymous bind with an empty password.
+        let connections = *server.connections.lock().unwrap();
+        assert!(matches!(
+            ldap.authenticate("alice", "").await,
+            Err(LdapError::InvalidCredentials)
+        ));
+        assert_eq!(*server.connections.lock().unwrap(), connections);
+
+        // A wrong service password isn't the user's failure.
+        let ldap = client(&server, &SEARCH.replace("service-secret", "wrong-secret"));
+        assert!(matches!(
+            ldap.authenticate("alice", "secret").await,
+            Err(LdapError::Server(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn users_log_in_by_simple_bind_over_tls() {
+        for ldaps in [false, true] {
+            let server = serve_directory(ldaps).await;
+            let ldap = client(
+                &server,
+                &format!(
+                    "user-dn: uid={{username}},ou=people,dc=example,dc=com\n            starttls: {}",
+                    !ldaps
+                ),
+            );
+            let alice = ldap.authenticate("alice", "secret").await.unwrap();
+            assert_eq!(alice.groups, [SOC]);
+            assert!(matches!(
+                ldap.authenticate("alice", "wrong").await,
+                Err(LdapError::InvalidCredentials)
+            ));
+            assert!(matches!(
+                ldap.authenticate("carol", "secret").await,
+                Err(LdapError::InvalidCredentials)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn users_are_provisioned_from_the_directory() {
+        let server = serve_directory(false).await;
+        let ldap = client(&server, SEARCH);
+        let dir = tempfile::tempdir().unwrap();
+        let configdb = crate::sqlite::configdb::open(Some(&dir.path().join("config.sqlite")))
+            .await
+            .unwrap();
+
+        let user = ldap.login(&configdb, "alice", "secret").await.unwrap();
+        assert_eq!(user.role, Role::Analyst);
+        assert_eq!(
+            configdb.get_user_source("alice").await.unwrap().as_deref(),
+            Some(SOURCE)
+        );
+        assert!(
+            configdb
+                .get_user_by_username_password("alice", "secret")
+                .await
+                .is_err()
+        );
+
+        // Local users can't be logged into through the directory.
+        configdb
+            .add_user("bob", "local-secret", Role::Viewer)
+            .await
+            .unwrap();
+        assert!(matches!(
+            ldap.login(&configdb, "bob", "hunter2").await,
+            Err(LdapError::Denied(_))
+        ));
+    }
+
+    #[test]
+    fn configuration_is_checked() {
+        let config = |yaml: &str| {
+            let _ = rustls::crypto::ring::default_provider().install_default();
+            Ldap::new(serde_yaml::from_str::<LdapConfig>(yaml).unwrap())
+        };
+        assert!(config("url: ldap://localhost\nbase-dn: dc=example,dc=com").is_ok());
+        assert!(config("url: http://localhost\nbase-dn: dc=example,dc=com").is_err());
+        assert!(config("url: ldaps://localhost\nstarttls: true\nbase-dn: dc=x").is_err());
+        assert!(config("url: ldap://localhost").is_err());
+        assert!(config("url: ldap://localhost\nbase-dn: dc=x\nbind-dn: cn=x").is_err());
+        assert!(
+            config("url: ldap://localhost\nbase-dn: dc=x\nuser-filter: (uid={username}").is_err()
+        );
+    }
+
+    /// A xorshift generator, for repeatable malformed input.
+    fn pseudo_random(state: &mut u64) -> usize {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state as usize
+    }
+
+    /// Read every element of `data`, and of those constructed, as the
+    /// decoders do.
+    fn walk(data: &[u8], depth: usize) -> Result<(), LdapError> {
+        let mut reader = Reader::new(data);
+        while !reader.is_empty() {
+            let (tag, contents) = reader.next()?;
+            if tag & 0x20 != 0 && depth < 8 {
+                walk(contents, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn ber_reader_rejects_malformed_elements() {
+        for (data, why) in [
+            (&[][..], "empty"),
+            (&[0x04][..], "no length"),
+            (&[0x04, 0x80][..], "indefinite length"),
+            (
+                &[0x04, 0x85, 0, 0, 0, 0, 1][..],
+                "length of more than 4 bytes",
+            ),
+            (&[0x04, 0x82, 0x01][..], "truncated length"),
+            (
+                &[0x04, 0x84, 0xff, 0xff, 0xff, 0xff, 0x00][..],
+                "length past the data",
+            ),
+            (&[0x04, 0x03, b'a', b'b'][..], "truncated contents"),
+        ] {
+            assert!(Reader::new(data).next().is_err(), "{why}");
+        }
+        assert!(Reader::new(&[0x04, 0x01, b'a']).expect(0x30).is_err());
+        assert!(decode_integer(&[]).is_err());
+        assert!(decode_integer(&[1; 9]).is_err());
+        // An attribute that isn't a sequence.
+        assert!(Entry::decode(&[0x04, 0x00, 0x30, 0x02, 0x04, 0x00]).is_err());
+        assert!(check_result(&[0x0a, 0x01]).is_err());
+    }
+
+    #[tokio::test]
+    async fn malformed_messages_are_rejected() {
+        for (data, why) in [
+            (&[][..], "empty"),
+            (&[0x04, 0x00][..], "not a sequence"),
+            (&[0x30][..], "truncated header"),
+            (&[0x30, 0x80][..], "indefinite length"),
+            (
+                &[0x30, 0x85, 0, 0, 0, 0, 1][..],
+                "length of more than 4 bytes",
+            ),
+            (&[0x30, 0x82, 0x01][..], "truncated length"),
+            (
+                &[0x30, 0x84, 0x7f, 0xff, 0xff, 0xff][..],
+                "larger than the limit",
+            ),
+            (&[0x30, 0x05, 0x02, 0x01][..], "truncated contents"),
+        ] {
+            let mut stream = data;
+            assert!(read_message(&mut stream).await.is_err(), "{why}");
+        }
+        // A well formed message with the wrong ID, or no operation.
+        let mut stream = &tlv(0x30, &integer(0x02, 2))[..];
+        assert!(read_response(&mut stream, 2).await.is_err());
+        let message = tlv(0x30, &[integer(0x02, 0), tlv(0x78, &[])].concat());
+        let mut stream = &message[..];
+        assert!(read_response(&mut stream, 1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn mutated_responses_are_errors_not_panics() {
+        let attribute = tlv(
+            0x30,
+            &[
+                octets(0x04, b"memberOf"),
+                tlv(0x31, &octets(0x04, b"cn=admins,dc=example,dc=com")),
+            ]
+            .concat(),
+        );
+        let entry = tlv(
+            0x64,
+            &[
+                octets(0x04, b"uid=alice,dc=example,dc=com"),
+                tlv(0x30, &attribute),
+            ]
+            .concat(),
+        );
+        let done = tlv(
+            0x65,
+            &[integer(0x0a, 0), octets(0x04, b""), octets(0x04, b"")].concat(),
+        );
+        let mut state = 0x2545_f491_4f6c_dd1d;
+        for original in [entry, done] {
+            let original = tlv(0x30, &[integer(0x02, 1), original].concat());
+            for _ in 0..5000 {
+                let mut message = original.clone();
+                for _ in 0..=pseudo_random(&mut state) % 3 {
+                    let at = pseudo_random(&mut state) % message.len().max(1);
+                    let byte = pseudo_random(&mut state) as u8;
+                    match pseudo_random(&mut state) % 3 {
+                        0 if !message.is_empty() => message[at] = byte,
+                        1 => message.truncate(at),
+                        _ => message.insert(at.min(message.len()), byte),
+                    }
+                }
+                let _ = walk(&message, 0);
+                let mut stream = &message[..];
+                if let Ok((tag, body)) = read_response(&mut stream, 1).await {
+                    let _ = walk(&body, 0);
+                    let _ = check_result(&body);
+                    if tag == 0x64 {
+                        let _ = Entry::decode(&body);
+                    }
+                }
+            }
+        }
+        for _ in 0..5000 {
+            let len = pseudo_random(&mut state) % 64;
+            let data: Vec<u8> = (0..len).map(|_| pseudo_random(&mut state) as u8).collect();
+            let _ = walk(&data, 0);
+            let _ = Entry::decode(&data);
+            let mut stream = &data[..];
+            let _ = read_response(&mut stream, 1).await;
+        }
+    }
+}
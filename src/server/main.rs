@@ -11,7 +11,12 @@ use crate::eve::socket;
 use crate::eve::watcher::EvePatternWatcher;
 use crate::eventrepo::EventRepo;
 use crate::server::api;
-use crate::server::session::Session;
+use crate::server::api::login::check_password;
+use crate::server::audit::{Auditor, Outcome};
+use crate::server::client_cert;
+use crate::server::proxy::{self, ProxyAuthError};
+use crate::server::session::{RequiredRole, Role, Session};
+use crate::server::totp;
 use crate::sqlite::configdb::{self, ConfigDb};
 use crate::sqlite::connection::init_event_db;
 use crate::sqlite::{self};
@@ -31,8 +36,8 @@ use std::convert::Infallible;
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use tower_http::trace::DefaultOnResponse;
 use tower_http::trace::TraceLayer;
-use tower_http::trace::{DefaultMakeSpan, DefaultOnResponse};
 use tracing::{Level, debug, error, info, warn};
 
 fn load_event_services(filename: &str) -> Result<serde_json::Value> {
@@ -98,7 +103,41 @@ pub async fn main(args: &clap::ArgMatches) -> Result<()> {
         || config.get_bool("no-check-certificate")?;
     server_config.http_request_logging = config.get_bool("http.request-logging")?;
     server_config.http_reverse_proxy = config.get_bool("http.reverse-proxy")?;
+    server_config.trusted_proxies = crate::server::proxy::trusted_proxies(&config)?;
+    if !server_config.trusted_proxies.is_empty() && !server_config.http_reverse_proxy {
+        bail!("http.trusted-proxies requires http.reverse-proxy");
+    }
     server_config.agents_allow_unauthenticated = config.get_bool("agents.allow-unauthenticated")?;
+    server_config.suricata_command_roles = config
+        .get_value("agents.suricata-commands")?
+        .unwrap_or_default();
+    for command in server_config.suricata_command_roles.keys() {
+        if !crate::agent::protocol::SURICATA_COMMANDS.contains(&command.as_str()) {
+            bail!(
+                "agents.suricata-commands: {command:?} cannot be proxied; supported commands are {}",
+                crate::agent::protocol::SURICATA_COMMANDS.join(", ")
+            );
+        }
+    }
+
+    server_config.agents_client_ca = config.get("agents.client-certificates.ca")?;
+    server_config.agents_require_certificate =
+        config.get_bool("agents.client-certificates.required")?;
+    server_config.agents_certificate_without_key =
+        config.get_bool("agents.client-certificates.without-key")?;
+    if server_config.agents_client_ca.is_none()
+        && (server_config.agents_require_certificate
+            || server_config.agents_certificate_without_key)
+    {
+        bail!("agents.client-certificates requires a CA: set agents.client-certificates.ca");
+    }
+    if server_config.agents_client_ca.is_some() && !server_config.tls_enabled {
+        bail!("agents.client-certificates requires TLS: enable http.tls");
+    }
+    server_config.agent_key_expiry_warning_days =
+        config.get_value("agents.key-expiry-warning-days")?;
+    server_config.agent_key_unused_warning_days =
+        config.get_value("agents.key-unused-warning-days")?;
 
     debug!(
         "Certificate checks disabled: {}",
@@ -106,6 +145,9 @@ pub async fn main(args: &clap::ArgMatches) -> Result<()> {
     );
 
     server_config.authentication_required = is_authentication_required(&config);
+    server_config.lockout = crate::server::throttle::LockoutConfig::configure(&config)?;
+    server_config.session = crate::server::session::SessionConfig::configure(&config)?;
+    server_config.password = crate::server::password::PasswordPolicy::configure(&config)?;
 
     // Do we need a data-directory? If so, make sure its set.
     let data_directory_required = server_config.datastore == "sqlite"
@@ -194,7 +236,39 @@ pub async fn main(args: &clap::ArgMatches) -> Result<()> {
     ));
     context.filters = Some(submitted_event_filters);
 
-    context.pcap = Arc::new(crate::server::pcap::configure(&config));
+    let mut pcap = crate::server::pcap::configure(&config);
+    pcap.evidence =
+        crate::server::pcap::evidence::configure(&config, server_config.data_directory.as_deref())?;
+    match crate::server::audit::configure_retention(&config)? {
+        Some(retention) => {
+            crate::server::audit::start_retention_task(context.configdb.clone(), retention)
+        }
+        None => info!("Audit retention disabled"),
+    }
+    pcap.audit = crate::server::pcap::audit::AuditLog::spawn(context.configdb.clone());
+    pcap.jobs = crate::server::pcap::jobs::JobRegistry::new(crate::server::pcap::jobs::configure(
+        &config,
+        server_config.data_directory.as_deref(),
+    )?);
+    if let Some(settings) =
+        crate::server::analyze::configure(&config, server_config.data_directory.as_deref())?
+    {
+        let analyzer = crate::server::analyze::Analyzer::open(settings)?;
+        for (analysis, capture) in analyzer.captures() {
+            pcap.add_analysis(&analysis.id, &analysis.sensor, vec![capture]);
+        }
+        context.analyzer = Some(Arc::new(analyzer));
+    }
+    context.pcap = Arc::new(pcap);
+    context.oidc = crate::server::oidc::configure(&config)?.map(Arc::new);
+    context.ldap = crate::server::ldap::configure(&config)?.map(Arc::new);
+    context.proxy_auth = crate::server::proxy::configure(&config, &context.config)?.map(Arc::new);
+
+    #[cfg(not(windows))]
+    if let Some(spool) = context.pcap.indexed_spool() {
+        info!("Indexing PCAP spool {}", spool.directory.display());
+        tokio::spawn(crate::pcap::index::run_indexer(move || Some(spool.clone())));
+    }
 
     // Apply the persisted operator pcap routing table, if any, after
     // the configured service replaces the context's default one.
@@ -284,6 +358,11 @@ pub async fn main(args: &clap::ArgMatches) -> Result<()> {
     }
 
     let context = Arc::new(context);
+    tokio::spawn(crate::server::pcap::retention::run(context.clone()));
+    {
+        let context = context.clone();
+        tokio::spawn(async move { context.pcap.jobs.run_reaper().await });
+    }
     info!(
         "Starting server on {}:{}, tls={}",
         server_config.host, server_config.port, server_config.tls_enabled
@@ -349,7 +428,10 @@ async fn create_admin_user(context: &ServerContext) -> Result<(String, String)>
         .take(12)
         .map(char::from)
         .collect();
-    context.configdb.add_user(username, &password).await?;
+    context
+        .configdb
+        .add_user(username, &password, Role::Admin)
+        .await?;
     Ok((username.to_string(), password))
 }
 
@@ -384,8 +466,25 @@ pub(crate) fn build_axum_service(
         HeaderValue::from_static(crate::version::build_rev()),
     );
 
+    // Spans carry the client address, behind a proxy the one it reports.
+    let trace_context = context.clone();
     let request_tracing = TraceLayer::new_for_http()
-        .make_span_with(DefaultMakeSpan::new().include_headers(false))
+        .make_span_with(move |request: &axum::http::Request<axum::body::Body>| {
+            let client = request
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(peer)| {
+                    proxy::client_addr(&trace_context.config, request.headers(), *peer)
+                })
+                .unwrap_or_default();
+            tracing::debug_span!(
+                "request",
+                method = %request.method(),
+                uri = %request.uri(),
+                version = ?request.version(),
+                client = %client,
+            )
+        })
         .on_response(DefaultOnResponse::new().level(Level::INFO))
         .on_request(());
 
@@ -415,10 +514,33 @@ pub(crate) async fn run_axum_server_with_tls(
     let port: u16 = config.port;
     let addr: SocketAddr = format!("{}:{}", config.host, port).parse()?;
     let service = build_axum_service(context.clone());
+    let tls_config = rustls_config(config).await?;
+    axum_server::bind_rustls(addr, tls_config)
+        .map(client_cert::PeerCertificateAcceptor::new)
+        .serve(service)
+        .await?;
+    Ok(())
+}
 
-    let tls_config = load_rustls_config(
+/// The TLS server configuration: the server certificate, and client
+/// certificate verification for agents when a client CA is configured.
+pub(crate) async fn rustls_config(
+    config: &ServerConfig,
+) -> Result<axum_server::tls_rustls::RustlsConfig> {
+    let client_verifier = match &config.agents_client_ca {
+        Some(path) => {
+            let verifier = client_cert::client_verifier(path).map_err(|err| {
+                anyhow!("Failed to load the agent client CA file {path:?}: {err}")
+            })?;
+            info!("Requesting client certificates for agents, verified against {path:?}");
+            Some(verifier)
+        }
+        None => None,
+    };
+    load_rustls_config(
         config.tls_cert_filename.as_ref().unwrap(),
         config.tls_key_filename.as_ref().unwrap(),
+        client_verifier,
     )
     .await
     .map_err(|err| {
@@ -428,16 +550,13 @@ pub(crate) async fn run_axum_server_with_tls(
             config.tls_key_filename,
             err
         )
-    })?;
-    axum_server::bind_rustls(addr, tls_config)
-        .serve(service)
-        .await?;
-    Ok(())
+    })
 }
 
 async fn load_rustls_config(
     cert_path: impl AsRef<Path>,
     key_path: impl AsRef<Path>,
+    client_verifier: Option<Arc<dyn rustls::server::danger::ClientCertVerifier>>,
 ) -> std::io::Result<axum_server::tls_rustls::RustlsConfig> {
     use rustls_pki_types::pem::PemObject;
     use rustls_pki_types::{CertificateDer, PrivateKeyDer};
@@ -462,8 +581,12 @@ async fn load_rustls_config(
         .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
 
     // Build rustls ServerConfig
-    let server_config = rustls::ServerConfig::builder()
-        .with_no_client_auth()
+    let builder = rustls::ServerConfig::builder();
+    let builder = match client_verifier {
+        Some(verifier) => builder.with_client_cert_verifier(verifier),
+        None => builder.with_no_client_auth(),
+    };
+    let server_config = builder
         .with_single_cert(certs, key)
         .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
 
@@ -721,109 +844,272 @@ where
         req: &mut axum::http::request::Parts,
         state: &S,
     ) -> Result<Self, Self::Rejection> {
-        let Extension(context) =
-            <Extension<Arc<ServerContext>> as FromRequestParts<S>>::from_request_parts(req, state)
-                .await
-                .unwrap();
-        let enable_reverse_proxy = context.config.http_reverse_proxy;
-        let Extension(ConnectInfo(remote_addr)) =
-            <Extension<ConnectInfo<SocketAddr>> as FromRequestParts<S>>::from_request_parts(
-                req, state,
-            )
+        let session = authenticate(req, state).await?;
+        if let Some(RequiredRole(required)) = req.extensions.get::<RequiredRole>()
+            && session.role < *required
+        {
+            warn!(
+                "Access denied: user={:?} role={} required={} path={}",
+                session.username,
+                session.role,
+                required,
+                req.uri.path()
+            );
+            return Err((StatusCode::FORBIDDEN, "insufficient role"));
+        }
+        if session.password_change_required && req.extensions.get::<RequiredRole>().is_some() {
+            warn!(
+                "Access denied: user={:?} must change their password, path={}",
+                session.username,
+                req.uri.path()
+            );
+            return Err((StatusCode::FORBIDDEN, "password change required"));
+        }
+        Ok(Self(session))
+    }
+}
+
+/// Find the request's session: a session cookie, then basic
+/// authentication, or an anonymous session when authentication is not
+/// required.
+async fn authenticate<S>(
+    req: &mut axum::http::request::Parts,
+    state: &S,
+) -> Result<Arc<Session>, (StatusCode, &'static str)>
+where
+    S: Send + Sync,
+{
+    let Extension(context) =
+        <Extension<Arc<ServerContext>> as FromRequestParts<S>>::from_request_parts(req, state)
             .await
             .unwrap();
-        let headers = &req.headers;
+    let Extension(ConnectInfo(remote_addr)) =
+        <Extension<ConnectInfo<SocketAddr>> as FromRequestParts<S>>::from_request_parts(req, state)
+            .await
+            .unwrap();
+    let headers = &req.headers;
 
-        let cookies = CookieJar::from_headers(headers);
-        let session_id = cookies
-            .get("x-evebox-session-id")
-            .map(|c| c.value().to_string());
+    let cookies = CookieJar::from_headers(headers);
+    let session_id = cookies
+        .get("x-evebox-session-id")
+        .map(|c| c.value().to_string());
 
-        let remote_user = headers
-            .get("remote_user")
-            .and_then(|h| h.to_str().map(|h| h.to_string()).ok());
+    let trusted_proxy = proxy::is_trusted(&context.config, remote_addr.ip());
+    let client_addr = proxy::client_addr(&context.config, headers, remote_addr);
+    let client_ip = proxy::client_ip(&context.config, headers, remote_addr);
 
-        let forwarded_for = if enable_reverse_proxy {
-            headers
-                .get("x-forwarded-for")
-                .and_then(|h| h.to_str().map(|h| h.to_string()).ok())
-        } else {
-            None
-        };
+    // Only a trusted proxy may name the user.
+    let remote_user = headers
+        .get("remote_user")
+        .and_then(|h| h.to_str().map(|h| h.to_string()).ok())
+        .filter(|_| trusted_proxy);
 
-        // TODO: Proper reverse proxy handling, mainly for logging.
-        let _remote_addr = forwarded_for.unwrap_or_else(|| remote_addr.to_string());
+    if let Some(proxy_auth) = &context.proxy_auth
+        && proxy_auth.has_headers(headers)
+    {
+        if !trusted_proxy {
+            warn!(
+                "Rejecting proxy login headers from untrusted peer {}",
+                remote_addr
+            );
+            return Err((StatusCode::FORBIDDEN, "untrusted proxy"));
+        }
+        match proxy_auth.login(&context.configdb, headers).await {
+            Ok(Some(user)) => {
+                debug!(
+                    "Proxy login for user {:?} from {}",
+                    user.username, client_addr
+                );
+                return Ok(Arc::new(Session::for_user(&user)));
+            }
+            Ok(None) => {}
+            Err(ProxyAuthError::Denied(err)) => {
+                warn!("Proxy login refused from {}: {}", client_addr, err);
+                return Err((StatusCode::FORBIDDEN, "login refused"));
+            }
+            Err(err) => {
+                error!("Proxy login failed: {:?}", err);
+                return Err((StatusCode::INTERNAL_SERVER_ERROR, "internal error"));
+            }
+        }
+    }
 
-        if let Some(session_id) = session_id {
-            let session = context.session_store.get(&session_id);
-            if let Some(session) = session {
-                return Ok(SessionExtractor(session));
+    if let Some(session_id) = session_id {
+        let now = chrono::Utc::now().timestamp();
+        let idle_timeout = context.config.session.idle_timeout_secs();
+        if let Some((session, save)) =
+            context
+                .session_store
+                .get_active(&session_id, now, idle_timeout)
+        {
+            if save && let Err(err) = context.configdb.touch_session(&session_id, now).await {
+                warn!("Failed to record session use: {:?}", err);
             }
+            return Ok(session);
+        }
 
-            debug!("Session not found in cache, checking database");
+        debug!("Session not found in cache, checking database");
 
-            match context.configdb.get_user_by_session(&session_id).await {
-                Ok(Some(user)) => {
-                    info!("Found session for user {}", &user.username);
-                    let session = Session {
-                        session_id: Some(session_id.to_string()),
-                        username: Some(user.username),
-                    };
-                    let session = Arc::new(session);
-                    let _ = context.session_store.put(session.clone());
-                    return Ok(SessionExtractor(session));
-                }
-                Ok(None) => {}
-                Err(err) => {
-                    error!("Failed to get user by session from database: {:?}", err);
+        match context
+            .configdb
+            .get_active_session(&session_id, idle_timeout)
+            .await
+        {
+            Ok(Some((user, expires_at))) => {
+                info!("Found session for user {}", &user.username);
+                if let Err(err) = context.configdb.touch_session(&session_id, now).await {
+                    warn!("Failed to record session use: {:?}", err);
                 }
+                let session = Session {
+                    session_id: Some(session_id.to_string()),
+                    username: Some(user.username),
+                    role: user.role,
+                    api_token: None,
+                    expires_at: Some(expires_at),
+                    sensors: user.sensors,
+                    password_change_required: user.password_change_required,
+                };
+                let session = Arc::new(session);
+                let _ = context.session_store.put(session.clone(), now);
+                return Ok(session);
+            }
+            Ok(None) => {}
+            Err(err) => {
+                error!("Failed to get user by session from database: {:?}", err);
             }
         }
+    }
 
-        use axum_extra::headers::Authorization;
-        use axum_extra::headers::authorization::Basic;
-
-        let authorization = if headers.contains_key("authorization") {
-            let TypedHeader(Authorization(basic)) =
-                <TypedHeader<Authorization<Basic>> as FromRequestParts<S>>::from_request_parts(
-                    req, state,
-                )
-                .await
-                .map_err(|err| {
-                    warn!("Failed to decode basic authentication header: {:?}", err);
-                    (StatusCode::UNAUTHORIZED, "bad authorization header")
-                })?;
-            Some(basic)
-        } else {
-            None
+    let bearer = headers
+        .get("authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string());
+    if let Some(token) = bearer {
+        return match context.configdb.verify_api_token(&token).await {
+            Ok(Some((token, user))) => {
+                info!(
+                    "api-token: user={:?} token={:?} remote={} request=\"{} {}\"",
+                    user.username,
+                    token.name,
+                    client_addr,
+                    req.method,
+                    req.uri.path()
+                );
+                let mut session = Session::for_user(&user);
+                session.role = user.role.min(token.scope);
+                session.api_token = Some(token.name.clone());
+                // Uses are audited as often as they are recorded on the
+                // token, at most once a minute.
+                match context.configdb.touch_api_token(token.id).await {
+                    Ok(true) => {
+                        Auditor::new(context.configdb.clone(), client_addr)
+                            .record(
+                                &session,
+                                "token.use",
+                                Some(&token.name),
+                                json!({"request": format!("{} {}", req.method, req.uri.path())}),
+                                Outcome::Ok,
+                            )
+                            .await;
+                    }
+                    Ok(false) => {}
+                    Err(err) => warn!("Failed to record API token use: {:?}", err),
+                }
+                Ok(Arc::new(session))
+            }
+            Ok(None) => {
+                warn!(
+                    "Unknown or expired API token presented from {}",
+                    client_addr
+                );
+                Err((StatusCode::UNAUTHORIZED, "invalid token"))
+            }
+            Err(err) => {
+                error!("Failed to verify API token: {:?}", err);
+                Err((StatusCode::INTERNAL_SERVER_ERROR, "internal error"))
+            }
         };
+    }
 
-        if context.config.authentication_required {
-            if let Some(basic) = authorization {
-                match context
-                    .configdb
-                    .get_user_by_username_password(basic.username(), basic.password())
-                    .await
-                {
-                    Ok(user) => {
-                        return Ok(Self(Arc::new(Session::with_username(&user.username))));
-                    }
-                    Err(err) => {
-                        warn!(
-                            "Basic authentication failure for username {}, error={:?}",
-                            basic.username(),
-                            err
-                        );
+    use axum_extra::headers::Authorization;
+    use axum_extra::headers::authorization::Basic;
+
+    let authorization = if headers.contains_key("authorization") {
+        let TypedHeader(Authorization(basic)) =
+            <TypedHeader<Authorization<Basic>> as FromRequestParts<S>>::from_request_parts(
+                req, state,
+            )
+            .await
+            .map_err(|err| {
+                warn!("Failed to decode basic authentication header: {:?}", err);
+                (StatusCode::UNAUTHORIZED, "bad authorization header")
+            })?;
+        Some(basic)
+    } else {
+        None
+    };
+
+    if context.config.authentication_required {
+        if let Some(basic) = authorization {
+            if context
+                .login_throttle
+                .check(basic.username(), &client_ip)
+                .is_some()
+            {
+                warn!(
+                    "Basic authentication for username {} from {} refused: locked out",
+                    basic.username(),
+                    client_addr
+                );
+                return Err((StatusCode::TOO_MANY_REQUESTS, "too many failed logins"));
+            }
+            match check_password(&context, basic.username(), basic.password()).await {
+                Ok(Some(user)) => {
+                    // Basic authentication has no second step, so users
+                    // with a second factor must use an API token.
+                    match totp::requires_second_factor(&context.configdb, &user.username).await {
+                        Ok(false) => {
+                            context.login_throttle.success(basic.username());
+                            return Ok(Arc::new(Session::for_user(&user)));
+                        }
+                        Ok(true) => {
+                            warn!(
+                                "Basic authentication refused for username {} from {}: second factor required",
+                                user.username, client_addr
+                            );
+                        }
+                        Err(err) => {
+                            error!(
+                                "Basic authentication error for username {}: {:?}",
+                                user.username, err
+                            );
+                        }
                     }
                 }
+                Ok(None) => {
+                    warn!(
+                        "Basic authentication failure for username {} from {}",
+                        basic.username(),
+                        client_addr
+                    );
+                    context.login_throttle.failure(basic.username(), &client_ip);
+                }
+                Err(err) => {
+                    error!(
+                        "Basic authentication error for username {}: {:?}",
+                        basic.username(),
+                        err
+                    );
+                }
             }
-            info!("Authentication required but no session found.");
-        } else {
-            return Ok(Self(Arc::new(Session::anonymous(remote_user))));
         }
-
-        Err((StatusCode::UNAUTHORIZED, "authentication required"))
+        info!("Authentication required but no session found.");
+    } else {
+        return Ok(Arc::new(Session::anonymous(remote_user)));
     }
+
+    Err((StatusCode::UNAUTHORIZED, "authentication required"))
 }
 
 pub(crate) fn get_bookmark_filename<P: AsRef<Path> + Clone>(
@@ -8,18 +8,28 @@ pub(crate) use main::build_context;
 pub use main::main;
 use metrics::Metrics;
 use serde::Serialize;
-use session::SessionStore;
+use session::{Role, SessionStore};
 use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
 
+pub(crate) mod agent_jobs;
 pub(crate) mod agents;
+pub(crate) mod analyze;
 pub(crate) mod api;
+pub(crate) mod audit;
 pub(crate) mod autoarchive;
+pub(crate) mod client_cert;
 pub(crate) mod context;
+pub(crate) mod ldap;
 pub(crate) mod main;
 pub(super) mod metrics;
+pub(crate) mod oidc;
+pub(crate) mod password;
 pub(crate) mod pcap;
+pub(crate) mod proxy;
 pub(crate) mod session;
+pub(crate) mod throttle;
+pub(crate) mod totp;
 
 const SUPPORTED_DEFAULT_TIME_RANGES: [&str; 9] =
     ["1m", "1h", "3h", "12h", "24h", "1d", "3d", "7d", "all"];
@@ -62,7 +72,18 @@ pub(crate) struct ServerContext {
     pub firehose: tokio::sync::broadcast::Sender<serde_json::Value>,
     pub(crate) agents: Arc<agents::AgentRegistry>,
     pub(crate) pcap_tasks: Arc<pcap::tasks::Registry>,
+    pub(crate) agent_jobs: Arc<agent_jobs::AgentJobs>,
     pub pcap: Arc<pcap::PcapService>,
+    /// PCAP analysis, when enabled.
+    pub(crate) analyzer: Option<Arc<analyze::Analyzer>>,
+    /// OpenID Connect login, when enabled.
+    pub(crate) oidc: Option<Arc<oidc::Oidc>>,
+    /// LDAP login, when enabled.
+    pub(crate) ldap: Option<Arc<ldap::Ldap>>,
+    /// Login by a trusted reverse proxy's headers, when enabled.
+    pub(crate) proxy_auth: Option<Arc<proxy::ProxyAuth>>,
+    /// Failed password logins, for lockout.
+    pub(crate) login_throttle: throttle::LoginThrottle,
 }
 
 impl ServerContext {
@@ -75,7 +96,11 @@ impl ServerContext {
         let (firehose, _) = tokio::sync::broadcast::channel::<serde_json::Value>(8192);
         let auto_archive: Arc<RwLock<AutoArchive>> = Default::default();
         let pcap_tasks = Arc::new(pcap::tasks::Registry::default());
-        let agents = Arc::new(agents::AgentRegistry::new(pcap_tasks.clone()));
+        let agent_jobs = Arc::new(agent_jobs::AgentJobs::default());
+        let handlers: Vec<Arc<dyn agents::AgentMessageHandler>> =
+            vec![pcap_tasks.clone(), agent_jobs.clone()];
+        let agents = Arc::new(agents::AgentRegistry::new(Arc::new(handlers)));
+        let login_throttle = throttle::LoginThrottle::new(config.lockout.clone());
         Self {
             config,
             mode: ServerMode::default(),
@@ -90,7 +115,13 @@ impl ServerContext {
             firehose,
             agents,
             pcap_tasks,
+            agent_jobs,
             pcap: Arc::new(pcap::PcapService::default()),
+            analyzer: None,
+            oidc: None,
+            ldap: None,
+            proxy_auth: None,
+            login_throttle,
         }
     }
 }
@@ -116,11 +147,36 @@ pub(crate) struct ServerConfig {
     pub config_directory: Option<String>,
     pub authentication_required: bool,
     pub http_reverse_proxy: bool,
+    /// Peers trusted to set `X-Forwarded-For` and the proxy login headers,
+    /// from `http.trusted-proxies`. Empty trusts any peer with
+    /// `X-Forwarded-For`.
+    pub trusted_proxies: Vec<proxy::Cidr>,
     pub http_request_logging: bool,
+    /// Failed login lockout, from `authentication.lockout`.
+    pub lockout: throttle::LockoutConfig,
+    /// Login session timeouts, from `authentication.session`.
+    pub session: session::SessionConfig,
+    /// Rules for new passwords, from `authentication.password`.
+    pub password: password::PasswordPolicy,
     /// Accept agent control-channel connections without an agent key. A lab
     /// escape hatch: agent keys are otherwise required regardless of
     /// `authentication.required`, which only governs browser access.
     pub agents_allow_unauthenticated: bool,
+    /// The least role for each proxied Suricata command, from
+    /// `agents.suricata-commands`, where it differs from the default.
+    pub suricata_command_roles: std::collections::BTreeMap<String, Role>,
+    /// CA bundle verifying agent client certificates, from
+    /// `agents.client-certificates.ca`. Requires TLS.
+    pub agents_client_ca: Option<PathBuf>,
+    /// Agents must present a client certificate mapped to their key.
+    pub agents_require_certificate: bool,
+    /// A mapped client certificate authenticates an agent without a key.
+    pub agents_certificate_without_key: bool,
+    /// Warn in the agent key listing this many days before a key expires.
+    pub agent_key_expiry_warning_days: Option<u64>,
+    /// Warn in the agent key listing about keys no agent has used for this
+    /// many days; 0 disables the warning.
+    pub agent_key_unused_warning_days: Option<u64>,
 }
 
 #[cfg(test)]
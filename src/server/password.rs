@@ -0,0 +1,177 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! Password policy.
+//!
+//! Passwords set through the API or `evebox config users` must be long
+//! enough, mix enough classes of character (lowercase, uppercase, digits
+//! and others), differ from the username and not be in a local list of
+//! breached passwords. The list is read at startup, one password per
+//! line, and matched without regard to case.
+
+use std::collections::HashSet;
+use std::io::BufRead;
+use std::path::PathBuf;
+
+use crate::prelude::*;
+
+#[derive(Clone)]
+pub(crate) struct PasswordPolicy {
+    pub min_length: usize,
+    /// Classes of character a password must have, of lowercase,
+    /// uppercase, digits and others.
+    pub min_classes: usize,
+    /// Breached passwords, lowercased.
+    pub breached: Arc<HashSet<String>>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            min_classes: 1,
+            breached: Default::default(),
+        }
+    }
+}
+
+impl std::fmt::Debug for PasswordPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PasswordPolicy")
+            .field("min_length", &self.min_length)
+            .field("min_classes", &self.min_classes)
+            .field("breached", &self.breached.len())
+            .finish()
+    }
+}
+
+impl PasswordPolicy {
+    /// The `authentication.password` configuration, defaults for what's
+    /// unset.
+    pub(crate) fn configure(config: &crate::config::Config) -> Result<Self> {
+        let default = Self::default();
+        let min_length = config
+            .get_value("authentication.password.min-length")?
+            .unwrap_or(default.min_length);
+        let min_classes = config
+            .get_value("authentication.password.min-classes")?
+            .unwrap_or(default.min_classes);
+        if min_length == 0 || !(1..=4).contains(&min_classes) {
+            bail!(
+                "authentication.password: min-length must be more than 0 and min-classes from 1 to 4"
+            );
+        }
+        let breached = match config.get_value::<PathBuf>("authentication.password.breached-file")? {
+            Some(path) => {
+                let breached = read_breached(&path).with_context(|| {
+                    format!(
+                        "authentication.password: failed to read breached passwords from {}",
+                        path.display()
+                    )
+                })?;
+                info!(
+                    "Loaded {} breached passwords from {}",
+                    breached.len(),
+                    path.display()
+                );
+                breached
+            }
+            None => HashSet::new(),
+        };
+        Ok(Self {
+            min_length,
+            min_classes,
+            breached: Arc::new(breached),
+        })
+    }
+
+    /// Why `password` is not allowed for `username`, or None when it is.
+    pub(crate) fn check(&self, username: &str, password: &str) -> Option<String> {
+        if password.chars().count() < self.min_length {
+            return Some(format!(
+                "password must be at least {} characters",
+                self.min_length
+            ));
+        }
+        if classes(password) < self.min_classes {
+            return Some(format!(
+                "password must have {} of lowercase, uppercase, digits and other characters",
+                self.min_classes
+            ));
+        }
+        if password.eq_ignore_ascii_case(username) {
+            return Some("password must not be the username".to_string());
+        }
+        if self.breached.contains(&password.to_lowercase()) {
+            return Some("password is in a list of breached passwords".to_string());
+        }
+        None
+    }
+}
+
+/// The number of classes of character in `password`.
+fn classes(password: &str) -> usize {
+    let mut found = [false; 4];
+    for c in password.chars() {
+        let class = if c.is_lowercase() {
+            0
+        } else if c.is_uppercase() {
+            1
+        } else if c.is_numeric() {
+            2
+        } else {
+            3
+        };
+        found[class] = true;
+    }
+    found.iter().filter(|found| **found).count()
+}
+
+fn read_breached(path: &std::path::Path) -> Result<HashSet<String>> {
+    let file = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut breached = HashSet::new();
+    for line in file.lines() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+        if !line.is_empty() {
+            breached.insert(line.to_lowercase());
+        }
+    }
+    Ok(breached)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passwords_are_checked_against_the_policy() {
+        let policy = PasswordPolicy {
+            min_length: 10,
+            min_classes: 3,
+            breached: Arc::new(["correct horse battery"].map(String::from).into()),
+        };
+        assert!(policy.check("bob", "Short1!").unwrap().contains("10"));
+        assert!(policy.check("bob", "lowercaseonly").is_some());
+        assert!(
+            policy
+                .check("Bobby-12345", "bobby-12345")
+                .unwrap()
+                .contains("username")
+        );
+        assert!(policy.check("bob", "Correct Horse Battery").is_some());
+        assert_eq!(policy.check("bob", "Lowercase-and-UPPER"), None);
+        assert_eq!(policy.check("bob", "Ünïcode 123 ok"), None);
+    }
+
+    #[test]
+    fn breached_passwords_are_read_one_per_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("breached.txt");
+        std::fs::write(&path, "Password1\r\n\nletmein\n").unwrap();
+        let breached = read_breached(&path).unwrap();
+        assert_eq!(breached.len(), 2);
+        assert!(breached.contains("password1"));
+        assert!(breached.contains("letmein"));
+    }
+}
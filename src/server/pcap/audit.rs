@@ -0,0 +1,104 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! The PCAP download audit trail.
+//!
+//! Every capture request ends in one audit record next to its `pcap:`
+//! log line: who asked, for which packets, from where, and the size and
+//! SHA-256 of the bytes they were sent. Records are handed to a writer
+//! task so the request paths, some of which are synchronous, never wait
+//! on the configuration database.
+
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+
+use crate::prelude::*;
+use crate::sqlite::configdb::{ConfigDb, PcapAuditRecord};
+
+/// The response header, or trailer for a streamed download, carrying
+/// the SHA-256 of the capture bytes.
+pub(crate) const SHA256_HEADER: &str = "x-evebox-pcap-sha256";
+
+/// Where audit records go. The default discards them, for contexts
+/// without a configuration database.
+#[derive(Clone, Default)]
+pub(crate) struct AuditLog {
+    tx: Option<mpsc::UnboundedSender<PcapAuditRecord>>,
+}
+
+impl AuditLog {
+    /// Persist records to `configdb` from a background task.
+    pub(crate) fn spawn(configdb: Arc<ConfigDb>) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel::<PcapAuditRecord>();
+        tokio::spawn(async move {
+            while let Some(record) = rx.recv().await {
+                if let Err(err) = configdb.add_pcap_audit(&record).await {
+                    error!("Failed to store pcap audit record: {err}");
+                }
+            }
+        });
+        Self { tx: Some(tx) }
+    }
+
+    pub(crate) fn record(&self, record: PcapAuditRecord) {
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(record);
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn channel() -> (Self, mpsc::UnboundedReceiver<PcapAuditRecord>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (Self { tx: Some(tx) }, rx)
+    }
+}
+
+/// A running SHA-256 and byte count of the capture bytes sent for one
+/// request, shared by its producer and the response body.
+#[derive(Clone, Default)]
+pub(crate) struct PayloadDigest(Arc<std::sync::Mutex<(Sha256, u64)>>);
+
+impl PayloadDigest {
+    pub(crate) fn update(&self, data: &[u8]) {
+        let mut state = self.0.lock().unwrap();
+        state.0.update(data);
+        state.1 = state.1.saturating_add(data.len() as u64);
+    }
+
+    /// The bytes counted so far.
+    pub(crate) fn bytes(&self) -> u64 {
+        self.0.lock().unwrap().1
+    }
+
+    /// The SHA-256 of the bytes so far, as lowercase hex; `None` when
+    /// nothing was sent.
+    pub(crate) fn sha256(&self) -> Option<String> {
+        let state = self.0.lock().unwrap();
+        (state.1 > 0).then(|| hex(state.0.clone().finalize().as_slice()))
+    }
+}
+
+/// The SHA-256 of `data` as lowercase hex.
+pub(crate) fn sha256(data: &[u8]) -> String {
+    hex(Sha256::digest(data).as_slice())
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn digest_matches_one_shot_hash() {
+        let digest = PayloadDigest::default();
+        assert_eq!(digest.sha256(), None);
+        digest.clone().update(b"pack");
+        digest.update(b"ets");
+        assert_eq!(digest.bytes(), 7);
+        assert_eq!(digest.sha256(), Some(sha256(b"packets")));
+        assert_eq!(sha256(b"packets"), crate::cert::fingerprint(b"packets"));
+    }
+}
@@ -0,0 +1,209 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! Durable store of the captures pinned for escalated events.
+//!
+//! Pinned captures live outside the rolling spool, by default in the
+//! `evidence` directory of the data directory, and are named by their
+//! SHA-256: the same flow pinned from several events is stored once,
+//! and a file can be checked against the hash recorded in the event
+//! history. A JSON record next to each capture describes what it holds.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::prelude::*;
+
+/// The description of a capture, as given by the pinning request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct CaptureInfo {
+    /// The download name of the capture.
+    pub(crate) filename: String,
+    pub(crate) source: String,
+    pub(crate) filter: String,
+    pub(crate) window: String,
+    pub(crate) truncated: bool,
+    /// The events the capture was pinned for.
+    pub(crate) event_ids: Vec<String>,
+}
+
+/// A stored capture's record.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct EvidenceRecord {
+    pub(crate) sha256: String,
+    pub(crate) bytes: u64,
+    /// RFC 3339 time the capture was first stored.
+    pub(crate) created: String,
+    #[serde(flatten)]
+    pub(crate) info: CaptureInfo,
+}
+
+pub(crate) struct EvidenceStore {
+    directory: PathBuf,
+    /// Held for the duration of a pin, so background extractions run one
+    /// at a time and leave the sources to interactive downloads.
+    pinning: tokio::sync::Mutex<()>,
+}
+
+impl EvidenceStore {
+    pub(crate) fn new(directory: PathBuf) -> Self {
+        Self {
+            directory,
+            pinning: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub(crate) fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub(crate) async fn lock(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.pinning.lock().await
+    }
+
+    /// Store a capture and its record. A capture already held keeps its
+    /// file and creation time; the new events are added to its record.
+    pub(crate) fn store(&self, data: &[u8], info: CaptureInfo) -> std::io::Result<EvidenceRecord> {
+        let sha256: String = Sha256::digest(data)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        std::fs::create_dir_all(&self.directory)?;
+
+        let capture = self.capture_path(&sha256);
+        let record = match self.record(&sha256)? {
+            Some(mut record) if capture.exists() => {
+                for event_id in info.event_ids {
+                    if !record.info.event_ids.contains(&event_id) {
+                        record.info.event_ids.push(event_id);
+                    }
+                }
+                record
+            }
+            _ => {
+                self.write(&capture, data)?;
+                EvidenceRecord {
+                    sha256: sha256.clone(),
+                    bytes: data.len() as u64,
+                    created: crate::datetime::DateTime::now().to_rfc3339_utc(),
+                    info,
+                }
+            }
+        };
+        let json = serde_json::to_vec_pretty(&record)?;
+        self.write(&self.record_path(&sha256), &json)?;
+        Ok(record)
+    }
+
+    /// The path and record of a stored capture.
+    pub(crate) fn get(&self, sha256: &str) -> std::io::Result<Option<(PathBuf, EvidenceRecord)>> {
+        if !is_sha256(sha256) {
+            return Ok(None);
+        }
+        let capture = self.capture_path(sha256);
+        match self.record(sha256)? {
+            Some(record) if capture.exists() => Ok(Some((capture, record))),
+            _ => Ok(None),
+        }
+    }
+
+    fn record(&self, sha256: &str) -> std::io::Result<Option<EvidenceRecord>> {
+        match std::fs::read(self.record_path(sha256)) {
+            Ok(json) => Ok(Some(serde_json::from_slice(&json)?)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn capture_path(&self, sha256: &str) -> PathBuf {
+        self.directory.join(format!("{sha256}.pcap"))
+    }
+
+    fn record_path(&self, sha256: &str) -> PathBuf {
+        self.directory.join(format!("{sha256}.json"))
+    }
+
+    /// Replace `path` atomically, so a crash never leaves a partial
+    /// capture under its hash.
+    fn write(&self, path: &Path, data: &[u8]) -> std::io::Result<()> {
+        let mut file = tempfile::NamedTempFile::new_in(&self.directory)?;
+        file.write_all(data)?;
+        file.as_file().sync_all()?;
+        file.persist(path).map_err(|err| err.error)?;
+        Ok(())
+    }
+}
+
+fn is_sha256(input: &str) -> bool {
+    input.len() == 64
+        && input
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+}
+
+/// Read `pcap.evidence`. Pinning is off unless `enabled` is set; the
+/// store defaults to `evidence` in the data directory.
+pub(crate) fn configure(
+    config: &crate::config::Config,
+    data_directory: Option<&str>,
+) -> Result<Option<EvidenceStore>> {
+    if !config.get_bool("pcap.evidence.enabled")? {
+        return Ok(None);
+    }
+    let directory = match config
+        .get::<String>("pcap.evidence.directory")?
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+    {
+        Some(directory) => PathBuf::from(directory),
+        None => match data_directory {
+            Some(data_directory) => PathBuf::from(data_directory).join("evidence"),
+            None => bail!("pcap.evidence requires a data directory or pcap.evidence.directory"),
+        },
+    };
+    std::fs::create_dir_all(&directory)
+        .map_err(|err| anyhow!("failed to create {}: {err}", directory.display()))?;
+    info!("Pinning escalated captures to {}", directory.display());
+    Ok(Some(EvidenceStore::new(directory)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn info(event_id: &str) -> CaptureInfo {
+        CaptureInfo {
+            filename: "capture.pcap".to_string(),
+            source: "local".to_string(),
+            filter: "udp 10.1.1.5:4000 <-> 192.0.2.10:53".to_string(),
+            window: "-".to_string(),
+            truncated: false,
+            event_ids: vec![event_id.to_string()],
+        }
+    }
+
+    #[test]
+    fn captures_are_stored_once_by_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = EvidenceStore::new(dir.path().join("evidence"));
+
+        let first = store.store(b"packets", info("1")).unwrap();
+        assert_eq!(first.sha256, crate::cert::fingerprint(b"packets"));
+        assert_eq!(first.bytes, 7);
+
+        let second = store.store(b"packets", info("2")).unwrap();
+        assert_eq!(second.sha256, first.sha256);
+        assert_eq!(second.created, first.created);
+        assert_eq!(second.info.event_ids, ["1", "2"]);
+
+        let (path, record) = store.get(&first.sha256).unwrap().unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), b"packets");
+        assert_eq!(record, second);
+
+        // Only hashes are looked up.
+        assert!(store.get("../evidence").unwrap().is_none());
+        assert!(store.get(&"0".repeat(64)).unwrap().is_none());
+    }
+}
@@ -0,0 +1,431 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! Asynchronous PCAP export jobs.
+//!
+//! A job extracts into a temporary file on the server instead of
+//! streaming to the browser, so the source's extraction slot is held
+//! only while packets are read, never for as long as a slow client
+//! takes to download. The API polls a job's progress and then
+//! downloads the capture, resuming with range requests as needed,
+//! until the job expires.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use tokio_util::sync::CancellationToken;
+
+use crate::datetime::DateTime;
+use crate::pcap::FetchProgress;
+use crate::prelude::*;
+
+/// Prefix of job capture files, so a restart can remove those left
+/// behind.
+const FILE_PREFIX: &str = "evebox-pcap-job-";
+
+/// How often expired jobs are removed.
+const REAP_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub(crate) struct JobSettings {
+    /// Where job captures are written.
+    pub(crate) directory: PathBuf,
+    /// How long a finished job, and its capture, is kept.
+    pub(crate) expiry: Duration,
+    /// Jobs kept at once, running or finished.
+    pub(crate) max_jobs: usize,
+}
+
+impl Default for JobSettings {
+    fn default() -> Self {
+        Self {
+            directory: std::env::temp_dir(),
+            expiry: Duration::from_secs(3600),
+            max_jobs: 16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum JobState {
+    /// Waiting for the capture source.
+    Queued,
+    Running,
+    Complete,
+    Failed,
+}
+
+/// A job's status, as returned by `/api/pcap/jobs`.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct JobStatus {
+    pub(crate) id: String,
+    pub(crate) state: JobState,
+    pub(crate) filename: String,
+    pub(crate) created: DateTime,
+    pub(crate) finished: Option<DateTime>,
+    /// When the job and its capture are removed.
+    pub(crate) expires: Option<DateTime>,
+    pub(crate) files_scanned: u32,
+    pub(crate) packets: u64,
+    /// Capture bytes written so far.
+    pub(crate) bytes: u64,
+    pub(crate) truncated: bool,
+    pub(crate) sha256: Option<String>,
+    pub(crate) error: Option<JobError>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct JobError {
+    pub(crate) code: String,
+    pub(crate) message: String,
+}
+
+struct JobInner {
+    state: JobState,
+    finished: Option<(DateTime, Instant)>,
+    truncated: bool,
+    sha256: Option<String>,
+    error: Option<JobError>,
+}
+
+pub(crate) struct PcapJob {
+    pub(crate) id: String,
+    /// The user the job belongs to; only they see it.
+    pub(crate) owner: String,
+    pub(crate) filename: String,
+    pub(crate) pcapng: bool,
+    pub(crate) event_id: Option<String>,
+    created: DateTime,
+    expiry: Duration,
+    /// Updated by a local extraction as it runs; a remote source
+    /// reports its counts when it completes.
+    pub(crate) progress: Arc<FetchProgress>,
+    bytes: AtomicU64,
+    /// Cancelled when the job is deleted.
+    pub(crate) cancel: CancellationToken,
+    /// Removed with the job.
+    file: tempfile::TempPath,
+    inner: Mutex<JobInner>,
+}
+
+impl PcapJob {
+    pub(crate) fn path(&self) -> &Path {
+        &self.file
+    }
+
+    pub(crate) fn state(&self) -> JobState {
+        self.inner.lock().unwrap().state
+    }
+
+    pub(crate) fn set_state(&self, state: JobState) {
+        self.inner.lock().unwrap().state = state;
+    }
+
+    pub(crate) fn add_bytes(&self, bytes: u64) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn complete(&self, truncated: bool, sha256: Option<String>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.state = JobState::Complete;
+        inner.finished = Some((DateTime::now(), Instant::now()));
+        inner.truncated = truncated;
+        inner.sha256 = sha256;
+    }
+
+    pub(crate) fn fail(&self, code: &str, message: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.state = JobState::Failed;
+        inner.finished = Some((DateTime::now(), Instant::now()));
+        inner.error = Some(JobError {
+            code: code.to_string(),
+            message: message.to_string(),
+        });
+    }
+
+    pub(crate) fn status(&self) -> JobStatus {
+        let inner = self.inner.lock().unwrap();
+        let expiry = chrono::Duration::from_std(self.expiry).unwrap_or(chrono::Duration::MAX);
+        JobStatus {
+            id: self.id.clone(),
+            state: inner.state,
+            filename: self.filename.clone(),
+            created: self.created.clone(),
+            finished: inner
+                .finished
+                .as_ref()
+                .map(|(finished, _)| finished.clone()),
+            expires: inner.finished.as_ref().map(|(finished, _)| DateTime {
+                datetime: finished.datetime + expiry,
+            }),
+            files_scanned: self.progress.files_scanned(),
+            packets: self.progress.packets(),
+            bytes: self.bytes(),
+            truncated: inner.truncated,
+            sha256: inner.sha256.clone(),
+            error: inner.error.clone(),
+        }
+    }
+
+    fn expired(&self, now: Instant) -> bool {
+        self.inner
+            .lock()
+            .unwrap()
+            .finished
+            .as_ref()
+            .is_some_and(|(_, finished)| now.duration_since(*finished) >= self.expiry)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum CreateError {
+    TooMany(usize),
+    Io(String),
+}
+
+/// The server's export jobs by id.
+pub(crate) struct JobRegistry {
+    pub(crate) settings: JobSettings,
+    jobs: Mutex<HashMap<String, Arc<PcapJob>>>,
+}
+
+impl Default for JobRegistry {
+    fn default() -> Self {
+        Self::new(JobSettings::default())
+    }
+}
+
+impl JobRegistry {
+    pub(crate) fn new(settings: JobSettings) -> Self {
+        Self {
+            settings,
+            jobs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Register a queued job and create its capture file, returned
+    /// open for writing.
+    pub(crate) fn create(
+        &self,
+        owner: &str,
+        filename: String,
+        pcapng: bool,
+        event_id: Option<String>,
+    ) -> Result<(Arc<PcapJob>, std::fs::File), CreateError> {
+        self.reap();
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs.len() >= self.settings.max_jobs {
+            return Err(CreateError::TooMany(self.settings.max_jobs));
+        }
+        let (file, path) = tempfile::Builder::new()
+            .prefix(FILE_PREFIX)
+            .tempfile_in(&self.settings.directory)
+            .map_err(|err| {
+                CreateError::Io(format!("{}: {err}", self.settings.directory.display()))
+            })?
+            .into_parts();
+        let job = Arc::new(PcapJob {
+            id: uuid::Uuid::new_v4().to_string(),
+            owner: owner.to_string(),
+            filename,
+            pcapng,
+            event_id,
+            created: DateTime::now(),
+            expiry: self.settings.expiry,
+            progress: Arc::new(FetchProgress::default()),
+            bytes: AtomicU64::new(0),
+            cancel: CancellationToken::new(),
+            file: path,
+            inner: Mutex::new(JobInner {
+                state: JobState::Queued,
+                finished: None,
+                truncated: false,
+                sha256: None,
+                error: None,
+            }),
+        });
+        jobs.insert(job.id.clone(), job.clone());
+        Ok((job, file))
+    }
+
+    /// The owner's job with this id, unless it expired.
+    pub(crate) fn get(&self, owner: &str, id: &str) -> Option<Arc<PcapJob>> {
+        self.reap();
+        self.jobs
+            .lock()
+            .unwrap()
+            .get(id)
+            .filter(|job| job.owner == owner)
+            .cloned()
+    }
+
+    /// The owner's jobs, newest first.
+    pub(crate) fn list(&self, owner: &str) -> Vec<Arc<PcapJob>> {
+        self.reap();
+        let mut jobs: Vec<_> = self
+            .jobs
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|job| job.owner == owner)
+            .cloned()
+            .collect();
+        jobs.sort_by_key(|job| std::cmp::Reverse(job.created.datetime));
+        jobs
+    }
+
+    /// Remove the owner's job, cancelling it if it is still running. Its
+    /// capture file goes with the last reference.
+    pub(crate) fn remove(&self, owner: &str, id: &str) -> Option<Arc<PcapJob>> {
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs.get(id).is_none_or(|job| job.owner != owner) {
+            return None;
+        }
+        let job = jobs.remove(id)?;
+        job.cancel.cancel();
+        Some(job)
+    }
+
+    fn reap(&self) {
+        let now = Instant::now();
+        self.jobs.lock().unwrap().retain(|_, job| !job.expired(now));
+    }
+
+    /// Remove expired jobs every interval. Runs forever.
+    pub(crate) async fn run_reaper(&self) {
+        let mut interval = tokio::time::interval(REAP_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.reap();
+        }
+    }
+}
+
+/// Read `pcap.jobs`. Captures default to `pcap-jobs` in the data
+/// directory, else the system temporary directory; files left by a
+/// previous run are removed.
+pub(crate) fn configure(
+    config: &crate::config::Config,
+    data_directory: Option<&str>,
+) -> Result<JobSettings> {
+    let mut settings = JobSettings::default();
+    match config
+        .get::<String>("pcap.jobs.directory")?
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+    {
+        Some(directory) => settings.directory = PathBuf::from(directory),
+        None => {
+            if let Some(data_directory) = data_directory {
+                settings.directory = PathBuf::from(data_directory).join("pcap-jobs");
+            }
+        }
+    }
+    if let Some(expiry) = config.get_value::<String>("pcap.jobs.expiry")? {
+        settings.expiry = humantime::parse_duration(expiry.trim())
+            .map_err(|err| anyhow!("bad pcap.jobs.expiry: {err}"))?;
+    }
+    if let Some(max_jobs) = config.get_value::<usize>("pcap.jobs.max-jobs")? {
+        settings.max_jobs = max_jobs;
+    }
+    std::fs::create_dir_all(&settings.directory)
+        .map_err(|err| anyhow!("failed to create {}: {err}", settings.directory.display()))?;
+    remove_stale_files(&settings.directory);
+    Ok(settings)
+}
+
+fn remove_stale_files(directory: &Path) {
+    let Ok(entries) = std::fs::read_dir(directory) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if entry.file_name().to_string_lossy().starts_with(FILE_PREFIX)
+            && let Err(err) = std::fs::remove_file(entry.path())
+        {
+            warn!(
+                "Failed to remove stale PCAP job file {}: {err}",
+                entry.path().display()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn registry(dir: &Path, expiry: Duration) -> JobRegistry {
+        JobRegistry::new(JobSettings {
+            directory: dir.to_path_buf(),
+            expiry,
+            max_jobs: 2,
+        })
+    }
+
+    #[test]
+    fn jobs_belong_to_their_owner_and_expire() {
+        let dir = tempfile::tempdir().unwrap();
+        let jobs = registry(dir.path(), Duration::ZERO);
+
+        let (job, _file) = jobs
+            .create("alice", "capture.pcap".to_string(), false, None)
+            .unwrap();
+        let path = job.path().to_path_buf();
+        assert!(path.exists());
+        assert!(jobs.get("bob", &job.id).is_none());
+        assert!(jobs.remove("bob", &job.id).is_none());
+        assert_eq!(jobs.list("alice").len(), 1);
+
+        jobs.create("alice", "other.pcap".to_string(), false, None)
+            .unwrap();
+        assert_eq!(
+            jobs.create("bob", "third.pcap".to_string(), false, None)
+                .err(),
+            Some(CreateError::TooMany(2))
+        );
+
+        // A running job never expires; a finished one goes after the
+        // expiry, and its file with the last reference.
+        job.add_bytes(24);
+        job.complete(false, Some("ab".repeat(32)));
+        let status = job.status();
+        assert_eq!(status.state, JobState::Complete);
+        assert_eq!(status.bytes, 24);
+        assert!(status.expires.is_some());
+        assert!(jobs.get("alice", &job.id).is_none());
+        assert_eq!(jobs.list("alice").len(), 1);
+        drop(job);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn removing_a_job_cancels_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let jobs = registry(dir.path(), Duration::from_secs(60));
+        let (job, _file) = jobs
+            .create("alice", "capture.pcap".to_string(), false, None)
+            .unwrap();
+        let removed = jobs.remove("alice", &job.id).unwrap();
+        assert!(removed.cancel.is_cancelled());
+        assert!(jobs.list("alice").is_empty());
+    }
+
+    #[test]
+    fn stale_files_are_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let stale = dir.path().join(format!("{FILE_PREFIX}abc"));
+        let other = dir.path().join("keep.pcap");
+        std::fs::write(&stale, b"x").unwrap();
+        std::fs::write(&other, b"x").unwrap();
+        remove_stale_files(dir.path());
+        assert!(!stale.exists());
+        assert!(other.exists());
+    }
+}
@@ -3,14 +3,20 @@
 
 //! Packet-capture source routing, limits, and remote task coordination.
 
+pub(crate) mod audit;
+pub(crate) mod evidence;
+pub(crate) mod jobs;
+pub(crate) mod retention;
 pub(crate) mod tasks;
 
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::time::Duration;
 
 use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 
-use crate::pcap::{PcapSource, SpoolConfig};
+use crate::pcap::retention::RetentionSettings;
+use crate::pcap::{PcapSource, SpoolConfig, SpoolUsage};
 use crate::prelude::*;
 use crate::server::agents::{AgentEntry, AgentRegistry, LOCAL_PCAP_SOURCE_NAME};
 
@@ -37,11 +43,25 @@ impl ResolvedPcapSource {
     /// One in-flight extraction slot per source: the local spool serializes
     /// its disk work just like each remote agent serializes its own.
     pub(crate) fn try_acquire(&self) -> Option<OwnedSemaphorePermit> {
-        let busy = match self {
+        self.busy().clone().try_acquire_owned().ok()
+    }
+
+    /// Wait up to `timeout` for the source's extraction slot to be free,
+    /// without taking it. A bundle extracts its flows one after another,
+    /// and each extraction's supervisor releases the slot just after its
+    /// response body completes.
+    pub(crate) async fn wait_idle(&self, timeout: Duration) -> bool {
+        matches!(
+            tokio::time::timeout(timeout, self.busy().acquire()).await,
+            Ok(Ok(_))
+        )
+    }
+
+    fn busy(&self) -> &Arc<Semaphore> {
+        match self {
             Self::Local { busy, .. } => busy,
             Self::Agent(entry) => &entry.pcap_busy,
-        };
-        busy.clone().try_acquire_owned().ok()
+        }
     }
 }
 
@@ -125,10 +145,33 @@ impl Default for PcapSettings {
     }
 }
 
+/// An uploaded capture, served under the synthetic sensor name its
+/// events were imported with.
+struct AnalysisSource {
+    sensor: String,
+    source: PcapSource,
+    busy: Arc<Semaphore>,
+}
+
 /// The one server-local packet capture source together with its extraction limits.
 pub(crate) struct PcapService {
     pub(crate) settings: PcapSettings,
+    /// Retention of the local spool and the escalated flows protected on
+    /// every spool.
+    pub(crate) retention: RetentionSettings,
+    /// Where captures are pinned for escalated events; `None` when
+    /// pinning is off.
+    pub(crate) evidence: Option<evidence::EvidenceStore>,
+    /// Where every request's audit record is sent.
+    pub(crate) audit: audit::AuditLog,
+    /// Asynchronous export jobs and their captures.
+    pub(crate) jobs: jobs::JobRegistry,
     source: Option<PcapSource>,
+    /// Captures uploaded for analysis by analysis id, each serving the
+    /// events imported from it.
+    analyses: std::sync::RwLock<HashMap<String, AnalysisSource>>,
+    /// Local spool usage after the last retention pass.
+    spool_usage: std::sync::RwLock<Option<SpoolUsage>>,
     routing: std::sync::RwLock<PcapRouting>,
     /// Held across a routing-table save (configdb write then
     /// `set_routing`) so concurrent saves cannot interleave and leave
@@ -154,7 +197,13 @@ impl PcapService {
         let global = Arc::new(Semaphore::new(settings.max_concurrent));
         Self {
             settings,
+            retention: RetentionSettings::default(),
+            evidence: None,
+            audit: audit::AuditLog::default(),
+            jobs: jobs::JobRegistry::default(),
             source,
+            analyses: std::sync::RwLock::new(HashMap::new()),
+            spool_usage: std::sync::RwLock::new(None),
             routing: std::sync::RwLock::new(PcapRouting::default()),
             routing_save: tokio::sync::Mutex::new(()),
             global,
@@ -178,6 +227,33 @@ impl PcapService {
         self.source.as_ref()
     }
 
+    /// The local spool, when it has sidecar indexing enabled.
+    #[cfg(not(windows))]
+    pub(crate) fn indexed_spool(&self) -> Option<SpoolConfig> {
+        match &self.source {
+            Some(PcapSource::Spool(spool)) if spool.index => Some(spool.clone()),
+            _ => None,
+        }
+    }
+
+    /// The local spool, if the local source is one.
+    #[cfg(not(windows))]
+    pub(crate) fn spool(&self) -> Option<&SpoolConfig> {
+        match &self.source {
+            Some(PcapSource::Spool(spool)) => Some(spool),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn set_spool_usage(&self, usage: SpoolUsage) {
+        *self.spool_usage.write().unwrap() = Some(usage);
+    }
+
+    /// Local spool usage, once retention has run.
+    pub(crate) fn spool_usage(&self) -> Option<SpoolUsage> {
+        *self.spool_usage.read().unwrap()
+    }
+
     pub(crate) fn has_source(&self) -> bool {
         self.source.is_some()
     }
@@ -187,6 +263,62 @@ impl PcapService {
         self.global.clone().try_acquire_owned().ok()
     }
 
+    /// Serve the events of an analysis from its uploaded capture.
+    pub(crate) fn add_analysis(&self, id: &str, sensor: &str, paths: Vec<PathBuf>) {
+        self.analyses.write().unwrap().insert(
+            id.to_string(),
+            AnalysisSource {
+                sensor: sensor.to_string(),
+                source: PcapSource::Files(paths),
+                busy: Arc::new(Semaphore::new(1)),
+            },
+        );
+    }
+
+    /// True when an analysis capture can serve downloads. Local
+    /// extraction, and so an analysis capture, is unavailable on Windows.
+    pub(crate) fn has_analyses(&self) -> bool {
+        cfg!(not(windows)) && !self.analyses.read().unwrap().is_empty()
+    }
+
+    /// The sensor names of the analysis captures, sorted.
+    pub(crate) fn analysis_sources(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .analyses
+            .read()
+            .unwrap()
+            .values()
+            .map(|analysis| analysis.sensor.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn analysis_source(&self, id: &str) -> Option<ResolvedPcapSource> {
+        self.analyses
+            .read()
+            .unwrap()
+            .get(id)
+            .map(|analysis| ResolvedPcapSource::Local {
+                name: analysis.sensor.clone(),
+                source: analysis.source.clone(),
+                busy: analysis.busy.clone(),
+            })
+    }
+
+    fn analysis_source_by_sensor(&self, sensor: &str) -> Option<ResolvedPcapSource> {
+        self.analyses
+            .read()
+            .unwrap()
+            .values()
+            .find(|analysis| analysis.sensor == sensor)
+            .map(|analysis| ResolvedPcapSource::Local {
+                name: analysis.sensor.clone(),
+                source: analysis.source.clone(),
+                busy: analysis.busy.clone(),
+            })
+    }
+
     /// The local capture input as a resolvable source, when one is configured.
     /// It always carries the reserved name `(server)` rather than a sensor
     /// identity of its own.
@@ -199,20 +331,22 @@ impl PcapService {
     }
 
     /// The source a name selects right now: the reserved `(server)`
-    /// name is the local spool, anything else a live agent advertising
-    /// the `pcap` capability.
+    /// name is the local spool, then an analysis capture's sensor name,
+    /// anything else a live agent advertising the `pcap` capability.
     fn source_by_name(&self, agents: &AgentRegistry, name: &str) -> Option<ResolvedPcapSource> {
         if name == LOCAL_PCAP_SOURCE_NAME {
             self.local_source()
         } else {
-            agents.pcap_agent(name).map(ResolvedPcapSource::Agent)
+            self.analysis_source_by_sensor(name)
+                .or_else(|| agents.pcap_agent(name).map(ResolvedPcapSource::Agent))
         }
     }
 
     /// Resolve a request across the optional server-local spool and live
     /// agents advertising the `pcap` capability.
     ///
-    /// An explicit source name always wins. Otherwise, when the operator
+    /// An explicit source name always wins, then the capture an analyzed
+    /// event was imported from. Otherwise, when the operator
     /// routing table is present it is fully in control: the first rule
     /// whose sensor equals the event's identity, else the default
     /// source, else no source. Without a table the implicit heuristics
@@ -240,6 +374,12 @@ impl PcapService {
 
         let identity = event.and_then(sensor_identity);
 
+        if let Some(id) = event.and_then(stamped_analysis_id) {
+            return self
+                .analysis_source(id)
+                .ok_or_else(|| RouteError::NoSource(identity.map(str::to_string)));
+        }
+
         {
             let routing = self.routing.read().unwrap();
             if !routing.is_empty() {
@@ -365,6 +505,11 @@ pub(crate) fn stamped_agent_id(source: &serde_json::Value) -> Option<&str> {
     source["evebox"]["agent"]["id"].as_str()
 }
 
+/// Analysis id stamped on an event imported from an uploaded capture.
+pub(crate) fn stamped_analysis_id(source: &serde_json::Value) -> Option<&str> {
+    source["evebox"]["analysis"]["id"].as_str()
+}
+
 /// Hostname stamped by an EveBox agent on an imported event.
 pub(crate) fn agent_hostname(source: &serde_json::Value) -> Option<&str> {
     source["evebox"]["agent"]["hostname"].as_str()
@@ -417,11 +562,22 @@ pub(crate) fn configure(config: &crate::config::Config) -> PcapService {
             })
             .map(|value| value.trim().to_string())
             .filter(|value| !value.is_empty());
+        let index = config.get_bool("pcap.index").unwrap_or_else(|err| {
+            warn!("Ignoring bad pcap.index: {err}");
+            false
+        });
         info!("Serving pcap from local spool {}", directory.display());
-        SpoolConfig::new(directory, prefix)
+        let mut spool = SpoolConfig::new(directory, prefix);
+        spool.index = index;
+        spool
     });
 
-    PcapService::new(PcapSettings::default(), spool.map(PcapSource::Spool))
+    let mut service = PcapService::new(PcapSettings::default(), spool.map(PcapSource::Spool));
+    service.retention = crate::pcap::retention::configure(config).unwrap_or_else(|err| {
+        warn!("Ignoring bad pcap.retention: {err}; no files will be deleted");
+        RetentionSettings::default()
+    });
+    service
 }
 
 #[cfg(test)]
@@ -534,6 +690,49 @@ mod test {
         ));
     }
 
+    #[test]
+    fn resolver_serves_analyzed_events_from_their_capture() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = PcapService::new(
+            PcapSettings::default(),
+            Some(PcapSource::Spool(SpoolConfig::new(dir.path(), None))),
+        );
+        let agents = AgentRegistry::default();
+        let capture = dir.path().join("capture.pcap");
+        service.add_analysis("abc", "analysis-abc", vec![capture.clone()]);
+        service.set_routing(PcapRouting {
+            rules: vec![],
+            default: Some(LOCAL_PCAP_SOURCE_NAME.to_string()),
+        });
+
+        // The analysis stamp wins over the routing table.
+        let event = serde_json::json!({
+            "host": "analysis-abc",
+            "evebox": { "analysis": { "id": "abc" } }
+        });
+        let Ok(ResolvedPcapSource::Local { name, source, .. }) =
+            service.resolve_source(&agents, Some(&event), None)
+        else {
+            panic!("expected the analysis capture");
+        };
+        assert_eq!(name, "analysis-abc");
+        assert!(matches!(source, PcapSource::Files(paths) if paths == [capture]));
+        assert!(matches!(
+            service.resolve_source(&agents, None, Some("analysis-abc")),
+            Ok(ResolvedPcapSource::Local { name, .. }) if name == "analysis-abc"
+        ));
+
+        let gone = serde_json::json!({
+            "host": "analysis-def",
+            "evebox": { "analysis": { "id": "def" } }
+        });
+        assert!(matches!(
+            service.resolve_source(&agents, Some(&gone), None),
+            Err(RouteError::NoSource(Some(name))) if name == "analysis-def"
+        ));
+        assert_eq!(service.analysis_sources(), ["analysis-abc"]);
+    }
+
     #[test]
     fn resolver_serves_unstamped_events_from_local_spool() {
         // An event without an EveBox agent-hostname stamp was ingested by
@@ -0,0 +1,273 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! Server-managed PCAP spool retention.
+//!
+//! Each interval the server derives the packet windows of escalated
+//! events, routes them to the source holding their packets the same
+//! way a capture request is routed, pushes each agent its windows, and
+//! enforces the retention policy on its own spool.
+
+use std::collections::HashMap;
+
+use crate::agent::protocol::{CAPABILITY_PCAP_RETENTION, ServerMessage};
+use crate::eventrepo::EventQueryParams;
+use crate::pcap::retention::{MAX_PROTECTED_WINDOWS, ProtectedWindows};
+use crate::prelude::*;
+use crate::queryparser::{QueryElement, QueryValue};
+use crate::server::ServerContext;
+use crate::server::agents::LOCAL_PCAP_SOURCE_NAME;
+use crate::server::pcap::RouteError;
+
+/// Escalated events considered per pass, newest first.
+const MAX_ESCALATED_EVENTS: u64 = 10_000;
+
+/// Slack around each event's derived window, matching the spool file
+/// selection margin of an extraction.
+const WINDOW_MARGIN_US: u64 = 60_000_000;
+
+/// Enforce retention and refresh protected windows every interval. Runs
+/// forever.
+pub(crate) async fn run(context: Arc<ServerContext>) {
+    let settings = context.pcap.retention.clone();
+    let mut interval = tokio::time::interval(settings.interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // The last list computed; kept when a refresh fails.
+    let mut protected: Option<HashMap<String, ProtectedWindows>> = None;
+    loop {
+        interval.tick().await;
+        let agents: Vec<_> = context
+            .agents
+            .pcap_agents()
+            .into_iter()
+            .filter(|entry| entry.supports(CAPABILITY_PCAP_RETENTION))
+            .collect();
+        if !context.pcap.has_source() && agents.is_empty() {
+            continue;
+        }
+
+        if settings.protect_escalated {
+            match escalated_windows(&context).await {
+                Ok(windows) => protected = Some(windows),
+                Err(err) => warn!("PCAP retention: failed to load escalated events: {err}"),
+            }
+        } else {
+            protected = Some(HashMap::new());
+        }
+
+        if let Some(protected) = &protected {
+            context.agents.set_protected(protected.clone());
+            for entry in &agents {
+                let windows = protected.get(&entry.name).cloned().unwrap_or_default();
+                if let Err(err) = entry.try_send(ServerMessage::pcap_protect(&windows)) {
+                    warn!(
+                        "Could not queue protected windows for agent {:?}: {err}",
+                        entry.name
+                    );
+                }
+            }
+        }
+
+        #[cfg(not(windows))]
+        enforce_local(&context, protected.as_ref()).await;
+    }
+}
+
+/// Enforce the policy on the local spool and record its usage. Until the
+/// protected windows are known only the minimum free space is enforced.
+#[cfg(not(windows))]
+async fn enforce_local(
+    context: &ServerContext,
+    protected: Option<&HashMap<String, ProtectedWindows>>,
+) {
+    let Some(spool) = context.pcap.spool().cloned() else {
+        return;
+    };
+    let (policy, protected) = match protected {
+        Some(protected) => (
+            context.pcap.retention.policy.clone(),
+            protected
+                .get(LOCAL_PCAP_SOURCE_NAME)
+                .cloned()
+                .unwrap_or_default(),
+        ),
+        None => (
+            crate::pcap::retention::RetentionPolicy {
+                min_free: context.pcap.retention.policy.min_free,
+                ..Default::default()
+            },
+            ProtectedWindows::default(),
+        ),
+    };
+    let result = tokio::task::spawn_blocking(move || {
+        crate::pcap::retention::enforce(&spool, &policy, &protected)
+    })
+    .await;
+    match result {
+        Ok(Ok(usage)) => context.pcap.set_spool_usage(usage),
+        Ok(Err(err)) => warn!("PCAP retention failed: {err}"),
+        Err(err) => error!("PCAP retention panicked: {err}"),
+    }
+}
+
+/// The packet windows of escalated events, by the name of the source
+/// holding their packets. Events whose source is disconnected are kept
+/// under its name so its files stay protected across the reconnect.
+async fn escalated_windows(context: &ServerContext) -> Result<HashMap<String, ProtectedWindows>> {
+    let params = EventQueryParams {
+        size: Some(MAX_ESCALATED_EVENTS),
+        query_string: vec![QueryElement {
+            negated: false,
+            value: QueryValue::Escalated,
+        }],
+        ..Default::default()
+    };
+    let mut response = context.datastore.events(params).await?;
+    let events = match response["events"].take() {
+        serde_json::Value::Array(events) => events,
+        _ => Vec::new(),
+    };
+    if events.len() as u64 >= MAX_ESCALATED_EVENTS {
+        warn!(
+            "PCAP retention: only the newest {MAX_ESCALATED_EVENTS} escalated events are protected"
+        );
+    }
+
+    let mut windows: HashMap<String, Vec<(u64, u64)>> = HashMap::new();
+    for mut event in events {
+        let event = event["_source"].take();
+        let Ok(window) = crate::pcap::derive_window(&event) else {
+            continue;
+        };
+        let start = micros(window.start.to_nanos()).saturating_sub(WINDOW_MARGIN_US);
+        let end = micros(window.end.to_nanos()).saturating_add(WINDOW_MARGIN_US);
+        let names = match context
+            .pcap
+            .resolve_source(&context.agents, Some(&event), None)
+        {
+            Ok(source) => vec![source.name().to_string()],
+            Err(RouteError::NoSource(Some(name))) => vec![name],
+            Err(RouteError::Ambiguous(names)) => names,
+            Err(RouteError::NoSource(None) | RouteError::NoRule(_)) => continue,
+        };
+        for name in names {
+            windows.entry(name).or_default().push((start, end));
+        }
+    }
+    Ok(windows
+        .into_iter()
+        .map(|(name, windows)| (name, ProtectedWindows::new(windows, MAX_PROTECTED_WINDOWS)))
+        .collect())
+}
+
+fn micros(nanos: i64) -> u64 {
+    u64::try_from(nanos / 1000).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    use crate::eventrepo::EventRepo;
+    use crate::server::ServerConfig;
+    use crate::server::metrics::Metrics;
+    use crate::server::pcap::{PcapService, PcapSettings};
+    use crate::sqlite::connection::{ConnectionBuilder, init_event_db};
+    use crate::sqlite::eventrepo::SqliteEventRepo;
+
+    const T0_US: u64 = 1_700_000_000_000_000;
+
+    fn event(agent: Option<&str>) -> serde_json::Value {
+        let mut event = json!({
+            "timestamp": "2023-11-14T22:15:20.000000+0000",
+            "event_type": "alert",
+            "proto": "UDP",
+            "src_ip": "10.1.1.5",
+            "src_port": 4000,
+            "dest_ip": "192.0.2.10",
+            "dest_port": 53,
+            "flow_id": 987654321i64,
+            "flow": { "start": "2023-11-14T22:13:20.000000+0000" },
+            "alert": { "signature_id": 2000001 },
+        });
+        if let Some(agent) = agent {
+            event["evebox"] = json!({ "agent": { "id": agent } });
+        }
+        event
+    }
+
+    async fn context(dir: &std::path::Path, events: Vec<serde_json::Value>) -> ServerContext {
+        let builder = ConnectionBuilder::filename(Some(&dir.join("events.sqlite")));
+        let mut writer = builder.open_connection(true).await.unwrap();
+        init_event_db(&mut writer).await.unwrap();
+        let pool = builder.open_pool(false).await.unwrap();
+        let writer = Arc::new(tokio::sync::Mutex::new(writer));
+        let datastore = EventRepo::SQLite(SqliteEventRepo::new(
+            writer,
+            pool,
+            Arc::new(Metrics::default()),
+        ));
+        let mut sink = datastore.get_importer().unwrap();
+        for event in events {
+            sink.submit(event).await.unwrap();
+        }
+        sink.commit().await.unwrap();
+
+        let configdb = crate::sqlite::configdb::open(Some(&dir.join("config.sqlite")))
+            .await
+            .unwrap();
+        let mut context = ServerContext::new(
+            ServerConfig::default(),
+            Arc::new(configdb),
+            datastore,
+            Arc::new(Metrics::default()),
+        );
+        context.pcap = Arc::new(PcapService::new(
+            PcapSettings::default(),
+            Some(crate::pcap::PcapSource::Spool(
+                crate::pcap::SpoolConfig::new(dir, None),
+            )),
+        ));
+        context
+    }
+
+    #[tokio::test]
+    async fn escalated_windows_are_routed_by_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let context = context(
+            dir.path(),
+            vec![event(None), event(Some("edge-a")), event(Some("edge-b"))],
+        )
+        .await;
+
+        // Nothing is escalated yet.
+        assert!(escalated_windows(&context).await.unwrap().is_empty());
+
+        let response = context
+            .datastore
+            .events(EventQueryParams::default())
+            .await
+            .unwrap();
+        for event in response["events"].as_array().unwrap() {
+            if event["_source"]["evebox"]["agent"]["id"] != "edge-b" {
+                let id = event["_id"].as_i64().unwrap().to_string();
+                context.datastore.escalate_event_by_id(&id).await.unwrap();
+            }
+        }
+
+        // The unstamped event is the local spool's; the stamped one
+        // stays with its disconnected agent.
+        let windows = escalated_windows(&context).await.unwrap();
+        let mut names: Vec<&str> = windows.keys().map(String::as_str).collect();
+        names.sort();
+        assert_eq!(names, [LOCAL_PCAP_SOURCE_NAME, "edge-a"]);
+        for windows in windows.values() {
+            assert_eq!(windows.windows().len(), 1);
+            let (start, end) = windows.windows()[0];
+            // flow.start - 1s and timestamp + 1m, widened by the margin.
+            assert_eq!(start, T0_US - 1_000_000 - WINDOW_MARGIN_US);
+            assert_eq!(end, T0_US + 180_000_000 + WINDOW_MARGIN_US);
+        }
+    }
+}
@@ -0,0 +1,415 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! Running behind a reverse proxy: the client address reported in
+//! `X-Forwarded-For`, and login by a header the proxy sets once it has
+//! authenticated the user. Both are only taken from trusted proxies.
+
+use std::net::{IpAddr, SocketAddr};
+
+use axum::http::{HeaderMap, HeaderName};
+
+use crate::prelude::*;
+use crate::server::ServerConfig;
+use crate::server::session::GroupRoles;
+use crate::sqlite::configdb::{ConfigDb, ConfigDbError, User};
+
+/// The source of users provisioned through the proxy.
+pub(crate) const SOURCE: &str = "proxy";
+
+/// An IP network, `address/prefix`, or a single address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub(crate) fn contains(&self, addr: IpAddr) -> bool {
+        let addr = match addr {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(addr),
+            addr => addr,
+        };
+        match (self.network, addr) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::str::FromStr for Cidr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (network, prefix) = match s.split_once('/') {
+            Some((network, prefix)) => (network, Some(prefix)),
+            None => (s, None),
+        };
+        let network: IpAddr = network
+            .parse()
+            .map_err(|_| anyhow!("invalid network {s:?}"))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| anyhow!("invalid prefix length in {s:?}"))?,
+            None => max,
+        };
+        Ok(Self { network, prefix })
+    }
+}
+
+/// Whether a peer may report the client address and user: with
+/// `http.reverse-proxy` enabled, a peer in `http.trusted-proxies`, or any
+/// peer when none are listed.
+pub(crate) fn is_trusted(config: &ServerConfig, peer: IpAddr) -> bool {
+    config.http_reverse_proxy
+        && (config.trusted_proxies.is_empty()
+            || config
+                .trusted_proxies
+                .iter()
+                .any(|network| network.contains(peer)))
+}
+
+/// The client's address. From a trusted proxy this is the last address in
+/// `X-Forwarded-For` that isn't itself a trusted proxy, as addresses
+/// before it may have been made up by the client; otherwise the peer.
+pub(crate) fn client_addr(config: &ServerConfig, headers: &HeaderMap, peer: SocketAddr) -> String {
+    if !is_trusted(config, peer.ip()) {
+        return peer.to_string();
+    }
+    let forwarded: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .collect();
+    for addr in forwarded.iter().rev() {
+        let ip = addr
+            .parse::<IpAddr>()
+            .ok()
+            .or_else(|| addr.parse::<SocketAddr>().ok().map(|addr| addr.ip()));
+        match ip {
+            Some(ip)
+                if config
+                    .trusted_proxies
+                    .iter()
+                    .any(|network| network.contains(ip)) => {}
+            _ => return addr.to_string(),
+        }
+    }
+    forwarded
+        .first()
+        .map(|addr| addr.to_string())
+        .unwrap_or_else(|| peer.to_string())
+}
+
+/// The client's address as `client_addr`, without a port, for counting
+/// requests by client.
+pub(crate) fn client_ip(config: &ServerConfig, headers: &HeaderMap, peer: SocketAddr) -> String {
+    let addr = client_addr(config, headers, peer);
+    match addr.parse::<SocketAddr>() {
+        Ok(addr) => addr.ip().to_string(),
+        Err(_) => addr,
+    }
+}
+
+/// `authentication.proxy` in the configuration file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct ProxyAuthConfig {
+    /// The header with the username.
+    #[serde(default = "default_user_header")]
+    pub user_header: String,
+    /// The header listing the user's groups.
+    pub groups_header: Option<String>,
+    #[serde(default = "default_groups_separator")]
+    pub groups_separator: String,
+    /// `roles`, `default-role` and `auto-provision`.
+    #[serde(flatten)]
+    pub group_roles: GroupRoles,
+}
+
+fn default_user_header() -> String {
+    "x-remote-user".to_string()
+}
+
+fn default_groups_separator() -> String {
+    ",".to_string()
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum ProxyAuthError {
+    #[error("{0}")]
+    Denied(String),
+    #[error("configuration database: {0}")]
+    ConfigDb(#[from] ConfigDbError),
+}
+
+pub(crate) struct ProxyAuth {
+    config: ProxyAuthConfig,
+    user_header: HeaderName,
+    groups_header: Option<HeaderName>,
+}
+
+impl ProxyAuth {
+    pub(crate) fn new(config: ProxyAuthConfig) -> Result<Self> {
+        let user_header = HeaderName::try_from(config.user_header.as_str())
+            .with_context(|| format!("invalid user-header {:?}", config.user_header))?;
+        let groups_header = config
+            .groups_header
+            .as_deref()
+            .map(HeaderName::try_from)
+            .transpose()
+            .with_context(|| format!("invalid groups-header {:?}", config.groups_header))?;
+        Ok(Self {
+            config,
+            user_header,
+            groups_header,
+        })
+    }
+
+    /// Whether a request carries any of the headers, which only trusted
+    /// proxies may set.
+    pub(crate) fn has_headers(&self, headers: &HeaderMap) -> bool {
+        headers.contains_key(&self.user_header)
+            || self
+                .groups_header
+                .as_ref()
+                .is_some_and(|header| headers.contains_key(header))
+    }
+
+    /// The user the proxy logged in, with their groups, if any.
+    pub(crate) fn identity(&self, headers: &HeaderMap) -> Option<(String, Vec<String>)> {
+        let username = headers
+            .get(&self.user_header)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|username| !username.is_empty())?;
+        let groups = self
+            .groups_header
+            .as_ref()
+            .into_iter()
+            .flat_map(|header| headers.get_all(header))
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(self.config.groups_separator.as_str()))
+            .map(str::trim)
+            .filter(|group| !group.is_empty())
+            .map(str::to_string)
+            .collect();
+        Some((username.to_string(), groups))
+    }
+
+    /// The user for the proxy's headers, created on first login when
+    /// auto-provisioning, with the role of their groups. `None` when the
+    /// headers have no user.
+    pub(crate) async fn login(
+        &self,
+        configdb: &ConfigDb,
+        headers: &HeaderMap,
+    ) -> Result<Option<User>, ProxyAuthError> {
+        let Some((username, groups)) = self.identity(headers) else {
+            return Ok(None);
+        };
+        let role = self.config.group_roles.role_for(&groups).ok_or_else(|| {
+            ProxyAuthError::Denied(format!(
+                "no role for user {username:?} with groups {groups:?}"
+            ))
+        })?;
+        configdb
+            .provision_external_user(
+                &username,
+                role,
+                SOURCE,
+                self.config.group_roles.auto_provision,
+            )
+            .await
+            .map(Some)
+            .map_err(|err| match err {
+                ConfigDbError::UserSourceMismatch(..) | ConfigDbError::NoUser(_) => {
+                    ProxyAuthError::Denied(err.to_string())
+                }
+                err => err.into(),
+            })
+    }
+}
+
+/// Parse `http.trusted-proxies`.
+pub(crate) fn trusted_proxies(config: &crate::config::Config) -> Result<Vec<Cidr>> {
+    config
+        .get_value::<Vec<String>>("http.trusted-proxies")?
+        .unwrap_or_default()
+        .iter()
+        .map(|network| network.parse().context("http.trusted-proxies"))
+        .collect()
+}
+
+pub(crate) fn configure(
+    config: &crate::config::Config,
+    server_config: &ServerConfig,
+) -> Result<Option<ProxyAuth>> {
+    if !config.get_bool("authentication.proxy.enabled")? {
+        return Ok(None);
+    }
+    if !server_config.http_reverse_proxy || server_config.trusted_proxies.is_empty() {
+        bail!("authentication.proxy requires http.reverse-proxy and http.trusted-proxies");
+    }
+    if !server_config.authentication_required {
+        bail!("authentication.proxy requires authentication.required");
+    }
+    let proxy = config
+        .get_value::<ProxyAuthConfig>("authentication.proxy")?
+        .unwrap_or_else(|| serde_yaml::from_str("{}").unwrap());
+    info!(
+        "Reverse proxy login enabled with header {}, from {:?}",
+        proxy.user_header, server_config.trusted_proxies
+    );
+    ProxyAuth::new(proxy)
+        .context("authentication.proxy")
+        .map(Some)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::server::session::Role;
+
+    fn config(trusted: &[&str]) -> ServerConfig {
+        ServerConfig {
+            http_reverse_proxy: true,
+            trusted_proxies: trusted.iter().map(|n| n.parse().unwrap()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn forwarded(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn networks_contain_their_addresses() {
+        let network: Cidr = "10.1.0.0/16".parse().unwrap();
+        assert!(network.contains("10.1.200.3".parse().unwrap()));
+        assert!(network.contains("::ffff:10.1.0.1".parse().unwrap()));
+        assert!(!network.contains("10.2.0.1".parse().unwrap()));
+        assert!(!network.contains("::1".parse().unwrap()));
+
+        let host: Cidr = "fd00::1".parse().unwrap();
+        assert!(host.contains("fd00::1".parse().unwrap()));
+        assert!(!host.contains("fd00::2".parse().unwrap()));
+        assert!(
+            "0.0.0.0/0"
+                .parse::<Cidr>()
+                .unwrap()
+                .contains("192.0.2.1".parse().unwrap())
+        );
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("proxy.example.com".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn client_address_is_taken_from_trusted_proxies() {
+        let peer: SocketAddr = "10.0.0.2:4000".parse().unwrap();
+        let config = config(&["10.0.0.0/24"]);
+
+        // The last address not a trusted proxy, skipping one the client
+        // made up.
+        let headers = forwarded("1.1.1.1, 192.0.2.7, 10.0.0.5");
+        assert_eq!(client_addr(&config, &headers, peer), "192.0.2.7");
+        assert_eq!(
+            client_addr(&config, &HeaderMap::new(), peer),
+            "10.0.0.2:4000"
+        );
+
+        // Not from an untrusted peer, or without reverse proxy support.
+        let untrusted: SocketAddr = "192.0.2.50:4000".parse().unwrap();
+        assert_eq!(client_addr(&config, &headers, untrusted), "192.0.2.50:4000");
+        let disabled = ServerConfig::default();
+        assert_eq!(client_addr(&disabled, &headers, peer), "10.0.0.2:4000");
+
+        // Any peer is trusted when none are listed.
+        assert_eq!(
+            client_addr(&self::config(&[]), &headers, untrusted),
+            "10.0.0.5"
+        );
+    }
+
+    #[tokio::test]
+    async fn users_are_provisioned_from_headers() {
+        let proxy = ProxyAuth::new(
+            serde_yaml::from_str(
+                r#"
+                user-header: X-Forwarded-User
+                groups-header: X-Forwarded-Groups
+                roles:
+                  soc: analyst
+                  admins: admin
+                "#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let configdb = crate::sqlite::configdb::open(Some(&dir.path().join("config.sqlite")))
+            .await
+            .unwrap();
+        let headers = |user: &str, groups: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-forwarded-user", user.parse().unwrap());
+            headers.insert("x-forwarded-groups", groups.parse().unwrap());
+            headers
+        };
+
+        let user = proxy
+            .login(&configdb, &headers("alice", "staff, soc"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.role, Role::Analyst);
+        assert_eq!(
+            configdb.get_user_source("alice").await.unwrap().as_deref(),
+            Some(SOURCE)
+        );
+        let user = proxy
+            .login(&configdb, &headers("alice", "soc,admins"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.role, Role::Admin);
+
+        assert!(proxy.has_headers(&headers("", "soc")));
+        assert!(
+            proxy
+                .login(&configdb, &headers("", "soc"))
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(matches!(
+            proxy.login(&configdb, &headers("carol", "staff")).await,
+            Err(ProxyAuthError::Denied(_))
+        ));
+        configdb
+            .add_user("bob", "secret", Role::Admin)
+            .await
+            .unwrap();
+        assert!(matches!(
+            proxy.login(&configdb, &headers("bob", "admins")).await,
+            Err(ProxyAuthError::Denied(_))
+        ));
+    }
+}
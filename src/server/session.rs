@@ -1,15 +1,68 @@
 // SPDX-FileCopyrightText: (C) 2020 Jason Ish <jason@codemonkey.net>
 // SPDX-License-Identifier: MIT
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use anyhow::Result;
 use base64::prelude::*;
 use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::queryparser::{QueryElement, QueryValue};
+use crate::sqlite::configdb::User;
+
+/// Seconds between saves of a session's last use to the database.
+const TOUCH_INTERVAL: i64 = 60;
+
+/// Login session lifetimes, from `authentication.session`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SessionConfig {
+    /// Sessions unused for this long end; never when unset.
+    pub idle_timeout: Option<Duration>,
+    /// Sessions end this long after login, however used.
+    pub absolute_timeout: Duration,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout: None,
+            absolute_timeout: Duration::from_secs(7 * 86400),
+        }
+    }
+}
+
+impl SessionConfig {
+    pub(crate) fn configure(config: &crate::config::Config) -> Result<Self> {
+        let idle_timeout = config.get_duration("authentication.session.idle-timeout")?;
+        let absolute_timeout = config
+            .get_duration("authentication.session.absolute-timeout")?
+            .unwrap_or(Self::default().absolute_timeout);
+        if idle_timeout.is_some_and(|t| t.is_zero()) || absolute_timeout.is_zero() {
+            bail!("authentication.session: timeouts must be more than 0");
+        }
+        Ok(Self {
+            idle_timeout,
+            absolute_timeout,
+        })
+    }
+
+    pub(crate) fn idle_timeout_secs(&self) -> Option<i64> {
+        self.idle_timeout.map(|t| t.as_secs() as i64)
+    }
+}
+
+struct CachedSession {
+    session: Arc<Session>,
+    last_seen: i64,
+    /// When `last_seen` was last saved to the database.
+    saved: i64,
+}
 
 pub(crate) struct SessionStore {
-    cache: Mutex<HashMap<String, Arc<Session>>>,
+    cache: Mutex<HashMap<String, CachedSession>>,
 }
 
 impl Default for SessionStore {
@@ -25,31 +78,166 @@ impl SessionStore {
         }
     }
 
-    pub fn put(&self, session: Arc<Session>) -> Result<()> {
+    /// Cache a session, last used at `now`, as saved in the database.
+    pub fn put(&self, session: Arc<Session>, now: i64) -> Result<()> {
         let mut cache = self.cache.lock().unwrap();
-        if let Some(session_id) = &session.session_id
-            && cache.insert(session_id.to_string(), session).is_some()
-        {
-            return Err(anyhow!("duplicate session-id"));
+        if let Some(session_id) = session.session_id.clone() {
+            let cached = CachedSession {
+                session,
+                last_seen: now,
+                saved: now,
+            };
+            if cache.insert(session_id, cached).is_some() {
+                return Err(anyhow!("duplicate session-id"));
+            }
         }
         Ok(())
     }
 
-    pub fn get(&self, session_id: &str) -> Option<Arc<Session>> {
-        let cache = self.cache.lock().unwrap();
-        cache.get(session_id).cloned()
+    /// A cached session that hasn't timed out, marked as used at `now`.
+    /// A timed out session is dropped. The flag is set when the last use
+    /// is due to be saved to the database.
+    pub fn get_active(
+        &self,
+        session_id: &str,
+        now: i64,
+        idle_timeout: Option<i64>,
+    ) -> Option<(Arc<Session>, bool)> {
+        let mut cache = self.cache.lock().unwrap();
+        let cached = cache.get_mut(session_id)?;
+        let expired = cached.session.expires_at.is_some_and(|at| now >= at)
+            || idle_timeout.is_some_and(|idle| now - cached.last_seen >= idle);
+        if expired {
+            cache.remove(session_id);
+            return None;
+        }
+        cached.last_seen = now;
+        let save = now - cached.saved >= TOUCH_INTERVAL;
+        if save {
+            cached.saved = now;
+        }
+        Some((cached.session.clone(), save))
     }
 
     pub fn delete(&self, session_id: &str) -> bool {
         let mut cache = self.cache.lock().unwrap();
         cache.remove(session_id).is_some()
     }
+
+    /// Drop a user's cached sessions, so they are reloaded from the
+    /// database with the user's current role.
+    pub fn evict_user(&self, username: &str) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, cached| cached.session.username.as_deref() != Some(username));
+    }
+}
+
+/// A user's role. Each role includes the permissions of those before it:
+/// viewers read events, analysts also triage them (archive, escalate,
+/// comment), pcap users also download packets, and admins also manage
+/// users, agents, filters and the datastore.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Role {
+    #[default]
+    Viewer,
+    Analyst,
+    Pcap,
+    Admin,
+}
+
+impl Role {
+    pub(crate) const ALL: [Role; 4] = [Role::Viewer, Role::Analyst, Role::Pcap, Role::Admin];
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Analyst => "analyst",
+            Role::Pcap => "pcap",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Role::ALL
+            .into_iter()
+            .find(|role| role.as_str() == s)
+            .ok_or_else(|| {
+                anyhow!("unknown role {s:?}, expected one of viewer, analyst, pcap, admin")
+            })
+    }
+}
+
+impl TryFrom<String> for Role {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+/// How users from an external login (the proxy, OIDC or LDAP) get their
+/// role from their groups.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct GroupRoles {
+    /// Group to role; a user gets the highest role of their groups.
+    #[serde(default)]
+    pub roles: BTreeMap<String, Role>,
+    /// The role of users in none of the mapped groups. Without it they
+    /// can't log in.
+    pub default_role: Option<Role>,
+    /// Create users on their first login.
+    #[serde(default = "default_auto_provision")]
+    pub auto_provision: bool,
+}
+
+fn default_auto_provision() -> bool {
+    true
+}
+
+impl GroupRoles {
+    /// The highest role of the groups, or the default role.
+    pub(crate) fn role_for(&self, groups: &[String]) -> Option<Role> {
+        groups
+            .iter()
+            .filter_map(|group| self.roles.get(group))
+            .max()
+            .copied()
+            .or(self.default_role)
+    }
 }
 
+/// The least role a route group requires, added as a request extension
+/// by the group's layer and checked by the `SessionExtractor`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RequiredRole(pub(crate) Role);
+
 #[derive(Debug, Default, Clone)]
 pub(crate) struct Session {
     pub session_id: Option<String>,
     pub username: Option<String>,
+    pub role: Role,
+    /// The name of the API token the request authenticated with.
+    pub api_token: Option<String>,
+    /// Unix seconds a login session ends.
+    pub expires_at: Option<i64>,
+    /// The sensors whose events the user may see; None for all of them.
+    pub sensors: Option<Vec<String>>,
+    /// Only the user's password may be changed until it is.
+    pub password_change_required: bool,
 }
 
 impl Session {
@@ -58,23 +246,81 @@ impl Session {
         Session {
             session_id: Some(session_id),
             username: None,
+            role: Role::default(),
+            api_token: None,
+            expires_at: None,
+            sensors: None,
+            password_change_required: false,
         }
     }
 
-    pub fn with_username(username: &str) -> Self {
+    pub fn with_username(username: &str, role: Role) -> Self {
         let session_id = generate_session_id();
         Session {
             session_id: Some(session_id),
             username: Some(username.to_string()),
+            role,
+            api_token: None,
+            expires_at: None,
+            sensors: None,
+            password_change_required: false,
         }
     }
 
+    /// A session for a logged in user, with their role and sensors.
+    pub fn for_user(user: &User) -> Self {
+        let mut session = Self::with_username(&user.username, user.role);
+        session.sensors = user.sensors.clone();
+        session.password_change_required = user.password_change_required;
+        session
+    }
+
+    /// A session without a login, used when authentication is not
+    /// required; it has every permission.
     pub fn anonymous(username: Option<String>) -> Session {
         Session {
             username,
             session_id: None,
+            role: Role::Admin,
+            api_token: None,
+            expires_at: None,
+            sensors: None,
+            password_change_required: false,
         }
     }
+
+    /// The query element limiting queries to the user's sensors, when
+    /// they are limited.
+    pub(crate) fn sensor_filter(&self) -> Option<QueryElement> {
+        self.sensors.as_ref().map(|sensors| QueryElement {
+            negated: false,
+            value: QueryValue::Sensors(sensors.clone()),
+        })
+    }
+
+    /// Whether the user may see events from the sensor `host`; events
+    /// without a host are seen only by users not limited to some
+    /// sensors.
+    pub(crate) fn may_see_sensor(&self, host: Option<&str>) -> bool {
+        sensor_allowed(self.sensors.as_deref(), host)
+    }
+
+    /// Whether the user may see an event, as stored or wrapped with its
+    /// `_source`.
+    pub(crate) fn may_see_event(&self, event: &serde_json::Value) -> bool {
+        let source = event.get("_source").unwrap_or(event);
+        self.may_see_sensor(source["host"].as_str())
+    }
+}
+
+/// Whether a user limited to `sensors`, or to none when None, may see
+/// events from the sensor `host`.
+pub(crate) fn sensor_allowed(sensors: Option<&[String]>, host: Option<&str>) -> bool {
+    match (sensors, host) {
+        (None, _) => true,
+        (Some(sensors), Some(host)) => sensors.iter().any(|sensor| sensor == host),
+        (Some(_), None) => false,
+    }
 }
 
 pub(crate) fn generate_session_id() -> String {
@@ -83,3 +329,73 @@ pub(crate) fn generate_session_id() -> String {
     rng.fill_bytes(&mut buf);
     BASE64_STANDARD.encode(&buf)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cached_sessions_time_out() {
+        let store = SessionStore::new();
+        let mut session = Session::with_username("alice", Role::Viewer);
+        session.expires_at = Some(1000);
+        let id = session.session_id.clone().unwrap();
+        store.put(Arc::new(session), 100).unwrap();
+
+        assert!(matches!(
+            store.get_active(&id, 130, Some(60)),
+            Some((_, false))
+        ));
+        // Used within the idle timeout, and due to be saved.
+        assert!(matches!(
+            store.get_active(&id, 185, Some(60)),
+            Some((_, true))
+        ));
+        assert!(store.get_active(&id, 245, Some(60)).is_none());
+        assert!(store.get_active(&id, 245, None).is_none());
+
+        let mut session = Session::with_username("alice", Role::Viewer);
+        session.expires_at = Some(1000);
+        let id = session.session_id.clone().unwrap();
+        store.put(Arc::new(session), 100).unwrap();
+        assert!(store.get_active(&id, 999, None).is_some());
+        assert!(store.get_active(&id, 1000, None).is_none());
+    }
+
+    #[test]
+    fn test_sessions_limited_to_sensors() {
+        let unlimited = Session::with_username("alice", Role::Viewer);
+        assert!(unlimited.sensor_filter().is_none());
+        assert!(unlimited.may_see_sensor(None));
+        assert!(unlimited.may_see_event(&serde_json::json!({"host": "b"})));
+
+        let mut limited = Session::with_username("bob", Role::Viewer);
+        limited.sensors = Some(vec!["a".to_string()]);
+        assert_eq!(
+            limited.sensor_filter().unwrap().value,
+            QueryValue::Sensors(vec!["a".to_string()])
+        );
+        assert!(limited.may_see_sensor(Some("a")));
+        assert!(!limited.may_see_sensor(Some("b")));
+        assert!(!limited.may_see_sensor(None));
+        assert!(limited.may_see_event(&serde_json::json!({"_source": {"host": "a"}})));
+        assert!(!limited.may_see_event(&serde_json::json!({"_source": {"host": "b"}})));
+    }
+
+    #[test]
+    fn test_group_roles() {
+        let groups = |groups: &[&str]| -> Vec<String> {
+            groups.iter().map(|group| group.to_string()).collect()
+        };
+        let config: GroupRoles = serde_yaml::from_str("roles: {soc: analyst, ops: admin}").unwrap();
+        assert!(config.auto_provision);
+        assert_eq!(config.role_for(&groups(&["soc", "ops"])), Some(Role::Admin));
+        assert_eq!(config.role_for(&groups(&["soc"])), Some(Role::Analyst));
+        assert_eq!(config.role_for(&groups(&["other"])), None);
+
+        let config: GroupRoles =
+            serde_yaml::from_str("default-role: viewer\nauto-provision: false").unwrap();
+        assert!(!config.auto_provision);
+        assert_eq!(config.role_for(&[]), Some(Role::Viewer));
+    }
+}
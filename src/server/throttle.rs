@@ -0,0 +1,273 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! Failed login throttling.
+//!
+//! Failed password logins are counted per username and per client
+//! address. Too many within the window locks that username, or address,
+//! out of password logins for a while, whether or not the password is
+//! then right. Counts are in memory, so a restart clears them.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::prelude::*;
+
+/// Entries kept before expired ones are pruned.
+const PRUNE_AT: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct LockoutConfig {
+    /// Failures of a username within the window that lock it out; 0 for
+    /// no limit.
+    pub max_failures: u32,
+    /// Failures from a client address within the window that lock it
+    /// out; 0 for no limit.
+    pub max_address_failures: u32,
+    pub window: Duration,
+    pub duration: Duration,
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        Self {
+            max_failures: 5,
+            max_address_failures: 20,
+            window: Duration::from_secs(15 * 60),
+            duration: Duration::from_secs(15 * 60),
+        }
+    }
+}
+
+impl LockoutConfig {
+    /// The `authentication.lockout` configuration, defaults for what's
+    /// unset.
+    pub(crate) fn configure(config: &crate::config::Config) -> Result<Self> {
+        let default = Self::default();
+        let lockout = Self {
+            max_failures: config
+                .get_value("authentication.lockout.max-failures")?
+                .unwrap_or(default.max_failures),
+            max_address_failures: config
+                .get_value("authentication.lockout.max-address-failures")?
+                .unwrap_or(default.max_address_failures),
+            window: config
+                .get_duration("authentication.lockout.window")?
+                .unwrap_or(default.window),
+            duration: config
+                .get_duration("authentication.lockout.duration")?
+                .unwrap_or(default.duration),
+        };
+        if lockout.window.is_zero() || lockout.duration.is_zero() {
+            bail!("authentication.lockout: window and duration must be more than 0");
+        }
+        Ok(lockout)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Username(String),
+    Address(String),
+}
+
+#[derive(Debug)]
+struct Failures {
+    count: u32,
+    window_start: Instant,
+    locked_until: Option<Instant>,
+}
+
+#[derive(Default)]
+pub(crate) struct LoginThrottle {
+    config: LockoutConfig,
+    failures: Mutex<HashMap<Key, Failures>>,
+}
+
+impl LoginThrottle {
+    pub(crate) fn new(config: LockoutConfig) -> Self {
+        Self {
+            config,
+            failures: Default::default(),
+        }
+    }
+
+    /// The key of a username as typed at login, case folded and trimmed
+    /// as LDAP compares usernames, so every spelling shares a count.
+    fn username_key(username: &str) -> Key {
+        Key::Username(username.trim().to_lowercase())
+    }
+
+    fn keys(username: &str, address: &str) -> [Key; 2] {
+        [
+            Self::username_key(username),
+            Key::Address(address.to_string()),
+        ]
+    }
+
+    fn limit(&self, key: &Key) -> u32 {
+        match key {
+            Key::Username(_) => self.config.max_failures,
+            Key::Address(_) => self.config.max_address_failures,
+        }
+    }
+
+    /// The time left when the username or address is locked out.
+    pub(crate) fn check(&self, username: &str, address: &str) -> Option<Duration> {
+        self.check_at(username, address, Instant::now())
+    }
+
+    fn check_at(&self, username: &str, address: &str, now: Instant) -> Option<Duration> {
+        let failures = self.failures.lock().unwrap();
+        Self::keys(username, address)
+            .iter()
+            .filter_map(|key| failures.get(key)?.locked_until)
+            .filter(|until| *until > now)
+            .max()
+            .map(|until| until - now)
+    }
+
+    /// Count a failed login, locking out the username or address at their
+    /// limit.
+    pub(crate) fn failure(&self, username: &str, address: &str) {
+        self.failure_at(username, address, Instant::now())
+    }
+
+    fn failure_at(&self, username: &str, address: &str, now: Instant) {
+        let mut failures = self.failures.lock().unwrap();
+        if failures.len() >= PRUNE_AT {
+            let window = self.config.window;
+            failures.retain(|_, f| {
+                f.locked_until.is_some_and(|until| until > now) || now - f.window_start < window
+            });
+        }
+        for key in Self::keys(username, address) {
+            let limit = self.limit(&key);
+            if limit == 0 {
+                continue;
+            }
+            let entry = failures.entry(key.clone()).or_insert(Failures {
+                count: 0,
+                window_start: now,
+                locked_until: None,
+            });
+            if now - entry.window_start >= self.config.window {
+                entry.count = 0;
+                entry.window_start = now;
+            }
+            entry.count += 1;
+            if entry.count >= limit {
+                entry.locked_until = Some(now + self.config.duration);
+                entry.count = 0;
+                entry.window_start = now;
+                warn!(
+                    "Locking out {:?} for {:?} after {} failed logins",
+                    key, self.config.duration, limit
+                );
+            }
+        }
+    }
+
+    /// Clear a username's failures after a successful login, given the
+    /// username as typed, like `check` and `failure`. The address's are
+    /// kept, so a valid login can't reset guessing at other usernames.
+    pub(crate) fn success(&self, username: &str) {
+        self.failures
+            .lock()
+            .unwrap()
+            .remove(&Self::username_key(username));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle() -> LoginThrottle {
+        LoginThrottle::new(LockoutConfig {
+            max_failures: 3,
+            max_address_failures: 5,
+            window: Duration::from_secs(60),
+            duration: Duration::from_secs(300),
+        })
+    }
+
+    #[test]
+    fn test_username_lockout() {
+        let throttle = throttle();
+        let now = Instant::now();
+        for _ in 0..2 {
+            throttle.failure_at("Alice", "10.0.0.1", now);
+        }
+        assert_eq!(throttle.check_at("alice", "10.0.0.2", now), None);
+        throttle.failure_at("alice", "10.0.0.2", now);
+        assert_eq!(
+            throttle.check_at("ALICE", "10.0.0.3", now + Duration::from_secs(100)),
+            Some(Duration::from_secs(200))
+        );
+        assert_eq!(throttle.check_at("bob", "10.0.0.3", now), None);
+        assert_eq!(
+            throttle.check_at("alice", "10.0.0.3", now + Duration::from_secs(300)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_failures_outside_the_window_are_forgotten() {
+        let throttle = throttle();
+        let now = Instant::now();
+        for i in 0..6 {
+            throttle.failure_at("alice", "10.0.0.1", now + Duration::from_secs(i * 40));
+        }
+        assert_eq!(throttle.check_at("alice", "10.0.0.1", now), None);
+    }
+
+    #[test]
+    fn test_address_lockout_and_success() {
+        let throttle = throttle();
+        let now = Instant::now();
+        for username in ["a", "b", "c", "d"] {
+            throttle.failure_at(username, "10.0.0.1", now);
+        }
+        throttle.failure_at("alice", "10.0.0.1", now);
+        throttle.failure_at("alice", "10.0.0.1", now);
+        assert!(throttle.check_at("zed", "10.0.0.1", now).is_some());
+        assert!(throttle.check_at("zed", "10.0.0.2", now).is_none());
+
+        // Success clears the username, not the address.
+        throttle.success("alice");
+        throttle.failure_at("alice", "10.0.0.2", now);
+        throttle.failure_at("alice", "10.0.0.2", now);
+        assert!(throttle.check_at("alice", "10.0.0.2", now).is_none());
+        assert!(throttle.check_at("alice", "10.0.0.1", now).is_some());
+    }
+
+    #[test]
+    fn test_spellings_of_a_username_share_failures() {
+        let throttle = throttle();
+        let now = Instant::now();
+        throttle.failure_at("Alice", "10.0.0.1", now);
+        throttle.failure_at(" alice ", "10.0.0.2", now);
+        throttle.success("ALICE");
+        throttle.failure_at("alice", "10.0.0.3", now);
+        throttle.failure_at("alice", "10.0.0.3", now);
+        assert_eq!(throttle.check_at("Alice", "10.0.0.4", now), None);
+        throttle.failure_at("alice ", "10.0.0.3", now);
+        assert!(throttle.check_at("Alice", "10.0.0.4", now).is_some());
+    }
+
+    #[test]
+    fn test_zero_disables() {
+        let throttle = LoginThrottle::new(LockoutConfig {
+            max_failures: 0,
+            max_address_failures: 0,
+            ..Default::default()
+        });
+        let now = Instant::now();
+        for _ in 0..100 {
+            throttle.failure_at("alice", "10.0.0.1", now);
+        }
+        assert!(throttle.check_at("alice", "10.0.0.1", now).is_none());
+    }
+}
@@ -0,0 +1,546 @@
+// SPDX-FileCopyrightText: (C) 2026 Jason Ish <jason@codemonkey.net>
+// SPDX-License-Identifier: MIT
+
+//! TOTP (RFC 6238) second factor for local users.
+//!
+//! Users enroll by scanning the provisioning URI into an authenticator
+//! app and confirming a code, getting single use recovery codes for when
+//! the app is lost. When the `config.totp` policy requires it, users
+//! without a second factor must enroll at their next login.
+
+use std::collections::HashSet;
+
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use rand::RngCore;
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::*;
+use crate::sqlite::configdb::{ConfigDb, ConfigDbError, LOCAL_SOURCE, User};
+
+/// The configdb kv key of the TOTP policy.
+pub(crate) const POLICY_KEY: &str = "config.totp";
+
+const ISSUER: &str = "EveBox";
+const PERIOD: u64 = 30;
+const DIGITS: u32 = 6;
+/// Codes from this many steps either side of now are accepted, for
+/// clock drift.
+const SKEW: i64 = 1;
+const SECRET_LEN: usize = 20;
+const RECOVERY_CODES: usize = 10;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct Policy {
+    /// Require all local users to use a second factor.
+    #[serde(default)]
+    pub required: bool,
+}
+
+/// The TOTP policy. One that can't be read is treated as required, so a
+/// bad value doesn't turn the requirement off.
+pub(crate) async fn policy(configdb: &ConfigDb) -> Policy {
+    match configdb.kv_get_config_as_t::<Policy>(POLICY_KEY).await {
+        Ok(policy) => policy.unwrap_or_default(),
+        Err(err) => {
+            error!("Failed to read TOTP policy, requiring TOTP: {:?}", err);
+            Policy { required: true }
+        }
+    }
+}
+
+pub(crate) fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in data.chunks(5) {
+        let mut buf = [0u8; 5];
+        buf[..chunk.len()].copy_from_slice(chunk);
+        let bits = buf.iter().fold(0u64, |acc, byte| (acc << 8) | *byte as u64);
+        let chars = (chunk.len() * 8).div_ceil(5);
+        for i in 0..chars {
+            let index = (bits >> (35 - i * 5)) & 0x1f;
+            out.push(BASE32_ALPHABET[index as usize] as char);
+        }
+    }
+    out
+}
+
+/// Decode base32, ignoring case, spaces and padding as authenticator
+/// apps show secrets.
+pub(crate) fn base32_decode(input: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut bits = 0u64;
+    let mut count = 0;
+    for c in input.chars().filter(|c| !matches!(c, ' ' | '-' | '=')) {
+        let c = c.to_ascii_uppercase() as u8;
+        let value = BASE32_ALPHABET.iter().position(|a| *a == c)?;
+        bits = (bits << 5) | value as u64;
+        count += 5;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+            bits &= (1 << count) - 1;
+        }
+    }
+    Some(out)
+}
+
+/// A new random secret, base32 encoded.
+pub(crate) fn generate_secret() -> String {
+    let mut buf = [0u8; SECRET_LEN];
+    rand::rng().fill_bytes(&mut buf);
+    base32_encode(&buf)
+}
+
+/// The `otpauth://` URI authenticator apps scan from a QR code.
+pub(crate) fn provisioning_uri(username: &str, secret: &str) -> String {
+    let label = utf8_percent_encode(&format!("{ISSUER}:{username}"), NON_ALPHANUMERIC).to_string();
+    format!(
+        "otpauth://totp/{label}?secret={secret}&issuer={ISSUER}&algorithm=SHA1&digits={DIGITS}&period={PERIOD}"
+    )
+}
+
+fn code_at(key: &[u8], step: i64, digits: u32) -> u32 {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, key);
+    let tag = hmac::sign(&key, &step.to_be_bytes());
+    let hash = tag.as_ref();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    value % 10u32.pow(digits)
+}
+
+/// Check a code against a secret at Unix time `now`, returning the time
+/// step it is for.
+pub(crate) fn verify(secret: &str, code: &str, now: u64) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let key = base32_decode(secret)?;
+    let step = (now / PERIOD) as i64;
+    (step - SKEW..=step + SKEW).find(|step| code_at(&key, *step, DIGITS) == code)
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// The code for a secret `offset` steps from now, as an authenticator
+/// app would show it.
+#[cfg(test)]
+pub(crate) fn current_code(secret: &str, offset: i64) -> String {
+    let key = base32_decode(secret).unwrap();
+    let step = (now() / PERIOD) as i64 + offset;
+    format!("{:06}", code_at(&key, step, DIGITS))
+}
+
+/// New recovery codes, formatted for display as `xxxxx-xxxxx`.
+pub(crate) fn generate_recovery_codes() -> Vec<String> {
+    const ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+    let mut rng = rand::rng();
+    let mut codes = Vec::with_capacity(RECOVERY_CODES);
+    let mut seen = HashSet::new();
+    while codes.len() < RECOVERY_CODES {
+        let code: String = (0..10)
+            .map(|_| ALPHABET[(rng.next_u32() as usize) % ALPHABET.len()] as char)
+            .collect();
+        if seen.insert(code.clone()) {
+            codes.push(format!("{}-{}", &code[..5], &code[5..]));
+        }
+    }
+    codes
+}
+
+/// A recovery code as stored, ignoring case, dashes and spaces.
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| !matches!(c, '-' | ' '))
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn normalized(codes: &[String]) -> Vec<String> {
+    codes.iter().map(|c| normalize_recovery_code(c)).collect()
+}
+
+/// Whether a user has, or must enroll, a second factor. Only local users
+/// have one; external users get theirs from their provider.
+async fn applies(configdb: &ConfigDb, username: &str) -> Result<bool, ConfigDbError> {
+    Ok(configdb.get_user_source(username).await?.as_deref() == Some(LOCAL_SOURCE))
+}
+
+/// Whether a user can't log in with their password alone.
+pub(crate) async fn requires_second_factor(
+    configdb: &ConfigDb,
+    username: &str,
+) -> anyhow::Result<bool> {
+    if !applies(configdb, username).await? {
+        return Ok(false);
+    }
+    let enabled = configdb
+        .get_totp(username)
+        .await?
+        .is_some_and(|totp| totp.enabled);
+    Ok(enabled || policy(configdb).await.required)
+}
+
+/// Check a TOTP or recovery code for a user with TOTP enabled, using it
+/// up so it can't be used again.
+pub(crate) async fn verify_code(
+    configdb: &ConfigDb,
+    username: &str,
+    code: &str,
+) -> Result<bool, ConfigDbError> {
+    let Some(totp) = configdb.get_totp(username).await? else {
+        return Ok(false);
+    };
+    if !totp.enabled {
+        return Ok(false);
+    }
+    if let Some(step) = verify(&totp.secret, code, now()) {
+        return configdb.use_totp_step(username, step).await;
+    }
+    configdb
+        .use_recovery_code(username, &normalize_recovery_code(code))
+        .await
+}
+
+/// Start, or restart, enrollment, returning the pending secret. `None`
+/// if TOTP is already enabled.
+pub(crate) async fn begin_enrollment(
+    configdb: &ConfigDb,
+    username: &str,
+) -> Result<Option<String>, ConfigDbError> {
+    let secret = generate_secret();
+    if configdb.set_pending_totp(username, &secret).await? {
+        Ok(Some(secret))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Confirm enrollment with a code from the pending secret, returning the
+/// new recovery codes. `None` if the code is wrong or nothing is pending.
+pub(crate) async fn confirm_enrollment(
+    configdb: &ConfigDb,
+    username: &str,
+    code: &str,
+) -> Result<Option<Vec<String>>, ConfigDbError> {
+    let Some(totp) = configdb.get_totp(username).await? else {
+        return Ok(None);
+    };
+    if totp.enabled {
+        return Ok(None);
+    }
+    let Some(step) = verify(&totp.secret, code, now()) else {
+        return Ok(None);
+    };
+    let codes = generate_recovery_codes();
+    if configdb
+        .enable_totp(username, step, &normalized(&codes))
+        .await?
+    {
+        Ok(Some(codes))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Replace a user's recovery codes, returning the new ones.
+pub(crate) async fn regenerate_recovery_codes(
+    configdb: &ConfigDb,
+    username: &str,
+) -> Result<Vec<String>, ConfigDbError> {
+    let codes = generate_recovery_codes();
+    configdb
+        .set_recovery_codes(username, &normalized(&codes))
+        .await?;
+    Ok(codes)
+}
+
+/// Where a password login is after its second factor is checked.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum LoginStep {
+    /// Logged in; with recovery codes to show if they just enrolled.
+    Done { recovery_codes: Option<Vec<String>> },
+    /// A code is needed; `invalid` if one was given and was wrong.
+    Code { invalid: bool },
+    /// The policy requires a second factor the user doesn't have: they
+    /// must add this secret to their app and log in with a code from it.
+    Enroll {
+        secret: String,
+        uri: String,
+        invalid: bool,
+    },
+}
+
+/// The second step of a password login for a user whose password was
+/// correct, with the code they gave, if any.
+pub(crate) async fn login_step(
+    configdb: &ConfigDb,
+    user: &User,
+    code: Option<&str>,
+) -> anyhow::Result<LoginStep> {
+    let username = &user.username;
+    if !applies(configdb, username).await? {
+        return Ok(LoginStep::Done {
+            recovery_codes: None,
+        });
+    }
+    let code = code.map(str::trim).filter(|code| !code.is_empty());
+    let totp = configdb.get_totp(username).await?;
+    if totp.as_ref().is_some_and(|totp| totp.enabled) {
+        return Ok(match code {
+            Some(code) if verify_code(configdb, username, code).await? => LoginStep::Done {
+                recovery_codes: None,
+            },
+            code => LoginStep::Code {
+                invalid: code.is_some(),
+            },
+        });
+    }
+    if !policy(configdb).await.required {
+        return Ok(LoginStep::Done {
+            recovery_codes: None,
+        });
+    }
+
+    // Enrollment at login: the pending secret is kept until confirmed, so
+    // the user can retry a code without rescanning.
+    if let Some(code) = code
+        && let Some(recovery_codes) = confirm_enrollment(configdb, username, code).await?
+    {
+        info!("User {} enrolled in TOTP at login", username);
+        return Ok(LoginStep::Done {
+            recovery_codes: Some(recovery_codes),
+        });
+    }
+    let secret = match totp {
+        Some(totp) => totp.secret,
+        None => match begin_enrollment(configdb, username).await? {
+            Some(secret) => secret,
+            None => bail!("TOTP enabled while enrolling {username}"),
+        },
+    };
+    Ok(LoginStep::Enroll {
+        uri: provisioning_uri(username, &secret),
+        secret,
+        invalid: code.is_some(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::session::Role;
+
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn test_rfc6238_vectors() {
+        for (time, code) in [
+            (59, 94287082),
+            (1111111109, 7081804),
+            (1111111111, 14050471),
+            (1234567890, 89005924),
+            (2000000000, 69279037),
+            (20000000000, 65353130),
+        ] {
+            assert_eq!(code_at(RFC_SECRET, time / PERIOD as i64, 8), code);
+        }
+
+        let secret = base32_encode(RFC_SECRET);
+        assert_eq!(verify(&secret, "287082", 59), Some(1));
+        assert_eq!(verify(&secret, "287082", 59 + PERIOD), Some(1));
+        assert_eq!(verify(&secret, "287082", 59 + 2 * PERIOD), None);
+        assert_eq!(verify(&secret, "28708", 59), None);
+        assert_eq!(verify(&secret, "28708x", 59), None);
+    }
+
+    #[test]
+    fn test_base32() {
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_decode("mzxw 6ytb oi======").unwrap(), b"foobar");
+        assert_eq!(
+            base32_decode(&base32_encode(RFC_SECRET)).unwrap(),
+            RFC_SECRET
+        );
+        assert!(base32_decode("MZ1W").is_none());
+        assert_eq!(generate_secret().len(), 32);
+    }
+
+    #[test]
+    fn test_provisioning_uri() {
+        assert_eq!(
+            provisioning_uri("bob smith", "ABC"),
+            "otpauth://totp/EveBox%3Abob%20smith?secret=ABC&issuer=EveBox&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_enrollment_and_login() {
+        let dir = tempfile::tempdir().unwrap();
+        let configdb = crate::sqlite::configdb::open(Some(&dir.path().join("config.sqlite")))
+            .await
+            .unwrap();
+        let user = User {
+            uuid: configdb
+                .add_user("alice", "secret", Role::Admin)
+                .await
+                .unwrap(),
+            username: "alice".to_string(),
+            role: Role::Admin,
+            sensors: None,
+            password_change_required: false,
+        };
+
+        // Not enrolled and not required.
+        assert_eq!(
+            login_step(&configdb, &user, None).await.unwrap(),
+            LoginStep::Done {
+                recovery_codes: None
+            }
+        );
+        assert!(!requires_second_factor(&configdb, "alice").await.unwrap());
+
+        let secret = begin_enrollment(&configdb, "alice").await.unwrap().unwrap();
+        // Pending enrollment doesn't change login.
+        assert!(!requires_second_factor(&configdb, "alice").await.unwrap());
+        assert!(
+            confirm_enrollment(&configdb, "alice", "abcdef")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        let codes = confirm_enrollment(&configdb, "alice", &current_code(&secret, 0))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(codes.len(), RECOVERY_CODES);
+        assert!(
+            begin_enrollment(&configdb, "alice")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(requires_second_factor(&configdb, "alice").await.unwrap());
+        assert_eq!(configdb.count_recovery_codes("alice").await.unwrap(), 10);
+
+        assert_eq!(
+            login_step(&configdb, &user, None).await.unwrap(),
+            LoginStep::Code { invalid: false }
+        );
+        // The code used to enroll can't be replayed.
+        assert_eq!(
+            login_step(&configdb, &user, Some(&current_code(&secret, 0)))
+                .await
+                .unwrap(),
+            LoginStep::Code { invalid: true }
+        );
+        assert_eq!(
+            login_step(&configdb, &user, Some(&current_code(&secret, 1)))
+                .await
+                .unwrap(),
+            LoginStep::Done {
+                recovery_codes: None
+            }
+        );
+
+        // Recovery codes work once, however they're typed.
+        let code = codes[0].to_uppercase().replace('-', " ");
+        assert!(verify_code(&configdb, "alice", &code).await.unwrap());
+        assert!(!verify_code(&configdb, "alice", &codes[0]).await.unwrap());
+        assert_eq!(configdb.count_recovery_codes("alice").await.unwrap(), 9);
+
+        assert!(configdb.remove_totp("alice").await.unwrap());
+        assert!(!configdb.remove_totp("alice").await.unwrap());
+        assert_eq!(configdb.count_recovery_codes("alice").await.unwrap(), 0);
+        assert!(!requires_second_factor(&configdb, "alice").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_required_enrollment_at_login() {
+        let dir = tempfile::tempdir().unwrap();
+        let configdb = crate::sqlite::configdb::open(Some(&dir.path().join("config.sqlite")))
+            .await
+            .unwrap();
+        let user = User {
+            uuid: configdb
+                .add_user("alice", "secret", Role::Admin)
+                .await
+                .unwrap(),
+            username: "alice".to_string(),
+            role: Role::Admin,
+            sensors: None,
+            password_change_required: false,
+        };
+        configdb
+            .kv_set_config(POLICY_KEY, &serde_json::json!({"required": true}))
+            .await
+            .unwrap();
+        assert!(requires_second_factor(&configdb, "alice").await.unwrap());
+
+        let LoginStep::Enroll {
+            secret,
+            uri,
+            invalid: false,
+        } = login_step(&configdb, &user, None).await.unwrap()
+        else {
+            panic!("expected enrollment");
+        };
+        assert!(uri.contains(&secret));
+
+        // The pending secret is kept for a retry.
+        let LoginStep::Enroll {
+            secret: retried,
+            invalid: true,
+            ..
+        } = login_step(&configdb, &user, Some("abc")).await.unwrap()
+        else {
+            panic!("expected enrollment");
+        };
+        assert_eq!(retried, secret);
+
+        let LoginStep::Done {
+            recovery_codes: Some(codes),
+        } = login_step(&configdb, &user, Some(&current_code(&secret, 0)))
+            .await
+            .unwrap()
+        else {
+            panic!("expected login");
+        };
+        assert_eq!(codes.len(), RECOVERY_CODES);
+        assert!(configdb.get_totp("alice").await.unwrap().unwrap().enabled);
+
+        // External users aren't asked.
+        let bob = configdb
+            .provision_external_user("bob", Role::Viewer, "ldap", true)
+            .await
+            .unwrap();
+        assert!(!requires_second_factor(&configdb, "bob").await.unwrap());
+        assert_eq!(
+            login_step(&configdb, &bob, None).await.unwrap(),
+            LoginStep::Done {
+                recovery_codes: None
+            }
+        );
+
+        // A policy that can't be read still requires TOTP.
+        configdb
+            .kv_set_config(POLICY_KEY, &serde_json::json!({"required": "no"}))
+            .await
+            .unwrap();
+        assert!(policy(&configdb).await.required);
+    }
+}
@@ -4,6 +4,18 @@
 use crate::queryparser;
 use crate::sqlite::prelude::*;
 
+/// A condition matching rows of the events `table` from one of `n`
+/// sensors, bound as arguments; with none it matches nothing.
+pub(crate) fn sensors_condition(table: &str, n: usize) -> String {
+    if n == 0 {
+        return "0".to_string();
+    }
+    format!(
+        "json_extract({table}.source, '$.host') IN ({})",
+        vec!["?"; n].join(", ")
+    )
+}
+
 #[derive(Default)]
 pub(crate) struct EventQueryBuilder<'a> {
     /// Is FTS available?
@@ -142,6 +154,15 @@ impl<'a> EventQueryBuilder<'a> {
         Ok(self)
     }
 
+    /// Only events from one of `sensors`.
+    pub(crate) fn where_sensors(&mut self, sensors: &[String]) -> Result<&mut Self, sqlx::Error> {
+        self.push_where(sensors_condition("events", sensors.len()));
+        for sensor in sensors {
+            self.push_arg(sensor.clone())?;
+        }
+        Ok(self)
+    }
+
     pub(crate) fn add_left_join(&mut self, sql: String) {
         if !self.left_join.contains(&sql) {
             self.left_join.push(sql);
@@ -174,6 +195,7 @@ impl<'a> EventQueryBuilder<'a> {
                 queryparser::QueryValue::Before(_) => {}
                 queryparser::QueryValue::Archived => {}
                 queryparser::QueryValue::Escalated => {}
+                queryparser::QueryValue::Sensors(_) => {}
             }
         }
         Ok(())
@@ -317,6 +339,9 @@ impl<'a> EventQueryBuilder<'a> {
                     let value = if e.negated { 0 } else { 1 };
                     self.push_where("events.escalated = ?").push_arg(value)?;
                 }
+                queryparser::QueryValue::Sensors(sensors) => {
+                    self.where_sensors(sensors)?;
+                }
             }
         }
         Ok(())
@@ -8,6 +8,7 @@ use std::collections::HashMap;
 use std::path::Path;
 
 use crate::datetime::DateTime;
+use crate::server::session::Role;
 use crate::sqlite::has_table;
 
 #[derive(thiserror::Error, Debug)]
@@ -32,6 +33,16 @@ pub(crate) enum ConfigDbError {
     EmptyAgentName,
     #[error("agent name is limited to {0} bytes")]
     AgentNameTooLong(usize),
+    #[error("invalid certificate fingerprint {0:?}: expected a SHA-256 fingerprint")]
+    InvalidCertificateFingerprint(String),
+    #[error("the certificate is already mapped to another agent key")]
+    AgentCertificateInUse,
+    #[error("an API token named {0:?} already exists for this user")]
+    ApiTokenNameInUse(String),
+    #[error("API token name must not be empty")]
+    EmptyApiTokenName,
+    #[error("user {0:?} is a {1} user")]
+    UserSourceMismatch(String, String),
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, FromRow)]
@@ -123,8 +134,82 @@ impl From<&FilterEntry> for EventFilter {
 pub(crate) struct User {
     pub uuid: String,
     pub username: String,
+    pub role: Role,
+    /// The sensors whose events the user may see; None for all of them.
+    pub sensors: Option<Vec<String>>,
+    /// The user must change their password before anything else.
+    pub password_change_required: bool,
 }
 
+/// A stored sensor list as the sensors a user may see; a list that
+/// doesn't parse allows none.
+fn parse_sensors(username: &str, sensors: Option<String>) -> Option<Vec<String>> {
+    sensors.map(|sensors| {
+        serde_json::from_str(&sensors).unwrap_or_else(|err| {
+            warn!("User {username:?} has a bad sensor list {sensors:?}, allowing none: {err}");
+            vec![]
+        })
+    })
+}
+
+/// A stored role name as a `Role`; a name this version does not know
+/// gets the least privilege.
+fn parse_role(username: &str, role: &str) -> Role {
+    role.parse().unwrap_or_else(|_| {
+        warn!("User {username:?} has unknown role {role:?}, treating as viewer");
+        Role::Viewer
+    })
+}
+
+/// Prefix on every personal API token.
+pub(crate) const API_TOKEN_PREFIX: &str = "ebt_";
+
+/// The source of users with a password in this database.
+pub(crate) const LOCAL_SOURCE: &str = "local";
+
+/// A login session, without its token.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub(crate) struct SessionInfo {
+    pub id: i64,
+    #[serde(skip)]
+    pub token: String,
+    /// Unix seconds; unset for sessions from before they were recorded.
+    pub created_at: Option<i64>,
+    pub last_seen: Option<i64>,
+    pub expires_at: i64,
+    /// The client address and user agent the session was created from.
+    pub remote: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// A user's TOTP second factor; pending until enabled by a confirmed
+/// code.
+#[derive(Debug, Clone, FromRow)]
+pub(crate) struct UserTotp {
+    pub secret: String,
+    pub enabled: bool,
+}
+
+/// A personal API token, presented as `Authorization: Bearer`. Only the
+/// token's SHA-256 is stored; the token itself is shown once, when it is
+/// created. A token acts as its user with at most the role in `scope`.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub(crate) struct ApiToken {
+    pub id: i64,
+    pub username: String,
+    pub name: String,
+    #[sqlx(try_from = "String")]
+    pub scope: Role,
+    pub created_at: crate::datetime::ChronoDateTime,
+    pub expires_at: Option<crate::datetime::ChronoDateTime>,
+    pub last_used: Option<crate::datetime::ChronoDateTime>,
+}
+
+const API_TOKEN_SELECT: &str = r#"
+    SELECT api_tokens.id, users.username, api_tokens.name, api_tokens.scope,
+        api_tokens.created_at, api_tokens.expires_at, api_tokens.last_used
+    FROM api_tokens JOIN users ON users.uuid = api_tokens.user_uuid"#;
+
 /// Prefix on every agent key, making a leaked key greppable and
 /// identifiable.
 pub(crate) const AGENT_KEY_PREFIX: &str = "eba_";
@@ -136,6 +221,11 @@ pub(crate) const AGENT_KEY_PREFIX: &str = "eba_";
 /// grants agent impersonation on the control channel, not access to stored
 /// data or the browser UI. Protect the configuration database file like
 /// agent.yaml.
+///
+/// Rotating a key keeps the row, and with it the agent's identity and
+/// server-managed configuration. The replaced key stays valid as
+/// `previous_key` until `previous_key_expires_at` so the agent can be
+/// re-provisioned without an outage.
 #[derive(Debug, Clone, Serialize, FromRow)]
 pub(crate) struct AgentKey {
     pub id: i64,
@@ -143,6 +233,130 @@ pub(crate) struct AgentKey {
     pub key: String,
     pub created_at: crate::datetime::ChronoDateTime,
     pub last_seen: Option<crate::datetime::ChronoDateTime>,
+    pub expires_at: Option<crate::datetime::ChronoDateTime>,
+    pub rotated_at: Option<crate::datetime::ChronoDateTime>,
+    pub previous_key: Option<String>,
+    pub previous_key_expires_at: Option<crate::datetime::ChronoDateTime>,
+    /// SHA-256 fingerprint of a client certificate identifying the agent.
+    pub cert_fingerprint: Option<String>,
+    /// Subject common name of client certificates identifying the agent.
+    pub cert_subject: Option<String>,
+}
+
+/// Server-managed configuration for the agent holding a key. `version`
+/// increases with every save; `applied_version` is the last version the
+/// agent acknowledged.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub(crate) struct AgentConfigRow {
+    pub key_id: i64,
+    pub version: i64,
+    pub config: sqlx::types::Json<crate::agent::protocol::WireAgentConfig>,
+    pub updated_at: crate::datetime::ChronoDateTime,
+    pub applied_version: Option<i64>,
+    pub applied_at: Option<crate::datetime::ChronoDateTime>,
+}
+
+/// One PCAP download audit record: who asked for which packets, and
+/// what they were given. `bytes` and `sha256` describe the bytes sent.
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq, FromRow)]
+pub(crate) struct PcapAuditRecord {
+    pub username: String,
+    pub remote: String,
+    pub event_id: Option<String>,
+    pub mode: Option<String>,
+    pub filter: Option<String>,
+    pub window: Option<String>,
+    pub source: Option<String>,
+    /// `pcap`, `pcapng` or `tar`.
+    pub format: String,
+    pub outcome: String,
+    pub message: Option<String>,
+    pub packets: Option<i64>,
+    pub bytes: i64,
+    pub truncated: bool,
+    pub sha256: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub(crate) struct PcapAuditRow {
+    pub id: i64,
+    pub timestamp: crate::datetime::ChronoDateTime,
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub record: PcapAuditRecord,
+}
+
+/// Filters for a PCAP audit search; unset fields match everything.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PcapAuditQuery {
+    pub username: Option<String>,
+    pub event_id: Option<String>,
+    pub source: Option<String>,
+    pub outcome: Option<String>,
+    pub sha256: Option<String>,
+    /// Unix seconds, inclusive.
+    pub from: Option<i64>,
+    /// Unix seconds, exclusive.
+    pub to: Option<i64>,
+    pub limit: u32,
+}
+
+/// One audit record: who did what to which object, from where, and how
+/// it ended. `parameters` holds the request's details, never secrets.
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq, FromRow)]
+pub(crate) struct AuditRecord {
+    pub username: String,
+    /// The name of the API token the action was taken with.
+    pub api_token: Option<String>,
+    pub remote: String,
+    /// Dotted, object first, such as `agent-key.rotate`.
+    pub action: String,
+    pub target: Option<String>,
+    pub parameters: sqlx::types::Json<serde_json::Value>,
+    /// `ok`, `rejected` or `error`.
+    pub outcome: String,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub(crate) struct AuditRow {
+    pub id: i64,
+    pub timestamp: crate::datetime::ChronoDateTime,
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub record: AuditRecord,
+}
+
+/// Filters for an audit search; unset fields match everything.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AuditQuery {
+    pub username: Option<String>,
+    /// An action, or an object matching all of its actions, such as
+    /// `user` for `user.add` and `user.delete`.
+    pub action: Option<String>,
+    pub target: Option<String>,
+    pub outcome: Option<String>,
+    /// Unix seconds, inclusive.
+    pub from: Option<i64>,
+    /// Unix seconds, exclusive.
+    pub to: Option<i64>,
+    pub limit: u32,
+}
+
+fn generate_api_token() -> String {
+    use base64::prelude::*;
+    use rand::RngCore;
+    let mut buf = [0u8; 32];
+    rand::rng().fill_bytes(&mut buf);
+    format!("{API_TOKEN_PREFIX}{}", BASE64_URL_SAFE_NO_PAD.encode(buf))
+}
+
+fn sha256_hex(token: &str) -> String {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
 }
 
 fn generate_agent_key() -> String {
@@ -153,6 +367,13 @@ fn generate_agent_key() -> String {
     format!("{AGENT_KEY_PREFIX}{}", BASE64_URL_SAFE_NO_PAD.encode(buf))
 }
 
+/// A `datetime('now', ?)` modifier for a duration from now, keeping stored
+/// timestamps in the same format as `CURRENT_TIMESTAMP` so they compare
+/// as text.
+fn from_now(duration: std::time::Duration) -> String {
+    format!("+{} seconds", duration.as_secs())
+}
+
 #[derive(Debug, Deserialize)]
 pub(crate) struct EnabledWithValue {
     pub enabled: bool,
@@ -175,15 +396,30 @@ impl ConfigDb {
         password_in: &str,
     ) -> Result<User, ConfigDbError> {
         let query = sqlx::query::<sqlx::Sqlite>(
-            "SELECT uuid, username, password FROM users WHERE username = ?",
+            "SELECT uuid, username, password, role, sensors, password_change_required FROM users WHERE username = ?",
         )
         .bind(username);
         if let Some(row) = query.fetch_optional(&self.pool).await? {
             let uuid: String = row.try_get(0)?;
             let username: String = row.try_get(1)?;
-            let password_hash: String = row.try_get(2)?;
+            // Users from an external provider have no password.
+            let password_hash: Option<String> = row.try_get(2)?;
+            let role: String = row.try_get(3)?;
+            let sensors: Option<String> = row.try_get(4)?;
+            let password_change_required: bool = row.try_get(5)?;
+            let Some(password_hash) = password_hash else {
+                return Err(ConfigDbError::BadPassword(username));
+            };
             if bcrypt::verify(password_in, &password_hash)? {
-                return Ok(User { uuid, username });
+                let role = parse_role(&username, &role);
+                let sensors = parse_sensors(&username, sensors);
+                return Ok(User {
+                    uuid,
+                    username,
+                    role,
+                    sensors,
+                    password_change_required,
+                });
             } else {
                 return Err(ConfigDbError::BadPassword(username));
             }
@@ -193,14 +429,20 @@ impl ConfigDb {
     }
 
     pub async fn get_user_by_name(&self, username: &str) -> Result<User, ConfigDbError> {
-        let row = sqlx::query("SELECT uuid, username FROM users WHERE username = ?")
-            .bind(username)
-            .fetch_optional(&self.pool)
-            .await?;
+        let row = sqlx::query(
+            "SELECT uuid, username, role, sensors, password_change_required FROM users WHERE username = ?",
+        )
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await?;
         if let Some(row) = row {
+            let role: String = row.try_get("role")?;
             Ok(User {
                 uuid: row.try_get("uuid")?,
                 username: row.try_get("username")?,
+                role: parse_role(username, &role),
+                sensors: parse_sensors(username, row.try_get("sensors")?),
+                password_change_required: row.try_get("password_change_required")?,
             })
         } else {
             Err(ConfigDbError::NoUser(username.to_string()))
@@ -216,63 +458,541 @@ impl ConfigDb {
     }
 
     pub async fn get_users(&self) -> Result<Vec<User>, ConfigDbError> {
-        let rows: Vec<(String, String)> = sqlx::query_as("SELECT uuid, username FROM users")
-            .fetch_all(&self.pool)
-            .await?;
+        let rows: Vec<(String, String, String, Option<String>, bool)> = sqlx::query_as(
+            "SELECT uuid, username, role, sensors, password_change_required FROM users",
+        )
+        .fetch_all(&self.pool)
+        .await?;
         Ok(rows
             .into_iter()
-            .map(|row| User {
-                uuid: row.0,
-                username: row.1,
-            })
+            .map(
+                |(uuid, username, role, sensors, password_change_required)| {
+                    let role = parse_role(&username, &role);
+                    let sensors = parse_sensors(&username, sensors);
+                    User {
+                        uuid,
+                        username,
+                        role,
+                        sensors,
+                        password_change_required,
+                    }
+                },
+            )
             .collect())
     }
 
-    pub async fn add_user(&self, username: &str, password: &str) -> Result<String, ConfigDbError> {
+    pub async fn add_user(
+        &self,
+        username: &str,
+        password: &str,
+        role: Role,
+    ) -> Result<String, ConfigDbError> {
         let password_hash = bcrypt::hash(password, bcrypt::DEFAULT_COST)?;
         let user_id = uuid::Uuid::new_v4().to_string();
-        sqlx::query("INSERT INTO users (uuid, username, password) VALUES (?, ?, ?)")
+        sqlx::query("INSERT INTO users (uuid, username, password, role) VALUES (?, ?, ?, ?)")
             .bind(&user_id)
             .bind(username)
             .bind(password_hash)
+            .bind(role.as_str())
             .execute(&self.pool)
             .await?;
         Ok(user_id)
     }
 
-    pub async fn remove_user(&self, username: &str) -> Result<u64, ConfigDbError> {
-        Ok(sqlx::query("DELETE FROM users WHERE username = ?")
+    /// Add a user authenticated by an external provider, such as
+    /// `"oidc"`. The user has no password, so can't log in locally.
+    pub(crate) async fn add_external_user(
+        &self,
+        username: &str,
+        role: Role,
+        source: &str,
+    ) -> Result<User, ConfigDbError> {
+        let user_id = uuid::Uuid::new_v4().to_string();
+        sqlx::query("INSERT INTO users (uuid, username, role, source) VALUES (?, ?, ?, ?)")
+            .bind(&user_id)
             .bind(username)
+            .bind(role.as_str())
+            .bind(source)
             .execute(&self.pool)
+            .await?;
+        Ok(User {
+            uuid: user_id,
+            username: username.to_string(),
+            role,
+            sensors: None,
+            password_change_required: false,
+        })
+    }
+
+    /// Where a user authenticates, `"local"` or an external provider, or
+    /// None when there is no such user.
+    pub(crate) async fn get_user_source(
+        &self,
+        username: &str,
+    ) -> Result<Option<String>, ConfigDbError> {
+        let row: Option<(String,)> = sqlx::query_as("SELECT source FROM users WHERE username = ?")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|(source,)| source))
+    }
+
+    /// The user an external provider logged in, with the role it gives
+    /// them, created when missing if `create`. Users of another source,
+    /// including local users, are refused.
+    pub(crate) async fn provision_external_user(
+        &self,
+        username: &str,
+        role: Role,
+        source: &str,
+        create: bool,
+    ) -> Result<User, ConfigDbError> {
+        match self.get_user_source(username).await? {
+            Some(found) if found != source => Err(ConfigDbError::UserSourceMismatch(
+                username.to_string(),
+                found,
+            )),
+            Some(_) => {
+                let mut user = self.get_user_by_name(username).await?;
+                if user.role != role {
+                    info!(
+                        "Role of {} user {:?} changed from {} to {}",
+                        source, username, user.role, role
+                    );
+                    self.set_user_role(username, role).await?;
+                    user.role = role;
+                }
+                Ok(user)
+            }
+            None if create => {
+                info!(
+                    "Provisioning {} user {:?} with role {}",
+                    source, username, role
+                );
+                self.add_external_user(username, role, source).await
+            }
+            None => Err(ConfigDbError::NoUser(username.to_string())),
+        }
+    }
+
+    pub(crate) async fn set_user_role(
+        &self,
+        username: &str,
+        role: Role,
+    ) -> Result<bool, ConfigDbError> {
+        let result = sqlx::query("UPDATE users SET role = ? WHERE username = ?")
+            .bind(role.as_str())
+            .bind(username)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Limit a user to the events of `sensors`, or with None let them see
+    /// every sensor, returning whether the user exists.
+    pub(crate) async fn set_user_sensors(
+        &self,
+        username: &str,
+        sensors: Option<&[String]>,
+    ) -> Result<bool, ConfigDbError> {
+        let sensors = sensors.map(|sensors| serde_json::to_string(sensors).unwrap());
+        let result = sqlx::query("UPDATE users SET sensors = ? WHERE username = ?")
+            .bind(sensors)
+            .bind(username)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Require, or no longer require, a user to change their password
+    /// before anything else, returning whether the user exists.
+    pub(crate) async fn set_password_change_required(
+        &self,
+        username: &str,
+        required: bool,
+    ) -> Result<bool, ConfigDbError> {
+        let result =
+            sqlx::query("UPDATE users SET password_change_required = ? WHERE username = ?")
+                .bind(required)
+                .bind(username)
+                .execute(&self.pool)
+                .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// The number of users with the admin role, so the last can't be
+    /// demoted or removed.
+    pub(crate) async fn count_admins(&self) -> Result<u64, ConfigDbError> {
+        let (count,): (u64,) = sqlx::query_as(
+            "SELECT count(*) FROM users WHERE role = ? AND username != '__system__'",
+        )
+        .bind(Role::Admin.as_str())
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(count)
+    }
+
+    /// Remove a user with their sessions and API tokens.
+    pub async fn remove_user(&self, username: &str) -> Result<u64, ConfigDbError> {
+        let mut tx = self.pool.begin().await?;
+        for sql in [
+            "DELETE FROM sessions WHERE uuid IN (SELECT uuid FROM users WHERE username = ?)",
+            "DELETE FROM api_tokens WHERE user_uuid IN (SELECT uuid FROM users WHERE username = ?)",
+            "DELETE FROM user_totp WHERE user_uuid IN (SELECT uuid FROM users WHERE username = ?)",
+            "DELETE FROM user_recovery_codes WHERE user_uuid IN (SELECT uuid FROM users WHERE username = ?)",
+        ] {
+            sqlx::query(sql).bind(username).execute(&mut *tx).await?;
+        }
+        let removed = sqlx::query("DELETE FROM users WHERE username = ?")
+            .bind(username)
+            .execute(&mut *tx)
             .await?
-            .rows_affected())
+            .rows_affected();
+        tx.commit().await?;
+        Ok(removed)
+    }
+
+    /// Create an API token for a user, returning the row and the token,
+    /// which is not stored and can't be shown again. The token expires
+    /// after `expires`, or never.
+    pub(crate) async fn add_api_token(
+        &self,
+        username: &str,
+        name: &str,
+        scope: Role,
+        expires: Option<std::time::Duration>,
+    ) -> Result<(ApiToken, String), ConfigDbError> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(ConfigDbError::EmptyApiTokenName);
+        }
+        let user = self.get_user_by_name(username).await?;
+        let token = generate_api_token();
+        let result = sqlx::query(
+            r#"
+            INSERT INTO api_tokens (user_uuid, name, token_hash, scope, expires_at)
+            VALUES (?, ?, ?, ?, datetime('now', ?))"#,
+        )
+        .bind(&user.uuid)
+        .bind(name)
+        .bind(sha256_hex(&token))
+        .bind(scope.as_str())
+        .bind(expires.map(from_now))
+        .execute(&self.pool)
+        .await;
+        if let Err(sqlx::Error::Database(err)) = &result
+            && err.is_unique_violation()
+        {
+            return Err(ConfigDbError::ApiTokenNameInUse(name.to_string()));
+        }
+        let id = result?.last_insert_rowid();
+        let row = sqlx::query_as(&format!("{API_TOKEN_SELECT} WHERE api_tokens.id = ?"))
+            .bind(id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok((row, token))
+    }
+
+    /// A user's API tokens, without the tokens themselves.
+    pub(crate) async fn list_api_tokens(
+        &self,
+        username: &str,
+    ) -> Result<Vec<ApiToken>, ConfigDbError> {
+        let rows = sqlx::query_as(&format!(
+            "{API_TOKEN_SELECT} WHERE users.username = ? ORDER BY api_tokens.id"
+        ))
+        .bind(username)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    /// Look up an unexpired API token by its presented value, with its
+    /// user.
+    pub(crate) async fn verify_api_token(
+        &self,
+        token: &str,
+    ) -> Result<Option<(ApiToken, User)>, ConfigDbError> {
+        if !token.starts_with(API_TOKEN_PREFIX) {
+            return Ok(None);
+        }
+        let row: Option<ApiToken> = sqlx::query_as(&format!(
+            r#"{API_TOKEN_SELECT}
+            WHERE api_tokens.token_hash = ?
+              AND (api_tokens.expires_at IS NULL OR api_tokens.expires_at > CURRENT_TIMESTAMP)"#
+        ))
+        .bind(sha256_hex(token))
+        .fetch_optional(&self.pool)
+        .await?;
+        match row {
+            Some(row) => {
+                let user = self.get_user_by_name(&row.username).await?;
+                Ok(Some((row, user)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Record a token's use, at most once a minute, returning whether it
+    /// was recorded.
+    pub(crate) async fn touch_api_token(&self, id: i64) -> Result<bool, ConfigDbError> {
+        let result = sqlx::query(
+            r#"
+            UPDATE api_tokens SET last_used = CURRENT_TIMESTAMP
+            WHERE id = ? AND (last_used IS NULL OR last_used < datetime('now', '-60 seconds'))"#,
+        )
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Revoke one of a user's API tokens, returning whether it existed.
+    pub(crate) async fn remove_api_token(
+        &self,
+        username: &str,
+        id: i64,
+    ) -> Result<bool, ConfigDbError> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM api_tokens
+            WHERE id = ? AND user_uuid IN (SELECT uuid FROM users WHERE username = ?)"#,
+        )
+        .bind(id)
+        .bind(username)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub(crate) async fn get_totp(&self, username: &str) -> Result<Option<UserTotp>, ConfigDbError> {
+        let row = sqlx::query_as(
+            r#"
+            SELECT secret, enabled FROM user_totp
+            WHERE user_uuid IN (SELECT uuid FROM users WHERE username = ?)"#,
+        )
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    /// The users with TOTP enabled.
+    pub(crate) async fn get_totp_users(
+        &self,
+    ) -> Result<std::collections::HashSet<String>, ConfigDbError> {
+        let rows: Vec<(String,)> = sqlx::query_as(
+            r#"
+            SELECT users.username FROM user_totp
+            JOIN users ON users.uuid = user_totp.user_uuid
+            WHERE user_totp.enabled = 1"#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(|(username,)| username).collect())
+    }
+
+    /// Give a user a pending TOTP secret, replacing a pending one. False
+    /// when the user has TOTP enabled, or doesn't exist.
+    pub(crate) async fn set_pending_totp(
+        &self,
+        username: &str,
+        secret: &str,
+    ) -> Result<bool, ConfigDbError> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO user_totp (user_uuid, secret)
+            SELECT uuid, ? FROM users WHERE username = ?
+            ON CONFLICT (user_uuid) DO UPDATE
+            SET secret = excluded.secret, last_step = NULL, created_at = CURRENT_TIMESTAMP
+            WHERE enabled = 0"#,
+        )
+        .bind(secret)
+        .bind(username)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Enable a user's pending TOTP secret, confirmed by a code of time
+    /// step `step`, replacing their recovery codes. False when there is
+    /// no pending secret.
+    pub(crate) async fn enable_totp(
+        &self,
+        username: &str,
+        step: i64,
+        recovery_codes: &[String],
+    ) -> Result<bool, ConfigDbError> {
+        let mut tx = self.pool.begin().await?;
+        let enabled = sqlx::query(
+            r#"
+            UPDATE user_totp SET enabled = 1, last_step = ?
+            WHERE enabled = 0 AND user_uuid IN (SELECT uuid FROM users WHERE username = ?)"#,
+        )
+        .bind(step)
+        .bind(username)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+            > 0;
+        if enabled {
+            Self::insert_recovery_codes(&mut tx, username, recovery_codes).await?;
+            tx.commit().await?;
+        }
+        Ok(enabled)
+    }
+
+    /// Record a TOTP code's time step as used. False when it is not after
+    /// the last used, so a code can't be replayed.
+    pub(crate) async fn use_totp_step(
+        &self,
+        username: &str,
+        step: i64,
+    ) -> Result<bool, ConfigDbError> {
+        let result = sqlx::query(
+            r#"
+            UPDATE user_totp SET last_step = ?
+            WHERE enabled = 1 AND (last_step IS NULL OR last_step < ?)
+              AND user_uuid IN (SELECT uuid FROM users WHERE username = ?)"#,
+        )
+        .bind(step)
+        .bind(step)
+        .bind(username)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Remove a user's second factor and recovery codes, returning false
+    /// if they had none.
+    pub(crate) async fn remove_totp(&self, username: &str) -> Result<bool, ConfigDbError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "DELETE FROM user_recovery_codes WHERE user_uuid IN (SELECT uuid FROM users WHERE username = ?)",
+        )
+        .bind(username)
+        .execute(&mut *tx)
+        .await?;
+        let removed = sqlx::query(
+            "DELETE FROM user_totp WHERE user_uuid IN (SELECT uuid FROM users WHERE username = ?)",
+        )
+        .bind(username)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        tx.commit().await?;
+        Ok(removed > 0)
+    }
+
+    /// Replace a user's recovery codes. Only their SHA-256 is stored.
+    pub(crate) async fn set_recovery_codes(
+        &self,
+        username: &str,
+        recovery_codes: &[String],
+    ) -> Result<(), ConfigDbError> {
+        let mut tx = self.pool.begin().await?;
+        Self::insert_recovery_codes(&mut tx, username, recovery_codes).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn insert_recovery_codes(
+        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        username: &str,
+        recovery_codes: &[String],
+    ) -> Result<(), ConfigDbError> {
+        sqlx::query(
+            "DELETE FROM user_recovery_codes WHERE user_uuid IN (SELECT uuid FROM users WHERE username = ?)",
+        )
+        .bind(username)
+        .execute(&mut **tx)
+        .await?;
+        for code in recovery_codes {
+            sqlx::query(
+                r#"
+                INSERT OR IGNORE INTO user_recovery_codes (user_uuid, code_hash)
+                SELECT uuid, ? FROM users WHERE username = ?"#,
+            )
+            .bind(sha256_hex(code))
+            .bind(username)
+            .execute(&mut **tx)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Use up a recovery code, false if it isn't one of the user's unused
+    /// codes.
+    pub(crate) async fn use_recovery_code(
+        &self,
+        username: &str,
+        code: &str,
+    ) -> Result<bool, ConfigDbError> {
+        let result = sqlx::query(
+            r#"
+            UPDATE user_recovery_codes SET used_at = CURRENT_TIMESTAMP
+            WHERE code_hash = ? AND used_at IS NULL
+              AND user_uuid IN (SELECT uuid FROM users WHERE username = ?)"#,
+        )
+        .bind(sha256_hex(code))
+        .bind(username)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// The number of a user's unused recovery codes.
+    pub(crate) async fn count_recovery_codes(&self, username: &str) -> Result<i64, ConfigDbError> {
+        let (count,): (i64,) = sqlx::query_as(
+            r#"
+            SELECT count(*) FROM user_recovery_codes
+            WHERE used_at IS NULL
+              AND user_uuid IN (SELECT uuid FROM users WHERE username = ?)"#,
+        )
+        .bind(username)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(count)
     }
 
+    /// Set a user's password, which no longer has to be changed.
     pub async fn update_password_by_id(
         &self,
         id: &str,
         password: &str,
     ) -> Result<bool, ConfigDbError> {
         let password_hash = bcrypt::hash(password, bcrypt::DEFAULT_COST)?;
-        let result = sqlx::query("UPDATE users SET password = ? WHERE uuid = ?")
-            .bind(&password_hash)
-            .bind(id)
-            .execute(&self.pool)
-            .await?;
+        let result = sqlx::query(
+            "UPDATE users SET password = ?, password_change_required = 0 WHERE uuid = ?",
+        )
+        .bind(&password_hash)
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
         Ok(result.rows_affected() > 0)
     }
 
+    /// Save a new login session, last used now.
     pub async fn save_session(
         &self,
         token: &str,
         uuid: &str,
         expires: i64,
+        remote: Option<&str>,
+        user_agent: Option<&str>,
     ) -> Result<(), ConfigDbError> {
-        let sql = "INSERT INTO sessions (token, uuid, expires_at) VALUES (?, ?, ?)";
+        let now = DateTime::now().to_seconds();
+        let sql = r#"
+            INSERT INTO sessions (token, uuid, expires_at, created_at, last_seen, remote, user_agent)
+            VALUES (?, ?, ?, ?, ?, ?, ?)"#;
         sqlx::query(sql)
             .bind(token)
             .bind(uuid)
             .bind(expires)
+            .bind(now)
+            .bind(now)
+            .bind(remote)
+            .bind(user_agent)
             .execute(&self.pool)
             .await?;
         Ok(())
@@ -284,52 +1004,161 @@ impl ConfigDb {
         Ok(())
     }
 
-    async fn expire_sessions(&self) -> Result<u64, ConfigDbError> {
-        let now = DateTime::now().to_seconds();
-        let result = sqlx::query("DELETE FROM sessions WHERE expires_at < ?")
+    /// Record a session's last use.
+    pub(crate) async fn touch_session(&self, token: &str, now: i64) -> Result<(), ConfigDbError> {
+        sqlx::query("UPDATE sessions SET last_seen = ?1 WHERE token = ?2 AND (last_seen IS NULL OR last_seen < ?1)")
             .bind(now)
+            .bind(token)
             .execute(&self.pool)
             .await?;
+        Ok(())
+    }
+
+    /// Delete sessions past their expiry, or unused for `idle_timeout`
+    /// seconds.
+    async fn expire_sessions(&self, idle_timeout: Option<i64>) -> Result<u64, ConfigDbError> {
+        let now = DateTime::now().to_seconds();
+        let result = sqlx::query(
+            r#"
+            DELETE FROM sessions
+            WHERE expires_at < ?1 OR (?2 IS NOT NULL AND last_seen <= ?1 - ?2)"#,
+        )
+        .bind(now)
+        .bind(idle_timeout)
+        .execute(&self.pool)
+        .await?;
         Ok(result.rows_affected())
     }
 
-    pub async fn get_user_by_session(&self, token: &str) -> Result<Option<User>, ConfigDbError> {
+    /// The user of a session that hasn't expired, or been unused for
+    /// `idle_timeout` seconds, with its expiry. Sessions from before
+    /// their last use was recorded count as used at their lookup.
+    pub(crate) async fn get_active_session(
+        &self,
+        token: &str,
+        idle_timeout: Option<i64>,
+    ) -> Result<Option<(User, i64)>, ConfigDbError> {
         let sql = r#"
-            SELECT users.uuid, users.username, sessions.expires_at
-            FROM users 
-            JOIN sessions ON 
-            users.uuid = sessions.uuid
+            SELECT users.uuid, users.username, users.role, users.sensors,
+                users.password_change_required, sessions.expires_at, sessions.last_seen
+            FROM users
+            JOIN sessions ON users.uuid = sessions.uuid
             WHERE sessions.token = ?"#;
-
-        // TODO: Remove transaction, not needed here but used as an example.
-        let mut tx = self.pool.begin().await?;
-        if let Some(row) = sqlx::query(sql)
+        let Some(row) = sqlx::query(sql)
             .bind(token)
-            .fetch_optional(&mut *tx)
+            .fetch_optional(&self.pool)
             .await?
-        {
-            let uuid: String = row.try_get("uuid")?;
-            let username: String = row.try_get("username")?;
-            let expires_at: i64 = row.try_get("expires_at")?;
-
-            let now = DateTime::now().to_seconds();
-            if now > expires_at {
-                match self.expire_sessions().await {
-                    Ok(n) => {
-                        if n > 0 {
-                            info!("Expired {} sessions", n);
-                        }
-                    }
-                    Err(err) => {
-                        error!("Failed to expire sessions: {:?}", err);
+        else {
+            return Ok(None);
+        };
+        let uuid: String = row.try_get("uuid")?;
+        let username: String = row.try_get("username")?;
+        let role: String = row.try_get("role")?;
+        let sensors: Option<String> = row.try_get("sensors")?;
+        let password_change_required: bool = row.try_get("password_change_required")?;
+        let expires_at: i64 = row.try_get("expires_at")?;
+        let last_seen: Option<i64> = row.try_get("last_seen")?;
+
+        let now = DateTime::now().to_seconds();
+        let idle = match (idle_timeout, last_seen) {
+            (Some(idle), Some(last_seen)) => now - last_seen >= idle,
+            _ => false,
+        };
+        if now > expires_at || idle {
+            match self.expire_sessions(idle_timeout).await {
+                Ok(n) => {
+                    if n > 0 {
+                        info!("Expired {} sessions", n);
                     }
                 }
-                return Ok(None);
+                Err(err) => {
+                    error!("Failed to expire sessions: {:?}", err);
+                }
             }
-            tx.commit().await?;
-            return Ok(Some(User { uuid, username }));
+            return Ok(None);
         }
-        Ok(None)
+        let role = parse_role(&username, &role);
+        let sensors = parse_sensors(&username, sensors);
+        Ok(Some((
+            User {
+                uuid,
+                username,
+                role,
+                sensors,
+                password_change_required,
+            },
+            expires_at,
+        )))
+    }
+
+    /// A user's unexpired login sessions, most recently used first.
+    pub(crate) async fn list_sessions(
+        &self,
+        username: &str,
+    ) -> Result<Vec<SessionInfo>, ConfigDbError> {
+        let rows = sqlx::query_as(
+            r#"
+            SELECT sessions.rowid AS id, sessions.token, sessions.created_at, sessions.last_seen,
+                sessions.expires_at, sessions.remote, sessions.user_agent
+            FROM sessions JOIN users ON users.uuid = sessions.uuid
+            WHERE users.username = ? AND sessions.expires_at >= ?
+            ORDER BY sessions.last_seen DESC, sessions.rowid DESC"#,
+        )
+        .bind(username)
+        .bind(DateTime::now().to_seconds())
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    /// Delete one of a user's sessions by id, returning its token.
+    pub(crate) async fn remove_user_session(
+        &self,
+        username: &str,
+        id: i64,
+    ) -> Result<Option<String>, ConfigDbError> {
+        let row: Option<(String,)> = sqlx::query_as(
+            r#"
+            DELETE FROM sessions
+            WHERE rowid = ? AND uuid IN (SELECT uuid FROM users WHERE username = ?)
+            RETURNING token"#,
+        )
+        .bind(id)
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|(token,)| token))
+    }
+
+    /// Delete a user's sessions other than the one with the token `keep`,
+    /// returning their tokens.
+    pub(crate) async fn remove_other_user_sessions(
+        &self,
+        username: &str,
+        keep: Option<&str>,
+    ) -> Result<Vec<String>, ConfigDbError> {
+        let rows: Vec<(String,)> = sqlx::query_as(
+            r#"
+            DELETE FROM sessions
+            WHERE uuid IN (SELECT uuid FROM users WHERE username = ?) AND token IS NOT ?
+            RETURNING token"#,
+        )
+        .bind(username)
+        .bind(keep)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(|(token,)| token).collect())
+    }
+
+    /// Delete all of a user's sessions, returning how many.
+    pub(crate) async fn remove_user_sessions(&self, username: &str) -> Result<u64, ConfigDbError> {
+        let result = sqlx::query(
+            "DELETE FROM sessions WHERE uuid IN (SELECT uuid FROM users WHERE username = ?)",
+        )
+        .bind(username)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
     }
 
     pub(crate) async fn get_filters(&self) -> Result<Vec<FilterRow>> {
@@ -379,10 +1208,15 @@ impl ConfigDb {
         Ok(())
     }
 
-    /// Create an agent key. The generated key is returned in the row and
-    /// remains re-showable through `list_agent_keys`. One key per name;
-    /// replace a key by removing it and adding a new one.
-    pub(crate) async fn add_agent_key(&self, name: &str) -> Result<AgentKey, ConfigDbError> {
+    /// Create an agent key, optionally expiring after `expires`. The
+    /// generated key is returned in the row and remains re-showable through
+    /// `list_agent_keys`. One key per name; replace a key with
+    /// `rotate_agent_key`.
+    pub(crate) async fn add_agent_key(
+        &self,
+        name: &str,
+        expires: Option<std::time::Duration>,
+    ) -> Result<AgentKey, ConfigDbError> {
         let name = name.trim();
         if name.is_empty() {
             return Err(ConfigDbError::EmptyAgentName);
@@ -396,11 +1230,14 @@ impl ConfigDb {
             return Err(ConfigDbError::AgentNameReserved(name.to_string()));
         }
         let key = generate_agent_key();
-        let result = sqlx::query("INSERT INTO agent_keys (name, key) VALUES (?, ?)")
-            .bind(name)
-            .bind(&key)
-            .execute(&self.pool)
-            .await;
+        let result = sqlx::query(
+            "INSERT INTO agent_keys (name, key, expires_at) VALUES (?, ?, datetime('now', ?))",
+        )
+        .bind(name)
+        .bind(&key)
+        .bind(expires.map(from_now))
+        .execute(&self.pool)
+        .await;
         if let Err(sqlx::Error::Database(err)) = &result
             && err.is_unique_violation()
         {
@@ -414,7 +1251,9 @@ impl ConfigDb {
         Ok(row)
     }
 
-    /// Look up an agent key by its presented value.
+    /// Look up an agent key by its presented value: the current key until
+    /// it expires, or the previous key of a rotated row during its grace
+    /// period.
     pub(crate) async fn verify_agent_key(
         &self,
         key: &str,
@@ -422,13 +1261,69 @@ impl ConfigDb {
         if !key.starts_with(AGENT_KEY_PREFIX) {
             return Ok(None);
         }
-        let row = sqlx::query_as("SELECT * FROM agent_keys WHERE key = ?")
+        let sql = r#"
+            SELECT * FROM agent_keys
+            WHERE (key = ? AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP))
+               OR (previous_key = ? AND previous_key_expires_at > CURRENT_TIMESTAMP)"#;
+        let row = sqlx::query_as(sql)
+            .bind(key)
             .bind(key)
             .fetch_optional(&self.pool)
             .await?;
         Ok(row)
     }
 
+    /// Replace the key of the row with this id, returning the updated row
+    /// with the new key, or None when there is no such row. The replaced
+    /// key is accepted for `grace` longer, but never past its own expiry;
+    /// a zero grace revokes it at once. The new key expires after
+    /// `expires`, or never.
+    pub(crate) async fn rotate_agent_key(
+        &self,
+        id: i64,
+        grace: std::time::Duration,
+        expires: Option<std::time::Duration>,
+    ) -> Result<Option<AgentKey>, ConfigDbError> {
+        let sql = r#"
+            UPDATE agent_keys SET
+                previous_key = CASE WHEN ?1 = 0 THEN NULL ELSE key END,
+                previous_key_expires_at = CASE
+                    WHEN ?1 = 0 THEN NULL
+                    WHEN expires_at IS NOT NULL AND expires_at < datetime('now', ?2)
+                        THEN expires_at
+                    ELSE datetime('now', ?2) END,
+                key = ?3,
+                expires_at = datetime('now', ?4),
+                rotated_at = CURRENT_TIMESTAMP
+            WHERE id = ?5
+            RETURNING *"#;
+        let row = sqlx::query_as(sql)
+            .bind(grace.as_secs() as i64)
+            .bind(from_now(grace))
+            .bind(generate_agent_key())
+            .bind(expires.map(from_now))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row)
+    }
+
+    /// Set when the current key of the row with this id expires, or clear
+    /// the expiry with None. Returns false when there is no such row.
+    pub(crate) async fn set_agent_key_expiry(
+        &self,
+        id: i64,
+        expires: Option<std::time::Duration>,
+    ) -> Result<bool, ConfigDbError> {
+        let result =
+            sqlx::query("UPDATE agent_keys SET expires_at = datetime('now', ?) WHERE id = ?")
+                .bind(expires.map(from_now))
+                .bind(id)
+                .execute(&self.pool)
+                .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
     pub(crate) async fn list_agent_keys(&self) -> Result<Vec<AgentKey>, ConfigDbError> {
         let rows = sqlx::query_as("SELECT * FROM agent_keys ORDER BY name, id")
             .fetch_all(&self.pool)
@@ -448,6 +1343,70 @@ impl ConfigDb {
         Ok(row)
     }
 
+    /// Map client certificates to the row with this id, by SHA-256
+    /// fingerprint and/or subject common name; None clears a mapping.
+    /// Returns false when there is no such row.
+    pub(crate) async fn set_agent_key_certificate(
+        &self,
+        id: i64,
+        fingerprint: Option<&str>,
+        subject: Option<&str>,
+    ) -> Result<bool, ConfigDbError> {
+        let fingerprint = fingerprint
+            .map(|input| {
+                crate::cert::normalize_fingerprint(input)
+                    .ok_or_else(|| ConfigDbError::InvalidCertificateFingerprint(input.to_string()))
+            })
+            .transpose()?;
+        let subject = subject.map(str::trim).filter(|subject| !subject.is_empty());
+        let result = sqlx::query(
+            "UPDATE agent_keys SET cert_fingerprint = ?, cert_subject = ? WHERE id = ?",
+        )
+        .bind(fingerprint)
+        .bind(subject)
+        .bind(id)
+        .execute(&self.pool)
+        .await;
+        if let Err(sqlx::Error::Database(err)) = &result
+            && err.is_unique_violation()
+        {
+            return Err(ConfigDbError::AgentCertificateInUse);
+        }
+        Ok(result?.rows_affected() > 0)
+    }
+
+    /// Find the agent key a verified client certificate maps to. A
+    /// fingerprint match wins over a subject match.
+    pub(crate) async fn agent_key_for_certificate(
+        &self,
+        fingerprint: &str,
+        subject: Option<&str>,
+    ) -> Result<Option<AgentKey>, ConfigDbError> {
+        let sql = r#"
+            SELECT * FROM agent_keys
+            WHERE cert_fingerprint = ?1 OR (?2 IS NOT NULL AND cert_subject = ?2)
+            ORDER BY cert_fingerprint = ?1 DESC
+            LIMIT 1"#;
+        let row = sqlx::query_as(sql)
+            .bind(fingerprint)
+            .bind(subject)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row)
+    }
+
+    /// Look up an agent key row by its agent name.
+    pub(crate) async fn get_agent_key_by_name(
+        &self,
+        name: &str,
+    ) -> Result<Option<AgentKey>, ConfigDbError> {
+        let row = sqlx::query_as("SELECT * FROM agent_keys WHERE name = ?")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row)
+    }
+
     /// Delete the key with this name, returning false when no key has the
     /// name.
     pub(crate) async fn remove_agent_key(&self, name: &str) -> Result<bool, ConfigDbError> {
@@ -455,26 +1414,199 @@ impl ConfigDb {
             .bind(name)
             .execute(&self.pool)
             .await?;
-        Ok(result.rows_affected() > 0)
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Delete the key with this row id, returning false when no key has
+    /// the id.
+    pub(crate) async fn remove_agent_key_by_id(&self, id: i64) -> Result<bool, ConfigDbError> {
+        let result = sqlx::query("DELETE FROM agent_keys WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub(crate) async fn touch_agent_key(&self, id: i64) -> Result<(), ConfigDbError> {
+        sqlx::query("UPDATE agent_keys SET last_seen = CURRENT_TIMESTAMP WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub(crate) async fn get_agent_config(
+        &self,
+        key_id: i64,
+    ) -> Result<Option<AgentConfigRow>, ConfigDbError> {
+        let row = sqlx::query_as("SELECT * FROM agent_config WHERE key_id = ?")
+            .bind(key_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row)
+    }
+
+    /// Store the configuration for an agent key under the next version.
+    /// The row is removed along with its key.
+    pub(crate) async fn set_agent_config(
+        &self,
+        key_id: i64,
+        config: &crate::agent::protocol::WireAgentConfig,
+    ) -> Result<AgentConfigRow, ConfigDbError> {
+        let sql = r#"
+            INSERT INTO agent_config (key_id, version, config) VALUES (?, 1, ?)
+            ON CONFLICT (key_id) DO UPDATE SET
+                version = version + 1,
+                config = excluded.config,
+                updated_at = CURRENT_TIMESTAMP
+            RETURNING *"#;
+        let row = sqlx::query_as(sql)
+            .bind(key_id)
+            .bind(sqlx::types::Json(config))
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(row)
+    }
+
+    /// Record the configuration version an agent acknowledged.
+    pub(crate) async fn set_agent_config_applied(
+        &self,
+        key_id: i64,
+        version: u64,
+    ) -> Result<(), ConfigDbError> {
+        let sql = "UPDATE agent_config SET applied_version = ?, applied_at = CURRENT_TIMESTAMP WHERE key_id = ?";
+        sqlx::query(sql)
+            .bind(version as i64)
+            .bind(key_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
     }
 
-    /// Delete the key with this row id, returning false when no key has
-    /// the id.
-    pub(crate) async fn remove_agent_key_by_id(&self, id: i64) -> Result<bool, ConfigDbError> {
-        let result = sqlx::query("DELETE FROM agent_keys WHERE id = ?")
-            .bind(id)
+    pub(crate) async fn add_pcap_audit(
+        &self,
+        record: &PcapAuditRecord,
+    ) -> Result<(), ConfigDbError> {
+        let sql = r#"
+            INSERT INTO pcap_audit (
+                username, remote, event_id, mode, filter, window, source, format,
+                outcome, message, packets, bytes, truncated, sha256)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#;
+        sqlx::query(sql)
+            .bind(&record.username)
+            .bind(&record.remote)
+            .bind(&record.event_id)
+            .bind(&record.mode)
+            .bind(&record.filter)
+            .bind(&record.window)
+            .bind(&record.source)
+            .bind(&record.format)
+            .bind(&record.outcome)
+            .bind(&record.message)
+            .bind(record.packets)
+            .bind(record.bytes)
+            .bind(record.truncated)
+            .bind(&record.sha256)
             .execute(&self.pool)
             .await?;
-        Ok(result.rows_affected() > 0)
+        Ok(())
     }
 
-    pub(crate) async fn touch_agent_key(&self, id: i64) -> Result<(), ConfigDbError> {
-        sqlx::query("UPDATE agent_keys SET last_seen = CURRENT_TIMESTAMP WHERE id = ?")
-            .bind(id)
+    /// PCAP audit records matching `query`, newest first.
+    pub(crate) async fn search_pcap_audit(
+        &self,
+        query: &PcapAuditQuery,
+    ) -> Result<Vec<PcapAuditRow>, ConfigDbError> {
+        let sql = r#"
+            SELECT * FROM pcap_audit
+            WHERE (?1 IS NULL OR username = ?1)
+                AND (?2 IS NULL OR event_id = ?2)
+                AND (?3 IS NULL OR source = ?3)
+                AND (?4 IS NULL OR outcome = ?4)
+                AND (?5 IS NULL OR sha256 = ?5)
+                AND (?6 IS NULL OR timestamp >= datetime(?6, 'unixepoch'))
+                AND (?7 IS NULL OR timestamp < datetime(?7, 'unixepoch'))
+            ORDER BY id DESC
+            LIMIT ?8"#;
+        let rows = sqlx::query_as(sql)
+            .bind(&query.username)
+            .bind(&query.event_id)
+            .bind(&query.source)
+            .bind(&query.outcome)
+            .bind(&query.sha256)
+            .bind(query.from)
+            .bind(query.to)
+            .bind(query.limit)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows)
+    }
+
+    pub(crate) async fn add_audit(&self, record: &AuditRecord) -> Result<(), ConfigDbError> {
+        let sql = r#"
+            INSERT INTO audit_log (
+                username, api_token, remote, action, target, parameters, outcome, message)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)"#;
+        sqlx::query(sql)
+            .bind(&record.username)
+            .bind(&record.api_token)
+            .bind(&record.remote)
+            .bind(&record.action)
+            .bind(&record.target)
+            .bind(&record.parameters)
+            .bind(&record.outcome)
+            .bind(&record.message)
             .execute(&self.pool)
             .await?;
         Ok(())
     }
+
+    /// Audit records matching `query`, newest first.
+    pub(crate) async fn search_audit(
+        &self,
+        query: &AuditQuery,
+    ) -> Result<Vec<AuditRow>, ConfigDbError> {
+        let sql = r#"
+            SELECT * FROM audit_log
+            WHERE (?1 IS NULL OR username = ?1)
+                AND (?2 IS NULL OR action = ?2 OR action LIKE ?2 || '.%')
+                AND (?3 IS NULL OR target = ?3)
+                AND (?4 IS NULL OR outcome = ?4)
+                AND (?5 IS NULL OR timestamp >= datetime(?5, 'unixepoch'))
+                AND (?6 IS NULL OR timestamp < datetime(?6, 'unixepoch'))
+            ORDER BY id DESC
+            LIMIT ?7"#;
+        let rows = sqlx::query_as(sql)
+            .bind(&query.username)
+            .bind(&query.action)
+            .bind(&query.target)
+            .bind(&query.outcome)
+            .bind(query.from)
+            .bind(query.to)
+            .bind(query.limit)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows)
+    }
+
+    /// Remove audit and PCAP audit records from before `before`, in Unix
+    /// seconds, returning how many went.
+    pub(crate) async fn prune_audit(&self, before: i64) -> Result<u64, ConfigDbError> {
+        let mut tx = self.pool.begin().await?;
+        let mut removed = 0;
+        for sql in [
+            "DELETE FROM audit_log WHERE timestamp < datetime(?, 'unixepoch')",
+            "DELETE FROM pcap_audit WHERE timestamp < datetime(?, 'unixepoch')",
+        ] {
+            removed += sqlx::query(sql)
+                .bind(before)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+        }
+        tx.commit().await?;
+        Ok(removed)
+    }
 }
 
 async fn get_legacy_version(conn: &mut SqliteConnection) -> Option<u8> {
@@ -641,7 +1773,7 @@ mod tests {
     #[tokio::test]
     async fn agent_keys_verify_and_touch() {
         let (_dir, db) = test_db().await;
-        let added = db.add_agent_key("sensor-a").await.unwrap();
+        let added = db.add_agent_key("sensor-a", None).await.unwrap();
         assert!(added.key.starts_with(AGENT_KEY_PREFIX));
         assert!(added.last_seen.is_none());
 
@@ -672,7 +1804,7 @@ mod tests {
     #[tokio::test]
     async fn removed_agent_keys_are_rejected() {
         let (_dir, db) = test_db().await;
-        let added = db.add_agent_key("sensor-a").await.unwrap();
+        let added = db.add_agent_key("sensor-a", None).await.unwrap();
         assert!(db.remove_agent_key("sensor-a").await.unwrap());
         assert!(db.verify_agent_key(&added.key).await.unwrap().is_none());
         // Nothing is left to remove.
@@ -683,15 +1815,15 @@ mod tests {
     #[tokio::test]
     async fn agent_key_names_are_unique() {
         let (_dir, db) = test_db().await;
-        let first = db.add_agent_key("sensor-a").await.unwrap();
+        let first = db.add_agent_key("sensor-a", None).await.unwrap();
         assert!(matches!(
-            db.add_agent_key("sensor-a").await,
+            db.add_agent_key("sensor-a", None).await,
             Err(ConfigDbError::AgentKeyNameInUse(_))
         ));
 
         // Removal frees the name for a replacement key.
         assert!(db.remove_agent_key("sensor-a").await.unwrap());
-        let second = db.add_agent_key("sensor-a").await.unwrap();
+        let second = db.add_agent_key("sensor-a", None).await.unwrap();
         assert_ne!(first.key, second.key);
 
         let rows = db.list_agent_keys().await.unwrap();
@@ -702,7 +1834,7 @@ mod tests {
     #[tokio::test]
     async fn agent_keys_by_id() {
         let (_dir, db) = test_db().await;
-        let added = db.add_agent_key("sensor-a").await.unwrap();
+        let added = db.add_agent_key("sensor-a", None).await.unwrap();
 
         let found = db.get_agent_key_by_id(added.id).await.unwrap().unwrap();
         assert_eq!(found.name, "sensor-a");
@@ -721,27 +1853,530 @@ mod tests {
         assert!(!db.remove_agent_key_by_id(added.id).await.unwrap());
     }
 
+    #[tokio::test]
+    async fn agent_config_versions_and_follows_its_key() {
+        let (_dir, db) = test_db().await;
+        let added = db.add_agent_key("sensor-a", None).await.unwrap();
+        assert!(db.get_agent_config(added.id).await.unwrap().is_none());
+
+        let mut config = crate::agent::protocol::WireAgentConfig {
+            geoip: Some(true),
+            ..Default::default()
+        };
+        let first = db.set_agent_config(added.id, &config).await.unwrap();
+        assert_eq!(first.version, 1);
+        assert_eq!(first.applied_version, None);
+        config.geoip = Some(false);
+        let second = db.set_agent_config(added.id, &config).await.unwrap();
+        assert_eq!(second.version, 2);
+        assert_eq!(second.config.geoip, Some(false));
+
+        db.set_agent_config_applied(added.id, 2).await.unwrap();
+        let row = db.get_agent_config(added.id).await.unwrap().unwrap();
+        assert_eq!(row.applied_version, Some(2));
+        assert!(row.applied_at.is_some());
+
+        assert!(db.remove_agent_key_by_id(added.id).await.unwrap());
+        assert!(db.get_agent_config(added.id).await.unwrap().is_none());
+    }
+
     #[tokio::test]
     async fn agent_key_names_are_validated() {
         let (_dir, db) = test_db().await;
         assert!(matches!(
-            db.add_agent_key("  ").await,
+            db.add_agent_key("  ", None).await,
             Err(ConfigDbError::EmptyAgentName)
         ));
         assert!(matches!(
-            db.add_agent_key(crate::server::agents::LOCAL_PCAP_SOURCE_NAME)
+            db.add_agent_key(crate::server::agents::LOCAL_PCAP_SOURCE_NAME, None)
                 .await,
             Err(ConfigDbError::AgentNameReserved(_))
         ));
         assert!(
-            db.add_agent_key(&"a".repeat(crate::server::agents::MAX_AGENT_NAME_BYTES))
+            db.add_agent_key(
+                &"a".repeat(crate::server::agents::MAX_AGENT_NAME_BYTES),
+                None
+            )
+            .await
+            .is_ok()
+        );
+        assert!(matches!(
+            db.add_agent_key(
+                &"b".repeat(crate::server::agents::MAX_AGENT_NAME_BYTES + 1),
+                None
+            )
+            .await,
+            Err(ConfigDbError::AgentNameTooLong(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn rotated_agent_keys_keep_their_row_and_honour_the_grace_period() {
+        let (_dir, db) = test_db().await;
+        let added = db.add_agent_key("sensor-a", None).await.unwrap();
+        let hour = std::time::Duration::from_secs(3600);
+
+        let rotated = db
+            .rotate_agent_key(added.id, hour, Some(hour * 24 * 90))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(rotated.id, added.id);
+        assert_ne!(rotated.key, added.key);
+        assert_eq!(rotated.previous_key.as_deref(), Some(added.key.as_str()));
+        assert!(rotated.previous_key_expires_at.is_some());
+        assert!(rotated.expires_at.is_some());
+        assert!(rotated.rotated_at.is_some());
+
+        // Both keys resolve to the same row during the grace period.
+        for key in [&added.key, &rotated.key] {
+            let found = db.verify_agent_key(key).await.unwrap().unwrap();
+            assert_eq!(found.id, added.id);
+        }
+
+        // A second rotation without grace drops every earlier key at once.
+        let again = db
+            .rotate_agent_key(added.id, std::time::Duration::ZERO, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(again.previous_key.is_none());
+        assert!(again.expires_at.is_none());
+        assert!(db.verify_agent_key(&added.key).await.unwrap().is_none());
+        assert!(db.verify_agent_key(&rotated.key).await.unwrap().is_none());
+        assert!(db.verify_agent_key(&again.key).await.unwrap().is_some());
+
+        assert!(
+            db.rotate_agent_key(added.id + 1, hour, None)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn expired_agent_keys_are_rejected() {
+        let (_dir, db) = test_db().await;
+        let added = db
+            .add_agent_key("sensor-a", Some(std::time::Duration::from_secs(3600)))
+            .await
+            .unwrap();
+        assert!(added.expires_at.is_some());
+        assert!(db.verify_agent_key(&added.key).await.unwrap().is_some());
+
+        sqlx::query("UPDATE agent_keys SET expires_at = datetime('now', '-1 seconds')")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert!(db.verify_agent_key(&added.key).await.unwrap().is_none());
+
+        // Rotation grace never extends the life of an already expired key.
+        let rotated = db
+            .rotate_agent_key(added.id, std::time::Duration::from_secs(3600), None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(db.verify_agent_key(&added.key).await.unwrap().is_none());
+        assert!(db.verify_agent_key(&rotated.key).await.unwrap().is_some());
+
+        // The expiry can be cleared again; unknown rows are reported.
+        assert!(db.set_agent_key_expiry(added.id, None).await.unwrap());
+        let row = db.get_agent_key_by_name("sensor-a").await.unwrap().unwrap();
+        assert!(row.expires_at.is_none());
+        assert!(!db.set_agent_key_expiry(added.id + 1, None).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn certificates_map_to_agent_keys() {
+        let (_dir, db) = test_db().await;
+        let a = db.add_agent_key("sensor-a", None).await.unwrap();
+        let b = db.add_agent_key("sensor-b", None).await.unwrap();
+        let fingerprint = "ab".repeat(32);
+
+        assert!(
+            db.agent_key_for_certificate(&fingerprint, Some("sensor-a"))
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let colons = vec!["AB"; 32].join(":");
+        assert!(
+            db.set_agent_key_certificate(a.id, Some(&colons), None)
+                .await
+                .unwrap()
+        );
+        assert!(
+            db.set_agent_key_certificate(b.id, None, Some("sensor-b"))
                 .await
-                .is_ok()
+                .unwrap()
+        );
+        let found = db
+            .agent_key_for_certificate(&fingerprint, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.id, a.id);
+        assert_eq!(
+            found.cert_fingerprint.as_deref(),
+            Some(fingerprint.as_str())
         );
+        // The fingerprint wins over a subject mapped to another key.
+        let found = db
+            .agent_key_for_certificate(&fingerprint, Some("sensor-b"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.id, a.id);
+        let found = db
+            .agent_key_for_certificate(&"cd".repeat(32), Some("sensor-b"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.id, b.id);
+
         assert!(matches!(
-            db.add_agent_key(&"b".repeat(crate::server::agents::MAX_AGENT_NAME_BYTES + 1))
+            db.set_agent_key_certificate(b.id, Some(&fingerprint), None)
                 .await,
-            Err(ConfigDbError::AgentNameTooLong(_))
+            Err(ConfigDbError::AgentCertificateInUse)
+        ));
+        assert!(matches!(
+            db.set_agent_key_certificate(b.id, Some("not-a-fingerprint"), None)
+                .await,
+            Err(ConfigDbError::InvalidCertificateFingerprint(_))
+        ));
+        assert!(
+            !db.set_agent_key_certificate(b.id + 1, None, None)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn pcap_audit_records_are_searchable() {
+        let (_dir, db) = test_db().await;
+        let record = |username: &str, outcome: &str| PcapAuditRecord {
+            username: username.to_string(),
+            remote: "192.0.2.1:50000".to_string(),
+            event_id: Some("1".to_string()),
+            window: Some("a..b".to_string()),
+            format: "pcap".to_string(),
+            outcome: outcome.to_string(),
+            bytes: 24,
+            sha256: Some("ab".repeat(32)),
+            ..Default::default()
+        };
+        db.add_pcap_audit(&record("alice", "ok")).await.unwrap();
+        db.add_pcap_audit(&record("bob", "no-match")).await.unwrap();
+
+        let query = PcapAuditQuery {
+            limit: 10,
+            ..Default::default()
+        };
+        let rows = db.search_pcap_audit(&query).await.unwrap();
+        assert_eq!(rows.len(), 2);
+        // Newest first.
+        assert_eq!(rows[0].record, record("bob", "no-match"));
+
+        let rows = db
+            .search_pcap_audit(&PcapAuditQuery {
+                username: Some("alice".to_string()),
+                ..query.clone()
+            })
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].record.outcome, "ok");
+
+        let now = chrono::Utc::now().timestamp();
+        let rows = db
+            .search_pcap_audit(&PcapAuditQuery {
+                from: Some(now + 60),
+                ..query.clone()
+            })
+            .await
+            .unwrap();
+        assert!(rows.is_empty());
+        let rows = db
+            .search_pcap_audit(&PcapAuditQuery {
+                from: Some(now - 60),
+                to: Some(now + 60),
+                limit: 1,
+                ..query
+            })
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn audit_records_are_searchable_and_pruned() {
+        let (_dir, db) = test_db().await;
+        let record = |username: &str, action: &str, target: &str| AuditRecord {
+            username: username.to_string(),
+            remote: "192.0.2.1:50000".to_string(),
+            action: action.to_string(),
+            target: Some(target.to_string()),
+            parameters: sqlx::types::Json(json!({"role": "analyst"})),
+            outcome: "ok".to_string(),
+            ..Default::default()
+        };
+        db.add_audit(&record("alice", "user.add", "bob"))
+            .await
+            .unwrap();
+        db.add_audit(&record("alice", "user.role", "bob"))
+            .await
+            .unwrap();
+        db.add_audit(&record("carol", "users.add", "dave"))
+            .await
+            .unwrap();
+
+        let query = AuditQuery {
+            limit: 10,
+            ..Default::default()
+        };
+        let rows = db.search_audit(&query).await.unwrap();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[2].record, record("alice", "user.add", "bob"));
+
+        // An object matches its actions, and only its own.
+        let rows = db
+            .search_audit(&AuditQuery {
+                action: Some("user".to_string()),
+                ..query.clone()
+            })
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 2);
+        let rows = db
+            .search_audit(&AuditQuery {
+                action: Some("user.role".to_string()),
+                target: Some("bob".to_string()),
+                ..query.clone()
+            })
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+        let rows = db
+            .search_audit(&AuditQuery {
+                username: Some("carol".to_string()),
+                outcome: Some("error".to_string()),
+                ..query.clone()
+            })
+            .await
+            .unwrap();
+        assert!(rows.is_empty());
+
+        sqlx::query("UPDATE audit_log SET timestamp = datetime('now', '-2 days') WHERE id = 1")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let removed = db
+            .prune_audit(chrono::Utc::now().timestamp() - 86400)
+            .await
+            .unwrap();
+        assert_eq!(removed, 1);
+        assert_eq!(db.search_audit(&query).await.unwrap().len(), 2);
+    }
+    #[tokio::test]
+    async fn users_carry_their_role() {
+        let (_dir, db) = test_db().await;
+        let uuid = db.add_user("alice", "secret", Role::Analyst).await.unwrap();
+        db.add_user("root", "secret", Role::Admin).await.unwrap();
+        assert_eq!(db.count_admins().await.unwrap(), 1);
+
+        let user = db
+            .get_user_by_username_password("alice", "secret")
+            .await
+            .unwrap();
+        assert_eq!(user.role, Role::Analyst);
+
+        assert!(db.set_user_role("alice", Role::Pcap).await.unwrap());
+        assert!(!db.set_user_role("nobody", Role::Pcap).await.unwrap());
+        db.save_session(
+            "token",
+            &uuid,
+            DateTime::now().to_seconds() + 60,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        let (user, _) = db.get_active_session("token", None).await.unwrap().unwrap();
+        assert_eq!(user.role, Role::Pcap);
+        assert_eq!(db.get_user_by_name("root").await.unwrap().role, Role::Admin);
+    }
+
+    #[tokio::test]
+    async fn users_carry_their_sensors() {
+        let (_dir, db) = test_db().await;
+        let uuid = db.add_user("alice", "secret", Role::Viewer).await.unwrap();
+        assert_eq!(db.get_user_by_name("alice").await.unwrap().sensors, None);
+
+        let sensors = vec!["sensor-a".to_string(), "sensor-b".to_string()];
+        assert!(db.set_user_sensors("alice", Some(&sensors)).await.unwrap());
+        assert!(!db.set_user_sensors("nobody", None).await.unwrap());
+        let user = db
+            .get_user_by_username_password("alice", "secret")
+            .await
+            .unwrap();
+        assert_eq!(user.sensors.as_ref(), Some(&sensors));
+        db.save_session(
+            "token",
+            &uuid,
+            DateTime::now().to_seconds() + 60,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        let (user, _) = db.get_active_session("token", None).await.unwrap().unwrap();
+        assert_eq!(user.sensors.as_ref(), Some(&sensors));
+
+        assert!(db.set_user_sensors("alice", None).await.unwrap());
+        let users = db.get_users().await.unwrap();
+        let alice = users.iter().find(|user| user.username == "alice").unwrap();
+        assert_eq!(alice.sensors, None);
+    }
+
+    #[tokio::test]
+    async fn password_changes_clear_the_required_flag() {
+        let (_dir, db) = test_db().await;
+        let uuid = db.add_user("alice", "secret", Role::Viewer).await.unwrap();
+        assert!(
+            db.set_password_change_required("alice", true)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !db.set_password_change_required("nobody", true)
+                .await
+                .unwrap()
+        );
+        assert!(
+            db.get_user_by_name("alice")
+                .await
+                .unwrap()
+                .password_change_required
+        );
+
+        let expires = DateTime::now().to_seconds() + 60;
+        for token in ["one", "two", "three"] {
+            db.save_session(token, &uuid, expires, None, None)
+                .await
+                .unwrap();
+        }
+        let (user, _) = db.get_active_session("one", None).await.unwrap().unwrap();
+        assert!(user.password_change_required);
+
+        assert!(db.update_password_by_id(&uuid, "changed").await.unwrap());
+        let user = db
+            .get_user_by_username_password("alice", "changed")
+            .await
+            .unwrap();
+        assert!(!user.password_change_required);
+        let mut removed = db
+            .remove_other_user_sessions("alice", Some("two"))
+            .await
+            .unwrap();
+        removed.sort();
+        assert_eq!(removed, ["one", "three"]);
+        assert!(db.get_active_session("two", None).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn idle_sessions_expire() {
+        let (_dir, db) = test_db().await;
+        db.add_user("alice", "secret", Role::Viewer).await.unwrap();
+        let uuid = db.get_user_by_name("alice").await.unwrap().uuid;
+        let now = DateTime::now().to_seconds();
+        for token in ["active", "idle"] {
+            db.save_session(token, &uuid, now + 600, Some("10.0.0.1"), None)
+                .await
+                .unwrap();
+        }
+        sqlx::query("UPDATE sessions SET last_seen = ? WHERE token = 'idle'")
+            .bind(now - 120)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(db.list_sessions("alice").await.unwrap().len(), 2);
+
+        assert!(
+            db.get_active_session("active", Some(60))
+                .await
+                .unwrap()
+                .is_some()
+        );
+        assert!(db.get_active_session("idle", None).await.unwrap().is_some());
+        assert!(
+            db.get_active_session("idle", Some(60))
+                .await
+                .unwrap()
+                .is_none()
+        );
+        let sessions = db.list_sessions("alice").await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].remote.as_deref(), Some("10.0.0.1"));
+        assert_eq!(db.remove_user_sessions("alice").await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn api_tokens_are_stored_hashed_and_expire() {
+        let (_dir, db) = test_db().await;
+        db.add_user("alice", "secret", Role::Pcap).await.unwrap();
+        let (row, token) = db
+            .add_api_token("alice", "backup", Role::Viewer, None)
+            .await
+            .unwrap();
+        assert!(token.starts_with(API_TOKEN_PREFIX));
+        assert_eq!(row.username, "alice");
+        assert_eq!(row.scope, Role::Viewer);
+        let (stored,): (String,) = sqlx::query_as("SELECT token_hash FROM api_tokens")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_ne!(stored, token);
+        assert!(matches!(
+            db.add_api_token("alice", "backup", Role::Viewer, None)
+                .await
+                .unwrap_err(),
+            ConfigDbError::ApiTokenNameInUse(_)
         ));
+
+        let (found, user) = db.verify_api_token(&token).await.unwrap().unwrap();
+        assert_eq!((found.id, user.role), (row.id, Role::Pcap));
+        assert!(db.verify_api_token("ebt_nope").await.unwrap().is_none());
+        assert!(db.touch_api_token(row.id).await.unwrap());
+        assert!(!db.touch_api_token(row.id).await.unwrap());
+        assert!(
+            db.list_api_tokens("alice").await.unwrap()[0]
+                .last_used
+                .is_some()
+        );
+
+        let (_, expired) = db
+            .add_api_token("alice", "old", Role::Viewer, None)
+            .await
+            .unwrap();
+        sqlx::query(
+            "UPDATE api_tokens SET expires_at = datetime('now', '-1 seconds') WHERE name = 'old'",
+        )
+        .execute(&db.pool)
+        .await
+        .unwrap();
+        assert!(db.verify_api_token(&expired).await.unwrap().is_none());
+
+        assert!(!db.remove_api_token("bob", row.id).await.unwrap());
+        assert!(db.remove_api_token("alice", row.id).await.unwrap());
+        assert!(db.verify_api_token(&token).await.unwrap().is_none());
+
+        db.remove_user("alice").await.unwrap();
+        assert!(db.list_api_tokens("alice").await.unwrap().is_empty());
+        let (count,): (i64,) = sqlx::query_as("SELECT count(*) FROM api_tokens")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
     }
 }
@@ -9,6 +9,7 @@ use crate::elastic::HistoryEntryBuilder;
 use crate::eve::eve::ensure_has_history;
 use crate::server::api::AlertGroupSpec;
 use crate::server::session::Session;
+use crate::sqlite::builder::sensors_condition;
 use crate::sqlite::log_query_plan;
 use crate::{LOG_QUERIES, LOG_QUERY_PLAN};
 use serde_json::json;
@@ -86,14 +87,27 @@ impl SqliteEventRepo {
 
     /// Return the earliest/minimum timestamp found in the events
     /// table.
-    pub(crate) async fn earliest_timestamp(&self) -> Result<Option<DateTime>> {
-        let sql = "SELECT MIN(timestamp) FROM events";
+    pub(crate) async fn earliest_timestamp(
+        &self,
+        sensors: Option<&[String]>,
+    ) -> Result<Option<DateTime>> {
+        let mut sql = "SELECT MIN(timestamp) FROM events".to_string();
+        let mut args = SqliteArguments::default();
+        if let Some(sensors) = sensors {
+            sql.push_str(" WHERE ");
+            sql.push_str(&sensors_condition("events", sensors.len()));
+            for sensor in sensors {
+                args.push(sensor.clone())?;
+            }
+        }
 
         if *LOG_QUERY_PLAN {
-            log_query_plan(&self.pool, sql, &SqliteArguments::default()).await;
+            log_query_plan(&self.pool, &sql, &args).await;
         }
 
-        let result: Option<i64> = sqlx::query_scalar(sql).fetch_optional(&self.pool).await?;
+        let result: Option<i64> = sqlx::query_scalar_with(&sql, args)
+            .fetch_one(&self.pool)
+            .await?;
         if let Some(ts) = result {
             Ok(Some(crate::datetime::DateTime::from_nanos(ts)))
         } else {
@@ -225,6 +239,13 @@ impl SqliteEventRepo {
         filters.push("timestamp <= ?".to_string());
         args.push(maxts_nanos)?;
 
+        if let Some(sensors) = alert_group.sensors {
+            filters.push(sensors_condition("events", sensors.len()));
+            for sensor in sensors {
+                args.push(sensor)?;
+            }
+        }
+
         let sql = sql.replace("%WHERE%", &filters.join(" AND "));
 
         if *LOG_QUERY_PLAN {
@@ -296,6 +317,13 @@ impl SqliteEventRepo {
         filters.push("timestamp <= ?".to_string());
         args.push(maxts.to_nanos())?;
 
+        if let Some(sensors) = alert_group.sensors {
+            filters.push(sensors_condition("events", sensors.len()));
+            for sensor in sensors {
+                args.push(sensor)?;
+            }
+        }
+
         let sql = sql.replace("%WHERE%", &filters.join(" AND "));
 
         if *LOG_QUERY_PLAN {
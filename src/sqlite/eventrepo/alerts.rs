@@ -16,7 +16,7 @@ use super::SqliteEventRepo;
 use crate::datetime::DateTime;
 use crate::elastic::AlertQueryOptions;
 use crate::eventrepo::{AggAlert, AggAlertMetadata, AlertsResult};
-use crate::sqlite::builder::EventQueryBuilder;
+use crate::sqlite::builder::{EventQueryBuilder, sensors_condition};
 use crate::sqlite::log_query_plan;
 use crate::{LOG_QUERIES, LOG_QUERY_PLAN, queryparser};
 
@@ -85,6 +85,10 @@ impl SqliteEventRepo {
             }
         }
 
+        if let Some(sensors) = &options.sensors {
+            builder.where_sensors(sensors)?;
+        }
+
         // TODO: With a timeout, we can remove this.
         if let Some(ts) = options.timestamp_gte {
             builder
@@ -168,6 +172,9 @@ impl SqliteEventRepo {
                                 let value = if el.negated { 0 } else { 1 };
                                 builder.push_where("escalated = ?").push_arg(value)?;
                             }
+                            queryparser::QueryValue::Sensors(sensors) => {
+                                builder.where_sensors(sensors)?;
+                            }
                         }
                     }
                 }
@@ -414,6 +421,13 @@ impl SqliteEventRepo {
             }
         }
 
+        if let Some(sensors) = options.sensors {
+            filters.push(sensors_condition("events", sensors.len()));
+            for sensor in sensors {
+                args.push(sensor)?;
+            }
+        }
+
         if let Some(ts) = options.timestamp_gte {
             filters.push("timestamp >= ?".into());
             args.push(ts.to_nanos())?;
@@ -505,6 +519,12 @@ impl SqliteEventRepo {
                                 filters.push("escalated = ?".into());
                                 args.push(if el.negated { 0 } else { 1 })?;
                             }
+                            queryparser::QueryValue::Sensors(sensors) => {
+                                filters.push(sensors_condition("events", sensors.len()));
+                                for sensor in sensors {
+                                    args.push(sensor.clone())?;
+                                }
+                            }
                         }
                     }
                 }
@@ -7,7 +7,8 @@ use std::sync::Arc;
 
 use sqlx::Connection;
 
-use crate::{elastic::HistoryEntryBuilder, server::session::Session};
+use crate::elastic::{HistoryEntry, HistoryEntryBuilder};
+use crate::server::session::Session;
 
 use super::SqliteEventRepo;
 
@@ -47,4 +48,27 @@ impl SqliteEventRepo {
             Ok(())
         }
     }
+
+    /// Append an entry to an event's history without otherwise
+    /// changing it.
+    pub async fn add_history_by_id(&self, event_id: &str, entry: &HistoryEntry) -> Result<()> {
+        let event_id: i64 = event_id.parse()?;
+        let sql = r#"
+            UPDATE events
+            SET history = json_insert(history, '$[#]', json(?))
+            WHERE rowid = ?"#;
+
+        let mut conn = self.writer.lock().await;
+        let n = sqlx::query(sql)
+            .bind(entry.to_json())
+            .bind(event_id)
+            .execute(&mut *conn)
+            .await?
+            .rows_affected();
+        if n == 0 {
+            bail!("sqlite: event not found");
+        } else {
+            Ok(())
+        }
+    }
 }
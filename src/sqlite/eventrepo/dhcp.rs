@@ -7,6 +7,7 @@ use crate::sqlite::prelude::*;
 use super::SqliteEventRepo;
 use crate::LOG_QUERY_PLAN;
 use crate::datetime::DateTime;
+use crate::sqlite::builder::sensors_condition;
 use crate::sqlite::log_query_plan;
 
 impl SqliteEventRepo {
@@ -15,6 +16,7 @@ impl SqliteEventRepo {
         earliest: Option<DateTime>,
         dhcp_type: &str,
         sensor: Option<String>,
+        sensors: Option<&[String]>,
     ) -> Result<Vec<serde_json::Value>> {
         let mut wheres = vec![
             "json_extract(events.source, '$.event_type') = 'dhcp'".to_string(),
@@ -36,6 +38,17 @@ impl SqliteEventRepo {
             }
         }
 
+        // The latest event of a client is looked up again by its timestamp,
+        // so is limited to the sensors too.
+        let mut outer_where = String::new();
+        if let Some(sensors) = sensors {
+            wheres.push(sensors_condition("events", sensors.len()));
+            outer_where = format!("and {}", sensors_condition("t1", sensors.len()));
+            for sensor in sensors.iter().chain(sensors) {
+                params.push(sensor.to_string())?;
+            }
+        }
+
         let sql = r#"
             select t1.source
             from events t1
@@ -49,10 +62,11 @@ impl SqliteEventRepo {
             on
               t1.timestamp = t2.timestamp
               and json_extract(t1.source, '$.dhcp.client_mac') = t2.dhcp_client_mac
-            where json_extract(t1.source, '$.event_type') = 'dhcp'
+            where json_extract(t1.source, '$.event_type') = 'dhcp' %outer_where%
         "#;
 
         let sql = sql.replace("%where%", &wheres.join(" and "));
+        let sql = sql.replace("%outer_where%", &outer_where);
 
         if *LOG_QUERY_PLAN {
             log_query_plan(&self.pool, &sql, &params).await;
@@ -73,15 +87,17 @@ impl SqliteEventRepo {
         &self,
         earliest: Option<DateTime>,
         sensor: Option<String>,
+        sensors: Option<&[String]>,
     ) -> Result<Vec<serde_json::Value>> {
-        self.dhcp(earliest, "request", sensor).await
+        self.dhcp(earliest, "request", sensor, sensors).await
     }
 
     pub async fn dhcp_ack(
         &self,
         earliest: Option<DateTime>,
         sensor: Option<String>,
+        sensors: Option<&[String]>,
     ) -> Result<Vec<serde_json::Value>> {
-        self.dhcp(earliest, "ack", sensor).await
+        self.dhcp(earliest, "ack", sensor, sensors).await
     }
 }
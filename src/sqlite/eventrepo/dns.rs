@@ -12,6 +12,7 @@ impl SqliteEventRepo {
         &self,
         before: Option<DateTime>,
         sensor: Option<String>,
+        sensors: Option<&[String]>,
         src_ip: String,
         dest_ip: String,
     ) -> Result<serde_json::Value> {
@@ -41,6 +42,9 @@ impl SqliteEventRepo {
                 builder.wherejs("host", "=", host).unwrap();
             }
         }
+        if let Some(sensors) = sensors {
+            builder.where_sensors(sensors).unwrap();
+        }
 
         builder.push_where(
             r#"(
@@ -9,7 +9,10 @@ use crate::{
     datetime::DateTime,
     eventrepo::StatsAggQueryParams,
     queryparser::{QueryElement, QueryValue},
-    sqlite::{builder::EventQueryBuilder, log_query_plan, log_query_plan2},
+    sqlite::{
+        builder::{EventQueryBuilder, sensors_condition},
+        log_query_plan, log_query_plan2,
+    },
     util,
 };
 use futures::TryStreamExt;
@@ -171,6 +174,10 @@ impl SqliteEventRepo {
         );
         args.push(&field)?;
 
+        let sensors_filter = qp
+            .sensors
+            .as_ref()
+            .map(|sensors| sensors_condition("events", sensors.len()));
         let mut filters = vec![
             "json_extract(events.source, '$.event_type') = 'stats'",
             "timestamp >= ?",
@@ -188,6 +195,13 @@ impl SqliteEventRepo {
             }
         }
 
+        if let (Some(filter), Some(sensors)) = (&sensors_filter, &qp.sensors) {
+            filters.push(filter);
+            for sensor in sensors {
+                args.push(sensor.clone())?;
+            }
+        }
+
         let sql = sql.replace("%WHERE%", &filters.join(" AND "));
         if *LOG_QUERY_PLAN {
             log_query_plan(&self.pool, &sql, &args).await;
@@ -264,6 +278,11 @@ impl SqliteEventRepo {
 
         let mut args = SqliteArguments::default();
 
+        let sensors_filter = match &params.sensors {
+            Some(sensors) => format!("AND {}", sensors_condition("events", sensors.len())),
+            None => String::new(),
+        };
+
         // Get sensor data without COALESCE for better performance
         let sql = format!(
             "
@@ -275,6 +294,7 @@ impl SqliteEventRepo {
             WHERE json_extract(events.source, '$.event_type') = 'stats'
               AND timestamp >= ?
               AND timestamp <= ?
+              {sensors_filter}
             GROUP BY sensor, bucket_time
             ORDER BY sensor, bucket_time
             "
@@ -282,6 +302,9 @@ impl SqliteEventRepo {
         args.push(&field)?;
         args.push(start_time)?;
         args.push(end_time)?;
+        for sensor in params.sensors.iter().flatten() {
+            args.push(sensor.clone())?;
+        }
 
         if *LOG_QUERY_PLAN {
             log_query_plan(&self.pool, &sql, &args).await;
@@ -334,6 +357,11 @@ impl SqliteEventRepo {
 
         let mut args = SqliteArguments::default();
 
+        let sensors_filter = match &params.sensors {
+            Some(sensors) => format!("AND {}", sensors_condition("events", sensors.len())),
+            None => String::new(),
+        };
+
         // Get sensor data without COALESCE for better performance
         let sql = format!(
             "
@@ -345,6 +373,7 @@ impl SqliteEventRepo {
             WHERE json_extract(events.source, '$.event_type') = 'stats'
               AND timestamp >= ?
               AND timestamp <= ?
+              {sensors_filter}
             GROUP BY sensor, bucket_time
             ORDER BY sensor, bucket_time
             "
@@ -352,6 +381,9 @@ impl SqliteEventRepo {
         args.push(&field)?;
         args.push(start_time)?;
         args.push(end_time)?;
+        for sensor in params.sensors.iter().flatten() {
+            args.push(sensor.clone())?;
+        }
 
         if *LOG_QUERY_PLAN {
             log_query_plan(&self.pool, &sql, &args).await;
@@ -331,6 +331,209 @@ pub(crate) fn create(linktype: u32, ts: DateTime, packet: &[u8]) -> Vec<u8> {
     buf.to_vec()
 }
 
+//
+// PCAPNG File Generation Functions
+//
+
+const PCAPNG_SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+const PCAPNG_OPT_ENDOFOPT: u16 = 0;
+const PCAPNG_OPT_COMMENT: u16 = 1;
+const PCAPNG_SHB_USERAPPL: u16 = 4;
+
+/// Block type and both total length fields.
+const PCAPNG_BLOCK_OVERHEAD: usize = 12;
+/// Interface id, split timestamp, and both lengths.
+const PCAPNG_PACKET_FIELDS: usize = 20;
+
+fn pad4(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// The encoded size of an options list: each option padded to 32 bits,
+/// plus the end-of-options marker. Zero when there are no options.
+fn pcapng_options_size(values: &[&[u8]]) -> usize {
+    if values.is_empty() {
+        return 0;
+    }
+    values
+        .iter()
+        .map(|value| 4 + pad4(value.len()))
+        .sum::<usize>()
+        + 4
+}
+
+fn put_pcapng_options(buf: &mut BytesMut, options: &[(u16, &[u8])]) {
+    if options.is_empty() {
+        return;
+    }
+    for (code, value) in options {
+        buf.put_u16_le(*code);
+        buf.put_u16_le(value.len() as u16);
+        buf.put_slice(value);
+        buf.put_bytes(0, pad4(value.len()) - value.len());
+    }
+    buf.put_u16_le(PCAPNG_OPT_ENDOFOPT);
+    buf.put_u16_le(0);
+}
+
+/// Frame a block body (already padded to 32 bits) with its type and
+/// total length.
+fn pcapng_block(block_type: u32, body: &[u8]) -> Vec<u8> {
+    let total = (PCAPNG_BLOCK_OVERHEAD + body.len()) as u32;
+    let mut buf = BytesMut::with_capacity(total as usize);
+    buf.put_u32_le(block_type);
+    buf.put_u32_le(total);
+    buf.put_slice(body);
+    buf.put_u32_le(total);
+    buf.to_vec()
+}
+
+/// An option value clamped to the 16 bit option length field.
+fn option_value(value: &str) -> &[u8] {
+    &value.as_bytes()[..value.len().min(u16::MAX as usize - 3)]
+}
+
+/// Creates a little-endian pcapng section header block naming EveBox as
+/// the writing application, with an optional comment describing where the
+/// packets came from.
+pub(crate) fn create_pcapng_section_header(comment: Option<&str>) -> Vec<u8> {
+    let application = format!("EveBox {}", crate::version::version());
+    let mut options = vec![(PCAPNG_SHB_USERAPPL, option_value(&application))];
+    if let Some(comment) = comment {
+        options.push((PCAPNG_OPT_COMMENT, option_value(comment)));
+    }
+    let mut body = BytesMut::new();
+    body.put_u32_le(PCAPNG_BYTE_ORDER_MAGIC);
+    body.put_u16_le(1); // Major version
+    body.put_u16_le(0); // Minor version
+    body.put_i64_le(-1); // Section length: unspecified
+    put_pcapng_options(&mut body, &options);
+    pcapng_block(PCAPNG_SECTION_HEADER, &body)
+}
+
+/// Creates a pcapng interface description block for a link type. The
+/// default microsecond timestamp resolution is used, so no options are
+/// needed.
+pub(crate) fn create_pcapng_interface(linktype: u32) -> Vec<u8> {
+    let mut body = BytesMut::with_capacity(8);
+    body.put_u16_le(linktype as u16);
+    body.put_u16_le(0); // Reserved
+    body.put_u32_le(0); // Snap length: unlimited
+    pcapng_block(PCAPNG_INTERFACE_DESCRIPTION, &body)
+}
+
+/// The size of an enhanced packet block for `data_len` bytes of packet
+/// data and an optional comment.
+pub(crate) fn pcapng_packet_size(data_len: usize, comment: Option<&str>) -> usize {
+    let options: Vec<&[u8]> = comment.map(option_value).into_iter().collect();
+    PCAPNG_BLOCK_OVERHEAD + PCAPNG_PACKET_FIELDS + pad4(data_len) + pcapng_options_size(&options)
+}
+
+/// Creates a pcapng enhanced packet block on `interface`, with an
+/// optional packet comment. The captured length is the length of `data`.
+pub(crate) fn create_pcapng_packet(
+    interface: u32,
+    ts_sec: u32,
+    ts_usec: u32,
+    orig_len: u32,
+    data: &[u8],
+    comment: Option<&str>,
+) -> Vec<u8> {
+    let ts = u64::from(ts_sec) * 1_000_000 + u64::from(ts_usec);
+    let mut body = BytesMut::with_capacity(pcapng_packet_size(data.len(), comment));
+    body.put_u32_le(interface);
+    body.put_u32_le((ts >> 32) as u32);
+    body.put_u32_le(ts as u32);
+    body.put_u32_le(data.len() as u32);
+    body.put_u32_le(orig_len);
+    body.put_slice(data);
+    body.put_bytes(0, pad4(data.len()) - data.len());
+    let options: Vec<(u16, &[u8])> = comment
+        .map(|comment| (PCAPNG_OPT_COMMENT, option_value(comment)))
+        .into_iter()
+        .collect();
+    put_pcapng_options(&mut body, &options);
+    pcapng_block(PCAPNG_ENHANCED_PACKET, &body)
+}
+
+/// Tracks the pcapng blocks that must precede each packet: the section
+/// header before the first one, and an interface description block the
+/// first time each link type appears. Packets of any link type can share
+/// one file this way.
+#[derive(Debug, Default)]
+pub(crate) struct PcapngInterfaces {
+    section_written: bool,
+    linktypes: Vec<u32>,
+}
+
+impl PcapngInterfaces {
+    /// The blocks to write before a packet of `linktype`, and the
+    /// packet's interface id.
+    pub(crate) fn prepare(
+        &mut self,
+        linktype: u32,
+        section_comment: Option<&str>,
+    ) -> (Vec<u8>, u32) {
+        let mut blocks = Vec::new();
+        if !self.section_written {
+            blocks.extend_from_slice(&create_pcapng_section_header(section_comment));
+            self.section_written = true;
+        }
+        let interface = match self.linktypes.iter().position(|known| *known == linktype) {
+            Some(interface) => interface,
+            None => {
+                blocks.extend_from_slice(&create_pcapng_interface(linktype));
+                self.linktypes.push(linktype);
+                self.linktypes.len() - 1
+            }
+        };
+        (blocks, interface as u32)
+    }
+
+    /// The size of the blocks [`Self::prepare`] would return.
+    pub(crate) fn prepare_size(&self, linktype: u32, section_comment: Option<&str>) -> usize {
+        let mut size = 0;
+        if !self.section_written {
+            size += create_pcapng_section_header(section_comment).len();
+        }
+        if !self.linktypes.contains(&linktype) {
+            size += PCAPNG_BLOCK_OVERHEAD + 8;
+        }
+        size
+    }
+}
+
+/// A pcapng packet comment linking a packet back to the EVE event it was
+/// captured for: the EveBox event id when known, the flow id, and the
+/// alert signature.
+pub(crate) fn event_packet_comment(event: &serde_json::Value, event_id: Option<&str>) -> String {
+    let mut lines = Vec::new();
+    if let Some(event_id) = event_id {
+        lines.push(format!("EveBox event: {event_id}"));
+    }
+    if let Some(flow_id) = event["flow_id"].as_u64() {
+        lines.push(format!("flow_id: {flow_id}"));
+    }
+    if let Some(event_type) = event["event_type"].as_str() {
+        lines.push(format!("event_type: {event_type}"));
+    }
+    let alert = &event["alert"];
+    if let Some(sid) = alert["signature_id"].as_u64() {
+        lines.push(format!(
+            "signature: [{}:{}:{}] {}",
+            alert["gid"].as_u64().unwrap_or(1),
+            sid,
+            alert["rev"].as_u64().unwrap_or(0),
+            alert["signature"].as_str().unwrap_or_default(),
+        ));
+    }
+    lines.join("\n")
+}
+
 /// Construct a packet from EVE event payload
 pub(crate) fn packet_from_payload(event: &serde_json::Value) -> Result<Vec<u8>> {
     let payload = &event["payload"]
@@ -403,8 +606,8 @@ pub(crate) fn packet_from_payload(event: &serde_json::Value) -> Result<Vec<u8>>
     }
 }
 
-/// Convert an EVE packet to PCAP data
-pub(crate) fn packet_to_pcap(event: &serde_json::Value) -> Result<Vec<u8>> {
+/// The link type, timestamp and data of an EVE event's `packet` field.
+fn event_packet(event: &serde_json::Value) -> Result<(u32, DateTime, Vec<u8>)> {
     let linktype = if let Some(linktype) = &event["packet_info"]["linktype"].as_u64() {
         *linktype as u32
     } else {
@@ -412,7 +615,7 @@ pub(crate) fn packet_to_pcap(event: &serde_json::Value) -> Result<Vec<u8>> {
         LinkType::Ethernet as u32
     };
 
-    let packet = &event["packet"]
+    let packet = event["packet"]
         .as_str()
         .map(|s| BASE64_STANDARD.decode(s))
         .ok_or_else(|| anyhow!("no packet in event".to_string()))?
@@ -422,29 +625,69 @@ pub(crate) fn packet_to_pcap(event: &serde_json::Value) -> Result<Vec<u8>> {
         .datetime()
         .ok_or_else(|| anyhow!("bad or missing timestamp field".to_string()))?;
 
-    let pcap_buffer = create(linktype, ts, packet);
-    Ok(pcap_buffer)
+    Ok((linktype, ts, packet))
 }
 
-/// Convert an EVE payload to PCAP data
-pub(crate) fn payload_to_pcap(event: &serde_json::Value) -> Result<Vec<u8>> {
+/// The link type, timestamp and data of a packet built from an EVE
+/// event's `payload` field.
+fn event_payload_packet(event: &serde_json::Value) -> Result<(u32, DateTime, Vec<u8>)> {
     let ts = event
         .datetime()
         .ok_or_else(|| anyhow!("bad or missing timestamp field".to_string()))?;
     let packet = packet_from_payload(event)?;
-    let pcap_buffer = create(LinkType::Raw as u32, ts, &packet);
-    Ok(pcap_buffer)
+    Ok((LinkType::Raw as u32, ts, packet))
 }
 
-/// Convert an EVE event to PCAP data based on the specified type
-pub(crate) fn eve_to_pcap(event_type: &str, event: &serde_json::Value) -> Result<Vec<u8>> {
+/// The link type, timestamp and data of the packet an EVE event carries,
+/// taken from its `packet` field or built from its `payload` field.
+pub(crate) fn eve_packet(
+    event_type: &str,
+    event: &serde_json::Value,
+) -> Result<(u32, DateTime, Vec<u8>)> {
     match event_type {
-        "packet" => packet_to_pcap(event),
-        "payload" => payload_to_pcap(event),
+        "packet" => event_packet(event),
+        "payload" => event_payload_packet(event),
         _ => bail!("invalid event type"),
     }
 }
 
+/// Convert an EVE packet to PCAP data
+pub(crate) fn packet_to_pcap(event: &serde_json::Value) -> Result<Vec<u8>> {
+    let (linktype, ts, packet) = event_packet(event)?;
+    Ok(create(linktype, ts, &packet))
+}
+
+/// Convert an EVE payload to PCAP data
+pub(crate) fn payload_to_pcap(event: &serde_json::Value) -> Result<Vec<u8>> {
+    let (linktype, ts, packet) = event_payload_packet(event)?;
+    Ok(create(linktype, ts, &packet))
+}
+
+/// Convert an EVE event to PCAP data based on the specified type
+pub(crate) fn eve_to_pcap(event_type: &str, event: &serde_json::Value) -> Result<Vec<u8>> {
+    let (linktype, ts, packet) = eve_packet(event_type, event)?;
+    Ok(create(linktype, ts, &packet))
+}
+
+/// Convert an EVE event to a single packet pcapng file, the packet
+/// commented with the event's flow id and signature.
+pub(crate) fn eve_to_pcapng(event_type: &str, event: &serde_json::Value) -> Result<Vec<u8>> {
+    let (linktype, ts, packet) = eve_packet(event_type, event)?;
+    let comment = event_packet_comment(event, None);
+    let (mut buf, interface) = PcapngInterfaces::default().prepare(linktype, None);
+    buf.extend_from_slice(&create_pcapng_packet(
+        interface,
+        ts.to_seconds() as u32,
+        ts.micros_part() as u32,
+        packet.len() as u32,
+        &packet,
+        Some(&comment)
+            .filter(|comment| !comment.is_empty())
+            .map(String::as_str),
+    ));
+    Ok(buf)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -501,6 +744,62 @@ mod test {
         let _pcap_buffer = super::create(LinkType::Raw as u32, ts, &packet);
     }
 
+    #[test]
+    fn test_pcapng_blocks_are_padded_and_framed() {
+        let header = create_pcapng_section_header(Some("abc"));
+        assert_eq!(header.len() % 4, 0);
+        assert_eq!(&header[0..4], &PCAPNG_SECTION_HEADER.to_le_bytes());
+        assert_eq!(&header[8..12], &PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes());
+        let len = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+        assert_eq!(len, header.len());
+        assert_eq!(&header[len - 4..], &header[4..8]);
+
+        let packet = create_pcapng_packet(0, 1, 2, 60, &[0u8; 5], Some("flow_id: 7"));
+        assert_eq!(packet.len(), pcapng_packet_size(5, Some("flow_id: 7")));
+        assert_eq!(packet.len() % 4, 0);
+        // Captured and original lengths.
+        assert_eq!(&packet[20..24], &5u32.to_le_bytes());
+        assert_eq!(&packet[24..28], &60u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_eve_to_pcapng_comments_packet() {
+        let event: serde_json::Value = serde_json::from_str(TEST_EVE_RECORD).unwrap();
+        let buf = eve_to_pcapng("payload", &event).unwrap();
+        let text = String::from_utf8_lossy(&buf);
+        assert!(text.contains("signature: [1:2021915:1] ET MALWARE ELF/muBoT IRC Activity 4"));
+
+        let mut types = vec![];
+        let mut offset = 0;
+        while offset < buf.len() {
+            types.push(u32::from_le_bytes(
+                buf[offset..offset + 4].try_into().unwrap(),
+            ));
+            offset += u32::from_le_bytes(buf[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        }
+        assert_eq!(offset, buf.len());
+        assert_eq!(
+            types,
+            vec![
+                PCAPNG_SECTION_HEADER,
+                PCAPNG_INTERFACE_DESCRIPTION,
+                PCAPNG_ENHANCED_PACKET
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pcapng_interfaces_one_per_linktype() {
+        let mut interfaces = PcapngInterfaces::default();
+        let size = interfaces.prepare_size(1, None);
+        let (blocks, interface) = interfaces.prepare(1, None);
+        assert_eq!((blocks.len(), interface), (size, 0));
+        let (blocks, interface) = interfaces.prepare(101, None);
+        assert_eq!((blocks.len(), interface), (20, 1));
+        let (blocks, interface) = interfaces.prepare(1, None);
+        assert_eq!((blocks.len(), interface), (0, 0));
+    }
+
     const TEST_EVE_RECORD: &str = r#"
 {
     "@timestamp": "2020-05-01T13:13:37.621Z",
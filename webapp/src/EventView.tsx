@@ -58,6 +58,12 @@ interface HistoryEntry {
   username?: string;
   cause?: string;
   comment?: string;
+  pcap?: {
+    sha256: string;
+    bytes: number;
+    source: string;
+    truncated: boolean;
+  };
 }
 
 export function EventView() {
@@ -1870,6 +1876,12 @@ function History(props: {
                           <Match when={entry.action == "comment"}>
                             Comment
                           </Match>
+                          <Match when={entry.action == "pcap-pinned"}>
+                            Capture preserved
+                          </Match>
+                          <Match when={entry.action == "pcap-pin-failed"}>
+                            Capture preservation failed
+                          </Match>
                         </Switch>
                         <Show when={entry.username}>
                           {" by "}
@@ -1881,6 +1893,14 @@ function History(props: {
                         <Show when={entry.action == "comment"}>
                           <p class="m-0">{entry.comment}</p>
                         </Show>
+                        <Show when={entry.pcap}>
+                          <p class="m-0">
+                            <a href={`api/pcap/evidence/${entry.pcap!.sha256}`}>
+                              <code>{entry.pcap!.sha256}</code>
+                            </a>
+                            {` (${entry.pcap!.bytes} bytes from ${entry.pcap!.source}${entry.pcap!.truncated ? ", truncated" : ""})`}
+                          </p>
+                        </Show>
                       </li>
                     </>
                   )}
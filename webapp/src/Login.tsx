@@ -27,8 +27,15 @@ export const Login = () => {
   const [loginForm, setLoginForm] = createStore({
     username: "",
     password: "",
+    code: "",
   });
   const [error, setError] = createSignal(false);
+  const [lockedOut, setLockedOut] = createSignal(false);
+  // The second factor step, once the password has been accepted.
+  const [totp, setTotp] = createSignal<API.LoginResponse | null>(null);
+  const [recoveryCodes, setRecoveryCodes] = createSignal<string[] | null>(
+    null,
+  );
 
   const [searchParams, setSearchParams] = useSearchParams();
   const [loginOptions] = createResource(getLoginOptions);
@@ -37,19 +44,38 @@ export const Login = () => {
   const doLogin = async (e: any) => {
     e.preventDefault();
 
-    API.login(loginForm.username, loginForm.password)
-      .then(() => {
+    API.login(loginForm.username, loginForm.password, loginForm.code)
+      .then(([ok, response]) => {
+        if (!ok) {
+          setTotp(response);
+          setError(response.error !== undefined);
+          setLoginForm("code", "");
+          return;
+        }
         SET_IS_AUTHENTICATED(true);
-        navigate(searchParams.redirectTo || "/inbox");
+        if (response.recovery_codes) {
+          setRecoveryCodes(response.recovery_codes);
+        } else {
+          finish();
+        }
       })
       .catch((error) => {
         console.log(`Login error: ${error.toString()}`);
+        setLockedOut(error.response?.status === 429);
         setError(true);
       });
   };
 
+  const finish = () => {
+    navigate(searchParams.redirectTo || "/inbox");
+  };
+
   const isValid = () => {
-    return loginForm.username.length > 0 && loginForm.password.length > 0;
+    return (
+      loginForm.username.length > 0 &&
+      loginForm.password.length > 0 &&
+      (totp() === null || loginForm.code.length > 0)
+    );
   };
 
   createEffect(async () => {
@@ -72,16 +98,34 @@ export const Login = () => {
           <Col></Col>
 
           <Col xs={12} md={8} lg={6}>
-            <Show when={error()}>
+            <Show when={error() || searchParams.error}>
               <Alert dismissible variant={"danger"}>
-                Login Failed
+                {lockedOut()
+                  ? "Too many failed logins, try again later"
+                  : "Login Failed"}
               </Alert>
             </Show>
 
             <div class={"card"}>
               <div class={"card-body"}>
+                <Show when={recoveryCodes()}>
+                  <p>
+                    Two-factor authentication is now enabled. Save these
+                    recovery codes somewhere safe, each can be used once in
+                    place of a code if you lose your authenticator app. They
+                    won't be shown again.
+                  </p>
+                  <pre class={"text-center"}>
+                    {recoveryCodes()!.join("\n")}
+                  </pre>
+                  <div class={"d-grid mt-3"}>
+                    <Button variant={"primary"} onclick={finish}>
+                      Continue
+                    </Button>
+                  </div>
+                </Show>
                 <Suspense>
-                  {loginOptions() && (
+                  {loginOptions() && !recoveryCodes() && (
                     <Form onsubmit={doLogin}>
                       <Form.Group>
                         <Form.Label>Username:</Form.Label>
@@ -106,6 +150,36 @@ export const Login = () => {
                         />
                       </Form.Group>
 
+                      <Show when={totp()?.totp === "enroll"}>
+                        <Alert variant={"info"} class={"mt-3"}>
+                          Two-factor authentication is required. Add this
+                          secret to your authenticator app, or open the{" "}
+                          <a href={totp()?.uri}>setup link</a> on your phone,
+                          then enter a code from it.
+                          <pre class={"mt-2 mb-0"}>{totp()?.secret}</pre>
+                        </Alert>
+                      </Show>
+
+                      <Show when={totp()}>
+                        <Form.Group class={"mt-3"}>
+                          <Form.Label>Authentication code:</Form.Label>
+                          <Form.Control
+                            value={loginForm.code}
+                            oninput={(e) =>
+                              setLoginForm("code", e.currentTarget.value)
+                            }
+                            type={"text"}
+                            autocomplete={"one-time-code"}
+                            spellcheck={false}
+                            placeholder={
+                              totp()?.totp === "enroll"
+                                ? "Code..."
+                                : "Code or recovery code..."
+                            }
+                          />
+                        </Form.Group>
+                      </Show>
+
                       <div class={"d-grid mt-3"}>
                         <Button
                           class={""}
@@ -116,6 +190,16 @@ export const Login = () => {
                           Login
                         </Button>
                       </div>
+                      <Show when={loginOptions()?.oidc}>
+                        <div class={"d-grid mt-3"}>
+                          <Button
+                            variant={"outline-primary"}
+                            href={"api/login/oidc"}
+                          >
+                            Login with {loginOptions()?.oidc?.label}
+                          </Button>
+                        </div>
+                      </Show>
                     </Form>
                   )}
                 </Suspense>
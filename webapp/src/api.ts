@@ -105,17 +105,29 @@ export async function getConfig(): Promise<ConfigResponse> {
   return get("api/config").then((response) => response.data);
 }
 
+// Resolves to [false, response] when a second factor is needed.
 export async function login(
   username: string,
   password: string,
+  code?: string,
 ): Promise<[boolean, LoginResponse]> {
   let params = new URLSearchParams({
     username: username,
     password: password,
   });
+  if (code) {
+    params.append("code", code);
+  }
 
-  let response = await axios.post<LoginResponse>("api/login", params);
-  return [true, response.data];
+  try {
+    let response = await axios.post<LoginResponse>("api/login", params);
+    return [true, response.data];
+  } catch (error: any) {
+    if (error.response?.status === 401 && error.response.data?.totp) {
+      return [false, error.response.data];
+    }
+    throw error;
+  }
 }
 
 export async function logout() {
@@ -273,10 +285,21 @@ export interface LoginOptions {
   authentication: {
     required: boolean;
   };
+  oidc: {
+    label: string;
+  } | null;
 }
 
 export interface LoginResponse {
-  session_id: string;
+  session_id?: string;
+  // Set when just enrolled in TOTP at login; only shown this once.
+  recovery_codes?: string[];
+  // Set when the login needs a second factor: "required" for a code,
+  // "enroll" to add the secret to an authenticator app first.
+  totp?: "required" | "enroll";
+  secret?: string;
+  uri?: string;
+  error?: string;
 }
 
 export interface UserResponse {
@@ -504,6 +527,8 @@ export namespace API {
   //     default; buffered POST may only keep or lower it.
   //   - source: explicit local/agent source name. Normally omitted because
   //     event identity or the sole available source resolves it.
+  //   - format: "pcap" (the default) or "pcapng", whose packets carry
+  //     comments linking them back to the event.
   export interface PcapRequestParams {
     eventId?: string;
     filter?: string;
@@ -513,6 +538,7 @@ export namespace API {
     after?: string;
     maxSize?: string;
     source?: string;
+    format?: "pcap" | "pcapng";
   }
 
   // Build the server's snake_case query params from the camelCase
@@ -529,6 +555,7 @@ export namespace API {
     if (params.after !== undefined) q.set("after", params.after);
     if (params.maxSize !== undefined) q.set("max_size", params.maxSize);
     if (params.source !== undefined) q.set("source", params.source);
+    if (params.format !== undefined) q.set("format", params.format);
     return q;
   }
 
@@ -68,6 +68,15 @@ function defaultRetentionSizeSettings(): RetentionSettings {
   };
 }
 
+interface TotpPolicy {
+  required: boolean;
+}
+
+async function fetchTotpPolicy(): Promise<TotpPolicy> {
+  const json = await api.API.getJson("api/admin/kv/config");
+  return json["config.totp"] || { required: false };
+}
+
 export function Admin() {
   const [state, setState] = createStore({
     ja4: {
@@ -95,6 +104,15 @@ export function Admin() {
   const [localRetentionSizeSettings, setLocalRetentionSizeSettings] =
     createStore<RetentionSettings>(defaultRetentionSizeSettings());
 
+  // Two-factor authentication policy.
+  const [totpPolicy, { refetch: refetchTotpPolicy }] =
+    createResource<TotpPolicy>(fetchTotpPolicy);
+
+  const saveTotpPolicy = async (required: boolean) => {
+    await api.API.postJson("api/admin/kv/config/config.totp", { required });
+    refetchTotpPolicy();
+  };
+
   createEffect(() => {
     if (autoArchiveSettings()) {
       setLocalAutoArchiveSettings(autoArchiveSettings()!);
@@ -451,6 +469,34 @@ export function Admin() {
           </div>
         </div>
       </Show>
+
+      {/* Two-factor authentication policy. */}
+      <div class="row mt-2">
+        <div class="col">
+          <div class="card">
+            <div class="card-body">
+              <div class="form-check form-switch">
+                <input
+                  class="form-check-input"
+                  type="checkbox"
+                  role="switch"
+                  checked={totpPolicy.latest?.required ?? false}
+                  onChange={(e) => {
+                    saveTotpPolicy(e.target.checked);
+                  }}
+                />
+                <label class="form-check-label">
+                  Require two-factor authentication for local users
+                </label>
+              </div>
+              <div class="form-text">
+                Users without an authenticator app set up are asked to add
+                one at their next login.
+              </div>
+            </div>
+          </div>
+        </div>
+      </div>
     </>
   );
 }